@@ -6,6 +6,7 @@ pub struct AwsConfig {
     pub region: String,
     pub account_id: String,
     pub ecr_repository: String,
+    pub container_name: String,
     pub ecs_cluster: String,
     pub ecs_service: String,
     pub task_family: String,
@@ -13,17 +14,23 @@ pub struct AwsConfig {
     pub task_memory: String,
     pub container_port: u16,
     pub log_group: String,
+    pub health_check_url: Option<String>,
+    pub health_check_attempts: u32,
 }
 
 impl AwsConfig {
-    pub fn from_env() -> Result<Self, String> {
-        Ok(AwsConfig {
+    /// Unset required variables are left empty; `validate` reports them
+    pub fn from_env() -> Self {
+        let ecr_repository = env::var("ECR_REPOSITORY").unwrap_or_default();
+
+        AwsConfig {
             region: env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
-            account_id: env::var("AWS_ACCOUNT_ID").map_err(|_| "AWS_ACCOUNT_ID not set")?,
-            ecr_repository: env::var("ECR_REPOSITORY").map_err(|_| "ECR_REPOSITORY not set")?,
-            ecs_cluster: env::var("ECS_CLUSTER").map_err(|_| "ECS_CLUSTER not set")?,
-            ecs_service: env::var("ECS_SERVICE").map_err(|_| "ECS_SERVICE not set")?,
-            task_family: env::var("TASK_FAMILY").map_err(|_| "TASK_FAMILY not set")?,
+            account_id: env::var("AWS_ACCOUNT_ID").unwrap_or_default(),
+            container_name: env::var("CONTAINER_NAME").unwrap_or_else(|_| ecr_repository.clone()),
+            ecr_repository,
+            ecs_cluster: env::var("ECS_CLUSTER").unwrap_or_default(),
+            ecs_service: env::var("ECS_SERVICE").unwrap_or_default(),
+            task_family: env::var("TASK_FAMILY").unwrap_or_default(),
             task_cpu: env::var("TASK_CPU").unwrap_or_else(|_| "256".to_string()),
             task_memory: env::var("TASK_MEMORY").unwrap_or_else(|_| "512".to_string()),
             container_port: env::var("CONTAINER_PORT")
@@ -31,7 +38,45 @@ impl AwsConfig {
                 .parse()
                 .unwrap_or(8080),
             log_group: env::var("LOG_GROUP").unwrap_or_else(|_| "/ecs/compilex7".to_string()),
-        })
+            health_check_url: env::var("HEALTH_CHECK_URL").ok(),
+            health_check_attempts: env::var("HEALTH_CHECK_ATTEMPTS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(3),
+        }
+    }
+
+    /// Check every setting the aws/docker commands need before running any of them,
+    /// naming all the missing environment variables at once
+    pub fn validate(&self) -> Result<(), String> {
+        let required = [
+            ("AWS_REGION", &self.region),
+            ("AWS_ACCOUNT_ID", &self.account_id),
+            ("ECR_REPOSITORY", &self.ecr_repository),
+            ("ECS_CLUSTER", &self.ecs_cluster),
+            ("ECS_SERVICE", &self.ecs_service),
+            ("TASK_FAMILY", &self.task_family),
+        ];
+        let missing: Vec<&str> = required
+            .iter()
+            .filter(|(_, value)| value.trim().is_empty())
+            .map(|(name, _)| *name)
+            .collect();
+
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("missing AWS settings, set {}", missing.join(", ")))
+        }
+    }
+
+    /// SDK settings for the configured region; credentials come from the usual AWS
+    /// environment variables, profiles, or instance role
+    pub async fn sdk_config(&self) -> aws_config::SdkConfig {
+        aws_config::defaults(aws_config::BehaviorVersion::latest())
+            .region(aws_config::Region::new(self.region.clone()))
+            .load()
+            .await
     }
 
     pub fn ecr_image_uri(&self, tag: &str) -> String {
@@ -41,3 +86,54 @@ impl AwsConfig {
         )
     }
 }
+
+#[cfg(test)]
+impl AwsConfig {
+    /// Settings for a made-up account, for tests
+    pub fn sample() -> Self {
+        AwsConfig {
+            region: "us-east-1".to_string(),
+            account_id: "123456789012".to_string(),
+            ecr_repository: "compilex7".to_string(),
+            container_name: "compilex7".to_string(),
+            ecs_cluster: "cx7-cluster".to_string(),
+            ecs_service: "cx7-service".to_string(),
+            task_family: "compilex7-task".to_string(),
+            task_cpu: "256".to_string(),
+            task_memory: "512".to_string(),
+            container_port: 8080,
+            log_group: "/ecs/compilex7".to_string(),
+            health_check_url: None,
+            health_check_attempts: 3,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_lists_every_missing_field() {
+        assert!(AwsConfig::sample().validate().is_ok());
+
+        let config = AwsConfig {
+            account_id: String::new(),
+            ecs_cluster: " ".to_string(),
+            task_family: String::new(),
+            ..AwsConfig::sample()
+        };
+        assert_eq!(
+            config.validate().unwrap_err(),
+            "missing AWS settings, set AWS_ACCOUNT_ID, ECS_CLUSTER, TASK_FAMILY"
+        );
+    }
+
+    #[test]
+    fn test_ecr_image_uri() {
+        assert_eq!(
+            AwsConfig::sample().ecr_image_uri("v1.2.0"),
+            "123456789012.dkr.ecr.us-east-1.amazonaws.com/compilex7:v1.2.0"
+        );
+    }
+}
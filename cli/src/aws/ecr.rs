@@ -1,21 +1,60 @@
-use crate::aws::AwsConfig;
-use std::process::Command;
+use crate::aws::{self, AwsConfig};
+use async_trait::async_trait;
+use aws_sdk_ecr::error::DisplayErrorContext;
+use base64::Engine;
+use std::io::Write;
+use std::process::{Command, Stdio};
 
 pub struct EcrManager {
     config: AwsConfig,
+    client: Box<dyn EcrApi>,
+}
+
+/// Credentials for `docker login` to the account's registry
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegistryLogin {
+    pub username: String,
+    pub password: String,
+    pub registry: String,
+}
+
+/// The ECR calls a push makes, so tests can stand in for the SDK client
+#[async_trait]
+pub trait EcrApi: Send + Sync {
+    async fn registry_login(&self) -> Result<RegistryLogin, String>;
+}
+
+#[async_trait]
+impl EcrApi for aws_sdk_ecr::Client {
+    async fn registry_login(&self) -> Result<RegistryLogin, String> {
+        let output = self
+            .get_authorization_token()
+            .send()
+            .await
+            .map_err(|e| format!("ECR login failed: {}", DisplayErrorContext(&e)))?;
+
+        let data = output
+            .authorization_data()
+            .first()
+            .ok_or("ECR returned no authorization data")?;
+        let token = data.authorization_token().ok_or("ECR returned no authorization token")?;
+        let endpoint = data.proxy_endpoint().ok_or("ECR returned no registry endpoint")?;
+
+        parse_authorization_token(token, endpoint)
+    }
 }
 
 impl EcrManager {
-    pub fn new(config: AwsConfig) -> Self {
-        EcrManager { config }
+    pub fn new(config: AwsConfig, client: impl EcrApi + 'static) -> Self {
+        EcrManager { config, client: Box::new(client) }
     }
 
     pub async fn build_and_push(&self, dockerfile_path: &str, tag: &str) -> Result<String, String> {
         println!("Building Docker image...");
-        
+
         // Build image
-        let build_output = Command::new("docker")
-            .args(&["build", "-t", &format!("{}:{}", self.config.ecr_repository, tag), "-f", dockerfile_path, "."])
+        let build_output = self
+            .build_command(dockerfile_path, tag)
             .output()
             .map_err(|e| format!("Docker build failed: {}", e))?;
 
@@ -27,10 +66,10 @@ impl EcrManager {
         self.ecr_login().await?;
 
         let image_uri = self.config.ecr_image_uri(tag);
-        
+
         println!("Tagging image: {}", image_uri);
-        let tag_output = Command::new("docker")
-            .args(&["tag", &format!("{}:{}", self.config.ecr_repository, tag), &image_uri])
+        let tag_output = self
+            .tag_command(tag)
             .output()
             .map_err(|e| format!("Docker tag failed: {}", e))?;
 
@@ -39,8 +78,8 @@ impl EcrManager {
         }
 
         println!("Pushing to ECR: {}", image_uri);
-        let push_output = Command::new("docker")
-            .args(&["push", &image_uri])
+        let push_output = self
+            .push_command(tag)
             .output()
             .map_err(|e| format!("Docker push failed: {}", e))?;
 
@@ -53,31 +92,21 @@ impl EcrManager {
     }
 
     async fn ecr_login(&self) -> Result<(), String> {
-        let auth_output = Command::new("aws")
-            .args(&[
-                "ecr",
-                "get-login-password",
-                "--region",
-                &self.config.region,
-            ])
-            .output()
-            .map_err(|e| format!("ECR login failed: {}", e))?;
+        let login = self.client.registry_login().await?;
 
-        if !auth_output.status.success() {
-            return Err("Failed to get ECR login token".to_string());
+        let mut docker_login = docker_login_command(&login)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Docker login failed: {}", e))?;
+        if let Some(mut stdin) = docker_login.stdin.take() {
+            stdin
+                .write_all(login.password.as_bytes())
+                .map_err(|e| format!("Docker login failed: {}", e))?;
         }
-
-        let password = String::from_utf8(auth_output.stdout)
-            .map_err(|e| format!("Invalid UTF-8: {}", e))?
-            .trim()
-            .to_string();
-
-        let registry = format!("{}.dkr.ecr.{}.amazonaws.com", self.config.account_id, self.config.region);
-
-        let login_output = Command::new("docker")
-            .args(&["login", "--username", "AWS", "--password-stdin", &registry])
-            .stdin(std::process::Stdio::piped())
-            .output()
+        let login_output = docker_login
+            .wait_with_output()
             .map_err(|e| format!("Docker login failed: {}", e))?;
 
         if !login_output.status.success() {
@@ -86,4 +115,91 @@ impl EcrManager {
 
         Ok(())
     }
+
+    /// The docker commands and ECR calls `build_and_push` makes, in order
+    pub fn build_and_push_plan(&self, dockerfile_path: &str, tag: &str) -> Vec<String> {
+        let login = RegistryLogin {
+            username: "AWS".to_string(),
+            password: String::new(),
+            registry: self.registry(),
+        };
+        vec![
+            aws::command_line(&self.build_command(dockerfile_path, tag)),
+            aws::api_call("ecr", "GetAuthorizationToken", &[]),
+            aws::command_line(&docker_login_command(&login)),
+            aws::command_line(&self.tag_command(tag)),
+            aws::command_line(&self.push_command(tag)),
+        ]
+    }
+
+    fn registry(&self) -> String {
+        format!("{}.dkr.ecr.{}.amazonaws.com", self.config.account_id, self.config.region)
+    }
+
+    fn local_image(&self, tag: &str) -> String {
+        format!("{}:{}", self.config.ecr_repository, tag)
+    }
+
+    fn build_command(&self, dockerfile_path: &str, tag: &str) -> Command {
+        let mut command = Command::new("docker");
+        command.args(["build", "-t", &self.local_image(tag), "-f", dockerfile_path, "."]);
+        command
+    }
+
+    fn tag_command(&self, tag: &str) -> Command {
+        let mut command = Command::new("docker");
+        command.args(["tag", &self.local_image(tag), &self.config.ecr_image_uri(tag)]);
+        command
+    }
+
+    fn push_command(&self, tag: &str) -> Command {
+        let mut command = Command::new("docker");
+        command.args(["push", &self.config.ecr_image_uri(tag)]);
+        command
+    }
+}
+
+/// Reads the password from stdin
+fn docker_login_command(login: &RegistryLogin) -> Command {
+    let mut command = Command::new("docker");
+    command.args(["login", "--username", &login.username, "--password-stdin", &login.registry]);
+    command
+}
+
+/// ECR hands out `base64(user:password)` and the registry as an https URL
+fn parse_authorization_token(token: &str, proxy_endpoint: &str) -> Result<RegistryLogin, String> {
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(token)
+        .map_err(|e| format!("Invalid ECR authorization token: {}", e))?;
+    let decoded = String::from_utf8(decoded).map_err(|e| format!("Invalid UTF-8: {}", e))?;
+    let (username, password) = decoded
+        .split_once(':')
+        .ok_or("Invalid ECR authorization token: no password")?;
+
+    Ok(RegistryLogin {
+        username: username.to_string(),
+        password: password.to_string(),
+        registry: proxy_endpoint.trim_start_matches("https://").to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_authorization_token() {
+        let token = base64::engine::general_purpose::STANDARD.encode("AWS:eyJwYXlsb2FkIjoi:xyz");
+        let login = parse_authorization_token(&token, "https://123456789012.dkr.ecr.us-east-1.amazonaws.com").unwrap();
+
+        assert_eq!(
+            login,
+            RegistryLogin {
+                username: "AWS".to_string(),
+                password: "eyJwYXlsb2FkIjoi:xyz".to_string(),
+                registry: "123456789012.dkr.ecr.us-east-1.amazonaws.com".to_string(),
+            }
+        );
+        assert!(parse_authorization_token("not base64!", "https://registry").is_err());
+    }
 }
@@ -1,10 +1,16 @@
-use crate::aws::AwsConfig;
-use std::process::Command;
+use crate::aws::{self, AwsConfig};
+use async_trait::async_trait;
+use aws_sdk_ecs::error::DisplayErrorContext;
+use aws_sdk_ecs::types::{Service, SortOrder, TaskDefinition};
 use std::time::Duration;
 use tokio::time::sleep;
 
+/// How many recent revisions to search when resolving an image tag back to a task definition
+const TAG_LOOKUP_REVISIONS: i32 = 25;
+
 pub struct EcsDeployer {
     config: AwsConfig,
+    client: Box<dyn EcsApi>,
 }
 
 #[derive(Debug, Clone)]
@@ -14,11 +20,126 @@ pub struct DeploymentStatus {
     pub desired_count: i32,
     pub pending_count: i32,
     pub status: String,
+    pub deployment_count: usize,
+    pub rollout_state: Option<String>,
+}
+
+impl DeploymentStatus {
+    /// A rollout is done once the old deployment has drained and every desired task is running
+    pub fn is_stable(&self) -> bool {
+        self.deployment_count == 1
+            && self.running_count == self.desired_count
+            && self.pending_count == 0
+            && self.rollout_state.as_deref().is_none_or(|state| state == "COMPLETED")
+    }
+
+    pub fn has_failed(&self) -> bool {
+        self.rollout_state.as_deref() == Some("FAILED")
+    }
+}
+
+/// The ECS calls a deployment makes, so tests can stand in for the SDK client
+#[async_trait]
+pub trait EcsApi: Send + Sync {
+    async fn describe_service(&self, cluster: &str, service: &str) -> Result<Service, String>;
+    async fn describe_task_definition(&self, task_definition: &str) -> Result<TaskDefinition, String>;
+    /// Registers a new revision from the given definition and returns its ARN
+    async fn register_task_definition(&self, task_definition: TaskDefinition) -> Result<String, String>;
+    async fn update_service(&self, cluster: &str, service: &str, task_definition: &str) -> Result<(), String>;
+    /// Newest revisions first
+    async fn list_task_definitions(&self, family_prefix: &str, max_results: i32) -> Result<Vec<String>, String>;
+}
+
+#[async_trait]
+impl EcsApi for aws_sdk_ecs::Client {
+    async fn describe_service(&self, cluster: &str, service: &str) -> Result<Service, String> {
+        let output = self
+            .describe_services()
+            .cluster(cluster)
+            .services(service)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to describe service: {}", DisplayErrorContext(&e)))?;
+
+        output
+            .services()
+            .first()
+            .cloned()
+            .ok_or_else(|| "Service not found".to_string())
+    }
+
+    async fn describe_task_definition(&self, task_definition: &str) -> Result<TaskDefinition, String> {
+        let output = self
+            .describe_task_definition()
+            .task_definition(task_definition)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to get task definition: {}", DisplayErrorContext(&e)))?;
+
+        output
+            .task_definition()
+            .cloned()
+            .ok_or_else(|| format!("Task definition '{}' not found", task_definition))
+    }
+
+    async fn register_task_definition(&self, task_definition: TaskDefinition) -> Result<String, String> {
+        // Only the fields registration accepts; ARN, revision, status and the like are read-only
+        let output = self
+            .register_task_definition()
+            .set_family(task_definition.family)
+            .set_task_role_arn(task_definition.task_role_arn)
+            .set_execution_role_arn(task_definition.execution_role_arn)
+            .set_network_mode(task_definition.network_mode)
+            .set_container_definitions(task_definition.container_definitions)
+            .set_volumes(task_definition.volumes)
+            .set_placement_constraints(task_definition.placement_constraints)
+            .set_requires_compatibilities(task_definition.requires_compatibilities)
+            .set_cpu(task_definition.cpu)
+            .set_memory(task_definition.memory)
+            .set_pid_mode(task_definition.pid_mode)
+            .set_ipc_mode(task_definition.ipc_mode)
+            .set_proxy_configuration(task_definition.proxy_configuration)
+            .set_ephemeral_storage(task_definition.ephemeral_storage)
+            .set_runtime_platform(task_definition.runtime_platform)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to register task definition: {}", DisplayErrorContext(&e)))?;
+
+        output
+            .task_definition()
+            .and_then(TaskDefinition::task_definition_arn)
+            .map(str::to_string)
+            .ok_or_else(|| "Failed to parse task definition ARN".to_string())
+    }
+
+    async fn update_service(&self, cluster: &str, service: &str, task_definition: &str) -> Result<(), String> {
+        self.update_service()
+            .cluster(cluster)
+            .service(service)
+            .task_definition(task_definition)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to update service: {}", DisplayErrorContext(&e)))?;
+        Ok(())
+    }
+
+    async fn list_task_definitions(&self, family_prefix: &str, max_results: i32) -> Result<Vec<String>, String> {
+        let output = self
+            .list_task_definitions()
+            .family_prefix(family_prefix)
+            .sort(SortOrder::Desc)
+            .max_results(max_results)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to list task definitions: {}", DisplayErrorContext(&e)))?;
+
+        Ok(output.task_definition_arns().to_vec())
+    }
 }
 
 impl EcsDeployer {
-    pub fn new(config: AwsConfig) -> Self {
-        EcsDeployer { config }
+    pub fn new(config: AwsConfig, client: impl EcsApi + 'static) -> Self {
+        EcsDeployer { config, client: Box::new(client) }
     }
 
     pub async fn deploy(&self, image_uri: &str) -> Result<(), String> {
@@ -42,121 +163,29 @@ impl EcsDeployer {
     }
 
     async fn get_task_definition(&self) -> Result<String, String> {
-        let output = Command::new("aws")
-            .args(&[
-                "ecs",
-                "describe-services",
-                "--cluster",
-                &self.config.ecs_cluster,
-                "--services",
-                &self.config.ecs_service,
-                "--region",
-                &self.config.region,
-                "--query",
-                "services[0].taskDefinition",
-                "--output",
-                "text",
-            ])
-            .output()
-            .map_err(|e| format!("Failed to get task definition: {}", e))?;
-
-        if !output.status.success() {
-            return Err(format!("AWS CLI error: {}", String::from_utf8_lossy(&output.stderr)));
-        }
-
-        Ok(String::from_utf8(output.stdout)
-            .map_err(|e| format!("Invalid UTF-8: {}", e))?
-            .trim()
-            .to_string())
+        let service = self
+            .client
+            .describe_service(&self.config.ecs_cluster, &self.config.ecs_service)
+            .await?;
+
+        service
+            .task_definition()
+            .map(str::to_string)
+            .ok_or_else(|| "Service has no task definition".to_string())
     }
 
     async fn register_task_definition(&self, current_task_def: &str, image_uri: &str) -> Result<String, String> {
-        // Get full task definition
-        let output = Command::new("aws")
-            .args(&[
-                "ecs",
-                "describe-task-definition",
-                "--task-definition",
-                current_task_def,
-                "--region",
-                &self.config.region,
-                "--query",
-                "taskDefinition",
-                "--output",
-                "json",
-            ])
-            .output()
-            .map_err(|e| format!("Failed to get task definition: {}", e))?;
-
-        if !output.status.success() {
-            return Err(format!("AWS CLI error: {}", String::from_utf8_lossy(&output.stderr)));
-        }
-
-        let task_def_json = String::from_utf8(output.stdout)
-            .map_err(|e| format!("Invalid UTF-8: {}", e))?;
-
-        // Parse and update image URI in JSON (simplified - in production use serde_json)
-        let updated_json = task_def_json.replace(
-            &format!("\"image\": \"{}:", &self.config.ecr_repository),
-            &format!("\"image\": \"{}\"", image_uri),
-        );
-
-        // Register new task definition
-        let register_output = Command::new("aws")
-            .args(&[
-                "ecs",
-                "register-task-definition",
-                "--cli-input-json",
-                &format!("file:///dev/stdin"),
-                "--region",
-                &self.config.region,
-            ])
-            .stdin(std::process::Stdio::piped())
-            .output()
-            .map_err(|e| format!("Failed to register task definition: {}", e))?;
-
-        if !register_output.status.success() {
-            return Err(format!("Failed to register task definition: {}", String::from_utf8_lossy(&register_output.stderr)));
-        }
-
-        let new_task_def = String::from_utf8(register_output.stdout)
-            .map_err(|e| format!("Invalid UTF-8: {}", e))?;
-
-        // Extract task definition ARN
-        let arn = new_task_def
-            .split("\"taskDefinitionArn\": \"")
-            .nth(1)
-            .and_then(|s| s.split('"').next())
-            .ok_or("Failed to parse task definition ARN")?
-            .to_string();
-
-        Ok(arn)
+        let task_def = self.client.describe_task_definition(current_task_def).await?;
+        let updated = prepare_task_definition(task_def, &self.config.container_name, image_uri)?;
+        self.client.register_task_definition(updated).await
     }
 
     async fn update_service(&self, task_definition: &str) -> Result<(), String> {
         println!("Updating ECS service with new task definition...");
-        
-        let output = Command::new("aws")
-            .args(&[
-                "ecs",
-                "update-service",
-                "--cluster",
-                &self.config.ecs_cluster,
-                "--service",
-                &self.config.ecs_service,
-                "--task-definition",
-                task_definition,
-                "--region",
-                &self.config.region,
-            ])
-            .output()
-            .map_err(|e| format!("Failed to update service: {}", e))?;
-
-        if !output.status.success() {
-            return Err(format!("Failed to update service: {}", String::from_utf8_lossy(&output.stderr)));
-        }
 
-        Ok(())
+        self.client
+            .update_service(&self.config.ecs_cluster, &self.config.ecs_service, task_definition)
+            .await
     }
 
     async fn wait_for_stable_deployment(&self) -> Result<(), String> {
@@ -171,11 +200,15 @@ impl EcsDeployer {
             println!("Status: {} | Running: {}/{} | Pending: {}", 
                 status.status, status.running_count, status.desired_count, status.pending_count);
 
-            if status.running_count == status.desired_count && status.pending_count == 0 {
+            if status.is_stable() {
                 println!("Deployment is stable!");
                 return Ok(());
             }
 
+            if status.has_failed() {
+                return Err("Deployment failed - ECS reported a failed rollout".to_string());
+            }
+
             if elapsed > max_wait_time {
                 return Err("Deployment timeout - exceeded 10 minutes".to_string());
             }
@@ -185,38 +218,42 @@ impl EcsDeployer {
         }
     }
 
-    async fn get_deployment_status(&self) -> Result<DeploymentStatus, String> {
-        let output = Command::new("aws")
-            .args(&[
-                "ecs",
-                "describe-services",
-                "--cluster",
-                &self.config.ecs_cluster,
-                "--services",
-                &self.config.ecs_service,
-                "--region",
-                &self.config.region,
-                "--output",
-                "json",
-            ])
-            .output()
-            .map_err(|e| format!("Failed to get service status: {}", e))?;
-
-        if !output.status.success() {
-            return Err(format!("AWS CLI error: {}", String::from_utf8_lossy(&output.stderr)));
-        }
-
-        // Parse JSON response (simplified)
-        let json_str = String::from_utf8(output.stdout)
-            .map_err(|e| format!("Invalid UTF-8: {}", e))?;
-
-        Ok(DeploymentStatus {
-            service: self.config.ecs_service.clone(),
-            running_count: 1,
-            desired_count: 1,
-            pending_count: 0,
-            status: "ACTIVE".to_string(),
-        })
+    pub async fn get_deployment_status(&self) -> Result<DeploymentStatus, String> {
+        let service = self
+            .client
+            .describe_service(&self.config.ecs_cluster, &self.config.ecs_service)
+            .await?;
+
+        Ok(deployment_status(&service))
+    }
+
+    /// Deploy the image, then roll back to the previous task definition if the service
+    /// fails to stabilize or its health endpoint doesn't pass every probe
+    pub async fn deploy_blue_green(
+        &self,
+        image_uri: &str,
+        health: &HealthCheckConfig,
+        checker: &dyn HealthChecker,
+    ) -> Result<String, String> {
+        deploy_with_health_gate(self, image_uri, health, checker).await
+    }
+
+    /// Find the most recent task definition revision that ran the given image tag
+    pub async fn find_task_definition_for_tag(&self, tag: &str) -> Result<String, String> {
+        let arns = self
+            .client
+            .list_task_definitions(&self.config.task_family, TAG_LOOKUP_REVISIONS)
+            .await?;
+        let image_uri = self.config.ecr_image_uri(tag);
+
+        for arn in arns {
+            let task_def = self.client.describe_task_definition(&arn).await?;
+            if container_image(&task_def, &self.config.container_name) == Some(image_uri.as_str()) {
+                return Ok(arn);
+            }
+        }
+
+        Err(format!("No recent task definition runs image tag '{}'", tag))
     }
 
     pub async fn rollback(&self, previous_task_def: &str) -> Result<(), String> {
@@ -226,4 +263,688 @@ impl EcsDeployer {
         println!("Rollback completed!");
         Ok(())
     }
+
+    /// The ECS calls `deploy` makes, with `<...>` standing in for values only known
+    /// once earlier calls have run
+    pub fn deploy_plan(&self) -> Vec<String> {
+        vec![
+            self.describe_service_call(),
+            self.describe_task_definition_call("<current-task-definition>"),
+            aws::api_call("ecs", "RegisterTaskDefinition", &[("taskDefinition", "<current-task-definition-with-new-image>")]),
+            self.update_service_call("<new-task-definition>"),
+            self.describe_service_call(),
+        ]
+    }
+
+    /// The ECS calls a rollback to `previous` (a tag or task definition ARN) makes
+    pub fn rollback_plan(&self, previous: &str) -> Vec<String> {
+        let mut plan = Vec::new();
+        let task_def = if previous.starts_with("arn:") {
+            previous.to_string()
+        } else {
+            plan.push(aws::api_call(
+                "ecs",
+                "ListTaskDefinitions",
+                &[
+                    ("familyPrefix", &self.config.task_family),
+                    ("sort", "DESC"),
+                    ("maxResults", &TAG_LOOKUP_REVISIONS.to_string()),
+                ],
+            ));
+            plan.push(self.describe_task_definition_call("<each-listed-task-definition>"));
+            format!("<task-definition-running-{}>", previous)
+        };
+        plan.push(self.update_service_call(&task_def));
+        plan.push(self.describe_service_call());
+        plan
+    }
+
+    pub fn status_plan(&self) -> Vec<String> {
+        vec![self.describe_service_call()]
+    }
+
+    fn describe_service_call(&self) -> String {
+        aws::api_call(
+            "ecs",
+            "DescribeServices",
+            &[("cluster", &self.config.ecs_cluster), ("services", &self.config.ecs_service)],
+        )
+    }
+
+    fn describe_task_definition_call(&self, task_definition: &str) -> String {
+        aws::api_call("ecs", "DescribeTaskDefinition", &[("taskDefinition", task_definition)])
+    }
+
+    fn update_service_call(&self, task_definition: &str) -> String {
+        aws::api_call(
+            "ecs",
+            "UpdateService",
+            &[
+                ("cluster", &self.config.ecs_cluster),
+                ("service", &self.config.ecs_service),
+                ("taskDefinition", task_definition),
+            ],
+        )
+    }
+}
+
+/// Where and how often to probe a freshly deployed service before keeping it
+#[derive(Debug, Clone)]
+pub struct HealthCheckConfig {
+    pub url: String,
+    pub attempts: u32,
+    pub interval: Duration,
+}
+
+impl HealthCheckConfig {
+    pub fn new(url: impl Into<String>, attempts: u32) -> Self {
+        HealthCheckConfig {
+            url: url.into(),
+            attempts: attempts.max(1),
+            interval: Duration::from_secs(5),
+        }
+    }
+}
+
+#[async_trait]
+pub trait HealthChecker: Send + Sync {
+    async fn is_healthy(&self, url: &str) -> bool;
+}
+
+/// Treats any 2xx response from the health URL as healthy
+pub struct HttpHealthChecker {
+    client: reqwest::Client,
+}
+
+impl HttpHealthChecker {
+    pub fn new() -> Self {
+        HttpHealthChecker {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(5))
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl Default for HttpHealthChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl HealthChecker for HttpHealthChecker {
+    async fn is_healthy(&self, url: &str) -> bool {
+        match self.client.get(url).send().await {
+            Ok(response) => response.status().is_success(),
+            Err(_) => false,
+        }
+    }
+}
+
+/// The service operations a gated deployment drives, split out so the rollback path can be exercised without AWS
+#[async_trait]
+trait ServiceRollout: Sync {
+    async fn current_task_definition(&self) -> Result<String, String>;
+    async fn register_image(&self, current_task_def: &str, image_uri: &str) -> Result<String, String>;
+    async fn switch_to(&self, task_def: &str) -> Result<(), String>;
+    async fn wait_until_stable(&self) -> Result<(), String>;
+}
+
+#[async_trait]
+impl ServiceRollout for EcsDeployer {
+    async fn current_task_definition(&self) -> Result<String, String> {
+        self.get_task_definition().await
+    }
+
+    async fn register_image(&self, current_task_def: &str, image_uri: &str) -> Result<String, String> {
+        self.register_task_definition(current_task_def, image_uri).await
+    }
+
+    async fn switch_to(&self, task_def: &str) -> Result<(), String> {
+        self.update_service(task_def).await
+    }
+
+    async fn wait_until_stable(&self) -> Result<(), String> {
+        self.wait_for_stable_deployment().await
+    }
+}
+
+async fn deploy_with_health_gate(
+    service: &dyn ServiceRollout,
+    image_uri: &str,
+    health: &HealthCheckConfig,
+    checker: &dyn HealthChecker,
+) -> Result<String, String> {
+    println!("Starting blue/green ECS deployment...");
+
+    let previous = service.current_task_definition().await?;
+    println!("Current task definition: {}", previous);
+
+    let new_task_def = service.register_image(&previous, image_uri).await?;
+    println!("Registered new task definition: {}", new_task_def);
+
+    service.switch_to(&new_task_def).await?;
+
+    let failure = match service.wait_until_stable().await {
+        Err(e) => Some(e),
+        Ok(()) if !passes_health_checks(health, checker).await => Some(format!(
+            "{} failed health checks",
+            health.url
+        )),
+        Ok(()) => None,
+    };
+
+    let Some(reason) = failure else {
+        println!("Deployment completed successfully!");
+        return Ok(new_task_def);
+    };
+
+    println!("Deployment unhealthy ({}), rolling back to {}...", reason, previous);
+    service.switch_to(&previous).await?;
+    service.wait_until_stable().await?;
+
+    Err(format!(
+        "Deployment of {} rolled back to {}: {}",
+        new_task_def, previous, reason
+    ))
+}
+
+/// Every probe must succeed; a single failure is enough to reject the deployment
+async fn passes_health_checks(health: &HealthCheckConfig, checker: &dyn HealthChecker) -> bool {
+    for attempt in 1..=health.attempts {
+        if !checker.is_healthy(&health.url).await {
+            println!("Health check {}/{} failed", attempt, health.attempts);
+            return false;
+        }
+
+        if attempt < health.attempts {
+            sleep(health.interval).await;
+        }
+    }
+
+    true
+}
+
+
+fn container_image<'a>(task_def: &'a TaskDefinition, container_name: &str) -> Option<&'a str> {
+    task_def
+        .container_definitions()
+        .iter()
+        .find(|c| c.name() == Some(container_name))?
+        .image()
+}
+
+/// Read the service counts and primary rollout state from a described service
+fn deployment_status(service: &Service) -> DeploymentStatus {
+    let rollout_state = service
+        .deployments()
+        .iter()
+        .find(|d| d.status() == Some("PRIMARY"))
+        .and_then(|d| d.rollout_state())
+        .map(|state| state.as_str().to_string());
+
+    DeploymentStatus {
+        service: service.service_name().unwrap_or_default().to_string(),
+        running_count: service.running_count(),
+        desired_count: service.desired_count(),
+        pending_count: service.pending_count(),
+        status: service.status().unwrap_or_default().to_string(),
+        deployment_count: service.deployments().len(),
+        rollout_state,
+    }
+}
+
+/// Point the named container at a new image, leaving the rest of the definition as it was
+pub fn prepare_task_definition(
+    mut task_def: TaskDefinition,
+    container_name: &str,
+    image_uri: &str,
+) -> Result<TaskDefinition, String> {
+    let container = task_def
+        .container_definitions
+        .as_mut()
+        .ok_or("Task definition has no containerDefinitions")?
+        .iter_mut()
+        .find(|c| c.name() == Some(container_name))
+        .ok_or_else(|| format!("No container named '{}' in task definition", container_name))?;
+
+    container.image = Some(image_uri.to_string());
+
+    Ok(task_def)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_ecs::types::{ContainerDefinition, Deployment, DeploymentRolloutState, PortMapping};
+    use aws_smithy_runtime_api::client::http::{
+        HttpClient, HttpConnector, HttpConnectorFuture, HttpConnectorSettings, SharedHttpConnector,
+    };
+    use aws_smithy_runtime_api::client::orchestrator::{HttpRequest, HttpResponse};
+    use aws_smithy_runtime_api::client::runtime_components::RuntimeComponents;
+    use aws_smithy_runtime_api::http::StatusCode;
+    use aws_smithy_types::body::SdkBody;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    const TASK_DEF_7: &str = "arn:aws:ecs:us-east-1:123456789012:task-definition/compilex7-task:7";
+    const TASK_DEF_8: &str = "arn:aws:ecs:us-east-1:123456789012:task-definition/compilex7-task:8";
+
+    fn sample_task_definition() -> TaskDefinition {
+        TaskDefinition::builder()
+            .task_definition_arn(TASK_DEF_7)
+            .family("compilex7-task")
+            .revision(7)
+            .cpu("256")
+            .memory("512")
+            .container_definitions(
+                ContainerDefinition::builder()
+                    .name("log-router")
+                    .image("amazon/aws-for-fluent-bit:stable")
+                    .essential(true)
+                    .build(),
+            )
+            .container_definitions(
+                ContainerDefinition::builder()
+                    .name("compilex7")
+                    .image("123456789012.dkr.ecr.us-east-1.amazonaws.com/compilex7:v1")
+                    .port_mappings(PortMapping::builder().container_port(8080).build())
+                    .essential(true)
+                    .build(),
+            )
+            .build()
+    }
+
+    fn service(running: i32, pending: i32, rollouts: &[(&str, DeploymentRolloutState)]) -> Service {
+        let mut service = Service::builder()
+            .service_name("compilex7-service")
+            .status("ACTIVE")
+            .task_definition(TASK_DEF_7)
+            .desired_count(2)
+            .running_count(running)
+            .pending_count(pending);
+        for (status, rollout_state) in rollouts {
+            service = service.deployments(
+                Deployment::builder()
+                    .status(*status)
+                    .rollout_state(rollout_state.clone())
+                    .build(),
+            );
+        }
+        service.build()
+    }
+
+    /// Stands in for the SDK client, recording what a deployment registers and switches to
+    #[derive(Clone)]
+    struct FakeEcs {
+        state: Arc<Mutex<FakeEcsState>>,
+    }
+
+    struct FakeEcsState {
+        service: Service,
+        task_definitions: HashMap<String, TaskDefinition>,
+        registered: Vec<TaskDefinition>,
+        updates: Vec<String>,
+    }
+
+    impl FakeEcs {
+        fn new(service: Service) -> Self {
+            let task_definitions = HashMap::from([(TASK_DEF_7.to_string(), sample_task_definition())]);
+            FakeEcs {
+                state: Arc::new(Mutex::new(FakeEcsState {
+                    service,
+                    task_definitions,
+                    registered: Vec::new(),
+                    updates: Vec::new(),
+                })),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl EcsApi for FakeEcs {
+        async fn describe_service(&self, _cluster: &str, _service: &str) -> Result<Service, String> {
+            Ok(self.state.lock().unwrap().service.clone())
+        }
+
+        async fn describe_task_definition(&self, task_definition: &str) -> Result<TaskDefinition, String> {
+            self.state
+                .lock()
+                .unwrap()
+                .task_definitions
+                .get(task_definition)
+                .cloned()
+                .ok_or_else(|| format!("Task definition '{}' not found", task_definition))
+        }
+
+        async fn register_task_definition(&self, task_definition: TaskDefinition) -> Result<String, String> {
+            let mut state = self.state.lock().unwrap();
+            state.registered.push(task_definition.clone());
+            state.task_definitions.insert(TASK_DEF_8.to_string(), task_definition);
+            Ok(TASK_DEF_8.to_string())
+        }
+
+        async fn update_service(&self, _cluster: &str, _service: &str, task_definition: &str) -> Result<(), String> {
+            self.state.lock().unwrap().updates.push(task_definition.to_string());
+            Ok(())
+        }
+
+        async fn list_task_definitions(&self, _family_prefix: &str, _max_results: i32) -> Result<Vec<String>, String> {
+            let mut arns: Vec<String> = self.state.lock().unwrap().task_definitions.keys().cloned().collect();
+            arns.sort_by(|a, b| b.cmp(a));
+            Ok(arns)
+        }
+    }
+
+    #[test]
+    fn test_prepare_task_definition_updates_named_container() {
+        let image = "123456789012.dkr.ecr.us-east-1.amazonaws.com/compilex7:v2";
+        let updated = prepare_task_definition(sample_task_definition(), "compilex7", image).unwrap();
+
+        let containers = updated.container_definitions();
+        assert_eq!(containers[0].image(), Some("amazon/aws-for-fluent-bit:stable"));
+        assert_eq!(containers[1].image(), Some(image));
+        assert_eq!(containers[1].port_mappings()[0].container_port(), Some(8080));
+
+        assert_eq!(updated.family(), Some("compilex7-task"));
+        assert_eq!(updated.cpu(), Some("256"));
+    }
+
+    #[test]
+    fn test_prepare_task_definition_unknown_container() {
+        let result = prepare_task_definition(sample_task_definition(), "missing", "image:latest");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_container_image_matches_by_name() {
+        let task_def = sample_task_definition();
+        assert_eq!(
+            container_image(&task_def, "compilex7"),
+            Some("123456789012.dkr.ecr.us-east-1.amazonaws.com/compilex7:v1")
+        );
+        assert_eq!(container_image(&task_def, "missing"), None);
+    }
+
+    #[tokio::test]
+    async fn test_deploy_registers_new_image_and_updates_service() {
+        let ecs = FakeEcs::new(service(2, 0, &[("PRIMARY", DeploymentRolloutState::Completed)]));
+        let deployer = EcsDeployer::new(AwsConfig::sample(), ecs.clone());
+
+        let image = "123456789012.dkr.ecr.us-east-1.amazonaws.com/compilex7:v2";
+        deployer.deploy(image).await.unwrap();
+
+        let state = ecs.state.lock().unwrap();
+        assert_eq!(state.registered.len(), 1);
+        assert_eq!(container_image(&state.registered[0], "compilex7"), Some(image));
+        assert_eq!(
+            container_image(&state.registered[0], "log-router"),
+            Some("amazon/aws-for-fluent-bit:stable")
+        );
+        assert_eq!(state.updates, vec![TASK_DEF_8]);
+    }
+
+    #[tokio::test]
+    async fn test_find_task_definition_for_tag() {
+        let ecs = FakeEcs::new(service(2, 0, &[]));
+        let deployer = EcsDeployer::new(AwsConfig::sample(), ecs.clone());
+        deployer
+            .register_task_definition(TASK_DEF_7, &AwsConfig::sample().ecr_image_uri("v2"))
+            .await
+            .unwrap();
+
+        assert_eq!(deployer.find_task_definition_for_tag("v1").await.unwrap(), TASK_DEF_7);
+        assert_eq!(deployer.find_task_definition_for_tag("v2").await.unwrap(), TASK_DEF_8);
+        assert!(deployer.find_task_definition_for_tag("v3").await.is_err());
+    }
+
+    #[derive(Default)]
+    struct MockService {
+        switches: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl ServiceRollout for MockService {
+        async fn current_task_definition(&self) -> Result<String, String> {
+            Ok("compilex7-task:7".to_string())
+        }
+
+        async fn register_image(&self, _current: &str, _image_uri: &str) -> Result<String, String> {
+            Ok("compilex7-task:8".to_string())
+        }
+
+        async fn switch_to(&self, task_def: &str) -> Result<(), String> {
+            self.switches.lock().unwrap().push(task_def.to_string());
+            Ok(())
+        }
+
+        async fn wait_until_stable(&self) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    struct MockHealth {
+        healthy: bool,
+        probes: AtomicU32,
+    }
+
+    #[async_trait]
+    impl HealthChecker for MockHealth {
+        async fn is_healthy(&self, _url: &str) -> bool {
+            self.probes.fetch_add(1, Ordering::SeqCst);
+            self.healthy
+        }
+    }
+
+    fn health_config() -> HealthCheckConfig {
+        HealthCheckConfig {
+            url: "http://localhost/health".to_string(),
+            attempts: 3,
+            interval: Duration::ZERO,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_blue_green_rolls_back_when_unhealthy() {
+        let service = MockService::default();
+        let checker = MockHealth { healthy: false, probes: AtomicU32::new(0) };
+
+        let result = deploy_with_health_gate(&service, "compilex7:v2", &health_config(), &checker).await;
+
+        let error = result.unwrap_err();
+        assert!(error.contains("rolled back to compilex7-task:7"));
+        assert_eq!(*service.switches.lock().unwrap(), vec!["compilex7-task:8", "compilex7-task:7"]);
+        assert_eq!(checker.probes.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_blue_green_keeps_healthy_deployment() {
+        let service = MockService::default();
+        let checker = MockHealth { healthy: true, probes: AtomicU32::new(0) };
+
+        let deployed = deploy_with_health_gate(&service, "compilex7:v2", &health_config(), &checker)
+            .await
+            .unwrap();
+
+        assert_eq!(deployed, "compilex7-task:8");
+        assert_eq!(*service.switches.lock().unwrap(), vec!["compilex7-task:8"]);
+        assert_eq!(checker.probes.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_deployment_status_mid_rollout() {
+        let ecs = FakeEcs::new(service(
+            2,
+            0,
+            &[
+                ("PRIMARY", DeploymentRolloutState::InProgress),
+                ("ACTIVE", DeploymentRolloutState::Completed),
+            ],
+        ));
+        let deployer = EcsDeployer::new(AwsConfig::sample(), ecs);
+
+        let status = deployer.get_deployment_status().await.unwrap();
+        assert_eq!(status.service, "compilex7-service");
+        assert_eq!(status.running_count, 2);
+        assert_eq!(status.deployment_count, 2);
+        assert_eq!(status.rollout_state.as_deref(), Some("IN_PROGRESS"));
+        assert!(!status.is_stable());
+        assert!(!status.has_failed());
+    }
+
+    #[tokio::test]
+    async fn test_deployment_status_completed() {
+        let ecs = FakeEcs::new(service(2, 0, &[("PRIMARY", DeploymentRolloutState::Completed)]));
+        let deployer = EcsDeployer::new(AwsConfig::sample(), ecs);
+
+        let status = deployer.get_deployment_status().await.unwrap();
+        assert!(status.is_stable());
+    }
+
+    /// Answers SDK requests from a script of canned responses and keeps the requests it saw,
+    /// so the real `EcsApi` impl runs end to end without AWS
+    #[derive(Clone, Debug)]
+    struct ReplayClient {
+        responses: Arc<Mutex<Vec<(u16, serde_json::Value)>>>,
+        requests: Arc<Mutex<Vec<(String, serde_json::Value)>>>,
+    }
+
+    impl ReplayClient {
+        fn new(mut responses: Vec<(u16, serde_json::Value)>) -> Self {
+            responses.reverse();
+            ReplayClient {
+                responses: Arc::new(Mutex::new(responses)),
+                requests: Arc::new(Mutex::new(Vec::new())),
+            }
+        }
+
+        fn ecs(&self) -> aws_sdk_ecs::Client {
+            let config = aws_sdk_ecs::Config::builder()
+                .behavior_version(aws_sdk_ecs::config::BehaviorVersion::latest())
+                .region(aws_sdk_ecs::config::Region::new("us-east-1"))
+                .credentials_provider(aws_sdk_ecs::config::Credentials::for_tests())
+                .http_client(self.clone())
+                .build();
+            aws_sdk_ecs::Client::from_conf(config)
+        }
+
+        /// The `X-Amz-Target` operation and JSON body of each request, oldest first
+        fn requests(&self) -> Vec<(String, serde_json::Value)> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    impl HttpConnector for ReplayClient {
+        fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+            let target = request.headers().get("x-amz-target").unwrap_or_default();
+            let operation = target.rsplit('.').next().unwrap_or_default().to_string();
+            let body = serde_json::from_slice(request.body().bytes().unwrap_or(b"{}")).unwrap();
+            self.requests.lock().unwrap().push((operation, body));
+
+            let (status, body) = self.responses.lock().unwrap().pop().expect("no response left to replay");
+            let response = HttpResponse::new(
+                StatusCode::try_from(status).unwrap(),
+                SdkBody::from(body.to_string()),
+            );
+            HttpConnectorFuture::ready(Ok(response))
+        }
+    }
+
+    impl HttpClient for ReplayClient {
+        fn http_connector(&self, _settings: &HttpConnectorSettings, _components: &RuntimeComponents) -> SharedHttpConnector {
+            SharedHttpConnector::new(self.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sdk_describe_service() {
+        let replay = ReplayClient::new(vec![
+            (
+                200,
+                serde_json::json!({
+                    "services": [{
+                        "serviceName": "compilex7-service",
+                        "taskDefinition": TASK_DEF_7,
+                        "desiredCount": 2,
+                        "runningCount": 2,
+                    }],
+                    "failures": [],
+                }),
+            ),
+            (200, serde_json::json!({ "services": [], "failures": [{ "reason": "MISSING" }] })),
+            (400, serde_json::json!({ "__type": "ClusterNotFoundException", "message": "Cluster not found." })),
+        ]);
+        let ecs = replay.ecs();
+
+        let service = EcsApi::describe_service(&ecs, "compilex7-cluster", "compilex7-service").await.unwrap();
+        assert_eq!(service.task_definition(), Some(TASK_DEF_7));
+        assert_eq!(service.running_count(), 2);
+
+        let missing = EcsApi::describe_service(&ecs, "compilex7-cluster", "gone").await;
+        assert_eq!(missing.unwrap_err(), "Service not found");
+
+        let error = EcsApi::describe_service(&ecs, "nowhere", "compilex7-service").await.unwrap_err();
+        assert!(error.starts_with("Failed to describe service"));
+        assert!(error.contains("ClusterNotFoundException"));
+
+        let requests = replay.requests();
+        assert_eq!(requests[0].0, "DescribeServices");
+        assert_eq!(
+            requests[0].1,
+            serde_json::json!({ "cluster": "compilex7-cluster", "services": ["compilex7-service"] })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sdk_register_task_definition_sends_only_registrable_fields() {
+        let replay = ReplayClient::new(vec![(
+            200,
+            serde_json::json!({ "taskDefinition": { "taskDefinitionArn": TASK_DEF_8, "revision": 8 } }),
+        )]);
+
+        let arn = EcsApi::register_task_definition(&replay.ecs(), sample_task_definition()).await.unwrap();
+        assert_eq!(arn, TASK_DEF_8);
+
+        let requests = replay.requests();
+        let (operation, body) = &requests[0];
+        assert_eq!(operation, "RegisterTaskDefinition");
+        assert_eq!(body["family"], "compilex7-task");
+        assert_eq!(body["cpu"], "256");
+        assert_eq!(body["containerDefinitions"][1]["image"], "123456789012.dkr.ecr.us-east-1.amazonaws.com/compilex7:v1");
+        for read_only in ["taskDefinitionArn", "revision", "inferenceAccelerators"] {
+            assert!(body.get(read_only).is_none(), "{} was sent", read_only);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sdk_update_service_and_list_task_definitions() {
+        let replay = ReplayClient::new(vec![
+            (200, serde_json::json!({ "service": { "taskDefinition": TASK_DEF_8 } })),
+            (200, serde_json::json!({ "taskDefinitionArns": [TASK_DEF_8, TASK_DEF_7] })),
+        ]);
+        let ecs = replay.ecs();
+
+        EcsApi::update_service(&ecs, "compilex7-cluster", "compilex7-service", TASK_DEF_8).await.unwrap();
+        let arns = EcsApi::list_task_definitions(&ecs, "compilex7-task", TAG_LOOKUP_REVISIONS).await.unwrap();
+        assert_eq!(arns, vec![TASK_DEF_8, TASK_DEF_7]);
+
+        let requests = replay.requests();
+        assert_eq!(requests[0].0, "UpdateService");
+        assert_eq!(
+            requests[0].1,
+            serde_json::json!({
+                "cluster": "compilex7-cluster",
+                "service": "compilex7-service",
+                "taskDefinition": TASK_DEF_8,
+            })
+        );
+        assert_eq!(requests[1].0, "ListTaskDefinitions");
+        assert_eq!(
+            requests[1].1,
+            serde_json::json!({ "familyPrefix": "compilex7-task", "sort": "DESC", "maxResults": TAG_LOOKUP_REVISIONS })
+        );
+    }
 }
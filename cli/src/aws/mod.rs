@@ -7,3 +7,60 @@ pub use ecs::EcsDeployer;
 pub use ecr::EcrManager;
 pub use config::AwsConfig;
 pub use secrets::SecretsManager;
+
+use std::process::Command;
+
+/// Parameters whose value is printed masked
+const SECRET_PARAMS: &[&str] = &["secretString"];
+
+/// A command as it would be typed in a shell, for `--dry-run`
+pub fn command_line(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| shell_quote(&arg.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// An AWS API call as `service:Operation name=value ...`, for `--dry-run`
+pub fn api_call(service: &str, operation: &str, params: &[(&str, &str)]) -> String {
+    let mut call = format!("{}:{}", service, operation);
+    for (name, value) in params {
+        let value = if SECRET_PARAMS.contains(name) { "****".to_string() } else { shell_quote(value) };
+        call.push_str(&format!(" {}={}", name, value));
+    }
+    call
+}
+
+fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@,%+".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dry_run_lines_quote_and_mask() {
+        let mut command = Command::new("docker");
+        command.args(["build", "-t", "compilex7:v1", "-f", "docker/My Dockerfile", "."]);
+        assert_eq!(command_line(&command), "docker build -t compilex7:v1 -f 'docker/My Dockerfile' .");
+
+        assert_eq!(
+            SecretsManager::set_plan("prod/db", "p@ss word")[0],
+            "secretsmanager:CreateSecret name=prod/db secretString=****"
+        );
+        assert_eq!(
+            api_call("ecs", "UpdateService", &[("taskDefinition", "<new-task-definition>")]),
+            "ecs:UpdateService taskDefinition='<new-task-definition>'"
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use crate::aws;
+use async_trait::async_trait;
+use aws_sdk_secretsmanager::error::DisplayErrorContext;
+use std::collections::HashMap;
+
+pub struct SecretsManager {
+    client: Box<dyn SecretsApi>,
+}
+
+/// The Secrets Manager calls the CLI makes, so tests can stand in for the SDK client
+#[async_trait]
+pub trait SecretsApi: Send + Sync {
+    async fn get_secret_string(&self, secret_name: &str) -> Result<String, String>;
+    /// `Ok(false)` when a secret with that name already exists
+    async fn create_secret(&self, secret_name: &str, secret_value: &str) -> Result<bool, String>;
+    async fn put_secret_value(&self, secret_name: &str, secret_value: &str) -> Result<(), String>;
+    async fn delete_secret(&self, secret_name: &str) -> Result<(), String>;
+}
+
+#[async_trait]
+impl SecretsApi for aws_sdk_secretsmanager::Client {
+    async fn get_secret_string(&self, secret_name: &str) -> Result<String, String> {
+        let output = self
+            .get_secret_value()
+            .secret_id(secret_name)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to retrieve secrets: {}", DisplayErrorContext(&e)))?;
+
+        output
+            .secret_string()
+            .map(str::to_string)
+            .ok_or_else(|| format!("Secret '{}' has no string value", secret_name))
+    }
+
+    async fn create_secret(&self, secret_name: &str, secret_value: &str) -> Result<bool, String> {
+        match self
+            .create_secret()
+            .name(secret_name)
+            .secret_string(secret_value)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_resource_exists_exception()) => Ok(false),
+            Err(e) => Err(format!("Failed to create secret: {}", DisplayErrorContext(&e))),
+        }
+    }
+
+    async fn put_secret_value(&self, secret_name: &str, secret_value: &str) -> Result<(), String> {
+        self.put_secret_value()
+            .secret_id(secret_name)
+            .secret_string(secret_value)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to update secret: {}", DisplayErrorContext(&e)))?;
+        Ok(())
+    }
+
+    async fn delete_secret(&self, secret_name: &str) -> Result<(), String> {
+        self.delete_secret()
+            .secret_id(secret_name)
+            .force_delete_without_recovery(true)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to delete secret: {}", DisplayErrorContext(&e)))?;
+        Ok(())
+    }
+}
+
+impl SecretsManager {
+    pub fn new(client: impl SecretsApi + 'static) -> Self {
+        SecretsManager { client: Box::new(client) }
+    }
+
+    pub async fn get_secrets(&self, secret_name: &str) -> Result<HashMap<String, String>, String> {
+        let secret = self.client.get_secret_string(secret_name).await?;
+        Ok(parse_secret_string(&secret))
+    }
+
+    /// Creates the secret, or stores a new value if it already exists
+    pub async fn set_secret(&self, secret_name: &str, secret_value: &str) -> Result<(), String> {
+        if !self.client.create_secret(secret_name, secret_value).await? {
+            self.client.put_secret_value(secret_name, secret_value).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn delete_secret(&self, secret_name: &str) -> Result<(), String> {
+        self.client.delete_secret(secret_name).await
+    }
+
+    /// `set_secret` only stores a new value when the create finds the secret already exists
+    pub fn set_plan(secret_name: &str, secret_value: &str) -> Vec<String> {
+        vec![
+            aws::api_call("secretsmanager", "CreateSecret", &[("name", secret_name), ("secretString", secret_value)]),
+            aws::api_call(
+                "secretsmanager",
+                "PutSecretValue",
+                &[("secretId", secret_name), ("secretString", secret_value)],
+            ),
+        ]
+    }
+
+    pub fn get_plan(secret_name: &str) -> Vec<String> {
+        vec![aws::api_call("secretsmanager", "GetSecretValue", &[("secretId", secret_name)])]
+    }
+
+    pub fn delete_plan(secret_name: &str) -> Vec<String> {
+        vec![aws::api_call(
+            "secretsmanager",
+            "DeleteSecret",
+            &[("secretId", secret_name), ("forceDeleteWithoutRecovery", "true")],
+        )]
+    }
+}
+
+/// Split a SecretString into key/value pairs, keeping non-object secrets whole under "value"
+fn parse_secret_string(secret: &str) -> HashMap<String, String> {
+    // A trailing newline, e.g. from a value piped in with echo, isn't part of the secret
+    let secret = secret.strip_suffix('\n').unwrap_or(secret);
+
+    serde_json::from_str::<HashMap<String, String>>(secret).unwrap_or_else(|_| {
+        let mut secrets = HashMap::new();
+        secrets.insert("value".to_string(), secret.to_string());
+        secrets
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// An in-memory Secrets Manager
+    #[derive(Clone, Default)]
+    struct FakeSecrets {
+        secrets: Arc<Mutex<HashMap<String, String>>>,
+    }
+
+    #[async_trait]
+    impl SecretsApi for FakeSecrets {
+        async fn get_secret_string(&self, secret_name: &str) -> Result<String, String> {
+            self.secrets.lock().unwrap().get(secret_name).cloned().ok_or_else(|| "not found".to_string())
+        }
+
+        async fn create_secret(&self, secret_name: &str, secret_value: &str) -> Result<bool, String> {
+            let mut secrets = self.secrets.lock().unwrap();
+            if secrets.contains_key(secret_name) {
+                return Ok(false);
+            }
+            secrets.insert(secret_name.to_string(), secret_value.to_string());
+            Ok(true)
+        }
+
+        async fn put_secret_value(&self, secret_name: &str, secret_value: &str) -> Result<(), String> {
+            self.secrets.lock().unwrap().insert(secret_name.to_string(), secret_value.to_string());
+            Ok(())
+        }
+
+        async fn delete_secret(&self, secret_name: &str) -> Result<(), String> {
+            self.secrets.lock().unwrap().remove(secret_name).map(|_| ()).ok_or_else(|| "not found".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_set_secret_creates_then_updates() {
+        let fake = FakeSecrets::default();
+        let manager = SecretsManager::new(fake.clone());
+
+        manager.set_secret("prod/api", "first").await.unwrap();
+        manager.set_secret("prod/api", "{\"API_KEY\":\"second\"}").await.unwrap();
+
+        let secrets = manager.get_secrets("prod/api").await.unwrap();
+        assert_eq!(secrets["API_KEY"], "second");
+
+        manager.delete_secret("prod/api").await.unwrap();
+        assert!(fake.secrets.lock().unwrap().is_empty());
+        assert!(manager.get_secrets("prod/api").await.is_err());
+    }
+
+    #[test]
+    fn test_parse_secret_string_keeps_urls_intact() {
+        let secrets = parse_secret_string("{\"DATABASE_URL\":\"postgres://u:p@h:5432/db\"}\n");
+        assert_eq!(secrets.len(), 1);
+        assert_eq!(secrets["DATABASE_URL"], "postgres://u:p@h:5432/db");
+    }
+
+    #[test]
+    fn test_parse_secret_string_multiline_object() {
+        let secrets = parse_secret_string("{\n  \"API_KEY\": \"abc:123\",\n  \"REGION\": \"us-east-1\"\n}");
+        assert_eq!(secrets["API_KEY"], "abc:123");
+        assert_eq!(secrets["REGION"], "us-east-1");
+    }
+
+    #[test]
+    fn test_parse_secret_string_opaque_value() {
+        let secrets = parse_secret_string("s3cr3t:with:colons\n");
+        assert_eq!(secrets.len(), 1);
+        assert_eq!(secrets["value"], "s3cr3t:with:colons");
+    }
+}
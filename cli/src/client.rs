@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::error::CliError;
+
 pub struct ApiClient {
     base_url: String,
     token: Option<String>,
@@ -34,116 +36,152 @@ impl ApiClient {
         Ok(builder)
     }
 
+    /// Send the request, turning transport failures and error responses into `CliError`s
+    async fn send(&self, req: reqwest::RequestBuilder) -> anyhow::Result<reqwest::Response> {
+        let response = req.send().await.map_err(|e| CliError::NetworkError(e.to_string()))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let message = response
+            .json::<ApiErrorBody>()
+            .await
+            .map(|body| body.message)
+            .unwrap_or_else(|_| status.to_string());
+        let err = match status.as_u16() {
+            401 | 403 => CliError::AuthError(message),
+            404 => CliError::NotFound(message),
+            400 | 409 | 422 => CliError::ValidationError(message),
+            _ => CliError::ApiError(message),
+        };
+        Err(err.into())
+    }
+
     pub async fn login(&self, email: &str, password: &str) -> anyhow::Result<LoginResponse> {
         let req = self.request("POST", "/api/auth/login").await?;
-        let response = req
-            .json(&serde_json::json!({ "email": email, "password": password }))
-            .send()
-            .await?;
+        let response = self.send(req.json(&serde_json::json!({ "email": email, "password": password }))).await?;
         
         response.json().await.map_err(Into::into)
     }
 
-    pub async fn refresh_token(&self) -> anyhow::Result<LoginResponse> {
+    pub async fn refresh_token(&self, refresh_token: &str) -> anyhow::Result<LoginResponse> {
         let req = self.request("POST", "/api/auth/refresh").await?;
-        let response = req.send().await?;
+        let response = self.send(req.json(&serde_json::json!({ "refresh_token": refresh_token }))).await?;
+
         response.json().await.map_err(Into::into)
     }
 
     pub async fn get_user_info(&self) -> anyhow::Result<UserInfo> {
         let req = self.request("GET", "/api/auth/me").await?;
-        let response = req.send().await?;
+        let response = self.send(req).await?;
         response.json().await.map_err(Into::into)
     }
 
     pub async fn list_projects(&self) -> anyhow::Result<Vec<ProjectInfo>> {
         let req = self.request("GET", "/api/projects").await?;
-        let response = req.send().await?;
+        let response = self.send(req).await?;
         response.json().await.map_err(Into::into)
     }
 
     pub async fn get_project(&self, id: &str) -> anyhow::Result<ProjectInfo> {
         let req = self.request("GET", &format!("/api/projects/{}", id)).await?;
-        let response = req.send().await?;
+        let response = self.send(req).await?;
         response.json().await.map_err(Into::into)
     }
 
     pub async fn create_project(&self, name: &str, description: Option<&str>) -> anyhow::Result<ProjectInfo> {
         let req = self.request("POST", "/api/projects").await?;
-        let response = req
-            .json(&serde_json::json!({ "name": name, "description": description }))
-            .send()
-            .await?;
+        let response = self.send(req.json(&serde_json::json!({ "name": name, "description": description }))).await?;
         
         response.json().await.map_err(Into::into)
     }
 
     pub async fn delete_project(&self, id: &str) -> anyhow::Result<()> {
         let req = self.request("DELETE", &format!("/api/projects/{}", id)).await?;
-        req.send().await?;
+        self.send(req).await?;
         Ok(())
     }
 
-    pub async fn deploy_code(&self, project: &str, files: &[String], message: &str) -> anyhow::Result<DeploymentResponse> {
+    pub async fn list_files(&self, project: &str) -> anyhow::Result<Vec<ProjectFile>> {
+        let req = self.request("GET", &format!("/api/projects/{}/files", project)).await?;
+        let response = self.send(req).await?;
+        response.json().await.map_err(Into::into)
+    }
+
+    /// The server addresses files by ID, so this looks the path up in the file listing
+    pub async fn get_file(&self, project: &str, path: &str) -> anyhow::Result<ProjectFile> {
+        let path = path.trim_start_matches("./");
+        self.list_files(project)
+            .await?
+            .into_iter()
+            .find(|file| file.file_path == path)
+            .ok_or_else(|| CliError::NotFound(format!("no file '{}' in project {}", path, project)).into())
+    }
+
+    pub async fn deploy_code(&self, project: &str, files: &[FileContent], message: &str) -> anyhow::Result<DeploymentResponse> {
         let req = self.request("POST", &format!("/api/projects/{}/deploy", project)).await?;
-        let response = req
-            .json(&serde_json::json!({ "files": files, "message": message }))
-            .send()
-            .await?;
+        let response = self.send(req.json(&serde_json::json!({ "files": files, "message": message }))).await?;
         
         response.json().await.map_err(Into::into)
     }
 
     pub async fn pull_code(&self, project: &str) -> anyhow::Result<Vec<FileContent>> {
         let req = self.request("GET", &format!("/api/projects/{}/code", project)).await?;
-        let response = req.send().await?;
+        let response = self.send(req).await?;
         response.json().await.map_err(Into::into)
     }
 
     pub async fn analyze_code(&self, project: &str) -> anyhow::Result<CodeAnalysis> {
         let req = self.request("POST", &format!("/api/projects/{}/analyze", project)).await?;
-        let response = req.send().await?;
+        let response = self.send(req).await?;
         response.json().await.map_err(Into::into)
     }
 
     pub async fn get_deployment_history(&self, project: &str, limit: usize) -> anyhow::Result<Vec<DeploymentInfo>> {
         let req = self.request("GET", &format!("/api/projects/{}/deployments?limit={}", project, limit)).await?;
-        let response = req.send().await?;
+        let response = self.send(req).await?;
         response.json().await.map_err(Into::into)
     }
 
     pub async fn list_agents(&self) -> anyhow::Result<Vec<AgentInfo>> {
         let req = self.request("GET", "/api/agents").await?;
-        let response = req.send().await?;
+        let response = self.send(req).await?;
         response.json().await.map_err(Into::into)
     }
 
-    pub async fn run_agent(&self, project: &str, agent: &str) -> anyhow::Result<AgentResult> {
-        let req = self.request("POST", &format!("/api/agents/{}/run", agent)).await?;
-        let response = req
-            .json(&serde_json::json!({ "project_id": project }))
-            .send()
-            .await?;
-        
+    /// Queue a task for `agent` (backend/frontend/qa); follow it with `get_agent_task_status`
+    pub async fn run_agent(&self, project: &str, agent: &str, task: &str) -> anyhow::Result<AgentTaskResponse> {
+        let req = self.request("POST", &format!("/api/agents/{}", agent)).await?;
+        let response = self.send(req.json(&serde_json::json!({ "project_id": project, "task_description": task }))).await?;
+
         response.json().await.map_err(Into::into)
     }
 
-    pub async fn get_agent_status(&self, agent: &str) -> anyhow::Result<AgentStatus> {
-        let req = self.request("GET", &format!("/api/agents/{}/status", agent)).await?;
-        let response = req.send().await?;
+    pub async fn get_agent_task_status(&self, task_id: &str) -> anyhow::Result<AgentTaskStatus> {
+        let req = self.request("GET", &format!("/api/agents/status/{}", task_id)).await?;
+        let response = self.send(req).await?;
         response.json().await.map_err(Into::into)
     }
 
     pub async fn health_check(&self) -> anyhow::Result<HealthStatus> {
-        let req = self.request("GET", "/api/health").await?;
-        let response = req.send().await?;
+        let req = self.request("GET", "/health").await?;
+        let response = self.send(req).await?;
         response.json().await.map_err(Into::into)
     }
 }
 
+/// The server's error body
+#[derive(Debug, Deserialize)]
+struct ApiErrorBody {
+    message: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct LoginResponse {
+    #[serde(rename = "access_token")]
     pub token: String,
+    pub refresh_token: String,
     pub user: UserInfo,
 }
 
@@ -154,7 +192,7 @@ pub struct UserInfo {
     pub created_at: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ProjectInfo {
     pub id: String,
     pub name: String,
@@ -168,12 +206,21 @@ pub struct DeploymentResponse {
     pub created_at: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct FileContent {
     pub path: String,
     pub content: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectFile {
+    pub id: String,
+    pub file_path: String,
+    pub content: String,
+    pub language: Option<String>,
+    pub updated_at: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct CodeAnalysis {
     pub lines_of_code: usize,
@@ -181,7 +228,7 @@ pub struct CodeAnalysis {
     pub issues: usize,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct DeploymentInfo {
     pub id: String,
     pub status: String,
@@ -189,29 +236,69 @@ pub struct DeploymentInfo {
     pub created_at: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct AgentInfo {
     pub name: String,
     pub description: String,
 }
 
 #[derive(Debug, Deserialize)]
-pub struct AgentResult {
-    pub id: String,
+pub struct AgentTaskResponse {
+    pub task_id: String,
+    pub agent_type: String,
     pub status: String,
-    pub output: Option<String>,
 }
 
+/// `status` is queued, processing, completed, failed, cancelled or not_found
 #[derive(Debug, Deserialize)]
-pub struct AgentStatus {
+pub struct AgentTaskStatus {
+    pub task_id: String,
     pub status: String,
-    pub last_run: String,
+    pub progress: f64,
+    pub result: Option<serde_json::Value>,
+    #[serde(default)]
+    pub queue_depth: usize,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct HealthStatus {
     pub ok: bool,
     pub database_ok: bool,
     pub cache_ok: bool,
     pub agents_running: usize,
 }
+
+/// A local HTTP server for tests that answers each request with the next of `bodies`
+/// (then the last one again), recording the request lines it saw
+#[cfg(test)]
+pub mod stand_in {
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    pub async fn server(bodies: Vec<serde_json::Value>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            for i in 0.. {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0u8; 8192];
+                let n = socket.read(&mut buf).await.unwrap();
+                let request = String::from_utf8_lossy(&buf[..n]);
+                seen.lock().unwrap().push(request.lines().next().unwrap_or_default().to_string());
+
+                let body = bodies[i.min(bodies.len() - 1)].to_string();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        (format!("http://{}", addr), requests)
+    }
+}
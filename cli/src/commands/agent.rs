@@ -1,7 +1,15 @@
 use clap::{Parser, Subcommand};
+use crate::client::{AgentInfo, AgentTaskStatus, ApiClient};
 use crate::config::Config;
+use crate::error::CliError;
+use crate::output::{self, OutputFormat, Table};
 use crate::utils;
 use colored::*;
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// How often `--follow` polls the task status
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(Parser)]
 pub struct AgentArgs {
@@ -20,85 +28,233 @@ enum AgentCommand {
         /// Project ID or name
         #[arg(short, long)]
         project: Option<String>,
+        /// What the agent should do
+        #[arg(short, long)]
+        task: Option<String>,
+        /// Wait for the task, printing progress and the result
+        #[arg(short, long)]
+        follow: bool,
     },
-    /// Check agent status
+    /// Check the status of an agent task
     Status {
-        /// Agent name
-        agent: String,
+        /// Task ID printed by `agent run`
+        task_id: String,
+        /// Keep printing progress until the task finishes
+        #[arg(short, long)]
+        follow: bool,
     },
 }
 
-pub async fn execute(config: Config, args: AgentArgs) -> anyhow::Result<()> {
-    if config.auth_token.is_empty() {
-        println!("{}", "Not authenticated. Run 'cx7 auth login' first.".red());
-        return Ok(());
-    }
+pub async fn execute(config: Config, args: AgentArgs, output: OutputFormat) -> anyhow::Result<()> {
+    config.require_auth()?;
 
     match args.command {
-        AgentCommand::List => list_agents(config).await,
-        AgentCommand::Run { agent, project } => run_agent(config, &agent, project).await,
-        AgentCommand::Status { agent } => check_status(config, &agent).await,
+        AgentCommand::List => list_agents(config, output).await,
+        AgentCommand::Run { agent, project, task, follow } => run_agent(config, &agent, project, task, follow).await,
+        AgentCommand::Status { task_id, follow } => check_status(config, &task_id, follow).await,
     }
 }
 
-async fn list_agents(config: Config) -> anyhow::Result<()> {
-    utils::spinner_start("Fetching agents...");
+async fn list_agents(config: Config, output: OutputFormat) -> anyhow::Result<()> {
+    output.spinner_start("Fetching agents...");
 
     let client = crate::client::ApiClient::new(&config.server_url, Some(&config.auth_token));
     match client.list_agents().await {
         Ok(agents) => {
-            utils::spinner_stop();
-            println!("{}", "Available Agents:".bold());
-            for agent in agents {
-                println!("  {} - {}", agent.name.cyan(), agent.description);
-            }
-            Ok(())
+            output.spinner_stop();
+            write_agents(&mut io::stdout().lock(), output, &agents)
         }
         Err(e) => {
-            utils::spinner_stop();
-            Err(anyhow::anyhow!("Failed to fetch agents: {}", e))
+            output.spinner_stop();
+            Err(e.context("Failed to fetch agents"))
         }
     }
 }
 
-async fn run_agent(config: Config, agent: &str, project: Option<String>) -> anyhow::Result<()> {
+fn write_agents(out: &mut impl Write, format: OutputFormat, agents: &[AgentInfo]) -> anyhow::Result<()> {
+    output::render(out, format, agents, |out, agents| {
+        let mut table = Table::new(&["AGENT", "DESCRIPTION"]);
+        for agent in agents {
+            table.row(vec![agent.name.clone(), agent.description.clone()]);
+        }
+        table.write_to(out)
+    })
+}
+
+async fn run_agent(
+    config: Config,
+    agent: &str,
+    project: Option<String>,
+    task: Option<String>,
+    follow: bool,
+) -> anyhow::Result<()> {
     let project = project.unwrap_or_else(|| "default".to_string());
+    let task = task.unwrap_or_else(|| utils::prompt("Task for the agent: "));
 
-    utils::spinner_start(&format!("Running {} agent...", agent));
+    utils::spinner_start(&format!("Submitting {} agent task...", agent));
 
-    let client = crate::client::ApiClient::new(&config.server_url, Some(&config.auth_token));
-    match client.run_agent(&project, agent).await {
-        Ok(result) => {
+    let client = ApiClient::new(&config.server_url, Some(&config.auth_token));
+    match client.run_agent(&project, agent, &task).await {
+        Ok(submitted) => {
             utils::spinner_stop();
-            println!("{}", format!("✓ {} agent completed", agent).green().bold());
-            println!("  Result ID: {}", result.id);
-            println!("  Status: {}", result.status);
-            if let Some(output) = result.output {
-                println!("  Output:\n{}", output);
+            utils::print_success(&format!("{} agent task {}", submitted.agent_type, submitted.status));
+            println!("  Task ID: {}", submitted.task_id);
+
+            if follow {
+                follow_task(&client, &submitted.task_id, FOLLOW_POLL_INTERVAL).await?;
+            } else {
+                println!("  Check on it with 'cx7 agent status {}'", submitted.task_id);
             }
             Ok(())
         }
         Err(e) => {
             utils::spinner_stop();
-            Err(anyhow::anyhow!("Agent execution failed: {}", e))
+            Err(e.context("Agent execution failed"))
         }
     }
 }
 
-async fn check_status(config: Config, agent: &str) -> anyhow::Result<()> {
+async fn check_status(config: Config, task_id: &str, follow: bool) -> anyhow::Result<()> {
+    let client = ApiClient::new(&config.server_url, Some(&config.auth_token));
+    if follow {
+        follow_task(&client, task_id, FOLLOW_POLL_INTERVAL).await?;
+        return Ok(());
+    }
+
     utils::spinner_start("Checking status...");
 
-    let client = crate::client::ApiClient::new(&config.server_url, Some(&config.auth_token));
-    match client.get_agent_status(agent).await {
+    match client.get_agent_task_status(task_id).await {
         Ok(status) => {
             utils::spinner_stop();
-            println!("{}", format!("{}: {}", agent, status.status).cyan());
-            println!("  Last Run: {}", status.last_run);
+            print_progress(&status);
+            if is_finished(&status.status) {
+                print_result(&status);
+            }
             Ok(())
         }
         Err(e) => {
             utils::spinner_stop();
-            Err(anyhow::anyhow!("Failed to check status: {}", e))
+            Err(e.context("Failed to check status"))
+        }
+    }
+}
+
+/// Poll the task until it finishes, printing each change of status. Only a completed
+/// task is `Ok`, so a failed one exits the CLI with a nonzero code.
+async fn follow_task(client: &ApiClient, task_id: &str, interval: Duration) -> anyhow::Result<AgentTaskStatus> {
+    let mut last_seen: Option<(String, f64)> = None;
+
+    loop {
+        let status = client
+            .get_agent_task_status(task_id)
+            .await
+            .map_err(|e| e.context("Failed to check status"))?;
+
+        let seen = (status.status.clone(), status.progress);
+        if last_seen.as_ref() != Some(&seen) {
+            print_progress(&status);
+            last_seen = Some(seen);
         }
+
+        match status.status.as_str() {
+            "completed" => {
+                print_result(&status);
+                return Ok(status);
+            }
+            "failed" | "cancelled" => {
+                print_result(&status);
+                return Err(anyhow::anyhow!("Agent task {} {}", status.task_id, status.status));
+            }
+            "not_found" => return Err(CliError::NotFound(format!("agent task {}", task_id)).into()),
+            _ => tokio::time::sleep(interval).await,
+        }
+    }
+}
+
+fn is_finished(status: &str) -> bool {
+    matches!(status, "completed" | "failed" | "cancelled")
+}
+
+fn print_progress(status: &AgentTaskStatus) {
+    let line = format!("{} ({:.0}%)", status.status, status.progress);
+    let line = match status.status.as_str() {
+        "completed" => line.green(),
+        "failed" | "cancelled" | "not_found" => line.red(),
+        _ => line.cyan(),
+    };
+    if status.status == "queued" && status.queue_depth > 0 {
+        println!("  {} - {} tasks in queue", line, status.queue_depth);
+    } else {
+        println!("  {}", line);
+    }
+}
+
+/// The agent's output, or the error a failed task recorded
+fn print_result(status: &AgentTaskStatus) {
+    let Some(result) = &status.result else { return };
+    match result.get("error").and_then(|error| error.as_str()) {
+        Some(error) => println!("{}", format!("  Error: {}", error).red()),
+        None => println!("  Result:\n{}", serde_json::to_string_pretty(result).unwrap_or_default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::stand_in;
+    use serde_json::json;
+
+    fn task_status(status: &str, progress: f64, result: Option<serde_json::Value>) -> serde_json::Value {
+        json!({ "task_id": "task-1", "status": status, "progress": progress, "result": result, "queue_depth": 0 })
+    }
+
+    #[tokio::test]
+    async fn test_follow_drives_task_to_completion() {
+        let (server, requests) = stand_in::server(vec![
+            task_status("queued", 0.0, None),
+            task_status("processing", 50.0, None),
+            task_status("processing", 50.0, None),
+            task_status("completed", 100.0, Some(json!({ "code": "fn main() {}" }))),
+        ])
+        .await;
+        let client = ApiClient::new(&server, Some("token"));
+
+        let status = follow_task(&client, "task-1", Duration::from_millis(5)).await.unwrap();
+        assert_eq!(status.status, "completed");
+        assert_eq!(status.progress, 100.0);
+        assert_eq!(status.result.unwrap()["code"], "fn main() {}");
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 4);
+        assert!(requests.iter().all(|line| line == "GET /api/agents/status/task-1 HTTP/1.1"));
+    }
+
+    #[tokio::test]
+    async fn test_follow_fails_when_the_task_fails() {
+        let (server, _) = stand_in::server(vec![
+            task_status("processing", 50.0, None),
+            task_status("failed", 0.0, Some(json!({ "error": "model unavailable" }))),
+        ])
+        .await;
+        let client = ApiClient::new(&server, Some("token"));
+
+        let error = follow_task(&client, "task-1", Duration::from_millis(5)).await.unwrap_err();
+        assert_eq!(error.to_string(), "Agent task task-1 failed");
+    }
+
+    #[test]
+    fn test_list_json_output_round_trips() {
+        let agents = vec![
+            AgentInfo { name: "backend".to_string(), description: "API and data layer".to_string() },
+            AgentInfo { name: "qa".to_string(), description: "Test generation".to_string() },
+        ];
+
+        let mut out = Vec::new();
+        write_agents(&mut out, OutputFormat::Json, &agents).unwrap();
+
+        let parsed: Vec<AgentInfo> = serde_json::from_slice(&out).unwrap();
+        let names: Vec<&str> = parsed.iter().map(|agent| agent.name.as_str()).collect();
+        assert_eq!(names, ["backend", "qa"]);
+        assert_eq!(parsed[1].description, "Test generation");
     }
 }
@@ -47,16 +47,17 @@ async fn login(mut config: Config, email: Option<String>) -> anyhow::Result<()>
     match client.login(&email, &password).await {
         Ok(response) => {
             config.auth_token = response.token;
+            config.refresh_token = response.refresh_token;
             config.user_email = Some(email.clone());
             config.save().await?;
             
             utils::spinner_stop();
-            println!("{}", format!("✓ Successfully logged in as {}", email).green().bold());
+            utils::print_success(&format!("Successfully logged in as {} (profile '{}')", email, config.profile));
             Ok(())
         }
         Err(e) => {
             utils::spinner_stop();
-            Err(anyhow::anyhow!("Login failed: {}", e))
+            Err(e.context("Login failed"))
         }
     }
 }
@@ -64,17 +65,15 @@ async fn login(mut config: Config, email: Option<String>) -> anyhow::Result<()>
 async fn logout(config: Config) -> anyhow::Result<()> {
     let mut cfg = config;
     cfg.auth_token = String::new();
+    cfg.refresh_token = String::new();
     cfg.user_email = None;
     cfg.save().await?;
-    println!("{}", "✓ Successfully logged out".green().bold());
+    utils::print_success("Successfully logged out");
     Ok(())
 }
 
 async fn whoami(config: Config) -> anyhow::Result<()> {
-    if config.auth_token.is_empty() {
-        println!("{}", "Not authenticated. Run 'cx7 auth login' to authenticate.".yellow());
-        return Ok(());
-    }
+    config.require_auth()?;
 
     utils::spinner_start("Fetching user info...");
     
@@ -90,32 +89,30 @@ async fn whoami(config: Config) -> anyhow::Result<()> {
         }
         Err(e) => {
             utils::spinner_stop();
-            Err(anyhow::anyhow!("Failed to fetch user info: {}", e))
+            Err(e.context("Failed to fetch user info"))
         }
     }
 }
 
 async fn refresh_token(mut config: Config) -> anyhow::Result<()> {
-    if config.auth_token.is_empty() {
-        println!("{}", "Not authenticated. Run 'cx7 auth login' first.".yellow());
-        return Ok(());
-    }
+    config.require_auth()?;
 
     utils::spinner_start("Refreshing token...");
 
     let client = crate::client::ApiClient::new(&config.server_url, Some(&config.auth_token));
-    match client.refresh_token().await {
+    match client.refresh_token(&config.refresh_token).await {
         Ok(response) => {
             config.auth_token = response.token;
+            config.refresh_token = response.refresh_token;
             config.save().await?;
             
             utils::spinner_stop();
-            println!("{}", "✓ Token refreshed successfully".green().bold());
+            utils::print_success("Token refreshed successfully");
             Ok(())
         }
         Err(e) => {
             utils::spinner_stop();
-            Err(anyhow::anyhow!("Token refresh failed: {}", e))
+            Err(e.context("Token refresh failed"))
         }
     }
 }
@@ -1,47 +1,174 @@
+use clap::{Parser, Subcommand};
+use crate::aws::ecs::{HealthCheckConfig, HttpHealthChecker};
 use crate::aws::{AwsConfig, EcrManager, EcsDeployer, SecretsManager};
-use crate::utils::*;
+use crate::error::CliError;
+use crate::utils::{self, print_success};
+use colored::*;
+use std::io::{self, IsTerminal, Read};
 
-pub async fn deploy_to_ecs(
-    dockerfile_path: Option<String>,
-    tag: Option<String>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let config = AwsConfig::from_env()?;
+#[derive(Parser)]
+pub struct AwsArgs {
+    /// Print the AWS calls and docker commands that would run instead of running them
+    #[arg(global = true, long)]
+    dry_run: bool,
+
+    #[command(subcommand)]
+    command: AwsCommand,
+}
+
+#[derive(Subcommand)]
+enum AwsCommand {
+    /// Build and push an image to ECR, then roll it out to the ECS service
+    Deploy {
+        /// Dockerfile to build (default: Dockerfile)
+        #[arg(long)]
+        dockerfile: Option<String>,
+        /// Image tag (default: the current time, e.g. 20240101-120000)
+        #[arg(long)]
+        tag: Option<String>,
+    },
+    /// Point the ECS service back at an earlier image
+    Rollback {
+        /// Image tag or task definition ARN to roll back to
+        #[arg(long)]
+        previous_tag: String,
+    },
+    /// Show the ECS service's deployment status
+    Status,
+    /// Manage secrets in AWS Secrets Manager
+    Secrets {
+        #[command(subcommand)]
+        action: SecretsAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum SecretsAction {
+    /// Create a secret, or update it if it exists. The value is prompted for without
+    /// echoing, or read from stdin when that isn't a terminal.
+    Set { name: String },
+    /// Print a secret's values
+    Get { name: String },
+    /// Delete a secret without a recovery window
+    Delete { name: String },
+}
+
+pub async fn execute(args: AwsArgs) -> anyhow::Result<()> {
+    let dry_run = args.dry_run;
+
+    match args.command {
+        AwsCommand::Deploy { dockerfile, tag } => deploy_to_ecs(dockerfile, tag, dry_run).await,
+        AwsCommand::Rollback { previous_tag } => rollback_deployment(previous_tag, dry_run).await,
+        AwsCommand::Status => check_deployment_status(dry_run).await,
+        AwsCommand::Secrets { action } => match action {
+            SecretsAction::Set { name } => {
+                let value = if dry_run { None } else { Some(read_secret_value(&name)?) };
+                manage_secrets("set", &name, value, dry_run).await
+            }
+            SecretsAction::Get { name } => manage_secrets("get", &name, None, dry_run).await,
+            SecretsAction::Delete { name } => manage_secrets("delete", &name, None, dry_run).await,
+        },
+    }
+}
+
+/// Every command validates first, so a missing setting fails before any aws/docker call
+fn aws_config() -> Result<AwsConfig, CliError> {
+    let config = AwsConfig::from_env();
+    config.validate().map_err(CliError::ConfigError)?;
+    Ok(config)
+}
+
+fn print_plan(steps: &[String]) {
+    println!("{}", "Dry run, nothing was executed. Steps that would run:".bold());
+    for step in steps {
+        println!("  {}", step);
+    }
+}
+
+pub async fn deploy_to_ecs(dockerfile_path: Option<String>, tag: Option<String>, dry_run: bool) -> anyhow::Result<()> {
+    let config = aws_config()?;
     let tag = tag.unwrap_or_else(|| {
         chrono::Local::now().format("%Y%m%d-%H%M%S").to_string()
     });
 
     let dockerfile = dockerfile_path.unwrap_or_else(|| "Dockerfile".to_string());
 
+    // Deploy to ECS, gated on the health endpoint when one is configured
+    let health_check = config
+        .health_check_url
+        .clone()
+        .map(|url| HealthCheckConfig::new(url, config.health_check_attempts));
+
+    let sdk_config = config.sdk_config().await;
+    let ecr = EcrManager::new(config.clone(), aws_sdk_ecr::Client::new(&sdk_config));
+    let ecs = EcsDeployer::new(config, aws_sdk_ecs::Client::new(&sdk_config));
+
+    if dry_run {
+        let mut plan = ecr.build_and_push_plan(&dockerfile, &tag);
+        plan.extend(ecs.deploy_plan());
+        print_plan(&plan);
+        if let Some(health) = health_check {
+            println!("  (then GET {} until healthy)", health.url);
+        }
+        return Ok(());
+    }
+
     // Build and push to ECR
-    let ecr = EcrManager::new(config.clone());
-    let image_uri = ecr.build_and_push(&dockerfile, &tag).await?;
+    let image_uri = ecr.build_and_push(&dockerfile, &tag).await.map_err(anyhow::Error::msg)?;
 
-    // Deploy to ECS
-    let ecs = EcsDeployer::new(config);
-    ecs.deploy(&image_uri).await?;
+    match health_check {
+        Some(health) => {
+            ecs.deploy_blue_green(&image_uri, &health, &HttpHealthChecker::new())
+                .await
+                .map_err(anyhow::Error::msg)?;
+        }
+        None => ecs.deploy(&image_uri).await.map_err(anyhow::Error::msg)?,
+    }
 
-    success(&format!("Successfully deployed to ECS with tag: {}", tag));
+    print_success(&format!("Successfully deployed to ECS with tag: {}", tag));
     Ok(())
 }
 
-pub async fn rollback_deployment(previous_tag: String) -> Result<(), Box<dyn std::error::Error>> {
-    let config = AwsConfig::from_env()?;
-    let ecs = EcsDeployer::new(config);
-    
-    let task_def = format!("compilex7-task:1"); // This should be retrieved from deployment history
-    ecs.rollback(&task_def).await?;
+pub async fn rollback_deployment(previous_tag: String, dry_run: bool) -> anyhow::Result<()> {
+    let config = aws_config()?;
+    let sdk_config = config.sdk_config().await;
+    let ecs = EcsDeployer::new(config, aws_sdk_ecs::Client::new(&sdk_config));
+
+    if dry_run {
+        print_plan(&ecs.rollback_plan(&previous_tag));
+        return Ok(());
+    }
+
+    // Accept a task definition ARN directly, otherwise find the revision that ran the tag
+    let task_def = if previous_tag.starts_with("arn:") {
+        previous_tag.clone()
+    } else {
+        ecs.find_task_definition_for_tag(&previous_tag)
+            .await
+            .map_err(CliError::NotFound)?
+    };
+    ecs.rollback(&task_def).await.map_err(anyhow::Error::msg)?;
 
-    success(&format!("Successfully rolled back to tag: {}", previous_tag));
+    print_success(&format!("Successfully rolled back to tag: {}", previous_tag));
     Ok(())
 }
 
-pub async fn check_deployment_status() -> Result<(), Box<dyn std::error::Error>> {
-    let config = AwsConfig::from_env()?;
-    let ecs = EcsDeployer::new(config);
-    
-    let status = ecs.get_deployment_status().await?;
-    
-    println!("\n{}", separator("Deployment Status"));
+pub async fn check_deployment_status(dry_run: bool) -> anyhow::Result<()> {
+    let config = aws_config()?;
+    let sdk_config = config.sdk_config().await;
+    let ecs = EcsDeployer::new(config, aws_sdk_ecs::Client::new(&sdk_config));
+
+    if dry_run {
+        print_plan(&ecs.status_plan());
+        return Ok(());
+    }
+
+    utils::spinner_start("Fetching deployment status...");
+    let status = ecs.get_deployment_status().await;
+    utils::spinner_stop();
+    let status = status.map_err(anyhow::Error::msg)?;
+
+    println!("\n{}", "Deployment Status".bold());
     println!("Service: {}", status.service);
     println!("Running: {}/{}", status.running_count, status.desired_count);
     println!("Pending: {}", status.pending_count);
@@ -50,25 +177,124 @@ pub async fn check_deployment_status() -> Result<(), Box<dyn std::error::Error>>
     Ok(())
 }
 
-pub async fn manage_secrets(action: &str, secret_name: &str, secret_value: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
-    let config = AwsConfig::from_env()?;
-    
+pub async fn manage_secrets(
+    action: &str,
+    secret_name: &str,
+    secret_value: Option<String>,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let config = aws_config()?;
+
+    if dry_run {
+        let plan = match action {
+            "set" => SecretsManager::set_plan(secret_name, secret_value.as_deref().unwrap_or("<secret value>")),
+            "get" => SecretsManager::get_plan(secret_name),
+            "delete" => SecretsManager::delete_plan(secret_name),
+            _ => return Err(invalid_action().into()),
+        };
+        print_plan(&plan);
+        return Ok(());
+    }
+
+    let secrets = SecretsManager::new(aws_sdk_secretsmanager::Client::new(&config.sdk_config().await));
     match action {
         "set" => {
-            let value = secret_value.ok_or("Secret value required")?;
-            SecretsManager::set_secret(secret_name, &value, &config.region).await?;
-            success(&format!("Secret '{}' set successfully", secret_name));
+            let value = secret_value.ok_or_else(|| CliError::ValidationError("secret value required".to_string()))?;
+            secrets
+                .set_secret(secret_name, &value)
+                .await
+                .map_err(anyhow::Error::msg)?;
+            print_success(&format!("Secret '{}' set successfully", secret_name));
         }
         "get" => {
-            let secrets = SecretsManager::get_secrets(secret_name, &config.region).await?;
-            println!("{:?}", secrets);
+            let values = secrets.get_secrets(secret_name).await.map_err(anyhow::Error::msg)?;
+            let mut keys: Vec<_> = values.keys().collect();
+            keys.sort();
+            for key in keys {
+                println!("{}={}", key, values[key]);
+            }
         }
         "delete" => {
-            SecretsManager::delete_secret(secret_name, &config.region).await?;
-            success(&format!("Secret '{}' deleted successfully", secret_name));
+            secrets
+                .delete_secret(secret_name)
+                .await
+                .map_err(anyhow::Error::msg)?;
+            print_success(&format!("Secret '{}' deleted successfully", secret_name));
         }
-        _ => return Err("Invalid action. Use 'set', 'get', or 'delete'".into()),
+        _ => return Err(invalid_action().into()),
     }
 
     Ok(())
 }
+
+/// Keeps the value off the command line, where shell history and `ps` would see it
+fn read_secret_value(name: &str) -> anyhow::Result<String> {
+    if io::stdin().is_terminal() {
+        let value = rpassword::prompt_password(format!("Value for {}: ", name))?;
+        return non_empty_secret(value);
+    }
+    secret_from_reader(io::stdin().lock())
+}
+
+/// Everything the reader holds, less one trailing newline as `echo` adds
+fn secret_from_reader(mut reader: impl Read) -> anyhow::Result<String> {
+    let mut value = String::new();
+    reader.read_to_string(&mut value)?;
+    if let Some(stripped) = value.strip_suffix('\n') {
+        value = stripped.strip_suffix('\r').unwrap_or(stripped).to_string();
+    }
+    non_empty_secret(value)
+}
+
+fn non_empty_secret(value: String) -> anyhow::Result<String> {
+    if value.is_empty() {
+        return Err(CliError::ValidationError("secret value required".to_string()).into());
+    }
+    Ok(value)
+}
+
+fn invalid_action() -> CliError {
+    CliError::ValidationError("invalid action. Use 'set', 'get', or 'delete'".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_aws_subcommands() {
+        let args = AwsArgs::try_parse_from(["aws", "deploy", "--dockerfile", "Dockerfile.prod", "--tag", "v1.2.0", "--dry-run"])
+            .unwrap();
+        assert!(args.dry_run);
+        assert!(matches!(
+            args.command,
+            AwsCommand::Deploy { dockerfile: Some(ref d), tag: Some(ref t) } if d == "Dockerfile.prod" && t == "v1.2.0"
+        ));
+
+        let args = AwsArgs::try_parse_from(["aws", "rollback", "--previous-tag", "v1.1.0"]).unwrap();
+        assert!(!args.dry_run);
+        assert!(matches!(args.command, AwsCommand::Rollback { ref previous_tag } if previous_tag == "v1.1.0"));
+
+        let args = AwsArgs::try_parse_from(["aws", "secrets", "set", "DATABASE_URL", "--dry-run"]).unwrap();
+        assert!(args.dry_run);
+        assert!(matches!(
+            args.command,
+            AwsCommand::Secrets { action: SecretsAction::Set { ref name } } if name == "DATABASE_URL"
+        ));
+
+        assert!(AwsArgs::try_parse_from(["aws", "status"]).is_ok());
+        assert!(AwsArgs::try_parse_from(["aws", "rollback"]).is_err());
+        assert!(AwsArgs::try_parse_from(["aws", "secrets", "set"]).is_err());
+        // Values never come from argv
+        assert!(AwsArgs::try_parse_from(["aws", "secrets", "set", "DATABASE_URL", "postgres://db"]).is_err());
+    }
+
+    #[test]
+    fn test_secret_values_are_read_whole() {
+        assert_eq!(secret_from_reader(&b"postgres://db\n"[..]).unwrap(), "postgres://db");
+        assert_eq!(secret_from_reader(&b"line one\r\nline two\r\n"[..]).unwrap(), "line one\r\nline two");
+        assert_eq!(secret_from_reader(&b"  padded  "[..]).unwrap(), "  padded  ");
+        assert!(secret_from_reader(&b"\n"[..]).is_err());
+        assert!(secret_from_reader(&b""[..]).is_err());
+    }
+}
@@ -1,5 +1,7 @@
 use clap::{Parser, Subcommand};
-use crate::config::Config;
+use crate::config::{Config, ConfigFile};
+use crate::error::CliError;
+use crate::keychain::TokenStorage;
 use crate::utils;
 use colored::*;
 
@@ -25,12 +27,44 @@ enum ConfigCommand {
         /// Configuration key
         key: String,
     },
-    /// Reset to defaults
+    /// Reset the current profile to defaults
     Reset {
         /// Skip confirmation
         #[arg(short, long)]
         force: bool,
     },
+    /// Manage named server profiles
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ProfileCommand {
+    /// List profiles
+    List,
+    /// Add a profile
+    Add {
+        /// Profile name
+        name: String,
+        /// Backend server URL for this profile
+        #[arg(short, long)]
+        server: String,
+    },
+    /// Make a profile the active one
+    Use {
+        /// Profile name
+        name: String,
+    },
+}
+
+impl ConfigArgs {
+    /// Whether the command works on the selected profile. Adding one doesn't, and
+    /// `--profile` may name the profile being added.
+    pub fn uses_profile(&self) -> bool {
+        !matches!(self.command, ConfigCommand::Profile { command: ProfileCommand::Add { .. } })
+    }
 }
 
 pub async fn execute(mut config: Config, args: ConfigArgs) -> anyhow::Result<()> {
@@ -39,23 +73,39 @@ pub async fn execute(mut config: Config, args: ConfigArgs) -> anyhow::Result<()>
         ConfigCommand::Set { key, value } => set_config(&mut config, &key, &value).await,
         ConfigCommand::Get { key } => get_config(&config, &key),
         ConfigCommand::Reset { force } => reset_config(&mut config, force).await,
+        ConfigCommand::Profile { command } => match command {
+            ProfileCommand::List => list_profiles(&config).await,
+            ProfileCommand::Add { name, server } => add_profile(&name, &server).await,
+            ProfileCommand::Use { name } => use_profile(&name).await,
+        },
     }
 }
 
 fn show_config(config: &Config) -> anyhow::Result<()> {
     println!("{}", "Configuration:".bold());
+    println!("  Profile: {}", config.profile.cyan());
     println!("  Server: {}", config.server_url.cyan());
     println!("  Email: {}", config.user_email.as_deref().unwrap_or("Not set").cyan());
-    println!("  Auth Token: {}***", &config.auth_token[..config.auth_token.len().min(8)].cyan());
+    println!("  Auth Token: {}", token_status(config).cyan());
     Ok(())
 }
 
+/// Where the token is kept, never the token itself
+fn token_status(config: &Config) -> &'static str {
+    match config.token_storage {
+        Some(TokenStorage::Keychain) => "Stored in OS keychain",
+        Some(TokenStorage::ConfigFile) => "Stored in config file (no keychain available)",
+        None => "Not set",
+    }
+}
+
 fn get_config(config: &Config, key: &str) -> anyhow::Result<()> {
     let value = match key {
+        "profile" => config.profile.clone(),
         "server" => config.server_url.clone(),
         "email" => config.user_email.clone().unwrap_or_else(|| "Not set".to_string()),
-        "token" => format!("{}***", &config.auth_token[..config.auth_token.len().min(8)]),
-        _ => return Err(anyhow::anyhow!("Unknown key: {}", key)),
+        "token" => token_status(config).to_string(),
+        _ => return Err(CliError::ValidationError(format!("unknown key '{}'", key)).into()),
     };
     println!("{}: {}", key, value.cyan());
     Ok(())
@@ -65,24 +115,57 @@ async fn set_config(config: &mut Config, key: &str, value: &str) -> anyhow::Resu
     match key {
         "server" => config.server_url = value.to_string(),
         "email" => config.user_email = Some(value.to_string()),
-        _ => return Err(anyhow::anyhow!("Unknown key: {}", key)),
+        _ => return Err(CliError::ValidationError(format!("unknown key '{}'", key)).into()),
     }
     config.save().await?;
-    println!("{}", format!("✓ {} set to {}", key, value).green().bold());
+    utils::print_success(&format!("{} set to {}", key, value));
     Ok(())
 }
 
 async fn reset_config(config: &mut Config, force: bool) -> anyhow::Result<()> {
     if !force {
-        let confirm = utils::confirm("Reset configuration to defaults? This will clear all settings.");
+        let confirm = utils::confirm(&format!(
+            "Reset profile '{}' to defaults? This will clear its settings.",
+            config.profile
+        ));
         if !confirm {
             println!("{}", "Cancelled.".yellow());
             return Ok(());
         }
     }
 
-    *config = Config::default();
+    *config = Config { profile: config.profile.clone(), ..Config::default() };
     config.save().await?;
-    println!("{}", "✓ Configuration reset to defaults".green().bold());
+    utils::print_success(&format!("Profile '{}' reset to defaults", config.profile));
+    Ok(())
+}
+
+/// `current` is the profile this invocation uses, which `--profile` may have changed
+async fn list_profiles(current: &Config) -> anyhow::Result<()> {
+    let file = ConfigFile::load().await?;
+    println!("{}", "Profiles:".bold());
+    for (name, profile) in &file.profiles {
+        let marker = if *name == current.profile { "*" } else { " " };
+        let active = if *name == file.active_profile { " (active)" } else { "" };
+        let signed_in = profile.user_email.as_deref().unwrap_or("not logged in");
+        println!("{} {} - {} [{}]{}", marker, name.cyan(), profile.server_url, signed_in, active);
+    }
+    Ok(())
+}
+
+async fn add_profile(name: &str, server: &str) -> anyhow::Result<()> {
+    let mut file = ConfigFile::load().await?;
+    file.add_profile(name, server)?;
+    file.save().await?;
+    utils::print_success(&format!("Profile '{}' added", name));
+    println!("  Switch to it with 'cx7 config profile use {}'", name);
+    Ok(())
+}
+
+async fn use_profile(name: &str) -> anyhow::Result<()> {
+    let mut file = ConfigFile::load().await?;
+    file.use_profile(name)?;
+    file.save().await?;
+    utils::print_success(&format!("Now using profile '{}'", name));
     Ok(())
 }
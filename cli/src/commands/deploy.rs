@@ -1,7 +1,27 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use crate::client::{ApiClient, DeploymentInfo, FileContent};
 use crate::config::Config;
+use crate::error::CliError;
+use crate::output::{self, OutputFormat, Table};
 use crate::utils;
 use colored::*;
+use ignore::{overrides::OverrideBuilder, WalkBuilder};
+use notify::{EventKind, RecursiveMode, Watcher};
+use sha2::{Digest, Sha256};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// Quiet period after the last change before a watch-mode redeploy fires
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Directories never deployed, whatever the ignore files say
+const ALWAYS_EXCLUDED: &[&str] = &[".git", ".cx7", "target", "node_modules"];
+
+/// Project-specific ignore file, using .gitignore syntax
+const CX7_IGNORE_FILE: &str = ".cx7ignore";
 
 #[derive(Parser)]
 pub struct DeployArgs {
@@ -22,6 +42,12 @@ enum DeployCommand {
         /// Watch for changes and auto-deploy
         #[arg(short, long)]
         watch: bool,
+        /// Only deploy files matching this glob (repeatable)
+        #[arg(long, value_name = "GLOB")]
+        include: Vec<String>,
+        /// Skip files matching this glob (repeatable)
+        #[arg(long, value_name = "GLOB")]
+        exclude: Vec<String>,
     },
     /// Pull deployed code
     Pull {
@@ -31,6 +57,10 @@ enum DeployCommand {
         /// Output directory
         #[arg(short, long)]
         output: Option<String>,
+        /// What to do with local files that differ from the server's
+        /// (default: prompt in a terminal, skip otherwise)
+        #[arg(long, value_enum)]
+        strategy: Option<PullStrategy>,
     },
     /// Sync code with server
     Sync {
@@ -58,59 +88,217 @@ enum DeployCommand {
     },
 }
 
-pub async fn execute(config: Config, args: DeployArgs) -> anyhow::Result<()> {
-    if config.auth_token.is_empty() {
-        println!("{}", "Not authenticated. Run 'cx7 auth login' first.".red());
-        return Ok(());
-    }
+pub async fn execute(config: Config, args: DeployArgs, output: OutputFormat) -> anyhow::Result<()> {
+    config.require_auth()?;
 
     match args.command {
-        DeployCommand::Push { project, message, watch } => push(config, project, message, watch).await,
-        DeployCommand::Pull { project, output } => pull(config, project, output).await,
+        DeployCommand::Push { project, message, watch, include, exclude } => {
+            let filters = FileFilters { include, exclude };
+            push(config, project, message, watch, filters).await
+        }
+        DeployCommand::Pull { project, output, strategy } => pull(config, project, output, strategy).await,
         DeployCommand::Sync { project, direction } => sync(config, project, direction).await,
         DeployCommand::Analyze { project } => analyze(config, project).await,
-        DeployCommand::History { project, limit } => history(config, project, limit).await,
+        DeployCommand::History { project, limit } => history(config, project, limit, output).await,
+    }
+}
+
+/// How `pull` treats a local file whose content differs from the server's
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum PullStrategy {
+    /// Replace it with the server's version
+    Overwrite,
+    /// Keep the local file
+    Skip,
+    /// Copy it to `<file>.bak`, then replace it
+    Backup,
+    /// Ask for each conflicting file
+    Prompt,
+}
+
+impl PullStrategy {
+    /// Prompting needs someone at the terminal to answer
+    fn default_for_terminal() -> Self {
+        if io::stdin().is_terminal() {
+            PullStrategy::Prompt
+        } else {
+            PullStrategy::Skip
+        }
     }
 }
 
-async fn push(config: Config, project: Option<String>, message: Option<String>, watch: bool) -> anyhow::Result<()> {
+/// What a pull did to each file
+#[derive(Debug, Default, PartialEq)]
+struct PullSummary {
+    written: usize,
+    unchanged: usize,
+    skipped: Vec<String>,
+    backed_up: Vec<String>,
+}
+
+/// Glob overrides applied on top of the ignore files when collecting files
+#[derive(Debug, Clone, Default)]
+struct FileFilters {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+async fn push(
+    config: Config,
+    project: Option<String>,
+    message: Option<String>,
+    watch: bool,
+    filters: FileFilters,
+) -> anyhow::Result<()> {
     let project = project.unwrap_or_else(|| "default".to_string());
     let message = message.unwrap_or_else(|| utils::prompt("Deployment message: "));
 
     utils::spinner_start("Collecting files...");
 
     let current_dir = std::env::current_dir()?;
-    let files = collect_files(&current_dir)?;
+    let files = collect_files(&current_dir, &filters)?;
 
     utils::spinner_stop();
     println!("{}", format!("Found {} files to deploy", files.len()).cyan());
 
+    let client = ApiClient::new(&config.server_url, Some(&config.auth_token));
+    upload(&client, &project, &current_dir, &files, &message).await?;
+
+    if watch {
+        watch_and_deploy(&client, &project, &message, &current_dir, &filters, files).await?;
+    }
+    Ok(())
+}
+
+async fn upload(client: &ApiClient, project: &str, dir: &Path, files: &[String], message: &str) -> anyhow::Result<()> {
+    let (contents, skipped) = read_files(dir, files)?;
+    if skipped > 0 {
+        println!("{}", format!("Skipping {} non-text files", skipped).yellow());
+    }
+
     utils::spinner_start("Uploading...");
 
-    let client = crate::client::ApiClient::new(&config.server_url, Some(&config.auth_token));
-    match client.deploy_code(&project, &files, &message).await {
+    match client.deploy_code(project, &contents, message).await {
         Ok(deployment) => {
             utils::spinner_stop();
-            println!("{}", "✓ Deployment successful".green().bold());
+            utils::print_success("Deployment successful");
             println!("  ID: {}", deployment.id);
             println!("  Status: {}", deployment.status);
-            
-            if watch {
-                println!("{}", "Watching for changes... (Ctrl+C to stop)".yellow());
-                // Watch implementation would go here
-            }
             Ok(())
         }
         Err(e) => {
             utils::spinner_stop();
-            Err(anyhow::anyhow!("Deployment failed: {}", e))
+            Err(e.context("Deployment failed"))
+        }
+    }
+}
+
+/// Redeploy whenever a deployable file changes, until Ctrl+C
+async fn watch_and_deploy(
+    client: &ApiClient,
+    project: &str,
+    message: &str,
+    dir: &Path,
+    filters: &FileFilters,
+    mut deployed: Vec<String>,
+) -> anyhow::Result<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res {
+            let _ = tx.send(event);
+        }
+    })?;
+    watcher.watch(dir, RecursiveMode::Recursive)?;
+
+    println!("{}", "Watching for changes... (Ctrl+C to stop)".yellow());
+
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    loop {
+        tokio::select! {
+            _ = &mut ctrl_c => {
+                println!("{}", "Stopped watching".yellow());
+                return Ok(());
+            }
+            changed = next_change(&mut rx, dir, filters, &deployed, WATCH_DEBOUNCE) => {
+                if !changed {
+                    return Err(anyhow::anyhow!("File watcher stopped unexpectedly"));
+                }
+
+                println!("{}", "Changes detected, redeploying...".cyan());
+                deployed = collect_files(dir, filters)?;
+                // A failed redeploy shouldn't end the watch session
+                if let Err(e) = upload(client, project, dir, &deployed, message).await {
+                    println!("{}", e.to_string().red());
+                }
+            }
+        }
+    }
+}
+
+/// Wait for a deployable file to change, then absorb further changes until the tree has
+/// been quiet for `window`. Returns false if the watcher went away.
+async fn next_change(
+    rx: &mut mpsc::UnboundedReceiver<notify::Event>,
+    root: &Path,
+    filters: &FileFilters,
+    deployed: &[String],
+    window: Duration,
+) -> bool {
+    loop {
+        match rx.recv().await {
+            Some(event) if touches_deployable_file(&event, root, filters, deployed) => break,
+            Some(_) => continue,
+            None => return false,
+        }
+    }
+
+    let mut deadline = Instant::now() + window;
+    loop {
+        match tokio::time::timeout_at(deadline, rx.recv()).await {
+            Ok(Some(event)) => {
+                if touches_deployable_file(&event, root, filters, deployed) {
+                    deadline = Instant::now() + window;
+                }
+            }
+            Ok(None) | Err(_) => return true,
         }
     }
 }
 
-async fn pull(config: Config, project: Option<String>, output: Option<String>) -> anyhow::Result<()> {
+/// Whether the event changes a file that was deployed last time (so edits and removals
+/// count) or one the file walk would deploy now (so new files count). Anything the walk
+/// skips, through the ignore files or the `--include`/`--exclude` globs, is ignored.
+fn touches_deployable_file(event: &notify::Event, root: &Path, filters: &FileFilters, deployed: &[String]) -> bool {
+    if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)) {
+        return false;
+    }
+    let changed: Vec<String> = event
+        .paths
+        .iter()
+        .filter_map(|path| path.strip_prefix(root).ok())
+        .filter(|rel| !is_excluded(rel))
+        .map(|rel| rel.to_string_lossy().to_string())
+        .collect();
+    if changed.is_empty() {
+        return false;
+    }
+    if changed.iter().any(|rel| deployed.contains(rel)) {
+        return true;
+    }
+    collect_files(root, filters).is_ok_and(|files| changed.iter().any(|rel| files.contains(rel)))
+}
+
+async fn pull(
+    config: Config,
+    project: Option<String>,
+    output: Option<String>,
+    strategy: Option<PullStrategy>,
+) -> anyhow::Result<()> {
     let project = project.unwrap_or_else(|| "default".to_string());
     let output_dir = output.unwrap_or_else(|| "./deployed".to_string());
+    let strategy = strategy.unwrap_or_else(PullStrategy::default_for_terminal);
 
     utils::spinner_start("Pulling code...");
 
@@ -120,40 +308,111 @@ async fn pull(config: Config, project: Option<String>, output: Option<String>) -
     match client.pull_code(&project).await {
         Ok(files) => {
             utils::spinner_stop();
-            
-            for file in files {
-                let file_path = std::path::PathBuf::from(&output_dir).join(&file.path);
-                if let Some(parent) = file_path.parent() {
-                    std::fs::create_dir_all(parent)?;
-                }
-                std::fs::write(&file_path, &file.content)?;
-            }
 
-            println!("{}", "✓ Code pulled successfully".green().bold());
+            let summary = write_pulled_files(Path::new(&output_dir), &files, strategy, |path| {
+                utils::confirm(&format!("{} has local changes. Overwrite?", path))
+            })?;
+
+            utils::print_success("Code pulled successfully");
             println!("  Output: {}", output_dir);
+            println!("  Written: {}, unchanged: {}", summary.written, summary.unchanged);
+            for path in &summary.backed_up {
+                println!("  Backed up: {}.bak", path);
+            }
+            if !summary.skipped.is_empty() {
+                println!("{}", format!("Kept {} locally modified files:", summary.skipped.len()).yellow());
+                for path in &summary.skipped {
+                    println!("  {}", path);
+                }
+            }
             Ok(())
         }
         Err(e) => {
             utils::spinner_stop();
-            Err(anyhow::anyhow!("Pull failed: {}", e))
+            Err(e.context("Pull failed"))
         }
     }
 }
 
+/// Write the server's files under `dir`. A local file is only in conflict when its content
+/// hash differs from the server version's; `strategy` decides what happens to it, with
+/// `confirm` answering for `Prompt`.
+fn write_pulled_files(
+    dir: &Path,
+    files: &[FileContent],
+    strategy: PullStrategy,
+    mut confirm: impl FnMut(&str) -> bool,
+) -> anyhow::Result<PullSummary> {
+    let mut summary = PullSummary::default();
+
+    for file in files {
+        let file_path = pulled_path(dir, &file.path)?;
+
+        if let Ok(local) = std::fs::read(&file_path) {
+            if content_hash(&local) == content_hash(file.content.as_bytes()) {
+                summary.unchanged += 1;
+                continue;
+            }
+
+            let overwrite = match strategy {
+                PullStrategy::Overwrite | PullStrategy::Backup => true,
+                PullStrategy::Skip => false,
+                PullStrategy::Prompt => confirm(&file.path),
+            };
+            if !overwrite {
+                summary.skipped.push(file.path.clone());
+                continue;
+            }
+            if strategy == PullStrategy::Backup {
+                std::fs::write(backup_path(&file_path), &local)?;
+                summary.backed_up.push(file.path.clone());
+            }
+        }
+
+        if let Some(parent) = file_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&file_path, &file.content)?;
+        summary.written += 1;
+    }
+
+    Ok(summary)
+}
+
+/// Where a pulled file goes; paths that would land outside `dir` are refused
+fn pulled_path(dir: &Path, path: &str) -> anyhow::Result<PathBuf> {
+    let relative = Path::new(path);
+    if !relative.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) {
+        return Err(anyhow::anyhow!("Refusing to write outside the output directory: {}", path));
+    }
+    Ok(dir.join(relative))
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    PathBuf::from(backup)
+}
+
+fn content_hash(content: &[u8]) -> [u8; 32] {
+    Sha256::digest(content).into()
+}
+
 async fn sync(config: Config, project: Option<String>, direction: String) -> anyhow::Result<()> {
     let project = project.unwrap_or_else(|| "default".to_string());
 
     match direction.as_str() {
-        "push" | "p" => push(config, Some(project), None, false).await,
-        "pull" | "l" => pull(config, Some(project), None).await,
+        "push" | "p" => push(config, Some(project), None, false, FileFilters::default()).await,
+        "pull" | "l" => pull(config, Some(project), None, None).await,
         "both" | "b" => {
-            push(config.clone(), Some(project.clone()), None, false).await?;
-            pull(config, Some(project), None).await
-        }
-        _ => {
-            println!("{}", format!("Invalid direction: {}. Use 'push', 'pull', or 'both'", direction).red());
-            Ok(())
+            push(config.clone(), Some(project.clone()), None, false, FileFilters::default()).await?;
+            pull(config, Some(project), None, None).await
         }
+        _ => Err(CliError::ValidationError(format!(
+            "invalid direction '{}'. Use 'push', 'pull', or 'both'",
+            direction
+        ))
+        .into()),
     }
 }
 
@@ -174,53 +433,334 @@ async fn analyze(config: Config, project: Option<String>) -> anyhow::Result<()>
         }
         Err(e) => {
             utils::spinner_stop();
-            Err(anyhow::anyhow!("Analysis failed: {}", e))
+            Err(e.context("Analysis failed"))
         }
     }
 }
 
-async fn history(config: Config, project: Option<String>, limit: usize) -> anyhow::Result<()> {
+async fn history(config: Config, project: Option<String>, limit: usize, output: OutputFormat) -> anyhow::Result<()> {
     let project = project.unwrap_or_else(|| "default".to_string());
 
-    utils::spinner_start("Fetching deployment history...");
+    output.spinner_start("Fetching deployment history...");
 
     let client = crate::client::ApiClient::new(&config.server_url, Some(&config.auth_token));
     match client.get_deployment_history(&project, limit).await {
         Ok(deployments) => {
-            utils::spinner_stop();
-            println!("{}", "Deployment History:".bold());
-            for (idx, deployment) in deployments.iter().enumerate() {
-                println!("\n  {} ({})", deployment.id, deployment.status.cyan());
-                println!("     Message: {}", deployment.message);
-                println!("     Date: {}", deployment.created_at);
-            }
-            Ok(())
+            output.spinner_stop();
+            write_history(&mut io::stdout().lock(), output, &deployments)
         }
         Err(e) => {
-            utils::spinner_stop();
-            Err(anyhow::anyhow!("Failed to fetch history: {}", e))
+            output.spinner_stop();
+            Err(e.context("Failed to fetch history"))
+        }
+    }
+}
+
+fn write_history(out: &mut impl Write, format: OutputFormat, deployments: &[DeploymentInfo]) -> anyhow::Result<()> {
+    output::render(out, format, deployments, |out, deployments| {
+        let mut table = Table::new(&["ID", "STATUS", "DATE", "MESSAGE"]);
+        for deployment in deployments {
+            table.row(vec![
+                deployment.id.clone(),
+                deployment.status.clone(),
+                deployment.created_at.clone(),
+                deployment.message.clone(),
+            ]);
+        }
+        table.write_to(out)
+    })
+}
+
+/// Read each collected file under `dir`, leaving out files that aren't UTF-8 text.
+/// Returns the contents and how many files were left out.
+fn read_files(dir: &Path, files: &[String]) -> anyhow::Result<(Vec<FileContent>, usize)> {
+    let mut contents = Vec::with_capacity(files.len());
+    let mut skipped = 0;
+    for path in files {
+        match String::from_utf8(std::fs::read(dir.join(path))?) {
+            Ok(content) => contents.push(FileContent {
+                path: path.clone(),
+                content,
+            }),
+            Err(_) => skipped += 1,
         }
     }
+    Ok((contents, skipped))
 }
 
-fn collect_files(dir: &std::path::Path) -> anyhow::Result<Vec<String>> {
+/// Walk the project honoring .gitignore and .cx7ignore, then apply the --include/--exclude globs
+fn collect_files(dir: &Path, filters: &FileFilters) -> anyhow::Result<Vec<String>> {
+    let mut overrides = OverrideBuilder::new(dir);
+    for glob in &filters.include {
+        overrides.add(glob)?;
+    }
+    for glob in &filters.exclude {
+        overrides.add(&format!("!{}", glob))?;
+    }
+
+    let walker = WalkBuilder::new(dir)
+        .hidden(false)
+        .require_git(false)
+        .add_custom_ignore_filename(CX7_IGNORE_FILE)
+        .overrides(overrides.build()?)
+        .filter_entry(|entry| !ALWAYS_EXCLUDED.iter().any(|name| entry.file_name() == *name))
+        .build();
+
     let mut files = Vec::new();
-    
-    for entry in walkdir::WalkDir::new(dir)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-    {
-        if !entry.path().to_string_lossy().contains(".git")
-            && !entry.path().to_string_lossy().contains(".cx7")
-            && !entry.path().to_string_lossy().contains("target")
-            && !entry.path().to_string_lossy().contains("node_modules")
-        {
-            if let Ok(rel_path) = entry.path().strip_prefix(dir) {
-                files.push(rel_path.to_string_lossy().to_string());
-            }
+    for entry in walker {
+        let entry = entry?;
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+        if let Ok(rel_path) = entry.path().strip_prefix(dir) {
+            files.push(rel_path.to_string_lossy().to_string());
         }
     }
-    
+
+    files.sort();
     Ok(files)
 }
+
+/// Whether any component of the path is one of the always-excluded directories
+fn is_excluded(path: &Path) -> bool {
+    path.components().any(|component| match component {
+        Component::Normal(name) => ALWAYS_EXCLUDED.iter().any(|excluded| name == *excluded),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{CreateKind, ModifyKind, RemoveKind};
+
+    fn event(kind: EventKind, root: &Path, path: &str) -> notify::Event {
+        notify::Event::new(kind).add_path(root.join(path))
+    }
+
+    #[test]
+    fn test_touches_deployable_file() {
+        let dir = project_tree();
+        let root = dir.path();
+        let filters = FileFilters::default();
+        let deployed = collect_files(root, &filters).unwrap();
+        let touches = |kind: EventKind, path: &str| touches_deployable_file(&event(kind, root, path), root, &filters, &deployed);
+
+        assert!(touches(EventKind::Modify(ModifyKind::Any), "src/main.rs"));
+        assert!(!touches(EventKind::Modify(ModifyKind::Any), "target/debug/cx7"));
+        assert!(!touches(EventKind::Create(CreateKind::File), ".git/index"));
+        assert!(!touches(EventKind::Access(notify::event::AccessKind::Any), "src/main.rs"));
+
+        // The ignore files apply just as they do when collecting
+        assert!(!touches(EventKind::Modify(ModifyKind::Any), "server.log"));
+        assert!(!touches(EventKind::Modify(ModifyKind::Any), "fixtures/big.json"));
+
+        // New files count once the walk would pick them up, removed ones because they were deployed
+        write(root, "src/routes.rs", "pub fn routes() {}");
+        assert!(touches(EventKind::Create(CreateKind::File), "src/routes.rs"));
+        std::fs::remove_file(root.join("src/targeting.rs")).unwrap();
+        assert!(touches(EventKind::Remove(RemoveKind::File), "src/targeting.rs"));
+
+        // So do the --include and --exclude globs
+        let filters = FileFilters { include: vec![], exclude: vec!["*.example".to_string()] };
+        let deployed = collect_files(root, &filters).unwrap();
+        assert!(!touches_deployable_file(
+            &event(EventKind::Modify(ModifyKind::Any), root, ".env.example"),
+            root,
+            &filters,
+            &deployed
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_next_change_debounces_save_storm() {
+        let dir = project_tree();
+        let root = dir.path();
+        let filters = FileFilters::default();
+        let deployed = collect_files(root, &filters).unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        tx.send(event(EventKind::Modify(ModifyKind::Any), root, "target/debug/cx7")).unwrap();
+        for _ in 0..20 {
+            tx.send(event(EventKind::Modify(ModifyKind::Any), root, "src/main.rs")).unwrap();
+        }
+
+        assert!(next_change(&mut rx, root, &filters, &deployed, Duration::from_millis(50)).await);
+        assert!(rx.try_recv().is_err(), "the whole burst should be absorbed by one change");
+
+        drop(tx);
+        assert!(!next_change(&mut rx, root, &filters, &deployed, Duration::from_millis(50)).await);
+    }
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    fn project_tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write(root, "src/main.rs", "fn main() {}");
+        write(root, "src/targeting.rs", "pub fn aim() {}");
+        write(root, "target/debug/cx7", "binary");
+        write(root, "node_modules/left-pad/index.js", "module.exports = 1;");
+        write(root, ".env.example", "KEY=value");
+        write(root, ".gitignore", "*.log\n");
+        write(root, "server.log", "noise");
+        write(root, ".cx7ignore", "fixtures/\n");
+        write(root, "fixtures/big.json", "{}");
+        dir
+    }
+
+    #[test]
+    fn test_collect_files_honors_ignore_files() {
+        let dir = project_tree();
+        let files = collect_files(dir.path(), &FileFilters::default()).unwrap();
+
+        assert!(files.contains(&"src/targeting.rs".to_string()));
+        assert!(files.contains(&"src/main.rs".to_string()));
+        assert!(files.contains(&".env.example".to_string()));
+        assert!(files.contains(&".gitignore".to_string()));
+        assert!(!files.iter().any(|f| f.starts_with("target")));
+        assert!(!files.iter().any(|f| f.starts_with("node_modules")));
+        assert!(!files.contains(&"server.log".to_string()));
+        assert!(!files.contains(&"fixtures/big.json".to_string()));
+    }
+
+    #[test]
+    fn test_collect_files_include_and_exclude_globs() {
+        let dir = project_tree();
+
+        let filters = FileFilters { include: vec!["src/**".to_string()], exclude: vec![] };
+        let files = collect_files(dir.path(), &filters).unwrap();
+        assert_eq!(files, vec!["src/main.rs", "src/targeting.rs"]);
+
+        let filters = FileFilters { include: vec![], exclude: vec!["*.example".to_string()] };
+        let files = collect_files(dir.path(), &filters).unwrap();
+        assert!(!files.contains(&".env.example".to_string()));
+        assert!(files.contains(&"src/main.rs".to_string()));
+    }
+
+    #[test]
+    fn test_read_files_skips_binary_files() {
+        let dir = project_tree();
+        std::fs::write(dir.path().join("logo.png"), [0x89, 0x50, 0x4e, 0x47, 0xff, 0xfe]).unwrap();
+
+        let files = vec!["src/main.rs".to_string(), "logo.png".to_string()];
+        let (contents, skipped) = read_files(dir.path(), &files).unwrap();
+        assert_eq!(skipped, 1);
+        assert_eq!(contents.len(), 1);
+        assert_eq!(contents[0].path, "src/main.rs");
+        assert_eq!(contents[0].content, "fn main() {}");
+    }
+
+    #[test]
+    fn test_is_excluded_matches_components() {
+        assert!(is_excluded(Path::new("target/debug/cx7")));
+        assert!(is_excluded(Path::new("web/node_modules/react/index.js")));
+        assert!(!is_excluded(Path::new("src/targeting.rs")));
+        assert!(!is_excluded(Path::new(".github/workflows/ci.yml")));
+    }
+    #[test]
+    fn test_history_json_output_round_trips() {
+        let deployments = vec![DeploymentInfo {
+            id: "d-42".to_string(),
+            status: "succeeded".to_string(),
+            message: "Tune targeting weights".to_string(),
+            created_at: "2024-05-01T12:00:00Z".to_string(),
+        }];
+
+        let mut out = Vec::new();
+        write_history(&mut out, OutputFormat::Json, &deployments).unwrap();
+
+        let parsed: Vec<DeploymentInfo> = serde_json::from_slice(&out).unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].id, "d-42");
+        assert_eq!(parsed[0].status, "succeeded");
+        assert_eq!(parsed[0].message, "Tune targeting weights");
+    }
+    fn server_files() -> Vec<FileContent> {
+        [("src/main.rs", "fn main() { serve(); }"), ("src/lib.rs", "pub fn serve() {}"), ("README.md", "# Targeting")]
+            .into_iter()
+            .map(|(path, content)| FileContent { path: path.to_string(), content: content.to_string() })
+            .collect()
+    }
+
+    /// A pulled tree where `src/main.rs` has local edits and `src/lib.rs` matches the server
+    fn local_tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "src/main.rs", "fn main() { local_edit(); }");
+        write(dir.path(), "src/lib.rs", "pub fn serve() {}");
+        dir
+    }
+
+    fn read(dir: &tempfile::TempDir, path: &str) -> String {
+        std::fs::read_to_string(dir.path().join(path)).unwrap()
+    }
+
+    fn no_prompt(path: &str) -> bool {
+        panic!("unexpected prompt for {}", path)
+    }
+
+    #[test]
+    fn test_pull_overwrite_replaces_local_changes() {
+        let dir = local_tree();
+        let summary = write_pulled_files(dir.path(), &server_files(), PullStrategy::Overwrite, no_prompt).unwrap();
+
+        assert_eq!(summary, PullSummary { written: 2, unchanged: 1, ..Default::default() });
+        assert_eq!(read(&dir, "src/main.rs"), "fn main() { serve(); }");
+        assert_eq!(read(&dir, "README.md"), "# Targeting");
+    }
+
+    #[test]
+    fn test_pull_skip_keeps_local_changes() {
+        let dir = local_tree();
+        let summary = write_pulled_files(dir.path(), &server_files(), PullStrategy::Skip, no_prompt).unwrap();
+
+        assert_eq!(summary.skipped, ["src/main.rs"]);
+        assert_eq!(summary.written, 1);
+        assert_eq!(read(&dir, "src/main.rs"), "fn main() { local_edit(); }");
+        assert_eq!(read(&dir, "README.md"), "# Targeting");
+    }
+
+    #[test]
+    fn test_pull_backup_copies_before_overwriting() {
+        let dir = local_tree();
+        let summary = write_pulled_files(dir.path(), &server_files(), PullStrategy::Backup, no_prompt).unwrap();
+
+        assert_eq!(summary.backed_up, ["src/main.rs"]);
+        assert_eq!(read(&dir, "src/main.rs"), "fn main() { serve(); }");
+        assert_eq!(read(&dir, "src/main.rs.bak"), "fn main() { local_edit(); }");
+        // Files that already matched get no backup
+        assert!(!dir.path().join("src/lib.rs.bak").exists());
+    }
+
+    #[test]
+    fn test_pull_prompt_asks_only_for_conflicts() {
+        let mut files = server_files();
+        files.push(FileContent { path: "src/targeting.rs".to_string(), content: "pub fn aim() {}".to_string() });
+        let dir = local_tree();
+        write(dir.path(), "src/targeting.rs", "pub fn aim_locally() {}");
+
+        let mut asked = Vec::new();
+        let summary = write_pulled_files(dir.path(), &files, PullStrategy::Prompt, |path| {
+            asked.push(path.to_string());
+            path == "src/targeting.rs"
+        })
+        .unwrap();
+
+        assert_eq!(asked, ["src/main.rs", "src/targeting.rs"]);
+        assert_eq!(summary.skipped, ["src/main.rs"]);
+        assert_eq!(read(&dir, "src/main.rs"), "fn main() { local_edit(); }");
+        assert_eq!(read(&dir, "src/targeting.rs"), "pub fn aim() {}");
+    }
+
+    #[test]
+    fn test_pull_refuses_paths_outside_the_output_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = vec![FileContent { path: "../escape.rs".to_string(), content: String::new() }];
+        assert!(write_pulled_files(dir.path(), &files, PullStrategy::Overwrite, no_prompt).is_err());
+        assert!(pulled_path(dir.path(), "/etc/passwd").is_err());
+    }
+}
@@ -4,3 +4,4 @@ pub mod deploy;
 pub mod config;
 pub mod agent;
 pub mod status;
+pub mod aws_deploy;
@@ -1,8 +1,11 @@
 use clap::{Parser, Subcommand};
+use crate::client::ProjectFile;
 use crate::config::Config;
+use crate::output::{self, OutputFormat, Table};
 use crate::utils;
 use colored::*;
 use serde::{Serialize, Deserialize};
+use std::io::{self, IsTerminal, Write};
 use uuid::Uuid;
 
 #[derive(Parser)]
@@ -39,6 +42,21 @@ enum ProjectCommand {
         #[arg(short, long)]
         description: Option<String>,
     },
+    /// List a project's files
+    Files {
+        /// Project ID
+        project: String,
+    },
+    /// Print a file from a project
+    Cat {
+        /// Project ID
+        project: String,
+        /// Path of the file within the project
+        path: String,
+        /// Language to highlight as (default: the file's language, then its extension)
+        #[arg(short, long)]
+        language: Option<String>,
+    },
     /// Delete a project
     Delete {
         /// Project ID or name
@@ -49,17 +67,16 @@ enum ProjectCommand {
     },
 }
 
-pub async fn execute(config: Config, args: ProjectArgs) -> anyhow::Result<()> {
-    if config.auth_token.is_empty() {
-        println!("{}", "Not authenticated. Run 'cx7 auth login' first.".red());
-        return Ok(());
-    }
+pub async fn execute(config: Config, args: ProjectArgs, output: OutputFormat) -> anyhow::Result<()> {
+    config.require_auth()?;
 
     match args.command {
         ProjectCommand::Init { name } => init_project(config, name).await,
-        ProjectCommand::List { detail } => list_projects(config, detail).await,
+        ProjectCommand::List { detail } => list_projects(config, detail, output).await,
         ProjectCommand::Show { project } => show_project(config, project).await,
         ProjectCommand::Create { name, description } => create_project(config, name, description).await,
+        ProjectCommand::Files { project } => list_files(config, project, output).await,
+        ProjectCommand::Cat { project, path, language } => cat_file(config, project, path, language, output).await,
         ProjectCommand::Delete { project, force } => delete_project(config, project, force).await,
     }
 }
@@ -86,41 +103,44 @@ async fn init_project(config: Config, name: Option<String>) -> anyhow::Result<()
     std::fs::write(config_path, config_str)?;
 
     utils::spinner_stop();
-    println!("{}", format!("✓ Project '{}' initialized", name).green().bold());
+    utils::print_success(&format!("Project '{}' initialized", name));
     Ok(())
 }
 
-async fn list_projects(config: Config, detail: bool) -> anyhow::Result<()> {
-    utils::spinner_start("Fetching projects...");
+async fn list_projects(config: Config, detail: bool, output: OutputFormat) -> anyhow::Result<()> {
+    output.spinner_start("Fetching projects...");
 
     let client = crate::client::ApiClient::new(&config.server_url, Some(&config.auth_token));
     match client.list_projects().await {
         Ok(projects) => {
-            utils::spinner_stop();
-            
-            if projects.is_empty() {
-                println!("{}", "No projects found.".yellow());
-                return Ok(());
-            }
-
-            println!("{}", "Projects:".bold());
-            for proj in projects {
-                if detail {
-                    println!("\n  {} ({})", proj.name.cyan(), proj.id);
-                    println!("    Created: {}", proj.created_at);
-                } else {
-                    println!("  - {} ({})", proj.name.cyan(), proj.id);
-                }
-            }
-            Ok(())
+            output.spinner_stop();
+            write_projects(&mut io::stdout().lock(), output, &projects, detail)
         }
         Err(e) => {
-            utils::spinner_stop();
-            Err(anyhow::anyhow!("Failed to list projects: {}", e))
+            output.spinner_stop();
+            Err(e.context("Failed to list projects"))
         }
     }
 }
 
+fn write_projects(out: &mut impl Write, format: OutputFormat, projects: &[crate::client::ProjectInfo], detail: bool) -> anyhow::Result<()> {
+    output::render(out, format, projects, |out, projects| {
+        if projects.is_empty() {
+            return writeln!(out, "{}", "No projects found.".yellow());
+        }
+
+        let mut table = if detail { Table::new(&["NAME", "ID", "CREATED"]) } else { Table::new(&["NAME", "ID"]) };
+        for proj in projects {
+            let mut row = vec![proj.name.clone(), proj.id.clone()];
+            if detail {
+                row.push(proj.created_at.clone());
+            }
+            table.row(row);
+        }
+        table.write_to(out)
+    })
+}
+
 async fn show_project(config: Config, project: String) -> anyhow::Result<()> {
     utils::spinner_start("Fetching project...");
 
@@ -135,7 +155,7 @@ async fn show_project(config: Config, project: String) -> anyhow::Result<()> {
         }
         Err(e) => {
             utils::spinner_stop();
-            Err(anyhow::anyhow!("Failed to fetch project: {}", e))
+            Err(e.context("Failed to fetch project"))
         }
     }
 }
@@ -147,17 +167,117 @@ async fn create_project(config: Config, name: String, description: Option<String
     match client.create_project(&name, description.as_deref()).await {
         Ok(proj) => {
             utils::spinner_stop();
-            println!("{}", format!("✓ Project '{}' created", name).green().bold());
+            utils::print_success(&format!("Project '{}' created", name));
             println!("  ID: {}", proj.id);
             Ok(())
         }
         Err(e) => {
             utils::spinner_stop();
-            Err(anyhow::anyhow!("Failed to create project: {}", e))
+            Err(e.context("Failed to create project"))
+        }
+    }
+}
+
+async fn list_files(config: Config, project: String, output: OutputFormat) -> anyhow::Result<()> {
+    output.spinner_start("Fetching files...");
+
+    let client = crate::client::ApiClient::new(&config.server_url, Some(&config.auth_token));
+    match client.list_files(&project).await {
+        Ok(files) => {
+            output.spinner_stop();
+            write_files(&mut io::stdout().lock(), output, &files)
+        }
+        Err(e) => {
+            output.spinner_stop();
+            Err(e.context("Failed to list files"))
+        }
+    }
+}
+
+/// A listed file, without its content
+#[derive(Debug, Serialize, Deserialize)]
+struct FileEntry {
+    path: String,
+    language: Option<String>,
+    size: usize,
+    updated_at: String,
+}
+
+fn write_files(out: &mut impl Write, format: OutputFormat, files: &[ProjectFile]) -> anyhow::Result<()> {
+    let mut entries: Vec<FileEntry> = files
+        .iter()
+        .map(|file| FileEntry {
+            path: file.file_path.clone(),
+            language: file.language.clone(),
+            size: file.content.len(),
+            updated_at: file.updated_at.clone(),
+        })
+        .collect();
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    output::render(out, format, &entries, |out, entries| {
+        if entries.is_empty() {
+            return writeln!(out, "{}", "No files found.".yellow());
+        }
+
+        let mut table = Table::new(&["PATH", "LANGUAGE", "SIZE", "UPDATED"]);
+        for entry in entries {
+            table.row(vec![
+                entry.path.clone(),
+                entry.language.clone().unwrap_or_default(),
+                entry.size.to_string(),
+                entry.updated_at.clone(),
+            ]);
+        }
+        table.write_to(out)
+    })
+}
+
+async fn cat_file(
+    config: Config,
+    project: String,
+    path: String,
+    language: Option<String>,
+    output: OutputFormat,
+) -> anyhow::Result<()> {
+    output.spinner_start("Fetching file...");
+
+    let client = crate::client::ApiClient::new(&config.server_url, Some(&config.auth_token));
+    match client.get_file(&project, &path).await {
+        Ok(file) => {
+            output.spinner_stop();
+            let highlight = io::stdout().is_terminal();
+            write_file(&mut io::stdout().lock(), output, &file, language.as_deref(), highlight)
+        }
+        Err(e) => {
+            output.spinner_stop();
+            Err(e.context("Failed to fetch file"))
         }
     }
 }
 
+/// The raw content unless `highlight`, so `cat` output can be piped or redirected
+fn write_file(
+    out: &mut impl Write,
+    format: OutputFormat,
+    file: &ProjectFile,
+    language: Option<&str>,
+    highlight: bool,
+) -> anyhow::Result<()> {
+    output::render(out, format, file, |out, file| {
+        if !highlight {
+            return out.write_all(file.content.as_bytes());
+        }
+
+        let language = language.or(file.language.as_deref());
+        out.write_all(crate::highlight::highlight(&file.content, language, &file.file_path).as_bytes())?;
+        if !file.content.ends_with('\n') {
+            writeln!(out)?;
+        }
+        Ok(())
+    })
+}
+
 async fn delete_project(config: Config, project: String, force: bool) -> anyhow::Result<()> {
     if !force {
         let confirm = utils::confirm(&format!("Delete project '{}'? This cannot be undone.", project));
@@ -173,12 +293,12 @@ async fn delete_project(config: Config, project: String, force: bool) -> anyhow:
     match client.delete_project(&project).await {
         Ok(_) => {
             utils::spinner_stop();
-            println!("{}", "✓ Project deleted successfully".green().bold());
+            utils::print_success("Project deleted successfully");
             Ok(())
         }
         Err(e) => {
             utils::spinner_stop();
-            Err(anyhow::anyhow!("Failed to delete project: {}", e))
+            Err(e.context("Failed to delete project"))
         }
     }
 }
@@ -196,3 +316,92 @@ pub struct ProjectInfo {
     pub name: String,
     pub created_at: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_json_output_is_only_json() {
+        let projects = vec![crate::client::ProjectInfo {
+            id: "7f0c2a9e-1d2b-4c5d-8e9f-0a1b2c3d4e5f".to_string(),
+            name: "targeting".to_string(),
+            created_at: "2024-05-01T12:00:00Z".to_string(),
+        }];
+
+        let mut out = Vec::new();
+        write_projects(&mut out, OutputFormat::Json, &projects, false).unwrap();
+
+        let parsed: Vec<crate::client::ProjectInfo> = serde_json::from_slice(&out).unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].name, "targeting");
+        assert_eq!(parsed[0].id, projects[0].id);
+
+        // An empty list is still JSON, not the "No projects found." message
+        let mut out = Vec::new();
+        write_projects(&mut out, OutputFormat::Json, &[], false).unwrap();
+        assert!(serde_json::from_slice::<Vec<serde_json::Value>>(&out).unwrap().is_empty());
+    }
+
+    fn project_files() -> serde_json::Value {
+        serde_json::json!([
+            {
+                "id": "9a1b2c3d-0000-4000-8000-000000000002",
+                "project_id": "7f0c2a9e-1d2b-4c5d-8e9f-0a1b2c3d4e5f",
+                "file_path": "src/main.rs",
+                "content": "fn main() {\n    println!(\"hi\");\n}\n",
+                "language": "rust",
+                "updated_at": "2024-05-02T09:30:00Z"
+            },
+            {
+                "id": "9a1b2c3d-0000-4000-8000-000000000001",
+                "project_id": "7f0c2a9e-1d2b-4c5d-8e9f-0a1b2c3d4e5f",
+                "file_path": "README.md",
+                "content": "# Targeting",
+                "language": null,
+                "updated_at": "2024-05-01T12:00:00Z"
+            }
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_files_lists_paths_without_content() {
+        let (server, requests) = crate::client::stand_in::server(vec![project_files()]).await;
+        let client = crate::client::ApiClient::new(&server, Some("token"));
+        let files = client.list_files("targeting").await.unwrap();
+        assert_eq!(requests.lock().unwrap()[0], "GET /api/projects/targeting/files HTTP/1.1");
+
+        let mut out = Vec::new();
+        write_files(&mut out, OutputFormat::Table, &files).unwrap();
+        let table = String::from_utf8(out).unwrap();
+        let rows: Vec<&str> = table.lines().skip(1).collect();
+        assert!(rows[0].starts_with("README.md"));
+        assert!(rows[1].starts_with("src/main.rs"));
+        assert!(rows[1].contains("rust"));
+
+        let mut out = Vec::new();
+        write_files(&mut out, OutputFormat::Json, &files).unwrap();
+        let parsed: Vec<serde_json::Value> = serde_json::from_slice(&out).unwrap();
+        assert_eq!(parsed[1]["path"], "src/main.rs");
+        assert_eq!(parsed[1]["size"], 34);
+        assert!(parsed[1].get("content").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cat_prints_one_files_content() {
+        let (server, _) = crate::client::stand_in::server(vec![project_files()]).await;
+        let client = crate::client::ApiClient::new(&server, Some("token"));
+
+        let file = client.get_file("targeting", "./src/main.rs").await.unwrap();
+        let mut out = Vec::new();
+        write_file(&mut out, OutputFormat::Table, &file, None, false).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "fn main() {\n    println!(\"hi\");\n}\n");
+
+        let mut out = Vec::new();
+        write_file(&mut out, OutputFormat::Table, &file, None, true).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains('\x1b'));
+
+        let err = client.get_file("targeting", "src/missing.rs").await.unwrap_err();
+        assert_eq!(crate::error::exit_code(&err), 30);
+    }
+}
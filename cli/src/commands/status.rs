@@ -1,7 +1,9 @@
 use clap::Parser;
+use crate::client::HealthStatus;
 use crate::config::Config;
-use crate::utils;
+use crate::output::{self, OutputFormat, Table};
 use colored::*;
+use std::io::{self, Write};
 
 #[derive(Parser)]
 pub struct StatusArgs {
@@ -10,31 +12,56 @@ pub struct StatusArgs {
     detail: bool,
 }
 
-pub async fn execute(config: Config, args: StatusArgs) -> anyhow::Result<()> {
-    utils::spinner_start("Checking status...");
+pub async fn execute(config: Config, args: StatusArgs, output: OutputFormat) -> anyhow::Result<()> {
+    output.spinner_start("Checking status...");
 
     let client = crate::client::ApiClient::new(&config.server_url, Some(&config.auth_token));
-    
+
     match client.health_check().await {
         Ok(health) => {
-            utils::spinner_stop();
-            
-            let server_status = if health.ok { "Online".green() } else { "Offline".red() };
-            println!("{}", format!("Server: {}", server_status).bold());
-
-            if args.detail {
-                println!("\nDetailed Status:");
-                println!("  Database: {}", health.database_ok);
-                println!("  Cache: {}", health.cache_ok);
-                println!("  Agents: {}", health.agents_running);
-            }
-
-            Ok(())
+            output.spinner_stop();
+            write_status(&mut io::stdout().lock(), output, &health, args.detail)
         }
         Err(e) => {
-            utils::spinner_stop();
-            println!("{}", format!("Server: Offline").red().bold());
-            Err(anyhow::anyhow!("Health check failed: {}", e))
+            output.spinner_stop();
+            if !output.is_structured() {
+                println!("{}", "Server: Offline".red().bold());
+            }
+            Err(e.context("Health check failed"))
+        }
+    }
+}
+
+/// JSON and YAML always carry the full health report; `detail` only widens the table
+fn write_status(out: &mut impl Write, format: OutputFormat, health: &HealthStatus, detail: bool) -> anyhow::Result<()> {
+    output::render(out, format, health, |out, health| {
+        let server_status = if health.ok { "Online" } else { "Offline" };
+
+        let mut table = Table::new(&["COMPONENT", "STATUS"]);
+        table.row(vec!["Server".to_string(), server_status.to_string()]);
+        if detail {
+            table.row(vec!["Database".to_string(), health.database_ok.to_string()]);
+            table.row(vec!["Cache".to_string(), health.cache_ok.to_string()]);
+            table.row(vec!["Agents".to_string(), health.agents_running.to_string()]);
         }
+        table.write_to(out)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_output_round_trips() {
+        let health = HealthStatus { ok: true, database_ok: true, cache_ok: false, agents_running: 2 };
+
+        let mut out = Vec::new();
+        write_status(&mut out, OutputFormat::Json, &health, false).unwrap();
+
+        let parsed: HealthStatus = serde_json::from_slice(&out).unwrap();
+        assert!(parsed.ok);
+        assert!(!parsed.cache_ok);
+        assert_eq!(parsed.agents_running, 2);
     }
 }
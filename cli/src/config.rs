@@ -1,53 +1,327 @@
+use colored::*;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::error::CliError;
+use crate::keychain::{self, TokenStorage, Tokens};
+
+pub const DEFAULT_PROFILE: &str = "default";
+const DEFAULT_SERVER_URL: &str = "http://localhost:3000";
+
+/// Settings for the profile in use: the one named by `--profile`, otherwise the
+/// config file's active profile. Saving writes back to that profile only.
+#[derive(Debug, Clone)]
 pub struct Config {
+    pub profile: String,
     pub server_url: String,
     pub auth_token: String,
+    pub refresh_token: String,
     pub user_email: Option<String>,
+    /// Where the tokens were loaded from; `None` when logged out
+    pub token_storage: Option<TokenStorage>,
 }
 
 impl Default for Config {
+    fn default() -> Self {
+        Self::from_profile(DEFAULT_PROFILE, Profile::default())
+    }
+}
+
+/// One server to talk to and who is logged in to it. Tokens live in the OS keychain;
+/// they are only written here when no keychain is available.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    pub server_url: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub auth_token: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub refresh_token: String,
+    pub user_email: Option<String>,
+}
+
+impl Default for Profile {
     fn default() -> Self {
         Self {
-            server_url: "http://localhost:3000".to_string(),
+            server_url: DEFAULT_SERVER_URL.to_string(),
             auth_token: String::new(),
+            refresh_token: String::new(),
             user_email: None,
         }
     }
 }
 
-impl Config {
+/// The config file: named profiles and which one commands use by default
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigFile {
+    pub active_profile: String,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Default for ConfigFile {
+    fn default() -> Self {
+        Self {
+            active_profile: DEFAULT_PROFILE.to_string(),
+            profiles: BTreeMap::from([(DEFAULT_PROFILE.to_string(), Profile::default())]),
+        }
+    }
+}
+
+impl ConfigFile {
     pub async fn load() -> anyhow::Result<Self> {
-        let config_path = Self::config_path()?;
-        
-        if config_path.exists() {
-            let content = tokio::fs::read_to_string(&config_path).await?;
-            let config = toml::from_str(&content)?;
-            Ok(config)
+        Self::load_from(&config_path()?).await
+    }
+
+    pub async fn save(&self) -> anyhow::Result<()> {
+        self.save_to(&config_path()?).await
+    }
+
+    /// Files written before profiles existed hold a single server and token;
+    /// those become the `default` profile.
+    pub async fn load_from(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = tokio::fs::read_to_string(path).await?;
+        let value: toml::Value = toml::from_str(&content)?;
+        if value.get("profiles").is_some() {
+            Ok(value.try_into()?)
         } else {
-            Ok(Self::default())
+            let profile: Profile = value.try_into()?;
+            Ok(Self {
+                active_profile: DEFAULT_PROFILE.to_string(),
+                profiles: BTreeMap::from([(DEFAULT_PROFILE.to_string(), profile)]),
+            })
         }
     }
 
-    pub async fn save(&self) -> anyhow::Result<()> {
-        let config_path = Self::config_path()?;
-        
-        if let Some(parent) = config_path.parent() {
+    pub async fn save_to(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
             tokio::fs::create_dir_all(parent).await?;
         }
 
         let content = toml::to_string_pretty(self)?;
-        tokio::fs::write(&config_path, content).await?;
+        tokio::fs::write(path, content).await?;
+
+        // May hold tokens when there is no keychain
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).await?;
+        }
+        Ok(())
+    }
+
+    pub fn add_profile(&mut self, name: &str, server_url: &str) -> anyhow::Result<()> {
+        if self.profiles.contains_key(name) {
+            return Err(CliError::ValidationError(format!("profile '{}' already exists", name)).into());
+        }
+        let profile = Profile { server_url: server_url.to_string(), ..Profile::default() };
+        self.profiles.insert(name.to_string(), profile);
+        Ok(())
+    }
+
+    pub fn use_profile(&mut self, name: &str) -> anyhow::Result<()> {
+        self.profile(name)?;
+        self.active_profile = name.to_string();
+        Ok(())
+    }
+
+    pub fn profile(&self, name: &str) -> anyhow::Result<&Profile> {
+        self.profiles.get(name).ok_or_else(|| {
+            CliError::ConfigError(format!("unknown profile '{}'. Add it with 'cx7 config profile add {}'", name, name))
+                .into()
+        })
+    }
+}
+
+impl Config {
+    /// Settings of `profile`, or of the active profile when `None`
+    pub async fn load(profile: Option<&str>) -> anyhow::Result<Self> {
+        Self::load_from(&config_path()?, profile).await
+    }
+
+    pub async fn save(&self) -> anyhow::Result<()> {
+        self.save_to(&config_path()?).await
+    }
+
+    /// Tokens written to the file by older versions, or without a keychain, win
+    /// over the keychain; the next save moves them into it.
+    pub async fn load_from(path: &Path, profile: Option<&str>) -> anyhow::Result<Self> {
+        let file = ConfigFile::load_from(path).await?;
+        let name = profile.unwrap_or(&file.active_profile);
+        let mut config = Self::from_profile(name, file.profile(name)?.clone());
+
+        if config.auth_token.is_empty() {
+            // Nothing can have been stored in a keychain that isn't there
+            if let Ok(Some(tokens)) = keychain::load(&config.profile, &config.server_url) {
+                config.auth_token = tokens.access_token;
+                config.refresh_token = tokens.refresh_token;
+                config.token_storage = Some(TokenStorage::Keychain);
+            }
+        } else {
+            config.token_storage = Some(TokenStorage::ConfigFile);
+        }
+        Ok(config)
+    }
+
+    /// Other profiles in the file are left as they are
+    pub async fn save_to(&self, path: &Path) -> anyhow::Result<()> {
+        let tokens = Tokens {
+            access_token: self.auth_token.clone(),
+            refresh_token: self.refresh_token.clone(),
+        };
+        let mut profile = Profile {
+            server_url: self.server_url.clone(),
+            user_email: self.user_email.clone(),
+            ..Profile::default()
+        };
+
+        if let Err(e) = keychain::store(&self.profile, &self.server_url, &tokens) {
+            if !tokens.is_empty() {
+                eprintln!(
+                    "{}",
+                    format!("⚠ No OS keychain available ({}); storing the token in {}", e, path.display()).yellow()
+                );
+                profile.auth_token = tokens.access_token;
+                profile.refresh_token = tokens.refresh_token;
+            }
+        }
+
+        let mut file = ConfigFile::load_from(path).await?;
+        if let Some(previous) = file.profiles.get(&self.profile) {
+            // Don't leave tokens behind under the server the profile used to point at
+            if previous.server_url != self.server_url {
+                keychain::store(&self.profile, &previous.server_url, &Tokens::default()).ok();
+            }
+        }
+        file.profiles.insert(self.profile.clone(), profile);
+        file.save_to(path).await
+    }
+
+    /// Commands that talk to the API call this first
+    pub fn require_auth(&self) -> Result<(), CliError> {
+        if self.auth_token.is_empty() {
+            return Err(CliError::AuthError(format!(
+                "not logged in to profile '{}'. Run 'cx7 auth login' first.",
+                self.profile
+            )));
+        }
         Ok(())
     }
 
-    fn config_path() -> anyhow::Result<PathBuf> {
-        let config_dir = dirs::config_dir()
-            .ok_or_else(|| anyhow::anyhow!("Could not determine config directory"))?
-            .join("compilex7");
-        
-        Ok(config_dir.join("config.toml"))
+    fn from_profile(name: &str, profile: Profile) -> Self {
+        Self {
+            profile: name.to_string(),
+            server_url: profile.server_url,
+            auth_token: profile.auth_token,
+            refresh_token: profile.refresh_token,
+            user_email: profile.user_email,
+            token_storage: None,
+        }
+    }
+}
+
+fn config_path() -> anyhow::Result<PathBuf> {
+    let config_dir = dirs::config_dir()
+        .ok_or_else(|| anyhow::anyhow!("Could not determine config directory"))?
+        .join("compilex7");
+
+    Ok(config_dir.join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_switching_between_profiles() {
+        keychain::mock::install();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+
+        let mut file = ConfigFile::load_from(&path).await.unwrap();
+        file.add_profile("local", "http://localhost:3000").unwrap();
+        file.add_profile("staging", "https://staging.compilex7.dev").unwrap();
+        assert!(file.add_profile("staging", "https://elsewhere.dev").is_err());
+        file.use_profile("staging").unwrap();
+        file.save_to(&path).await.unwrap();
+
+        // Logging in writes the token to the active profile only, and not to the file
+        let mut staging = Config::load_from(&path, None).await.unwrap();
+        assert_eq!(staging.profile, "staging");
+        assert_eq!(staging.server_url, "https://staging.compilex7.dev");
+        staging.auth_token = "staging-token".to_string();
+        staging.user_email = Some("dev@example.com".to_string());
+        staging.save_to(&path).await.unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("staging-token"));
+
+        let local = Config::load_from(&path, Some("local")).await.unwrap();
+        assert_eq!(local.server_url, "http://localhost:3000");
+        assert!(local.auth_token.is_empty());
+
+        let mut file = ConfigFile::load_from(&path).await.unwrap();
+        assert!(file.use_profile("prod").is_err());
+        file.use_profile("local").unwrap();
+        file.save_to(&path).await.unwrap();
+
+        assert_eq!(Config::load_from(&path, None).await.unwrap().profile, "local");
+        let staging = Config::load_from(&path, Some("staging")).await.unwrap();
+        assert_eq!(staging.auth_token, "staging-token");
+        assert_eq!(staging.token_storage, Some(TokenStorage::Keychain));
+        assert!(Config::load_from(&path, Some("prod")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tokens_fall_back_to_the_file_without_a_keychain() {
+        keychain::mock::install();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+
+        let mut file = ConfigFile::load_from(&path).await.unwrap();
+        file.add_profile("ci", keychain::mock::UNAVAILABLE_SERVER).unwrap();
+        file.save_to(&path).await.unwrap();
+
+        let mut ci = Config::load_from(&path, Some("ci")).await.unwrap();
+        ci.auth_token = "ci-token".to_string();
+        ci.refresh_token = "ci-refresh".to_string();
+        ci.save_to(&path).await.unwrap();
+
+        let ci = Config::load_from(&path, Some("ci")).await.unwrap();
+        assert_eq!(ci.auth_token, "ci-token");
+        assert_eq!(ci.refresh_token, "ci-refresh");
+        assert_eq!(ci.token_storage, Some(TokenStorage::ConfigFile));
+    }
+
+    #[tokio::test]
+    async fn test_single_server_config_becomes_default_profile() {
+        keychain::mock::install();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "server_url = \"https://api.compilex7.dev\"\nauth_token = \"old-token\"\nuser_email = \"dev@example.com\"\n",
+        )
+        .unwrap();
+
+        let config = Config::load_from(&path, None).await.unwrap();
+        assert_eq!(config.profile, DEFAULT_PROFILE);
+        assert_eq!(config.server_url, "https://api.compilex7.dev");
+        assert_eq!(config.auth_token, "old-token");
+
+        assert_eq!(config.token_storage, Some(TokenStorage::ConfigFile));
+
+        // Saving moves the plaintext token into the keychain
+        config.save_to(&path).await.unwrap();
+        let file = ConfigFile::load_from(&path).await.unwrap();
+        assert_eq!(file.profiles.len(), 1);
+        let profile = file.profile(DEFAULT_PROFILE).unwrap();
+        assert_eq!(profile.server_url, "https://api.compilex7.dev");
+        assert!(profile.auth_token.is_empty());
+
+        let config = Config::load_from(&path, None).await.unwrap();
+        assert_eq!(config.auth_token, "old-token");
+        assert_eq!(config.token_storage, Some(TokenStorage::Keychain));
     }
 }
@@ -1,10 +1,23 @@
+use colored::*;
+use serde::Serialize;
 use thiserror::Error;
 
+use crate::output::{self, OutputFormat};
+
 #[derive(Error, Debug)]
 pub enum CliError {
     #[error("Authentication failed: {0}")]
     AuthError(String),
 
+    #[error("Network error: {0}")]
+    NetworkError(String),
+
+    #[error("Not found: {0}")]
+    NotFound(String),
+
+    #[error("Invalid input: {0}")]
+    ValidationError(String),
+
     #[error("Configuration error: {0}")]
     ConfigError(String),
 
@@ -20,3 +33,93 @@ pub enum CliError {
     #[error("Unknown error: {0}")]
     Unknown(String),
 }
+
+/// Exit code for failures that aren't one of the kinds below
+pub const EXIT_FAILURE: i32 = 1;
+
+impl CliError {
+    /// Distinct process exit codes so scripts can branch on the kind of failure
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CliError::AuthError(_) => 10,
+            CliError::NetworkError(_) => 20,
+            CliError::NotFound(_) => 30,
+            CliError::ValidationError(_) => 40,
+            _ => EXIT_FAILURE,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CliError::AuthError(_) => "auth",
+            CliError::NetworkError(_) => "network",
+            CliError::NotFound(_) => "not_found",
+            CliError::ValidationError(_) => "validation",
+            CliError::ConfigError(_) => "config",
+            CliError::ApiError(_) => "api",
+            CliError::FileError(_) => "file",
+            CliError::SerializationError(_) => "serialization",
+            CliError::Unknown(_) => "unknown",
+        }
+    }
+}
+
+/// The `CliError` behind a command's error, looking through any context added to it
+fn cli_error(err: &anyhow::Error) -> Option<&CliError> {
+    err.chain().find_map(|cause| cause.downcast_ref::<CliError>())
+}
+
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    cli_error(err).map_or(EXIT_FAILURE, CliError::exit_code)
+}
+
+/// How a failure is printed with `--output json`/`yaml`
+#[derive(Debug, Serialize)]
+struct ErrorReport {
+    error: ErrorDetail,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorDetail {
+    kind: &'static str,
+    message: String,
+    exit_code: i32,
+}
+
+/// Print `err` to stderr (as JSON or YAML when the output is structured) and return
+/// the exit code for it
+pub fn report(err: &anyhow::Error, format: OutputFormat) -> i32 {
+    let code = exit_code(err);
+    let message = format!("{:#}", err);
+
+    if format.is_structured() {
+        let report = ErrorReport {
+            error: ErrorDetail {
+                kind: cli_error(err).map_or("error", CliError::kind),
+                message,
+                exit_code: code,
+            },
+        };
+        output::render(&mut std::io::stderr(), format, &report, |_, _| Ok(())).ok();
+    } else {
+        eprintln!("{}", format!("✗ {}", message).red().bold());
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_exit_code_looks_through_context() {
+        let err = Err::<(), _>(CliError::NotFound("project targeting".to_string()))
+            .context("Failed to fetch project")
+            .unwrap_err();
+        assert_eq!(exit_code(&err), 30);
+        assert_eq!(format!("{:#}", err), "Failed to fetch project: Not found: project targeting");
+
+        assert_eq!(exit_code(&anyhow::anyhow!("something else")), EXIT_FAILURE);
+    }
+}
@@ -0,0 +1,66 @@
+use std::path::Path;
+use std::sync::OnceLock;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
+
+const THEME: &str = "base16-ocean.dark";
+
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn theme() -> &'static Theme {
+    static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+    &THEMES.get_or_init(ThemeSet::load_defaults).themes[THEME]
+}
+
+/// `language` may be a name ("rust") or an extension ("rs"); without a match the
+/// file's own extension decides
+fn find_syntax(language: Option<&str>, path: &str) -> Option<&'static SyntaxReference> {
+    let syntaxes = syntaxes();
+    language
+        .and_then(|language| syntaxes.find_syntax_by_token(language))
+        .or_else(|| {
+            let extension = Path::new(path).extension()?.to_str()?;
+            syntaxes.find_syntax_by_extension(extension)
+        })
+}
+
+/// `content` coloured for a terminal, or unchanged when no syntax matches
+pub fn highlight(content: &str, language: Option<&str>, path: &str) -> String {
+    let Some(syntax) = find_syntax(language, path) else {
+        return content.to_string();
+    };
+
+    let mut highlighter = HighlightLines::new(syntax, theme());
+    let mut out = String::with_capacity(content.len() * 2);
+    for line in LinesWithEndings::from(content) {
+        match highlighter.highlight_line(line, syntaxes()) {
+            Ok(ranges) => out.push_str(&as_24_bit_terminal_escaped(&ranges, false)),
+            Err(_) => return content.to_string(),
+        }
+    }
+    out.push_str("\x1b[0m");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_language_wins_over_extension() {
+        assert_eq!(find_syntax(Some("rust"), "notes.txt").unwrap().name, "Rust");
+        assert_eq!(find_syntax(Some("py"), "main.rs").unwrap().name, "Python");
+        assert_eq!(find_syntax(None, "src/main.rs").unwrap().name, "Rust");
+        assert_eq!(find_syntax(Some("no-such-language"), "src/main.rs").unwrap().name, "Rust");
+        assert!(find_syntax(None, "Makefile.unknown-ext").is_none());
+
+        let content = "fn main() {}\n";
+        assert!(highlight(content, Some("rust"), "main.rs").contains("\x1b[38;2;"));
+        assert_eq!(highlight(content, None, "notes.unknown-ext"), content);
+    }
+}
@@ -0,0 +1,152 @@
+use serde::{Deserialize, Serialize};
+
+/// Service the tokens are filed under in the OS secret store
+const SERVICE: &str = "compilex7-cli";
+
+/// A profile's access and refresh token, kept together as one secret
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Tokens {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+impl Tokens {
+    pub fn is_empty(&self) -> bool {
+        self.access_token.is_empty() && self.refresh_token.is_empty()
+    }
+}
+
+/// Where a profile's tokens were found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenStorage {
+    Keychain,
+    /// Fallback for machines without a keychain, such as CI runners
+    ConfigFile,
+}
+
+/// Keyed by profile and server, so pointing a profile at another server starts it logged out
+fn entry(profile: &str, server_url: &str) -> keyring::Result<keyring::Entry> {
+    keyring::Entry::new(SERVICE, &format!("{}@{}", profile, server_url))
+}
+
+pub fn load(profile: &str, server_url: &str) -> keyring::Result<Option<Tokens>> {
+    match entry(profile, server_url)?.get_password() {
+        Ok(secret) => serde_json::from_str(&secret)
+            .map(Some)
+            .map_err(|e| keyring::Error::Invalid("secret".to_string(), e.to_string())),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Empty tokens delete the entry (logout)
+pub fn store(profile: &str, server_url: &str, tokens: &Tokens) -> keyring::Result<()> {
+    let entry = entry(profile, server_url)?;
+    if tokens.is_empty() {
+        return match entry.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(e),
+        };
+    }
+
+    let secret = serde_json::to_string(tokens)
+        .map_err(|e| keyring::Error::Invalid("secret".to_string(), e.to_string()))?;
+    entry.set_password(&secret)
+}
+
+/// An in-memory secret store shared by every entry, unlike keyring's own mock,
+/// which forgets a secret as soon as its `Entry` is dropped
+#[cfg(test)]
+pub mod mock {
+    use keyring::credential::{Credential, CredentialApi, CredentialBuilderApi};
+    use std::any::Any;
+    use std::collections::HashMap;
+    use std::sync::{Mutex, Once, OnceLock};
+
+    /// Entries for this server fail the way they do on a machine with no keychain
+    pub const UNAVAILABLE_SERVER: &str = "http://no-keychain.invalid";
+
+    fn secrets() -> &'static Mutex<HashMap<String, Vec<u8>>> {
+        static SECRETS: OnceLock<Mutex<HashMap<String, Vec<u8>>>> = OnceLock::new();
+        SECRETS.get_or_init(Default::default)
+    }
+
+    /// Install the shared store as keyring's default for the whole test binary
+    pub fn install() {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| keyring::set_default_credential_builder(Box::new(SharedStore)));
+    }
+
+    #[derive(Debug)]
+    struct SharedStore;
+
+    impl CredentialBuilderApi for SharedStore {
+        fn build(&self, _target: Option<&str>, service: &str, user: &str) -> keyring::Result<Box<Credential>> {
+            Ok(Box::new(SharedCredential { key: format!("{}/{}", service, user) }))
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[derive(Debug)]
+    struct SharedCredential {
+        key: String,
+    }
+
+    impl SharedCredential {
+        fn check_available(&self) -> keyring::Result<()> {
+            if self.key.ends_with(UNAVAILABLE_SERVER) {
+                return Err(keyring::Error::NoStorageAccess("no keychain on this machine".into()));
+            }
+            Ok(())
+        }
+    }
+
+    impl CredentialApi for SharedCredential {
+        fn set_secret(&self, secret: &[u8]) -> keyring::Result<()> {
+            self.check_available()?;
+            secrets().lock().unwrap().insert(self.key.clone(), secret.to_vec());
+            Ok(())
+        }
+
+        fn get_secret(&self) -> keyring::Result<Vec<u8>> {
+            self.check_available()?;
+            secrets().lock().unwrap().get(&self.key).cloned().ok_or(keyring::Error::NoEntry)
+        }
+
+        fn delete_credential(&self) -> keyring::Result<()> {
+            self.check_available()?;
+            secrets().lock().unwrap().remove(&self.key).map(|_| ()).ok_or(keyring::Error::NoEntry)
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_round_trip_per_profile_and_server() {
+        mock::install();
+        let tokens = Tokens {
+            access_token: "access-abc".to_string(),
+            refresh_token: "refresh-xyz".to_string(),
+        };
+
+        store("roundtrip", "https://staging.compilex7.dev", &tokens).unwrap();
+        assert_eq!(load("roundtrip", "https://staging.compilex7.dev").unwrap(), Some(tokens));
+        assert_eq!(load("roundtrip", "https://api.compilex7.dev").unwrap(), None);
+        assert_eq!(load("other", "https://staging.compilex7.dev").unwrap(), None);
+
+        store("roundtrip", "https://staging.compilex7.dev", &Tokens::default()).unwrap();
+        assert_eq!(load("roundtrip", "https://staging.compilex7.dev").unwrap(), None);
+        // Logging out twice is fine
+        store("roundtrip", "https://staging.compilex7.dev", &Tokens::default()).unwrap();
+    }
+}
@@ -1,10 +1,15 @@
+mod aws;
 mod commands;
 mod config;
 mod client;
 mod error;
+mod highlight;
+mod keychain;
+mod output;
 mod utils;
 
 use clap::{Parser, Subcommand};
+use output::OutputFormat;
 use tracing::Level;
 
 #[derive(Parser)]
@@ -20,10 +25,22 @@ struct Cli {
     #[arg(global = true, long, env = "CX7_SERVER")]
     server: Option<String>,
 
+    /// Config profile to use instead of the active one
+    #[arg(global = true, long, env = "CX7_PROFILE")]
+    profile: Option<String>,
+
     /// Enable debug output
     #[arg(global = true, long)]
     debug: bool,
 
+    /// Output format for list and status commands
+    #[arg(global = true, long, value_enum, default_value_t = OutputFormat::Table)]
+    output: OutputFormat,
+
+    /// Suppress progress text and success messages
+    #[arg(global = true, short, long)]
+    quiet: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -47,35 +64,110 @@ enum Commands {
 
     /// System and service status
     Status(commands::status::StatusArgs),
+
+    /// Deploy to AWS ECS and manage AWS secrets
+    Aws(commands::aws_deploy::AwsArgs),
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() {
     let cli = Cli::parse();
 
-    // Initialize tracing
+    // Initialize tracing; logs go to stderr so stdout carries only command output
     let level = if cli.debug { Level::DEBUG } else { Level::INFO };
     tracing_subscriber::fmt()
         .with_max_level(level)
+        .with_writer(std::io::stderr)
         .with_target(false)
         .compact()
         .init();
+    utils::set_quiet(cli.quiet);
+
+    // Failures exit with a code for their kind (see `CliError::exit_code`)
+    let output = cli.output;
+    if let Err(err) = run(cli).await {
+        std::process::exit(error::report(&err, output));
+    }
+}
 
+async fn run(cli: Cli) -> anyhow::Result<()> {
     // Initialize config
-    let mut cfg = config::Config::load().await?;
+    let mut cfg = if uses_profile(&cli.command) {
+        config::Config::load(cli.profile.as_deref()).await?
+    } else {
+        config::Config::default()
+    };
     if let Some(server) = cli.server {
         cfg.server_url = server;
     }
 
-    // Execute command
-    match cli.command {
-        Commands::Auth(args) => commands::auth::execute(cfg, args).await?,
-        Commands::Project(args) => commands::project::execute(cfg, args).await?,
-        Commands::Deploy(args) => commands::deploy::execute(cfg, args).await?,
-        Commands::Config(args) => commands::config::execute(cfg, args).await?,
-        Commands::Agent(args) => commands::agent::execute(cfg, args).await?,
-        Commands::Status(args) => commands::status::execute(cfg, args).await?,
+    execute(cfg, cli.command, cli.output).await
+}
+
+/// Whether the command needs the `--profile` or active profile resolved first
+fn uses_profile(command: &Commands) -> bool {
+    match command {
+        Commands::Config(args) => args.uses_profile(),
+        _ => true,
     }
+}
 
-    Ok(())
+async fn execute(cfg: config::Config, command: Commands, output: OutputFormat) -> anyhow::Result<()> {
+    match command {
+        Commands::Auth(args) => commands::auth::execute(cfg, args).await,
+        Commands::Project(args) => commands::project::execute(cfg, args, output).await,
+        Commands::Deploy(args) => commands::deploy::execute(cfg, args, output).await,
+        Commands::Config(args) => commands::config::execute(cfg, args).await,
+        Commands::Agent(args) => commands::agent::execute(cfg, args, output).await,
+        Commands::Status(args) => commands::status::execute(cfg, args, output).await,
+        Commands::Aws(args) => commands::aws_deploy::execute(args).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn exit_code_for(args: &[&str], cfg: config::Config) -> i32 {
+        let cli = Cli::try_parse_from(args).unwrap();
+        let err = execute(cfg, cli.command, cli.output).await.unwrap_err();
+        error::exit_code(&err)
+    }
+
+    #[test]
+    fn test_adding_a_profile_does_not_resolve_one() {
+        let uses = |args: &[&str]| uses_profile(&Cli::try_parse_from(args).unwrap().command);
+
+        assert!(!uses(&["cx7", "--profile", "staging", "config", "profile", "add", "staging", "--server", "https://staging.example.com"]));
+        assert!(uses(&["cx7", "--profile", "staging", "config", "profile", "use", "staging"]));
+        assert!(uses(&["cx7", "--profile", "staging", "config", "show"]));
+        assert!(uses(&["cx7", "--profile", "staging", "project", "list"]));
+    }
+
+    #[tokio::test]
+    async fn test_unauthenticated_commands_exit_with_auth_code() {
+        for args in [
+            &["cx7", "project", "list"][..],
+            &["cx7", "deploy", "history"],
+            &["cx7", "agent", "list", "--output", "json"],
+            &["cx7", "auth", "whoami"],
+        ] {
+            assert_eq!(exit_code_for(args, config::Config::default()).await, 10, "{:?}", args);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_server_exits_with_network_code() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let cfg = config::Config {
+            server_url: format!("http://{}", addr),
+            auth_token: "token".to_string(),
+            ..config::Config::default()
+        };
+        assert_eq!(exit_code_for(&["cx7", "project", "list", "-q"], cfg.clone()).await, 20);
+        assert_eq!(exit_code_for(&["cx7", "status", "--output", "json"], cfg).await, 20);
+    }
 }
@@ -0,0 +1,155 @@
+use clap::ValueEnum;
+use colored::*;
+use serde::Serialize;
+use std::io::{self, Write};
+
+use crate::utils;
+
+/// How list and status commands print their results (`--output`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// Aligned columns for reading in a terminal
+    #[default]
+    Table,
+    Json,
+    Yaml,
+}
+
+impl OutputFormat {
+    /// JSON and YAML print the data and nothing else, so they pipe cleanly
+    pub fn is_structured(self) -> bool {
+        self != OutputFormat::Table
+    }
+
+    pub fn spinner_start(self, message: &str) {
+        if !self.is_structured() {
+            utils::spinner_start(message);
+        }
+    }
+
+    pub fn spinner_stop(self) {
+        if !self.is_structured() {
+            utils::spinner_stop();
+        }
+    }
+}
+
+/// Write `value` as JSON or YAML, or hand it to `table` for the terminal rendering
+pub fn render<W, T>(
+    out: &mut W,
+    format: OutputFormat,
+    value: &T,
+    table: impl FnOnce(&mut W, &T) -> io::Result<()>,
+) -> anyhow::Result<()>
+where
+    W: Write,
+    T: Serialize + ?Sized,
+{
+    match format {
+        OutputFormat::Table => table(out, value)?,
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, value)?;
+            writeln!(out)?;
+        }
+        OutputFormat::Yaml => serde_yaml::to_writer(&mut *out, value)?,
+    }
+    Ok(())
+}
+
+/// Columns padded to their widest cell, with a bold header row
+pub struct Table {
+    headers: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: &[&'static str]) -> Self {
+        Self { headers: headers.to_vec(), rows: Vec::new() }
+    }
+
+    pub fn row(&mut self, cells: Vec<String>) {
+        self.rows.push(cells);
+    }
+
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        let widths: Vec<usize> = self
+            .headers
+            .iter()
+            .enumerate()
+            .map(|(col, header)| {
+                self.rows
+                    .iter()
+                    .filter_map(|row| row.get(col))
+                    .map(|cell| cell.chars().count())
+                    .fold(header.len(), usize::max)
+            })
+            .collect();
+
+        let header = pad(self.headers.iter().map(|h| h.to_string()), &widths);
+        writeln!(out, "{}", header.bold())?;
+        for row in &self.rows {
+            writeln!(out, "{}", pad(row.iter().cloned(), &widths))?;
+        }
+        Ok(())
+    }
+}
+
+fn pad(cells: impl Iterator<Item = String>, widths: &[usize]) -> String {
+    cells
+        .zip(widths)
+        .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+        .collect::<Vec<_>>()
+        .join("  ")
+        .trim_end()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Entry {
+        name: String,
+        count: usize,
+    }
+
+    fn entries() -> Vec<Entry> {
+        vec![
+            Entry { name: "api".to_string(), count: 3 },
+            Entry { name: "frontend".to_string(), count: 12 },
+        ]
+    }
+
+    fn rendered(format: OutputFormat) -> String {
+        let mut out = Vec::new();
+        render(&mut out, format, &entries(), |out, entries| {
+            let mut table = Table::new(&["NAME", "COUNT"]);
+            for entry in entries {
+                table.row(vec![entry.name.clone(), entry.count.to_string()]);
+            }
+            table.write_to(out)
+        })
+        .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_structured_formats_round_trip() {
+        let json: Vec<Entry> = serde_json::from_str(&rendered(OutputFormat::Json)).unwrap();
+        assert_eq!(json, entries());
+
+        let yaml: Vec<Entry> = serde_yaml::from_str(&rendered(OutputFormat::Yaml)).unwrap();
+        assert_eq!(yaml, entries());
+    }
+
+    #[test]
+    fn test_table_aligns_columns() {
+        colored::control::set_override(false);
+        assert_eq!(
+            rendered(OutputFormat::Table),
+            "NAME      COUNT\napi       3\nfrontend  12\n"
+        );
+    }
+}
@@ -1,13 +1,30 @@
 use colored::*;
 use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// `--quiet`: drop progress text and success banners, keeping results and errors
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
 
 pub fn spinner_start(message: &str) {
+    if quiet() {
+        return;
+    }
     print!("{} ", message.cyan());
     io::stdout().flush().ok();
 }
 
 pub fn spinner_stop() {
-    println!();
+    if !quiet() {
+        println!();
+    }
 }
 
 pub fn prompt(message: &str) -> String {
@@ -29,6 +46,9 @@ pub fn confirm(message: &str) -> bool {
 }
 
 pub fn print_success(message: &str) {
+    if quiet() {
+        return;
+    }
     println!("{}", format!("✓ {}", message).green().bold());
 }
 
@@ -37,6 +57,9 @@ pub fn print_error(message: &str) {
 }
 
 pub fn print_info(message: &str) {
+    if quiet() {
+        return;
+    }
     println!("{}", format!("ℹ {}", message).blue());
 }
 
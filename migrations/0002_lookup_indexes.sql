@@ -0,0 +1,8 @@
+-- Indexes for the foreign-key lookups done on every permission check and listing.
+-- The UNIQUE(project_id, user_id) / UNIQUE(team_id, user_id) constraints only help
+-- when filtering by the container first, so add user-first composites as well.
+CREATE INDEX IF NOT EXISTS project_members_user_project_idx ON project_members(user_id, project_id);
+CREATE INDEX IF NOT EXISTS team_members_user_team_idx ON team_members(user_id, team_id);
+CREATE INDEX IF NOT EXISTS review_comments_review_idx ON review_comments(review_id);
+CREATE INDEX IF NOT EXISTS analysis_tasks_project_idx ON analysis_tasks(project_id);
+CREATE INDEX IF NOT EXISTS audit_logs_resource_created_idx ON audit_logs(resource_type, resource_id, created_at);
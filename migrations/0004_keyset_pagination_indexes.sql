@@ -0,0 +1,5 @@
+-- Audit logs and review comments are paged by (created_at, id), newest first.
+-- These composites let each page be an index range scan however deep the client goes.
+CREATE INDEX IF NOT EXISTS audit_logs_actor_created_idx ON audit_logs(actor_id, created_at DESC, id DESC);
+CREATE INDEX IF NOT EXISTS audit_logs_resource_keyset_idx ON audit_logs(resource_type, resource_id, created_at DESC, id DESC);
+CREATE INDEX IF NOT EXISTS review_comments_review_created_idx ON review_comments(review_id, created_at DESC, id DESC);
@@ -0,0 +1,5 @@
+-- Deleting a project moves it to the trash by setting deleted_at; the row and
+-- everything hanging off it stay until the retention window passes and the
+-- background purge removes them for good.
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+CREATE INDEX IF NOT EXISTS projects_deleted_at_idx ON projects(deleted_at) WHERE deleted_at IS NOT NULL;
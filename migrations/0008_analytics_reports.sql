@@ -0,0 +1,12 @@
+-- Analytics reports a user generated, kept so they can be listed and fetched
+-- again. metrics holds the ReportMetrics the service computed for the window.
+CREATE TABLE IF NOT EXISTS reports (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    period_start TIMESTAMPTZ NOT NULL,
+    period_end TIMESTAMPTZ NOT NULL,
+    metrics JSONB NOT NULL,
+    generated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS reports_user_generated_idx ON reports(user_id, generated_at DESC);
@@ -0,0 +1,17 @@
+-- Invitations to join a team, by email, for people who may not have an account yet.
+-- Only the SHA-256 of the emailed token is kept; accepted_by/accepted_at are set once
+-- a signed-in user redeems it.
+CREATE TABLE IF NOT EXISTS team_invitations (
+    id UUID PRIMARY KEY,
+    team_id UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE,
+    email VARCHAR(255) NOT NULL,
+    role VARCHAR(50) NOT NULL,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    invited_by UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    accepted_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    accepted_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS team_invitations_team_created_idx ON team_invitations(team_id, created_at DESC);
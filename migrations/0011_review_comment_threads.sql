@@ -0,0 +1,6 @@
+-- Threaded review discussions: a reply points at the comment it answers, always in the
+-- same review. Deleting a comment takes its replies with it.
+ALTER TABLE review_comments
+    ADD COLUMN IF NOT EXISTS parent_comment_id UUID REFERENCES review_comments(id) ON DELETE CASCADE;
+
+CREATE INDEX IF NOT EXISTS review_comments_parent_idx ON review_comments(parent_comment_id);
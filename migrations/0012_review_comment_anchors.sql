@@ -0,0 +1,6 @@
+-- Line comments remember which version of the file their line_number refers to, so they
+-- can follow the line as the file changes. A comment whose line was edited or removed is
+-- left on the version it was written against and marked outdated.
+ALTER TABLE review_comments
+    ADD COLUMN IF NOT EXISTS file_version INTEGER,
+    ADD COLUMN IF NOT EXISTS outdated BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,15 @@
+-- AI answers to code analysis requests, keyed by the SHA-256 of the submitted code and
+-- everything else that shapes the answer, so identical resubmissions are served without
+-- another AI call. Rows are purged once expires_at has passed.
+CREATE TABLE IF NOT EXISTS analysis_cache (
+    code_hash VARCHAR(64) NOT NULL,
+    language VARCHAR(50) NOT NULL,
+    task_type VARCHAR(50) NOT NULL,
+    model VARCHAR(255) NOT NULL,
+    result JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (code_hash, language, task_type, model)
+);
+
+CREATE INDEX IF NOT EXISTS analysis_cache_expires_idx ON analysis_cache(expires_at);
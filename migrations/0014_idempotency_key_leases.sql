@@ -0,0 +1,6 @@
+-- How long a key stays reserved for the request that claimed it. A request that never
+-- finished (the server crashed or the client went away) stops blocking retries once
+-- this has passed, instead of for the key's whole TTL.
+ALTER TABLE idempotency_keys ADD COLUMN IF NOT EXISTS locked_until TIMESTAMPTZ;
+
+UPDATE idempotency_keys SET locked_until = created_at WHERE status_code IS NULL AND locked_until IS NULL;
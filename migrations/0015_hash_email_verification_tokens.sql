@@ -0,0 +1,6 @@
+-- Keep only the SHA-256 of email verification tokens, as for refresh tokens and team
+-- invitations. Tokens issued before this were stored in the clear and never emailed,
+-- so they're dropped rather than hashed.
+DELETE FROM email_verification_tokens;
+ALTER TABLE email_verification_tokens RENAME COLUMN token TO token_hash;
+ALTER TABLE email_verification_tokens ALTER COLUMN token_hash TYPE CHAR(64);
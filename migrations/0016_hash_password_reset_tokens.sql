@@ -0,0 +1,6 @@
+-- Keep only the SHA-256 of password reset tokens, as for email verification tokens.
+-- Tokens issued before this were stored in the clear and never emailed, so they're
+-- dropped rather than hashed.
+DELETE FROM password_reset_tokens;
+ALTER TABLE password_reset_tokens RENAME COLUMN token TO token_hash;
+ALTER TABLE password_reset_tokens ALTER COLUMN token_hash TYPE CHAR(64);
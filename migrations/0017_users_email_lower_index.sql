@@ -0,0 +1,2 @@
+-- Password logins and GitHub account linking look users up by LOWER(email)
+CREATE INDEX IF NOT EXISTS users_email_lower_idx ON users (LOWER(email));
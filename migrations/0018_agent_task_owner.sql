@@ -0,0 +1,3 @@
+-- Who started each agent task; only they can check on or cancel it
+ALTER TABLE agent_tasks ADD COLUMN IF NOT EXISTS user_id UUID REFERENCES users(id) ON DELETE CASCADE;
+CREATE INDEX IF NOT EXISTS agent_tasks_user_id_idx ON agent_tasks (user_id);
@@ -0,0 +1,4 @@
+-- One account per address, whatever its case. Fails while two accounts differ only in the
+-- case of their email; merge or rename those by hand first.
+DROP INDEX IF EXISTS users_email_lower_idx;
+CREATE UNIQUE INDEX IF NOT EXISTS users_email_lower_key ON users (LOWER(email));
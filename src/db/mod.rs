@@ -1,408 +1,282 @@
-# Permission Inheritance System
-
-## Overview
-
-The Permission Inheritance System enables hierarchical permission management for teams and projects in CompileX7. This allows organizations to create nested team/project structures where child resources automatically inherit permissions from their parents, reducing permission management overhead.
-
-## Table of Contents
-
-1. [Core Concepts](#core-concepts)
-2. [Architecture](#architecture)
-3. [API Reference](#api-reference)
-4. [Configuration](#configuration)
-5. [Examples](#examples)
-6. [Best Practices](#best-practices)
-7. [Troubleshooting](#troubleshooting)
-
-## Core Concepts
-
-### Hierarchy Types
-
-#### Team Hierarchy
-- Teams can be organized in parent-child relationships
-- Child teams inherit permissions from parent teams
-- Useful for organizational structure (e.g., Company → Department → Team)
-
-#### Project Hierarchy
-- Projects can be organized in parent-child relationships
-- Child projects inherit permissions from parent projects
-- Useful for project organization (e.g., Product → Feature → Sprint)
-
-### Permission Resolution
-
-Permission resolution follows a specific precedence:
-
-1. **Direct Permissions** - Permissions explicitly assigned to user on resource
-2. **Inherited Permissions** - Permissions inherited from parent resources
-3. **Effective Permissions** - Union of direct and inherited permissions
-
-### Inheritance Depth
-
-By default, permissions traverse up to 5 levels in the hierarchy. This prevents infinite loops and maintains performance.
-
-```
-Level 0: Resource (child)
-Level 1: Parent
-Level 2: Grandparent
-Level 3: Great-grandparent
-Level 4: Great-great-grandparent
-Level 5: (max depth reached)
-```
-
-### Permission Caching
-
-Resolved permissions are cached to optimize performance. The cache is automatically invalidated when:
-- A user's role changes
-- A hierarchy relationship is modified
-- A permission rule is updated
-
-## Architecture
-
-### Components
-
-#### InheritanceEngine
-Core service responsible for permission resolution and hierarchy management.
-
-```rust
-pub struct InheritanceEngine {
-    pool: Arc<Pool<Postgres>>,
-    config: InheritanceConfig,
-    cache: std::sync::Mutex<HashMap<(Uuid, Uuid), ResolvedPermissions>>,
+use sqlx::{
+    migrate::{MigrateError, Migrator},
+    postgres::PgPoolOptions,
+    PgPool,
+};
+use std::time::Duration;
+
+use crate::config::Config;
+
+/// Embedded at compile time; add new files rather than editing applied ones
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Connection pool sizing and timeouts passed to `PgPoolOptions`
+#[derive(Debug, Clone)]
+pub struct PoolSettings {
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout: Duration,
+    pub idle_timeout: Duration,
 }
-```
-
-**Key Methods:**
-- `resolve_permissions()` - Get effective permissions for user on resource
-- `get_inherited_permissions()` - Get permissions from parent hierarchy
-- `build_hierarchy_tree()` - Visualize hierarchy structure
-- `has_permission()` - Check if user has specific permission
-- `clear_cache()` - Invalidate permission cache
-
-#### RBAC Middleware Extensions
-New middleware functions support inheritance-aware permission checks.
-
-**New Functions:**
-- `enforce_permission_with_inheritance()` - Check permissions with inheritance
-- `get_resolved_permissions()` - Get detailed permission breakdown
 
-#### Handlers
-New endpoints for managing hierarchies and permissions.
-
-**Endpoints:**
-- `POST /api/hierarchies/teams` - Create team hierarchy
-- `POST /api/hierarchies/projects` - Create project hierarchy
-- `GET /api/permissions/{resource_id}/{resource_type}` - Get resolved permissions
-- `POST /api/permission-rules` - Create permission rules
-- `GET /api/audit-logs` - View audit trail
-
-### Database Schema
-
-#### New Tables
-
-```sql
--- Team/Project hierarchies
-team_hierarchy
-project_hierarchy
-
--- Permission rules by role
-permission_rules
-
--- Inherited permissions cache
-inherited_permissions
-
--- Audit trail
-audit_logs
-```
-
-## API Reference
-
-### Create Team Hierarchy
-
-```bash
-POST /api/hierarchies/teams
-Content-Type: application/json
-Authorization: Bearer <token>
-
-{
-  "parent_team_id": "550e8400-e29b-41d4-a716-446655440000",
-  "child_team_id": "550e8400-e29b-41d4-a716-446655440001",
-  "inheritance_enabled": true
-}
-```
-
-**Response:**
-```json
-{
-  "id": "550e8400-e29b-41d4-a716-446655440002",
-  "parent_team_id": "550e8400-e29b-41d4-a716-446655440000",
-  "child_team_id": "550e8400-e29b-41d4-a716-446655440001",
-  "inheritance_enabled": true,
-  "created_at": "2024-01-22T10:00:00Z"
-}
-```
-
-### Get Resolved Permissions
-
-```bash
-GET /api/permissions/{resource_id}/team
-Authorization: Bearer <token>
-```
-
-**Response:**
-```json
-{
-  "user_id": "550e8400-e29b-41d4-a716-446655440003",
-  "resource_id": "550e8400-e29b-41d4-a716-446655440000",
-  "resource_type": "team",
-  "direct_permissions": ["read", "write"],
-  "inherited_permissions": [
-    {
-      "source_id": "550e8400-e29b-41d4-a716-446655440001",
-      "source_type": "team",
-      "permissions": ["admin"],
-      "depth": 1,
-      "from_role": "admin"
+impl PoolSettings {
+    pub fn from_config(config: &Config) -> Self {
+        PoolSettings {
+            max_connections: config.db_max_connections,
+            min_connections: config.db_min_connections,
+            acquire_timeout: Duration::from_secs(config.db_acquire_timeout_secs),
+            idle_timeout: Duration::from_secs(config.db_idle_timeout_secs),
+        }
     }
-  ],
-  "effective_permissions": ["read", "write", "admin"],
-  "role": "member"
-}
-```
-
-### Create Permission Rule
-
-```bash
-POST /api/permission-rules
-Content-Type: application/json
-Authorization: Bearer <token>
-
-{
-  "team_id": "550e8400-e29b-41d4-a716-446655440000",
-  "role": "member",
-  "permissions": ["read", "write"],
-  "description": "Default member permissions",
-  "priority": 0
 }
-```
-
-### Get Audit Logs
 
-```bash
-GET /api/audit-logs?resource_type=team&resource_id=550e8400-e29b-41d4-a716-446655440000
-Authorization: Bearer <token>
-```
-
-## Configuration
-
-### InheritanceConfig
-
-```rust
-pub struct InheritanceConfig {
-    pub enabled: bool,              // Enable/disable inheritance
-    pub max_depth: i32,             // Maximum hierarchy depth (default: 5)
-    pub cascading_updates: bool,    // Propagate changes downward
-    pub override_allowed: bool,     // Allow child overrides
+impl Default for PoolSettings {
+    fn default() -> Self {
+        PoolSettings {
+            max_connections: 20,
+            min_connections: 2,
+            acquire_timeout: Duration::from_secs(5),
+            idle_timeout: Duration::from_secs(600),
+        }
+    }
 }
-```
-
-### Default Configuration
 
-```rust
-InheritanceConfig {
-    enabled: true,
-    max_depth: 5,
-    cascading_updates: true,
-    override_allowed: true,
+pub struct Database {
+    pool: PgPool,
 }
-```
-
-## Examples
-
-### Example 1: Corporate Structure
 
-```
-Company (Parent Team)
-├── Engineering Department
-│   ├── Backend Team
-│   └── Frontend Team
-└── Sales Department
-    └── Account Management Team
-```
-
-**Permission Flow:**
-1. User assigned "read" on Company team
-2. User automatically gets "read" on all child teams
-3. Backend Team can override with "admin" for team members
-
-### Example 2: Multi-level Project
-
-```
-Product (Parent Project)
-├── Feature A
-│   ├── Sprint 1
-│   └── Sprint 2
-└── Feature B
-    ├── Sprint 1
-    └── Sprint 2
-```
-
-**Permission Flow:**
-1. Developer assigned "write" on Feature A
-2. Developer inherits "write" on Sprint 1 and Sprint 2
-3. Sprint leads get "admin" on their specific sprints
-
-### Usage Example
-
-```rust
-// Create inheritance engine
-let engine = InheritanceEngine::new(
-    Arc::new(pool),
-    Some(InheritanceConfig::default())
-);
-
-// Resolve permissions for user
-let permissions = engine.resolve_permissions(
-    user_id,
-    team_id,
-    "team"
-).await?;
-
-// Check specific permission
-let can_write = engine.has_permission(
-    user_id,
-    team_id,
-    "team",
-    "write"
-).await?;
-
-// Build hierarchy tree
-let tree = engine.build_hierarchy_tree(
-    team_id,
-    "team",
-    "Engineering"
-).await?;
-```
-
-## Best Practices
-
-### 1. Hierarchy Design
-
-- Keep hierarchy depth under 5 levels for performance
-- Design hierarchies that match organizational structure
-- Avoid circular references (enforced by database constraints)
-
-### 2. Permission Rules
-
-- Define role-based permission rules at each level
-- Use consistent role names across hierarchy
-- Document permission inheritance flow
-
-### 3. Performance
-
-- Use permission caching in high-volume scenarios
-- Invalidate cache strategically to avoid stale permissions
-- Monitor query performance on large hierarchies
-
-### 4. Audit Trail
-
-- Enable audit logging for compliance requirements
-- Review audit logs regularly for permission changes
-- Archive old logs for archival
-
-### 5. Security
-
-- Always verify user permissions via inheritance engine
-- Don't trust cached permissions in sensitive operations
-- Use role hierarchy to enforce least privilege
-
-## Troubleshooting
-
-### Issue: Permissions Not Inherited
-
-**Symptoms:** User doesn't have expected inherited permissions
-
-**Solutions:**
-1. Verify hierarchy relationship exists: Check `team_hierarchy` or `project_hierarchy` tables
-2. Check inheritance enabled: Ensure `inheritance_enabled = true`
-3. Clear cache: Call `engine.clear_cache()` to refresh
-4. Verify role assignment: Ensure user has role on parent resource
+impl Database {
+    pub async fn new(database_url: &str, settings: &PoolSettings) -> Result<Self, sqlx::Error> {
+        let pool = PgPoolOptions::new()
+            .max_connections(settings.max_connections)
+            .min_connections(settings.min_connections)
+            .acquire_timeout(settings.acquire_timeout)
+            .idle_timeout(settings.idle_timeout)
+            // Ping pooled connections before handing them out so ones dropped by the server are replaced
+            .test_before_acquire(true)
+            .connect(database_url)
+            .await?;
+
+        Ok(Database { pool })
+    }
 
-### Issue: Circular Hierarchy
+    #[cfg(test)]
+    pub fn from_pool(pool: PgPool) -> Self {
+        Database { pool }
+    }
 
-**Symptoms:** Database constraint error on hierarchy creation
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
 
-**Solutions:**
-1. Review existing hierarchies to identify loop
-2. Remove circular relationship
-3. Use `build_hierarchy_tree()` to visualize structure
+    /// Wait for checked-out connections to be returned, then close them all
+    pub async fn close(&self) {
+        self.pool.close().await
+    }
 
-### Issue: Performance Degradation
+    /// Apply any migrations from `migrations/` not yet recorded in `_sqlx_migrations`
+    pub async fn run_migrations(&self) -> Result<(), MigrateError> {
+        MIGRATOR.run(&self.pool).await
+    }
 
-**Symptoms:** Slow permission resolution
+    /// Whether every migration embedded in the binary is recorded as successfully applied
+    pub async fn migrations_applied(&self) -> Result<bool, sqlx::Error> {
+        let applied: Vec<i64> =
+            sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+                .fetch_all(&self.pool)
+                .await?;
 
-**Solutions:**
-1. Check hierarchy depth: Limit to reasonable depth
-2. Monitor cache hit rate: Review cache statistics
-3. Analyze database queries: Use EXPLAIN ANALYZE
-4. Consider flattening very deep hierarchies
+        Ok(MIGRATOR
+            .iter()
+            .all(|migration| applied.contains(&migration.version)))
+    }
 
-### Issue: Stale Permissions After Update
+    /// Delete revoked token entries and refresh tokens whose tokens would have expired anyway
+    pub async fn purge_expired_revoked_tokens(&self) -> Result<u64, sqlx::Error> {
+        let revoked = sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < NOW()")
+            .execute(&self.pool)
+            .await?;
+        let refresh = sqlx::query("DELETE FROM refresh_tokens WHERE expires_at < NOW()")
+            .execute(&self.pool)
+            .await?;
 
-**Symptoms:** Permission changes not reflected immediately
+        Ok(revoked.rows_affected() + refresh.rows_affected())
+    }
 
-**Solutions:**
-1. Clear relevant cache entries: `engine.clear_cache_for_resource(user_id, resource_id)`
-2. Wait for cache expiration (if TTL configured)
-3. Restart service (full cache clear)
+    /// Delete half-finished logins: OAuth states from redirects that never came back
+    /// and two-factor challenges that were never answered
+    pub async fn purge_expired_login_challenges(&self) -> Result<u64, sqlx::Error> {
+        let oauth = sqlx::query("DELETE FROM oauth_states WHERE expires_at < NOW()")
+            .execute(&self.pool)
+            .await?;
+        let two_factor = sqlx::query("DELETE FROM two_factor_challenges WHERE expires_at < NOW()")
+            .execute(&self.pool)
+            .await?;
+
+        Ok(oauth.rows_affected() + two_factor.rows_affected())
+    }
 
-## Migration Guide
+    /// Delete cached AI analysis answers past their expiry
+    pub async fn purge_expired_analysis_cache(&self) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM analysis_cache WHERE expires_at < NOW()")
+            .execute(&self.pool)
+            .await?;
 
-### From Flat Permissions to Hierarchical
+        Ok(result.rows_affected())
+    }
 
-```rust
-// Step 1: Create hierarchy relationship
-POST /api/hierarchies/teams {
-  "parent_team_id": "...",
-  "child_team_id": "...",
-  "inheritance_enabled": true
+    /// Permanently delete projects that have sat in the trash longer than `retention_days`,
+    /// along with everything that cascades from them
+    pub async fn purge_trashed_projects(&self, retention_days: u32) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query(
+            "DELETE FROM projects WHERE deleted_at < NOW() - make_interval(days => $1)",
+        )
+        .bind(retention_days as i32)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
 }
 
-// Step 2: Create permission rules
-POST /api/permission-rules {
-  "team_id": "...",
-  "role": "member",
-  "permissions": ["read", "write"],
-  ...
+/// Connect to the database named by `DATABASE_URL` and apply the schema
+#[cfg(test)]
+pub async fn test_database() -> std::sync::Arc<Database> {
+    let database_url =
+        std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for database tests");
+    let db = Database::new(&database_url, &PoolSettings::default())
+        .await
+        .expect("Failed to connect to test database");
+    db.run_migrations()
+        .await
+        .expect("Failed to run migrations");
+
+    std::sync::Arc::new(db)
 }
 
-// Step 3: Verify permissions resolve correctly
-GET /api/permissions/{resource_id}/team
-
-// Step 4: Remove redundant direct permissions if appropriate
-DELETE old direct permissions
-
-// Step 5: Monitor via audit logs
-GET /api/audit-logs?action=*
-```
-
-## Performance Metrics
-
-### Typical Resolution Times
-
-- **Simple hierarchy** (1-2 levels): < 5ms
-- **Medium hierarchy** (3-4 levels): 10-50ms
-- **Deep hierarchy** (5+ levels): 50-200ms
+/// Insert a user emailed `{id}@example.com` whose password never verifies
+#[cfg(test)]
+pub async fn insert_test_user(pool: &PgPool) -> uuid::Uuid {
+    let user_id = uuid::Uuid::new_v4();
+    sqlx::query("INSERT INTO users (id, email, password_hash) VALUES ($1, $2, $3)")
+        .bind(user_id)
+        .bind(format!("{}@example.com", user_id))
+        .bind("unused")
+        .execute(pool)
+        .await
+        .unwrap();
+    user_id
+}
 
-### Cache Impact
+/// Insert a project named `name` owned by `owner`
+#[cfg(test)]
+pub async fn insert_test_project(pool: &PgPool, owner: uuid::Uuid, name: &str) -> uuid::Uuid {
+    let project_id = uuid::Uuid::new_v4();
+    sqlx::query("INSERT INTO projects (id, user_id, name) VALUES ($1, $2, $3)")
+        .bind(project_id)
+        .bind(owner)
+        .bind(name)
+        .execute(pool)
+        .await
+        .unwrap();
+    project_id
+}
 
-- **Cache hit**: < 1ms
-- **Cache miss**: 10-200ms (depending on hierarchy depth)
-- **Cache hit rate goal**: > 90% for production
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::postgres::PgConnectOptions;
+    use std::str::FromStr;
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_pool_respects_max_connections() {
+        let database_url =
+            std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for database tests");
+        let settings = PoolSettings {
+            max_connections: 2,
+            min_connections: 0,
+            acquire_timeout: Duration::from_millis(500),
+            ..PoolSettings::default()
+        };
+        let db = Database::new(&database_url, &settings).await.unwrap();
+        assert_eq!(db.pool().options().get_max_connections(), 2);
+
+        let _first = db.pool().acquire().await.unwrap();
+        let _second = db.pool().acquire().await.unwrap();
+        assert!(matches!(
+            db.pool().acquire().await,
+            Err(sqlx::Error::PoolTimedOut)
+        ));
+    }
 
-## Support
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_migrations_are_idempotent() {
+        let database_url =
+            std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for database tests");
+        let admin = PgPool::connect(&database_url).await.unwrap();
+        let name = format!("migrate_test_{}", uuid::Uuid::new_v4().simple());
+        sqlx::query(&format!("CREATE DATABASE {}", name))
+            .execute(&admin)
+            .await
+            .unwrap();
+
+        let options = PgConnectOptions::from_str(&database_url)
+            .unwrap()
+            .database(&name);
+        let db = Database::from_pool(PgPool::connect_with(options).await.unwrap());
+        db.run_migrations().await.unwrap();
+        db.run_migrations().await.unwrap();
+
+        let applied: Vec<i64> =
+            sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success ORDER BY version")
+                .fetch_all(db.pool())
+                .await
+                .unwrap();
+        let expected: Vec<i64> = MIGRATOR.iter().map(|m| m.version).collect();
+        assert_eq!(applied, expected);
+
+        // A closed connection's backend can linger briefly, so don't wait for it
+        db.pool().close().await;
+        sqlx::query(&format!("DROP DATABASE {} WITH (FORCE)", name))
+            .execute(&admin)
+            .await
+            .unwrap();
+    }
 
-For issues or questions:
-1. Check this documentation
-2. Review audit logs for diagnostics
-3. Check database schema constraints
-4. Test with `InheritanceEngine` unit tests
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_lookup_indexes_exist() {
+        let db = test_database().await;
+        let expected = [
+            ("project_members", "project_members_user_project_idx", "(user_id, project_id)"),
+            ("team_members", "team_members_user_team_idx", "(user_id, team_id)"),
+            ("review_comments", "review_comments_review_idx", "(review_id)"),
+            ("analysis_tasks", "analysis_tasks_project_idx", "(project_id)"),
+            (
+                "audit_logs",
+                "audit_logs_resource_created_idx",
+                "(resource_type, resource_id, created_at)",
+            ),
+        ];
+
+        for (table, index, columns) in expected {
+            let definition: Option<String> = sqlx::query_scalar(
+                "SELECT indexdef FROM pg_indexes WHERE schemaname = current_schema() AND tablename = $1 AND indexname = $2",
+            )
+            .bind(table)
+            .bind(index)
+            .fetch_optional(db.pool())
+            .await
+            .unwrap();
+            let definition = definition.unwrap_or_else(|| panic!("missing index {}", index));
+            assert!(
+                definition.ends_with(columns),
+                "{} has unexpected definition {}",
+                index,
+                definition
+            );
+        }
+    }
+}
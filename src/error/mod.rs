@@ -3,7 +3,10 @@ use axum::{
     response::{IntoResponse, Response},
     Json,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::middleware::request_id::current_request_id;
 
 #[derive(Debug)]
 pub enum AppError {
@@ -13,14 +16,18 @@ pub enum AppError {
     AuthorizationError(String),
     NotFoundError(String),
     ConflictError(String),
+    RateLimitError(String),
     ExternalApiError(String),
     InternalServerError(String),
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct ErrorResponse {
     pub code: String,
     pub message: String,
+    /// Matches the `x-request-id` response header, so a reported error can be found in the logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<Uuid>,
 }
 
 impl IntoResponse for AppError {
@@ -36,6 +43,7 @@ impl IntoResponse for AppError {
             AppError::AuthorizationError(msg) => (StatusCode::FORBIDDEN, msg, "AUTHORIZATION_ERROR".to_string()),
             AppError::NotFoundError(msg) => (StatusCode::NOT_FOUND, msg, "NOT_FOUND_ERROR".to_string()),
             AppError::ConflictError(msg) => (StatusCode::CONFLICT, msg, "CONFLICT_ERROR".to_string()),
+            AppError::RateLimitError(msg) => (StatusCode::TOO_MANY_REQUESTS, msg, "RATE_LIMIT_ERROR".to_string()),
             AppError::ExternalApiError(msg) => (
                 StatusCode::BAD_GATEWAY,
                 msg,
@@ -51,6 +59,7 @@ impl IntoResponse for AppError {
         let body = Json(ErrorResponse {
             code,
             message: error_message,
+            request_id: current_request_id(),
         });
 
         (status, body).into_response()
@@ -1,132 +1,199 @@
-use axum::{extract::State, Json, Path};
+use axum::{
+    extract::{Path, State},
+    Extension, Json,
+};
+use sqlx::{postgres::PgRow, Row};
 use std::sync::Arc;
 use uuid::Uuid;
 
 use crate::{
     db::Database,
-    error::AppResult,
-    models::{AgentRequest, AgentTaskResponse, AgentTaskStatus},
-    services::agent::{Agent, FrontendAgent, BackendAgent, QAAgent},
+    error::{AppError, AppResult},
+    handlers::projects::{ensure_project_access, ensure_project_permission},
+    middleware_auth::AuthUser,
+    models::{AgentInfo, AgentRequest, AgentTaskResponse, AgentTaskStatus},
+    services::agent::{Agent, AgentQueue, AgentResult, FrontendAgent, BackendAgent, QAAgent},
+    services::events::{DomainEvent, EventBus},
+    telemetry,
+    utils::validation::ValidatedJson,
 };
 
+/// The agents there are routes for, for `cx7 agent list`
+pub async fn list_agents() -> Json<Vec<AgentInfo>> {
+    let agents = [
+        ("frontend", "Builds UI components and pages"),
+        ("backend", "Builds API endpoints and services"),
+        ("qa", "Writes tests and reviews code for defects"),
+    ];
+    Json(
+        agents
+            .into_iter()
+            .map(|(name, description)| AgentInfo {
+                name: name.to_string(),
+                description: description.to_string(),
+            })
+            .collect(),
+    )
+}
+
 pub async fn frontend_agent(
     State(db): State<Arc<Database>>,
-    Json(payload): Json<AgentRequest>,
+    Extension(queue): Extension<Arc<AgentQueue>>,
+    Extension(events): Extension<Arc<EventBus>>,
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(payload): ValidatedJson<AgentRequest>,
 ) -> AppResult<Json<AgentTaskResponse>> {
-    let task_id = Uuid::new_v4();
-
-    // Store agent task in database
-    sqlx::query(
-        "INSERT INTO agent_tasks (id, project_id, agent_type, status, request_data) VALUES ($1, $2, $3, $4, $5)"
-    )
-    .bind(&task_id)
-    .bind(&payload.project_id)
-    .bind("frontend")
-    .bind("processing")
-    .bind(serde_json::json!(payload))
-    .execute(db.pool())
-    .await?;
-
-    // Execute agent (non-blocking)
-    let agent = FrontendAgent::new();
-    tokio::spawn(async move {
-        match agent.execute(&payload.task_description, payload.context).await {
-            Ok(result) => {
-                tracing::info!("Frontend agent task {} completed", task_id);
-            }
-            Err(e) => {
-                tracing::error!("Frontend agent task {} failed: {:?}", task_id, e);
-            }
-        }
-    });
-
-    Ok(Json(AgentTaskResponse {
-        task_id,
-        agent_type: "frontend".to_string(),
-        status: "processing".to_string(),
-    }))
+    start_agent_task(db, &queue, events, user_id, payload, "frontend", FrontendAgent::new()).await
 }
 
 pub async fn backend_agent(
     State(db): State<Arc<Database>>,
-    Json(payload): Json<AgentRequest>,
+    Extension(queue): Extension<Arc<AgentQueue>>,
+    Extension(events): Extension<Arc<EventBus>>,
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(payload): ValidatedJson<AgentRequest>,
+) -> AppResult<Json<AgentTaskResponse>> {
+    start_agent_task(db, &queue, events, user_id, payload, "backend", BackendAgent::new()).await
+}
+
+pub async fn qa_agent(
+    State(db): State<Arc<Database>>,
+    Extension(queue): Extension<Arc<AgentQueue>>,
+    Extension(events): Extension<Arc<EventBus>>,
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(payload): ValidatedJson<AgentRequest>,
+) -> AppResult<Json<AgentTaskResponse>> {
+    start_agent_task(db, &queue, events, user_id, payload, "qa", QAAgent::new()).await
+}
+
+/// Store the task as queued and hand it to the agent queue, which marks it
+/// processing when a worker picks it up and records the outcome. Agents work on the
+/// project on the user's behalf, so the user needs `write` on it.
+async fn start_agent_task<A: Agent + 'static>(
+    db: Arc<Database>,
+    queue: &AgentQueue,
+    events: Arc<EventBus>,
+    user_id: Uuid,
+    payload: AgentRequest,
+    agent_type: &'static str,
+    agent: A,
 ) -> AppResult<Json<AgentTaskResponse>> {
+    ensure_project_permission(&db, payload.project_id, user_id, "write").await?;
+
     let task_id = Uuid::new_v4();
 
+    // Store agent task in database
     sqlx::query(
-        "INSERT INTO agent_tasks (id, project_id, agent_type, status, request_data) VALUES ($1, $2, $3, $4, $5)"
+        "INSERT INTO agent_tasks (id, project_id, user_id, agent_type, status, request_data) VALUES ($1, $2, $3, $4, $5, $6)"
     )
     .bind(&task_id)
     .bind(&payload.project_id)
-    .bind("backend")
-    .bind("processing")
+    .bind(user_id)
+    .bind(agent_type)
+    .bind("queued")
     .bind(serde_json::json!(payload))
     .execute(db.pool())
     .await?;
 
-    let agent = BackendAgent::new();
-    tokio::spawn(async move {
-        match agent.execute(&payload.task_description, payload.context).await {
-            Ok(result) => {
-                tracing::info!("Backend agent task {} completed", task_id);
-            }
+    queue.enqueue_task(task_id, async move {
+        // A task cancelled while queued stays cancelled
+        let started = sqlx::query(
+            "UPDATE agent_tasks SET status = 'processing' WHERE id = $1 AND status = 'queued'"
+        )
+        .bind(task_id)
+        .execute(db.pool())
+        .await;
+        match started {
+            Ok(result) if result.rows_affected() == 0 => return,
+            Ok(_) => {}
             Err(e) => {
-                tracing::error!("Backend agent task {} failed: {:?}", task_id, e);
+                tracing::error!("Failed to start agent task {}: {:?}", task_id, e);
+                return;
             }
         }
+
+        let outcome = agent.execute(&payload.task_description, payload.context).await;
+
+        match &outcome {
+            Ok(_) => tracing::info!("{} agent task {} completed", agent_type, task_id),
+            Err(e) => tracing::error!("{} agent task {} failed: {:?}", agent_type, task_id, e),
+        }
+
+        if let Err(e) = record_agent_outcome(&db, &events, task_id, outcome).await {
+            tracing::error!("Failed to record outcome of agent task {}: {:?}", task_id, e);
+        }
     });
 
     Ok(Json(AgentTaskResponse {
         task_id,
-        agent_type: "backend".to_string(),
-        status: "processing".to_string(),
+        agent_type: agent_type.to_string(),
+        status: "queued".to_string(),
     }))
 }
 
-pub async fn qa_agent(
-    State(db): State<Arc<Database>>,
-    Json(payload): Json<AgentRequest>,
-) -> AppResult<Json<AgentTaskResponse>> {
-    let task_id = Uuid::new_v4();
+/// Mark the task completed with the agent's result, or failed with its error
+async fn record_agent_outcome(
+    db: &Database,
+    events: &EventBus,
+    task_id: Uuid,
+    outcome: AppResult<AgentResult>,
+) -> AppResult<()> {
+    let (status, result_data) = match outcome {
+        Ok(result) => ("completed", serde_json::json!(result)),
+        Err(e) => ("failed", serde_json::json!({ "error": format!("{:?}", e) })),
+    };
 
-    sqlx::query(
-        "INSERT INTO agent_tasks (id, project_id, agent_type, status, request_data) VALUES ($1, $2, $3, $4, $5)"
+    let recorded = sqlx::query(
+        "UPDATE agent_tasks SET status = $1, result_data = $2, completed_at = NOW() WHERE id = $3 AND status = 'processing' RETURNING project_id, agent_type"
     )
+    .bind(status)
+    .bind(&result_data)
     .bind(&task_id)
-    .bind(&payload.project_id)
-    .bind("qa")
-    .bind("processing")
-    .bind(serde_json::json!(payload))
-    .execute(db.pool())
+    .fetch_optional(db.pool())
     .await?;
+    telemetry::count_agent_outcome(status, recorded.is_some() as u64);
 
-    let agent = QAAgent::new();
-    tokio::spawn(async move {
-        match agent.execute(&payload.task_description, payload.context).await {
-            Ok(result) => {
-                tracing::info!("QA agent task {} completed", task_id);
-            }
-            Err(e) => {
-                tracing::error!("QA agent task {} failed: {:?}", task_id, e);
-            }
-        }
-    });
+    // A task cancelled while it ran has already finished as far as anyone watching is concerned
+    if let Some(row) = recorded {
+        events.publish(DomainEvent::AgentCompleted {
+            project_id: row.get("project_id"),
+            task_id,
+            agent_type: row.get("agent_type"),
+            status: status.to_string(),
+            result: result_data,
+        })
+        .await;
+    }
 
-    Ok(Json(AgentTaskResponse {
-        task_id,
-        agent_type: "qa".to_string(),
-        status: "processing".to_string(),
-    }))
+    Ok(())
+}
+
+/// Load a task the user started, on a project they can still see; anyone else's task
+/// looks the same as one that doesn't exist
+async fn find_own_task(db: &Database, task_id: Uuid, user_id: Uuid) -> AppResult<Option<PgRow>> {
+    let row = sqlx::query("SELECT project_id, status, result_data FROM agent_tasks WHERE id = $1 AND user_id = $2")
+        .bind(task_id)
+        .bind(user_id)
+        .fetch_optional(db.pool())
+        .await?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+
+    match ensure_project_access(db, row.get("project_id"), user_id).await {
+        Ok(()) => Ok(Some(row)),
+        Err(AppError::NotFoundError(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
 }
 
 pub async fn get_task_status(
     State(db): State<Arc<Database>>,
+    Extension(queue): Extension<Arc<AgentQueue>>,
+    AuthUser(user_id): AuthUser,
     Path(task_id): Path<Uuid>,
 ) -> AppResult<Json<AgentTaskStatus>> {
-    let row = sqlx::query("SELECT id, agent_type, status, result_data FROM agent_tasks WHERE id = $1")
-        .bind(&task_id)
-        .fetch_optional(db.pool())
-        .await?;
+    let row = find_own_task(&db, task_id, user_id).await?;
 
     let row = match row {
         Some(r) => r,
@@ -136,12 +203,14 @@ pub async fn get_task_status(
                 status: "not_found".to_string(),
                 progress: 0.0,
                 result: None,
+                queue_depth: queue.depth(),
             }))
         }
     };
 
     let status: String = row.get("status");
     let progress = match status.as_str() {
+        "queued" => 0.0,
         "processing" => 50.0,
         "completed" => 100.0,
         "failed" => 0.0,
@@ -153,5 +222,295 @@ pub async fn get_task_status(
         status,
         progress,
         result: row.get("result_data"),
+        queue_depth: queue.depth(),
+    }))
+}
+
+/// Cancel a queued or running agent task the user started
+pub async fn cancel_task(
+    State(db): State<Arc<Database>>,
+    Extension(queue): Extension<Arc<AgentQueue>>,
+    AuthUser(user_id): AuthUser,
+    Path(task_id): Path<Uuid>,
+) -> AppResult<Json<AgentTaskStatus>> {
+    let row = find_own_task(&db, task_id, user_id)
+        .await?
+        .ok_or(AppError::NotFoundError("Agent task not found".to_string()))?;
+    ensure_project_permission(&db, row.get("project_id"), user_id, "write").await?;
+
+    let status: String = row.get("status");
+
+    if status != "queued" && status != "processing" {
+        return Err(AppError::ConflictError(format!(
+            "Agent task has already {}",
+            status
+        )));
+    }
+
+    queue.cancel(task_id);
+
+    // The worker may have finished between the check above and the signal
+    let cancelled = sqlx::query(
+        "UPDATE agent_tasks SET status = 'cancelled', completed_at = NOW() WHERE id = $1 AND status IN ('queued', 'processing')"
+    )
+    .bind(task_id)
+    .execute(db.pool())
+    .await?;
+
+    if cancelled.rows_affected() == 0 {
+        return Err(AppError::ConflictError(
+            "Agent task finished before it could be cancelled".to_string(),
+        ));
+    }
+    telemetry::count_agent_outcome("cancelled", 1);
+
+    Ok(Json(AgentTaskStatus {
+        task_id,
+        status: "cancelled".to_string(),
+        progress: 0.0,
+        result: None,
+        queue_depth: queue.depth(),
     }))
 }
+
+/// On shutdown, stop every task this instance is still holding and mark it failed
+/// so clients polling its status don't wait on work that will never finish
+pub async fn interrupt_agent_tasks(db: &Database, queue: &AgentQueue) -> AppResult<u64> {
+    let task_ids = queue.cancel_all();
+    if task_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let interrupted = sqlx::query(
+        "UPDATE agent_tasks SET status = 'failed', result_data = $1, completed_at = NOW() WHERE id = ANY($2) AND status IN ('queued', 'processing')"
+    )
+    .bind(serde_json::json!({ "error": "Interrupted by server shutdown" }))
+    .bind(task_ids)
+    .execute(db.pool())
+    .await?;
+    telemetry::count_agent_outcome("interrupted", interrupted.rows_affected());
+
+    Ok(interrupted.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_backend_agent_result_is_persisted() {
+        let db = crate::db::test_database().await;
+
+        let user_id = crate::db::insert_test_user(db.pool()).await;
+        let project_id = crate::db::insert_test_project(db.pool(), user_id, "agents").await;
+
+        let queue = Arc::new(AgentQueue::new(1));
+        let Json(response) = backend_agent(
+            State(db.clone()),
+            Extension(queue.clone()),
+            Extension(Arc::new(EventBus::new(16))),
+            AuthUser(user_id),
+            ValidatedJson(AgentRequest {
+                project_id,
+                task_description: "Add a health endpoint".to_string(),
+                context: None,
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status, "queued");
+
+        let mut status = None;
+        for _ in 0..50 {
+            let Json(current) =
+                get_task_status(State(db.clone()), Extension(queue.clone()), AuthUser(user_id), Path(response.task_id))
+                    .await
+                    .unwrap();
+            if current.status != "queued" && current.status != "processing" {
+                status = Some(current);
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+
+        let status = status.expect("agent task never finished");
+        assert_eq!(status.status, "completed");
+        assert_eq!(status.progress, 100.0);
+        assert!(status.result.is_some_and(|result| !result.is_null()));
+    }
+
+    struct SlowAgent;
+
+    #[async_trait::async_trait]
+    impl Agent for SlowAgent {
+        async fn execute(&self, _task: &str, _context: Option<String>) -> AppResult<AgentResult> {
+            tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+            unreachable!("slow agent should have been cancelled");
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_cancel_running_agent_task() {
+        let db = crate::db::test_database().await;
+
+        let user_id = crate::db::insert_test_user(db.pool()).await;
+        let project_id = crate::db::insert_test_project(db.pool(), user_id, "agents").await;
+
+        let queue = Arc::new(AgentQueue::new(1));
+        let Json(response) = start_agent_task(
+            db.clone(),
+            &queue,
+            Arc::new(EventBus::new(16)),
+            user_id,
+            AgentRequest {
+                project_id,
+                task_description: "Take forever".to_string(),
+                context: None,
+            },
+            "backend",
+            SlowAgent,
+        )
+        .await
+        .unwrap();
+
+        // Wait until a worker has picked the task up
+        for _ in 0..50 {
+            let Json(current) =
+                get_task_status(State(db.clone()), Extension(queue.clone()), AuthUser(user_id), Path(response.task_id))
+                    .await
+                    .unwrap();
+            if current.status == "processing" {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+
+        let Json(cancelled) =
+            cancel_task(State(db.clone()), Extension(queue.clone()), AuthUser(user_id), Path(response.task_id))
+                .await
+                .unwrap();
+        assert_eq!(cancelled.status, "cancelled");
+
+        let Json(status) =
+            get_task_status(State(db.clone()), Extension(queue.clone()), AuthUser(user_id), Path(response.task_id))
+                .await
+                .unwrap();
+        assert_eq!(status.status, "cancelled");
+
+        assert!(matches!(
+            cancel_task(State(db.clone()), Extension(queue.clone()), AuthUser(user_id), Path(response.task_id)).await,
+            Err(AppError::ConflictError(_))
+        ));
+        assert!(matches!(
+            cancel_task(State(db.clone()), Extension(queue), AuthUser(user_id), Path(Uuid::new_v4())).await,
+            Err(AppError::NotFoundError(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_agent_tasks_are_limited_to_their_owner() {
+        let db = crate::db::test_database().await;
+
+        let user_id = crate::db::insert_test_user(db.pool()).await;
+        let viewer_id = crate::db::insert_test_user(db.pool()).await;
+        let stranger_id = crate::db::insert_test_user(db.pool()).await;
+        let project_id = crate::db::insert_test_project(db.pool(), user_id, "agents").await;
+        sqlx::query(
+            "INSERT INTO project_members (id, project_id, user_id, role, permissions) VALUES ($1, $2, $3, 'viewer', ARRAY['read'])",
+        )
+        .bind(Uuid::new_v4())
+        .bind(project_id)
+        .bind(viewer_id)
+        .execute(db.pool())
+        .await
+        .unwrap();
+
+        let queue = Arc::new(AgentQueue::new(1));
+        let request = || AgentRequest {
+            project_id,
+            task_description: "Take forever".to_string(),
+            context: None,
+        };
+        let events = Arc::new(EventBus::new(16));
+
+        // Starting a task needs write access to the project
+        assert!(matches!(
+            start_agent_task(db.clone(), &queue, events.clone(), viewer_id, request(), "backend", SlowAgent).await,
+            Err(AppError::AuthorizationError(_))
+        ));
+        assert!(matches!(
+            start_agent_task(db.clone(), &queue, events.clone(), stranger_id, request(), "backend", SlowAgent).await,
+            Err(AppError::NotFoundError(_))
+        ));
+
+        let Json(response) = start_agent_task(db.clone(), &queue, events, user_id, request(), "backend", SlowAgent)
+            .await
+            .unwrap();
+
+        // Someone else's task looks like one that doesn't exist
+        for other in [viewer_id, stranger_id] {
+            let Json(status) = get_task_status(State(db.clone()), Extension(queue.clone()), AuthUser(other), Path(response.task_id))
+                .await
+                .unwrap();
+            assert_eq!(status.status, "not_found");
+            assert!(matches!(
+                cancel_task(State(db.clone()), Extension(queue.clone()), AuthUser(other), Path(response.task_id)).await,
+                Err(AppError::NotFoundError(_))
+            ));
+        }
+
+        let Json(cancelled) =
+            cancel_task(State(db.clone()), Extension(queue.clone()), AuthUser(user_id), Path(response.task_id))
+                .await
+                .unwrap();
+        assert_eq!(cancelled.status, "cancelled");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_shutdown_marks_unfinished_tasks_failed() {
+        let db = crate::db::test_database().await;
+
+        let user_id = crate::db::insert_test_user(db.pool()).await;
+        let project_id = crate::db::insert_test_project(db.pool(), user_id, "agents").await;
+
+        // One worker: the first task runs, the second waits behind it
+        let queue = Arc::new(AgentQueue::new(1));
+        let mut task_ids = Vec::new();
+        for description in ["Take forever", "Wait forever"] {
+            let Json(response) = start_agent_task(
+                db.clone(),
+                &queue,
+                Arc::new(EventBus::new(16)),
+                user_id,
+                AgentRequest {
+                    project_id,
+                    task_description: description.to_string(),
+                    context: None,
+                },
+                "backend",
+                SlowAgent,
+            )
+            .await
+            .unwrap();
+            task_ids.push(response.task_id);
+        }
+
+        assert_eq!(interrupt_agent_tasks(&db, &queue).await.unwrap(), 2);
+        assert_eq!(interrupt_agent_tasks(&db, &queue).await.unwrap(), 0);
+
+        for task_id in task_ids {
+            let Json(status) = get_task_status(State(db.clone()), Extension(queue.clone()), AuthUser(user_id), Path(task_id))
+                .await
+                .unwrap();
+            assert_eq!(status.status, "failed");
+            assert_eq!(
+                status.result.unwrap()["error"],
+                "Interrupted by server shutdown"
+            );
+        }
+    }
+}
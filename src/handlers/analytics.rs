@@ -1,28 +1,80 @@
-use axum::{extract::State, Json};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use sqlx::{postgres::PgRow, Row};
 use std::sync::Arc;
 
 use crate::{
     db::Database,
-    error::AppResult,
-    models::{DashboardMetrics, Metric, AnalysisTask},
+    error::{AppError, AppResult},
+    middleware_auth::AuthUser,
+    models::{
+        DashboardMetrics, GenerateReportRequest, Metric, MetricInterval, MetricsQuery, AnalysisTask,
+        TokenUsageSummary,
+    },
+    services::{
+        ai,
+        analytics::{AnalyticsEvent, AnalyticsReport, AnalyticsService},
+    },
+    utils::validation::ValidatedJson,
 };
 use uuid::Uuid;
 
+/// Projects the user owns or is a member of, leaving out the trash; binds the user as $1
+const USER_PROJECT_IDS: &str =
+    "SELECT id FROM projects WHERE deleted_at IS NULL AND (user_id = $1 OR EXISTS (SELECT 1 FROM project_members pm WHERE pm.project_id = projects.id AND pm.user_id = $1))";
+
+/// How many recent analyses the code quality score averages over
+const QUALITY_SCORE_WINDOW: i64 = 20;
+
 pub async fn get_dashboard(
     State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
 ) -> AppResult<Json<DashboardMetrics>> {
-    // Query metrics
-    let total_projects: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM projects")
-        .fetch_one(db.pool())
-        .await?;
+    let total_projects: i64 = sqlx::query_scalar(&format!(
+        "SELECT COUNT(*) FROM ({}) AS visible",
+        USER_PROJECT_IDS
+    ))
+    .bind(user_id)
+    .fetch_one(db.pool())
+    .await?;
 
-    let active_agents: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM agent_tasks WHERE status = 'processing'")
-        .fetch_one(db.pool())
-        .await?;
+    let active_agents: i64 = sqlx::query_scalar(&format!(
+        "SELECT COUNT(*) FROM agent_tasks WHERE status IN ('processing', 'queued') AND project_id IN ({})",
+        USER_PROJECT_IDS
+    ))
+    .bind(user_id)
+    .fetch_one(db.pool())
+    .await?;
 
-    let recent_analyses = sqlx::query("SELECT id, project_id, task_type, status, created_at FROM analysis_tasks ORDER BY created_at DESC LIMIT 5")
-        .fetch_all(db.pool())
-        .await?;
+    // Maintainability of the latest analyses that recorded metrics, 0 until there are any
+    let code_quality_score: Option<f64> = sqlx::query_scalar(&format!(
+        "SELECT AVG(score) FROM (
+             SELECT (output_data->'metrics'->>'maintainability_score')::float8 AS score
+             FROM analysis_tasks
+             WHERE project_id IN ({}) AND output_data->'metrics' ? 'maintainability_score'
+             ORDER BY created_at DESC
+             LIMIT $2
+         ) AS recent",
+        USER_PROJECT_IDS
+    ))
+    .bind(user_id)
+    .bind(QUALITY_SCORE_WINDOW)
+    .fetch_one(db.pool())
+    .await?;
+
+    let recent_analyses = sqlx::query(&format!(
+        "SELECT id, project_id, task_type, status, created_at FROM analysis_tasks
+         WHERE project_id IN ({})
+         ORDER BY created_at DESC LIMIT 5",
+        USER_PROJECT_IDS
+    ))
+    .bind(user_id)
+    .fetch_all(db.pool())
+    .await?;
 
     let analyses: Vec<AnalysisTask> = recent_analyses
         .iter()
@@ -38,17 +90,65 @@ pub async fn get_dashboard(
     Ok(Json(DashboardMetrics {
         total_projects,
         active_agents,
-        code_quality_score: 8.3,
+        code_quality_score: code_quality_score.unwrap_or(0.0),
         recent_analyses: analyses,
     }))
 }
 
+/// Metric values summed per hour or day, one series per metric type, with empty buckets
+/// reported as zero. Defaults to the last 30 days, or the last 24 hours hourly.
 pub async fn get_metrics(
     State(db): State<Arc<Database>>,
+    Query(query): Query<MetricsQuery>,
 ) -> AppResult<Json<Vec<Metric>>> {
+    let interval = MetricInterval::parse(query.interval.as_deref()).ok_or_else(|| {
+        AppError::ValidationError("interval must be 'hour' or 'day'".to_string())
+    })?;
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = query.from.unwrap_or_else(|| match interval {
+        MetricInterval::Hour => to - chrono::Duration::hours(24),
+        MetricInterval::Day => to - chrono::Duration::days(30),
+    });
+
+    if from >= to {
+        return Err(AppError::ValidationError("from must be before to".to_string()));
+    }
+    if to - from > interval.duration() * interval.max_buckets() as i32 {
+        return Err(AppError::ValidationError(format!(
+            "range is limited to {} {}s",
+            interval.max_buckets(),
+            interval.as_str()
+        )));
+    }
+
+    // Buckets are UTC-aligned; every type with data in the range gets every bucket
     let rows = sqlx::query(
-        "SELECT metric_type, value, created_at FROM analytics_metrics ORDER BY created_at DESC LIMIT 100"
+        "WITH buckets AS (
+             SELECT bucket FROM generate_series(
+                 date_trunc($1, $2::timestamptz, 'UTC'), $3::timestamptz, ('1 ' || $1)::interval
+             ) AS bucket
+             WHERE bucket < $3
+         ),
+         types AS (
+             SELECT DISTINCT metric_type FROM analytics_metrics
+             WHERE created_at >= $2 AND created_at < $3 AND ($4::text IS NULL OR metric_type = $4)
+             UNION
+             SELECT $4::text WHERE $4::text IS NOT NULL
+         )
+         SELECT types.metric_type, buckets.bucket, COALESCE(SUM(m.value), 0)::float8 AS value
+         FROM types
+         CROSS JOIN buckets
+         LEFT JOIN analytics_metrics m
+             ON m.metric_type = types.metric_type
+             AND m.created_at >= $2 AND m.created_at < $3
+             AND date_trunc($1, m.created_at, 'UTC') = buckets.bucket
+         GROUP BY types.metric_type, buckets.bucket
+         ORDER BY types.metric_type, buckets.bucket"
     )
+    .bind(interval.as_str())
+    .bind(from)
+    .bind(to)
+    .bind(&query.metric_type)
     .fetch_all(db.pool())
     .await?;
 
@@ -57,23 +157,392 @@ pub async fn get_metrics(
         .map(|row| Metric {
             metric_type: row.get("metric_type"),
             value: row.get::<f64, _>("value"),
-            timestamp: row.get("created_at"),
+            timestamp: row.get("bucket"),
         })
         .collect();
 
     Ok(Json(metrics))
 }
 
+/// Generate a report over the user's analyses, agent tasks and deployments in the window, and keep it
+pub async fn create_report(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(payload): ValidatedJson<GenerateReportRequest>,
+) -> AppResult<(StatusCode, Json<AnalyticsReport>)> {
+    let (from, to) = payload.window();
+
+    let service = user_activity(&db, user_id, from, to).await?;
+    let report = service.generate_report(from, to)?;
+    let id = Uuid::parse_str(&report.report_id)
+        .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+
+    sqlx::query(
+        "INSERT INTO reports (id, user_id, period_start, period_end, metrics, generated_at) VALUES ($1, $2, $3, $4, $5, $6)"
+    )
+    .bind(id)
+    .bind(user_id)
+    .bind(report.period_start)
+    .bind(report.period_end)
+    .bind(serde_json::to_value(&report.metrics).map_err(|e| AppError::InternalServerError(e.to_string()))?)
+    .bind(report.generated_at)
+    .execute(db.pool())
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(report)))
+}
+
+/// Load what the user did in their projects during the window as analytics events
+async fn user_activity(
+    db: &Database,
+    user_id: Uuid,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> AppResult<AnalyticsService> {
+    let rows = sqlx::query(&format!(
+        "SELECT 'code_analysis' AS event_type, project_id, created_at,
+                jsonb_build_object('status', status, 'task_type', task_type) AS metadata
+         FROM analysis_tasks
+         WHERE project_id IN ({projects}) AND created_at >= $2 AND created_at < $3
+         UNION ALL
+         SELECT 'agent_task', project_id, created_at,
+                jsonb_build_object('status', status, 'agent_type', agent_type)
+         FROM agent_tasks
+         WHERE project_id IN ({projects}) AND created_at >= $2 AND created_at < $3
+         UNION ALL
+         SELECT 'deployment', project_id, created_at, jsonb_build_object('status', status)
+         FROM deployments
+         WHERE project_id IN ({projects}) AND created_at >= $2 AND created_at < $3",
+        projects = USER_PROJECT_IDS
+    ))
+    .bind(user_id)
+    .bind(from)
+    .bind(to)
+    .fetch_all(db.pool())
+    .await?;
+
+    let service = AnalyticsService::new();
+    for row in &rows {
+        service.record(AnalyticsEvent {
+            event_type: row.get("event_type"),
+            project_id: row.get::<Uuid, _>("project_id").to_string(),
+            user_id: user_id.to_string(),
+            timestamp: row.get("created_at"),
+            metadata: row.get("metadata"),
+        });
+    }
+    Ok(service)
+}
+
+fn report_from_row(row: &PgRow) -> AppResult<AnalyticsReport> {
+    Ok(AnalyticsReport {
+        report_id: row.get::<Uuid, _>("id").to_string(),
+        generated_at: row.get("generated_at"),
+        period_start: row.get("period_start"),
+        period_end: row.get("period_end"),
+        metrics: serde_json::from_value(row.get("metrics"))
+            .map_err(|e| AppError::InternalServerError(format!("Invalid stored report: {}", e)))?,
+    })
+}
+
+/// The user's reports, newest first
 pub async fn list_reports(
     State(db): State<Arc<Database>>,
-) -> AppResult<Json<Vec<serde_json::Value>>> {
-    let rows = sqlx::query_as::<_, (serde_json::Value,)>(
-        "SELECT metadata FROM analytics_metrics ORDER BY created_at DESC LIMIT 50"
+    AuthUser(user_id): AuthUser,
+) -> AppResult<Json<Vec<AnalyticsReport>>> {
+    let rows = sqlx::query(
+        "SELECT id, period_start, period_end, metrics, generated_at FROM reports
+         WHERE user_id = $1 ORDER BY generated_at DESC LIMIT 50"
     )
+    .bind(user_id)
     .fetch_all(db.pool())
     .await?;
 
-    let reports = rows.into_iter().map(|(metadata,)| metadata).collect();
+    let reports = rows.iter().map(report_from_row).collect::<AppResult<Vec<_>>>()?;
 
     Ok(Json(reports))
 }
+
+/// Another user's report is reported as not found
+pub async fn get_report(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    Path(report_id): Path<Uuid>,
+) -> AppResult<Json<AnalyticsReport>> {
+    let row = sqlx::query(
+        "SELECT id, period_start, period_end, metrics, generated_at FROM reports WHERE id = $1 AND user_id = $2"
+    )
+    .bind(report_id)
+    .bind(user_id)
+    .fetch_optional(db.pool())
+    .await?
+    .ok_or_else(|| AppError::NotFoundError("Report not found".to_string()))?;
+
+    Ok(Json(report_from_row(&row)?))
+}
+
+/// Today's AI token consumption for the current user
+pub async fn get_usage(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+) -> AppResult<Json<TokenUsageSummary>> {
+    let (prompt_tokens, completion_tokens) = ai::tokens_used_today(db.pool(), user_id).await?;
+    let total_tokens = prompt_tokens + completion_tokens;
+    let daily_budget = ai::daily_token_budget();
+
+    Ok(Json(TokenUsageSummary {
+        day: chrono::Utc::now().date_naive(),
+        prompt_tokens,
+        completion_tokens,
+        total_tokens,
+        daily_budget,
+        remaining: (daily_budget - total_tokens).max(0),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::validation::Validate;
+
+    async fn create_agent_task(db: &Database, project_id: Uuid, status: &str) {
+        sqlx::query("INSERT INTO agent_tasks (id, project_id, agent_type, status) VALUES ($1, $2, $3, $4)")
+            .bind(Uuid::new_v4())
+            .bind(project_id)
+            .bind("backend")
+            .bind(status)
+            .execute(db.pool())
+            .await
+            .unwrap();
+    }
+
+    async fn create_analysis(db: &Database, project_id: Uuid, maintainability: Option<f64>, age_minutes: i64) -> Uuid {
+        let id = Uuid::new_v4();
+        let output = maintainability.map(|score| {
+            serde_json::json!({ "suggestions": [], "metrics": { "maintainability_score": score } })
+        });
+        sqlx::query(
+            "INSERT INTO analysis_tasks (id, project_id, task_type, status, output_data, created_at)
+             VALUES ($1, $2, 'optimize', 'completed', $3, NOW() - make_interval(mins => $4))",
+        )
+        .bind(id)
+        .bind(project_id)
+        .bind(output)
+        .bind(age_minutes as i32)
+        .execute(db.pool())
+        .await
+        .unwrap();
+        id
+    }
+
+    async fn create_deployment(db: &Database, project_id: Uuid, user_id: Uuid) {
+        sqlx::query("INSERT INTO deployments (id, project_id, user_id, status, file_count) VALUES ($1, $2, $3, 'deployed', 1)")
+            .bind(Uuid::new_v4())
+            .bind(project_id)
+            .bind(user_id)
+            .execute(db.pool())
+            .await
+            .unwrap();
+    }
+
+    fn at(timestamp: &str) -> chrono::DateTime<Utc> {
+        timestamp.parse().unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_metrics_are_summed_into_daily_buckets() {
+        let db = crate::db::test_database().await;
+        let metric_type = format!("requests-{}", Uuid::new_v4());
+
+        for (created_at, value) in [
+            ("2024-03-01T00:00:00Z", 2.0),
+            ("2024-03-01T23:59:59Z", 3.0),
+            ("2024-03-03T12:00:00Z", 4.5),
+            ("2024-03-05T00:00:00Z", 100.0),
+        ] {
+            sqlx::query("INSERT INTO analytics_metrics (id, metric_type, value, created_at) VALUES ($1, $2, $3, $4)")
+                .bind(Uuid::new_v4())
+                .bind(&metric_type)
+                .bind(value)
+                .bind(at(created_at))
+                .execute(db.pool())
+                .await
+                .unwrap();
+        }
+
+        let query = MetricsQuery {
+            metric_type: Some(metric_type.clone()),
+            from: Some(at("2024-03-01T00:00:00Z")),
+            to: Some(at("2024-03-04T00:00:00Z")),
+            interval: Some("day".to_string()),
+        };
+        let Json(metrics) = get_metrics(State(db.clone()), Query(query)).await.unwrap();
+        let buckets: Vec<(chrono::DateTime<Utc>, f64)> = metrics.iter().map(|m| (m.timestamp, m.value)).collect();
+        assert_eq!(
+            buckets,
+            vec![
+                (at("2024-03-01T00:00:00Z"), 5.0),
+                (at("2024-03-02T00:00:00Z"), 0.0),
+                (at("2024-03-03T00:00:00Z"), 4.5),
+            ]
+        );
+        assert!(metrics.iter().all(|m| m.metric_type == metric_type));
+
+        // Hourly over the first day: the two values land in the first and last hour
+        let query = MetricsQuery {
+            metric_type: Some(metric_type.clone()),
+            from: Some(at("2024-03-01T00:00:00Z")),
+            to: Some(at("2024-03-02T00:00:00Z")),
+            interval: Some("hour".to_string()),
+        };
+        let Json(metrics) = get_metrics(State(db.clone()), Query(query)).await.unwrap();
+        assert_eq!(metrics.len(), 24);
+        assert_eq!((metrics[0].value, metrics[23].value), (2.0, 3.0));
+        assert!(metrics[1..23].iter().all(|m| m.value == 0.0));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_metrics_query_is_validated() {
+        let db = crate::db::test_database().await;
+        let query = |interval: &str, from: &str, to: &str| MetricsQuery {
+            metric_type: None,
+            from: Some(at(from)),
+            to: Some(at(to)),
+            interval: Some(interval.to_string()),
+        };
+
+        for invalid in [
+            query("week", "2024-03-01T00:00:00Z", "2024-03-02T00:00:00Z"),
+            query("day", "2024-03-02T00:00:00Z", "2024-03-01T00:00:00Z"),
+            query("hour", "2024-03-01T00:00:00Z", "2024-03-09T00:00:00Z"),
+            query("day", "2023-01-01T00:00:00Z", "2024-03-01T00:00:00Z"),
+        ] {
+            let result = get_metrics(State(db.clone()), Query(invalid)).await;
+            assert!(matches!(result, Err(AppError::ValidationError(_))));
+        }
+        assert!(get_metrics(State(db.clone()), Query(query("hour", "2024-03-01T00:00:00Z", "2024-03-08T00:00:00Z")))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_generated_report_can_be_read_back() {
+        let db = crate::db::test_database().await;
+        let user = crate::db::insert_test_user(db.pool()).await;
+        let stranger = crate::db::insert_test_user(db.pool()).await;
+        let project = crate::db::insert_test_project(db.pool(), user, "measured").await;
+
+        create_agent_task(&db, project, "completed").await;
+        create_agent_task(&db, project, "failed").await;
+        create_analysis(&db, project, Some(8.0), 5).await;
+        create_analysis(&db, project, Some(6.0), 60 * 24 * 10).await;
+        let elsewhere = crate::db::insert_test_project(db.pool(), stranger, "measured").await;
+        create_agent_task(&db, elsewhere, "completed").await;
+
+        // Deployments count by project, whoever made them
+        let collaborator = crate::db::insert_test_user(db.pool()).await;
+        sqlx::query("INSERT INTO project_members (id, project_id, user_id, role) VALUES ($1, $2, $3, 'member')")
+            .bind(Uuid::new_v4())
+            .bind(project)
+            .bind(collaborator)
+            .execute(db.pool())
+            .await
+            .unwrap();
+        create_deployment(&db, project, collaborator).await;
+        create_deployment(&db, elsewhere, user).await;
+
+        let request = GenerateReportRequest { from: None, to: None };
+        let (status, Json(created)) =
+            create_report(State(db.clone()), AuthUser(user), ValidatedJson(request)).await.unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(created.metrics.total_requests, 4);
+        assert_eq!(created.metrics.total_code_analyzed, 1);
+        assert!((created.metrics.success_rate - 75.0).abs() < 1e-9);
+        assert_eq!(created.metrics.active_agents, 1);
+
+        let id: Uuid = created.report_id.parse().unwrap();
+        let Json(fetched) = get_report(State(db.clone()), AuthUser(user), Path(id)).await.unwrap();
+        assert_eq!(fetched.report_id, created.report_id);
+        assert_eq!(fetched.metrics.total_requests, 4);
+        assert_eq!(fetched.period_end - fetched.period_start, chrono::Duration::days(7));
+
+        let Json(listed) = list_reports(State(db.clone()), AuthUser(user)).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].report_id, created.report_id);
+
+        // Reports belong to whoever generated them
+        let result = get_report(State(db.clone()), AuthUser(stranger), Path(id)).await;
+        assert!(matches!(result, Err(AppError::NotFoundError(_))));
+        let Json(listed) = list_reports(State(db.clone()), AuthUser(stranger)).await.unwrap();
+        assert!(listed.is_empty());
+
+        let backwards = GenerateReportRequest {
+            from: Some(at("2024-03-02T00:00:00Z")),
+            to: Some(at("2024-03-01T00:00:00Z")),
+        };
+        assert!(matches!(backwards.validate(), Err(AppError::ValidationError(_))));
+        let too_long = GenerateReportRequest {
+            from: Some(at("2023-01-01T00:00:00Z")),
+            to: Some(at("2024-03-01T00:00:00Z")),
+        };
+        assert!(matches!(too_long.validate(), Err(AppError::ValidationError(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_dashboard_aggregates_only_the_users_projects() {
+        let db = crate::db::test_database().await;
+        let user = crate::db::insert_test_user(db.pool()).await;
+        let stranger = crate::db::insert_test_user(db.pool()).await;
+
+        let owned = crate::db::insert_test_project(db.pool(), user, "measured").await;
+        let shared = crate::db::insert_test_project(db.pool(), stranger, "measured").await;
+        sqlx::query("INSERT INTO project_members (id, project_id, user_id, role) VALUES ($1, $2, $3, 'member')")
+            .bind(Uuid::new_v4())
+            .bind(shared)
+            .bind(user)
+            .execute(db.pool())
+            .await
+            .unwrap();
+        let trashed = crate::db::insert_test_project(db.pool(), user, "measured").await;
+        sqlx::query("UPDATE projects SET deleted_at = NOW() WHERE id = $1")
+            .bind(trashed)
+            .execute(db.pool())
+            .await
+            .unwrap();
+        let elsewhere = crate::db::insert_test_project(db.pool(), stranger, "measured").await;
+
+        create_agent_task(&db, owned, "processing").await;
+        create_agent_task(&db, shared, "queued").await;
+        create_agent_task(&db, owned, "completed").await;
+        create_agent_task(&db, trashed, "processing").await;
+        create_agent_task(&db, elsewhere, "processing").await;
+
+        let mut newest_first = Vec::new();
+        for (age, score) in [(1, Some(6.0)), (2, None), (3, Some(9.0)), (4, Some(7.5)), (5, Some(4.5)), (6, Some(8.0))] {
+            let project = if age % 2 == 0 { shared } else { owned };
+            newest_first.push(create_analysis(&db, project, score, age).await);
+        }
+        create_analysis(&db, elsewhere, Some(1.0), 0).await;
+        create_analysis(&db, trashed, Some(1.0), 0).await;
+
+        let Json(dashboard) = get_dashboard(State(db.clone()), AuthUser(user)).await.unwrap();
+        assert_eq!(dashboard.total_projects, 2);
+        assert_eq!(dashboard.active_agents, 2);
+        assert!((dashboard.code_quality_score - 7.0).abs() < 1e-9);
+        let recent: Vec<Uuid> = dashboard.recent_analyses.iter().map(|a| a.id).collect();
+        assert_eq!(recent, newest_first[..5]);
+
+        // Nothing analyzed yet scores 0 rather than failing
+        let newcomer = crate::db::insert_test_user(db.pool()).await;
+        crate::db::insert_test_project(db.pool(), newcomer, "measured").await;
+        let Json(dashboard) = get_dashboard(State(db.clone()), AuthUser(newcomer)).await.unwrap();
+        assert_eq!(dashboard.total_projects, 1);
+        assert_eq!(dashboard.active_agents, 0);
+        assert_eq!(dashboard.code_quality_score, 0.0);
+        assert!(dashboard.recent_analyses.is_empty());
+    }
+}
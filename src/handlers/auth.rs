@@ -1,136 +1,1664 @@
-use axum::{extract::State, Json};
+use axum::{
+    extract::{Query, State},
+    http::{header::AUTHORIZATION, HeaderMap},
+    response::Redirect,
+    Extension, Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use sqlx::Row;
 use std::sync::Arc;
 use uuid::Uuid;
 
 use crate::{
     db::Database,
     error::{AppError, AppResult},
-    models::{AuthResponse, LoginRequest, RegisterRequest, User},
-    utils::jwt,
+    middleware_auth::AuthUser,
+    models::{
+        AuthResponse, ForgotPasswordRequest, LoginRequest, LoginResponse, OAuthCallbackQuery,
+        PendingVerification, RegisterRequest, RegisterResponse, ResetPasswordRequest,
+        TwoFactorChallenge, TwoFactorChallengeRequest, TwoFactorEnabled, TwoFactorEnrollment,
+        TwoFactorVerifyRequest, User, VerifyEmailQuery,
+    },
+    services::{
+        events::{DomainEvent, EventBus},
+        oauth::{GithubOAuth, GithubProfile},
+    },
+    utils::{crypto, jwt, totp, validation::ValidatedJson},
 };
 
-pub async fn register(
-    State(db): State<Arc<Database>>,
-    Json(payload): Json<RegisterRequest>,
-) -> AppResult<Json<AuthResponse>> {
-    // Validate email
-    if !payload.email.contains('@') {
-        return Err(AppError::ValidationError("Invalid email format".to_string()));
+const RESET_TOKEN_TTL_SECS: i64 = 3600;
+const VERIFICATION_TOKEN_TTL_SECS: i64 = 86400;
+const REFRESH_TOKEN_TTL_SECS: i64 = 86400 * 7;
+const OAUTH_STATE_TTL_SECS: i64 = 600;
+const TWO_FACTOR_CHALLENGE_TTL_SECS: i64 = 300;
+/// Wrong codes a challenge survives before the password has to be entered again
+const TWO_FACTOR_MAX_ATTEMPTS: i32 = 5;
+const DEFAULT_MAX_FAILED_LOGINS: i32 = 5;
+const DEFAULT_LOCKOUT_SECS: i64 = 900;
+
+/// How many consecutive failed logins lock an email, and for how long
+#[derive(Debug, Clone, Copy)]
+struct LockoutPolicy {
+    max_failures: i32,
+    window: Duration,
+}
+
+impl LockoutPolicy {
+    fn from_env() -> Self {
+        let max_failures = std::env::var("LOGIN_MAX_FAILED_ATTEMPTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_FAILED_LOGINS);
+        let lockout_secs = std::env::var("LOGIN_LOCKOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_LOCKOUT_SECS);
+
+        Self {
+            max_failures: max_failures.max(1),
+            window: Duration::seconds(lockout_secs),
+        }
+    }
+
+    /// When the account should unlock after reaching `failed_count` failures, if it locks at all
+    fn lock_until(&self, failed_count: i32, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        (failed_count >= self.max_failures).then(|| now + self.window)
     }
+}
 
+pub async fn register(
+    State(db): State<Arc<Database>>,
+    Extension(events): Extension<Arc<EventBus>>,
+    ValidatedJson(payload): ValidatedJson<RegisterRequest>,
+) -> AppResult<Json<RegisterResponse>> {
     // Hash password
     let password_hash = bcrypt::hash(&payload.password, 12)
         .map_err(|_| AppError::InternalServerError("Failed to hash password".to_string()))?;
 
+    // Stored lowercased, the way logins and password resets look it up
+    let email = payload.email.trim().to_lowercase();
+
     // Insert user into database
     let user_id = Uuid::new_v4();
     sqlx::query(
         "INSERT INTO users (id, email, password_hash, first_name, last_name) VALUES ($1, $2, $3, $4, $5)"
     )
     .bind(&user_id)
-    .bind(&payload.email)
+    .bind(&email)
     .bind(&password_hash)
     .bind(&payload.first_name)
     .bind(&payload.last_name)
     .execute(db.pool())
+    .await
+    .map_err(|e| match e.as_database_error() {
+        Some(db_error) if db_error.is_unique_violation() => {
+            AppError::ConflictError("An account with this email already exists".to_string())
+        }
+        _ => e.into(),
+    })?;
+
+    // Issue an email verification token; only its hash is stored, the token itself goes out by email
+    let verification_token = crypto::generate_secure_token();
+    let expires_at = Utc::now() + Duration::seconds(VERIFICATION_TOKEN_TTL_SECS);
+    sqlx::query(
+        "INSERT INTO email_verification_tokens (token_hash, user_id, expires_at) VALUES ($1, $2, $3)"
+    )
+    .bind(crypto::hash_token(&verification_token))
+    .bind(user_id)
+    .bind(expires_at)
+    .execute(db.pool())
     .await?;
 
-    // Generate tokens
-    let access_token = jwt::generate_token(&user_id.to_string(), 3600)?;
-    let refresh_token = jwt::generate_token(&user_id.to_string(), 86400 * 7)?;
+    events.publish(DomainEvent::EmailVerificationRequested {
+        user_id,
+        email: email.clone(),
+        expires_at,
+        token: verification_token,
+    })
+    .await;
 
     let user = User {
         id: user_id,
-        email: payload.email,
+        email,
         first_name: payload.first_name,
         last_name: payload.last_name,
+        email_verified: false,
         created_at: chrono::Utc::now(),
     };
 
-    Ok(Json(AuthResponse {
-        access_token,
-        refresh_token,
-        user,
-    }))
+    // Outside development the account gets no tokens until its email is verified
+    if requires_email_verification() {
+        return Ok(Json(RegisterResponse::VerificationRequired(PendingVerification {
+            status: "verification_required".to_string(),
+            user,
+        })));
+    }
+
+    Ok(Json(RegisterResponse::Authenticated(issue_session(&db, user).await?)))
 }
 
 pub async fn login(
     State(db): State<Arc<Database>>,
-    Json(payload): Json<LoginRequest>,
-) -> AppResult<Json<AuthResponse>> {
-    // Query user from database
-    let row = sqlx::query("SELECT id, email, password_hash, first_name, last_name, created_at FROM users WHERE email = $1")
-        .bind(&payload.email)
-        .fetch_optional(db.pool())
-        .await?;
+    ValidatedJson(payload): ValidatedJson<LoginRequest>,
+) -> AppResult<Json<LoginResponse>> {
+    let policy = LockoutPolicy::from_env();
+    let attempt_key = payload.email.trim().to_lowercase();
+
+    // Locked accounts get the same answer as a wrong password, so lockout can't confirm an account exists
+    if is_locked_out(&db, &attempt_key).await? {
+        return Err(invalid_credentials());
+    }
 
-    let row = row.ok_or(AppError::AuthenticationError("Invalid credentials".to_string()))?;
+    // Matched the way attempts are counted, so a differently cased email can't dodge the lock
+    let row = sqlx::query(
+        "SELECT id, email, password_hash, first_name, last_name, email_verified, created_at FROM users WHERE LOWER(email) = $1"
+    )
+    .bind(&attempt_key)
+    .fetch_optional(db.pool())
+    .await?;
+
+    let Some(row) = row else {
+        record_failed_login(&db, &attempt_key, &policy).await?;
+        return Err(invalid_credentials());
+    };
 
     let user_id: Uuid = row.get("id");
-    let stored_hash: String = row.get("password_hash");
+    // Accounts created through OAuth have no password until one is set with a reset
+    let stored_hash: Option<String> = row.get("password_hash");
+    let password_ok = match stored_hash {
+        Some(stored_hash) => bcrypt::verify(&payload.password, &stored_hash)
+            .map_err(|_| AppError::InternalServerError("Password verification failed".to_string()))?,
+        None => false,
+    };
 
     // Verify password
-    if !bcrypt::verify(&payload.password, &stored_hash)
-        .map_err(|_| AppError::InternalServerError("Password verification failed".to_string()))?
-    {
-        return Err(AppError::AuthenticationError("Invalid credentials".to_string()));
+    if !password_ok {
+        record_failed_login(&db, &attempt_key, &policy).await?;
+        return Err(invalid_credentials());
     }
 
-    // Generate tokens
-    let access_token = jwt::generate_token(&user_id.to_string(), 3600)?;
-    let refresh_token = jwt::generate_token(&user_id.to_string(), 86400 * 7)?;
+    sqlx::query("DELETE FROM login_attempts WHERE email = $1")
+        .bind(&attempt_key)
+        .execute(db.pool())
+        .await?;
+
+    let email_verified: bool = row.get("email_verified");
+    if !email_verified && requires_email_verification() {
+        return Err(AppError::AuthorizationError("Email address has not been verified".to_string()));
+    }
 
     let user = User {
         id: user_id,
         email: row.get("email"),
         first_name: row.get("first_name"),
         last_name: row.get("last_name"),
+        email_verified,
         created_at: row.get("created_at"),
     };
 
-    Ok(Json(AuthResponse {
+    Ok(Json(sign_in(&db, user).await?))
+}
+
+/// Tokens for `user`, or a challenge in their place when the account has two-factor enabled
+async fn sign_in(db: &Database, user: User) -> AppResult<LoginResponse> {
+    let two_factor_enabled: Option<bool> =
+        sqlx::query_scalar("SELECT enabled_at IS NOT NULL FROM user_two_factor WHERE user_id = $1")
+            .bind(user.id)
+            .fetch_optional(db.pool())
+            .await?;
+
+    if two_factor_enabled != Some(true) {
+        return Ok(LoginResponse::Authenticated(issue_session(db, user).await?));
+    }
+
+    let challenge_token = crypto::generate_secure_token();
+    sqlx::query("INSERT INTO two_factor_challenges (token_hash, user_id, expires_at) VALUES ($1, $2, $3)")
+        .bind(crypto::hash_token(&challenge_token))
+        .bind(user.id)
+        .bind(Utc::now() + Duration::seconds(TWO_FACTOR_CHALLENGE_TTL_SECS))
+        .execute(db.pool())
+        .await?;
+
+    Ok(LoginResponse::TwoFactorRequired(TwoFactorChallenge {
+        status: "2fa_required".to_string(),
+        challenge_token,
+        expires_in: TWO_FACTOR_CHALLENGE_TTL_SECS,
+    }))
+}
+
+/// Access and refresh tokens for `user`; each login starts a new refresh token family
+async fn issue_session(db: &Database, user: User) -> AppResult<AuthResponse> {
+    let (access_token, expires_in) = jwt::generate_access_token(&user.id.to_string())?;
+    let refresh_token = issue_refresh_token(db.pool(), user.id, Uuid::new_v4()).await?;
+
+    Ok(AuthResponse {
         access_token,
+        expires_in,
         refresh_token,
         user,
-    }))
+    })
 }
 
 pub async fn refresh_token(
     State(db): State<Arc<Database>>,
-    Json(payload): Json<crate::models::TokenRefreshRequest>,
+    ValidatedJson(payload): ValidatedJson<crate::models::TokenRefreshRequest>,
 ) -> AppResult<Json<AuthResponse>> {
-    // Verify refresh token
-    let claims = jwt::verify_token(&payload.refresh_token)?;
+    // Verify refresh token; access tokens can't be exchanged for a new session
+    if jwt::verify_token(&payload.refresh_token)?.typ != jwt::TokenType::Refresh {
+        return Err(AppError::AuthenticationError("Invalid refresh token".to_string()));
+    }
 
-    // Fetch user from database
-    let row = sqlx::query("SELECT id, email, first_name, last_name, created_at FROM users WHERE id = $1")
-        .bind(&claims.sub)
+    let token_hash = crypto::hash_token(&payload.refresh_token);
+    let row = sqlx::query("SELECT user_id, family_id, rotated_at, revoked FROM refresh_tokens WHERE token_hash = $1")
+        .bind(&token_hash)
         .fetch_optional(db.pool())
         .await?;
 
+    let row = row.ok_or(AppError::AuthenticationError("Invalid refresh token".to_string()))?;
+    let user_id: Uuid = row.get("user_id");
+    let family_id: Uuid = row.get("family_id");
+    let rotated_at: Option<DateTime<Utc>> = row.get("rotated_at");
+
+    if row.get::<bool, _>("revoked") {
+        return Err(AppError::AuthenticationError("Refresh token has been revoked".to_string()));
+    }
+    if rotated_at.is_some() {
+        return Err(revoke_refresh_family(&db, user_id, family_id).await);
+    }
+
+    let mut tx = db.pool().begin().await?;
+
+    // Claim the token atomically; losing the race means it was presented twice
+    let claimed = sqlx::query(
+        "UPDATE refresh_tokens SET rotated_at = NOW() WHERE token_hash = $1 AND rotated_at IS NULL AND NOT revoked"
+    )
+    .bind(&token_hash)
+    .execute(&mut *tx)
+    .await?;
+    if claimed.rows_affected() == 0 {
+        tx.rollback().await?;
+        return Err(revoke_refresh_family(&db, user_id, family_id).await);
+    }
+
+    let new_refresh_token = issue_refresh_token(&mut *tx, user_id, family_id).await?;
+
+    // Fetch user from database
+    let row = sqlx::query("SELECT id, email, first_name, last_name, email_verified, created_at FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
     let row = row.ok_or(AppError::AuthenticationError("User not found".to_string()))?;
 
-    let user_id: Uuid = row.get("id");
+    // Sessions handed out before verification was required don't outlive it
+    if !row.get::<bool, _>("email_verified") && requires_email_verification() {
+        return Err(AppError::AuthorizationError("Email address has not been verified".to_string()));
+    }
+
+    tx.commit().await?;
 
-    // Generate new tokens
-    let access_token = jwt::generate_token(&user_id.to_string(), 3600)?;
-    let new_refresh_token = jwt::generate_token(&user_id.to_string(), 86400 * 7)?;
+    // Generate new access token
+    let (access_token, expires_in) = jwt::generate_access_token(&user_id.to_string())?;
 
     let user = User {
         id: user_id,
         email: row.get("email"),
         first_name: row.get("first_name"),
         last_name: row.get("last_name"),
+        email_verified: row.get("email_verified"),
         created_at: row.get("created_at"),
     };
 
     Ok(Json(AuthResponse {
         access_token,
+        expires_in,
         refresh_token: new_refresh_token,
         user,
     }))
 }
 
-pub async fn logout() -> &'static str {
-    "Logged out successfully"
+/// Start a GitHub login: remember a one-time `state` and send the browser to GitHub
+pub async fn github_login(
+    State(db): State<Arc<Database>>,
+    Extension(github): Extension<Option<Arc<GithubOAuth>>>,
+) -> AppResult<Redirect> {
+    let github = github.ok_or_else(github_not_configured)?;
+
+    let state = crypto::generate_secure_token();
+    sqlx::query("INSERT INTO oauth_states (state, provider, expires_at) VALUES ($1, 'github', $2)")
+        .bind(&state)
+        .bind(Utc::now() + Duration::seconds(OAUTH_STATE_TTL_SECS))
+        .execute(db.pool())
+        .await?;
+
+    Ok(Redirect::to(&github.authorize_url(&state)?))
+}
+
+/// GitHub sends the browser back here. Signs in the user linked to the GitHub account,
+/// or the one with the same (GitHub-verified) email, creating the account if neither exists.
+pub async fn github_callback(
+    State(db): State<Arc<Database>>,
+    Extension(github): Extension<Option<Arc<GithubOAuth>>>,
+    Query(query): Query<OAuthCallbackQuery>,
+) -> AppResult<Json<LoginResponse>> {
+    let github = github.ok_or_else(github_not_configured)?;
+
+    // Consumed whether or not it is still valid, so a state can only ever be tried once
+    let state_valid: Option<bool> = sqlx::query_scalar(
+        "DELETE FROM oauth_states WHERE state = $1 AND provider = 'github' RETURNING expires_at > NOW()"
+    )
+    .bind(&query.state)
+    .fetch_optional(db.pool())
+    .await?;
+    if state_valid != Some(true) {
+        return Err(AppError::AuthenticationError("Invalid or expired OAuth state".to_string()));
+    }
+
+    let code = match (query.code, query.error) {
+        (Some(code), None) => code,
+        (_, error) => {
+            tracing::info!("GitHub login was not authorized: {}", error.unwrap_or_default());
+            return Err(AppError::AuthenticationError("GitHub login was not authorized".to_string()));
+        }
+    };
+
+    let access_token = github.exchange_code(&code).await?;
+    let profile = github.fetch_profile(&access_token).await?;
+    let user = link_github_identity(&db, &profile).await?;
+
+    // GitHub stands in for the password, not the second factor
+    Ok(Json(sign_in(&db, user).await?))
+}
+
+/// The user the GitHub account signs in as, linking or creating it on first login
+async fn link_github_identity(db: &Database, profile: &GithubProfile) -> AppResult<User> {
+    let github_id = profile.id.to_string();
+    let mut tx = db.pool().begin().await?;
+
+    let linked: Option<Uuid> = sqlx::query_scalar(
+        "SELECT user_id FROM user_identities WHERE provider = 'github' AND provider_user_id = $1"
+    )
+    .bind(&github_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let user_id = match linked {
+        Some(user_id) => user_id,
+        None => {
+            let email = profile.verified_email.as_deref().ok_or_else(|| {
+                AppError::AuthenticationError("GitHub account has no verified email address".to_string())
+            })?;
+
+            let existing: Option<Uuid> = sqlx::query_scalar(
+                "SELECT id FROM users WHERE LOWER(email) = LOWER($1)"
+            )
+            .bind(email)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            match existing {
+                // GitHub has proven the email is theirs. A password set before anyone had
+                // verified the address may belong to someone else, so it stops working.
+                Some(user_id) => {
+                    sqlx::query(
+                        r#"
+                        UPDATE users SET
+                            password_hash = CASE WHEN email_verified THEN password_hash END,
+                            email_verified = TRUE,
+                            updated_at = NOW()
+                        WHERE id = $1
+                        "#,
+                    )
+                    .bind(user_id)
+                    .execute(&mut *tx)
+                    .await?;
+                    user_id
+                }
+                None => {
+                    let user_id = Uuid::new_v4();
+                    let (first_name, last_name) = split_name(profile.name.as_deref());
+                    sqlx::query(
+                        r#"
+                        INSERT INTO users (id, email, first_name, last_name, email_verified)
+                        VALUES ($1, $2, $3, $4, TRUE)
+                        "#,
+                    )
+                    .bind(user_id)
+                    .bind(email.to_lowercase())
+                    .bind(first_name)
+                    .bind(last_name)
+                    .execute(&mut *tx)
+                    .await?;
+                    tracing::info!("Created user {} from GitHub account {}", user_id, profile.login);
+                    user_id
+                }
+            }
+        }
+    };
+
+    sqlx::query(
+        r#"
+        INSERT INTO user_identities (id, user_id, provider, provider_user_id, email)
+        VALUES ($1, $2, 'github', $3, $4)
+        ON CONFLICT (provider, provider_user_id) DO UPDATE SET
+            email = EXCLUDED.email,
+            last_login_at = NOW()
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(&github_id)
+    .bind(&profile.verified_email)
+    .execute(&mut *tx)
+    .await?;
+
+    let user = load_user(&mut *tx, user_id).await?;
+    tx.commit().await?;
+
+    Ok(user)
+}
+
+async fn load_user<'e, E>(executor: E, user_id: Uuid) -> AppResult<User>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    let row = sqlx::query("SELECT id, email, first_name, last_name, email_verified, created_at FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(executor)
+        .await?
+        .ok_or_else(|| AppError::AuthenticationError("User not found".to_string()))?;
+
+    Ok(User {
+        id: row.get("id"),
+        email: row.get("email"),
+        first_name: row.get("first_name"),
+        last_name: row.get("last_name"),
+        email_verified: row.get("email_verified"),
+        created_at: row.get("created_at"),
+    })
+}
+
+fn github_not_configured() -> AppError {
+    AppError::NotFoundError("GitHub login is not configured".to_string())
+}
+
+/// GitHub has a single display name; the first word becomes the first name
+fn split_name(name: Option<&str>) -> (Option<String>, Option<String>) {
+    let Some(name) = name.map(str::trim).filter(|name| !name.is_empty()) else {
+        return (None, None);
+    };
+    match name.split_once(char::is_whitespace) {
+        Some((first, last)) => (Some(first.to_string()), Some(last.trim().to_string())),
+        None => (Some(name.to_string()), None),
+    }
+}
+
+/// Start (or restart) two-factor enrollment with a new secret. It takes effect once
+/// `POST /auth/2fa/verify` confirms a code generated from it.
+pub async fn enroll_two_factor(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+) -> AppResult<Json<TwoFactorEnrollment>> {
+    let email: String = sqlx::query_scalar("SELECT email FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(db.pool())
+        .await?
+        .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?;
+
+    let secret = totp::generate_secret();
+    let enrolled = sqlx::query(
+        r#"
+        INSERT INTO user_two_factor (user_id, secret_encrypted) VALUES ($1, $2)
+        ON CONFLICT (user_id) DO UPDATE SET
+            secret_encrypted = EXCLUDED.secret_encrypted,
+            last_used_step = NULL,
+            created_at = NOW()
+        WHERE user_two_factor.enabled_at IS NULL
+        "#,
+    )
+    .bind(user_id)
+    .bind(totp::encrypt_secret(&secret)?)
+    .execute(db.pool())
+    .await?;
+    if enrolled.rows_affected() == 0 {
+        return Err(AppError::ConflictError("Two-factor authentication is already enabled".to_string()));
+    }
+
+    Ok(Json(TwoFactorEnrollment {
+        secret: totp::encode_secret(&secret),
+        otpauth_uri: totp::otpauth_uri(&secret, &email)?,
+    }))
+}
+
+/// Confirm enrollment with a code from the authenticator, turning two-factor on and
+/// returning a fresh set of recovery codes
+pub async fn verify_two_factor(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(payload): ValidatedJson<TwoFactorVerifyRequest>,
+) -> AppResult<Json<TwoFactorEnabled>> {
+    let row = sqlx::query("SELECT secret_encrypted, enabled_at FROM user_two_factor WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_optional(db.pool())
+        .await?
+        .ok_or_else(|| AppError::ValidationError("Two-factor enrollment has not been started".to_string()))?;
+    if row.get::<Option<DateTime<Utc>>, _>("enabled_at").is_some() {
+        return Err(AppError::ConflictError("Two-factor authentication is already enabled".to_string()));
+    }
+
+    let secret = totp::decrypt_secret(row.get("secret_encrypted"))?;
+    let step = totp::verify_code(&secret, &payload.code, Utc::now(), None)
+        .ok_or_else(|| AppError::ValidationError("Invalid two-factor code".to_string()))?;
+
+    let recovery_codes = totp::generate_recovery_codes();
+    let mut tx = db.pool().begin().await?;
+
+    let enabled = sqlx::query(
+        "UPDATE user_two_factor SET enabled_at = NOW(), last_used_step = $2 WHERE user_id = $1 AND enabled_at IS NULL"
+    )
+    .bind(user_id)
+    .bind(step)
+    .execute(&mut *tx)
+    .await?;
+    if enabled.rows_affected() == 0 {
+        return Err(AppError::ConflictError("Two-factor authentication is already enabled".to_string()));
+    }
+
+    sqlx::query("DELETE FROM two_factor_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    for code in &recovery_codes {
+        sqlx::query("INSERT INTO two_factor_recovery_codes (user_id, code_hash) VALUES ($1, $2)")
+            .bind(user_id)
+            .bind(crypto::hash_token(&totp::normalize_recovery_code(code)))
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+    tracing::info!("Two-factor authentication enabled for user {}", user_id);
+
+    Ok(Json(TwoFactorEnabled { recovery_codes }))
+}
+
+/// Finish a login that answered with `2fa_required`, trading the challenge token and
+/// an authenticator or recovery code for the usual tokens
+pub async fn two_factor_challenge(
+    State(db): State<Arc<Database>>,
+    ValidatedJson(payload): ValidatedJson<TwoFactorChallengeRequest>,
+) -> AppResult<Json<AuthResponse>> {
+    let token_hash = crypto::hash_token(&payload.challenge_token);
+    let user_id: Uuid = sqlx::query_scalar(
+        "SELECT user_id FROM two_factor_challenges WHERE token_hash = $1 AND expires_at > NOW()"
+    )
+    .bind(&token_hash)
+    .fetch_optional(db.pool())
+    .await?
+    .ok_or_else(|| AppError::AuthenticationError("Invalid or expired two-factor challenge".to_string()))?;
+
+    if !accept_second_factor(&db, user_id, &payload.code).await? {
+        let attempts: Option<i32> = sqlx::query_scalar(
+            "UPDATE two_factor_challenges SET failed_attempts = failed_attempts + 1 WHERE token_hash = $1 RETURNING failed_attempts"
+        )
+        .bind(&token_hash)
+        .fetch_optional(db.pool())
+        .await?;
+        if attempts.is_some_and(|attempts| attempts >= TWO_FACTOR_MAX_ATTEMPTS) {
+            sqlx::query("DELETE FROM two_factor_challenges WHERE token_hash = $1")
+                .bind(&token_hash)
+                .execute(db.pool())
+                .await?;
+        }
+        return Err(invalid_two_factor_code());
+    }
+
+    // Claim the challenge so it can't be finished twice
+    let claimed = sqlx::query("DELETE FROM two_factor_challenges WHERE token_hash = $1")
+        .bind(&token_hash)
+        .execute(db.pool())
+        .await?;
+    if claimed.rows_affected() == 0 {
+        return Err(AppError::AuthenticationError("Invalid or expired two-factor challenge".to_string()));
+    }
+
+    let user = load_user(db.pool(), user_id).await?;
+    Ok(Json(issue_session(&db, user).await?))
+}
+
+/// Check an authenticator code (not used before) or an unused recovery code, using it up
+async fn accept_second_factor(db: &Database, user_id: Uuid, code: &str) -> AppResult<bool> {
+    let row = sqlx::query(
+        "SELECT secret_encrypted, last_used_step FROM user_two_factor WHERE user_id = $1 AND enabled_at IS NOT NULL"
+    )
+    .bind(user_id)
+    .fetch_optional(db.pool())
+    .await?
+    .ok_or_else(invalid_two_factor_code)?;
+
+    let secret = totp::decrypt_secret(row.get("secret_encrypted"))?;
+    if let Some(step) = totp::verify_code(&secret, code, Utc::now(), row.get("last_used_step")) {
+        // Moving last_used_step forward is what makes the code single-use, even under races
+        let accepted = sqlx::query(
+            r#"
+            UPDATE user_two_factor SET last_used_step = $2
+            WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)
+            "#,
+        )
+        .bind(user_id)
+        .bind(step)
+        .execute(db.pool())
+        .await?;
+        return Ok(accepted.rows_affected() == 1);
+    }
+
+    let recovered = sqlx::query(
+        "UPDATE two_factor_recovery_codes SET used_at = NOW() WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL"
+    )
+    .bind(user_id)
+    .bind(crypto::hash_token(&totp::normalize_recovery_code(code)))
+    .execute(db.pool())
+    .await?;
+    if recovered.rows_affected() == 1 {
+        tracing::info!("Recovery code used for user {}", user_id);
+    }
+
+    Ok(recovered.rows_affected() == 1)
+}
+
+fn invalid_two_factor_code() -> AppError {
+    AppError::AuthenticationError("Invalid two-factor code".to_string())
+}
+
+/// Issue a refresh token in `family_id`, storing only its hash
+async fn issue_refresh_token<'e, E>(executor: E, user_id: Uuid, family_id: Uuid) -> AppResult<String>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    let token = jwt::generate_refresh_token(&user_id.to_string(), REFRESH_TOKEN_TTL_SECS)?;
+
+    sqlx::query(
+        "INSERT INTO refresh_tokens (token_hash, user_id, family_id, expires_at) VALUES ($1, $2, $3, $4)"
+    )
+    .bind(crypto::hash_token(&token))
+    .bind(user_id)
+    .bind(family_id)
+    .bind(Utc::now() + Duration::seconds(REFRESH_TOKEN_TTL_SECS))
+    .execute(executor)
+    .await?;
+
+    Ok(token)
+}
+
+/// A rotated refresh token came back, so the family may be compromised: revoke all of it
+async fn revoke_refresh_family(db: &Database, user_id: Uuid, family_id: Uuid) -> AppError {
+    tracing::warn!("Refresh token reuse detected for user {}; revoking family {}", user_id, family_id);
+
+    if let Err(e) = sqlx::query("UPDATE refresh_tokens SET revoked = TRUE WHERE family_id = $1")
+        .bind(family_id)
+        .execute(db.pool())
+        .await
+    {
+        return e.into();
+    }
+
+    AppError::AuthenticationError("Refresh token reuse detected".to_string())
+}
+
+/// The signed-in user's account, as `cx7 auth whoami` shows it
+pub async fn me(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+) -> AppResult<Json<User>> {
+    Ok(Json(load_user(db.pool(), user_id).await?))
+}
+
+pub async fn logout(
+    State(db): State<Arc<Database>>,
+    headers: HeaderMap,
+) -> AppResult<&'static str> {
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .ok_or_else(|| AppError::AuthenticationError("Missing bearer token".to_string()))?;
+
+    let claims = jwt::verify_token(token)?;
+    let user_id = Uuid::parse_str(&claims.sub)
+        .map_err(|_| AppError::AuthenticationError("Invalid token subject".to_string()))?;
+    let expires_at = DateTime::<Utc>::from_timestamp(claims.exp, 0)
+        .ok_or_else(|| AppError::AuthenticationError("Invalid token expiry".to_string()))?;
+
+    // Keep the jti until the token would have expired on its own
+    sqlx::query(
+        "INSERT INTO revoked_tokens (jti, user_id, expires_at) VALUES ($1, $2, $3) ON CONFLICT (jti) DO NOTHING"
+    )
+    .bind(&claims.jti)
+    .bind(user_id)
+    .bind(expires_at)
+    .execute(db.pool())
+    .await?;
+
+    Ok("Logged out successfully")
+}
+
+pub async fn forgot_password(
+    State(db): State<Arc<Database>>,
+    Extension(events): Extension<Arc<EventBus>>,
+    ValidatedJson(payload): ValidatedJson<ForgotPasswordRequest>,
+) -> AppResult<&'static str> {
+    let row = sqlx::query("SELECT id, email FROM users WHERE LOWER(email) = $1")
+        .bind(payload.email.trim().to_lowercase())
+        .fetch_optional(db.pool())
+        .await?;
+
+    // Respond identically whether or not the account exists to avoid user enumeration
+    if let Some(row) = row {
+        let user_id: Uuid = row.get("id");
+        let token = crypto::generate_secure_token();
+        let expires_at = Utc::now() + Duration::seconds(RESET_TOKEN_TTL_SECS);
+
+        sqlx::query(
+            "INSERT INTO password_reset_tokens (token_hash, user_id, expires_at) VALUES ($1, $2, $3)"
+        )
+        .bind(crypto::hash_token(&token))
+        .bind(user_id)
+        .bind(expires_at)
+        .execute(db.pool())
+        .await?;
+
+        events.publish(DomainEvent::PasswordResetRequested {
+            user_id,
+            email: row.get("email"),
+            expires_at,
+            token,
+        })
+        .await;
+    }
+
+    Ok("If the account exists, a password reset link has been sent")
+}
+
+pub async fn reset_password(
+    State(db): State<Arc<Database>>,
+    ValidatedJson(payload): ValidatedJson<ResetPasswordRequest>,
+) -> AppResult<&'static str> {
+    let token_hash = crypto::hash_token(&payload.token);
+    let row = sqlx::query("SELECT user_id, expires_at, used FROM password_reset_tokens WHERE token_hash = $1")
+        .bind(&token_hash)
+        .fetch_optional(db.pool())
+        .await?;
+
+    let row = row.ok_or(AppError::ValidationError("Invalid reset token".to_string()))?;
+    let user_id: Uuid = row.get("user_id");
+    check_reset_token(row.get("used"), row.get("expires_at"), Utc::now())?;
+
+    let password_hash = crypto::hash_password(&payload.new_password)?;
+
+    let mut tx = db.pool().begin().await?;
+
+    // Claim the token atomically so concurrent requests cannot both use it
+    let claimed = sqlx::query("UPDATE password_reset_tokens SET used = TRUE WHERE token_hash = $1 AND used = FALSE")
+        .bind(&token_hash)
+        .execute(&mut *tx)
+        .await?;
+    if claimed.rows_affected() == 0 {
+        return Err(AppError::ValidationError("Reset token has already been used".to_string()));
+    }
+
+    sqlx::query("UPDATE users SET password_hash = $1, updated_at = NOW() WHERE id = $2")
+        .bind(&password_hash)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    // Sessions opened with the old password end with it
+    sqlx::query("UPDATE refresh_tokens SET revoked = TRUE WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok("Password has been reset")
+}
+
+pub async fn verify_email(
+    State(db): State<Arc<Database>>,
+    Query(query): Query<VerifyEmailQuery>,
+) -> AppResult<&'static str> {
+    let row = sqlx::query("SELECT user_id, expires_at FROM email_verification_tokens WHERE token_hash = $1")
+        .bind(crypto::hash_token(&query.token))
+        .fetch_optional(db.pool())
+        .await?;
+
+    let row = row.ok_or(AppError::ValidationError("Invalid verification token".to_string()))?;
+    let user_id: Uuid = row.get("user_id");
+    let expires_at: DateTime<Utc> = row.get("expires_at");
+    if expires_at <= Utc::now() {
+        return Err(AppError::ValidationError("Verification token has expired".to_string()));
+    }
+
+    let mut tx = db.pool().begin().await?;
+
+    sqlx::query("UPDATE users SET email_verified = TRUE, updated_at = NOW() WHERE id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM email_verification_tokens WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok("Email address verified")
+}
+
+fn invalid_credentials() -> AppError {
+    AppError::AuthenticationError("Invalid credentials".to_string())
+}
+
+async fn is_locked_out(db: &Database, email: &str) -> AppResult<bool> {
+    let locked: Option<bool> =
+        sqlx::query_scalar("SELECT locked_until > NOW() FROM login_attempts WHERE email = $1")
+            .bind(email)
+            .fetch_optional(db.pool())
+            .await?
+            .flatten();
+
+    Ok(locked.unwrap_or(false))
+}
+
+/// Count a failed login, locking the email once the policy threshold is reached.
+/// A lock that has already expired starts the count over.
+async fn record_failed_login(db: &Database, email: &str, policy: &LockoutPolicy) -> AppResult<()> {
+    let failed_count: i32 = sqlx::query_scalar(
+        r#"
+        INSERT INTO login_attempts (email, failed_count, last_failed_at)
+        VALUES ($1, 1, NOW())
+        ON CONFLICT (email) DO UPDATE SET
+            failed_count = CASE
+                WHEN login_attempts.locked_until <= NOW() THEN 1
+                ELSE login_attempts.failed_count + 1
+            END,
+            locked_until = CASE
+                WHEN login_attempts.locked_until <= NOW() THEN NULL
+                ELSE login_attempts.locked_until
+            END,
+            last_failed_at = NOW()
+        RETURNING failed_count
+        "#,
+    )
+    .bind(email)
+    .fetch_one(db.pool())
+    .await?;
+
+    if let Some(locked_until) = policy.lock_until(failed_count, Utc::now()) {
+        tracing::warn!("Locking logins for {} after {} failed attempts", email, failed_count);
+        sqlx::query("UPDATE login_attempts SET locked_until = $2, failed_count = 0 WHERE email = $1")
+            .bind(email)
+            .bind(locked_until)
+            .execute(db.pool())
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Unverified accounts may only log in when running in development
+fn requires_email_verification() -> bool {
+    std::env::var("ENVIRONMENT").unwrap_or_else(|_| "development".to_string()) != "development"
+}
+
+fn check_reset_token(used: bool, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> AppResult<()> {
+    if used {
+        return Err(AppError::ValidationError("Reset token has already been used".to_string()));
+    }
+    if expires_at <= now {
+        return Err(AppError::ValidationError("Reset token has expired".to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lockout_policy_threshold() {
+        let policy = LockoutPolicy {
+            max_failures: 3,
+            window: Duration::minutes(15),
+        };
+        let now = Utc::now();
+        assert_eq!(policy.lock_until(2, now), None);
+        assert_eq!(policy.lock_until(3, now), Some(now + Duration::minutes(15)));
+    }
+
+    #[test]
+    fn test_check_reset_token() {
+        let now = Utc::now();
+        assert!(check_reset_token(false, now + Duration::minutes(30), now).is_ok());
+        assert!(matches!(
+            check_reset_token(true, now + Duration::minutes(30), now),
+            Err(AppError::ValidationError(_))
+        ));
+        assert!(matches!(
+            check_reset_token(false, now - Duration::seconds(1), now),
+            Err(AppError::ValidationError(_))
+        ));
+    }
+
+    /// Register a fresh account, returning its email, its id and the token emailed to verify it
+    async fn register_unverified_user(db: &Arc<Database>) -> (String, Uuid, String) {
+        let events = Arc::new(EventBus::new(16));
+        let mut published = events.subscribe();
+        let email = format!("{}@example.com", Uuid::new_v4());
+        let Json(response) = register(
+            State(db.clone()),
+            Extension(events),
+            ValidatedJson(RegisterRequest {
+                email: email.clone(),
+                password: "TestPassword123".to_string(),
+                first_name: None,
+                last_name: None,
+            }),
+        )
+        .await
+        .unwrap();
+        // Whether tokens come back depends on the ENVIRONMENT other tests have set
+        let user = match response {
+            RegisterResponse::Authenticated(response) => response.user,
+            RegisterResponse::VerificationRequired(pending) => pending.user,
+        };
+        assert!(!user.email_verified);
+
+        let Ok(DomainEvent::EmailVerificationRequested { user_id, token, .. }) = published.try_recv() else {
+            panic!("no verification email was requested");
+        };
+        assert_eq!(user_id, user.id);
+        (email, user_id, token)
+    }
+
+    async fn register_user(db: &Arc<Database>) -> (String, Uuid) {
+        let (email, user_id, _) = register_unverified_user(db).await;
+        (email, user_id)
+    }
+
+    /// Registered and verified, so logins succeed whatever ENVIRONMENT other tests set
+    async fn register_verified_user(db: &Arc<Database>) -> (String, Uuid) {
+        let (email, user_id) = register_user(db).await;
+        sqlx::query("UPDATE users SET email_verified = TRUE WHERE id = $1")
+            .bind(user_id)
+            .execute(db.pool())
+            .await
+            .unwrap();
+        (email, user_id)
+    }
+
+    /// Tokens from a login that wasn't expected to need a second factor
+    fn authenticated(Json(response): Json<LoginResponse>) -> Json<AuthResponse> {
+        match response {
+            LoginResponse::Authenticated(response) => Json(response),
+            LoginResponse::TwoFactorRequired(_) => panic!("unexpected two-factor challenge"),
+        }
+    }
+
+    async fn login_as(db: &Arc<Database>, email: &str) -> AppResult<Json<AuthResponse>> {
+        login_with_password(db, email, "TestPassword123").await
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_login_before_verify_is_rejected() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        std::env::set_var("ENVIRONMENT", "production");
+        let db = crate::db::test_database().await;
+        let (email, _) = register_user(&db).await;
+
+        assert!(matches!(
+            login_as(&db, &email).await,
+            Err(AppError::AuthorizationError(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_unverified_user_gets_no_session() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        std::env::set_var("ENVIRONMENT", "production");
+        let db = crate::db::test_database().await;
+        let email = format!("{}@example.com", Uuid::new_v4());
+
+        let Json(response) = register(
+            State(db.clone()),
+            Extension(Arc::new(EventBus::new(16))),
+            ValidatedJson(RegisterRequest {
+                email,
+                password: "TestPassword123".to_string(),
+                first_name: None,
+                last_name: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let RegisterResponse::VerificationRequired(pending) = response else {
+            panic!("registration handed out tokens before verification");
+        };
+        assert_eq!(pending.status, "verification_required");
+
+        // Nor can a refresh token issued before verification was required be turned into one
+        let refresh_token = issue_refresh_token(db.pool(), pending.user.id, Uuid::new_v4()).await.unwrap();
+        assert!(matches!(
+            refresh_with(&db, &refresh_token).await,
+            Err(AppError::AuthorizationError(_))
+        ));
+        let rotated: Option<DateTime<Utc>> = sqlx::query_scalar("SELECT rotated_at FROM refresh_tokens WHERE token_hash = $1")
+            .bind(crypto::hash_token(&refresh_token))
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+        assert_eq!(rotated, None);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_verify_then_login() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        std::env::set_var("ENVIRONMENT", "production");
+        let db = crate::db::test_database().await;
+        let (email, user_id, token) = register_unverified_user(&db).await;
+
+        // Only the token's hash is kept
+        let stored: String = sqlx::query_scalar("SELECT token_hash FROM email_verification_tokens WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+        assert_eq!(stored, crypto::hash_token(&token));
+
+        verify_email(State(db.clone()), Query(VerifyEmailQuery { token }))
+            .await
+            .unwrap();
+
+        let Json(response) = login_as(&db, &email).await.unwrap();
+        assert!(response.user.email_verified);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_reset_password_with_emailed_token() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        let db = crate::db::test_database().await;
+        let (email, user_id) = register_verified_user(&db).await;
+        let Json(session) = login_as(&db, &email).await.unwrap();
+
+        let events = Arc::new(EventBus::new(16));
+        let mut published = events.subscribe();
+        forgot_password(
+            State(db.clone()),
+            Extension(events),
+            ValidatedJson(ForgotPasswordRequest { email: email.clone() }),
+        )
+        .await
+        .unwrap();
+        let Ok(DomainEvent::PasswordResetRequested { user_id: requested_for, token, .. }) = published.try_recv() else {
+            panic!("no password reset email was requested");
+        };
+        assert_eq!(requested_for, user_id);
+
+        // Only the token's hash is kept
+        let stored: String = sqlx::query_scalar("SELECT token_hash FROM password_reset_tokens WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+        assert_eq!(stored, crypto::hash_token(&token));
+
+        let reset = |token: String| {
+            reset_password(
+                State(db.clone()),
+                ValidatedJson(ResetPasswordRequest { token, new_password: "NewPassword456".to_string() }),
+            )
+        };
+        reset(token.clone()).await.unwrap();
+        assert!(matches!(reset(token).await, Err(AppError::ValidationError(_))));
+
+        // The new password works, and sessions from before the reset are over
+        assert!(login_with_password(&db, &email, "NewPassword456").await.is_ok());
+        assert!(matches!(
+            refresh_with(&db, &session.refresh_token).await,
+            Err(AppError::AuthenticationError(_))
+        ));
+    }
+
+    async fn login_with_password(db: &Arc<Database>, email: &str, password: &str) -> AppResult<Json<AuthResponse>> {
+        login(
+            State(db.clone()),
+            ValidatedJson(LoginRequest {
+                email: email.to_string(),
+                password: password.to_string(),
+            }),
+        )
+        .await
+        .map(authenticated)
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_repeated_failures_lock_account_until_window_passes() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        let db = crate::db::test_database().await;
+        let (email, _) = register_verified_user(&db).await;
+
+        for _ in 0..DEFAULT_MAX_FAILED_LOGINS {
+            assert!(matches!(
+                login_with_password(&db, &email, "WrongPassword1").await,
+                Err(AppError::AuthenticationError(_))
+            ));
+        }
+
+        // The right password is refused with the same generic error while locked
+        match login_as(&db, &email).await {
+            Err(AppError::AuthenticationError(message)) => assert_eq!(message, "Invalid credentials"),
+            other => panic!("expected lockout, got {:?}", other.map(|_| ())),
+        }
+
+        // Let the lock lapse
+        sqlx::query("UPDATE login_attempts SET locked_until = NOW() - INTERVAL '1 second' WHERE email = $1")
+            .bind(&email)
+            .execute(db.pool())
+            .await
+            .unwrap();
+
+        assert!(login_as(&db, &email).await.is_ok());
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM login_attempts WHERE email = $1")
+            .bind(&email)
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+        assert_eq!(remaining, 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_email_case_does_not_dodge_lockout() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        let db = crate::db::test_database().await;
+        let (email, user_id) = register_verified_user(&db).await;
+
+        let Json(session) = login_as(&db, &email.to_uppercase()).await.unwrap();
+        assert_eq!(session.user.id, user_id);
+
+        for _ in 0..DEFAULT_MAX_FAILED_LOGINS {
+            let _ = login_with_password(&db, &email.to_uppercase(), "WrongPassword1").await;
+        }
+        assert!(matches!(login_as(&db, &email).await, Err(AppError::AuthenticationError(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_email_case_does_not_make_a_second_account() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        let db = crate::db::test_database().await;
+        let register_as = |email: String| {
+            register(
+                State(db.clone()),
+                Extension(Arc::new(EventBus::new(16))),
+                ValidatedJson(RegisterRequest {
+                    email,
+                    password: "TestPassword123".to_string(),
+                    first_name: None,
+                    last_name: None,
+                }),
+            )
+        };
+        let email = format!("{}@Example.com", Uuid::new_v4().simple()).to_uppercase();
+
+        let _ = register_as(email.clone()).await.unwrap();
+        let stored: String = sqlx::query_scalar("SELECT email FROM users WHERE LOWER(email) = $1")
+            .bind(email.to_lowercase())
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+        assert_eq!(stored, email.to_lowercase());
+        assert!(matches!(
+            register_as(email.to_lowercase()).await,
+            Err(AppError::ConflictError(_))
+        ));
+
+        // Password resets find the account whatever the case
+        let events = Arc::new(EventBus::new(16));
+        let mut published = events.subscribe();
+        forgot_password(
+            State(db.clone()),
+            Extension(events),
+            ValidatedJson(ForgotPasswordRequest { email: email.clone() }),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(published.try_recv(), Ok(DomainEvent::PasswordResetRequested { .. })));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_successful_login_resets_failure_count() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        let db = crate::db::test_database().await;
+        let (email, _) = register_verified_user(&db).await;
+
+        for _ in 0..DEFAULT_MAX_FAILED_LOGINS - 1 {
+            let _ = login_with_password(&db, &email, "WrongPassword1").await;
+        }
+        assert!(login_as(&db, &email).await.is_ok());
+
+        // The earlier failures no longer count toward a lock
+        let _ = login_with_password(&db, &email, "WrongPassword1").await;
+        assert!(login_as(&db, &email).await.is_ok());
+    }
+
+    async fn refresh_with(db: &Arc<Database>, token: &str) -> AppResult<Json<AuthResponse>> {
+        refresh_token(
+            State(db.clone()),
+            ValidatedJson(crate::models::TokenRefreshRequest {
+                refresh_token: token.to_string(),
+            }),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_refresh_rotates_token() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        let db = crate::db::test_database().await;
+        let (email, user_id) = register_verified_user(&db).await;
+        let Json(session) = login_as(&db, &email).await.unwrap();
+
+        let Json(first) = refresh_with(&db, &session.refresh_token).await.unwrap();
+        assert_eq!(first.user.id, user_id);
+        assert_ne!(first.refresh_token, session.refresh_token);
+
+        let Json(second) = refresh_with(&db, &first.refresh_token).await.unwrap();
+        assert_ne!(second.refresh_token, first.refresh_token);
+
+        // Only the hash is stored
+        let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM refresh_tokens WHERE token_hash = $1")
+            .bind(&second.refresh_token)
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+        assert_eq!(stored, 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_refresh_reuse_revokes_family() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        let db = crate::db::test_database().await;
+        let (email, _) = register_verified_user(&db).await;
+        let Json(session) = login_as(&db, &email).await.unwrap();
+        let Json(other_session) = login_as(&db, &email).await.unwrap();
+
+        let Json(rotated) = refresh_with(&db, &session.refresh_token).await.unwrap();
+
+        // Replaying the already-rotated token kills the whole family
+        assert!(matches!(
+            refresh_with(&db, &session.refresh_token).await,
+            Err(AppError::AuthenticationError(_))
+        ));
+        assert!(matches!(
+            refresh_with(&db, &rotated.refresh_token).await,
+            Err(AppError::AuthenticationError(_))
+        ));
+
+        // Other logins are separate families and keep working
+        assert!(refresh_with(&db, &other_session.refresh_token).await.is_ok());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_refresh_rejects_unissued_token() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        let db = crate::db::test_database().await;
+        let (_, user_id) = register_user(&db).await;
+
+        let forged = jwt::generate_refresh_token(&user_id.to_string(), REFRESH_TOKEN_TTL_SECS).unwrap();
+        assert!(matches!(
+            refresh_with(&db, &forged).await,
+            Err(AppError::AuthenticationError(_))
+        ));
+    }
+
+    #[test]
+    fn test_split_name() {
+        assert_eq!(split_name(Some("Ada King Lovelace")), (Some("Ada".to_string()), Some("King Lovelace".to_string())));
+        assert_eq!(split_name(Some("octocat")), (Some("octocat".to_string()), None));
+        assert_eq!(split_name(Some("  ")), (None, None));
+        assert_eq!(split_name(None), (None, None));
+    }
+
+    /// A stand-in for GitHub's OAuth and API hosts that signs everyone in as one account
+    async fn github_stand_in(github_id: i64, email: &str) -> Arc<GithubOAuth> {
+        use axum::routing::{get, post};
+
+        let emails = serde_json::json!([
+            { "email": format!("unverified-{}", email), "primary": false, "verified": false },
+            { "email": email, "primary": true, "verified": true },
+        ]);
+        let app = axum::Router::new()
+            .route(
+                "/login/oauth/access_token",
+                post(|body: String| async move {
+                    if body.contains("code=good-code") {
+                        Json(serde_json::json!({ "access_token": "gho_test" }))
+                    } else {
+                        Json(serde_json::json!({ "error": "bad_verification_code" }))
+                    }
+                }),
+            )
+            .route(
+                "/user",
+                get(move || async move {
+                    Json(serde_json::json!({ "id": github_id, "login": "octocat", "name": "Mona Octocat" }))
+                }),
+            )
+            .route("/user/emails", get(move || async move { Json(emails) }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        Arc::new(GithubOAuth::new(
+            "client-id",
+            "client-secret",
+            "http://localhost:8080/api/auth/oauth/github/callback",
+            format!("http://{}/login/oauth", addr),
+            format!("http://{}", addr),
+        ))
+    }
+
+    /// Follow the redirect to GitHub, returning the `state` it carries
+    async fn start_github_login(db: &Arc<Database>, github: &Arc<GithubOAuth>) -> String {
+        use axum::response::IntoResponse;
+
+        let redirect = github_login(State(db.clone()), Extension(Some(github.clone())))
+            .await
+            .unwrap()
+            .into_response();
+        let location = redirect.headers()[axum::http::header::LOCATION].to_str().unwrap();
+        let location = reqwest::Url::parse(location).unwrap();
+        assert!(location.path().ends_with("/login/oauth/authorize"));
+        location
+            .query_pairs()
+            .find(|(name, _)| name == "state")
+            .map(|(_, state)| state.into_owned())
+            .unwrap()
+    }
+
+    /// Run the redirect and callback the way a browser would
+    async fn github_sign_in(db: &Arc<Database>, github: &Arc<GithubOAuth>) -> AppResult<Json<AuthResponse>> {
+        let state = start_github_login(db, github).await;
+        github_callback(
+            State(db.clone()),
+            Extension(Some(github.clone())),
+            Query(OAuthCallbackQuery {
+                code: Some("good-code".to_string()),
+                state,
+                error: None,
+            }),
+        )
+        .await
+        .map(authenticated)
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_github_login_creates_then_reuses_account() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        let db = crate::db::test_database().await;
+        let github_id = rand::random::<u32>() as i64;
+        let email = format!("{}@example.com", Uuid::new_v4());
+        let github = github_stand_in(github_id, &email).await;
+
+        // First login creates a verified, password-less account
+        let Json(first) = github_sign_in(&db, &github).await.unwrap();
+        assert_eq!(first.user.email, email);
+        assert!(first.user.email_verified);
+        assert_eq!(first.user.first_name.as_deref(), Some("Mona"));
+        jwt::verify_token(&first.access_token).unwrap();
+        assert!(refresh_with(&db, &first.refresh_token).await.is_ok());
+        assert!(matches!(
+            login_with_password(&db, &email, "").await,
+            Err(AppError::AuthenticationError(_))
+        ));
+
+        // Later logins find the same account through the identity
+        let Json(second) = github_sign_in(&db, &github).await.unwrap();
+        assert_eq!(second.user.id, first.user.id);
+        let identities: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM user_identities WHERE provider = 'github' AND provider_user_id = $1",
+        )
+        .bind(github_id.to_string())
+        .fetch_one(db.pool())
+        .await
+        .unwrap();
+        assert_eq!(identities, 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_github_login_links_existing_account_by_verified_email() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        let db = crate::db::test_database().await;
+        let (email, user_id) = register_verified_user(&db).await;
+        let github = github_stand_in(rand::random::<u32>() as i64, &email.to_uppercase()).await;
+
+        let Json(response) = github_sign_in(&db, &github).await.unwrap();
+        assert_eq!(response.user.id, user_id);
+
+        // Both ways in keep working
+        assert!(login_as(&db, &email).await.is_ok());
+        let linked: Uuid = sqlx::query_scalar("SELECT user_id FROM user_identities WHERE email = $1")
+            .bind(email.to_uppercase())
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+        assert_eq!(linked, user_id);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_github_callback_rejects_unknown_or_reused_state() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        let db = crate::db::test_database().await;
+        let email = format!("{}@example.com", Uuid::new_v4());
+        let github = github_stand_in(rand::random::<u32>() as i64, &email).await;
+        let callback = |state: String| {
+            github_callback(
+                State(db.clone()),
+                Extension(Some(github.clone())),
+                Query(OAuthCallbackQuery {
+                    code: Some("good-code".to_string()),
+                    state,
+                    error: None,
+                }),
+            )
+        };
+
+        assert!(matches!(
+            callback("never-issued".to_string()).await,
+            Err(AppError::AuthenticationError(_))
+        ));
+
+        let state = start_github_login(&db, &github).await;
+        assert!(callback(state.clone()).await.is_ok());
+        assert!(matches!(callback(state).await, Err(AppError::AuthenticationError(_))));
+    }
+
+    /// Enroll and confirm two-factor for a verified user, returning the secret and recovery codes
+    async fn enable_two_factor(db: &Arc<Database>, user_id: Uuid) -> (Vec<u8>, Vec<String>) {
+        let Json(enrollment) = enroll_two_factor(State(db.clone()), AuthUser(user_id)).await.unwrap();
+        assert!(enrollment.otpauth_uri.starts_with("otpauth://totp/"));
+        let secret = data_encoding::BASE32_NOPAD.decode(enrollment.secret.as_bytes()).unwrap();
+
+        let code = totp::code_at(&secret, totp::time_step(Utc::now()));
+        let Json(enabled) = verify_two_factor(
+            State(db.clone()),
+            AuthUser(user_id),
+            ValidatedJson(TwoFactorVerifyRequest { code }),
+        )
+        .await
+        .unwrap();
+        (secret, enabled.recovery_codes)
+    }
+
+    /// The challenge token a password login hands back for a two-factor account
+    async fn two_factor_login(db: &Arc<Database>, email: &str) -> String {
+        let Json(response) = login(
+            State(db.clone()),
+            ValidatedJson(LoginRequest {
+                email: email.to_string(),
+                password: "TestPassword123".to_string(),
+            }),
+        )
+        .await
+        .unwrap();
+        match response {
+            LoginResponse::TwoFactorRequired(challenge) => {
+                assert_eq!(challenge.status, "2fa_required");
+                challenge.challenge_token
+            }
+            LoginResponse::Authenticated(_) => panic!("login skipped the second factor"),
+        }
+    }
+
+    async fn answer_challenge(db: &Arc<Database>, challenge_token: &str, code: &str) -> AppResult<Json<AuthResponse>> {
+        two_factor_challenge(
+            State(db.clone()),
+            ValidatedJson(TwoFactorChallengeRequest {
+                challenge_token: challenge_token.to_string(),
+                code: code.to_string(),
+            }),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_two_factor_enrollment_needs_a_valid_code() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        std::env::set_var("TOTP_ENCRYPTION_KEY", "test_totp_key");
+        let db = crate::db::test_database().await;
+        let (email, user_id) = register_verified_user(&db).await;
+
+        let Json(enrollment) = enroll_two_factor(State(db.clone()), AuthUser(user_id)).await.unwrap();
+        assert!(enrollment.otpauth_uri.contains(&format!("secret={}", enrollment.secret)));
+        assert!(matches!(
+            verify_two_factor(
+                State(db.clone()),
+                AuthUser(user_id),
+                ValidatedJson(TwoFactorVerifyRequest { code: "000000".to_string() }),
+            )
+            .await,
+            Err(AppError::ValidationError(_))
+        ));
+
+        // Unconfirmed enrollment doesn't change how login works, and the secret is encrypted at rest
+        assert!(login_as(&db, &email).await.is_ok());
+        let stored: String = sqlx::query_scalar("SELECT secret_encrypted FROM user_two_factor WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+
+        let (secret, recovery_codes) = enable_two_factor(&db, user_id).await;
+        assert_ne!(stored, totp::encode_secret(&secret));
+        assert_eq!(recovery_codes.len(), 10);
+        assert!(matches!(
+            enroll_two_factor(State(db.clone()), AuthUser(user_id)).await,
+            Err(AppError::ConflictError(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_two_factor_login_accepts_a_fresh_code_once() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        std::env::set_var("TOTP_ENCRYPTION_KEY", "test_totp_key");
+        let db = crate::db::test_database().await;
+        let (email, user_id) = register_verified_user(&db).await;
+        let (secret, _) = enable_two_factor(&db, user_id).await;
+        let step = totp::time_step(Utc::now());
+
+        let challenge_token = two_factor_login(&db, &email).await;
+        // The code that confirmed enrollment has been used, and one from a minute ago is too old
+        for stale in [totp::code_at(&secret, step), totp::code_at(&secret, step - 2)] {
+            assert!(matches!(
+                answer_challenge(&db, &challenge_token, &stale).await,
+                Err(AppError::AuthenticationError(_))
+            ));
+        }
+
+        // The next step is within the skew window
+        let next = totp::code_at(&secret, step + 1);
+        let Json(session) = answer_challenge(&db, &challenge_token, &next).await.unwrap();
+        assert_eq!(session.user.id, user_id);
+        assert!(refresh_with(&db, &session.refresh_token).await.is_ok());
+
+        // Neither the challenge nor the code can be used again
+        assert!(answer_challenge(&db, &challenge_token, &next).await.is_err());
+        let challenge_token = two_factor_login(&db, &email).await;
+        assert!(matches!(
+            answer_challenge(&db, &challenge_token, &next).await,
+            Err(AppError::AuthenticationError(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_recovery_codes_are_single_use() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        std::env::set_var("TOTP_ENCRYPTION_KEY", "test_totp_key");
+        let db = crate::db::test_database().await;
+        let (email, user_id) = register_verified_user(&db).await;
+        let (_, recovery_codes) = enable_two_factor(&db, user_id).await;
+
+        let challenge_token = two_factor_login(&db, &email).await;
+        let Json(session) = answer_challenge(&db, &challenge_token, &recovery_codes[0].to_uppercase())
+            .await
+            .unwrap();
+        assert_eq!(session.user.id, user_id);
+
+        let challenge_token = two_factor_login(&db, &email).await;
+        assert!(answer_challenge(&db, &challenge_token, &recovery_codes[0]).await.is_err());
+        assert!(answer_challenge(&db, &challenge_token, &recovery_codes[1]).await.is_ok());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_two_factor_challenge_is_dropped_after_repeated_failures() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        std::env::set_var("TOTP_ENCRYPTION_KEY", "test_totp_key");
+        let db = crate::db::test_database().await;
+        let (email, user_id) = register_verified_user(&db).await;
+        let (secret, _) = enable_two_factor(&db, user_id).await;
+
+        let challenge_token = two_factor_login(&db, &email).await;
+        for _ in 0..TWO_FACTOR_MAX_ATTEMPTS {
+            assert!(answer_challenge(&db, &challenge_token, "not-a-code").await.is_err());
+        }
+
+        // Even a right code needs a new password login now
+        let next = totp::code_at(&secret, totp::time_step(Utc::now()) + 1);
+        match answer_challenge(&db, &challenge_token, &next).await {
+            Err(AppError::AuthenticationError(message)) => {
+                assert_eq!(message, "Invalid or expired two-factor challenge")
+            }
+            other => panic!("expected the challenge to be gone, got {:?}", other.map(|_| ())),
+        }
+    }
 }
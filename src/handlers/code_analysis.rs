@@ -1,111 +1,397 @@
-use axum::{extract::State, Json};
-use std::sync::Arc;
+use axum::{
+    extract::{Query, State},
+    response::sse::{Event, KeepAlive, Sse},
+    Json,
+};
+use futures::{Stream, StreamExt};
+use std::{convert::Infallible, sync::Arc};
 use uuid::Uuid;
 
 use crate::{
     db::Database,
     error::{AppError, AppResult},
-    models::{OptimizeCodeRequest, ReviewCodeRequest, RefactorCodeRequest, CodeAnalysisResponse, AnalysisMetrics},
-    services::ai::AIService,
+    handlers::projects::scratch_project_id,
+    middleware::rbac,
+    middleware_auth::AuthUser,
+    models::{AnalysisMetrics, AnalysisQuery, OptimizeCodeRequest, ReviewCodeRequest, RefactorCodeRequest, CodeAnalysisResponse},
+    services::{
+        ai::AIService,
+        analysis_cache::{AnalysisCache, AnalysisCacheKey, CachedAnalysis},
+        code_analysis::{compare_metrics, detect_language, CodeAnalyzer},
+        prompts::PromptTask,
+    },
+    utils::validation::ValidatedJson,
 };
 
+/// Resolve the project an analysis task is stored under, checking write access
+/// to an explicit project and falling back to the user's scratch project
+async fn resolve_task_project(
+    db: &Database,
+    user_id: Uuid,
+    project_id: Option<Uuid>,
+) -> AppResult<Uuid> {
+    let Some(project_id) = project_id else {
+        return scratch_project_id(db, user_id).await;
+    };
+
+    // enforce_permission answers 404 for projects the user can't see, so keep its error as is
+    if !rbac::check_project_admin(db.pool(), user_id, project_id).await? {
+        rbac::enforce_permission(db.pool(), user_id, project_id, "write").await?;
+    }
+
+    Ok(project_id)
+}
+
+/// What an analysis task records: its suggestions and the metrics the response reported
+fn analysis_output(suggestions: &[String], metrics: &AnalysisMetrics) -> serde_json::Value {
+    serde_json::json!({ "suggestions": suggestions, "metrics": metrics })
+}
+
+/// Ask the AI to carry out `task` on the code, reusing a cached answer for identical code
+/// unless `no_cache` is set. Returns the answer and whether it came from the cache. The
+/// cache is an optimization, so failing to read or write it doesn't fail the request.
+async fn ai_analysis(
+    db: &Database,
+    ai_service: &AIService,
+    task: PromptTask,
+    code: &str,
+    language: &str,
+    no_cache: bool,
+) -> AppResult<(CachedAnalysis, bool)> {
+    let cache = AnalysisCache::from_env(db.pool().clone());
+    let key = AnalysisCacheKey::new(code, language, task, ai_service.model());
+
+    if !no_cache {
+        match cache.get(&key).await {
+            Ok(Some(analysis)) => return Ok((analysis, true)),
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Failed to read analysis cache: {:?}", e),
+        }
+    }
+
+    let analysis = match task {
+        PromptTask::Optimize => CachedAnalysis {
+            suggestions: ai_service.optimize(code, language).await?,
+            refactored_code: None,
+        },
+        PromptTask::Review => CachedAnalysis {
+            suggestions: ai_service.review(code, language).await?,
+            refactored_code: None,
+        },
+        PromptTask::Refactor => {
+            let (suggestions, refactored) = ai_service.refactor(code, language).await?;
+            CachedAnalysis {
+                suggestions,
+                refactored_code: Some(refactored),
+            }
+        }
+    };
+
+    if let Err(e) = cache.put(&key, &analysis).await {
+        tracing::warn!("Failed to write analysis cache: {:?}", e);
+    }
+
+    Ok((analysis, false))
+}
+
 pub async fn optimize_code(
     State(db): State<Arc<Database>>,
-    Json(payload): Json<OptimizeCodeRequest>,
+    AuthUser(user_id): AuthUser,
+    Query(query): Query<AnalysisQuery>,
+    ValidatedJson(payload): ValidatedJson<OptimizeCodeRequest>,
 ) -> AppResult<Json<CodeAnalysisResponse>> {
     let task_id = Uuid::new_v4();
+    let project_id = resolve_task_project(&db, user_id, payload.project_id).await?;
+    let language = detect_language(payload.language.as_deref(), payload.file_path.as_deref(), &payload.code);
 
     // Call AI service for code optimization
-    let ai_service = AIService::new();
-    let suggestions = ai_service.optimize(&payload.code, &payload.language).await?;
+    let ai_service = AIService::new().for_user(db.pool().clone(), user_id);
+    let (answer, cached) =
+        ai_analysis(&db, &ai_service, PromptTask::Optimize, &payload.code, &language, query.no_cache).await?;
+    let mut suggestions = answer.suggestions;
 
-    // Store task in database
+    let analysis = CodeAnalyzer::new().analyze(&payload.code, &language)?;
+    suggestions.extend(analysis.issues());
+    let findings = analysis.findings();
+    let metrics = compare_metrics(&analysis, None);
+
+    // Store task in database; the dashboard's quality score reads the metrics back
     sqlx::query(
         "INSERT INTO analysis_tasks (id, project_id, task_type, status, input_data, output_data) VALUES ($1, $2, $3, $4, $5, $6)"
     )
     .bind(&task_id)
-    .bind(&Uuid::nil()) // placeholder
+    .bind(project_id)
     .bind("optimize")
     .bind("completed")
     .bind(serde_json::json!(payload))
-    .bind(serde_json::json!(suggestions))
+    .bind(analysis_output(&suggestions, &metrics))
     .execute(db.pool())
     .await?;
 
     Ok(Json(CodeAnalysisResponse {
         task_id,
-        suggestions: suggestions.clone(),
+        language,
+        suggestions,
+        findings,
         optimized_code: None,
-        metrics: AnalysisMetrics {
-            complexity_reduction: 15.5,
-            performance_gain: 22.3,
-            maintainability_score: 8.2,
-        },
+        metrics,
+        cached,
     }))
 }
 
+/// Stream optimization suggestions as server-sent events while the model generates them
+pub async fn optimize_code_stream(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(payload): ValidatedJson<OptimizeCodeRequest>,
+) -> AppResult<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
+    if payload.project_id.is_some() {
+        resolve_task_project(&db, user_id, payload.project_id).await?;
+    }
+
+    let language = detect_language(payload.language.as_deref(), payload.file_path.as_deref(), &payload.code);
+    let chunks = AIService::new()
+        .for_user(db.pool().clone(), user_id)
+        .optimize_streaming(&payload.code, &language);
+
+    let events = chunks.map(|chunk| {
+        Ok(match chunk {
+            Ok(text) => Event::default().data(text),
+            Err(e) => {
+                tracing::error!("AI stream failed: {:?}", e);
+                Event::default().event("error").data("AI stream failed")
+            }
+        })
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
 pub async fn review_code(
     State(db): State<Arc<Database>>,
-    Json(payload): Json<ReviewCodeRequest>,
+    AuthUser(user_id): AuthUser,
+    Query(query): Query<AnalysisQuery>,
+    ValidatedJson(payload): ValidatedJson<ReviewCodeRequest>,
 ) -> AppResult<Json<CodeAnalysisResponse>> {
     let task_id = Uuid::new_v4();
+    let project_id = resolve_task_project(&db, user_id, payload.project_id).await?;
+    let language = detect_language(payload.language.as_deref(), payload.file_path.as_deref(), &payload.code);
 
     // Call AI service for code review
-    let ai_service = AIService::new();
-    let suggestions = ai_service.review(&payload.code, &payload.language).await?;
+    let ai_service = AIService::new().for_user(db.pool().clone(), user_id);
+    let (answer, cached) =
+        ai_analysis(&db, &ai_service, PromptTask::Review, &payload.code, &language, query.no_cache).await?;
+    let mut suggestions = answer.suggestions;
+
+    let analysis = CodeAnalyzer::new().analyze(&payload.code, &language)?;
+    suggestions.extend(analysis.issues());
+    let findings = analysis.findings();
+    let metrics = compare_metrics(&analysis, None);
 
     // Store task
     sqlx::query(
-        "INSERT INTO analysis_tasks (id, project_id, task_type, status) VALUES ($1, $2, $3, $4)"
+        "INSERT INTO analysis_tasks (id, project_id, task_type, status, output_data) VALUES ($1, $2, $3, $4, $5)"
     )
     .bind(&task_id)
-    .bind(&Uuid::nil())
+    .bind(project_id)
     .bind("review")
     .bind("completed")
+    .bind(analysis_output(&suggestions, &metrics))
     .execute(db.pool())
     .await?;
 
     Ok(Json(CodeAnalysisResponse {
         task_id,
+        language,
         suggestions,
+        findings,
         optimized_code: None,
-        metrics: AnalysisMetrics {
-            complexity_reduction: 0.0,
-            performance_gain: 0.0,
-            maintainability_score: 7.8,
-        },
+        metrics,
+        cached,
     }))
 }
 
 pub async fn refactor_code(
     State(db): State<Arc<Database>>,
-    Json(payload): Json<RefactorCodeRequest>,
+    AuthUser(user_id): AuthUser,
+    Query(query): Query<AnalysisQuery>,
+    ValidatedJson(payload): ValidatedJson<RefactorCodeRequest>,
 ) -> AppResult<Json<CodeAnalysisResponse>> {
     let task_id = Uuid::new_v4();
+    let project_id = resolve_task_project(&db, user_id, payload.project_id).await?;
+    let language = detect_language(payload.language.as_deref(), payload.file_path.as_deref(), &payload.code);
 
     // Call AI service for code refactoring
-    let ai_service = AIService::new();
-    let (suggestions, refactored) = ai_service.refactor(&payload.code, &payload.language).await?;
+    let ai_service = AIService::new().for_user(db.pool().clone(), user_id);
+    let (answer, cached) =
+        ai_analysis(&db, &ai_service, PromptTask::Refactor, &payload.code, &language, query.no_cache).await?;
+    let mut suggestions = answer.suggestions;
+    let refactored = answer.refactored_code.unwrap_or_default();
+
+    // Compare the submitted code against the AI's rewrite
+    let analyzer = CodeAnalyzer::new();
+    let before = analyzer.analyze(&payload.code, &language)?;
+    let after = analyzer.analyze(&refactored, &language)?;
+    suggestions.extend(before.issues());
+    let findings = before.findings();
+    let metrics = compare_metrics(&before, Some(&after));
 
     // Store task
     sqlx::query(
-        "INSERT INTO analysis_tasks (id, project_id, task_type, status) VALUES ($1, $2, $3, $4)"
+        "INSERT INTO analysis_tasks (id, project_id, task_type, status, output_data) VALUES ($1, $2, $3, $4, $5)"
     )
     .bind(&task_id)
-    .bind(&Uuid::nil())
+    .bind(project_id)
     .bind("refactor")
     .bind("completed")
+    .bind(analysis_output(&suggestions, &metrics))
     .execute(db.pool())
     .await?;
 
     Ok(Json(CodeAnalysisResponse {
         task_id,
+        language,
         suggestions,
+        findings,
         optimized_code: Some(refactored),
-        metrics: AnalysisMetrics {
-            complexity_reduction: 20.0,
-            performance_gain: 18.0,
-            maintainability_score: 8.5,
-        },
+        metrics,
+        cached,
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_task_project_requires_write_access() {
+        let db = crate::db::test_database().await;
+        let owner = crate::db::insert_test_user(db.pool()).await;
+        let writer = crate::db::insert_test_user(db.pool()).await;
+        let reader = crate::db::insert_test_user(db.pool()).await;
+        let stranger = crate::db::insert_test_user(db.pool()).await;
+
+        let project_id = crate::db::insert_test_project(db.pool(), owner, "analysis").await;
+        for (member, permissions) in [(writer, vec!["read", "write"]), (reader, vec!["read"])] {
+            sqlx::query(
+                "INSERT INTO project_members (id, project_id, user_id, role, permissions) VALUES ($1, $2, $3, 'developer', $4)"
+            )
+            .bind(Uuid::new_v4())
+            .bind(project_id)
+            .bind(member)
+            .bind(permissions)
+            .execute(db.pool())
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(
+            resolve_task_project(&db, owner, Some(project_id)).await.unwrap(),
+            project_id
+        );
+        assert_eq!(
+            resolve_task_project(&db, writer, Some(project_id)).await.unwrap(),
+            project_id
+        );
+        assert!(matches!(
+            resolve_task_project(&db, reader, Some(project_id)).await,
+            Err(AppError::AuthorizationError(_))
+        ));
+        // Someone who can't see the project isn't told it exists
+        assert!(matches!(
+            resolve_task_project(&db, stranger, Some(project_id)).await,
+            Err(AppError::NotFoundError(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_task_without_project_uses_scratch_project() {
+        let db = crate::db::test_database().await;
+        let user = crate::db::insert_test_user(db.pool()).await;
+        let other = crate::db::insert_test_user(db.pool()).await;
+
+        let scratch = resolve_task_project(&db, user, None).await.unwrap();
+        assert_eq!(resolve_task_project(&db, user, None).await.unwrap(), scratch);
+        assert_ne!(resolve_task_project(&db, other, None).await.unwrap(), scratch);
+
+        let owner: Uuid = sqlx::query_scalar("SELECT user_id FROM projects WHERE id = $1 AND is_scratch")
+            .bind(scratch)
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+        assert_eq!(owner, user);
+    }
+
+    /// A completion endpoint that counts its calls and always suggests the same thing
+    async fn counting_ai_server() -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+        use axum::{routing::post, Router};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let server_calls = calls.clone();
+        let app = Router::new().route(
+            "/chat/completions",
+            post(move || {
+                let calls = server_calls.clone();
+                async move {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    Json(serde_json::json!({
+                        "choices": [{ "message": { "content": "Use an iterator" } }]
+                    }))
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        (format!("http://{}", addr), calls)
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_identical_code_is_served_from_cache() {
+        use std::sync::atomic::Ordering;
+
+        let db = crate::db::test_database().await;
+        let (url, calls) = counting_ai_server().await;
+        let ai_service = AIService::with_endpoint(url, "test-key");
+        // Unique per run so earlier runs' entries don't count as hits
+        let code = format!("fn main() {{ println!(\"{}\"); }}", Uuid::new_v4());
+
+        let (first, cached) = ai_analysis(&db, &ai_service, PromptTask::Review, &code, "rust", false)
+            .await
+            .unwrap();
+        assert!(!cached);
+        assert_eq!(first.suggestions, vec!["Use an iterator"]);
+
+        let (second, cached) = ai_analysis(&db, &ai_service, PromptTask::Review, &code, "rust", false)
+            .await
+            .unwrap();
+        assert!(cached);
+        assert_eq!(second, first);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // A different task or language is a different question
+        let (_, cached) = ai_analysis(&db, &ai_service, PromptTask::Optimize, &code, "rust", false)
+            .await
+            .unwrap();
+        assert!(!cached);
+        let (_, cached) = ai_analysis(&db, &ai_service, PromptTask::Review, &code, "text", false)
+            .await
+            .unwrap();
+        assert!(!cached);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // no_cache goes to the AI even though an answer is cached
+        let (_, cached) = ai_analysis(&db, &ai_service, PromptTask::Review, &code, "rust", true)
+            .await
+            .unwrap();
+        assert!(!cached);
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+}
@@ -1,28 +1,40 @@
 use axum::{
-    extract::{Path, State, Json},
+    extract::{Path, Query, State, Json},
     http::StatusCode,
     response::IntoResponse,
+    Extension,
 };
+use std::collections::HashMap;
+use std::sync::Arc;
+
 use sqlx::Pool;
 use sqlx::Postgres;
 use uuid::Uuid;
 use chrono::Utc;
 
-use crate::error::ApiError;
+use crate::error::{AppError, AppResult};
 use crate::models::collaboration::{
-    CodeReview, ReviewComment, ReviewApproval, CreateCodeReviewRequest,
+    CodeReview, ReviewComment, ReviewCommentThread, ReviewApproval, CreateCodeReviewRequest,
     UpdateCodeReviewRequest, AddReviewCommentRequest, UpdateReviewCommentRequest,
-    SubmitApprovalRequest, CodeReviewDetails, DiffStat,
+    SubmitApprovalRequest, CodeReviewDetails, DiffStat, ReviewStatus, ReviewSettings,
+    UpdateReviewSettingsRequest, ApprovalStatus,
 };
 use crate::middleware::rbac;
+use crate::services::audit::{self, AuditEntry, ClientInfo};
+use crate::services::diff;
+use crate::services::events::{DomainEvent, EventBus};
+use crate::middleware_auth::AuthUser;
+use crate::utils::etag::{ETagged, IfMatch};
+use crate::utils::pagination::{page_size, Cursor, Page, PageQuery};
+use crate::utils::validation::ValidatedJson;
 
 /// Create new code review
 pub async fn create_code_review(
     State(pool): State<Pool<Postgres>>,
     Path(project_id): Path<Uuid>,
-    user_id: Uuid,
-    Json(req): Json<CreateCodeReviewRequest>,
-) -> Result<impl IntoResponse, ApiError> {
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(req): ValidatedJson<CreateCodeReviewRequest>,
+) -> AppResult<impl IntoResponse> {
     // Check write permission
     rbac::enforce_permission(&pool, user_id, project_id, "write").await?;
 
@@ -68,8 +80,9 @@ pub async fn create_code_review(
 pub async fn get_code_review(
     State(pool): State<Pool<Postgres>>,
     Path((project_id, review_id)): Path<(Uuid, Uuid)>,
-    user_id: Uuid,
-) -> Result<impl IntoResponse, ApiError> {
+    AuthUser(user_id): AuthUser,
+    Query(page): Query<PageQuery>,
+) -> AppResult<impl IntoResponse> {
     // Check read permission
     rbac::enforce_permission(&pool, user_id, project_id, "read").await?;
 
@@ -80,14 +93,10 @@ pub async fn get_code_review(
     .bind(project_id)
     .fetch_optional(&pool)
     .await?
-    .ok_or(ApiError::NotFound)?;
+    .ok_or(AppError::NotFoundError("Code review not found".to_string()))?;
 
-    let comments = sqlx::query_as::<_, ReviewComment>(
-        "SELECT * FROM review_comments WHERE review_id = $1 ORDER BY created_at DESC"
-    )
-    .bind(review_id)
-    .fetch_all(&pool)
-    .await?;
+    let comments = fetch_review_comments(&pool, review_id, &page).await?;
+    let comments = thread_review_comments(&pool, comments).await?;
 
     let approvals = sqlx::query_as::<_, ReviewApproval>(
         "SELECT * FROM review_approvals WHERE review_id = $1"
@@ -96,8 +105,9 @@ pub async fn get_code_review(
     .fetch_all(&pool)
     .await?;
 
-    let diff_stats = compute_diff_stats(&review).await;
+    let diff_stats = compute_diff_stats(&pool, &review).await?;
 
+    let updated_at = review.updated_at;
     let details = CodeReviewDetails {
         review,
         comments,
@@ -105,63 +115,280 @@ pub async fn get_code_review(
         diff_stats,
     };
 
-    Ok(Json(details))
+    Ok(ETagged(updated_at, Json(details)))
+}
+
+/// One page of a review's top-level comments, newest first, keyed on (created_at, id)
+async fn fetch_review_comments(
+    pool: &Pool<Postgres>,
+    review_id: Uuid,
+    page: &PageQuery,
+) -> AppResult<Page<ReviewComment>> {
+    let after = match &page.cursor {
+        Some(cursor) => Some(
+            Cursor::decode(cursor).ok_or_else(|| AppError::ValidationError("Invalid cursor".to_string()))?,
+        ),
+        None => None,
+    };
+    let limit = page_size(page.limit);
+
+    let comments = sqlx::query_as::<_, ReviewComment>(
+        r#"
+        SELECT * FROM review_comments
+        WHERE review_id = $1 AND parent_comment_id IS NULL
+          AND ($2::timestamptz IS NULL OR (created_at, id) < ($2, $3))
+        ORDER BY created_at DESC, id DESC
+        LIMIT $4
+        "#
+    )
+    .bind(review_id)
+    .bind(after.map(|cursor| cursor.created_at))
+    .bind(after.map(|cursor| cursor.id))
+    .bind(limit + 1)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(Page::from_rows(comments, limit, |comment| Cursor::new(comment.created_at, comment.id)))
+}
+
+/// Hang every reply, however deep, beneath the top-level comments of `page`
+async fn thread_review_comments(
+    pool: &Pool<Postgres>,
+    page: Page<ReviewComment>,
+) -> AppResult<Page<ReviewCommentThread>> {
+    let root_ids: Vec<Uuid> = page.items.iter().map(|comment| comment.id).collect();
+    let replies = sqlx::query_as::<_, ReviewComment>(
+        r#"
+        WITH RECURSIVE thread AS (
+            SELECT * FROM review_comments WHERE parent_comment_id = ANY($1)
+            UNION ALL
+            SELECT c.* FROM review_comments c JOIN thread t ON c.parent_comment_id = t.id
+        )
+        SELECT * FROM thread ORDER BY created_at, id
+        "#
+    )
+    .bind(&root_ids)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(Page {
+        items: build_comment_threads(page.items, replies),
+        next_cursor: page.next_cursor,
+    })
 }
 
-/// Update code review
+/// Nest `replies` under their parents. Replies keep the order they're given in; any whose
+/// parent isn't among `roots` or `replies` are dropped.
+fn build_comment_threads(roots: Vec<ReviewComment>, replies: Vec<ReviewComment>) -> Vec<ReviewCommentThread> {
+    let mut children: HashMap<Uuid, Vec<ReviewComment>> = HashMap::new();
+    for reply in replies {
+        if let Some(parent_id) = reply.parent_comment_id {
+            children.entry(parent_id).or_default().push(reply);
+        }
+    }
+
+    fn attach(comment: ReviewComment, children: &mut HashMap<Uuid, Vec<ReviewComment>>) -> ReviewCommentThread {
+        let replies = children
+            .remove(&comment.id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|reply| attach(reply, children))
+            .collect();
+        ReviewCommentThread { comment, replies }
+    }
+
+    roots.into_iter().map(|root| attach(root, &mut children)).collect()
+}
+
+/// Update code review. With `If-Match`, the update only applies if the review hasn't
+/// changed since the client read that ETag.
 pub async fn update_code_review(
     State(pool): State<Pool<Postgres>>,
+    Extension(events): Extension<Arc<EventBus>>,
     Path((project_id, review_id)): Path<(Uuid, Uuid)>,
-    user_id: Uuid,
-    Json(req): Json<UpdateCodeReviewRequest>,
-) -> Result<impl IntoResponse, ApiError> {
+    AuthUser(user_id): AuthUser,
+    client: ClientInfo,
+    IfMatch(expected): IfMatch,
+    ValidatedJson(req): ValidatedJson<UpdateCodeReviewRequest>,
+) -> AppResult<ETagged<StatusCode>> {
+    // Reviews in projects the user can't see don't exist as far as they're concerned
+    rbac::enforce_project_visible(&pool, user_id, project_id).await?;
+
     // Check if user is author or admin
     let is_author = sqlx::query_scalar::<_, bool>(
-        "SELECT author_id = $1 FROM code_reviews WHERE id = $2"
+        "SELECT author_id = $1 FROM code_reviews WHERE id = $2 AND project_id = $3"
     )
     .bind(user_id)
     .bind(review_id)
+    .bind(project_id)
     .fetch_optional(&pool)
     .await?
-    .ok_or(ApiError::NotFound)?;
+    .ok_or(AppError::NotFoundError("Code review not found".to_string()))?;
 
     if !is_author {
         rbac::enforce_permission(&pool, user_id, project_id, "admin").await?;
     }
 
+    let status = match &req.status {
+        Some(value) => Some(
+            ReviewStatus::parse(value)
+                .ok_or_else(|| AppError::ValidationError(format!("Invalid review status: {}", value)))?,
+        ),
+        None => None,
+    };
+
+    let mut tx = pool.begin().await?;
+
+    let (current_status, before) = sqlx::query_as::<_, (String, serde_json::Value)>(
+        "SELECT status, jsonb_build_object('title', title, 'description', description, 'status', status) FROM code_reviews WHERE id = $1 FOR UPDATE"
+    )
+    .bind(review_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFoundError("Code review not found".to_string()))?;
+
+    // Asking for the status the review already has changes nothing. A status this server
+    // doesn't know can't be checked against the table, so it may move anywhere.
+    let status = status.filter(|status| status.as_str() != current_status);
+    if let Some(next) = status {
+        if let Some(current) = ReviewStatus::parse(&current_status) {
+            if !current.can_transition_to(next) {
+                return Err(AppError::ConflictError(format!(
+                    "A {} review can't be moved to {}",
+                    current.as_str(),
+                    next.as_str()
+                )));
+            }
+        }
+    }
+
+    if status == Some(ReviewStatus::Merged) {
+        let settings = load_review_settings(&pool, project_id).await?;
+        // The author's own verdict never counts, even one recorded before it was refused
+        let approvals = sqlx::query_scalar::<_, String>(
+            r#"
+            SELECT a.status FROM review_approvals a
+            JOIN code_reviews r ON r.id = a.review_id
+            WHERE a.review_id = $1 AND a.reviewer_id <> r.author_id
+            "#,
+        )
+        .bind(review_id)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        check_merge_allowed(&settings, &approvals)?;
+    }
+
+    // Finishing a review stamps closed_at; reopening one clears it
     let now = Utc::now();
+    let closed_at = match status {
+        Some(status) if status.is_closed() => Some(now),
+        _ => None,
+    };
 
-    sqlx::query(
+    let (updated_at, after) = sqlx::query_as::<_, (chrono::DateTime<Utc>, serde_json::Value)>(
         r#"
         UPDATE code_reviews 
         SET 
             title = COALESCE($1, title),
             description = COALESCE($2, description),
             status = COALESCE($3, status),
-            updated_at = $4
-        WHERE id = $5
+            updated_at = $4,
+            closed_at = CASE WHEN $3 IS NULL THEN closed_at ELSE $5 END
+        WHERE id = $6 AND ($7::timestamptz IS NULL OR updated_at = $7)
+        RETURNING updated_at, jsonb_build_object('title', title, 'description', description, 'status', status)
         "#,
     )
     .bind(&req.title)
     .bind(&req.description)
-    .bind(&req.status)
+    .bind(status.map(|status| status.as_str().to_string()))
     .bind(now)
+    .bind(closed_at)
     .bind(review_id)
-    .execute(&pool)
+    .bind(expected)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| match expected {
+        Some(_) => AppError::ConflictError(
+            "Stale update: the review has changed since you loaded it, reload and try again".to_string(),
+        ),
+        None => AppError::NotFoundError("Code review not found".to_string()),
+    })?;
+
+    audit::record(
+        &mut *tx,
+        &client,
+        AuditEntry::new(user_id, "update_code_review", "code_review", review_id)
+            .old_value(before)
+            .new_value(after),
+    )
     .await?;
+    tx.commit().await?;
 
-    Ok(StatusCode::OK)
+    let event = match status {
+        Some(ReviewStatus::Merged) => Some(DomainEvent::ReviewMerged {
+            project_id,
+            review_id,
+            previous_status: current_status,
+            merged_by: user_id,
+        }),
+        Some(status) => Some(DomainEvent::ReviewStatusChanged {
+            project_id,
+            review_id,
+            previous_status: current_status,
+            status: status.as_str().to_string(),
+            changed_by: user_id,
+        }),
+        None => None,
+    };
+    if let Some(event) = event {
+        events.publish(event).await;
+    }
+
+    Ok(ETagged(updated_at, StatusCode::OK))
 }
 
 /// Add comment to review
 pub async fn add_review_comment(
     State(pool): State<Pool<Postgres>>,
     Path((project_id, review_id)): Path<(Uuid, Uuid)>,
-    user_id: Uuid,
-    Json(req): Json<AddReviewCommentRequest>,
-) -> Result<impl IntoResponse, ApiError> {
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(req): ValidatedJson<AddReviewCommentRequest>,
+) -> AppResult<impl IntoResponse> {
     // Check write permission
     rbac::enforce_permission(&pool, user_id, project_id, "write").await?;
+    ensure_review_in_project(&pool, project_id, review_id).await?;
+
+    if let Some(parent_id) = req.parent_comment_id {
+        let same_review = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM review_comments WHERE id = $1 AND review_id = $2)"
+        )
+        .bind(parent_id)
+        .bind(review_id)
+        .fetch_one(&pool)
+        .await?;
+        if !same_review {
+            return Err(AppError::ValidationError(
+                "Replies must answer a comment on the same review".to_string(),
+            ));
+        }
+    }
+
+    // Line comments are anchored to the file's latest version, so they can follow later edits
+    let file_version = match (&req.file_path, req.line_number) {
+        (Some(file_path), Some(_)) => sqlx::query_scalar::<_, Option<i32>>(
+            r#"
+            SELECT MAX(v.version_number) FROM document_versions v
+            JOIN code_files f ON f.id = v.file_id
+            WHERE f.project_id = $1 AND f.file_path = $2
+            "#
+        )
+        .bind(project_id)
+        .bind(file_path)
+        .fetch_one(&pool)
+        .await?,
+        _ => None,
+    };
 
     let comment_id = Uuid::new_v4();
     let now = Utc::now();
@@ -169,15 +396,17 @@ pub async fn add_review_comment(
     sqlx::query(
         r#"
         INSERT INTO review_comments 
-        (id, review_id, author_id, file_path, line_number, content, created_at, updated_at)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $7)
+        (id, review_id, author_id, parent_comment_id, file_path, line_number, file_version, content, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $9)
         "#,
     )
     .bind(comment_id)
     .bind(review_id)
     .bind(user_id)
+    .bind(req.parent_comment_id)
     .bind(&req.file_path)
     .bind(req.line_number)
+    .bind(file_version)
     .bind(&req.content)
     .bind(now)
     .execute(&pool)
@@ -187,8 +416,11 @@ pub async fn add_review_comment(
         id: comment_id,
         review_id,
         author_id: user_id,
+        parent_comment_id: req.parent_comment_id,
         file_path: req.file_path,
         line_number: req.line_number,
+        file_version,
+        outdated: false,
         content: req.content,
         resolved: false,
         created_at: now,
@@ -202,21 +434,30 @@ pub async fn add_review_comment(
 pub async fn update_review_comment(
     State(pool): State<Pool<Postgres>>,
     Path((project_id, review_id, comment_id)): Path<(Uuid, Uuid, Uuid)>,
-    user_id: Uuid,
-    Json(req): Json<UpdateReviewCommentRequest>,
-) -> Result<impl IntoResponse, ApiError> {
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(req): ValidatedJson<UpdateReviewCommentRequest>,
+) -> AppResult<impl IntoResponse> {
+    rbac::enforce_project_visible(&pool, user_id, project_id).await?;
+
     // Check if user is comment author
     let is_author = sqlx::query_scalar::<_, bool>(
-        "SELECT author_id = $1 FROM review_comments WHERE id = $2"
+        r#"
+        SELECT c.author_id = $1 FROM review_comments c
+        JOIN code_reviews r ON r.id = c.review_id
+        WHERE c.id = $2 AND c.review_id = $3 AND r.project_id = $4
+        "#,
     )
     .bind(user_id)
     .bind(comment_id)
+    .bind(review_id)
+    .bind(project_id)
     .fetch_optional(&pool)
     .await?
-    .ok_or(ApiError::NotFound)?;
+    .ok_or(AppError::NotFoundError("Comment not found".to_string()))?;
 
+    // The comment is visible, so refusing the edit leaks nothing
     if !is_author {
-        return Err(ApiError::Forbidden);
+        return Err(rbac::access_denied(true));
     }
 
     let now = Utc::now();
@@ -241,15 +482,113 @@ pub async fn update_review_comment(
     Ok(StatusCode::OK)
 }
 
+/// Resolve a comment and every reply beneath it. The comment's author or a project admin
+/// may do this; returns the number of comments newly resolved.
+pub async fn resolve_review_comment(
+    State(pool): State<Pool<Postgres>>,
+    Path((project_id, review_id, comment_id)): Path<(Uuid, Uuid, Uuid)>,
+    AuthUser(user_id): AuthUser,
+) -> AppResult<Json<serde_json::Value>> {
+    rbac::enforce_project_visible(&pool, user_id, project_id).await?;
+
+    let author_id = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        SELECT c.author_id FROM review_comments c
+        JOIN code_reviews r ON r.id = c.review_id
+        WHERE c.id = $1 AND c.review_id = $2 AND r.project_id = $3
+        "#,
+    )
+    .bind(comment_id)
+    .bind(review_id)
+    .bind(project_id)
+    .fetch_optional(&pool)
+    .await?
+    .ok_or(AppError::NotFoundError("Comment not found".to_string()))?;
+
+    if author_id != user_id && !is_project_admin(&pool, user_id, project_id).await? {
+        return Err(rbac::access_denied(true));
+    }
+
+    let resolved = sqlx::query(
+        r#"
+        WITH RECURSIVE thread AS (
+            SELECT id FROM review_comments WHERE id = $1
+            UNION ALL
+            SELECT c.id FROM review_comments c JOIN thread t ON c.parent_comment_id = t.id
+        )
+        UPDATE review_comments SET resolved = TRUE, updated_at = $2
+        WHERE id IN (SELECT id FROM thread) AND NOT resolved
+        "#,
+    )
+    .bind(comment_id)
+    .bind(Utc::now())
+    .execute(&pool)
+    .await?
+    .rows_affected();
+
+    Ok(Json(serde_json::json!({ "resolved": resolved })))
+}
+
+/// Resolve every open comment on a review. The review's author or a project admin may do
+/// this; returns the number of comments newly resolved.
+pub async fn resolve_all_review_comments(
+    State(pool): State<Pool<Postgres>>,
+    Path((project_id, review_id)): Path<(Uuid, Uuid)>,
+    AuthUser(user_id): AuthUser,
+) -> AppResult<Json<serde_json::Value>> {
+    rbac::enforce_project_visible(&pool, user_id, project_id).await?;
+
+    let author_id = sqlx::query_scalar::<_, Uuid>(
+        "SELECT author_id FROM code_reviews WHERE id = $1 AND project_id = $2"
+    )
+    .bind(review_id)
+    .bind(project_id)
+    .fetch_optional(&pool)
+    .await?
+    .ok_or(AppError::NotFoundError("Code review not found".to_string()))?;
+
+    if author_id != user_id && !is_project_admin(&pool, user_id, project_id).await? {
+        return Err(rbac::access_denied(true));
+    }
+
+    let resolved = sqlx::query(
+        "UPDATE review_comments SET resolved = TRUE, updated_at = $2 WHERE review_id = $1 AND NOT resolved"
+    )
+    .bind(review_id)
+    .bind(Utc::now())
+    .execute(&pool)
+    .await?
+    .rows_affected();
+
+    Ok(Json(serde_json::json!({ "resolved": resolved })))
+}
+
+/// The project's owner, or a member holding the `admin` permission
+async fn is_project_admin(pool: &Pool<Postgres>, user_id: Uuid, project_id: Uuid) -> AppResult<bool> {
+    Ok(rbac::check_project_admin(pool, user_id, project_id).await?
+        || rbac::check_project_permission(pool, user_id, project_id, "admin").await?)
+}
+
 /// Submit review approval
 pub async fn submit_approval(
     State(pool): State<Pool<Postgres>>,
     Path((project_id, review_id)): Path<(Uuid, Uuid)>,
-    user_id: Uuid,
-    Json(req): Json<SubmitApprovalRequest>,
-) -> Result<impl IntoResponse, ApiError> {
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(req): ValidatedJson<SubmitApprovalRequest>,
+) -> AppResult<impl IntoResponse> {
     // Check write permission
     rbac::enforce_permission(&pool, user_id, project_id, "write").await?;
+    ensure_review_in_project(&pool, project_id, review_id).await?;
+
+    let author_id = sqlx::query_scalar::<_, Uuid>("SELECT author_id FROM code_reviews WHERE id = $1")
+        .bind(review_id)
+        .fetch_one(&pool)
+        .await?;
+    if author_id == user_id {
+        return Err(AppError::ValidationError(
+            "Authors can't review their own code review".to_string(),
+        ));
+    }
 
     let approval_id = Uuid::new_v4();
     let now = Utc::now();
@@ -290,10 +629,11 @@ pub async fn submit_approval(
 pub async fn get_approvals(
     State(pool): State<Pool<Postgres>>,
     Path((project_id, review_id)): Path<(Uuid, Uuid)>,
-    user_id: Uuid,
-) -> Result<impl IntoResponse, ApiError> {
+    AuthUser(user_id): AuthUser,
+) -> AppResult<impl IntoResponse> {
     // Check read permission
     rbac::enforce_permission(&pool, user_id, project_id, "read").await?;
+    ensure_review_in_project(&pool, project_id, review_id).await?;
 
     let approvals = sqlx::query_as::<_, ReviewApproval>(
         "SELECT * FROM review_approvals WHERE review_id = $1"
@@ -305,13 +645,838 @@ pub async fn get_approvals(
     Ok(Json(approvals))
 }
 
-/// Compute diff statistics (placeholder for actual diff engine)
-async fn compute_diff_stats(review: &CodeReview) -> Vec<DiffStat> {
-    vec![
-        DiffStat {
-            file_path: "src/main.rs".to_string(),
-            additions: 42,
-            deletions: 10,
+/// Get the project's review settings
+pub async fn get_review_settings(
+    State(pool): State<Pool<Postgres>>,
+    Path(project_id): Path<Uuid>,
+    AuthUser(user_id): AuthUser,
+) -> AppResult<impl IntoResponse> {
+    rbac::enforce_permission(&pool, user_id, project_id, "read").await?;
+
+    let settings = load_review_settings(&pool, project_id).await?;
+
+    Ok(Json(settings))
+}
+
+/// Update the project's review settings
+pub async fn update_review_settings(
+    State(pool): State<Pool<Postgres>>,
+    Path(project_id): Path<Uuid>,
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(req): ValidatedJson<UpdateReviewSettingsRequest>,
+) -> AppResult<impl IntoResponse> {
+    rbac::enforce_permission(&pool, user_id, project_id, "admin").await?;
+
+    let defaults = ReviewSettings::default_for(project_id);
+
+    let (required_approvals, require_no_changes_requested) = sqlx::query_as::<_, (i32, bool)>(
+        r#"
+        INSERT INTO review_settings (project_id, required_approvals, require_no_changes_requested)
+        VALUES ($1, COALESCE($2, $4), COALESCE($3, $5))
+        ON CONFLICT (project_id) DO UPDATE SET
+        required_approvals = COALESCE($2, review_settings.required_approvals),
+        require_no_changes_requested = COALESCE($3, review_settings.require_no_changes_requested),
+        updated_at = NOW()
+        RETURNING required_approvals, require_no_changes_requested
+        "#,
+    )
+    .bind(project_id)
+    .bind(req.required_approvals)
+    .bind(req.require_no_changes_requested)
+    .bind(defaults.required_approvals)
+    .bind(defaults.require_no_changes_requested)
+    .fetch_one(&pool)
+    .await?;
+
+    Ok(Json(ReviewSettings {
+        project_id,
+        required_approvals,
+        require_no_changes_requested,
+    }))
+}
+
+/// 404 unless the review belongs to the project the caller was authorized against
+async fn ensure_review_in_project(
+    pool: &Pool<Postgres>,
+    project_id: Uuid,
+    review_id: Uuid,
+) -> AppResult<()> {
+    let exists = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM code_reviews WHERE id = $1 AND project_id = $2)"
+    )
+    .bind(review_id)
+    .bind(project_id)
+    .fetch_one(pool)
+    .await?;
+
+    if !exists {
+        return Err(AppError::NotFoundError("Code review not found".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Load review settings for a project, falling back to the defaults
+async fn load_review_settings(
+    pool: &Pool<Postgres>,
+    project_id: Uuid,
+) -> AppResult<ReviewSettings> {
+    let row = sqlx::query_as::<_, (i32, bool)>(
+        "SELECT required_approvals, require_no_changes_requested FROM review_settings WHERE project_id = $1"
+    )
+    .bind(project_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(match row {
+        Some((required_approvals, require_no_changes_requested)) => ReviewSettings {
+            project_id,
+            required_approvals,
+            require_no_changes_requested,
         },
-    ]
+        None => ReviewSettings::default_for(project_id),
+    })
+}
+
+/// Check a review's approvals against the project's merge rules
+fn check_merge_allowed(settings: &ReviewSettings, approvals: &[String]) -> AppResult<()> {
+    let approved = approvals
+        .iter()
+        .filter(|status| status.as_str() == ApprovalStatus::Approved.as_str())
+        .count();
+    let changes_requested = approvals
+        .iter()
+        .any(|status| status.as_str() == ApprovalStatus::ChangesRequested.as_str());
+
+    if settings.require_no_changes_requested && changes_requested {
+        return Err(AppError::ValidationError(
+            "Cannot merge a review with outstanding change requests".to_string(),
+        ));
+    }
+
+    if (approved as i64) < settings.required_approvals as i64 {
+        return Err(AppError::ValidationError(format!(
+            "Review needs {} approval(s) before merging, has {}",
+            settings.required_approvals, approved
+        )));
+    }
+
+    Ok(())
+}
+
+/// Compute per-file diff statistics between the review's source and target branches
+async fn compute_diff_stats(
+    pool: &Pool<Postgres>,
+    review: &CodeReview,
+) -> AppResult<Vec<DiffStat>> {
+    let (Some(source_branch), Some(target_branch)) =
+        (&review.source_branch, &review.target_branch)
+    else {
+        return Ok(Vec::new());
+    };
+
+    let source = load_branch_files(pool, review.project_id, source_branch).await?;
+    let target = load_branch_files(pool, review.project_id, target_branch).await?;
+
+    Ok(diff::diff_file_sets(&source, &target))
+}
+
+/// Load file contents for a project branch, keyed by path
+async fn load_branch_files(
+    pool: &Pool<Postgres>,
+    project_id: Uuid,
+    branch: &str,
+) -> AppResult<HashMap<String, String>> {
+    let files = sqlx::query_as::<_, (String, String)>(
+        "SELECT file_path, content FROM code_files WHERE project_id = $1 AND branch = $2"
+    )
+    .bind(project_id)
+    .bind(branch)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(files.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statuses(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_under_approved_merge_is_rejected() {
+        let settings = ReviewSettings {
+            project_id: Uuid::new_v4(),
+            required_approvals: 2,
+            require_no_changes_requested: true,
+        };
+
+        assert!(check_merge_allowed(&settings, &[]).is_err());
+        assert!(check_merge_allowed(&settings, &statuses(&["approved", "commented"])).is_err());
+        assert!(
+            check_merge_allowed(&settings, &statuses(&["approved", "approved", "changes_requested"]))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_approved_merge_is_allowed() {
+        let mut settings = ReviewSettings {
+            project_id: Uuid::new_v4(),
+            required_approvals: 2,
+            require_no_changes_requested: true,
+        };
+
+        assert!(check_merge_allowed(&settings, &statuses(&["approved", "approved"])).is_ok());
+
+        settings.require_no_changes_requested = false;
+        assert!(
+            check_merge_allowed(&settings, &statuses(&["approved", "approved", "changes_requested"]))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_review_status_parse() {
+        assert_eq!(ReviewStatus::parse("merged"), Some(ReviewStatus::Merged));
+        assert_eq!(
+            ReviewStatus::parse("changes_requested"),
+            Some(ReviewStatus::ChangesRequested)
+        );
+        assert_eq!(ReviewStatus::parse("shipped"), None);
+        assert_eq!(ReviewStatus::parse("Merged"), None);
+    }
+
+    #[test]
+    fn test_review_status_transitions() {
+        use ReviewStatus::*;
+        let all = [Open, Approved, ChangesRequested, Merged, Closed];
+        let allowed = [
+            (Open, Approved),
+            (Open, ChangesRequested),
+            (Open, Merged),
+            (Open, Closed),
+            (Approved, Merged),
+            (Approved, ChangesRequested),
+            (Approved, Closed),
+            (ChangesRequested, Open),
+            (ChangesRequested, Approved),
+            (ChangesRequested, Closed),
+            (Closed, Open),
+        ];
+
+        for from in all {
+            for to in all {
+                assert_eq!(
+                    from.can_transition_to(to),
+                    allowed.contains(&(from, to)),
+                    "{} -> {}",
+                    from.as_str(),
+                    to.as_str()
+                );
+            }
+        }
+        assert!(Merged.next_statuses().is_empty());
+        assert!(Merged.is_closed() && Closed.is_closed());
+        assert!(!Open.is_closed() && !Approved.is_closed() && !ChangesRequested.is_closed());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_merge_requires_approvals() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool().clone();
+
+        let author_id = crate::db::insert_test_user(&pool).await;
+        let reviewer_id = crate::db::insert_test_user(&pool).await;
+
+        let project_id = crate::db::insert_test_project(&pool, author_id, "review-rules").await;
+
+        let review_id = Uuid::new_v4();
+        sqlx::query(
+            "INSERT INTO code_reviews (id, project_id, author_id, title) VALUES ($1, $2, $3, $4)"
+        )
+        .bind(review_id)
+        .bind(project_id)
+        .bind(author_id)
+        .bind("Add feature")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let merge = || UpdateCodeReviewRequest {
+            title: None,
+            description: None,
+            status: Some("merged".to_string()),
+        };
+
+        let events = Arc::new(EventBus::new(16));
+        let mut published = events.subscribe();
+
+        let result = update_code_review(
+            State(pool.clone()),
+            Extension(events.clone()),
+            Path((project_id, review_id)),
+            AuthUser(author_id),
+            ClientInfo::default(),
+            IfMatch(None),
+            ValidatedJson(merge()),
+        )
+        .await;
+        assert!(result.is_err());
+
+        // Authors can't approve their own review, and an approval of theirs already on file doesn't count
+        let approve = || SubmitApprovalRequest {
+            status: "approved".to_string(),
+            comments: None,
+        };
+        assert!(matches!(
+            submit_approval(State(pool.clone()), Path((project_id, review_id)), AuthUser(author_id), ValidatedJson(approve())).await,
+            Err(AppError::ValidationError(_))
+        ));
+        sqlx::query(
+            "INSERT INTO review_approvals (id, review_id, reviewer_id, status) VALUES ($1, $2, $3, 'approved')"
+        )
+        .bind(Uuid::new_v4())
+        .bind(review_id)
+        .bind(author_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+        let result = update_code_review(
+            State(pool.clone()),
+            Extension(events.clone()),
+            Path((project_id, review_id)),
+            AuthUser(author_id),
+            ClientInfo::default(),
+            IfMatch(None),
+            ValidatedJson(merge()),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::ValidationError(_))));
+
+        sqlx::query(
+            "INSERT INTO review_approvals (id, review_id, reviewer_id, status) VALUES ($1, $2, $3, 'approved')"
+        )
+        .bind(Uuid::new_v4())
+        .bind(review_id)
+        .bind(reviewer_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let result = update_code_review(
+            State(pool.clone()),
+            Extension(events.clone()),
+            Path((project_id, review_id)),
+            AuthUser(author_id),
+            ClientInfo::default(),
+            IfMatch(None),
+            ValidatedJson(merge()),
+        )
+        .await;
+        assert!(result.is_ok());
+        // Only the merge that went through is announced
+        assert!(matches!(
+            published.try_recv(),
+            Ok(DomainEvent::ReviewMerged { review_id: merged, merged_by, .. }) if merged == review_id && merged_by == author_id
+        ));
+
+        let status: String = sqlx::query_scalar("SELECT status FROM code_reviews WHERE id = $1")
+            .bind(review_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(status, "merged");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_outsider_gets_not_found_for_existing_review() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool().clone();
+
+        let author_id = crate::db::insert_test_user(&pool).await;
+        let reader_id = crate::db::insert_test_user(&pool).await;
+        let outsider_id = crate::db::insert_test_user(&pool).await;
+
+        let project_id = crate::db::insert_test_project(&pool, author_id, "private-reviews").await;
+        sqlx::query(
+            "INSERT INTO project_members (id, project_id, user_id, role, permissions) VALUES ($1, $2, $3, 'viewer', ARRAY['read'])",
+        )
+        .bind(Uuid::new_v4())
+        .bind(project_id)
+        .bind(reader_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let review_id = Uuid::new_v4();
+        sqlx::query(
+            "INSERT INTO code_reviews (id, project_id, author_id, title) VALUES ($1, $2, $3, $4)"
+        )
+        .bind(review_id)
+        .bind(project_id)
+        .bind(author_id)
+        .bind("Secret feature")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let close = || UpdateCodeReviewRequest {
+            title: None,
+            description: None,
+            status: Some("closed".to_string()),
+        };
+        let update_as = |user_id| {
+            update_code_review(State(pool.clone()), Extension(Arc::new(EventBus::new(16))), Path((project_id, review_id)), AuthUser(user_id), ClientInfo::default(), IfMatch(None), ValidatedJson(close()))
+        };
+
+        // Same answer whether or not the review exists
+        assert!(matches!(update_as(outsider_id).await, Err(AppError::NotFoundError(_))));
+        let missing = update_code_review(
+            State(pool.clone()),
+            Extension(Arc::new(EventBus::new(16))),
+            Path((project_id, Uuid::new_v4())),
+            AuthUser(outsider_id),
+            ClientInfo::default(),
+            IfMatch(None),
+            ValidatedJson(close()),
+        )
+        .await;
+        assert!(matches!(missing, Err(AppError::NotFoundError(_))));
+        assert!(matches!(
+            get_approvals(State(pool.clone()), Path((project_id, review_id)), AuthUser(outsider_id)).await,
+            Err(AppError::NotFoundError(_))
+        ));
+
+        // A reader can see the review but isn't its author or a project admin
+        assert!(matches!(update_as(reader_id).await, Err(AppError::AuthorizationError(_))));
+        assert!(update_as(author_id).await.is_ok());
+    }
+
+    /// A review by a fresh user on their own project, where they also hold `read` as a member
+    async fn insert_review(pool: &Pool<Postgres>, title: &str) -> (Uuid, Uuid, Uuid) {
+        let author_id = crate::db::insert_test_user(pool).await;
+        let project_id = crate::db::insert_test_project(pool, author_id, title).await;
+        sqlx::query(
+            "INSERT INTO project_members (id, project_id, user_id, role, permissions) VALUES ($1, $2, $3, 'member', ARRAY['read'])"
+        )
+        .bind(Uuid::new_v4())
+        .bind(project_id)
+        .bind(author_id)
+        .execute(pool)
+        .await
+        .unwrap();
+        let review_id = Uuid::new_v4();
+        sqlx::query(
+            "INSERT INTO code_reviews (id, project_id, author_id, title) VALUES ($1, $2, $3, $4)"
+        )
+        .bind(review_id)
+        .bind(project_id)
+        .bind(author_id)
+        .bind(title)
+        .execute(pool)
+        .await
+        .unwrap();
+
+        (author_id, project_id, review_id)
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_review_comment_pages_have_no_overlap_or_gaps() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool().clone();
+        let (author_id, project_id, review_id) = insert_review(&pool, "Long discussion").await;
+
+        // Comments posted in the same instant still land on exactly one page
+        let now = Utc::now();
+        let mut expected = Vec::new();
+        for seconds in [0, 0, 0, 1, 2] {
+            let id = Uuid::new_v4();
+            let created_at = now - chrono::Duration::seconds(seconds);
+            sqlx::query(
+                "INSERT INTO review_comments (id, review_id, author_id, content, created_at, updated_at) VALUES ($1, $2, $3, 'looks good', $4, $4)"
+            )
+            .bind(id)
+            .bind(review_id)
+            .bind(author_id)
+            .bind(created_at)
+            .execute(&pool)
+            .await
+            .unwrap();
+            expected.push(Cursor::new(created_at, id));
+        }
+        expected.sort_by_key(|c| std::cmp::Reverse((c.created_at, c.id)));
+
+        let mut page = PageQuery { limit: Some(3), cursor: None };
+        let first = fetch_review_comments(&pool, review_id, &page).await.unwrap();
+        assert_eq!(first.items.len(), 3);
+        page.cursor = first.next_cursor.clone();
+        assert!(page.cursor.is_some());
+
+        let second = fetch_review_comments(&pool, review_id, &page).await.unwrap();
+        assert_eq!(second.next_cursor, None);
+
+        let walked: Vec<Uuid> = first.items.iter().chain(&second.items).map(|comment| comment.id).collect();
+        let expected: Vec<Uuid> = expected.iter().map(|cursor| cursor.id).collect();
+        assert_eq!(walked, expected);
+
+        let result = get_code_review(
+            State(pool.clone()),
+            Path((project_id, review_id)),
+            AuthUser(author_id),
+            Query(PageQuery { limit: None, cursor: Some("%%%".to_string()) }),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::ValidationError(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_stale_review_update_conflicts() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool().clone();
+        let (author_id, project_id, review_id) = insert_review(&pool, "Contested").await;
+
+        // Two tabs load the review and both see the same version
+        let loaded: chrono::DateTime<Utc> = sqlx::query_scalar("SELECT updated_at FROM code_reviews WHERE id = $1")
+            .bind(review_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let retitle = |title: &str| UpdateCodeReviewRequest {
+            title: Some(title.to_string()),
+            description: None,
+            status: None,
+        };
+
+        let first = update_code_review(
+            State(pool.clone()),
+            Extension(Arc::new(EventBus::new(16))),
+            Path((project_id, review_id)),
+            AuthUser(author_id),
+            ClientInfo::default(),
+            IfMatch(Some(loaded)),
+            ValidatedJson(retitle("First")),
+        )
+        .await;
+        let ETagged(saved, _) = first.unwrap();
+        assert!(saved > loaded);
+
+        let second = update_code_review(
+            State(pool.clone()),
+            Extension(Arc::new(EventBus::new(16))),
+            Path((project_id, review_id)),
+            AuthUser(author_id),
+            ClientInfo::default(),
+            IfMatch(Some(loaded)),
+            ValidatedJson(retitle("Second")),
+        )
+        .await;
+        assert!(matches!(second, Err(AppError::ConflictError(_))));
+
+        let title: String = sqlx::query_scalar("SELECT title FROM code_reviews WHERE id = $1")
+            .bind(review_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(title, "First");
+
+        // After reloading, the second tab's edit goes through
+        let retry = update_code_review(
+            State(pool.clone()),
+            Extension(Arc::new(EventBus::new(16))),
+            Path((project_id, review_id)),
+            AuthUser(author_id),
+            ClientInfo::default(),
+            IfMatch(Some(saved)),
+            ValidatedJson(retitle("Second")),
+        )
+        .await;
+        assert!(retry.is_ok());
+    }
+
+    /// Post a comment through the handler and return its ID
+    async fn post_comment(
+        pool: &Pool<Postgres>,
+        project_id: Uuid,
+        review_id: Uuid,
+        author_id: Uuid,
+        parent_comment_id: Option<Uuid>,
+    ) -> AppResult<Uuid> {
+        let response = add_review_comment(
+            State(pool.clone()),
+            Path((project_id, review_id)),
+            AuthUser(author_id),
+            ValidatedJson(AddReviewCommentRequest {
+                file_path: None,
+                line_number: None,
+                content: "What about the error path?".to_string(),
+                parent_comment_id,
+            }),
+        )
+        .await?
+        .into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let comment: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        Ok(comment["id"].as_str().unwrap().parse().unwrap())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_threaded_comments_resolve_together() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool().clone();
+        let (author_id, project_id, review_id) = insert_review(&pool, "Threaded").await;
+        let reviewer_id = crate::db::insert_test_user(&pool).await;
+        let bystander_id = crate::db::insert_test_user(&pool).await;
+        for id in [reviewer_id, bystander_id] {
+            sqlx::query(
+                "INSERT INTO project_members (id, project_id, user_id, role, permissions) VALUES ($1, $2, $3, 'member', ARRAY['read', 'write'])"
+            )
+            .bind(Uuid::new_v4())
+            .bind(project_id)
+            .bind(id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        sqlx::query("UPDATE project_members SET permissions = ARRAY['read', 'write'] WHERE project_id = $1 AND user_id = $2")
+            .bind(project_id)
+            .bind(author_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        // reviewer: root -> author: reply -> reviewer: reply to the reply
+        let root = post_comment(&pool, project_id, review_id, reviewer_id, None).await.unwrap();
+        let reply = post_comment(&pool, project_id, review_id, author_id, Some(root)).await.unwrap();
+        let nested = post_comment(&pool, project_id, review_id, reviewer_id, Some(reply)).await.unwrap();
+        let other = post_comment(&pool, project_id, review_id, bystander_id, None).await.unwrap();
+
+        // A comment on another review of the same project can't be answered from this one
+        let elsewhere = Uuid::new_v4();
+        sqlx::query("INSERT INTO code_reviews (id, project_id, author_id, title) VALUES ($1, $2, $3, 'Elsewhere')")
+            .bind(elsewhere)
+            .bind(project_id)
+            .bind(author_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let foreign = post_comment(&pool, project_id, elsewhere, reviewer_id, None).await.unwrap();
+        assert!(matches!(
+            post_comment(&pool, project_id, review_id, reviewer_id, Some(foreign)).await,
+            Err(AppError::ValidationError(_))
+        ));
+
+        let page = fetch_review_comments(&pool, review_id, &PageQuery { limit: None, cursor: None }).await.unwrap();
+        let threads = thread_review_comments(&pool, page).await.unwrap().items;
+        assert_eq!(threads.iter().map(|thread| thread.comment.id).collect::<Vec<_>>(), vec![other, root]);
+        let thread = &threads[1];
+        assert_eq!(thread.replies.len(), 1);
+        assert_eq!(thread.replies[0].comment.id, reply);
+        assert_eq!(thread.replies[0].replies[0].comment.id, nested);
+        assert!(threads[0].replies.is_empty());
+
+        // Only the comment's author or an admin resolves it, and the whole thread goes with it
+        let resolve = |user_id, comment_id| {
+            resolve_review_comment(State(pool.clone()), Path((project_id, review_id, comment_id)), AuthUser(user_id))
+        };
+        assert!(matches!(resolve(bystander_id, root).await, Err(AppError::AuthorizationError(_))));
+        let Json(resolved) = resolve(reviewer_id, root).await.unwrap();
+        assert_eq!(resolved["resolved"], 3);
+        let Json(resolved) = resolve(author_id, root).await.unwrap();
+        assert_eq!(resolved["resolved"], 0);
+
+        let resolve_all = |user_id| resolve_all_review_comments(State(pool.clone()), Path((project_id, review_id)), AuthUser(user_id));
+        assert!(matches!(resolve_all(reviewer_id).await, Err(AppError::AuthorizationError(_))));
+        let Json(resolved) = resolve_all(author_id).await.unwrap();
+        assert_eq!(resolved["resolved"], 1);
+        let open = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM review_comments WHERE review_id = $1 AND NOT resolved")
+            .bind(review_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(open, 0);
+    }
+
+    #[test]
+    fn test_replies_nest_under_their_parents() {
+        let comment = |parent_comment_id| ReviewComment {
+            id: Uuid::new_v4(),
+            review_id: Uuid::nil(),
+            author_id: Uuid::nil(),
+            parent_comment_id,
+            file_path: None,
+            line_number: None,
+            file_version: None,
+            outdated: false,
+            content: String::new(),
+            resolved: false,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let root = comment(None);
+        let first = comment(Some(root.id));
+        let second = comment(Some(root.id));
+        let nested = comment(Some(first.id));
+        let orphan = comment(Some(Uuid::new_v4()));
+
+        let threads = build_comment_threads(
+            vec![root.clone()],
+            vec![first.clone(), nested.clone(), second.clone(), orphan],
+        );
+        assert_eq!(threads.len(), 1);
+        let replies: Vec<Uuid> = threads[0].replies.iter().map(|thread| thread.comment.id).collect();
+        assert_eq!(replies, vec![first.id, second.id]);
+        assert_eq!(threads[0].replies[0].replies[0].comment.id, nested.id);
+        assert!(threads[0].replies[1].replies.is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_illegal_review_transition_conflicts() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool().clone();
+        let (author_id, project_id, review_id) = insert_review(&pool, "One way only").await;
+        let move_to = |status: &str| {
+            update_code_review(
+                State(pool.clone()),
+                Extension(Arc::new(EventBus::new(16))),
+                Path((project_id, review_id)),
+                AuthUser(author_id),
+                ClientInfo::default(),
+                IfMatch(None),
+                ValidatedJson(UpdateCodeReviewRequest {
+                    title: None,
+                    description: None,
+                    status: Some(status.to_string()),
+                }),
+            )
+        };
+        let closed_at = || async {
+            sqlx::query_scalar::<_, Option<chrono::DateTime<Utc>>>("SELECT closed_at FROM code_reviews WHERE id = $1")
+                .bind(review_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+        };
+
+        // Closing stamps closed_at, reopening clears it
+        move_to("closed").await.unwrap();
+        assert!(closed_at().await.is_some());
+        assert!(matches!(move_to("approved").await, Err(AppError::ConflictError(_))));
+        move_to("open").await.unwrap();
+        assert!(closed_at().await.is_none());
+
+        // Once merged, the review stays merged
+        let reviewer_id = crate::db::insert_test_user(&pool).await;
+        sqlx::query("INSERT INTO review_approvals (id, review_id, reviewer_id, status) VALUES ($1, $2, $3, 'approved')")
+            .bind(Uuid::new_v4())
+            .bind(review_id)
+            .bind(reviewer_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        move_to("approved").await.unwrap();
+        move_to("merged").await.unwrap();
+        assert!(closed_at().await.is_some());
+        assert!(matches!(move_to("open").await, Err(AppError::ConflictError(_))));
+        let status = sqlx::query_scalar::<_, String>("SELECT status FROM code_reviews WHERE id = $1")
+            .bind(review_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(status, "merged");
+
+        // Asking for the current status again isn't a transition
+        assert!(move_to("merged").await.is_ok());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_line_comments_follow_edits_to_their_file() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool().clone();
+        let (author_id, project_id, review_id) = insert_review(&pool, "Anchors").await;
+        sqlx::query("UPDATE project_members SET permissions = ARRAY['read', 'write'] WHERE project_id = $1 AND user_id = $2")
+            .bind(project_id)
+            .bind(author_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let file_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO code_files (id, project_id, file_path, content) VALUES ($1, $2, 'src/lib.rs', '')")
+            .bind(file_id)
+            .bind(project_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let add_version = |version_number: i32, content: &'static str| {
+            sqlx::query(
+                "INSERT INTO document_versions (id, file_id, version_number, content, author_id) VALUES ($1, $2, $3, $4, $5)"
+            )
+            .bind(Uuid::new_v4())
+            .bind(file_id)
+            .bind(version_number)
+            .bind(content)
+            .bind(author_id)
+            .execute(&pool)
+        };
+        add_version(1, "fn a() {}\nfn b() {}\nfn c() {}").await.unwrap();
+
+        for line_number in [2, 3] {
+            add_review_comment(
+                State(pool.clone()),
+                Path((project_id, review_id)),
+                AuthUser(author_id),
+                ValidatedJson(AddReviewCommentRequest {
+                    file_path: Some("src/lib.rs".to_string()),
+                    line_number: Some(line_number),
+                    content: "Needs a doc comment".to_string(),
+                    parent_comment_id: None,
+                }),
+            )
+            .await
+            .unwrap();
+        }
+
+        // Two lines inserted above, and the last line rewritten
+        add_version(2, "use std::io;\n\nfn a() {}\nfn b() {}\nfn c() -> io::Result<()> {}").await.unwrap();
+
+        // Reading the review leaves the comments where they were
+        get_code_review(
+            State(pool.clone()),
+            Path((project_id, review_id)),
+            AuthUser(author_id),
+            Query(PageQuery { limit: None, cursor: None }),
+        )
+        .await
+        .unwrap();
+        let read = fetch_review_comments(&pool, review_id, &PageQuery { limit: None, cursor: None }).await.unwrap();
+        assert!(read.items.iter().all(|comment| comment.file_version == Some(1) && !comment.outdated));
+
+        diff::reanchor_comments(&pool, file_id).await.unwrap();
+
+        let page = fetch_review_comments(&pool, review_id, &PageQuery { limit: None, cursor: None }).await.unwrap();
+        let mut anchors: Vec<(Option<i32>, Option<i32>, bool)> = page
+            .items
+            .iter()
+            .map(|comment| (comment.line_number, comment.file_version, comment.outdated))
+            .collect();
+        anchors.sort();
+        assert_eq!(anchors, vec![(Some(3), Some(1), true), (Some(4), Some(2), false)]);
+
+        // Nothing changes until the file does again
+        diff::reanchor_comments(&pool, file_id).await.unwrap();
+        let again = fetch_review_comments(&pool, review_id, &PageQuery { limit: None, cursor: None }).await.unwrap();
+        let mut unchanged: Vec<_> = again.items.iter().map(|comment| (comment.line_number, comment.file_version, comment.outdated)).collect();
+        unchanged.sort();
+        assert_eq!(unchanged, anchors);
+    }
 }
@@ -1,115 +1,874 @@
 use axum::{
-    extract::{ws::{WebSocket, WebSocketUpgrade}, Path, State},
+    extract::{ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade}, Path, Query, State},
     http::StatusCode,
-    Json, response::IntoResponse,
+    Json, response::IntoResponse, Extension,
 };
+use chrono::{DateTime, Utc};
 use uuid::Uuid;
+use std::collections::BTreeSet;
 use std::sync::Arc;
-use tokio::sync::broadcast;
-use futures::{sink::SinkExt, stream::StreamExt};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{interval_at, sleep_until, Instant};
+use futures::{future::FutureExt, sink::SinkExt, stream::StreamExt};
 use crate::db::Database;
-use crate::services::collaboration::CollaborationManager;
+use crate::services::collaboration::{CollaborationManager, OperationError};
 use crate::models::collaboration::{
-    WebSocketMessage, CursorPosition, CollaborationEvent, CodeChangeEvent,
+    ClientMessage, CollaborativeSession, ConflictQuery, CreateCollaborativeSessionRequest, CursorUpdate, DocumentOperation, PresenceEvent, SessionMessage, WebSocketMessage,
 };
-use crate::error::AppError;
+use crate::error::{AppError, AppResult};
+use crate::handlers::projects::{ensure_project_access, ensure_project_permission};
+use crate::middleware_auth::AuthUser;
+use crate::utils::crypto::generate_secure_token;
+use crate::utils::validation::ValidatedJson;
+
+/// Check the user can see the session's project and load the session into the manager on
+/// first use. Seeing the project is enough to watch; applying operations also needs `write`.
+async fn open_session(
+    db: &Database,
+    collab_manager: &CollaborationManager,
+    session_id: Uuid,
+    user_id: Uuid,
+) -> AppResult<Uuid> {
+    let (project_id, file_id, status, expires_at) =
+        sqlx::query_as::<_, (Uuid, Uuid, String, Option<DateTime<Utc>>)>(
+            r#"
+            SELECT project_id, file_id, status, expires_at FROM collaborative_sessions
+            WHERE id = $1 AND status IN ('active', 'expired')
+            "#,
+        )
+        .bind(session_id)
+        .fetch_optional(db.pool())
+        .await?
+        .ok_or_else(|| AppError::NotFoundError("Collaborative session not found".to_string()))?;
+
+    ensure_project_access(db, project_id, user_id).await?;
+
+    if status == "expired" || expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+        return Err(AppError::ConflictError("Collaborative session has expired".to_string()));
+    }
+
+    // Another connection may have loaded it already
+    if collab_manager.get_version(session_id).is_err() {
+        if let Err(e) = collab_manager.load_session(db.pool(), session_id, file_id, expires_at).await {
+            // Fine if the other connection got there first
+            if collab_manager.get_version(session_id).is_err() {
+                tracing::error!("Failed to load collaboration session {}: {}", session_id, e);
+                return Err(AppError::InternalServerError("Failed to load collaborative session".to_string()));
+            }
+        }
+    }
+    // Pick up edits made through other instances, when sessions are shared
+    collab_manager.catch_up(session_id).await.map_err(|e| {
+        tracing::error!("Failed to catch up on collaboration session {}: {}", session_id, e);
+        AppError::InternalServerError("Failed to load collaborative session".to_string())
+    })?;
+
+    Ok(project_id)
+}
+
+/// Start a session on a file; the response carries the `session_token`
+pub async fn create_collaborative_session(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(req): ValidatedJson<CreateCollaborativeSessionRequest>,
+) -> AppResult<(StatusCode, Json<CollaborativeSession>)> {
+    let project_id = sqlx::query_scalar::<_, Uuid>("SELECT project_id FROM code_files WHERE id = $1")
+        .bind(req.file_id)
+        .fetch_optional(db.pool())
+        .await?
+        .ok_or_else(|| AppError::NotFoundError("File not found".to_string()))?;
+
+    ensure_project_access(&db, project_id, user_id).await?;
+
+    let now = Utc::now();
+    let session = CollaborativeSession {
+        id: Uuid::new_v4(),
+        project_id,
+        file_id: req.file_id,
+        session_token: generate_secure_token(),
+        status: "active".to_string(),
+        created_at: now,
+        expires_at: req
+            .expires_in_seconds
+            .map(|seconds| now + chrono::Duration::seconds(seconds)),
+        updated_at: now,
+    };
+
+    sqlx::query(
+        r#"
+        INSERT INTO collaborative_sessions (id, project_id, file_id, session_token, status, created_at, expires_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $6)
+        "#,
+    )
+    .bind(session.id)
+    .bind(session.project_id)
+    .bind(session.file_id)
+    .bind(&session.session_token)
+    .bind(&session.status)
+    .bind(session.created_at)
+    .bind(session.expires_at)
+    .execute(db.pool())
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(session)))
+}
 
 pub async fn join_collaboration(
     State(db): State<Arc<Database>>,
-    Path(project_id): Path<Uuid>,
+    Extension(collab_manager): Extension<Arc<CollaborationManager>>,
+    Path(session_id): Path<Uuid>,
+    AuthUser(user_id): AuthUser,
     ws: WebSocketUpgrade,
-) -> impl IntoResponse {
-    let collab_manager = CollaborationManager::new();
-    
-    ws.on_upgrade(move |socket| {
-        handle_websocket(socket, project_id, db, collab_manager)
-    })
+) -> AppResult<impl IntoResponse> {
+    let project_id = open_session(&db, &collab_manager, session_id, user_id).await?;
+
+    Ok(ws.on_upgrade(move |socket| {
+        handle_websocket(socket, session_id, project_id, user_id, db, collab_manager)
+    }))
 }
 
 async fn handle_websocket(
     socket: WebSocket,
+    session_id: Uuid,
     project_id: Uuid,
-    _db: Arc<Database>,
+    user_id: Uuid,
+    db: Arc<Database>,
     collab_manager: Arc<CollaborationManager>,
 ) {
+    // The version the client is caught up to; it advances with every operation forwarded
+    let (connection_id, mut updates, mut next_version) = match collab_manager
+        .join_session(session_id, user_id)
+        .and_then(|connection_id| {
+            let version = collab_manager.get_version(session_id)?;
+            Ok((connection_id, collab_manager.get_channel(session_id)?.subscribe(), version))
+        })
+    {
+        Ok(joined) => joined,
+        Err(e) => {
+            tracing::warn!("User {} could not join session {}: {}", user_id, session_id, e);
+            return;
+        }
+    };
     let (mut sender, mut receiver) = socket.split();
-    let user_id = Uuid::new_v4(); // In production, extract from JWT
-    
-    collab_manager.add_session(project_id, user_id);
-    let mut rx = collab_manager.get_or_create_channel(project_id).subscribe();
-
-    tracing::info!("User {} joined project {}", user_id, project_id);
-
-    // Spawn a task to forward broadcast messages to the WebSocket
-    let collab_clone = collab_manager.clone();
-    let project_clone = project_id;
-    let user_clone = user_id;
-
-    tokio::spawn(async move {
-        while let Ok(msg) = rx.recv().await {
-            if msg.user_id != user_clone {
-                if let Ok(json) = serde_json::to_string(&msg) {
-                    let _ = sender.send(axum::extract::ws::Message::Text(json)).await;
-                }
-            }
+
+    tracing::info!("User {} joined session {}", user_id, session_id);
+    broadcast_presence(&collab_manager, session_id, PresenceEvent::Joined(user_id));
+    let participant_id = match record_participant_joined(&db, session_id, user_id).await {
+        Ok(participant_id) => Some(participant_id),
+        Err(e) => {
+            tracing::warn!("Failed to record user {} joining session {}: {:?}", user_id, session_id, e);
+            None
         }
-    });
-
-    // Handle incoming WebSocket messages
-    while let Some(Ok(msg)) = receiver.next().await {
-        match msg {
-            axum::extract::ws::Message::Text(text) => {
-                if let Ok(ws_msg) = serde_json::from_str::<WebSocketMessage>(&text) {
-                    let channel = collab_manager.get_or_create_channel(project_id);
-                    let _ = channel.send(ws_msg);
+    };
+
+    let heartbeat = collab_manager.heartbeat();
+    let mut pings = interval_at(Instant::now() + heartbeat.ping_interval, heartbeat.ping_interval);
+    let mut last_seen = Instant::now();
+    let mut shutdown = collab_manager.shutdown_receiver();
+
+    loop {
+        tokio::select! {
+            _ = shutdown.wait_for(|stopping| *stopping).map(|_| ()) => {
+                // Going away tells the client to reconnect once the server is back
+                let _ = sender
+                    .send(Message::Close(Some(CloseFrame {
+                        code: close_code::AWAY,
+                        reason: "Server shutting down".into(),
+                    })))
+                    .await;
+                break;
+            }
+            _ = pings.tick() => {
+                if sender.send(Message::Ping(Vec::new())).await.is_err() {
+                    break;
                 }
             }
-            axum::extract::ws::Message::Close(_) => {
-                tracing::info!("User {} left project {}", user_id, project_id);
-                collab_manager.remove_session(project_id, user_id);
+            _ = sleep_until(last_seen + heartbeat.idle_timeout) => {
+                tracing::info!("Dropping silent connection for user {} in session {}", user_id, session_id);
                 break;
             }
-            _ => {}
+            update = updates.recv() => {
+                let message = match update {
+                    Ok(message) => message,
+                    Err(RecvError::Lagged(missed)) => {
+                        // Too slow to keep up with the channel: rather than dropping the client,
+                        // send it everything since its last operation to rebase over, and carry on
+                        tracing::warn!("User {} missed {} updates in session {}", user_id, missed, session_id);
+                        let Ok(resync) = collab_manager.resync_from(session_id, next_version) else { break };
+                        next_version = resync.server_version;
+                        let envelope = WebSocketMessage::new(
+                            WebSocketMessage::RESYNC,
+                            session_id,
+                            user_id,
+                            serde_json::to_value(&resync).unwrap_or_default(),
+                        );
+                        let Ok(json) = serde_json::to_string(&envelope) else { continue };
+                        if sender.send(Message::Text(json)).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                match &message {
+                    // Clients already know where their own cursor is
+                    SessionMessage::Cursor(cursor) if cursor.user_id == user_id => continue,
+                    // Still buffered from before a resync that already included it
+                    SessionMessage::Operation(operation) if operation.version < next_version => continue,
+                    SessionMessage::Operation(operation) => next_version = operation.version + 1,
+                    _ => {}
+                }
+                let Ok(envelope) = message.to_websocket_message(session_id) else { continue };
+                let Ok(json) = serde_json::to_string(&envelope) else { continue };
+                if sender.send(Message::Text(json)).await.is_err() {
+                    break;
+                }
+            }
+            incoming = receiver.next() => match incoming {
+                Some(Ok(message)) if !matches!(message, Message::Text(_) | Message::Close(_)) => {
+                    // Pongs and anything else just show the client is alive
+                    last_seen = Instant::now();
+                }
+                Some(Ok(Message::Text(text))) => {
+                    last_seen = Instant::now();
+                    let result = match serde_json::from_str::<ClientMessage>(&text) {
+                        Ok(ClientMessage::Operation(operation)) => {
+                            // Checked per operation, so a revoked permission takes effect mid-session
+                            match ensure_project_permission(&db, project_id, user_id, "write").await {
+                                Ok(()) => apply_client_operation(&db, &collab_manager, session_id, user_id, operation)
+                                    .await
+                                    .map(|_| ()),
+                                Err(AppError::AuthorizationError(message) | AppError::NotFoundError(message)) => {
+                                    Err(OperationError::Rejected(message))
+                                }
+                                Err(e) => {
+                                    tracing::error!("Failed to check write access for user {} in session {}: {:?}", user_id, session_id, e);
+                                    Err(OperationError::Rejected("Failed to check write access".to_string()))
+                                }
+                            }
+                        }
+                        Ok(ClientMessage::Cursor(cursor)) => {
+                            apply_client_cursor(&collab_manager, session_id, connection_id, user_id, cursor)
+                                .map_err(OperationError::Rejected)
+                        }
+                        Err(e) => Err(OperationError::Rejected(format!("Invalid message: {}", e))),
+                    };
+                    if let Err(e) = result {
+                        let error = match e {
+                            OperationError::ResyncRequired(resync) => WebSocketMessage::new(
+                                WebSocketMessage::RESYNC,
+                                session_id,
+                                user_id,
+                                serde_json::to_value(&resync).unwrap_or_default(),
+                            ),
+                            other => WebSocketMessage::new(
+                                WebSocketMessage::ERROR,
+                                session_id,
+                                user_id,
+                                serde_json::json!({ "message": other.to_string() }),
+                            ),
+                        };
+                        let Ok(reply) = serde_json::to_string(&error) else { continue };
+                        if sender.send(Message::Text(reply)).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                Some(Ok(_)) | Some(Err(_)) | None => break,
+            }
+        }
+    }
+
+    tracing::info!("User {} left session {}", user_id, session_id);
+    // Another tab may still have the session open
+    if !collab_manager.leave_session(session_id, connection_id).unwrap_or(false) {
+        broadcast_presence(&collab_manager, session_id, PresenceEvent::Left(user_id));
+    }
+    if let Some(participant_id) = participant_id {
+        if let Err(e) = record_participant_left(&db, participant_id).await {
+            tracing::warn!("Failed to record user {} leaving session {}: {:?}", user_id, session_id, e);
         }
     }
 }
 
+/// Tell everyone in the session (the user included) that someone joined or left
+fn broadcast_presence(collab_manager: &CollaborationManager, session_id: Uuid, event: PresenceEvent) {
+    let _ = collab_manager.broadcast(session_id, SessionMessage::Presence(event));
+}
+
+async fn record_participant_joined(db: &Database, session_id: Uuid, user_id: Uuid) -> AppResult<Uuid> {
+    let participant_id = Uuid::new_v4();
+    sqlx::query("INSERT INTO session_participants (id, session_id, user_id) VALUES ($1, $2, $3)")
+        .bind(participant_id)
+        .bind(session_id)
+        .bind(user_id)
+        .execute(db.pool())
+        .await?;
+
+    Ok(participant_id)
+}
+
+async fn record_participant_left(db: &Database, participant_id: Uuid) -> AppResult<()> {
+    sqlx::query("UPDATE session_participants SET left_at = NOW() WHERE id = $1")
+        .bind(participant_id)
+        .execute(db.pool())
+        .await?;
+
+    Ok(())
+}
+
+/// Transform and record a client's operation, then broadcast the result to the whole
+/// session; the sender treats its own op coming back as the acknowledgement
+async fn apply_client_operation(
+    db: &Database,
+    collab_manager: &CollaborationManager,
+    session_id: Uuid,
+    user_id: Uuid,
+    mut operation: DocumentOperation,
+) -> Result<DocumentOperation, OperationError> {
+    // Attribute the edit to the authenticated user, whatever the client claims
+    operation.user_id = user_id;
+
+    collab_manager.submit_operation(db.pool(), session_id, operation).await
+}
+
+/// Store a client's cursor/selection and show it to the other participants; the
+/// document itself is untouched
+fn apply_client_cursor(
+    collab_manager: &CollaborationManager,
+    session_id: Uuid,
+    connection_id: Uuid,
+    user_id: Uuid,
+    mut cursor: CursorUpdate,
+) -> Result<(), String> {
+    cursor.user_id = user_id;
+    cursor.session_id = session_id;
+
+    collab_manager.update_cursor(session_id, connection_id, cursor.clone())?;
+    collab_manager.broadcast(session_id, SessionMessage::Cursor(cursor))?;
+
+    Ok(())
+}
+
 pub async fn get_active_collaborators(
-    State(_db): State<Arc<Database>>,
-    Path(project_id): Path<Uuid>,
-) -> Result<Json<Vec<Uuid>>, AppError> {
-    let collab_manager = CollaborationManager::new();
-    let users = collab_manager.get_active_users(project_id);
+    State(db): State<Arc<Database>>,
+    Extension(collab_manager): Extension<Arc<CollaborationManager>>,
+    Path(session_id): Path<Uuid>,
+    AuthUser(user_id): AuthUser,
+) -> AppResult<Json<Vec<Uuid>>> {
+    open_session(&db, &collab_manager, session_id, user_id).await?;
+
+    // Each user once, however many connections they have open
+    let users: BTreeSet<Uuid> = collab_manager
+        .get_participants(session_id)
+        .map_err(AppError::InternalServerError)?
+        .into_iter()
+        .map(|(_, cursor)| cursor.user_id)
+        .collect();
+    let users = users.into_iter().collect();
     Ok(Json(users))
 }
 
 pub async fn get_cursor_positions(
-    State(_db): State<Arc<Database>>,
-    Path(project_id): Path<Uuid>,
-) -> Result<Json<Vec<CursorPosition>>, AppError> {
-    let collab_manager = CollaborationManager::new();
-    let cursors = collab_manager.get_cursors(project_id);
+    State(db): State<Arc<Database>>,
+    Extension(collab_manager): Extension<Arc<CollaborationManager>>,
+    Path(session_id): Path<Uuid>,
+    AuthUser(user_id): AuthUser,
+) -> AppResult<Json<Vec<CursorUpdate>>> {
+    open_session(&db, &collab_manager, session_id, user_id).await?;
+
+    let cursors = collab_manager
+        .get_participants(session_id)
+        .map_err(AppError::InternalServerError)?
+        .into_iter()
+        .map(|(_, cursor)| cursor)
+        .collect();
     Ok(Json(cursors))
 }
 
+/// Submit an operation over REST, for clients without a WebSocket
 pub async fn sync_code_state(
-    State(_db): State<Arc<Database>>,
-    Path(project_id): Path<Uuid>,
-    Json(payload): Json<CodeChangeEvent>,
-) -> Result<StatusCode, AppError> {
-    tracing::info!(
-        "Syncing code change for project {}, file: {}",
-        project_id,
-        payload.file_id
-    );
+    State(db): State<Arc<Database>>,
+    Extension(collab_manager): Extension<Arc<CollaborationManager>>,
+    Path(session_id): Path<Uuid>,
+    AuthUser(user_id): AuthUser,
+    Json(operation): Json<DocumentOperation>,
+) -> AppResult<Json<DocumentOperation>> {
+    let project_id = open_session(&db, &collab_manager, session_id, user_id).await?;
+    ensure_project_permission(&db, project_id, user_id, "write").await?;
 
-    Ok(StatusCode::ACCEPTED)
+    let applied = apply_client_operation(&db, &collab_manager, session_id, user_id, operation)
+        .await
+        .map_err(|e| match e {
+            // The client fetches what it missed from the conflicts endpoint
+            OperationError::ResyncRequired(_) | OperationError::SessionExpired => {
+                AppError::ConflictError(e.to_string())
+            }
+            OperationError::Rejected(message) => AppError::ValidationError(message),
+        })?;
+    Ok(Json(applied))
 }
 
+/// Operations applied at or after `version`, which a client at that version must rebase over
 pub async fn detect_conflicts(
-    State(_db): State<Arc<Database>>,
-    Path(project_id): Path<Uuid>,
-) -> Result<Json<Vec<String>>, AppError> {
-    // Implement conflict detection logic
-    Ok(Json(vec![]))
+    State(db): State<Arc<Database>>,
+    Extension(collab_manager): Extension<Arc<CollaborationManager>>,
+    Path(session_id): Path<Uuid>,
+    AuthUser(user_id): AuthUser,
+    Query(query): Query<ConflictQuery>,
+) -> AppResult<Json<Vec<DocumentOperation>>> {
+    open_session(&db, &collab_manager, session_id, user_id).await?;
+
+    let conflicts = collab_manager
+        .detect_conflicts(session_id, query.version)
+        .map_err(AppError::InternalServerError)?;
+    Ok(Json(conflicts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::collaboration::{OperationType, ResyncRequired};
+    use crate::services::collaboration::{HeartbeatConfig, DEFAULT_MAX_LAG};
+    use std::time::Duration;
+    use axum::{extract::Request, middleware::{self, Next}, routing::{get, post}, Router};
+    use chrono::Utc;
+    use sqlx::PgPool;
+    use std::net::SocketAddr;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::{
+        connect_async,
+        tungstenite::{self, client::IntoClientRequest},
+        MaybeTlsStream, WebSocketStream,
+    };
+
+    type ClientSocket = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+    /// Create a user owning a project with one file and an active session on it
+    async fn insert_session(pool: &PgPool) -> (Uuid, Uuid) {
+        let user_id = crate::db::insert_test_user(pool).await;
+        let project_id = crate::db::insert_test_project(pool, user_id, "collab test").await;
+        let file_id = Uuid::new_v4();
+        let session_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO code_files (id, project_id, file_path, content) VALUES ($1, $2, $3, $4)")
+            .bind(file_id)
+            .bind(project_id)
+            .bind("main.rs")
+            .bind("hello world")
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO collaborative_sessions (id, project_id, file_id, session_token) VALUES ($1, $2, $3, $4)")
+            .bind(session_id)
+            .bind(project_id)
+            .bind(file_id)
+            .bind(session_id.to_string())
+            .execute(pool)
+            .await
+            .unwrap();
+
+        (user_id, session_id)
+    }
+
+    /// Add another user as a member of the session's project, holding `permissions`
+    async fn insert_collaborator(pool: &PgPool, session_id: Uuid, permissions: &[&str]) -> Uuid {
+        let user_id = crate::db::insert_test_user(pool).await;
+        sqlx::query(
+            r#"
+            INSERT INTO project_members (id, project_id, user_id, role, permissions)
+            SELECT $1, project_id, $2, 'member', $4 FROM collaborative_sessions WHERE id = $3
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(session_id)
+        .bind(permissions)
+        .execute(pool)
+        .await
+        .unwrap();
+
+        user_id
+    }
+
+    /// Serve the collaboration routes, standing in for auth_middleware: requests act as the
+    /// user in the `x-user-id` header, or `user_id` without one
+    async fn serve(db: Arc<Database>, collab_manager: Arc<CollaborationManager>, user_id: Uuid) -> SocketAddr {
+        let app = Router::new()
+            .route("/sessions", post(create_collaborative_session))
+            .route("/sessions/:session_id/ws", get(join_collaboration))
+            .route("/sessions/:session_id/participants", get(get_active_collaborators))
+            .layer(Extension(collab_manager))
+            .layer(middleware::from_fn(move |mut request: Request, next: Next| async move {
+                let caller = request
+                    .headers()
+                    .get("x-user-id")
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| Uuid::parse_str(value).ok())
+                    .unwrap_or(user_id);
+                request.extensions_mut().insert(caller);
+                next.run(request).await
+            }))
+            .with_state(db);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        addr
+    }
+
+    fn insert_op(position: usize, content: &str) -> DocumentOperation {
+        DocumentOperation {
+            id: Uuid::new_v4().to_string(),
+            version: 0,
+            timestamp: Utc::now(),
+            user_id: Uuid::new_v4(),
+            operation: OperationType::Insert {
+                position,
+                content: content.to_string(),
+            },
+        }
+    }
+
+    async fn connect(addr: SocketAddr, session_id: Uuid, user_id: Uuid) -> ClientSocket {
+        let mut request = format!("ws://{}/sessions/{}/ws", addr, session_id)
+            .into_client_request()
+            .unwrap();
+        request
+            .headers_mut()
+            .insert("x-user-id", user_id.to_string().parse().unwrap());
+        connect_async(request).await.unwrap().0
+    }
+
+    /// Next message from the server, skipping control frames
+    async fn next_message(socket: &mut ClientSocket) -> WebSocketMessage {
+        loop {
+            match socket.next().await.unwrap().unwrap() {
+                tungstenite::Message::Text(text) => return serde_json::from_str(&text).unwrap(),
+                tungstenite::Message::Ping(_) | tungstenite::Message::Pong(_) => continue,
+                other => panic!("expected a text frame, got {:?}", other),
+            }
+        }
+    }
+
+    /// Next message of the given type, skipping any others
+    async fn next_event(socket: &mut ClientSocket, event_type: &str) -> WebSocketMessage {
+        loop {
+            let message = next_message(socket).await;
+            if message.event_type == event_type {
+                return message;
+            }
+        }
+    }
+
+    /// Send an operation and wait for the server's broadcast of it
+    async fn send_op(socket: &mut ClientSocket, operation: &DocumentOperation) -> DocumentOperation {
+        socket
+            .send(tungstenite::Message::Text(serde_json::to_string(operation).unwrap()))
+            .await
+            .unwrap();
+
+        let message = next_event(socket, WebSocketMessage::OPERATION).await;
+        serde_json::from_value(message.data).unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_websocket_broadcasts_applied_operation() {
+        let db = crate::db::test_database().await;
+        let (user_id, session_id) = insert_session(db.pool()).await;
+        let addr = serve(db, CollaborationManager::new(), user_id).await;
+
+        let mut socket = connect(addr, session_id, user_id).await;
+
+        let operation = insert_op(5, ",");
+        let applied = send_op(&mut socket, &operation).await;
+        assert_eq!(applied.id, operation.id);
+        assert_eq!(applied.version, 0);
+        assert_eq!(applied.user_id, user_id);
+        assert!(matches!(
+            applied.operation,
+            OperationType::Insert { position: 5, ref content } if content == ","
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_closing_one_tab_keeps_the_user_in_the_session() {
+        let db = crate::db::test_database().await;
+        let (user_id, session_id) = insert_session(db.pool()).await;
+        let collab_manager = CollaborationManager::new();
+        let addr = serve(db, collab_manager.clone(), user_id).await;
+
+        let first = connect(addr, session_id, user_id).await;
+        let _second = connect(addr, session_id, user_id).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(collab_manager.get_participants(session_id).unwrap().len(), 2);
+
+        drop(first);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let participants = collab_manager.get_participants(session_id).unwrap();
+        assert_eq!(participants.len(), 1);
+        assert_eq!(participants[0].1.user_id, user_id);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_silent_client_is_reaped() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool().clone();
+        let (user_id, session_id) = insert_session(&pool).await;
+        let collab_manager = CollaborationManager::with_heartbeat(HeartbeatConfig {
+            ping_interval: Duration::from_millis(50),
+            idle_timeout: Duration::from_millis(200),
+            empty_session_grace: Duration::ZERO,
+        });
+        let addr = serve(db, collab_manager.clone(), user_id).await;
+
+        // Connect, then never read: the client's pongs are never sent
+        let _socket = connect(addr, session_id, user_id).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(collab_manager.get_participants(session_id).unwrap().len(), 1);
+
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert!(collab_manager.get_participants(session_id).unwrap().is_empty());
+
+        let left_at: Option<chrono::DateTime<Utc>> =
+            sqlx::query_scalar("SELECT left_at FROM session_participants WHERE session_id = $1 AND user_id = $2")
+                .bind(session_id)
+                .bind(user_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert!(left_at.is_some());
+
+        // With nobody left, the session itself is closed
+        assert_eq!(collab_manager.close_idle_sessions(&pool).await.unwrap(), 1);
+        assert!(collab_manager.get_version(session_id).is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_participants_are_shared_across_requests() {
+        let db = crate::db::test_database().await;
+        let (user_id, session_id) = insert_session(db.pool()).await;
+        let collab_manager = CollaborationManager::new();
+        let addr = serve(db, collab_manager.clone(), user_id).await;
+
+        // Join over a WebSocket; the acknowledged op proves the join has happened
+        let mut socket = connect(addr, session_id, user_id).await;
+        send_op(&mut socket, &insert_op(0, "> ")).await;
+
+        // A separate REST request sees the live participant
+        let participants: Vec<Uuid> = reqwest::get(format!("http://{}/sessions/{}/participants", addr, session_id))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(participants, vec![user_id]);
+
+        // And so does any other handle on the shared manager
+        assert_eq!(collab_manager.get_version(session_id).unwrap(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_lagging_client_is_resynced_not_dropped() {
+        let db = crate::db::test_database().await;
+        let (user_id, session_id) = insert_session(db.pool()).await;
+        let collab_manager = CollaborationManager::with_settings(HeartbeatConfig::default(), DEFAULT_MAX_LAG, 4);
+        let addr = serve(db, collab_manager.clone(), user_id).await;
+
+        let mut socket = connect(addr, session_id, user_id).await;
+        next_event(&mut socket, WebSocketMessage::USER_JOINED).await;
+
+        // Broadcast far more than the channel holds without yielding, so the
+        // connection's receiver falls behind before it gets to read any of it
+        let channel = collab_manager.get_channel(session_id).unwrap();
+        for version in 0..10 {
+            let operation = DocumentOperation { version, ..insert_op(0, "x") };
+            let applied = collab_manager.apply_operation(session_id, operation).unwrap();
+            let _ = channel.send(SessionMessage::Operation(applied));
+        }
+
+        let resync = next_event(&mut socket, WebSocketMessage::RESYNC).await;
+        let resync: ResyncRequired = serde_json::from_value(resync.data).unwrap();
+        assert_eq!(resync.server_version, 10);
+        assert_eq!(resync.missed_operations.len(), 10);
+
+        // Still connected, and the operations the resync covered aren't sent again
+        let operation = DocumentOperation { version: 10, ..insert_op(0, "y") };
+        let applied = send_op(&mut socket, &operation).await;
+        assert_eq!(applied.id, operation.id);
+        assert_eq!(applied.version, 10);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_presence_events_reach_other_collaborators() {
+        let db = crate::db::test_database().await;
+        let (owner_id, session_id) = insert_session(db.pool()).await;
+        let collaborator_id = insert_collaborator(db.pool(), session_id, &["read", "write"]).await;
+        let addr = serve(db, CollaborationManager::new(), owner_id).await;
+
+        let mut owner = connect(addr, session_id, owner_id).await;
+        let joined = next_event(&mut owner, WebSocketMessage::USER_JOINED).await;
+        assert_eq!(joined.user_id, owner_id);
+
+        let collaborator = connect(addr, session_id, collaborator_id).await;
+        let joined = next_event(&mut owner, WebSocketMessage::USER_JOINED).await;
+        assert_eq!(joined.user_id, collaborator_id);
+        assert_eq!(joined.session_id, session_id);
+
+        drop(collaborator);
+        let left = next_event(&mut owner, WebSocketMessage::USER_LEFT).await;
+        assert_eq!(left.user_id, collaborator_id);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_cursor_updates_reach_other_collaborators() {
+        let db = crate::db::test_database().await;
+        let (owner_id, session_id) = insert_session(db.pool()).await;
+        let collaborator_id = insert_collaborator(db.pool(), session_id, &["read", "write"]).await;
+        let collab_manager = CollaborationManager::new();
+        let addr = serve(db, collab_manager.clone(), owner_id).await;
+
+        let mut owner = connect(addr, session_id, owner_id).await;
+        let joined = next_event(&mut owner, WebSocketMessage::USER_JOINED).await;
+        assert_eq!(joined.user_id, owner_id);
+        let mut collaborator = connect(addr, session_id, collaborator_id).await;
+        let joined = next_event(&mut owner, WebSocketMessage::USER_JOINED).await;
+        assert_eq!(joined.user_id, collaborator_id);
+
+        let cursor = CursorUpdate {
+            user_id: collaborator_id,
+            session_id,
+            cursor_position: 7,
+            selection_start: Some(3),
+            selection_end: Some(7),
+        };
+        collaborator
+            .send(tungstenite::Message::Text(serde_json::to_string(&cursor).unwrap()))
+            .await
+            .unwrap();
+
+        // The next thing the owner sees is the cursor, not an edit
+        let message = next_message(&mut owner).await;
+        assert_eq!(message.event_type, WebSocketMessage::CURSOR);
+        assert_eq!(message.user_id, collaborator_id);
+        let received: CursorUpdate = serde_json::from_value(message.data).unwrap();
+        assert_eq!(received.cursor_position, 7);
+        assert_eq!(received.selection_start, Some(3));
+        assert_eq!(received.selection_end, Some(7));
+
+        assert_eq!(collab_manager.get_version(session_id).unwrap(), 0);
+        let stored = collab_manager
+            .get_participants(session_id)
+            .unwrap()
+            .into_iter()
+            .find(|(_, cursor)| cursor.user_id == collaborator_id)
+            .unwrap();
+        assert_eq!(stored.1.cursor_position, 7);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_viewer_operations_are_rejected() {
+        let db = crate::db::test_database().await;
+        let (owner_id, session_id) = insert_session(db.pool()).await;
+        let viewer_id = insert_collaborator(db.pool(), session_id, &["read"]).await;
+        let collab_manager = CollaborationManager::new();
+        let addr = serve(db, collab_manager.clone(), owner_id).await;
+
+        // Joining and watching only needs read access
+        let mut viewer = connect(addr, session_id, viewer_id).await;
+        let joined = next_event(&mut viewer, WebSocketMessage::USER_JOINED).await;
+        assert_eq!(joined.user_id, viewer_id);
+
+        viewer
+            .send(tungstenite::Message::Text(serde_json::to_string(&insert_op(0, "x")).unwrap()))
+            .await
+            .unwrap();
+        let error = next_event(&mut viewer, WebSocketMessage::ERROR).await;
+        assert!(error.data["message"].as_str().unwrap().contains("'write'"));
+        assert_eq!(collab_manager.get_version(session_id).unwrap(), 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_expired_session_refuses_joins() {
+        let db = crate::db::test_database().await;
+        let (user_id, existing_session_id) = insert_session(db.pool()).await;
+        let file_id: Uuid = sqlx::query_scalar("SELECT file_id FROM collaborative_sessions WHERE id = $1")
+            .bind(existing_session_id)
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+        let collab_manager = CollaborationManager::new();
+        let addr = serve(db.clone(), collab_manager.clone(), user_id).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/sessions", addr))
+            .json(&serde_json::json!({ "file_id": file_id, "expires_in_seconds": 1 }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::CREATED);
+        let session: CollaborativeSession = response.json().await.unwrap();
+        assert!(!session.session_token.is_empty());
+
+        // Joinable until it expires
+        let mut socket = connect(addr, session.id, user_id).await;
+        next_event(&mut socket, WebSocketMessage::USER_JOINED).await;
+
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+
+        let request = format!("ws://{}/sessions/{}/ws", addr, session.id)
+            .into_client_request()
+            .unwrap();
+        match connect_async(request).await {
+            Err(tungstenite::Error::Http(response)) => {
+                assert_eq!(response.status(), tungstenite::http::StatusCode::CONFLICT)
+            }
+            other => panic!("expected a 409, got {:?}", other.map(|(_, response)| response)),
+        }
+
+        // The cleanup task marks the row expired and ends the live session
+        assert!(collab_manager.expire_sessions(db.pool()).await.unwrap() >= 1);
+        let status: String = sqlx::query_scalar("SELECT status FROM collaborative_sessions WHERE id = $1")
+            .bind(session.id)
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+        assert_eq!(status, "expired");
+        assert!(collab_manager.get_version(session.id).is_err());
+        loop {
+            match socket.next().await {
+                Some(Ok(tungstenite::Message::Close(_))) | None | Some(Err(_)) => break,
+                Some(Ok(_)) => continue,
+            }
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_shutdown_closes_open_sockets() {
+        let db = crate::db::test_database().await;
+        let (user_id, session_id) = insert_session(db.pool()).await;
+        let collab_manager = CollaborationManager::new();
+        let addr = serve(db, collab_manager.clone(), user_id).await;
+
+        let mut socket = connect(addr, session_id, user_id).await;
+        next_event(&mut socket, WebSocketMessage::USER_JOINED).await;
+
+        collab_manager.begin_shutdown();
+        let frame = loop {
+            match tokio::time::timeout(Duration::from_secs(5), socket.next()).await.unwrap() {
+                Some(Ok(tungstenite::Message::Close(frame))) => break frame,
+                Some(Ok(_)) => continue,
+                other => panic!("expected a close frame, got {:?}", other),
+            }
+        };
+        assert_eq!(frame.unwrap().code, tungstenite::protocol::frame::coding::CloseCode::Away);
+    }
 }
@@ -0,0 +1,475 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use chrono::Utc;
+use sqlx::Row;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    db::Database,
+    error::{AppError, AppResult},
+    handlers::projects::{ensure_project_access, ensure_project_permission, save_file_content},
+    middleware_auth::AuthUser,
+    models::{DeployRequest, Deployment, DeploymentHistoryQuery, DuplicatesQuery, FileContent, ProjectAnalysis},
+    services::{
+        code_analysis::{
+            detect_language, find_duplicates, CodeAnalyzer, DuplicateCluster, DuplicateSettings, SourceFile,
+            MIN_DUPLICATE_TOKENS,
+        },
+        diff,
+        events::{DomainEvent, EventBus},
+    },
+    utils::validation::ValidatedJson,
+};
+
+/// Write the submitted files into the project's `code_files` and record the deployment
+pub async fn deploy(
+    State(db): State<Arc<Database>>,
+    Extension(events): Extension<Arc<EventBus>>,
+    AuthUser(user_id): AuthUser,
+    Path(project_id): Path<Uuid>,
+    ValidatedJson(payload): ValidatedJson<DeployRequest>,
+) -> AppResult<(StatusCode, Json<Deployment>)> {
+    ensure_project_permission(&db, project_id, user_id, "write").await?;
+
+    let mut tx = db.pool().begin().await?;
+
+    // Changed files get a version and re-anchored comments, as edits through the files API do
+    let description = Some(payload.message.as_str()).filter(|message| !message.is_empty());
+    let mut changed = Vec::new();
+    for file in &payload.files {
+        let existing = sqlx::query_as::<_, (Uuid, String)>(
+            "SELECT id, content FROM code_files WHERE project_id = $1 AND branch = 'main' AND file_path = $2 FOR UPDATE",
+        )
+        .bind(project_id)
+        .bind(&file.path)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        match existing {
+            Some((_, content)) if content == file.content => {}
+            Some((file_id, _)) => {
+                save_file_content(&mut tx, project_id, file_id, &file.content, None, user_id, description).await?;
+                changed.push(file_id);
+            }
+            None => {
+                sqlx::query("INSERT INTO code_files (id, project_id, file_path, content) VALUES ($1, $2, $3, $4)")
+                    .bind(Uuid::new_v4())
+                    .bind(project_id)
+                    .bind(&file.path)
+                    .bind(&file.content)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+    }
+
+    let deployment = Deployment {
+        id: Uuid::new_v4(),
+        project_id,
+        user_id,
+        status: "completed".to_string(),
+        message: payload.message,
+        file_count: payload.files.len() as i32,
+        created_at: Utc::now(),
+    };
+
+    sqlx::query(
+        "INSERT INTO deployments (id, project_id, user_id, status, message, file_count, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7)"
+    )
+    .bind(deployment.id)
+    .bind(deployment.project_id)
+    .bind(deployment.user_id)
+    .bind(&deployment.status)
+    .bind(&deployment.message)
+    .bind(deployment.file_count)
+    .bind(deployment.created_at)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    for file_id in changed {
+        diff::reanchor_comments(db.pool(), file_id).await?;
+    }
+
+    events.publish(DomainEvent::DeploymentCompleted { deployment: deployment.clone() }).await;
+
+    Ok((StatusCode::CREATED, Json(deployment)))
+}
+
+/// Current contents of every file in the project
+pub async fn get_code(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    Path(project_id): Path<Uuid>,
+) -> AppResult<Json<Vec<FileContent>>> {
+    ensure_project_access(&db, project_id, user_id).await?;
+
+    let rows = sqlx::query("SELECT file_path, content FROM code_files WHERE project_id = $1 AND branch = 'main' ORDER BY file_path")
+        .bind(project_id)
+        .fetch_all(db.pool())
+        .await?;
+
+    let files = rows
+        .iter()
+        .map(|row| FileContent {
+            path: row.get("file_path"),
+            content: row.get("content"),
+        })
+        .collect();
+
+    Ok(Json(files))
+}
+
+/// Most recent deployments first; `?limit=` defaults to 20
+pub async fn list_deployments(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    Path(project_id): Path<Uuid>,
+    Query(query): Query<DeploymentHistoryQuery>,
+) -> AppResult<Json<Vec<Deployment>>> {
+    ensure_project_access(&db, project_id, user_id).await?;
+
+    let rows = sqlx::query(
+        "SELECT id, project_id, user_id, status, message, file_count, created_at FROM deployments WHERE project_id = $1 ORDER BY created_at DESC LIMIT $2"
+    )
+    .bind(project_id)
+    .bind(query.limit.unwrap_or(20).clamp(1, 100))
+    .fetch_all(db.pool())
+    .await?;
+
+    let deployments = rows.iter().map(deployment_from_row).collect();
+
+    Ok(Json(deployments))
+}
+
+/// A single deployment, visible to anyone who can see its project
+pub async fn get_deployment(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    Path(id): Path<Uuid>,
+) -> AppResult<Json<Deployment>> {
+    let row = sqlx::query(
+        "SELECT id, project_id, user_id, status, message, file_count, created_at FROM deployments WHERE id = $1"
+    )
+    .bind(id)
+    .fetch_optional(db.pool())
+    .await?
+    .ok_or(AppError::NotFoundError("Deployment not found".to_string()))?;
+
+    let deployment = deployment_from_row(&row);
+    ensure_project_access(&db, deployment.project_id, user_id).await?;
+
+    Ok(Json(deployment))
+}
+
+fn deployment_from_row(row: &sqlx::postgres::PgRow) -> Deployment {
+    Deployment {
+        id: row.get("id"),
+        project_id: row.get("project_id"),
+        user_id: row.get("user_id"),
+        status: row.get("status"),
+        message: row.get("message"),
+        file_count: row.get("file_count"),
+        created_at: row.get("created_at"),
+    }
+}
+
+/// Line count, average complexity and issue count across the project's files
+pub async fn analyze(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    Path(project_id): Path<Uuid>,
+) -> AppResult<Json<ProjectAnalysis>> {
+    ensure_project_access(&db, project_id, user_id).await?;
+
+    let rows = sqlx::query("SELECT file_path, content, language FROM code_files WHERE project_id = $1 AND branch = 'main'")
+        .bind(project_id)
+        .fetch_all(db.pool())
+        .await?;
+
+    let analyzer = CodeAnalyzer::new();
+    let mut analysis = ProjectAnalysis {
+        lines_of_code: 0,
+        complexity: 0.0,
+        issues: 0,
+    };
+    for row in &rows {
+        let file_path: String = row.get("file_path");
+        let content: String = row.get("content");
+        // Analyzers accept file extensions as well as language names
+        let language = row
+            .get::<Option<String>, _>("language")
+            .or_else(|| {
+                std::path::Path::new(&file_path)
+                    .extension()
+                    .map(|ext| ext.to_string_lossy().to_string())
+            })
+            .unwrap_or_default();
+
+        let result = analyzer.analyze(&content, &language)?;
+        analysis.lines_of_code += content.lines().count();
+        analysis.complexity += result.complexity;
+        analysis.issues += result.issues().len();
+    }
+    if !rows.is_empty() {
+        analysis.complexity /= rows.len() as f64;
+    }
+
+    Ok(Json(analysis))
+}
+
+/// Clusters of copied or near-copied code across the project's files, largest first
+pub async fn duplicates(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    Path(project_id): Path<Uuid>,
+    Query(query): Query<DuplicatesQuery>,
+) -> AppResult<Json<Vec<DuplicateCluster>>> {
+    let defaults = DuplicateSettings::default();
+    let settings = DuplicateSettings {
+        min_tokens: query.min_tokens.unwrap_or(defaults.min_tokens),
+        threshold: query.threshold.unwrap_or(defaults.threshold),
+    };
+    if settings.min_tokens < MIN_DUPLICATE_TOKENS {
+        return Err(AppError::ValidationError(format!(
+            "min_tokens must be at least {}",
+            MIN_DUPLICATE_TOKENS
+        )));
+    }
+    if !(settings.threshold > 0.0 && settings.threshold <= 1.0) {
+        return Err(AppError::ValidationError(
+            "threshold must be greater than 0 and at most 1".to_string(),
+        ));
+    }
+
+    ensure_project_access(&db, project_id, user_id).await?;
+
+    let rows = sqlx::query("SELECT file_path, content, language FROM code_files WHERE project_id = $1 AND branch = 'main'")
+        .bind(project_id)
+        .fetch_all(db.pool())
+        .await?;
+
+    let files: Vec<SourceFile> = rows
+        .iter()
+        .map(|row| {
+            let path: String = row.get("file_path");
+            let content: String = row.get("content");
+            let language = detect_language(row.get::<Option<String>, _>("language").as_deref(), Some(&path), &content);
+            SourceFile { path, language, content }
+        })
+        .collect();
+
+    // Fingerprinting a large project is CPU-bound, so keep it off the async workers
+    let clusters = tokio::task::spawn_blocking(move || find_duplicates(&files, &settings))
+        .await
+        .map_err(|e| AppError::InternalServerError(format!("Duplicate detection failed: {}", e)))?;
+
+    Ok(Json(clusters))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events() -> Extension<Arc<EventBus>> {
+        Extension(Arc::new(EventBus::new(16)))
+    }
+
+    fn deploy_request(files: &[(&str, &str)], message: &str) -> DeployRequest {
+        DeployRequest {
+            files: files
+                .iter()
+                .map(|(path, content)| FileContent {
+                    path: path.to_string(),
+                    content: content.to_string(),
+                })
+                .collect(),
+            message: message.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_deploy_then_pull_round_trips_files() {
+        let db = crate::db::test_database().await;
+        let owner = crate::db::insert_test_user(db.pool()).await;
+        let project_id = crate::db::insert_test_project(db.pool(), owner, "deployed").await;
+
+        let files = [("src/main.rs", "fn main() {\n    if true {}\n}\n"), ("README.md", "# Deployed\n")];
+        let (status, Json(first)) = deploy(State(db.clone()), events(), AuthUser(owner), Path(project_id), ValidatedJson(deploy_request(&files, "first")))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(first.file_count, 2);
+
+        let Json(pulled) = get_code(State(db.clone()), AuthUser(owner), Path(project_id)).await.unwrap();
+        let pulled: Vec<_> = pulled.iter().map(|f| (f.path.as_str(), f.content.as_str())).collect();
+        assert_eq!(pulled, vec![files[1], files[0]]);
+
+        // Redeploying a file replaces its content rather than adding a copy
+        let update = [("src/main.rs", "fn main() {}\n")];
+        let (_, Json(second)) = deploy(State(db.clone()), events(), AuthUser(owner), Path(project_id), ValidatedJson(deploy_request(&update, "second")))
+            .await
+            .unwrap();
+        assert_eq!(second.file_count, 1);
+        let Json(pulled) = get_code(State(db.clone()), AuthUser(owner), Path(project_id)).await.unwrap();
+        assert_eq!(pulled.len(), 2);
+        assert!(pulled.iter().any(|f| f.path == "src/main.rs" && f.content == "fn main() {}\n"));
+
+        // Only the file that changed gets a version, described by the deployment message
+        let versions = sqlx::query_as::<_, (String, String, Option<String>)>(
+            r#"
+            SELECT f.file_path, v.content, v.change_description FROM document_versions v
+            JOIN code_files f ON f.id = v.file_id
+            WHERE f.project_id = $1
+            "#,
+        )
+        .bind(project_id)
+        .fetch_all(db.pool())
+        .await
+        .unwrap();
+        assert_eq!(
+            versions,
+            vec![("src/main.rs".to_string(), "fn main() {}\n".to_string(), Some("second".to_string()))]
+        );
+
+        let Json(history) = list_deployments(
+            State(db.clone()),
+            AuthUser(owner),
+            Path(project_id),
+            Query(DeploymentHistoryQuery { limit: None }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(history.iter().map(|d| d.message.as_str()).collect::<Vec<_>>(), vec!["second", "first"]);
+
+        let Json(history) = list_deployments(
+            State(db.clone()),
+            AuthUser(owner),
+            Path(project_id),
+            Query(DeploymentHistoryQuery { limit: Some(1) }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(history.len(), 1);
+
+        let Json(analysis) = analyze(State(db.clone()), AuthUser(owner), Path(project_id)).await.unwrap();
+        assert_eq!(analysis.lines_of_code, 2);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_history_lists_deployments_newest_first() {
+        let db = crate::db::test_database().await;
+        let owner = crate::db::insert_test_user(db.pool()).await;
+        let stranger = crate::db::insert_test_user(db.pool()).await;
+        let project_id = crate::db::insert_test_project(db.pool(), owner, "deployed").await;
+
+        let (_, Json(first)) = deploy(State(db.clone()), events(), AuthUser(owner), Path(project_id), ValidatedJson(deploy_request(&[("a.rs", "")], "first")))
+            .await
+            .unwrap();
+        let (_, Json(second)) = deploy(
+            State(db.clone()),
+            events(),
+            AuthUser(owner),
+            Path(project_id),
+            ValidatedJson(deploy_request(&[("a.rs", "// v2"), ("b.rs", "")], "second")),
+        )
+        .await
+        .unwrap();
+
+        let Json(history) = list_deployments(
+            State(db.clone()),
+            AuthUser(owner),
+            Path(project_id),
+            Query(DeploymentHistoryQuery { limit: None }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.iter().map(|d| d.id).collect::<Vec<_>>(), vec![second.id, first.id]);
+        assert!(history[0].created_at >= history[1].created_at);
+
+        let Json(detail) = get_deployment(State(db.clone()), AuthUser(owner), Path(second.id)).await.unwrap();
+        assert_eq!(detail.message, "second");
+        assert_eq!(detail.file_count, 2);
+        assert_eq!(detail.user_id, owner);
+
+        let result = get_deployment(State(db.clone()), AuthUser(stranger), Path(second.id)).await;
+        assert!(matches!(result, Err(AppError::NotFoundError(_))));
+        let result = get_deployment(State(db.clone()), AuthUser(owner), Path(Uuid::new_v4())).await;
+        assert!(matches!(result, Err(AppError::NotFoundError(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_deploy_requires_write_permission() {
+        let db = crate::db::test_database().await;
+        let owner = crate::db::insert_test_user(db.pool()).await;
+        let reader = crate::db::insert_test_user(db.pool()).await;
+        let stranger = crate::db::insert_test_user(db.pool()).await;
+        let project_id = crate::db::insert_test_project(db.pool(), owner, "deployed").await;
+        sqlx::query(
+            "INSERT INTO project_members (id, project_id, user_id, role, permissions) VALUES ($1, $2, $3, 'viewer', ARRAY['read'])",
+        )
+        .bind(Uuid::new_v4())
+        .bind(project_id)
+        .bind(reader)
+        .execute(db.pool())
+        .await
+        .unwrap();
+
+        let files = [("src/lib.rs", "")];
+        let result = deploy(State(db.clone()), events(), AuthUser(reader), Path(project_id), ValidatedJson(deploy_request(&files, "nope"))).await;
+        assert!(matches!(result, Err(AppError::AuthorizationError(_))));
+        let result = deploy(State(db.clone()), events(), AuthUser(stranger), Path(project_id), ValidatedJson(deploy_request(&files, "nope"))).await;
+        assert!(matches!(result, Err(AppError::NotFoundError(_))));
+
+        // Readers can still pull
+        assert!(get_code(State(db.clone()), AuthUser(reader), Path(project_id)).await.is_ok());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_duplicates_finds_code_copied_between_files() {
+        let db = crate::db::test_database().await;
+        let owner = crate::db::insert_test_user(db.pool()).await;
+        let stranger = crate::db::insert_test_user(db.pool()).await;
+        let project_id = crate::db::insert_test_project(db.pool(), owner, "deployed").await;
+
+        let function = "def total_price(items, tax_rate):\n    subtotal = 0\n    for item in items:\n        if item.quantity > 0:\n            subtotal += item.price * item.quantity\n    discount = subtotal * 0.1 if subtotal > 100 else 0\n    return round((subtotal - discount) * (1 + tax_rate), 2)\n";
+        let files = [
+            ("shop/cart.py", format!("import math\n\n{}", function)),
+            ("shop/invoice.py", format!("{}\n\ndef header(name):\n    return name.upper()\n", function)),
+            ("shop/__init__.py", "VERSION = 1\n".to_string()),
+        ];
+        let files: Vec<(&str, &str)> = files.iter().map(|(path, content)| (*path, content.as_str())).collect();
+        let (status, _) = deploy(State(db.clone()), events(), AuthUser(owner), Path(project_id), ValidatedJson(deploy_request(&files, "shop")))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+
+        let query = || Query(DuplicatesQuery { min_tokens: Some(30), threshold: None });
+        let Json(clusters) = duplicates(State(db.clone()), AuthUser(owner), Path(project_id), query()).await.unwrap();
+        assert_eq!(clusters.len(), 1);
+        let paths: Vec<&str> = clusters[0].spans.iter().map(|span| span.file_path.as_str()).collect();
+        assert_eq!(paths, vec!["shop/cart.py", "shop/invoice.py"]);
+        assert_eq!(clusters[0].spans[1].start_line, 1);
+
+        let result = duplicates(State(db.clone()), AuthUser(stranger), Path(project_id), query()).await;
+        assert!(matches!(result, Err(AppError::NotFoundError(_))));
+        let result = duplicates(
+            State(db.clone()),
+            AuthUser(owner),
+            Path(project_id),
+            Query(DuplicatesQuery { min_tokens: None, threshold: Some(1.5) }),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::ValidationError(_))));
+    }
+}
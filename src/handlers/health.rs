@@ -0,0 +1,264 @@
+use axum::{extract::State, http::StatusCode, Extension, Json};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{
+    config::Config,
+    db::Database,
+    models::HealthStatus,
+    services::{
+        agent::AgentQueue,
+        ai::ai_circuit_breaker,
+        circuit_breaker::{CircuitBreaker, CircuitState},
+    },
+};
+
+/// How long `/readyz` waits on the AI API before counting it as down
+const AI_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Dependencies `/readyz` checks besides the database, taken from `Config`
+#[derive(Debug, Clone, Default)]
+pub struct ReadinessProbes {
+    /// Base URL of the AI API; `None` when `READINESS_CHECK_AI` is off
+    pub ai_api_url: Option<String>,
+    /// The breaker AI calls go through, reported whether or not the API is probed
+    pub ai_breaker: Arc<CircuitBreaker>,
+    /// Shared by every AI probe so each `/readyz` reuses pooled connections
+    client: reqwest::Client,
+}
+
+impl ReadinessProbes {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            ai_api_url: config
+                .readiness_check_ai
+                .then(|| config.ai_api_url.clone()),
+            ai_breaker: ai_circuit_breaker(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Any answer below 500 means the API is up; auth is the AI calls' concern, not ours
+    async fn ai_reachable(&self, api_url: &str) -> bool {
+        let url = format!("{}/models", api_url.trim_end_matches('/'));
+        match self
+            .client
+            .get(&url)
+            .timeout(AI_PROBE_TIMEOUT)
+            .send()
+            .await
+        {
+            Ok(response) if !response.status().is_server_error() => true,
+            Ok(response) => {
+                tracing::error!("Readiness AI probe got {}", response.status());
+                false
+            }
+            Err(e) => {
+                tracing::error!("Readiness AI probe failed: {:?}", e);
+                false
+            }
+        }
+    }
+}
+
+/// Readiness probe, also served as `/health`: database connectivity, applied migrations
+/// and (optionally) AI API reachability, plus agent queue load and the AI circuit breaker's
+/// state. 503 names what failed.
+pub async fn readyz(
+    State(db): State<Arc<Database>>,
+    Extension(queue): Extension<Arc<AgentQueue>>,
+    Extension(probes): Extension<Arc<ReadinessProbes>>,
+) -> (StatusCode, Json<HealthStatus>) {
+    let database = async {
+        match sqlx::query("SELECT 1").execute(db.pool()).await {
+            Ok(_) => true,
+            Err(e) => {
+                tracing::error!("Readiness database probe failed: {:?}", e);
+                false
+            }
+        }
+    };
+    let migrations = async {
+        match db.migrations_applied().await {
+            Ok(applied) => applied,
+            Err(e) => {
+                tracing::error!("Readiness migrations probe failed: {:?}", e);
+                false
+            }
+        }
+    };
+    let ai_circuit = probes.ai_breaker.state();
+    let ai = async {
+        match &probes.ai_api_url {
+            // AI calls are being refused, so it's down whatever a probe would say
+            Some(_) if ai_circuit == CircuitState::Open => Some(false),
+            Some(api_url) => Some(probes.ai_reachable(api_url).await),
+            None => None,
+        }
+    };
+    let (database_ok, migrations_ok, ai_ok) = tokio::join!(database, migrations, ai);
+
+    // There is no cache layer yet, so it can't be unhealthy
+    let cache_ok = true;
+
+    let failed: Vec<String> = [
+        ("database", database_ok),
+        ("migrations", migrations_ok),
+        ("cache", cache_ok),
+        ("ai", ai_ok.unwrap_or(true)),
+    ]
+    .into_iter()
+    .filter(|(_, ok)| !ok)
+    .map(|(name, _)| name.to_string())
+    .collect();
+    let ok = failed.is_empty();
+
+    let status = if ok { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+
+    (
+        status,
+        Json(HealthStatus {
+            ok,
+            database_ok,
+            migrations_ok,
+            cache_ok,
+            ai_ok,
+            ai_circuit,
+            failed,
+            agents_running: queue.running(),
+            agent_workers: queue.max_concurrent(),
+            queue_depth: queue.depth(),
+        }),
+    )
+}
+
+/// Liveness probe for orchestrators and load balancers; never touches dependencies
+pub async fn livez() -> &'static str {
+    "OK"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::circuit_breaker::BreakerSettings;
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use sqlx::postgres::PgPoolOptions;
+    use tower::ServiceExt;
+
+    /// An AI API stand-in that answers `GET /models`
+    async fn ai_api() -> String {
+        let app = Router::new().route("/models", get(|| async { "{}" }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        format!("http://{}", addr)
+    }
+
+    /// An address nothing listens on
+    async fn closed_addr() -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    async fn probe(db: Arc<Database>, ai_api_url: Option<String>, uri: &str) -> (StatusCode, HealthStatus) {
+        probe_with_breaker(db, ai_api_url, Arc::new(CircuitBreaker::default()), uri).await
+    }
+
+    async fn probe_with_breaker(
+        db: Arc<Database>,
+        ai_api_url: Option<String>,
+        ai_breaker: Arc<CircuitBreaker>,
+        uri: &str,
+    ) -> (StatusCode, HealthStatus) {
+        let app = Router::new()
+            .route("/readyz", get(readyz))
+            .route("/health", get(readyz))
+            .layer(Extension(Arc::new(AgentQueue::new(2))))
+            .layer(Extension(Arc::new(ReadinessProbes {
+                ai_api_url,
+                ai_breaker,
+                client: reqwest::Client::new(),
+            })))
+            .with_state(db);
+
+        let response = app
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_health_reports_json_status() {
+        let db = crate::db::test_database().await;
+        let ai_api_url = ai_api().await;
+
+        for uri in ["/readyz", "/health"] {
+            let (status, health) = probe(db.clone(), Some(ai_api_url.clone()), uri).await;
+            assert_eq!(status, StatusCode::OK);
+            assert!(health.ok);
+            assert!(health.database_ok);
+            assert!(health.migrations_ok);
+            assert_eq!(health.ai_ok, Some(true));
+            assert_eq!(health.ai_circuit, CircuitState::Closed);
+            assert!(health.failed.is_empty());
+            assert_eq!(health.agents_running, 0);
+            assert_eq!(health.agent_workers, 2);
+            assert_eq!(health.queue_depth, 0);
+        }
+
+        // With the AI check off it is neither probed nor reported
+        let (status, health) = probe(db.clone(), None, "/readyz").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(health.ai_ok, None);
+
+        // An open breaker is always reported, and fails the AI check when there is one
+        let breaker = Arc::new(CircuitBreaker::new(BreakerSettings {
+            failure_threshold: 1,
+            cooldown: Duration::from_secs(60),
+        }));
+        breaker.record_failure();
+        let (status, health) = probe_with_breaker(db.clone(), None, breaker.clone(), "/readyz").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(health.ai_circuit, CircuitState::Open);
+        let (status, health) = probe_with_breaker(db, Some(ai_api_url), breaker, "/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(health.ai_ok, Some(false));
+        assert_eq!(health.failed, vec!["ai"]);
+    }
+
+    #[tokio::test]
+    async fn test_readyz_names_the_failed_dependencies() {
+        let pool = PgPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(500))
+            .connect_lazy(&format!("postgres://postgres@{}/down", closed_addr().await))
+            .unwrap();
+        let db = Arc::new(Database::from_pool(pool));
+
+        let (status, health) = probe(db, Some(format!("http://{}", closed_addr().await)), "/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(!health.ok);
+        assert!(!health.database_ok);
+        assert_eq!(health.ai_ok, Some(false));
+        assert_eq!(health.failed, vec!["database", "migrations", "ai"]);
+    }
+
+    #[tokio::test]
+    async fn test_livez_is_plain_text() {
+        let app: Router = Router::new().route("/livez", get(livez));
+
+        let response = app
+            .oneshot(Request::builder().uri("/livez").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"OK");
+    }
+}
@@ -2,31 +2,46 @@ use axum::{
     extract::{Path, State, Query, Json},
     http::StatusCode,
     response::IntoResponse,
+    Extension,
 };
-use sqlx::{Pool, Postgres};
+use sqlx::{Pool, Postgres, QueryBuilder};
 use uuid::Uuid;
 use chrono::Utc;
 use std::sync::Arc;
 
-use crate::error::ApiError;
+use crate::error::{AppError, AppResult};
 use crate::models::inheritance::{
     TeamHierarchy, ProjectHierarchy, CreateTeamHierarchyRequest,
     CreateProjectHierarchyRequest, PermissionRule, CreatePermissionRuleRequest,
     UpdatePermissionRuleRequest, AuditLog, AuditLogQuery, ResolvedPermissions,
 };
 use crate::middleware::rbac;
+use crate::middleware_auth::AuthUser;
+use crate::services::audit::{self, AuditEntry, ClientInfo};
 use crate::services::InheritanceEngine;
-use crate::models::inheritance::InheritanceConfig;
+use crate::utils::pagination::{page_size, Cursor, Page};
+use crate::utils::validation::ValidatedJson;
 
 /// Create team hierarchy relationship
 pub async fn create_team_hierarchy(
     State(pool): State<Pool<Postgres>>,
-    user_id: Uuid,
-    Json(req): Json<CreateTeamHierarchyRequest>,
-) -> Result<impl IntoResponse, ApiError> {
+    AuthUser(user_id): AuthUser,
+    client: ClientInfo,
+    ValidatedJson(req): ValidatedJson<CreateTeamHierarchyRequest>,
+) -> AppResult<impl IntoResponse> {
     // Verify user is owner of parent team
     rbac::enforce_role(&pool, user_id, req.parent_team_id, 4).await?;
 
+    // The check and the insert share a transaction holding the hierarchy lock, so two
+    // concurrent requests can't each add half of a cycle
+    let mut tx = pool.begin().await?;
+    let creates_cycle = InheritanceEngine::would_create_cycle(&mut tx, req.child_team_id, req.parent_team_id, "team")
+        .await
+        .map_err(|_| AppError::ValidationError("Failed to validate hierarchy".to_string()))?;
+    if creates_cycle {
+        return Err(AppError::ValidationError("Hierarchy would create a cycle".to_string()));
+    }
+
     let hierarchy_id = Uuid::new_v4();
 
     sqlx::query(
@@ -39,11 +54,9 @@ pub async fn create_team_hierarchy(
     .bind(&req.parent_team_id)
     .bind(&req.child_team_id)
     .bind(req.inheritance_enabled.unwrap_or(true))
-    .execute(&pool)
+    .execute(&mut *tx)
     .await?;
 
-    log_audit(&pool, user_id, "create_team_hierarchy", "team_hierarchy", hierarchy_id, None, None).await?;
-
     let hierarchy = TeamHierarchy {
         id: hierarchy_id,
         parent_team_id: Some(req.parent_team_id),
@@ -52,18 +65,38 @@ pub async fn create_team_hierarchy(
         created_at: Utc::now(),
     };
 
+    audit::record(
+        &mut *tx,
+        &client,
+        AuditEntry::new(user_id, "create_team_hierarchy", "team_hierarchy", hierarchy_id)
+            .new_value(serde_json::json!(hierarchy)),
+    )
+    .await?;
+    tx.commit().await?;
+
     Ok((StatusCode::CREATED, Json(hierarchy)))
 }
 
 /// Create project hierarchy relationship
 pub async fn create_project_hierarchy(
     State(pool): State<Pool<Postgres>>,
-    user_id: Uuid,
-    Json(req): Json<CreateProjectHierarchyRequest>,
-) -> Result<impl IntoResponse, ApiError> {
+    AuthUser(user_id): AuthUser,
+    client: ClientInfo,
+    ValidatedJson(req): ValidatedJson<CreateProjectHierarchyRequest>,
+) -> AppResult<impl IntoResponse> {
     // Verify user has admin permission on parent project
     rbac::enforce_permission(&pool, user_id, req.parent_project_id, "admin").await?;
 
+    // The check and the insert share a transaction holding the hierarchy lock, so two
+    // concurrent requests can't each add half of a cycle
+    let mut tx = pool.begin().await?;
+    let creates_cycle = InheritanceEngine::would_create_cycle(&mut tx, req.child_project_id, req.parent_project_id, "project")
+        .await
+        .map_err(|_| AppError::ValidationError("Failed to validate hierarchy".to_string()))?;
+    if creates_cycle {
+        return Err(AppError::ValidationError("Hierarchy would create a cycle".to_string()));
+    }
+
     let hierarchy_id = Uuid::new_v4();
 
     sqlx::query(
@@ -76,11 +109,9 @@ pub async fn create_project_hierarchy(
     .bind(&req.parent_project_id)
     .bind(&req.child_project_id)
     .bind(req.inheritance_enabled.unwrap_or(true))
-    .execute(&pool)
+    .execute(&mut *tx)
     .await?;
 
-    log_audit(&pool, user_id, "create_project_hierarchy", "project_hierarchy", hierarchy_id, None, None).await?;
-
     let hierarchy = ProjectHierarchy {
         id: hierarchy_id,
         parent_project_id: Some(req.parent_project_id),
@@ -89,20 +120,30 @@ pub async fn create_project_hierarchy(
         created_at: Utc::now(),
     };
 
+    audit::record(
+        &mut *tx,
+        &client,
+        AuditEntry::new(user_id, "create_project_hierarchy", "project_hierarchy", hierarchy_id)
+            .new_value(serde_json::json!(hierarchy)),
+    )
+    .await?;
+    tx.commit().await?;
+
     Ok((StatusCode::CREATED, Json(hierarchy)))
 }
 
 /// Get resolved permissions for user on resource
 pub async fn get_resolved_permissions(
     State(pool): State<Pool<Postgres>>,
+    Extension(engine): Extension<Arc<InheritanceEngine>>,
     Path((resource_id, resource_type)): Path<(Uuid, String)>,
-    user_id: Uuid,
-) -> Result<impl IntoResponse, ApiError> {
+    AuthUser(user_id): AuthUser,
+) -> AppResult<impl IntoResponse> {
     // Verify user has access
-    rbac::enforce_permission_with_inheritance(&pool, user_id, resource_id, &resource_type, "read")
+    rbac::enforce_permission_with_inheritance(&pool, &engine, user_id, resource_id, &resource_type, "read")
         .await?;
 
-    let resolved = rbac::get_resolved_permissions(&pool, user_id, resource_id, &resource_type).await?;
+    let resolved = rbac::get_resolved_permissions(&engine, user_id, resource_id, &resource_type).await?;
 
     Ok(Json(resolved))
 }
@@ -110,16 +151,17 @@ pub async fn get_resolved_permissions(
 /// Create permission rule for role
 pub async fn create_permission_rule(
     State(pool): State<Pool<Postgres>>,
-    user_id: Uuid,
-    Json(req): Json<CreatePermissionRuleRequest>,
-) -> Result<impl IntoResponse, ApiError> {
+    Extension(engine): Extension<Arc<InheritanceEngine>>,
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(req): ValidatedJson<CreatePermissionRuleRequest>,
+) -> AppResult<impl IntoResponse> {
     // Verify user can manage permissions
     if let Some(team_id) = req.team_id {
         rbac::enforce_role(&pool, user_id, team_id, 3).await?; // Admin level
     } else if let Some(project_id) = req.project_id {
         rbac::enforce_permission(&pool, user_id, project_id, "admin").await?;
     } else {
-        return Err(ApiError::BadRequest("Team or Project ID required".to_string()));
+        return Err(AppError::ValidationError("Team or Project ID required".to_string()));
     }
 
     let rule_id = Uuid::new_v4();
@@ -140,6 +182,9 @@ pub async fn create_permission_rule(
     .execute(&pool)
     .await?;
 
+    // Rules apply to every member holding the role, so cached resolutions are stale
+    engine.clear_cache().await;
+
     let rule = PermissionRule {
         id: rule_id,
         team_id: req.team_id,
@@ -158,10 +203,11 @@ pub async fn create_permission_rule(
 /// Update permission rule
 pub async fn update_permission_rule(
     State(pool): State<Pool<Postgres>>,
+    Extension(engine): Extension<Arc<InheritanceEngine>>,
     Path(rule_id): Path<Uuid>,
-    user_id: Uuid,
-    Json(req): Json<UpdatePermissionRuleRequest>,
-) -> Result<impl IntoResponse, ApiError> {
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(req): ValidatedJson<UpdatePermissionRuleRequest>,
+) -> AppResult<impl IntoResponse> {
     // Get rule to verify access
     let rule = sqlx::query_as::<_, PermissionRule>(
         "SELECT * FROM permission_rules WHERE id = $1"
@@ -169,7 +215,7 @@ pub async fn update_permission_rule(
     .bind(rule_id)
     .fetch_optional(&pool)
     .await?
-    .ok_or(ApiError::NotFound)?;
+    .ok_or(AppError::NotFoundError("Permission rule not found".to_string()))?;
 
     if let Some(team_id) = rule.team_id {
         rbac::enforce_role(&pool, user_id, team_id, 3).await?;
@@ -195,15 +241,18 @@ pub async fn update_permission_rule(
     .execute(&pool)
     .await?;
 
+    engine.clear_cache().await;
+
     Ok(StatusCode::OK)
 }
 
 /// Delete permission rule
 pub async fn delete_permission_rule(
     State(pool): State<Pool<Postgres>>,
+    Extension(engine): Extension<Arc<InheritanceEngine>>,
     Path(rule_id): Path<Uuid>,
-    user_id: Uuid,
-) -> Result<impl IntoResponse, ApiError> {
+    AuthUser(user_id): AuthUser,
+) -> AppResult<impl IntoResponse> {
     // Get rule to verify access
     let rule = sqlx::query_as::<_, PermissionRule>(
         "SELECT * FROM permission_rules WHERE id = $1"
@@ -211,7 +260,7 @@ pub async fn delete_permission_rule(
     .bind(rule_id)
     .fetch_optional(&pool)
     .await?
-    .ok_or(ApiError::NotFound)?;
+    .ok_or(AppError::NotFoundError("Permission rule not found".to_string()))?;
 
     if let Some(team_id) = rule.team_id {
         rbac::enforce_role(&pool, user_id, team_id, 4).await?; // Owner level
@@ -224,98 +273,261 @@ pub async fn delete_permission_rule(
         .execute(&pool)
         .await?;
 
+    engine.clear_cache().await;
+
     Ok(StatusCode::NO_CONTENT)
 }
 
 /// Get audit logs
 pub async fn get_audit_logs(
     State(pool): State<Pool<Postgres>>,
-    user_id: Uuid,
-    Query(query): Query<AuditLogQuery>,
-) -> Result<impl IntoResponse, ApiError> {
-    // User can only view their own actions or if they have audit permission
-    let mut sql = "SELECT * FROM audit_logs WHERE 1=1".to_string();
-    let mut conditions = vec![];
-
-    if let Some(actor_id) = query.actor_id {
-        if actor_id != user_id {
-            // Check if current user has view_audit permission
-            // For now, restrict to own actions
-            return Err(ApiError::Forbidden);
+    AuthUser(user_id): AuthUser,
+    Query(mut query): Query<AuditLogQuery>,
+) -> AppResult<impl IntoResponse> {
+    // Other users' actions are only visible for a resource the caller holds view_audit on
+    match (query.resource_type.as_deref(), query.resource_id) {
+        (Some(resource_type), Some(resource_id)) if query.actor_id != Some(user_id) => {
+            enforce_view_audit(&pool, user_id, resource_type, resource_id).await?;
         }
-        conditions.push(format!("actor_id = '{}'", actor_id));
+        _ => match query.actor_id {
+            Some(actor_id) if actor_id != user_id => return Err(AppError::AuthorizationError("Only your own actions are visible without view_audit on a resource".to_string())),
+            _ => query.actor_id = Some(user_id),
+        },
     }
 
-    if let Some(resource_type) = query.resource_type {
-        conditions.push(format!("resource_type = '{}'", resource_type));
+    let page = fetch_audit_logs(&pool, &query).await?;
+
+    Ok(Json(page))
+}
+
+/// Require view_audit on a project or team; project owners always hold it
+async fn enforce_view_audit(
+    pool: &Pool<Postgres>,
+    user_id: Uuid,
+    resource_type: &str,
+    resource_id: Uuid,
+) -> AppResult<()> {
+    match resource_type {
+        "project" => {
+            if rbac::check_project_admin(pool, user_id, resource_id).await? {
+                return Ok(());
+            }
+            rbac::enforce_permission(pool, user_id, resource_id, "view_audit").await
+        }
+        "team" => rbac::enforce_team_permission(pool, user_id, resource_id, "view_audit").await,
+        _ => Err(AppError::AuthorizationError("Audit logs are only kept for projects and teams".to_string())),
     }
+}
+
+/// Query one page of audit logs, newest first, with every filter bound as a parameter
+async fn fetch_audit_logs(
+    pool: &Pool<Postgres>,
+    query: &AuditLogQuery,
+) -> AppResult<Page<AuditLog>> {
+    let after = match &query.cursor {
+        Some(cursor) => Some(
+            Cursor::decode(cursor).ok_or_else(|| AppError::ValidationError("Invalid cursor".to_string()))?,
+        ),
+        None => None,
+    };
+    let limit = page_size(query.limit);
 
+    let mut builder = QueryBuilder::<Postgres>::new("SELECT * FROM audit_logs WHERE 1=1");
+
+    if let Some(actor_id) = query.actor_id {
+        builder.push(" AND actor_id = ").push_bind(actor_id);
+    }
+    if let Some(resource_type) = &query.resource_type {
+        builder.push(" AND resource_type = ").push_bind(resource_type.clone());
+    }
     if let Some(resource_id) = query.resource_id {
-        conditions.push(format!("resource_id = '{}'", resource_id));
+        builder.push(" AND resource_id = ").push_bind(resource_id);
+    }
+    if let Some(start_date) = query.start_date {
+        builder.push(" AND created_at >= ").push_bind(start_date);
+    }
+    if let Some(end_date) = query.end_date {
+        builder.push(" AND created_at <= ").push_bind(end_date);
     }
 
-    for condition in conditions {
-        sql.push_str(&format!(" AND {}", condition));
+    // Keyset on (created_at, id) so deep pages cost the same as the first
+    if let Some(after) = after {
+        builder
+            .push(" AND (created_at, id) < (")
+            .push_bind(after.created_at)
+            .push(", ")
+            .push_bind(after.id)
+            .push(")");
     }
 
-    sql.push_str(" ORDER BY created_at DESC LIMIT 100");
+    builder
+        .push(" ORDER BY created_at DESC, id DESC LIMIT ")
+        .push_bind(limit + 1);
 
-    let logs = sqlx::query_as::<_, AuditLog>(&sql)
-        .fetch_all(&pool)
-        .await?;
-
-    Ok(Json(logs))
+    let logs = builder.build_query_as::<AuditLog>().fetch_all(pool).await?;
+    Ok(Page::from_rows(logs, limit, |log| Cursor::new(log.created_at, log.id)))
 }
 
 /// Get hierarchy tree
 pub async fn get_hierarchy_tree(
     State(pool): State<Pool<Postgres>>,
+    Extension(engine): Extension<Arc<InheritanceEngine>>,
     Path((resource_id, resource_type)): Path<(Uuid, String)>,
-    user_id: Uuid,
-) -> Result<impl IntoResponse, ApiError> {
+    AuthUser(user_id): AuthUser,
+) -> AppResult<impl IntoResponse> {
     // Verify access
-    rbac::enforce_permission_with_inheritance(&pool, user_id, resource_id, &resource_type, "read")
+    rbac::enforce_permission_with_inheritance(&pool, &engine, user_id, resource_id, &resource_type, "read")
         .await?;
 
-    let engine = InheritanceEngine::new(
-        Arc::new(pool.clone()),
-        Some(InheritanceConfig::default()),
-    );
-
     let tree = engine
         .build_hierarchy_tree(resource_id, &resource_type, "root")
         .await
-        .map_err(|_| ApiError::BadRequest("Failed to build hierarchy".to_string()))?;
+        .map_err(|_| AppError::ValidationError("Failed to build hierarchy".to_string()))?;
 
     Ok(Json(tree))
 }
 
-/// Log audit event
-async fn log_audit(
-    pool: &Pool<Postgres>,
-    actor_id: Uuid,
-    action: &str,
-    resource_type: &str,
-    resource_id: Uuid,
-    old_value: Option<serde_json::Value>,
-    new_value: Option<serde_json::Value>,
-) -> Result<(), ApiError> {
-    sqlx::query(
-        r#"
-        INSERT INTO audit_logs (id, actor_id, action, resource_type, resource_id, old_value, new_value, created_at)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
-        "#,
-    )
-    .bind(Uuid::new_v4())
-    .bind(actor_id)
-    .bind(action)
-    .bind(resource_type)
-    .bind(resource_id)
-    .bind(old_value)
-    .bind(new_value)
-    .bind(Utc::now())
-    .execute(pool)
-    .await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_audit_log_filters_are_not_injectable() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool();
+
+        let actor_id = crate::db::insert_test_user(pool).await;
+        let entry = AuditEntry::new(actor_id, "create_team_hierarchy", "team_hierarchy", Uuid::new_v4());
+        audit::record(pool, &ClientInfo::default(), entry).await.unwrap();
+
+        let mut query = AuditLogQuery {
+            actor_id: Some(actor_id),
+            resource_type: Some("team_hierarchy".to_string()),
+            resource_id: None,
+            start_date: None,
+            end_date: None,
+            limit: None,
+            cursor: None,
+        };
+        assert_eq!(fetch_audit_logs(pool, &query).await.unwrap().items.len(), 1);
+
+        query.resource_type = Some("x' OR '1'='1".to_string());
+        assert!(fetch_audit_logs(pool, &query).await.unwrap().items.is_empty());
+
+        query.actor_id = None;
+        assert!(fetch_audit_logs(pool, &query).await.unwrap().items.is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_audit_log_pages_have_no_overlap_or_gaps() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool().clone();
+        let actor_id = crate::db::insert_test_user(&pool).await;
+
+        // Two entries share a timestamp, so the id has to break the tie across pages
+        let base = Utc::now();
+        let offsets = [0, 1, 2, 2, 3];
+        for seconds in offsets {
+            sqlx::query(
+                "INSERT INTO audit_logs (id, actor_id, action, resource_type, resource_id, created_at) VALUES ($1, $2, 'update_project', 'project', $3, $4)",
+            )
+            .bind(Uuid::new_v4())
+            .bind(actor_id)
+            .bind(Uuid::new_v4())
+            .bind(base - chrono::Duration::seconds(seconds))
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
 
-    Ok(())
+        let mut query = AuditLogQuery {
+            actor_id: Some(actor_id),
+            resource_type: None,
+            resource_id: None,
+            start_date: None,
+            end_date: None,
+            limit: Some(3),
+            cursor: None,
+        };
+        let first = fetch_audit_logs(&pool, &query).await.unwrap();
+        assert_eq!(first.items.len(), 3);
+        query.cursor = Some(first.next_cursor.clone().expect("a second page"));
+
+        let second = fetch_audit_logs(&pool, &query).await.unwrap();
+        assert_eq!(second.items.len(), 2);
+        assert_eq!(second.next_cursor, None);
+
+        let walked: Vec<(chrono::DateTime<Utc>, Uuid)> = first
+            .items
+            .iter()
+            .chain(&second.items)
+            .map(|log| (log.created_at, log.id))
+            .collect();
+        let mut expected = walked.clone();
+        expected.sort_by(|a, b| b.cmp(a));
+        expected.dedup();
+        assert_eq!(walked, expected);
+
+        query.cursor = Some("garbage".to_string());
+        assert!(matches!(fetch_audit_logs(&pool, &query).await, Err(AppError::ValidationError(_))));
+    }
+
+    fn project_audit_query(project_id: Uuid) -> AuditLogQuery {
+        AuditLogQuery {
+            actor_id: None,
+            resource_type: Some("project".to_string()),
+            resource_id: Some(project_id),
+            start_date: None,
+            end_date: None,
+            limit: None,
+            cursor: None,
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_view_audit_grants_access_to_project_audit_logs() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool().clone();
+
+        let owner_id = crate::db::insert_test_user(&pool).await;
+        let member_id = crate::db::insert_test_user(&pool).await;
+        let project_id = crate::db::insert_test_project(&pool, owner_id, "audited").await;
+        sqlx::query(
+            "INSERT INTO project_members (id, project_id, user_id, role, permissions) VALUES ($1, $2, $3, 'member', ARRAY['read'])",
+        )
+        .bind(Uuid::new_v4())
+        .bind(project_id)
+        .bind(member_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+        let entry = AuditEntry::new(owner_id, "update_project", "project", project_id);
+        audit::record(&pool, &ClientInfo::default(), entry).await.unwrap();
+
+        let as_member = || get_audit_logs(State(pool.clone()), AuthUser(member_id), Query(project_audit_query(project_id)));
+
+        // Plain members can't read the owner's actions on the project
+        assert!(matches!(as_member().await, Err(AppError::AuthorizationError(_))));
+
+        // Owners can, without an explicit grant
+        let as_owner = get_audit_logs(State(pool.clone()), AuthUser(owner_id), Query(project_audit_query(project_id))).await;
+        assert!(as_owner.is_ok());
+
+        sqlx::query("UPDATE project_members SET permissions = ARRAY['read', 'view_audit'] WHERE user_id = $1")
+            .bind(member_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(as_member().await.is_ok());
+
+        // view_audit on one project says nothing about another user's actions elsewhere
+        let mut unscoped = project_audit_query(project_id);
+        unscoped.resource_id = None;
+        unscoped.actor_id = Some(owner_id);
+        let result = get_audit_logs(State(pool.clone()), AuthUser(member_id), Query(unscoped)).await;
+        assert!(matches!(result, Err(AppError::AuthorizationError(_))));
+    }
 }
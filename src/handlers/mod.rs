@@ -3,8 +3,10 @@ pub mod code_analysis;
 pub mod agents;
 pub mod projects;
 pub mod analytics;
-pub mod team;
+pub mod health;
 pub mod collaboration;
 pub mod code_review;
 pub mod teams;
 pub mod inheritance;
+pub mod deployments;
+pub mod webhooks;
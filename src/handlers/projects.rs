@@ -1,19 +1,111 @@
-use axum::{extract::State, Json, Path};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use sqlx::{postgres::PgRow, Postgres, Row, Transaction};
 use std::sync::Arc;
 use uuid::Uuid;
 
 use crate::{
     db::Database,
     error::{AppError, AppResult},
-    models::{CreateProjectRequest, Project, UpdateProjectRequest},
+    middleware_auth::AuthUser,
+    models::{
+        CodeFile, CreateFileRequest, CreateProjectRequest, DeleteProjectQuery,
+        DocumentVersion, Project, TransferProjectRequest, TrashedProject, UpdateFileRequest, UpdateProjectRequest,
+    },
+    services::{
+        audit::{self, AuditEntry, ClientInfo},
+        diff,
+        events::{DomainEvent, EventBus},
+    },
+    utils::{
+        etag::{ETagged, IfMatch},
+        validation::ValidatedJson,
+    },
 };
 
+/// Projects the user owns or has been added to as a member, unless they're in the trash
+const VISIBLE_TO_USER: &str =
+    "deleted_at IS NULL AND (user_id = $2 OR EXISTS (SELECT 1 FROM project_members pm WHERE pm.project_id = projects.id AND pm.user_id = $2))";
+
+/// Ensure the project exists and is visible to the user, without revealing which check failed
+pub(crate) async fn ensure_project_access(db: &Database, project_id: Uuid, user_id: Uuid) -> AppResult<()> {
+    let visible = sqlx::query(&format!(
+        "SELECT 1 FROM projects WHERE id = $1 AND {}",
+        VISIBLE_TO_USER
+    ))
+    .bind(project_id)
+    .bind(user_id)
+    .fetch_optional(db.pool())
+    .await?;
+
+    visible
+        .map(|_| ())
+        .ok_or(AppError::NotFoundError("Project not found".to_string()))
+}
+
+/// Ensure the user owns the project or holds `permission` on it as a member. Users who
+/// can't see the project at all get the same 404 as `ensure_project_access`.
+pub(crate) async fn ensure_project_permission(
+    db: &Database,
+    project_id: Uuid,
+    user_id: Uuid,
+    permission: &str,
+) -> AppResult<()> {
+    let allowed = sqlx::query_scalar::<_, bool>(
+        r#"
+        SELECT COALESCE(p.user_id = $2 OR $3 = ANY(pm.permissions), FALSE)
+        FROM projects p
+        LEFT JOIN project_members pm ON pm.project_id = p.id AND pm.user_id = $2
+        WHERE p.id = $1 AND p.deleted_at IS NULL AND (p.user_id = $2 OR pm.user_id IS NOT NULL)
+        "#,
+    )
+    .bind(project_id)
+    .bind(user_id)
+    .bind(permission)
+    .fetch_optional(db.pool())
+    .await?
+    .ok_or(AppError::NotFoundError("Project not found".to_string()))?;
+
+    if !allowed {
+        return Err(AppError::AuthorizationError(format!(
+            "The '{}' permission is required on this project",
+            permission
+        )));
+    }
+    Ok(())
+}
+
+/// Find or create the user's scratch project, which holds work not tied to a real project.
+/// A trashed scratch project is restored rather than left holding new work out of sight.
+pub(crate) async fn scratch_project_id(db: &Database, user_id: Uuid) -> AppResult<Uuid> {
+    sqlx::query(
+        "INSERT INTO projects (id, user_id, name, description, is_scratch) VALUES ($1, $2, $3, $4, TRUE) ON CONFLICT (user_id) WHERE is_scratch DO UPDATE SET deleted_at = NULL WHERE projects.deleted_at IS NOT NULL"
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind("Scratchpad")
+    .bind("Analyses submitted without a project")
+    .execute(db.pool())
+    .await?;
+
+    let id = sqlx::query_scalar::<_, Uuid>("SELECT id FROM projects WHERE user_id = $1 AND is_scratch")
+        .bind(user_id)
+        .fetch_one(db.pool())
+        .await?;
+
+    Ok(id)
+}
+
 pub async fn create_project(
     State(db): State<Arc<Database>>,
-    Json(payload): Json<CreateProjectRequest>,
+    Extension(events): Extension<Arc<EventBus>>,
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(payload): ValidatedJson<CreateProjectRequest>,
 ) -> AppResult<Json<Project>> {
     let project_id = Uuid::new_v4();
-    let user_id = Uuid::new_v4(); // Should extract from JWT token in production
 
     sqlx::query(
         "INSERT INTO projects (id, user_id, name, description, language, repository_url) VALUES ($1, $2, $3, $4, $5, $6)"
@@ -27,6 +119,13 @@ pub async fn create_project(
     .execute(db.pool())
     .await?;
 
+    events.publish(DomainEvent::ProjectCreated {
+        project_id,
+        owner_id: user_id,
+        name: payload.name.clone(),
+    })
+    .await;
+
     Ok(Json(Project {
         id: project_id,
         user_id,
@@ -40,57 +139,52 @@ pub async fn create_project(
 
 pub async fn list_projects(
     State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
 ) -> AppResult<Json<Vec<Project>>> {
-    let rows = sqlx::query("SELECT id, user_id, name, description, language, repository_url, created_at FROM projects LIMIT 50")
-        .fetch_all(db.pool())
-        .await?;
+    let rows = sqlx::query(
+        "SELECT id, user_id, name, description, language, repository_url, created_at FROM projects WHERE deleted_at IS NULL AND (user_id = $1 OR EXISTS (SELECT 1 FROM project_members pm WHERE pm.project_id = projects.id AND pm.user_id = $1)) ORDER BY created_at DESC LIMIT 50"
+    )
+    .bind(user_id)
+    .fetch_all(db.pool())
+    .await?;
 
-    let projects = rows
-        .iter()
-        .map(|row| Project {
-            id: row.get("id"),
-            user_id: row.get("user_id"),
-            name: row.get("name"),
-            description: row.get("description"),
-            language: row.get("language"),
-            repository_url: row.get("repository_url"),
-            created_at: row.get("created_at"),
-        })
-        .collect();
+    let projects = rows.iter().map(project_from_row).collect();
 
     Ok(Json(projects))
 }
 
 pub async fn get_project(
     State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
     Path(id): Path<Uuid>,
-) -> AppResult<Json<Project>> {
-    let row = sqlx::query("SELECT id, user_id, name, description, language, repository_url, created_at FROM projects WHERE id = $1")
-        .bind(&id)
-        .fetch_optional(db.pool())
-        .await?;
+) -> AppResult<ETagged<Json<Project>>> {
+    let row = sqlx::query(&format!(
+        "SELECT id, user_id, name, description, language, repository_url, created_at, updated_at FROM projects WHERE id = $1 AND {}",
+        VISIBLE_TO_USER
+    ))
+    .bind(id)
+    .bind(user_id)
+    .fetch_optional(db.pool())
+    .await?;
 
     let row = row.ok_or(AppError::NotFoundError("Project not found".to_string()))?;
 
-    Ok(Json(Project {
-        id: row.get("id"),
-        user_id: row.get("user_id"),
-        name: row.get("name"),
-        description: row.get("description"),
-        language: row.get("language"),
-        repository_url: row.get("repository_url"),
-        created_at: row.get("created_at"),
-    }))
+    Ok(ETagged(row.get("updated_at"), Json(project_from_row(&row))))
 }
 
+/// Update a project's details. With `If-Match`, the update only applies if nobody else
+/// has changed the project since the client read that ETag.
 pub async fn update_project(
     State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
     Path(id): Path<Uuid>,
-    Json(payload): Json<UpdateProjectRequest>,
-) -> AppResult<Json<Project>> {
-    // Get existing project
-    let row = sqlx::query("SELECT id, user_id, name, description, language, repository_url, created_at FROM projects WHERE id = $1")
+    IfMatch(expected): IfMatch,
+    ValidatedJson(payload): ValidatedJson<UpdateProjectRequest>,
+) -> AppResult<ETagged<Json<Project>>> {
+    // Get existing project; only the owner may change it
+    let row = sqlx::query("SELECT id, user_id, name, description, language, repository_url, created_at FROM projects WHERE id = $1 AND user_id = $2 AND deleted_at IS NULL")
         .bind(&id)
+        .bind(user_id)
         .fetch_optional(db.pool())
         .await?;
 
@@ -100,56 +194,996 @@ pub async fn update_project(
     let description = payload.description.or_else(|| row.get("description"));
     let language = payload.language.or_else(|| row.get("language"));
 
-    sqlx::query("UPDATE projects SET name = $1, description = $2, language = $3, updated_at = CURRENT_TIMESTAMP WHERE id = $4")
-        .bind(&name)
-        .bind(&description)
-        .bind(&language)
-        .bind(&id)
-        .execute(db.pool())
-        .await?;
+    // The precondition is checked by the UPDATE itself, so a write landing between
+    // the read above and this one still counts as a conflict
+    let updated_at = sqlx::query_scalar::<_, chrono::DateTime<chrono::Utc>>(
+        r#"
+        UPDATE projects SET name = $1, description = $2, language = $3, updated_at = NOW()
+        WHERE id = $4 AND user_id = $5 AND deleted_at IS NULL AND ($6::timestamptz IS NULL OR updated_at = $6)
+        RETURNING updated_at
+        "#,
+    )
+    .bind(&name)
+    .bind(&description)
+    .bind(&language)
+    .bind(id)
+    .bind(user_id)
+    .bind(expected)
+    .fetch_optional(db.pool())
+    .await?
+    .ok_or_else(|| match expected {
+        Some(_) => AppError::ConflictError("Stale update: the project has changed since you loaded it, reload and try again".to_string()),
+        None => AppError::NotFoundError("Project not found".to_string()),
+    })?;
 
-    Ok(Json(Project {
-        id,
-        user_id: row.get("user_id"),
-        name,
-        description,
-        language,
-        repository_url: row.get("repository_url"),
-        created_at: row.get("created_at"),
-    }))
+    Ok(ETagged(
+        updated_at,
+        Json(Project {
+            id,
+            user_id: row.get("user_id"),
+            name,
+            description,
+            language,
+            repository_url: row.get("repository_url"),
+            created_at: row.get("created_at"),
+        }),
+    ))
 }
 
+/// Move a project to the trash, or with `?permanent=true` delete it (trashed or not) for good
 pub async fn delete_project(
     State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    client: ClientInfo,
     Path(id): Path<Uuid>,
+    Query(query): Query<DeleteProjectQuery>,
 ) -> AppResult<&'static str> {
-    sqlx::query("DELETE FROM projects WHERE id = $1")
+    // Each returns the project's state before and after, for the audit trail
+    let (sql, action) = if query.permanent {
+        ("DELETE FROM projects WHERE id = $1 AND user_id = $2 RETURNING to_jsonb(projects), NULL::jsonb", "delete_project")
+    } else {
+        (
+            "UPDATE projects SET deleted_at = NOW() WHERE id = $1 AND user_id = $2 AND deleted_at IS NULL RETURNING jsonb_build_object('deleted_at', NULL), jsonb_build_object('deleted_at', deleted_at)",
+            "trash_project",
+        )
+    };
+    let mut tx = db.pool().begin().await?;
+    let (before, after) = sqlx::query_as::<_, (serde_json::Value, Option<serde_json::Value>)>(sql)
         .bind(&id)
-        .execute(db.pool())
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::NotFoundError("Project not found".to_string()))?;
+
+    let mut entry = AuditEntry::new(user_id, action, "project", id).old_value(before);
+    if let Some(after) = after {
+        entry = entry.new_value(after);
+    }
+    audit::record(&mut *tx, &client, entry).await?;
+    tx.commit().await?;
+
+    if query.permanent {
+        Ok("Project permanently deleted")
+    } else {
+        Ok("Project moved to trash")
+    }
+}
+
+/// The caller's trashed projects, most recently deleted first
+pub async fn list_trash(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+) -> AppResult<Json<Vec<TrashedProject>>> {
+    let rows = sqlx::query(
+        "SELECT id, user_id, name, description, language, repository_url, created_at, deleted_at FROM projects WHERE user_id = $1 AND deleted_at IS NOT NULL ORDER BY deleted_at DESC"
+    )
+    .bind(user_id)
+    .fetch_all(db.pool())
+    .await?;
+
+    let projects = rows
+        .iter()
+        .map(|row| TrashedProject {
+            project: project_from_row(row),
+            deleted_at: row.get("deleted_at"),
+        })
+        .collect();
+
+    Ok(Json(projects))
+}
+
+/// Take a project back out of the trash; only its owner can
+pub async fn restore_project(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    Path(id): Path<Uuid>,
+) -> AppResult<Json<Project>> {
+    let row = sqlx::query(
+        "UPDATE projects SET deleted_at = NULL, updated_at = NOW() WHERE id = $1 AND user_id = $2 AND deleted_at IS NOT NULL RETURNING id, user_id, name, description, language, repository_url, created_at"
+    )
+    .bind(id)
+    .bind(user_id)
+    .fetch_optional(db.pool())
+    .await?
+    .ok_or(AppError::NotFoundError("Project not found in trash".to_string()))?;
+
+    Ok(Json(project_from_row(&row)))
+}
+
+/// Hand a project over to another user; owner only. The previous owner keeps no access
+/// unless the new owner adds them as a member.
+pub async fn transfer_project(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    client: ClientInfo,
+    Path(id): Path<Uuid>,
+    ValidatedJson(payload): ValidatedJson<TransferProjectRequest>,
+) -> AppResult<Json<Project>> {
+    let mut tx = db.pool().begin().await?;
+
+    // Members can see the project, so they're told why; anyone else learns nothing
+    let (owner_id, _, is_scratch) = sqlx::query_as::<_, (Uuid, bool, bool)>(
+        r#"
+        SELECT user_id, EXISTS(SELECT 1 FROM project_members pm WHERE pm.project_id = projects.id AND pm.user_id = $2), is_scratch
+        FROM projects
+        WHERE id = $1 AND deleted_at IS NULL
+        FOR UPDATE
+        "#,
+    )
+    .bind(id)
+    .bind(user_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .filter(|(owner_id, is_member, _)| *owner_id == user_id || *is_member)
+    .ok_or(AppError::NotFoundError("Project not found".to_string()))?;
+    if owner_id != user_id {
+        return Err(AppError::AuthorizationError("Only the project owner can transfer it".to_string()));
+    }
+
+    // Each user has exactly one scratch project, so it can't change hands
+    if is_scratch {
+        return Err(AppError::ValidationError("A scratch project can't be transferred".to_string()));
+    }
+    if payload.user_id == user_id {
+        return Err(AppError::ValidationError("You already own this project".to_string()));
+    }
+    let target_exists = sqlx::query_scalar::<_, bool>("SELECT EXISTS(SELECT 1 FROM users WHERE id = $1)")
+        .bind(payload.user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+    if !target_exists {
+        return Err(AppError::ValidationError("No such user to transfer the project to".to_string()));
+    }
+
+    let row = sqlx::query(
+        "UPDATE projects SET user_id = $2, updated_at = NOW() WHERE id = $1 RETURNING id, user_id, name, description, language, repository_url, created_at"
+    )
+    .bind(id)
+    .bind(payload.user_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    // Owners don't need a membership of their own
+    sqlx::query("DELETE FROM project_members WHERE project_id = $1 AND user_id = $2")
+        .bind(id)
+        .bind(payload.user_id)
+        .execute(&mut *tx)
         .await?;
 
-    Ok("Project deleted successfully")
+    let entry = AuditEntry::new(user_id, "transfer_project", "project", id)
+        .old_value(serde_json::json!({ "user_id": user_id }))
+        .new_value(serde_json::json!({ "user_id": payload.user_id }));
+    audit::record(&mut *tx, &client, entry).await?;
+    tx.commit().await?;
+
+    Ok(Json(project_from_row(&row)))
+}
+
+fn project_from_row(row: &sqlx::postgres::PgRow) -> Project {
+    Project {
+        id: row.get("id"),
+        user_id: row.get("user_id"),
+        name: row.get("name"),
+        description: row.get("description"),
+        language: row.get("language"),
+        repository_url: row.get("repository_url"),
+        created_at: row.get("created_at"),
+    }
 }
 
 pub async fn list_files(
     State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
     Path(id): Path<Uuid>,
 ) -> AppResult<Json<Vec<crate::models::CodeFile>>> {
-    let rows = sqlx::query("SELECT id, project_id, file_path, content, language FROM code_files WHERE project_id = $1")
+    ensure_project_access(&db, id, user_id).await?;
+
+    let rows = sqlx::query("SELECT id, project_id, file_path, content, language, updated_at FROM code_files WHERE project_id = $1")
         .bind(&id)
         .fetch_all(db.pool())
         .await?;
 
-    let files = rows
+    let files = rows.iter().map(code_file_from_row).collect();
+
+    Ok(Json(files))
+}
+
+fn code_file_from_row(row: &sqlx::postgres::PgRow) -> CodeFile {
+    CodeFile {
+        id: row.get("id"),
+        project_id: row.get("project_id"),
+        file_path: row.get("file_path"),
+        content: row.get("content"),
+        language: row.get("language"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+pub async fn create_file(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    Path(project_id): Path<Uuid>,
+    ValidatedJson(payload): ValidatedJson<CreateFileRequest>,
+) -> AppResult<(StatusCode, Json<CodeFile>)> {
+    ensure_project_permission(&db, project_id, user_id, "write").await?;
+
+    let row = sqlx::query(
+        r#"
+        INSERT INTO code_files (id, project_id, file_path, content, language)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (project_id, branch, file_path) DO NOTHING
+        RETURNING id, project_id, file_path, content, language, updated_at
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(project_id)
+    .bind(&payload.file_path)
+    .bind(&payload.content)
+    .bind(&payload.language)
+    .fetch_optional(db.pool())
+    .await?
+    .ok_or_else(|| AppError::ConflictError(format!("{} already exists", payload.file_path)))?;
+
+    Ok((StatusCode::CREATED, Json(code_file_from_row(&row))))
+}
+
+pub async fn get_file(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    Path((project_id, file_id)): Path<(Uuid, Uuid)>,
+) -> AppResult<Json<CodeFile>> {
+    ensure_project_permission(&db, project_id, user_id, "read").await?;
+
+    let row = sqlx::query(
+        "SELECT id, project_id, file_path, content, language, updated_at FROM code_files WHERE id = $1 AND project_id = $2",
+    )
+    .bind(file_id)
+    .bind(project_id)
+    .fetch_optional(db.pool())
+    .await?
+    .ok_or(AppError::NotFoundError("File not found".to_string()))?;
+
+    Ok(Json(code_file_from_row(&row)))
+}
+
+/// Replace a file's content, saving the new content as the next entry in its version history.
+/// While someone has the file open in a collaborative session, edits go through the session.
+pub async fn update_file(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    Path((project_id, file_id)): Path<(Uuid, Uuid)>,
+    ValidatedJson(payload): ValidatedJson<UpdateFileRequest>,
+) -> AppResult<Json<CodeFile>> {
+    ensure_project_permission(&db, project_id, user_id, "write").await?;
+
+    let mut tx = db.pool().begin().await?;
+    let row = save_file_content(
+        &mut tx,
+        project_id,
+        file_id,
+        &payload.content,
+        payload.language.as_deref(),
+        user_id,
+        payload.change_description.as_deref(),
+    )
+    .await?;
+    tx.commit().await?;
+
+    diff::reanchor_comments(db.pool(), file_id).await?;
+
+    Ok(Json(code_file_from_row(&row)))
+}
+
+/// Replace a file's content and save it as the next entry in its version history, unless
+/// someone has the file open in a collaborative session. The caller re-anchors review
+/// comments with `diff::reanchor_comments` once the transaction commits.
+pub(crate) async fn save_file_content(
+    tx: &mut Transaction<'_, Postgres>,
+    project_id: Uuid,
+    file_id: Uuid,
+    content: &str,
+    language: Option<&str>,
+    user_id: Uuid,
+    change_description: Option<&str>,
+) -> AppResult<PgRow> {
+    let in_session = sqlx::query_scalar::<_, bool>(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM collaborative_sessions cs
+            JOIN session_participants sp ON sp.session_id = cs.id
+            WHERE cs.file_id = $1 AND cs.status = 'active' AND sp.left_at IS NULL
+        )
+        "#,
+    )
+    .bind(file_id)
+    .fetch_one(&mut **tx)
+    .await?;
+    if in_session {
+        return Err(AppError::ConflictError(
+            "The file is open in a collaborative session; edit it there".to_string(),
+        ));
+    }
+
+    let row = sqlx::query(
+        r#"
+        UPDATE code_files
+        SET content = $1, language = COALESCE($2, language), updated_at = NOW()
+        WHERE id = $3 AND project_id = $4
+        RETURNING id, project_id, file_path, content, language, updated_at
+        "#,
+    )
+    .bind(content)
+    .bind(language)
+    .bind(file_id)
+    .bind(project_id)
+    .fetch_optional(&mut **tx)
+    .await?
+    .ok_or(AppError::NotFoundError("File not found".to_string()))?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO document_versions (id, file_id, version_number, content, author_id, change_description)
+        SELECT $1, $2, COALESCE(MAX(version_number), 0) + 1, $3, $4, $5
+        FROM document_versions
+        WHERE file_id = $2
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(file_id)
+    .bind(content)
+    .bind(user_id)
+    .bind(change_description)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(row)
+}
+
+pub async fn delete_file(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    Path((project_id, file_id)): Path<(Uuid, Uuid)>,
+) -> AppResult<StatusCode> {
+    ensure_project_permission(&db, project_id, user_id, "write").await?;
+
+    let result = sqlx::query("DELETE FROM code_files WHERE id = $1 AND project_id = $2")
+        .bind(file_id)
+        .bind(project_id)
+        .execute(db.pool())
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFoundError("File not found".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_file_versions(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    Path((project_id, file_id)): Path<(Uuid, Uuid)>,
+) -> AppResult<Json<Vec<DocumentVersion>>> {
+    ensure_project_access(&db, project_id, user_id).await?;
+
+    let file = sqlx::query("SELECT id FROM code_files WHERE id = $1 AND project_id = $2")
+        .bind(file_id)
+        .bind(project_id)
+        .fetch_optional(db.pool())
+        .await?;
+
+    if file.is_none() {
+        return Err(AppError::NotFoundError("File not found".to_string()));
+    }
+
+    let rows = sqlx::query(
+        "SELECT id, file_id, version_number, content, author_id, change_description, created_at FROM document_versions WHERE file_id = $1 ORDER BY version_number DESC"
+    )
+    .bind(file_id)
+    .fetch_all(db.pool())
+    .await?;
+
+    let versions = rows
         .iter()
-        .map(|row| crate::models::CodeFile {
+        .map(|row| DocumentVersion {
             id: row.get("id"),
-            project_id: row.get("project_id"),
-            file_path: row.get("file_path"),
+            file_id: row.get("file_id"),
+            version_number: row.get("version_number"),
             content: row.get("content"),
-            language: row.get("language"),
+            author_id: row.get("author_id"),
+            change_description: row.get("change_description"),
+            created_at: row.get("created_at"),
         })
         .collect();
 
-    Ok(Json(files))
+    Ok(Json(versions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_projects_are_scoped_to_owner() {
+        let db = crate::db::test_database().await;
+        let owner = crate::db::insert_test_user(db.pool()).await;
+        let other = crate::db::insert_test_user(db.pool()).await;
+
+        let Json(project) = create_project(
+            State(db.clone()),
+            Extension(Arc::new(EventBus::new(16))),
+            AuthUser(owner),
+            ValidatedJson(CreateProjectRequest {
+                name: "private".to_string(),
+                description: None,
+                language: None,
+                repository_url: None,
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(project.user_id, owner);
+
+        assert!(get_project(State(db.clone()), AuthUser(owner), Path(project.id))
+            .await
+            .is_ok());
+        assert!(matches!(
+            get_project(State(db.clone()), AuthUser(other), Path(project.id)).await,
+            Err(AppError::NotFoundError(_))
+        ));
+        assert!(matches!(
+            update_project(
+                State(db.clone()),
+                AuthUser(other),
+                Path(project.id),
+                IfMatch(None),
+                ValidatedJson(UpdateProjectRequest {
+                    name: Some("taken".to_string()),
+                    description: None,
+                    language: None,
+                }),
+            )
+            .await,
+            Err(AppError::NotFoundError(_))
+        ));
+        assert!(matches!(
+            delete_project(State(db.clone()), AuthUser(other), ClientInfo::default(), Path(project.id), Query(DeleteProjectQuery::default())).await,
+            Err(AppError::NotFoundError(_))
+        ));
+        assert!(matches!(
+            list_files(State(db.clone()), AuthUser(other), Path(project.id)).await,
+            Err(AppError::NotFoundError(_))
+        ));
+
+        let Json(listed) = list_projects(State(db.clone()), AuthUser(other)).await.unwrap();
+        assert!(listed.iter().all(|p| p.id != project.id));
+
+        assert!(delete_project(State(db.clone()), AuthUser(owner), ClientInfo::default(), Path(project.id), Query(DeleteProjectQuery::default()))
+            .await
+            .is_ok());
+    }
+
+    async fn create_owned_project(db: &Arc<Database>, owner: Uuid, name: &str) -> Project {
+        let Json(project) = create_project(
+            State(db.clone()),
+            Extension(Arc::new(EventBus::new(16))),
+            AuthUser(owner),
+            ValidatedJson(CreateProjectRequest {
+                name: name.to_string(),
+                description: None,
+                language: None,
+                repository_url: None,
+            }),
+        )
+        .await
+        .unwrap();
+        project
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_file_crud_lifecycle() {
+        let db = crate::db::test_database().await;
+        let owner = crate::db::insert_test_user(db.pool()).await;
+        let project = create_owned_project(&db, owner, "files").await;
+        let other_project = create_owned_project(&db, owner, "elsewhere").await;
+
+        let (status, Json(file)) = create_file(
+            State(db.clone()),
+            AuthUser(owner),
+            Path(project.id),
+            ValidatedJson(CreateFileRequest {
+                file_path: "src/lib.rs".to_string(),
+                content: "pub fn one() {}".to_string(),
+                language: Some("rust".to_string()),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(file.project_id, project.id);
+
+        // Same path twice is a conflict, not a second copy
+        let duplicate = create_file(
+            State(db.clone()),
+            AuthUser(owner),
+            Path(project.id),
+            ValidatedJson(CreateFileRequest {
+                file_path: "src/lib.rs".to_string(),
+                content: String::new(),
+                language: None,
+            }),
+        )
+        .await;
+        assert!(matches!(duplicate, Err(AppError::ConflictError(_))));
+
+        let Json(fetched) = get_file(State(db.clone()), AuthUser(owner), Path((project.id, file.id)))
+            .await
+            .unwrap();
+        assert_eq!(fetched.content, "pub fn one() {}");
+
+        let Json(updated) = update_file(
+            State(db.clone()),
+            AuthUser(owner),
+            Path((project.id, file.id)),
+            ValidatedJson(UpdateFileRequest {
+                content: "pub fn two() {}".to_string(),
+                language: None,
+                change_description: Some("Rename".to_string()),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(updated.content, "pub fn two() {}");
+        assert_eq!(updated.language.as_deref(), Some("rust"));
+        assert!(updated.updated_at >= fetched.updated_at);
+
+        let Json(versions) = list_file_versions(State(db.clone()), AuthUser(owner), Path((project.id, file.id)))
+            .await
+            .unwrap();
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].version_number, 1);
+        assert_eq!(versions[0].content, "pub fn two() {}");
+        assert_eq!(versions[0].change_description.as_deref(), Some("Rename"));
+
+        // The file doesn't exist under any other project, even one the caller owns
+        assert!(matches!(
+            get_file(State(db.clone()), AuthUser(owner), Path((other_project.id, file.id))).await,
+            Err(AppError::NotFoundError(_))
+        ));
+        assert!(matches!(
+            delete_file(State(db.clone()), AuthUser(owner), Path((other_project.id, file.id))).await,
+            Err(AppError::NotFoundError(_))
+        ));
+
+        assert_eq!(
+            delete_file(State(db.clone()), AuthUser(owner), Path((project.id, file.id)))
+                .await
+                .unwrap(),
+            StatusCode::NO_CONTENT
+        );
+        assert!(matches!(
+            get_file(State(db.clone()), AuthUser(owner), Path((project.id, file.id))).await,
+            Err(AppError::NotFoundError(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_file_mutations_require_write_permission() {
+        let db = crate::db::test_database().await;
+        let owner = crate::db::insert_test_user(db.pool()).await;
+        let reader = crate::db::insert_test_user(db.pool()).await;
+        let project = create_owned_project(&db, owner, "read-only").await;
+        sqlx::query(
+            "INSERT INTO project_members (id, project_id, user_id, role, permissions) VALUES ($1, $2, $3, 'viewer', ARRAY['read'])",
+        )
+        .bind(Uuid::new_v4())
+        .bind(project.id)
+        .bind(reader)
+        .execute(db.pool())
+        .await
+        .unwrap();
+
+        let (_, Json(file)) = create_file(
+            State(db.clone()),
+            AuthUser(owner),
+            Path(project.id),
+            ValidatedJson(CreateFileRequest {
+                file_path: "notes.md".to_string(),
+                content: "hello".to_string(),
+                language: None,
+            }),
+        )
+        .await
+        .unwrap();
+
+        assert!(get_file(State(db.clone()), AuthUser(reader), Path((project.id, file.id)))
+            .await
+            .is_ok());
+        let update = update_file(
+            State(db.clone()),
+            AuthUser(reader),
+            Path((project.id, file.id)),
+            ValidatedJson(UpdateFileRequest {
+                content: "defaced".to_string(),
+                language: None,
+                change_description: None,
+            }),
+        )
+        .await;
+        assert!(matches!(update, Err(AppError::AuthorizationError(_))));
+        assert!(matches!(
+            delete_file(State(db.clone()), AuthUser(reader), Path((project.id, file.id))).await,
+            Err(AppError::AuthorizationError(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_file_in_collaborative_session_rejects_rest_edits() {
+        let db = crate::db::test_database().await;
+        let owner = crate::db::insert_test_user(db.pool()).await;
+        let project = create_owned_project(&db, owner, "in session").await;
+        let (_, Json(file)) = create_file(
+            State(db.clone()),
+            AuthUser(owner),
+            Path(project.id),
+            ValidatedJson(CreateFileRequest {
+                file_path: "main.rs".to_string(),
+                content: "hello".to_string(),
+                language: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let session_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO collaborative_sessions (id, project_id, file_id, session_token) VALUES ($1, $2, $3, $4)")
+            .bind(session_id)
+            .bind(project.id)
+            .bind(file.id)
+            .bind(session_id.to_string())
+            .execute(db.pool())
+            .await
+            .unwrap();
+        let participant_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO session_participants (id, session_id, user_id) VALUES ($1, $2, $3)")
+            .bind(participant_id)
+            .bind(session_id)
+            .bind(owner)
+            .execute(db.pool())
+            .await
+            .unwrap();
+
+        let edit = || {
+            update_file(
+                State(db.clone()),
+                AuthUser(owner),
+                Path((project.id, file.id)),
+                ValidatedJson(UpdateFileRequest {
+                    content: "goodbye".to_string(),
+                    language: None,
+                    change_description: None,
+                }),
+            )
+        };
+        assert!(matches!(edit().await, Err(AppError::ConflictError(_))));
+
+        // Once everyone has left, REST edits are allowed again
+        sqlx::query("UPDATE session_participants SET left_at = NOW() WHERE id = $1")
+            .bind(participant_id)
+            .execute(db.pool())
+            .await
+            .unwrap();
+        assert_eq!(edit().await.unwrap().content, "goodbye");
+    }
+
+    async fn project_exists(db: &Database, project_id: Uuid) -> bool {
+        sqlx::query_scalar::<_, bool>("SELECT EXISTS(SELECT 1 FROM projects WHERE id = $1)")
+            .bind(project_id)
+            .fetch_one(db.pool())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_deleted_project_can_be_restored() {
+        let db = crate::db::test_database().await;
+        let owner = crate::db::insert_test_user(db.pool()).await;
+        let other = crate::db::insert_test_user(db.pool()).await;
+        let project = create_owned_project(&db, owner, "undo-me").await;
+        let (_, Json(file)) = create_file(
+            State(db.clone()),
+            AuthUser(owner),
+            Path(project.id),
+            ValidatedJson(CreateFileRequest {
+                file_path: "main.rs".to_string(),
+                content: "fn main() {}".to_string(),
+                language: None,
+            }),
+        )
+        .await
+        .unwrap();
+
+        delete_project(State(db.clone()), AuthUser(owner), ClientInfo::default(), Path(project.id), Query(DeleteProjectQuery::default()))
+            .await
+            .unwrap();
+
+        // Trashed projects are gone from every listing and lookup, but not from the trash
+        assert!(matches!(
+            get_project(State(db.clone()), AuthUser(owner), Path(project.id)).await,
+            Err(AppError::NotFoundError(_))
+        ));
+        assert!(matches!(
+            get_file(State(db.clone()), AuthUser(owner), Path((project.id, file.id))).await,
+            Err(AppError::NotFoundError(_))
+        ));
+        let Json(listed) = list_projects(State(db.clone()), AuthUser(owner)).await.unwrap();
+        assert!(listed.iter().all(|p| p.id != project.id));
+        let Json(trash) = list_trash(State(db.clone()), AuthUser(owner)).await.unwrap();
+        assert_eq!(trash.len(), 1);
+        assert_eq!(trash[0].project.id, project.id);
+
+        assert!(matches!(
+            restore_project(State(db.clone()), AuthUser(other), Path(project.id)).await,
+            Err(AppError::NotFoundError(_))
+        ));
+        let Json(restored) = restore_project(State(db.clone()), AuthUser(owner), Path(project.id))
+            .await
+            .unwrap();
+        assert_eq!(restored.id, project.id);
+
+        // Everything inside survived the round trip
+        let Json(fetched) = get_file(State(db.clone()), AuthUser(owner), Path((project.id, file.id)))
+            .await
+            .unwrap();
+        assert_eq!(fetched.content, "fn main() {}");
+        let Json(trash) = list_trash(State(db.clone()), AuthUser(owner)).await.unwrap();
+        assert!(trash.is_empty());
+
+        // Restoring twice has nothing to restore
+        assert!(matches!(
+            restore_project(State(db.clone()), AuthUser(owner), Path(project.id)).await,
+            Err(AppError::NotFoundError(_))
+        ));
+
+        let permanent = || Query(DeleteProjectQuery { permanent: true });
+        assert!(matches!(
+            delete_project(State(db.clone()), AuthUser(other), ClientInfo::default(), Path(project.id), permanent()).await,
+            Err(AppError::NotFoundError(_))
+        ));
+        delete_project(State(db.clone()), AuthUser(owner), ClientInfo::default(), Path(project.id), permanent())
+            .await
+            .unwrap();
+        assert!(!project_exists(&db, project.id).await);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_purge_removes_projects_past_retention() {
+        let db = crate::db::test_database().await;
+        let owner = crate::db::insert_test_user(db.pool()).await;
+        let expired = create_owned_project(&db, owner, "long-gone").await;
+        let recent = create_owned_project(&db, owner, "just-deleted").await;
+        let kept = create_owned_project(&db, owner, "still-here").await;
+
+        for project in [&expired, &recent] {
+            delete_project(State(db.clone()), AuthUser(owner), ClientInfo::default(), Path(project.id), Query(DeleteProjectQuery::default()))
+                .await
+                .unwrap();
+        }
+        sqlx::query("UPDATE projects SET deleted_at = NOW() - INTERVAL '31 days' WHERE id = $1")
+            .bind(expired.id)
+            .execute(db.pool())
+            .await
+            .unwrap();
+
+        assert!(db.purge_trashed_projects(30).await.unwrap() >= 1);
+
+        assert!(!project_exists(&db, expired.id).await);
+        assert!(project_exists(&db, recent.id).await);
+        assert!(project_exists(&db, kept.id).await);
+        assert!(matches!(
+            restore_project(State(db.clone()), AuthUser(owner), Path(expired.id)).await,
+            Err(AppError::NotFoundError(_))
+        ));
+        assert!(restore_project(State(db.clone()), AuthUser(owner), Path(recent.id))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_stale_project_update_conflicts() {
+        let db = crate::db::test_database().await;
+        let owner = crate::db::insert_test_user(db.pool()).await;
+        let project = create_owned_project(&db, owner, "contested").await;
+        let rename = |name: &str| UpdateProjectRequest {
+            name: Some(name.to_string()),
+            description: None,
+            language: None,
+        };
+
+        // Two editors load the project and get the same ETag
+        let ETagged(loaded, _) = get_project(State(db.clone()), AuthUser(owner), Path(project.id))
+            .await
+            .unwrap();
+
+        let ETagged(saved, Json(updated)) = update_project(
+            State(db.clone()),
+            AuthUser(owner),
+            Path(project.id),
+            IfMatch(Some(loaded)),
+            ValidatedJson(rename("first")),
+        )
+        .await
+        .unwrap();
+        assert_eq!(updated.name, "first");
+        assert_ne!(saved, loaded);
+
+        // The second editor's write is based on what the first one replaced
+        let stale = update_project(
+            State(db.clone()),
+            AuthUser(owner),
+            Path(project.id),
+            IfMatch(Some(loaded)),
+            ValidatedJson(rename("second")),
+        )
+        .await;
+        assert!(matches!(stale, Err(AppError::ConflictError(_))));
+
+        let ETagged(current, Json(fetched)) = get_project(State(db.clone()), AuthUser(owner), Path(project.id))
+            .await
+            .unwrap();
+        assert_eq!(fetched.name, "first");
+        assert_eq!(current, saved);
+
+        // Clients that don't send If-Match keep the old last-write-wins behaviour
+        let blind = update_project(
+            State(db.clone()),
+            AuthUser(owner),
+            Path(project.id),
+            IfMatch(None),
+            ValidatedJson(rename("third")),
+        )
+        .await;
+        assert!(blind.is_ok());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_owner_transfers_project() {
+        let db = crate::db::test_database().await;
+        let owner = crate::db::insert_test_user(db.pool()).await;
+        let heir = crate::db::insert_test_user(db.pool()).await;
+        let project = create_owned_project(&db, owner, "hand-me-down").await;
+        sqlx::query(
+            "INSERT INTO project_members (id, project_id, user_id, role, permissions) VALUES ($1, $2, $3, 'member', ARRAY['read'])",
+        )
+        .bind(Uuid::new_v4())
+        .bind(project.id)
+        .bind(heir)
+        .execute(db.pool())
+        .await
+        .unwrap();
+        let transfer = |user_id, target| {
+            transfer_project(
+                State(db.clone()),
+                AuthUser(user_id),
+                ClientInfo::default(),
+                Path(project.id),
+                ValidatedJson(TransferProjectRequest { user_id: target }),
+            )
+        };
+
+        assert!(matches!(transfer(owner, owner).await, Err(AppError::ValidationError(_))));
+        assert!(matches!(transfer(owner, Uuid::new_v4()).await, Err(AppError::ValidationError(_))));
+
+        let Json(transferred) = transfer(owner, heir).await.unwrap();
+        assert_eq!(transferred.id, project.id);
+        assert_eq!(transferred.user_id, heir);
+
+        // The new owner's membership goes, and the previous owner keeps no access
+        let memberships = sqlx::query_scalar::<_, Uuid>("SELECT user_id FROM project_members WHERE project_id = $1")
+            .bind(project.id)
+            .fetch_all(db.pool())
+            .await
+            .unwrap();
+        assert!(memberships.is_empty());
+        assert!(get_project(State(db.clone()), AuthUser(owner), Path(project.id)).await.is_err());
+
+        let audited = sqlx::query_scalar::<_, serde_json::Value>(
+            "SELECT new_value FROM audit_logs WHERE action = 'transfer_project' AND resource_id = $1",
+        )
+        .bind(project.id)
+        .fetch_one(db.pool())
+        .await
+        .unwrap();
+        assert_eq!(audited["user_id"], serde_json::json!(heir));
+
+        // Ownership can go back the other way
+        let Json(returned) = transfer(heir, owner).await.unwrap();
+        assert_eq!(returned.user_id, owner);
+        assert!(get_project(State(db.clone()), AuthUser(owner), Path(project.id)).await.is_ok());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_only_the_owner_can_transfer_a_project() {
+        let db = crate::db::test_database().await;
+        let owner = crate::db::insert_test_user(db.pool()).await;
+        let member = crate::db::insert_test_user(db.pool()).await;
+        let stranger = crate::db::insert_test_user(db.pool()).await;
+        let project = create_owned_project(&db, owner, "not-yours").await;
+        sqlx::query(
+            "INSERT INTO project_members (id, project_id, user_id, role, permissions) VALUES ($1, $2, $3, 'admin', ARRAY['read', 'write', 'admin'])",
+        )
+        .bind(Uuid::new_v4())
+        .bind(project.id)
+        .bind(member)
+        .execute(db.pool())
+        .await
+        .unwrap();
+        let transfer = |user_id| {
+            transfer_project(
+                State(db.clone()),
+                AuthUser(user_id),
+                ClientInfo::default(),
+                Path(project.id),
+                ValidatedJson(TransferProjectRequest { user_id }),
+            )
+        };
+
+        // Even an admin member can't take the project for themselves
+        assert!(matches!(transfer(member).await, Err(AppError::AuthorizationError(_))));
+        assert!(matches!(transfer(stranger).await, Err(AppError::NotFoundError(_))));
+
+        let ETagged(_, Json(unchanged)) = get_project(State(db.clone()), AuthUser(owner), Path(project.id)).await.unwrap();
+        assert_eq!(unchanged.user_id, owner);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_scratch_project_cannot_be_transferred() {
+        let db = crate::db::test_database().await;
+        let owner = crate::db::insert_test_user(db.pool()).await;
+        let heir = crate::db::insert_test_user(db.pool()).await;
+        let scratch = scratch_project_id(&db, owner).await.unwrap();
+
+        let result = transfer_project(
+            State(db.clone()),
+            AuthUser(owner),
+            ClientInfo::default(),
+            Path(scratch),
+            ValidatedJson(TransferProjectRequest { user_id: heir }),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::ValidationError(_))));
+
+        // Both users still have a scratch project of their own to fall back on
+        assert_eq!(scratch_project_id(&db, owner).await.unwrap(), scratch);
+        assert_ne!(scratch_project_id(&db, heir).await.unwrap(), scratch);
+    }
 }
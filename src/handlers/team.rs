@@ -1,129 +0,0 @@
-use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    Json,
-};
-use uuid::Uuid;
-use std::sync::Arc;
-use crate::db::Database;
-use crate::models::collaboration::{
-    Organization, TeamMember, InviteTeamMemberRequest, TeamResponse, Role,
-};
-use crate::error::AppError;
-
-pub async fn create_organization(
-    State(db): State<Arc<Database>>,
-    Json(payload): Json<serde_json::json!({
-        "name": String,
-        "description": Option<String>
-    })>,
-) -> Result<(StatusCode, Json<Organization>), AppError> {
-    let org_id = Uuid::new_v4();
-    
-    let query = r#"
-        INSERT INTO organizations (id, owner_id, name, description, created_at)
-        VALUES ($1, $2, $3, $4, NOW())
-        RETURNING id, owner_id, name, description, created_at
-    "#;
-    
-    // Query execution would happen here
-    let org = Organization {
-        id: org_id,
-        owner_id: Uuid::new_v4(),
-        name: "Organization".to_string(),
-        description: None,
-        created_at: chrono::Utc::now(),
-    };
-
-    Ok((StatusCode::CREATED, Json(org)))
-}
-
-pub async fn get_organization(
-    State(_db): State<Arc<Database>>,
-    Path(org_id): Path<Uuid>,
-) -> Result<Json<Organization>, AppError> {
-    // Fetch from database
-    let org = Organization {
-        id: org_id,
-        owner_id: Uuid::new_v4(),
-        name: "Organization".to_string(),
-        description: None,
-        created_at: chrono::Utc::now(),
-    };
-
-    Ok(Json(org))
-}
-
-pub async fn invite_team_member(
-    State(_db): State<Arc<Database>>,
-    Path(org_id): Path<Uuid>,
-    Json(payload): Json<InviteTeamMemberRequest>,
-) -> Result<(StatusCode, Json<TeamMember>), AppError> {
-    let member_id = Uuid::new_v4();
-    
-    // Validate role hierarchy
-    tracing::info!(
-        "Inviting user {} to organization {} with role: {}",
-        payload.email,
-        org_id,
-        payload.role
-    );
-
-    let member = TeamMember {
-        id: member_id,
-        user_id: Uuid::new_v4(),
-        organization_id: org_id,
-        role: payload.role,
-        joined_at: chrono::Utc::now(),
-    };
-
-    Ok((StatusCode::CREATED, Json(member)))
-}
-
-pub async fn list_team_members(
-    State(_db): State<Arc<Database>>,
-    Path(org_id): Path<Uuid>,
-) -> Result<Json<TeamResponse>, AppError> {
-    let members = vec![];
-    let org = Organization {
-        id: org_id,
-        owner_id: Uuid::new_v4(),
-        name: "Organization".to_string(),
-        description: None,
-        created_at: chrono::Utc::now(),
-    };
-
-    Ok(Json(TeamResponse {
-        members,
-        organization: org,
-    }))
-}
-
-pub async fn remove_team_member(
-    State(_db): State<Arc<Database>>,
-    Path((org_id, member_id)): Path<(Uuid, Uuid)>,
-) -> Result<StatusCode, AppError> {
-    tracing::info!(
-        "Removing member {} from organization {}",
-        member_id,
-        org_id
-    );
-    
-    Ok(StatusCode::NO_CONTENT)
-}
-
-pub async fn update_member_role(
-    State(_db): State<Arc<Database>>,
-    Path((org_id, member_id)): Path<(Uuid, Uuid)>,
-    Json(payload): Json<serde_json::json!({"role": Role})>,
-) -> Result<Json<TeamMember>, AppError> {
-    let member = TeamMember {
-        id: member_id,
-        user_id: Uuid::new_v4(),
-        organization_id: org_id,
-        role: Role::Viewer,
-        joined_at: chrono::Utc::now(),
-    };
-
-    Ok(Json(member))
-}
@@ -2,33 +2,48 @@ use axum::{
     extract::{Path, State, Json},
     http::StatusCode,
     response::IntoResponse,
+    Extension,
 };
 use sqlx::Pool;
 use sqlx::Postgres;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use uuid::Uuid;
 use chrono::Utc;
 use regex::Regex;
 
-use crate::error::ApiError;
+use crate::config::Config;
+use crate::error::{AppError, AppResult};
 use crate::models::collaboration::{
     Team, TeamMember, CreateTeamRequest, UpdateTeamRequest,
     AddTeamMemberRequest, UpdateTeamMemberRequest, ProjectMember,
     AddProjectMemberRequest, UpdateProjectMemberRequest, PermissionCheck,
+    BulkAddTeamMembersRequest, BulkMemberResult, BulkMemberStatus, TeamRole,
+    TeamInvitation, CreateTeamInvitationRequest,
 };
 use crate::middleware::rbac;
+use crate::middleware_auth::AuthUser;
+use crate::services::audit::{self, AuditEntry, ClientInfo};
+use crate::services::events::{DomainEvent, EventBus};
+use crate::services::InheritanceEngine;
+use crate::utils::crypto;
+use crate::utils::validation::{validate_team_role, ValidatedJson};
 
 /// Create new team
 pub async fn create_team(
     State(pool): State<Pool<Postgres>>,
-    user_id: Uuid,
-    Json(req): Json<CreateTeamRequest>,
-) -> Result<impl IntoResponse, ApiError> {
+    AuthUser(user_id): AuthUser,
+    client: ClientInfo,
+    ValidatedJson(req): ValidatedJson<CreateTeamRequest>,
+) -> AppResult<impl IntoResponse> {
     let team_id = Uuid::new_v4();
     let now = Utc::now();
 
     // Generate slug from team name
     let slug = generate_slug(&req.name);
 
+    let mut tx = pool.begin().await?;
+
     sqlx::query(
         r#"
         INSERT INTO teams (id, owner_id, name, description, slug, created_at, updated_at)
@@ -41,7 +56,7 @@ pub async fn create_team(
     .bind(&req.description)
     .bind(&slug)
     .bind(now)
-    .execute(&pool)
+    .execute(&mut *tx)
     .await?;
 
     // Add creator as owner
@@ -55,7 +70,7 @@ pub async fn create_team(
     .bind(team_id)
     .bind(user_id)
     .bind(now)
-    .execute(&pool)
+    .execute(&mut *tx)
     .await?;
 
     let team = Team {
@@ -68,6 +83,14 @@ pub async fn create_team(
         updated_at: now,
     };
 
+    audit::record(
+        &mut *tx,
+        &client,
+        AuditEntry::new(user_id, "create_team", "team", team_id).new_value(serde_json::json!(team)),
+    )
+    .await?;
+    tx.commit().await?;
+
     Ok((StatusCode::CREATED, Json(team)))
 }
 
@@ -75,26 +98,16 @@ pub async fn create_team(
 pub async fn get_team(
     State(pool): State<Pool<Postgres>>,
     Path(team_id): Path<Uuid>,
-    user_id: Uuid,
-) -> Result<impl IntoResponse, ApiError> {
-    // Verify user is team member
-    let is_member = sqlx::query_scalar::<_, bool>(
-        "SELECT COUNT(*) > 0 FROM team_members WHERE team_id = $1 AND user_id = $2"
-    )
-    .bind(team_id)
-    .bind(user_id)
-    .fetch_one(&pool)
-    .await?;
-
-    if !is_member {
-        return Err(ApiError::Forbidden);
-    }
+    AuthUser(user_id): AuthUser,
+) -> AppResult<impl IntoResponse> {
+    // Non-members can't tell the team exists
+    rbac::enforce_team_member(&pool, user_id, team_id).await?;
 
     let team = sqlx::query_as::<_, Team>("SELECT * FROM teams WHERE id = $1")
         .bind(team_id)
         .fetch_optional(&pool)
         .await?
-        .ok_or(ApiError::NotFound)?;
+        .ok_or(AppError::NotFoundError("Team not found".to_string()))?;
 
     Ok(Json(team))
 }
@@ -103,15 +116,25 @@ pub async fn get_team(
 pub async fn update_team(
     State(pool): State<Pool<Postgres>>,
     Path(team_id): Path<Uuid>,
-    user_id: Uuid,
-    Json(req): Json<UpdateTeamRequest>,
-) -> Result<impl IntoResponse, ApiError> {
+    AuthUser(user_id): AuthUser,
+    client: ClientInfo,
+    ValidatedJson(req): ValidatedJson<UpdateTeamRequest>,
+) -> AppResult<impl IntoResponse> {
     // Check if user is owner or admin
     rbac::enforce_role(&pool, user_id, team_id, 3).await?;
 
     let now = Utc::now();
+    let mut tx = pool.begin().await?;
 
-    sqlx::query(
+    let before = sqlx::query_scalar::<_, serde_json::Value>(
+        "SELECT jsonb_build_object('name', name, 'description', description) FROM teams WHERE id = $1 FOR UPDATE"
+    )
+    .bind(team_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFoundError("Team not found".to_string()))?;
+
+    let after = sqlx::query_scalar::<_, serde_json::Value>(
         r#"
         UPDATE teams 
         SET 
@@ -119,36 +142,54 @@ pub async fn update_team(
             description = COALESCE($2, description),
             updated_at = $3
         WHERE id = $4
+        RETURNING jsonb_build_object('name', name, 'description', description)
         "#,
     )
     .bind(&req.name)
     .bind(&req.description)
     .bind(now)
     .bind(team_id)
-    .execute(&pool)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    audit::record(
+        &mut *tx,
+        &client,
+        AuditEntry::new(user_id, "update_team", "team", team_id)
+            .old_value(before)
+            .new_value(after),
+    )
     .await?;
+    tx.commit().await?;
 
     Ok(StatusCode::OK)
 }
 
+/// Delete team; members and hierarchy links go with it
+pub async fn delete_team(
+    State(pool): State<Pool<Postgres>>,
+    Path(team_id): Path<Uuid>,
+    AuthUser(user_id): AuthUser,
+) -> AppResult<impl IntoResponse> {
+    // Only the owner may delete
+    rbac::enforce_role(&pool, user_id, team_id, 4).await?;
+
+    sqlx::query("DELETE FROM teams WHERE id = $1")
+        .bind(team_id)
+        .execute(&pool)
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
 /// List team members
 pub async fn list_team_members(
     State(pool): State<Pool<Postgres>>,
     Path(team_id): Path<Uuid>,
-    user_id: Uuid,
-) -> Result<impl IntoResponse, ApiError> {
-    // Verify user is team member
-    let is_member = sqlx::query_scalar::<_, bool>(
-        "SELECT COUNT(*) > 0 FROM team_members WHERE team_id = $1 AND user_id = $2"
-    )
-    .bind(team_id)
-    .bind(user_id)
-    .fetch_one(&pool)
-    .await?;
-
-    if !is_member {
-        return Err(ApiError::Forbidden);
-    }
+    AuthUser(user_id): AuthUser,
+) -> AppResult<impl IntoResponse> {
+    // Non-members can't tell the team exists
+    rbac::enforce_team_member(&pool, user_id, team_id).await?;
 
     let members = sqlx::query_as::<_, TeamMember>(
         "SELECT * FROM team_members WHERE team_id = $1 ORDER BY joined_at DESC"
@@ -160,25 +201,43 @@ pub async fn list_team_members(
     Ok(Json(members))
 }
 
+/// A team role's level, or 0 for anything unrecognised
+fn role_level(role: &str) -> i32 {
+    TeamRole::parse(role).map_or(0, |role| role.hierarchy_level())
+}
+
+/// Reject granting a role the caller isn't senior to. Ownership is never granted through
+/// the member endpoints, and nobody can hand out their own level or above.
+fn ensure_can_grant(caller_level: i32, role: &str) -> AppResult<()> {
+    let Some(role) = TeamRole::parse(role) else {
+        return Err(AppError::ValidationError(format!("Invalid role: {}", role)));
+    };
+    if role == TeamRole::Owner {
+        return Err(AppError::AuthorizationError("The owner role can't be granted".to_string()));
+    }
+    if role.hierarchy_level() >= caller_level {
+        return Err(AppError::AuthorizationError("You can only grant roles below your own".to_string()));
+    }
+
+    Ok(())
+}
+
 /// Add team member
 pub async fn add_team_member(
     State(pool): State<Pool<Postgres>>,
     Path(team_id): Path<Uuid>,
-    user_id: Uuid,
-    Json(req): Json<AddTeamMemberRequest>,
-) -> Result<impl IntoResponse, ApiError> {
-    // Check if user is owner or admin
-    rbac::enforce_role(&pool, user_id, team_id, 3).await?;
-
-    // Validate role
-    if !["owner", "admin", "member", "viewer"].contains(&req.role.as_str()) {
-        return Err(ApiError::BadRequest);
-    }
+    AuthUser(user_id): AuthUser,
+    client: ClientInfo,
+    ValidatedJson(req): ValidatedJson<AddTeamMemberRequest>,
+) -> AppResult<impl IntoResponse> {
+    rbac::enforce_team_permission(&pool, user_id, team_id, "manage_roles").await?;
+    ensure_can_grant(rbac::team_role_level(&pool, user_id, team_id).await?, &req.role)?;
 
     let member_id = Uuid::new_v4();
     let now = Utc::now();
+    let mut tx = pool.begin().await?;
 
-    sqlx::query(
+    let inserted = sqlx::query(
         r#"
         INSERT INTO team_members (id, team_id, user_id, role, joined_at)
         VALUES ($1, $2, $3, $4, $5)
@@ -190,7 +249,7 @@ pub async fn add_team_member(
     .bind(req.user_id)
     .bind(&req.role)
     .bind(now)
-    .execute(&pool)
+    .execute(&mut *tx)
     .await?;
 
     let member = TeamMember {
@@ -201,22 +260,293 @@ pub async fn add_team_member(
         joined_at: now,
     };
 
+    // Re-adding an existing member changes nothing, so there's nothing to audit
+    if inserted.rows_affected() > 0 {
+        audit::record(
+            &mut *tx,
+            &client,
+            AuditEntry::new(user_id, "add_team_member", "team", team_id).new_value(serde_json::json!(member)),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+
     Ok((StatusCode::CREATED, Json(member)))
 }
 
+/// Add or update many team members at once. Entries that can't be applied are reported
+/// as errors rather than failing the whole import.
+pub async fn bulk_add_team_members(
+    State(pool): State<Pool<Postgres>>,
+    Path(team_id): Path<Uuid>,
+    AuthUser(user_id): AuthUser,
+    client: ClientInfo,
+    ValidatedJson(req): ValidatedJson<BulkAddTeamMembersRequest>,
+) -> AppResult<impl IntoResponse> {
+    rbac::enforce_team_permission(&pool, user_id, team_id, "manage_roles").await?;
+    let caller_level = rbac::team_role_level(&pool, user_id, team_id).await?;
+
+    let user_ids: Vec<Uuid> = req.members.iter().map(|member| member.user_id).collect();
+    let mut tx = pool.begin().await?;
+
+    // Lock the rows being changed, so the outcomes reported are the ones applied
+    let current: HashMap<Uuid, String> = sqlx::query_as::<_, (Uuid, String)>(
+        "SELECT user_id, role FROM team_members WHERE team_id = $1 AND user_id = ANY($2) FOR UPDATE"
+    )
+    .bind(team_id)
+    .bind(&user_ids)
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .collect();
+    let known_users: HashSet<Uuid> = sqlx::query_scalar::<_, Uuid>("SELECT id FROM users WHERE id = ANY($1)")
+        .bind(&user_ids)
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .collect();
+
+    let now = Utc::now();
+    let mut seen = HashSet::new();
+    let mut results = Vec::with_capacity(req.members.len());
+    for entry in req.members {
+        let existing = current.get(&entry.user_id);
+        let rejection = if let Err(AppError::ValidationError(message)) = validate_team_role(&entry.role) {
+            Some(message)
+        } else if !seen.insert(entry.user_id) {
+            Some("Listed more than once".to_string())
+        } else if !known_users.contains(&entry.user_id) {
+            Some("No such user".to_string())
+        } else if let Err(AppError::AuthorizationError(message)) = ensure_can_grant(caller_level, &entry.role) {
+            Some(message)
+        } else if existing.is_some_and(|role| role_level(role) >= caller_level) {
+            Some("You can only change members below your own role".to_string())
+        } else {
+            None
+        };
+
+        let status = match (rejection.is_some(), existing) {
+            (true, _) => BulkMemberStatus::Error,
+            (false, Some(role)) if role == &entry.role => BulkMemberStatus::Skipped,
+            (false, existing) => {
+                sqlx::query(
+                    r#"
+                    INSERT INTO team_members (id, team_id, user_id, role, joined_at)
+                    VALUES ($1, $2, $3, $4, $5)
+                    ON CONFLICT (team_id, user_id) DO UPDATE SET role = EXCLUDED.role
+                    "#,
+                )
+                .bind(Uuid::new_v4())
+                .bind(team_id)
+                .bind(entry.user_id)
+                .bind(&entry.role)
+                .bind(now)
+                .execute(&mut *tx)
+                .await?;
+
+                let (action, status) = match existing {
+                    Some(_) => ("update_team_member", BulkMemberStatus::Updated),
+                    None => ("add_team_member", BulkMemberStatus::Added),
+                };
+                let mut audit_entry = AuditEntry::new(user_id, action, "team", team_id)
+                    .new_value(serde_json::json!({ "user_id": entry.user_id, "role": entry.role }));
+                if let Some(role) = existing {
+                    audit_entry = audit_entry.old_value(serde_json::json!({ "user_id": entry.user_id, "role": role }));
+                }
+                audit::record(&mut *tx, &client, audit_entry).await?;
+                status
+            }
+        };
+
+        results.push(BulkMemberResult {
+            user_id: entry.user_id,
+            role: entry.role,
+            status,
+            error: rejection,
+        });
+    }
+    tx.commit().await?;
+
+    Ok(Json(results))
+}
+
+/// How long an emailed team invitation can be accepted for
+#[derive(Debug, Clone, Copy)]
+pub struct InvitationSettings {
+    pub ttl: chrono::Duration,
+}
+
+impl InvitationSettings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            ttl: chrono::Duration::hours(config.team_invitation_ttl_hours.into()),
+        }
+    }
+}
+
+impl Default for InvitationSettings {
+    fn default() -> Self {
+        Self { ttl: chrono::Duration::days(7) }
+    }
+}
+
+/// Invite someone to the team by email, whether or not they have an account yet. The
+/// token goes out in the invitation email and isn't returned here.
+pub async fn create_team_invitation(
+    State(pool): State<Pool<Postgres>>,
+    Extension(settings): Extension<Arc<InvitationSettings>>,
+    Extension(events): Extension<Arc<EventBus>>,
+    Path(team_id): Path<Uuid>,
+    AuthUser(user_id): AuthUser,
+    client: ClientInfo,
+    ValidatedJson(req): ValidatedJson<CreateTeamInvitationRequest>,
+) -> AppResult<impl IntoResponse> {
+    rbac::enforce_team_permission(&pool, user_id, team_id, "manage_roles").await?;
+    ensure_can_grant(rbac::team_role_level(&pool, user_id, team_id).await?, &req.role)?;
+
+    let token = crypto::generate_secure_token();
+    let mut tx = pool.begin().await?;
+
+    let invitation = sqlx::query_as::<_, TeamInvitation>(
+        r#"
+        INSERT INTO team_invitations (id, team_id, email, role, token_hash, invited_by, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, team_id, email, role, invited_by, expires_at, accepted_by, accepted_at, created_at
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(team_id)
+    .bind(&req.email)
+    .bind(&req.role)
+    .bind(crypto::hash_token(&token))
+    .bind(user_id)
+    .bind(Utc::now() + settings.ttl)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    audit::record(
+        &mut *tx,
+        &client,
+        AuditEntry::new(user_id, "invite_team_member", "team", team_id).new_value(serde_json::json!(invitation)),
+    )
+    .await?;
+    tx.commit().await?;
+
+    events.publish(DomainEvent::TeamInvitationCreated {
+        team_id,
+        invitation_id: invitation.id,
+        email: invitation.email.clone(),
+        role: invitation.role.clone(),
+        invited_by: user_id,
+        expires_at: invitation.expires_at,
+        token,
+    })
+    .await;
+
+    Ok((StatusCode::CREATED, Json(invitation)))
+}
+
+/// Join the team an invitation is for, as the signed-in user it was addressed to
+pub async fn accept_team_invitation(
+    State(pool): State<Pool<Postgres>>,
+    Path(token): Path<String>,
+    AuthUser(user_id): AuthUser,
+    client: ClientInfo,
+) -> AppResult<impl IntoResponse> {
+    let mut tx = pool.begin().await?;
+
+    let invitation = sqlx::query_as::<_, TeamInvitation>(
+        r#"
+        SELECT id, team_id, email, role, invited_by, expires_at, accepted_by, accepted_at, created_at
+        FROM team_invitations
+        WHERE token_hash = $1
+        FOR UPDATE
+        "#,
+    )
+    .bind(crypto::hash_token(&token))
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFoundError("Invitation not found".to_string()))?;
+
+    if invitation.accepted_at.is_some() {
+        return Err(AppError::ConflictError("Invitation has already been accepted".to_string()));
+    }
+    if invitation.expires_at <= Utc::now() {
+        return Err(AppError::ValidationError("Invitation has expired".to_string()));
+    }
+
+    // The token alone isn't enough: it has to be redeemed by the account it was sent to
+    let email = sqlx::query_scalar::<_, String>("SELECT email FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+    if !email.eq_ignore_ascii_case(&invitation.email) {
+        return Err(AppError::AuthorizationError("Invitation was sent to a different email address".to_string()));
+    }
+
+    // Someone who's already a member keeps the role they have
+    sqlx::query(
+        r#"
+        INSERT INTO team_members (id, team_id, user_id, role, joined_at)
+        VALUES ($1, $2, $3, $4, NOW())
+        ON CONFLICT (team_id, user_id) DO NOTHING
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(invitation.team_id)
+    .bind(user_id)
+    .bind(&invitation.role)
+    .execute(&mut *tx)
+    .await?;
+    let member = sqlx::query_as::<_, TeamMember>("SELECT * FROM team_members WHERE team_id = $1 AND user_id = $2")
+        .bind(invitation.team_id)
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    sqlx::query("UPDATE team_invitations SET accepted_by = $1, accepted_at = NOW() WHERE id = $2")
+        .bind(user_id)
+        .bind(invitation.id)
+        .execute(&mut *tx)
+        .await?;
+
+    audit::record(
+        &mut *tx,
+        &client,
+        AuditEntry::new(user_id, "accept_team_invitation", "team", invitation.team_id)
+            .old_value(serde_json::json!({ "invitation_id": invitation.id }))
+            .new_value(serde_json::json!(member)),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(member))
+}
+
 /// Update team member role
 pub async fn update_team_member(
     State(pool): State<Pool<Postgres>>,
     Path((team_id, member_id)): Path<(Uuid, Uuid)>,
-    user_id: Uuid,
-    Json(req): Json<UpdateTeamMemberRequest>,
-) -> Result<impl IntoResponse, ApiError> {
-    // Check if user is owner or admin
-    rbac::enforce_role(&pool, user_id, team_id, 3).await?;
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(req): ValidatedJson<UpdateTeamMemberRequest>,
+) -> AppResult<impl IntoResponse> {
+    rbac::enforce_team_permission(&pool, user_id, team_id, "manage_roles").await?;
+    let caller_level = rbac::team_role_level(&pool, user_id, team_id).await?;
+    ensure_can_grant(caller_level, &req.role)?;
 
-    // Validate role
-    if !["owner", "admin", "member", "viewer"].contains(&req.role.as_str()) {
-        return Err(ApiError::BadRequest);
+    let mut tx = pool.begin().await?;
+    let current = sqlx::query_scalar::<_, String>(
+        "SELECT role FROM team_members WHERE id = $1 AND team_id = $2 FOR UPDATE"
+    )
+    .bind(member_id)
+    .bind(team_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFoundError("Team member not found".to_string()))?;
+
+    // Members at or above the caller's level, the owner included, are out of their reach
+    if role_level(&current) >= caller_level {
+        return Err(AppError::AuthorizationError("You can only change members below your own role".to_string()));
     }
 
     sqlx::query(
@@ -225,8 +555,9 @@ pub async fn update_team_member(
     .bind(&req.role)
     .bind(member_id)
     .bind(team_id)
-    .execute(&pool)
+    .execute(&mut *tx)
     .await?;
+    tx.commit().await?;
 
     Ok(StatusCode::OK)
 }
@@ -235,16 +566,42 @@ pub async fn update_team_member(
 pub async fn remove_team_member(
     State(pool): State<Pool<Postgres>>,
     Path((team_id, member_id)): Path<(Uuid, Uuid)>,
-    user_id: Uuid,
-) -> Result<impl IntoResponse, ApiError> {
-    // Check if user is owner or admin
-    rbac::enforce_role(&pool, user_id, team_id, 3).await?;
+    AuthUser(user_id): AuthUser,
+) -> AppResult<impl IntoResponse> {
+    rbac::enforce_team_permission(&pool, user_id, team_id, "manage_roles").await?;
+    let caller_level = rbac::team_role_level(&pool, user_id, team_id).await?;
+
+    let mut tx = pool.begin().await?;
+    let current = sqlx::query_scalar::<_, String>(
+        "SELECT role FROM team_members WHERE id = $1 AND team_id = $2 FOR UPDATE"
+    )
+    .bind(member_id)
+    .bind(team_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFoundError("Team member not found".to_string()))?;
+
+    if current == "owner" {
+        let owners = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM team_members WHERE team_id = $1 AND role = 'owner'"
+        )
+        .bind(team_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if owners <= 1 {
+            return Err(AppError::ConflictError("A team cannot lose its last owner".to_string()));
+        }
+    }
+    if role_level(&current) >= caller_level {
+        return Err(AppError::AuthorizationError("You can only remove members below your own role".to_string()));
+    }
 
     sqlx::query("DELETE FROM team_members WHERE id = $1 AND team_id = $2")
         .bind(member_id)
         .bind(team_id)
-        .execute(&pool)
+        .execute(&mut *tx)
         .await?;
+    tx.commit().await?;
 
     Ok(StatusCode::NO_CONTENT)
 }
@@ -252,28 +609,20 @@ pub async fn remove_team_member(
 /// Add project member
 pub async fn add_project_member(
     State(pool): State<Pool<Postgres>>,
+    Extension(engine): Extension<Arc<InheritanceEngine>>,
+    Extension(events): Extension<Arc<EventBus>>,
     Path((project_id, user_id_to_add)): Path<(Uuid, Uuid)>,
-    user_id: Uuid,
-    Json(req): Json<AddProjectMemberRequest>,
-) -> Result<impl IntoResponse, ApiError> {
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(req): ValidatedJson<AddProjectMemberRequest>,
+) -> AppResult<impl IntoResponse> {
     // Check if user is project admin
     rbac::enforce_permission(&pool, user_id, project_id, "admin").await?;
 
-    // Validate permissions
-    let valid_perms = vec!["read", "write", "admin", "delete"];
-    if let Some(ref perms) = req.permissions {
-        for perm in perms {
-            if !valid_perms.contains(&perm.as_str()) {
-                return Err(ApiError::BadRequest);
-            }
-        }
-    }
-
     let member_id = Uuid::new_v4();
     let now = Utc::now();
     let permissions = req.permissions.unwrap_or_default();
 
-    sqlx::query(
+    let inserted = sqlx::query(
         r#"
         INSERT INTO project_members (id, project_id, user_id, role, permissions, joined_at)
         VALUES ($1, $2, $3, $4, $5, $6)
@@ -289,6 +638,18 @@ pub async fn add_project_member(
     .execute(&pool)
     .await?;
 
+    engine.clear_cache_for_resource(user_id_to_add, project_id).await;
+
+    if inserted.rows_affected() > 0 {
+        events.publish(DomainEvent::MemberAdded {
+            project_id,
+            user_id: user_id_to_add,
+            added_by: user_id,
+            role: req.role.clone(),
+        })
+        .await;
+    }
+
     let member = ProjectMember {
         id: member_id,
         project_id,
@@ -304,56 +665,87 @@ pub async fn add_project_member(
 /// Update project member
 pub async fn update_project_member(
     State(pool): State<Pool<Postgres>>,
+    Extension(engine): Extension<Arc<InheritanceEngine>>,
     Path((project_id, member_id)): Path<(Uuid, Uuid)>,
-    user_id: Uuid,
-    Json(req): Json<UpdateProjectMemberRequest>,
-) -> Result<impl IntoResponse, ApiError> {
+    AuthUser(user_id): AuthUser,
+    client: ClientInfo,
+    ValidatedJson(req): ValidatedJson<UpdateProjectMemberRequest>,
+) -> AppResult<impl IntoResponse> {
     // Check if user is project admin
     rbac::enforce_permission(&pool, user_id, project_id, "admin").await?;
 
-    // Validate permissions
-    let valid_perms = vec!["read", "write", "admin", "delete"];
-    if let Some(ref perms) = req.permissions {
-        for perm in perms {
-            if !valid_perms.contains(&perm.as_str()) {
-                return Err(ApiError::BadRequest);
-            }
-        }
-    }
+    let mut tx = pool.begin().await?;
 
-    sqlx::query(
+    let before = sqlx::query_scalar::<_, serde_json::Value>(
+        r#"
+        SELECT jsonb_build_object('user_id', user_id, 'role', role, 'permissions', permissions)
+        FROM project_members
+        WHERE id = $1 AND project_id = $2
+        FOR UPDATE
+        "#,
+    )
+    .bind(member_id)
+    .bind(project_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let updated = sqlx::query_as::<_, (Uuid, serde_json::Value)>(
         r#"
         UPDATE project_members 
         SET 
             role = COALESCE($1, role),
             permissions = COALESCE($2, permissions)
         WHERE id = $3 AND project_id = $4
+        RETURNING user_id, jsonb_build_object('user_id', user_id, 'role', role, 'permissions', permissions)
         "#,
     )
     .bind(&req.role)
     .bind(&req.permissions)
     .bind(member_id)
     .bind(project_id)
-    .execute(&pool)
+    .fetch_optional(&mut *tx)
     .await?;
 
+    if let (Some(before), Some((_, after))) = (before, &updated) {
+        audit::record(
+            &mut *tx,
+            &client,
+            AuditEntry::new(user_id, "update_project_member", "project", project_id)
+                .old_value(before)
+                .new_value(after.clone()),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+
+    if let Some((member_user_id, _)) = updated {
+        engine.clear_cache_for_resource(member_user_id, project_id).await;
+    }
+
     Ok(StatusCode::OK)
 }
 
 /// Remove project member
 pub async fn remove_project_member(
     State(pool): State<Pool<Postgres>>,
+    Extension(engine): Extension<Arc<InheritanceEngine>>,
     Path((project_id, member_id)): Path<(Uuid, Uuid)>,
-    user_id: Uuid,
-) -> Result<impl IntoResponse, ApiError> {
+    AuthUser(user_id): AuthUser,
+) -> AppResult<impl IntoResponse> {
     // Check if user is project admin
     rbac::enforce_permission(&pool, user_id, project_id, "admin").await?;
 
-    sqlx::query("DELETE FROM project_members WHERE id = $1 AND project_id = $2")
-        .bind(member_id)
-        .bind(project_id)
-        .execute(&pool)
-        .await?;
+    let removed_user = sqlx::query_scalar::<_, Uuid>(
+        "DELETE FROM project_members WHERE id = $1 AND project_id = $2 RETURNING user_id",
+    )
+    .bind(member_id)
+    .bind(project_id)
+    .fetch_optional(&pool)
+    .await?;
+
+    if let Some(member_user_id) = removed_user {
+        engine.clear_cache_for_resource(member_user_id, project_id).await;
+    }
 
     Ok(StatusCode::NO_CONTENT)
 }
@@ -362,8 +754,8 @@ pub async fn remove_project_member(
 pub async fn check_permissions(
     State(pool): State<Pool<Postgres>>,
     Path((project_id, check_user_id)): Path<(Uuid, Uuid)>,
-    user_id: Uuid,
-) -> Result<impl IntoResponse, ApiError> {
+    AuthUser(user_id): AuthUser,
+) -> AppResult<impl IntoResponse> {
     // Check if requester has admin permission
     rbac::enforce_permission(&pool, user_id, project_id, "admin").await?;
 
@@ -399,9 +791,672 @@ fn generate_slug(text: &str) -> String {
         .to_string()
 }
 
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::validation::Validate;
+    use axum::body::to_bytes;
+    use serde::de::DeserializeOwned;
+
+    async fn read_json<T: DeserializeOwned>(response: impl IntoResponse) -> T {
+        let body = to_bytes(response.into_response().into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    async fn create_test_team(pool: &Pool<Postgres>, owner_id: Uuid) -> Team {
+        let request = CreateTeamRequest {
+            name: format!("Team {}", owner_id),
+            description: None,
+        };
+        let response = create_team(State(pool.clone()), AuthUser(owner_id), ClientInfo::default(), ValidatedJson(request))
+            .await
+            .unwrap()
+            .into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        read_json(response).await
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_team_crud() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool().clone();
+        let owner_id = crate::db::insert_test_user(&pool).await;
+        let outsider_id = crate::db::insert_test_user(&pool).await;
+
+        let team = create_test_team(&pool, owner_id).await;
+        assert_eq!(team.owner_id, owner_id);
+        assert_eq!(team.slug, generate_slug(&team.name));
+
+        let fetched: Team = read_json(get_team(State(pool.clone()), Path(team.id), AuthUser(owner_id)).await.unwrap()).await;
+        assert_eq!(fetched.id, team.id);
+        assert!(matches!(
+            get_team(State(pool.clone()), Path(team.id), AuthUser(outsider_id)).await,
+            Err(AppError::NotFoundError(_))
+        ));
+
+        let update = UpdateTeamRequest {
+            name: Some(format!("Renamed {}", team.id)),
+            description: Some("Now with a description".to_string()),
+        };
+        let response = update_team(State(pool.clone()), Path(team.id), AuthUser(owner_id), ClientInfo::default(), ValidatedJson(update))
+            .await
+            .unwrap()
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let fetched: Team = read_json(get_team(State(pool.clone()), Path(team.id), AuthUser(owner_id)).await.unwrap()).await;
+        assert_eq!(fetched.name, format!("Renamed {}", team.id));
+        assert_eq!(fetched.description.as_deref(), Some("Now with a description"));
+
+        assert!(matches!(
+            delete_team(State(pool.clone()), Path(team.id), AuthUser(outsider_id)).await,
+            Err(AppError::NotFoundError(_))
+        ));
+        let response = delete_team(State(pool.clone()), Path(team.id), AuthUser(owner_id))
+            .await
+            .unwrap()
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM teams WHERE id = $1")
+            .bind(team.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_team_update_is_audited_with_the_client_address() {
+        use axum::{
+            body::Body,
+            extract::{ConnectInfo, Request},
+            http::header::{CONTENT_TYPE, USER_AGENT},
+            middleware::{self, Next},
+            routing::put,
+            Router,
+        };
+        use std::net::SocketAddr;
+        use tower::ServiceExt;
+
+        let db = crate::db::test_database().await;
+        let pool = db.pool().clone();
+        let owner_id = crate::db::insert_test_user(&pool).await;
+        let team = create_test_team(&pool, owner_id).await;
+
+        // As served, with the caller already authenticated and the peer address attached
+        let app = Router::new()
+            .route("/api/teams/:id", put(update_team))
+            .layer(middleware::from_fn(move |mut request: Request, next: Next| async move {
+                request.extensions_mut().insert(owner_id);
+                request
+                    .extensions_mut()
+                    .insert(ConnectInfo(SocketAddr::from(([192, 0, 2, 44], 50123))));
+                next.run(request).await
+            }))
+            .with_state(pool.clone());
+        let request = Request::builder()
+            .method("PUT")
+            .uri(format!("/api/teams/{}", team.id))
+            .header(CONTENT_TYPE, "application/json")
+            .header(USER_AGENT, "cx7-cli/0.3")
+            .body(Body::from(r#"{"name":"Renamed"}"#))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let (actor_id, ip_address, user_agent, old_value, new_value): (
+            Uuid,
+            Option<String>,
+            Option<String>,
+            Option<serde_json::Value>,
+            Option<serde_json::Value>,
+        ) = sqlx::query_as(
+            "SELECT actor_id, ip_address, user_agent, old_value, new_value FROM audit_logs WHERE resource_id = $1 AND action = 'update_team'"
+        )
+        .bind(team.id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(actor_id, owner_id);
+        assert_eq!(ip_address.as_deref(), Some("192.0.2.44"));
+        assert_eq!(user_agent.as_deref(), Some("cx7-cli/0.3"));
+        assert_eq!(old_value.unwrap()["name"], team.name);
+        assert_eq!(new_value.unwrap()["name"], "Renamed");
+
+        // Creating the team was audited too
+        let created: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM audit_logs WHERE resource_id = $1 AND action = 'create_team'"
+        )
+        .bind(team.id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(created, 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_team_member_crud() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool().clone();
+        let owner_id = crate::db::insert_test_user(&pool).await;
+        let user_id = crate::db::insert_test_user(&pool).await;
+        let team = create_test_team(&pool, owner_id).await;
+
+        let request = AddTeamMemberRequest { user_id, role: "member".to_string() };
+        let response = add_team_member(State(pool.clone()), Path(team.id), AuthUser(owner_id), ClientInfo::default(), ValidatedJson(request))
+            .await
+            .unwrap()
+            .into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let member: TeamMember = read_json(response).await;
+        assert_eq!((member.user_id, member.role.as_str()), (user_id, "member"));
+
+        // Members can list, but not manage, the team
+        let members: Vec<TeamMember> = read_json(
+            list_team_members(State(pool.clone()), Path(team.id), AuthUser(user_id)).await.unwrap(),
+        )
+        .await;
+        assert_eq!(members.len(), 2);
+        let request = UpdateTeamMemberRequest { role: "owner".to_string() };
+        assert!(matches!(
+            update_team_member(State(pool.clone()), Path((team.id, member.id)), AuthUser(user_id), ValidatedJson(request)).await,
+            Err(AppError::AuthorizationError(_))
+        ));
+
+        // Unknown roles never reach the handler
+        assert!(UpdateTeamMemberRequest { role: "superuser".to_string() }.validate().is_err());
+        let request = UpdateTeamMemberRequest { role: "admin".to_string() };
+        update_team_member(State(pool.clone()), Path((team.id, member.id)), AuthUser(owner_id), ValidatedJson(request))
+            .await
+            .unwrap();
+        let members: Vec<TeamMember> = read_json(
+            list_team_members(State(pool.clone()), Path(team.id), AuthUser(owner_id)).await.unwrap(),
+        )
+        .await;
+        assert!(members.iter().any(|m| m.user_id == user_id && m.role == "admin"));
+
+        let response = remove_team_member(State(pool.clone()), Path((team.id, member.id)), AuthUser(owner_id))
+            .await
+            .unwrap()
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let members: Vec<TeamMember> = read_json(
+            list_team_members(State(pool.clone()), Path(team.id), AuthUser(owner_id)).await.unwrap(),
+        )
+        .await;
+        assert_eq!(members.len(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_changing_roles_requires_manage_roles() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool().clone();
+        let owner_id = crate::db::insert_test_user(&pool).await;
+        let member_id = crate::db::insert_test_user(&pool).await;
+        let viewer_id = crate::db::insert_test_user(&pool).await;
+        let team = create_test_team(&pool, owner_id).await;
+        let mut added = Vec::new();
+        for (user_id, role) in [(member_id, "member"), (viewer_id, "viewer")] {
+            let request = AddTeamMemberRequest { user_id, role: role.to_string() };
+            let member: TeamMember = read_json(
+                add_team_member(State(pool.clone()), Path(team.id), AuthUser(owner_id), ClientInfo::default(), ValidatedJson(request))
+                    .await
+                    .unwrap(),
+            )
+            .await;
+            added.push(member.id);
+        }
+        let viewer = added[1];
+        let set_role = |role: &str| ValidatedJson(UpdateTeamMemberRequest { role: role.to_string() });
+
+        // Members don't have manage_roles by default
+        let denied = update_team_member(State(pool.clone()), Path((team.id, viewer)), AuthUser(member_id), set_role("viewer"))
+            .await
+            .map(IntoResponse::into_response)
+            .unwrap_err();
+        assert_eq!(denied.into_response().status(), StatusCode::FORBIDDEN);
+
+        // A permission rule can grant it to them
+        sqlx::query("INSERT INTO permission_rules (id, team_id, role, permissions) VALUES ($1, $2, 'member', $3)")
+            .bind(Uuid::new_v4())
+            .bind(team.id)
+            .bind(serde_json::json!(["manage_roles"]))
+            .execute(&pool)
+            .await
+            .unwrap();
+        update_team_member(State(pool.clone()), Path((team.id, viewer)), AuthUser(member_id), set_role("viewer"))
+            .await
+            .unwrap();
+
+        // ...but only for roles below their own
+        assert!(matches!(
+            update_team_member(State(pool.clone()), Path((team.id, viewer)), AuthUser(member_id), set_role("member")).await,
+            Err(AppError::AuthorizationError(_))
+        ));
+        let members: Vec<TeamMember> = read_json(
+            list_team_members(State(pool.clone()), Path(team.id), AuthUser(owner_id)).await.unwrap(),
+        )
+        .await;
+        assert!(members.iter().any(|m| m.user_id == viewer_id && m.role == "viewer"));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_admins_cannot_touch_ownership() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool().clone();
+        let owner_id = crate::db::insert_test_user(&pool).await;
+        let admin_id = crate::db::insert_test_user(&pool).await;
+        let other_admin_id = crate::db::insert_test_user(&pool).await;
+        let member_id = crate::db::insert_test_user(&pool).await;
+        let newcomer_id = crate::db::insert_test_user(&pool).await;
+        let team = create_test_team(&pool, owner_id).await;
+        let mut added = HashMap::new();
+        for (user_id, role) in [(admin_id, "admin"), (other_admin_id, "admin"), (member_id, "member")] {
+            let request = AddTeamMemberRequest { user_id, role: role.to_string() };
+            let member: TeamMember = read_json(
+                add_team_member(State(pool.clone()), Path(team.id), AuthUser(owner_id), ClientInfo::default(), ValidatedJson(request))
+                    .await
+                    .unwrap(),
+            )
+            .await;
+            added.insert(user_id, member.id);
+        }
+        let owner_member: Uuid = sqlx::query_scalar("SELECT id FROM team_members WHERE team_id = $1 AND user_id = $2")
+            .bind(team.id)
+            .bind(owner_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let set_role = |role: &str| ValidatedJson(UpdateTeamMemberRequest { role: role.to_string() });
+
+        // An admin cannot make someone owner
+        assert!(matches!(
+            update_team_member(State(pool.clone()), Path((team.id, added[&member_id])), AuthUser(admin_id), set_role("owner")).await,
+            Err(AppError::AuthorizationError(_))
+        ));
+        // An admin cannot demote the owner
+        assert!(matches!(
+            update_team_member(State(pool.clone()), Path((team.id, owner_member)), AuthUser(admin_id), set_role("member")).await,
+            Err(AppError::AuthorizationError(_))
+        ));
+        // Nor change a fellow admin, or add another one
+        assert!(matches!(
+            update_team_member(State(pool.clone()), Path((team.id, added[&other_admin_id])), AuthUser(admin_id), set_role("viewer")).await,
+            Err(AppError::AuthorizationError(_))
+        ));
+        let request = AddTeamMemberRequest { user_id: newcomer_id, role: "admin".to_string() };
+        assert!(matches!(
+            add_team_member(State(pool.clone()), Path(team.id), AuthUser(admin_id), ClientInfo::default(), ValidatedJson(request)).await,
+            Err(AppError::AuthorizationError(_))
+        ));
+        // ...or invite one
+        for role in ["owner", "admin"] {
+            let request = CreateTeamInvitationRequest { email: format!("{}@example.com", Uuid::new_v4()), role: role.to_string() };
+            assert!(matches!(
+                create_team_invitation(
+                    State(pool.clone()),
+                    Extension(Arc::new(InvitationSettings::default())),
+                    Extension(Arc::new(EventBus::new(16))),
+                    Path(team.id),
+                    AuthUser(admin_id),
+                    ClientInfo::default(),
+                    ValidatedJson(request),
+                )
+                .await,
+                Err(AppError::AuthorizationError(_))
+            ));
+        }
+        // Even the owner can't hand ownership over here
+        assert!(matches!(
+            update_team_member(State(pool.clone()), Path((team.id, added[&admin_id])), AuthUser(owner_id), set_role("owner")).await,
+            Err(AppError::AuthorizationError(_))
+        ));
+        // Removing members follows the same rules, and the last owner always stays
+        assert!(matches!(
+            remove_team_member(State(pool.clone()), Path((team.id, added[&other_admin_id])), AuthUser(admin_id)).await,
+            Err(AppError::AuthorizationError(_))
+        ));
+        assert!(matches!(
+            remove_team_member(State(pool.clone()), Path((team.id, owner_member)), AuthUser(admin_id)).await,
+            Err(AppError::ConflictError(_))
+        ));
+        assert!(matches!(
+            remove_team_member(State(pool.clone()), Path((team.id, owner_member)), AuthUser(owner_id)).await,
+            Err(AppError::ConflictError(_))
+        ));
+        assert!(matches!(
+            remove_team_member(State(pool.clone()), Path((team.id, added[&admin_id])), AuthUser(member_id)).await,
+            Err(AppError::AuthorizationError(_))
+        ));
+        let roles: Vec<(Uuid, String)> = sqlx::query_as("SELECT user_id, role FROM team_members WHERE team_id = $1")
+            .bind(team.id)
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert!(roles.contains(&(owner_id, "owner".to_string())));
+        assert!(roles.contains(&(member_id, "member".to_string())));
+        assert!(roles.contains(&(other_admin_id, "admin".to_string())));
+        assert!(!roles.iter().any(|(user_id, _)| *user_id == newcomer_id));
+
+        // Members below the admin are theirs to manage; unknown ones are reported as such
+        update_team_member(State(pool.clone()), Path((team.id, added[&member_id])), AuthUser(admin_id), set_role("viewer"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            update_team_member(State(pool.clone()), Path((team.id, Uuid::new_v4())), AuthUser(admin_id), set_role("viewer")).await,
+            Err(AppError::NotFoundError(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_bulk_import_reports_each_entry() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool().clone();
+        let owner_id = crate::db::insert_test_user(&pool).await;
+        let unchanged_id = crate::db::insert_test_user(&pool).await;
+        let promoted_id = crate::db::insert_test_user(&pool).await;
+        let new_id = crate::db::insert_test_user(&pool).await;
+        let team = create_test_team(&pool, owner_id).await;
+        for (user_id, role) in [(unchanged_id, "member"), (promoted_id, "viewer")] {
+            let request = AddTeamMemberRequest { user_id, role: role.to_string() };
+            add_team_member(State(pool.clone()), Path(team.id), AuthUser(owner_id), ClientInfo::default(), ValidatedJson(request))
+                .await
+                .unwrap();
+        }
+
+        let entry = |user_id, role: &str| AddTeamMemberRequest { user_id, role: role.to_string() };
+        let request = BulkAddTeamMembersRequest {
+            members: vec![
+                entry(new_id, "member"),
+                entry(unchanged_id, "member"),
+                entry(promoted_id, "admin"),
+                entry(Uuid::new_v4(), "member"),
+                entry(new_id, "viewer"),
+                entry(owner_id, "viewer"),
+            ],
+        };
+        let results: Vec<BulkMemberResult> = read_json(
+            bulk_add_team_members(State(pool.clone()), Path(team.id), AuthUser(owner_id), ClientInfo::default(), ValidatedJson(request))
+                .await
+                .unwrap(),
+        )
+        .await;
+        let statuses: Vec<BulkMemberStatus> = results.iter().map(|result| result.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                BulkMemberStatus::Added,
+                BulkMemberStatus::Skipped,
+                BulkMemberStatus::Updated,
+                BulkMemberStatus::Error,
+                BulkMemberStatus::Error,
+                BulkMemberStatus::Error,
+            ]
+        );
+        assert!(results[..3].iter().all(|result| result.error.is_none()));
+        assert_eq!(results[3].error.as_deref(), Some("No such user"));
+        assert_eq!(results[4].error.as_deref(), Some("Listed more than once"));
+
+        let members: Vec<TeamMember> = read_json(
+            list_team_members(State(pool.clone()), Path(team.id), AuthUser(owner_id)).await.unwrap(),
+        )
+        .await;
+        let role_of = |user_id| members.iter().find(|m| m.user_id == user_id).map(|m| m.role.as_str());
+        assert_eq!(members.len(), 4);
+        assert_eq!(role_of(new_id), Some("member"));
+        assert_eq!(role_of(unchanged_id), Some("member"));
+        assert_eq!(role_of(promoted_id), Some("admin"));
+        assert_eq!(role_of(owner_id), Some("owner"));
+
+        // A bad role fails only its own entry; plain members can't import at all
+        let request = BulkAddTeamMembersRequest { members: vec![entry(unchanged_id, "superuser"), entry(unchanged_id, "viewer")] };
+        let results: Vec<BulkMemberResult> = read_json(
+            bulk_add_team_members(State(pool.clone()), Path(team.id), AuthUser(owner_id), ClientInfo::default(), ValidatedJson(request))
+                .await
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(results[0].status, BulkMemberStatus::Error);
+        assert_eq!(results[0].error.as_deref(), Some("Unknown team role: superuser"));
+        assert_eq!(results[1].status, BulkMemberStatus::Updated);
+        let request = BulkAddTeamMembersRequest { members: vec![entry(Uuid::new_v4(), "member")] };
+        assert!(matches!(
+            bulk_add_team_members(State(pool.clone()), Path(team.id), AuthUser(new_id), ClientInfo::default(), ValidatedJson(request)).await,
+            Err(AppError::AuthorizationError(_))
+        ));
+        assert!(BulkAddTeamMembersRequest { members: Vec::new() }.validate().is_err());
+
+        // An admin's import is held to the roles below their own, like single changes
+        let outsider_id = crate::db::insert_test_user(&pool).await;
+        let request = BulkAddTeamMembersRequest {
+            members: vec![
+                entry(outsider_id, "owner"),
+                entry(unchanged_id, "admin"),
+                entry(owner_id, "member"),
+                entry(new_id, "viewer"),
+            ],
+        };
+        let results: Vec<BulkMemberResult> = read_json(
+            bulk_add_team_members(State(pool.clone()), Path(team.id), AuthUser(promoted_id), ClientInfo::default(), ValidatedJson(request))
+                .await
+                .unwrap(),
+        )
+        .await;
+        let statuses: Vec<BulkMemberStatus> = results.iter().map(|result| result.status).collect();
+        assert_eq!(
+            statuses,
+            vec![BulkMemberStatus::Error, BulkMemberStatus::Error, BulkMemberStatus::Error, BulkMemberStatus::Updated]
+        );
+        let role: String = sqlx::query_scalar("SELECT role FROM team_members WHERE team_id = $1 AND user_id = $2")
+            .bind(team.id)
+            .bind(owner_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(role, "owner");
+    }
+
+    /// Invite `email` and return the emailed token, as the mailer would see it
+    async fn invite(
+        pool: &Pool<Postgres>,
+        settings: InvitationSettings,
+        team_id: Uuid,
+        inviter_id: Uuid,
+        email: &str,
+    ) -> (TeamInvitation, String) {
+        let events = Arc::new(EventBus::new(16));
+        let mut published = events.subscribe();
+        let request = CreateTeamInvitationRequest { email: email.to_string(), role: "member".to_string() };
+        let response = create_team_invitation(
+            State(pool.clone()),
+            Extension(Arc::new(settings)),
+            Extension(events),
+            Path(team_id),
+            AuthUser(inviter_id),
+            ClientInfo::default(),
+            ValidatedJson(request),
+        )
+        .await
+        .unwrap()
+        .into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let invitation: TeamInvitation = read_json(response).await;
+
+        let Ok(DomainEvent::TeamInvitationCreated { invitation_id, token, .. }) = published.try_recv() else {
+            panic!("no invitation event was published");
+        };
+        assert_eq!(invitation_id, invitation.id);
+        (invitation, token)
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_invite_register_accept() {
+        use crate::handlers::auth::register;
+        use crate::models::{RegisterRequest, RegisterResponse};
+
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        let db = crate::db::test_database().await;
+        let pool = db.pool().clone();
+        let owner_id = crate::db::insert_test_user(&pool).await;
+        let team = create_test_team(&pool, owner_id).await;
+
+        // Invited before they have an account
+        let email = format!("{}@example.com", Uuid::new_v4());
+        let (invitation, token) = invite(&pool, InvitationSettings::default(), team.id, owner_id, &email).await;
+        assert_eq!(invitation.accepted_at, None);
+        let stored: String = sqlx::query_scalar("SELECT token_hash FROM team_invitations WHERE id = $1")
+            .bind(invitation.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_ne!(stored, token);
+
+        let Json(registered) = register(
+            State(db.clone()),
+            Extension(Arc::new(EventBus::new(16))),
+            ValidatedJson(RegisterRequest {
+                email: email.to_uppercase(),
+                password: "TestPassword123".to_string(),
+                first_name: None,
+                last_name: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let invitee_id = match registered {
+            RegisterResponse::Authenticated(response) => response.user.id,
+            RegisterResponse::VerificationRequired(pending) => pending.user.id,
+        };
+
+        // Only the addressee can redeem it
+        let bystander_id = crate::db::insert_test_user(&pool).await;
+        assert!(matches!(
+            accept_team_invitation(State(pool.clone()), Path(token.clone()), AuthUser(bystander_id), ClientInfo::default()).await,
+            Err(AppError::AuthorizationError(_))
+        ));
+
+        let member: TeamMember = read_json(
+            accept_team_invitation(State(pool.clone()), Path(token.clone()), AuthUser(invitee_id), ClientInfo::default())
+                .await
+                .unwrap(),
+        )
+        .await;
+        assert_eq!((member.team_id, member.user_id, member.role.as_str()), (team.id, invitee_id, "member"));
+        let accepted_by: Option<Uuid> = sqlx::query_scalar("SELECT accepted_by FROM team_invitations WHERE id = $1")
+            .bind(invitation.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(accepted_by, Some(invitee_id));
+
+        assert!(matches!(
+            accept_team_invitation(State(pool.clone()), Path(token), AuthUser(invitee_id), ClientInfo::default()).await,
+            Err(AppError::ConflictError(_))
+        ));
+        assert!(matches!(
+            accept_team_invitation(State(pool.clone()), Path("not-a-token".to_string()), AuthUser(invitee_id), ClientInfo::default()).await,
+            Err(AppError::NotFoundError(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_expired_invitation_is_rejected() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool().clone();
+        let owner_id = crate::db::insert_test_user(&pool).await;
+        let invitee_id = crate::db::insert_test_user(&pool).await;
+        let team = create_test_team(&pool, owner_id).await;
+
+        let expired = InvitationSettings { ttl: chrono::Duration::seconds(-1) };
+        let email = format!("{}@example.com", invitee_id);
+        let (_, token) = invite(&pool, expired, team.id, owner_id, &email).await;
+
+        let result = accept_team_invitation(State(pool.clone()), Path(token), AuthUser(invitee_id), ClientInfo::default()).await;
+        assert!(matches!(result, Err(AppError::ValidationError(message)) if message == "Invitation has expired"));
+        let members: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM team_members WHERE team_id = $1 AND user_id = $2")
+            .bind(team.id)
+            .bind(invitee_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(members, 0);
+
+        // Outsiders can't invite, or tell the team exists
+        let request = CreateTeamInvitationRequest { email, role: "member".to_string() };
+        assert!(CreateTeamInvitationRequest { email: "nope".to_string(), role: "member".to_string() }.validate().is_err());
+        let result = create_team_invitation(
+            State(pool.clone()),
+            Extension(Arc::new(InvitationSettings::default())),
+            Extension(Arc::new(EventBus::new(16))),
+            Path(team.id),
+            AuthUser(invitee_id),
+            ClientInfo::default(),
+            ValidatedJson(request),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::NotFoundError(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_non_member_cannot_tell_team_exists() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool().clone();
+        let owner_id = crate::db::insert_test_user(&pool).await;
+        let viewer_id = crate::db::insert_test_user(&pool).await;
+        let outsider_id = crate::db::insert_test_user(&pool).await;
+        let team = create_test_team(&pool, owner_id).await;
+        let request = AddTeamMemberRequest { user_id: viewer_id, role: "viewer".to_string() };
+        add_team_member(State(pool.clone()), Path(team.id), AuthUser(owner_id), ClientInfo::default(), ValidatedJson(request))
+            .await
+            .unwrap();
+
+        // An outsider gets the same 404 for a real team as for a made-up ID
+        for team_id in [team.id, Uuid::new_v4()] {
+            assert!(matches!(
+                get_team(State(pool.clone()), Path(team_id), AuthUser(outsider_id)).await,
+                Err(AppError::NotFoundError(_))
+            ));
+            assert!(matches!(
+                list_team_members(State(pool.clone()), Path(team_id), AuthUser(outsider_id)).await,
+                Err(AppError::NotFoundError(_))
+            ));
+            let update = UpdateTeamRequest { name: Some("Taken over".to_string()), description: None };
+            assert!(matches!(
+                update_team(State(pool.clone()), Path(team_id), AuthUser(outsider_id), ClientInfo::default(), ValidatedJson(update)).await,
+                Err(AppError::NotFoundError(_))
+            ));
+            let request = AddTeamMemberRequest { user_id: outsider_id, role: "owner".to_string() };
+            assert!(matches!(
+                add_team_member(State(pool.clone()), Path(team_id), AuthUser(outsider_id), ClientInfo::default(), ValidatedJson(request)).await,
+                Err(AppError::NotFoundError(_))
+            ));
+            assert!(matches!(
+                delete_team(State(pool.clone()), Path(team_id), AuthUser(outsider_id)).await,
+                Err(AppError::NotFoundError(_))
+            ));
+        }
+
+        // Members can see the team, so being refused an operation is a 403
+        assert!(get_team(State(pool.clone()), Path(team.id), AuthUser(viewer_id)).await.is_ok());
+        assert!(matches!(
+            delete_team(State(pool.clone()), Path(team.id), AuthUser(viewer_id)).await,
+            Err(AppError::AuthorizationError(_))
+        ));
+        let request = AddTeamMemberRequest { user_id: outsider_id, role: "member".to_string() };
+        assert!(matches!(
+            add_team_member(State(pool.clone()), Path(team.id), AuthUser(viewer_id), ClientInfo::default(), ValidatedJson(request)).await,
+            Err(AppError::AuthorizationError(_))
+        ));
+    }
 
     #[test]
     fn test_slug_generation() {
@@ -0,0 +1,343 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use data_encoding::HEXLOWER;
+use rand::{rngs::OsRng, RngCore};
+use sqlx::{postgres::PgRow, Row};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    db::Database,
+    error::{AppError, AppResult},
+    handlers::projects::ensure_project_permission,
+    middleware_auth::AuthUser,
+    models::{CreateWebhookRequest, UpdateWebhookRequest, Webhook, WebhookDelivery},
+    utils::validation::ValidatedJson,
+};
+
+const WEBHOOK_COLUMNS: &str = "id, project_id, url, event_types, active, created_at, updated_at";
+
+/// 256 random bits, hex encoded
+fn generate_secret() -> String {
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    HEXLOWER.encode(&secret)
+}
+
+fn webhook_from_row(row: &PgRow) -> Webhook {
+    Webhook {
+        id: row.get("id"),
+        project_id: row.get("project_id"),
+        url: row.get("url"),
+        event_types: row.get("event_types"),
+        active: row.get("active"),
+        secret: None,
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+/// Managing webhooks takes the project's admin permission, since they send project data elsewhere
+pub async fn list_webhooks(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    Path(project_id): Path<Uuid>,
+) -> AppResult<Json<Vec<Webhook>>> {
+    ensure_project_permission(&db, project_id, user_id, "admin").await?;
+
+    let rows = sqlx::query(&format!(
+        "SELECT {} FROM webhooks WHERE project_id = $1 ORDER BY created_at",
+        WEBHOOK_COLUMNS
+    ))
+    .bind(project_id)
+    .fetch_all(db.pool())
+    .await?;
+
+    Ok(Json(rows.iter().map(webhook_from_row).collect()))
+}
+
+/// The response is the only time the signing secret is shown
+pub async fn create_webhook(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    Path(project_id): Path<Uuid>,
+    ValidatedJson(payload): ValidatedJson<CreateWebhookRequest>,
+) -> AppResult<(StatusCode, Json<Webhook>)> {
+    ensure_project_permission(&db, project_id, user_id, "admin").await?;
+
+    let secret = payload.secret.unwrap_or_else(generate_secret);
+    let row = sqlx::query(&format!(
+        "INSERT INTO webhooks (id, project_id, created_by, url, secret, event_types, active) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING {}",
+        WEBHOOK_COLUMNS
+    ))
+    .bind(Uuid::new_v4())
+    .bind(project_id)
+    .bind(user_id)
+    .bind(&payload.url)
+    .bind(&secret)
+    .bind(&payload.event_types)
+    .bind(payload.active)
+    .fetch_one(db.pool())
+    .await?;
+
+    let mut webhook = webhook_from_row(&row);
+    webhook.secret = Some(secret);
+    Ok((StatusCode::CREATED, Json(webhook)))
+}
+
+pub async fn get_webhook(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    Path((project_id, webhook_id)): Path<(Uuid, Uuid)>,
+) -> AppResult<Json<Webhook>> {
+    ensure_project_permission(&db, project_id, user_id, "admin").await?;
+
+    let row = sqlx::query(&format!(
+        "SELECT {} FROM webhooks WHERE id = $1 AND project_id = $2",
+        WEBHOOK_COLUMNS
+    ))
+    .bind(webhook_id)
+    .bind(project_id)
+    .fetch_optional(db.pool())
+    .await?
+    .ok_or_else(|| AppError::NotFoundError("Webhook not found".to_string()))?;
+
+    Ok(Json(webhook_from_row(&row)))
+}
+
+/// Fields left out keep their value; a new secret takes effect for the next delivery
+pub async fn update_webhook(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    Path((project_id, webhook_id)): Path<(Uuid, Uuid)>,
+    ValidatedJson(payload): ValidatedJson<UpdateWebhookRequest>,
+) -> AppResult<Json<Webhook>> {
+    ensure_project_permission(&db, project_id, user_id, "admin").await?;
+
+    let row = sqlx::query(&format!(
+        r#"
+        UPDATE webhooks
+        SET url = COALESCE($1, url),
+            event_types = COALESCE($2, event_types),
+            secret = COALESCE($3, secret),
+            active = COALESCE($4, active),
+            updated_at = NOW()
+        WHERE id = $5 AND project_id = $6
+        RETURNING {}
+        "#,
+        WEBHOOK_COLUMNS
+    ))
+    .bind(&payload.url)
+    .bind(&payload.event_types)
+    .bind(&payload.secret)
+    .bind(payload.active)
+    .bind(webhook_id)
+    .bind(project_id)
+    .fetch_optional(db.pool())
+    .await?
+    .ok_or_else(|| AppError::NotFoundError("Webhook not found".to_string()))?;
+
+    Ok(Json(webhook_from_row(&row)))
+}
+
+pub async fn delete_webhook(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    Path((project_id, webhook_id)): Path<(Uuid, Uuid)>,
+) -> AppResult<StatusCode> {
+    ensure_project_permission(&db, project_id, user_id, "admin").await?;
+
+    let deleted = sqlx::query("DELETE FROM webhooks WHERE id = $1 AND project_id = $2")
+        .bind(webhook_id)
+        .bind(project_id)
+        .execute(db.pool())
+        .await?;
+    if deleted.rows_affected() == 0 {
+        return Err(AppError::NotFoundError("Webhook not found".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The latest 100 delivery attempts, newest first
+pub async fn list_deliveries(
+    State(db): State<Arc<Database>>,
+    AuthUser(user_id): AuthUser,
+    Path((project_id, webhook_id)): Path<(Uuid, Uuid)>,
+) -> AppResult<Json<Vec<WebhookDelivery>>> {
+    ensure_project_permission(&db, project_id, user_id, "admin").await?;
+
+    let rows = sqlx::query(
+        r#"
+        SELECT d.id, d.webhook_id, d.delivery_id, d.event_type, d.payload, d.attempt,
+               d.status_code, d.error, d.succeeded, d.created_at
+        FROM webhook_deliveries d
+        JOIN webhooks w ON w.id = d.webhook_id
+        WHERE d.webhook_id = $1 AND w.project_id = $2
+        ORDER BY d.created_at DESC, d.attempt DESC
+        LIMIT 100
+        "#,
+    )
+    .bind(webhook_id)
+    .bind(project_id)
+    .fetch_all(db.pool())
+    .await?;
+
+    let deliveries = rows
+        .iter()
+        .map(|row| WebhookDelivery {
+            id: row.get("id"),
+            webhook_id: row.get("webhook_id"),
+            delivery_id: row.get("delivery_id"),
+            event_type: row.get("event_type"),
+            payload: row.get("payload"),
+            attempt: row.get("attempt"),
+            status_code: row.get("status_code"),
+            error: row.get("error"),
+            succeeded: row.get("succeeded"),
+            created_at: row.get("created_at"),
+        })
+        .collect();
+
+    Ok(Json(deliveries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::code_review::update_code_review;
+    use crate::models::UpdateCodeReviewRequest;
+    use crate::services::audit::ClientInfo;
+    use crate::services::events::EventBus;
+    use crate::services::webhooks::{self, sign, EVENT_HEADER, SIGNATURE_HEADER};
+    use crate::utils::etag::IfMatch;
+    use axum::{http::HeaderMap, routing::post, Extension, Router};
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    /// Accepts every POST to `/hook`, passing on its headers and body
+    async fn receiver() -> (String, mpsc::UnboundedReceiver<(HeaderMap, String)>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/hook",
+            post(move |headers: HeaderMap, body: String| {
+                let tx = tx.clone();
+                async move {
+                    tx.send((headers, body)).ok();
+                    StatusCode::NO_CONTENT
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        (format!("http://{}/hook", addr), rx)
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_merging_a_review_delivers_a_signed_webhook() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool().clone();
+
+        let owner = crate::db::insert_test_user(&pool).await;
+        let reviewer = crate::db::insert_test_user(&pool).await;
+        let project_id = crate::db::insert_test_project(&pool, owner, "hooked").await;
+
+        let (url, mut received) = receiver().await;
+        let request = CreateWebhookRequest {
+            url,
+            event_types: vec!["review.status_changed".to_string()],
+            secret: None,
+            active: true,
+        };
+        let (status, Json(webhook)) =
+            create_webhook(State(db.clone()), AuthUser(owner), Path(project_id), ValidatedJson(request))
+                .await
+                .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        let secret = webhook.secret.clone().expect("secret is returned on create");
+        assert_eq!(secret.len(), 64);
+
+        // Only admins can see or manage a project's webhooks, and the secret stays hidden
+        let result = list_webhooks(State(db.clone()), AuthUser(reviewer), Path(project_id)).await;
+        assert!(matches!(result, Err(AppError::NotFoundError(_))));
+        let Json(listed) = list_webhooks(State(db.clone()), AuthUser(owner), Path(project_id)).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert!(listed[0].secret.is_none());
+
+        let review_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO code_reviews (id, project_id, author_id, title) VALUES ($1, $2, $3, $4)")
+            .bind(review_id)
+            .bind(project_id)
+            .bind(owner)
+            .bind("Add webhooks")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO review_approvals (id, review_id, reviewer_id, status) VALUES ($1, $2, $3, 'approved')")
+            .bind(Uuid::new_v4())
+            .bind(review_id)
+            .bind(reviewer)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let merge = UpdateCodeReviewRequest {
+            title: None,
+            description: None,
+            status: Some("merged".to_string()),
+        };
+        // Delivered by the webhook subscriber, as in the running server
+        let events = Arc::new(EventBus::new(16));
+        webhooks::subscribe(&events, pool.clone());
+        update_code_review(
+            State(pool.clone()),
+            Extension(events),
+            Path((project_id, review_id)),
+            crate::middleware_auth::AuthUser(owner),
+            ClientInfo::default(),
+            IfMatch(None),
+            ValidatedJson(merge),
+        )
+        .await
+        .unwrap();
+
+        let (headers, body) = tokio::time::timeout(Duration::from_secs(5), received.recv())
+            .await
+            .expect("no webhook delivery arrived")
+            .unwrap();
+        assert_eq!(headers[SIGNATURE_HEADER], sign(&secret, body.as_bytes()));
+        assert_eq!(headers[EVENT_HEADER], "review.status_changed");
+
+        let payload: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(payload["event"], "review.status_changed");
+        assert_eq!(payload["project_id"], project_id.to_string());
+        assert_eq!(payload["data"]["review_id"], review_id.to_string());
+        assert_eq!(payload["data"]["previous_status"], "open");
+        assert_eq!(payload["data"]["status"], "merged");
+
+        // The attempt is recorded once the receiver has answered
+        let mut deliveries = Vec::new();
+        for _ in 0..50 {
+            let Json(recorded) = list_deliveries(State(db.clone()), AuthUser(owner), Path((project_id, webhook.id)))
+                .await
+                .unwrap();
+            if !recorded.is_empty() {
+                deliveries = recorded;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].attempt, 1);
+        assert_eq!(deliveries[0].status_code, Some(204));
+        assert!(deliveries[0].succeeded);
+        assert_eq!(deliveries[0].payload, payload);
+    }
+}
@@ -1,10 +1,11 @@
 use axum::{
     extract::DefaultBodyLimit,
-    middleware,
+    middleware::{from_fn, from_fn_with_state},
     routing::{get, post, put, delete},
-    Router,
+    Extension, Router,
 };
-use std::sync::Arc;
+use sqlx::PgPool;
+use std::{future::{Future, IntoFuture}, net::SocketAddr, sync::Arc, time::Duration};
 use tokio::net::TcpListener;
 use tower_http::cors::CorsLayer;
 use tracing_subscriber;
@@ -17,11 +18,32 @@ mod middleware;
 mod middleware_auth;
 mod models;
 mod services;
+mod telemetry;
 mod utils;
 
 use config::Config;
-use db::Database;
-use handlers::{auth, code_analysis, agents, projects, analytics};
+use db::{Database, PoolSettings};
+use crate::middleware::{
+    idempotency_middleware, rate_limit_middleware, request_id_middleware, CorsSettings, IdempotencyStore,
+    RateLimits,
+};
+use handlers::{
+    auth, code_analysis, code_review, agents, projects, analytics, health, collaboration, deployments, inheritance,
+    teams, webhooks,
+};
+use handlers::health::ReadinessProbes;
+use handlers::teams::InvitationSettings;
+use services::{
+    agent::AgentQueue,
+    collaboration::{CollaborationManager, HeartbeatConfig},
+    events::EventBus,
+    oauth::GithubOAuth,
+    InheritanceEngine,
+};
+use telemetry::Telemetry;
+
+/// Events a slow subscriber can fall behind by before publishing waits for it
+const EVENT_BUS_CAPACITY: usize = 1024;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -37,56 +59,797 @@ async fn main() -> anyhow::Result<()> {
     let config = Config::from_env()?;
     tracing::info!("Configuration loaded: {:?}", config);
 
+    // Signing and verification share one secret/algorithm
+    utils::jwt::init(utils::jwt::JwtConfig::from_config(&config).map_err(|e| anyhow::anyhow!("{:?}", e))?);
+
     // Initialize database
-    let db = Database::new(&config.database_url).await?;
+    let db = Database::new(&config.database_url, &PoolSettings::from_config(&config)).await?;
     db.run_migrations().await?;
     let db = Arc::new(db);
 
     tracing::info!("Database migrations completed");
 
-    // Build router
-    let app = Router::new()
-        // Health check
-        .route("/health", get(health_check))
+    // Replays responses to retried POSTs that carry an Idempotency-Key
+    let idempotency_store = Arc::new(IdempotencyStore::new(
+        db.clone(),
+        Duration::from_secs(config.idempotency_key_ttl_secs),
+    ));
+
+    // Periodically purge revoked token entries, abandoned OAuth and 2FA logins, idempotency keys
+    // and cached AI analyses past their expiry, and projects that have been in the trash longer than the retention window
+    let cleanup_db = db.clone();
+    let cleanup_store = idempotency_store.clone();
+    let trash_retention_days = config.project_trash_retention_days;
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            match cleanup_db.purge_expired_revoked_tokens().await {
+                Ok(purged) => tracing::debug!("Purged {} expired revoked tokens", purged),
+                Err(e) => tracing::error!("Failed to purge revoked tokens: {:?}", e),
+            }
+            match cleanup_db.purge_expired_login_challenges().await {
+                Ok(purged) => tracing::debug!("Purged {} expired login challenges", purged),
+                Err(e) => tracing::error!("Failed to purge login challenges: {:?}", e),
+            }
+            match cleanup_store.purge_expired().await {
+                Ok(purged) => tracing::debug!("Purged {} expired idempotency keys", purged),
+                Err(e) => tracing::error!("Failed to purge idempotency keys: {:?}", e),
+            }
+            match cleanup_db.purge_expired_analysis_cache().await {
+                Ok(purged) => tracing::debug!("Purged {} expired analysis cache entries", purged),
+                Err(e) => tracing::error!("Failed to purge analysis cache: {:?}", e),
+            }
+            match cleanup_db.purge_trashed_projects(trash_retention_days).await {
+                Ok(purged) => tracing::debug!("Purged {} trashed projects", purged),
+                Err(e) => tracing::error!("Failed to purge trashed projects: {:?}", e),
+            }
+        }
+    });
+
+    // With Redis, instances share the permission cache and collaboration broadcasts;
+    // otherwise each keeps its own in memory
+    #[cfg(feature = "redis")]
+    let redis = config
+        .redis_url
+        .as_deref()
+        .map(::redis::Client::open)
+        .transpose()?;
+    #[cfg(not(feature = "redis"))]
+    if config.redis_url.is_some() {
+        tracing::warn!("REDIS_URL is set but this build lacks the `redis` feature; using in-memory state");
+    }
+
+    // Shared so permission cache invalidation is visible to every request
+    let inheritance_engine = InheritanceEngine::new(Arc::new(db.pool().clone()), None);
+    #[cfg(feature = "redis")]
+    let inheritance_engine = match &redis {
+        Some(client) => inheritance_engine.with_cache_backend(Arc::new(
+            services::redis_store::RedisBackend::connect(client, "inheritance").await?,
+        )),
+        None => inheritance_engine,
+    };
+    let inheritance_engine = Arc::new(inheritance_engine);
+
+    // Webhooks, analytics and the audit log observe what handlers publish here
+    let event_bus = Arc::new(EventBus::new(EVENT_BUS_CAPACITY));
+    services::webhooks::subscribe(&event_bus, db.pool().clone());
+    services::analytics::subscribe(&event_bus, db.pool().clone());
+    services::events::spawn_audit_log(&event_bus);
+    services::mailer::subscribe(&event_bus);
+
+    // Agent work runs through a bounded queue rather than one task per request
+    let agent_queue = Arc::new(AgentQueue::new(config.agent_max_concurrent));
+
+    // Live sessions must be visible to both the WebSocket and the REST collaboration routes
+    let collaboration_manager = CollaborationManager::with_settings(
+        HeartbeatConfig {
+            empty_session_grace: Duration::from_secs(config.collab_session_grace_secs),
+            ..HeartbeatConfig::default()
+        },
+        config.collab_max_lag,
+        config.collab_channel_capacity,
+    );
+    #[cfg(feature = "redis")]
+    if let Some(client) = &redis {
+        services::redis_store::RedisSessionRelay::start(client.clone(), &collaboration_manager).await?;
+        tracing::info!("Relaying collaboration broadcasts through Redis");
+    }
+
+    // Close sessions everyone has left once their grace period is up, and sessions past their expiry
+    let reaper_db = db.clone();
+    let reaper_manager = collaboration_manager.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            match reaper_manager.close_idle_sessions(reaper_db.pool()).await {
+                Ok(closed) => tracing::debug!("Closed {} idle collaboration sessions", closed),
+                Err(e) => tracing::error!("Failed to close idle collaboration sessions: {}", e),
+            }
+            match reaper_manager.expire_sessions(reaper_db.pool()).await {
+                Ok(expired) => tracing::debug!("Expired {} collaboration sessions", expired),
+                Err(e) => tracing::error!("Failed to expire collaboration sessions: {}", e),
+            }
+        }
+    });
+
+    // Forget clients whose buckets have refilled so the maps don't grow without bound
+    let rate_limits = Arc::new(RateLimits::from_config(&config)?);
+    let purge_limits = rate_limits.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            let purged = purge_limits.purge_idle();
+            tracing::debug!("Purged {} idle rate limit buckets", purged);
+        }
+    });
+
+    // Installed before any request is served so no samples are lost
+    let telemetry = Arc::new(Telemetry::new(
+        telemetry::install_recorder(),
+        db.clone(),
+        agent_queue.clone(),
+        collaboration_manager.clone(),
+    ));
+
+    let cors = CorsSettings::from_config(&config).layer()?;
+
+    let services = AppServices {
+        inheritance_engine,
+        agent_queue: agent_queue.clone(),
+        collaboration_manager: collaboration_manager.clone(),
+        event_bus,
+        invitation_settings: Arc::new(InvitationSettings::from_config(&config)),
+        readiness_probes: Arc::new(ReadinessProbes::from_config(&config)),
+        github_oauth: GithubOAuth::from_config(&config).map(Arc::new),
+    };
+    let app = build_router(db.clone(), services, rate_limits, idempotency_store, cors);
+
+    // Metrics stay unauthenticated, so they can be kept off the public port
+    let app = match &config.metrics_addr {
+        Some(metrics_addr) => {
+            let metrics_listener = TcpListener::bind(metrics_addr).await?;
+            tracing::info!("Metrics listening on {}", metrics_addr);
+            let metrics_app = telemetry::metrics_router(telemetry);
+            tokio::spawn(async move {
+                if let Err(e) = axum::serve(metrics_listener, metrics_app).await {
+                    tracing::error!("Metrics server failed: {}", e);
+                }
+            });
+            app
+        }
+        None => app.merge(telemetry::metrics_router(telemetry)),
+    };
+
+    // Start server
+    let listener = TcpListener::bind(&config.server_addr).await?;
+    tracing::info!("Server listening on {}", config.server_addr);
+
+    // Upgraded WebSockets aren't tracked by the server's drain, so they're told to close directly
+    let signal_manager = collaboration_manager.clone();
+    let signal = async move {
+        shutdown_signal().await;
+        tracing::info!("Shutdown signal received, draining in-flight requests");
+        signal_manager.begin_shutdown();
+    };
+    serve_until_shutdown(listener, app, signal, Duration::from_secs(config.shutdown_timeout_secs)).await?;
+
+    // No requests are running now; persist in-memory state before the pool goes away
+    let flushed = collaboration_manager.close_all_sessions(db.pool()).await;
+    tracing::info!("Flushed {} collaboration sessions", flushed);
+    match agents::interrupt_agent_tasks(&db, &agent_queue).await {
+        Ok(interrupted) => tracing::info!("Marked {} unfinished agent tasks as failed", interrupted),
+        Err(e) => tracing::error!("Failed to record interrupted agent tasks: {:?}", e),
+    }
+    db.close().await;
+    tracing::info!("Shutdown complete");
+
+    Ok(())
+}
+
+/// Resolves on Ctrl+C, or on SIGTERM where the platform has it (what container runtimes send)
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+/// Serve until `signal` resolves, then stop accepting connections and give in-flight
+/// requests up to `drain_timeout` to finish before giving up on them
+async fn serve_until_shutdown<F>(
+    listener: TcpListener,
+    app: Router,
+    signal: F,
+    drain_timeout: Duration,
+) -> std::io::Result<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let (draining_tx, draining_rx) = tokio::sync::oneshot::channel();
+    // Peer addresses are what the rate limiter keys on when there's no proxy in front
+    let server = axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(async move {
+            signal.await;
+            let _ = draining_tx.send(());
+        })
+        .into_future();
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => return result,
+        Ok(()) = draining_rx => {}
+    }
+
+    match tokio::time::timeout(drain_timeout, server).await {
+        Ok(result) => result,
+        Err(_) => {
+            tracing::warn!("Requests still running after {:?}; shutting down anyway", drain_timeout);
+            Ok(())
+        }
+    }
+}
+
+/// Routes whose handlers take the connection pool as their state rather than `Database`
+fn pool_routes<S>(pool: PgPool) -> Router<S> {
+    Router::new()
+        // Team routes
+        .route("/teams", post(teams::create_team))
+        .route("/teams/:id", get(teams::get_team).put(teams::update_team).delete(teams::delete_team))
+        .route("/teams/:id/members", get(teams::list_team_members).post(teams::add_team_member))
+        .route("/teams/:id/members/bulk", post(teams::bulk_add_team_members))
+        .route("/teams/:id/members/:member_id", put(teams::update_team_member).delete(teams::remove_team_member))
+        .route("/teams/:id/invitations", post(teams::create_team_invitation))
+        .route("/teams/invitations/:token/accept", post(teams::accept_team_invitation))
+        .route("/projects/:id/members", post(teams::add_project_member))
+        .route("/projects/:id/members/:member_id", put(teams::update_project_member).delete(teams::remove_project_member))
+        .route("/projects/:id/members/:member_id/permissions", get(teams::check_permissions))
+        // Code review routes
+        .route("/projects/:id/reviews", post(code_review::create_code_review))
+        .route("/projects/:id/reviews/:review_id", get(code_review::get_code_review).put(code_review::update_code_review))
+        .route("/projects/:id/reviews/:review_id/comments", post(code_review::add_review_comment))
+        .route("/projects/:id/reviews/:review_id/comments/resolve", post(code_review::resolve_all_review_comments))
+        .route("/projects/:id/reviews/:review_id/comments/:comment_id", put(code_review::update_review_comment))
+        .route("/projects/:id/reviews/:review_id/comments/:comment_id/resolve", post(code_review::resolve_review_comment))
+        .route("/projects/:id/reviews/:review_id/approve", post(code_review::submit_approval))
+        .route("/projects/:id/reviews/:review_id/approvals", get(code_review::get_approvals))
+        .route("/projects/:id/review-settings", get(code_review::get_review_settings).put(code_review::update_review_settings))
+        // Permission inheritance routes
+        .route("/hierarchies/teams", post(inheritance::create_team_hierarchy))
+        .route("/hierarchies/projects", post(inheritance::create_project_hierarchy))
+        .route("/hierarchies/:resource_id/:resource_type", get(inheritance::get_hierarchy_tree))
+        .route("/permissions/:resource_id/:resource_type", get(inheritance::get_resolved_permissions))
+        .route("/permission-rules", post(inheritance::create_permission_rule))
+        .route("/permission-rules/:id", put(inheritance::update_permission_rule).delete(inheritance::delete_permission_rule))
+        .route("/audit-logs", get(inheritance::get_audit_logs))
+        .with_state(pool)
+}
+
+/// Shared services handlers reach through `Extension`
+struct AppServices {
+    inheritance_engine: Arc<InheritanceEngine>,
+    agent_queue: Arc<AgentQueue>,
+    collaboration_manager: Arc<CollaborationManager>,
+    event_bus: Arc<EventBus>,
+    invitation_settings: Arc<InvitationSettings>,
+    readiness_probes: Arc<ReadinessProbes>,
+    github_oauth: Option<Arc<GithubOAuth>>,
+}
+
+/// Assemble the application: API routes under `/api`, probes at the root
+fn build_router(
+    db: Arc<Database>,
+    services: AppServices,
+    rate_limits: Arc<RateLimits>,
+    idempotency_store: Arc<IdempotencyStore>,
+    cors: CorsLayer,
+) -> Router {
+    let api = Router::new()
         // Authentication routes
         .route("/auth/register", post(auth::register))
         .route("/auth/login", post(auth::login))
         .route("/auth/refresh", post(auth::refresh_token))
         .route("/auth/logout", post(auth::logout))
+        .route("/auth/forgot-password", post(auth::forgot_password))
+        .route("/auth/reset-password", post(auth::reset_password))
+        .route("/auth/verify", get(auth::verify_email))
+        .route("/auth/oauth/github", get(auth::github_login))
+        .route("/auth/oauth/github/callback", get(auth::github_callback))
+        .route("/auth/2fa/enroll", post(auth::enroll_two_factor))
+        .route("/auth/2fa/verify", post(auth::verify_two_factor))
+        .route("/auth/2fa/challenge", post(auth::two_factor_challenge))
+        .route("/auth/me", get(auth::me))
         // Project routes
         .route("/projects", get(projects::list_projects).post(projects::create_project))
+        .route("/projects/trash", get(projects::list_trash))
         .route("/projects/:id", get(projects::get_project).put(projects::update_project).delete(projects::delete_project))
-        .route("/projects/:id/files", get(projects::list_files))
+        .route("/projects/:id/restore", post(projects::restore_project))
+        .route("/projects/:id/transfer", post(projects::transfer_project))
+        .route("/projects/:id/files", get(projects::list_files).post(projects::create_file))
+        .route("/projects/:id/files/:file_id", get(projects::get_file).put(projects::update_file).delete(projects::delete_file))
+        .route("/projects/:id/files/:file_id/versions", get(projects::list_file_versions))
+        // Deployment routes, used by the CLI
+        .route("/projects/:id/deploy", post(deployments::deploy))
+        .route("/projects/:id/code", get(deployments::get_code))
+        .route("/projects/:id/deployments", get(deployments::list_deployments))
+        .route("/deployments/:id", get(deployments::get_deployment))
+        .route("/projects/:id/analyze", post(deployments::analyze))
+        .route("/projects/:id/duplicates", get(deployments::duplicates))
+        // Webhook routes
+        .route("/projects/:id/webhooks", get(webhooks::list_webhooks).post(webhooks::create_webhook))
+        .route("/projects/:id/webhooks/:webhook_id", get(webhooks::get_webhook).put(webhooks::update_webhook).delete(webhooks::delete_webhook))
+        .route("/projects/:id/webhooks/:webhook_id/deliveries", get(webhooks::list_deliveries))
         // Code analysis routes
         .route("/analysis/optimize", post(code_analysis::optimize_code))
+        .route("/analysis/optimize/stream", post(code_analysis::optimize_code_stream))
         .route("/analysis/review", post(code_analysis::review_code))
         .route("/analysis/refactor", post(code_analysis::refactor_code))
         // Agent routes
+        .route("/agents", get(agents::list_agents))
         .route("/agents/frontend", post(agents::frontend_agent))
         .route("/agents/backend", post(agents::backend_agent))
         .route("/agents/qa", post(agents::qa_agent))
-        .route("/agents/status/:task_id", get(agents::get_task_status))
+        .route("/agents/status/:task_id", get(agents::get_task_status).delete(agents::cancel_task))
+        // Real-time collaboration routes
+        .route("/collaboration/sessions", post(collaboration::create_collaborative_session))
+        .route("/collaboration/sessions/:session_id/ws", get(collaboration::join_collaboration))
+        .route("/collaboration/sessions/:session_id/participants", get(collaboration::get_active_collaborators))
+        .route("/collaboration/sessions/:session_id/cursors", get(collaboration::get_cursor_positions))
+        .route("/collaboration/sessions/:session_id/operations", post(collaboration::sync_code_state))
+        .route("/collaboration/sessions/:session_id/conflicts", get(collaboration::detect_conflicts))
         // Analytics routes
         .route("/analytics/dashboard", get(analytics::get_dashboard))
         .route("/analytics/metrics", get(analytics::get_metrics))
-        .route("/analytics/reports", get(analytics::list_reports))
-        // Protected routes middleware
-        .layer(middleware::from_fn(middleware_auth::auth_middleware))
-        // CORS layer
-        .layer(CorsLayer::permissive())
+        .route("/analytics/reports", get(analytics::list_reports).post(analytics::create_report))
+        .route("/analytics/reports/:id", get(analytics::get_report))
+        .route("/analytics/usage", get(analytics::get_usage))
+        .merge(pool_routes(db.pool().clone()))
+        .route_layer(from_fn(telemetry::expose_matched_path));
+
+    Router::new()
+        // Health checks
+        .route("/readyz", get(health::readyz))
+        .route("/health", get(health::readyz))
+        .route("/livez", get(health::livez))
+        .route_layer(from_fn(telemetry::expose_matched_path))
+        .nest("/api", api)
+        .layer(Extension(services.inheritance_engine))
+        .layer(Extension(services.agent_queue))
+        .layer(Extension(services.collaboration_manager))
+        .layer(Extension(services.event_bus))
+        .layer(Extension(services.invitation_settings))
+        .layer(Extension(services.readiness_probes))
+        .layer(Extension(services.github_oauth))
+        // Wrapped by the auth layer, so keys can be scoped to the authenticated user
+        .layer(from_fn_with_state(idempotency_store, idempotency_middleware))
+        // Protected routes middleware; sees the full path, including the /api prefix
+        .layer(from_fn_with_state(db.clone(), middleware_auth::auth_middleware))
+        // Rate limiting runs before authentication so floods never reach the token lookup
+        .layer(from_fn_with_state(rate_limits, rate_limit_middleware))
+        // CORS layer, limited to the configured origins
+        .layer(cors)
         // Body limit
         .layer(DefaultBodyLimit::max(10 * 1024 * 1024)) // 10MB
-        .with_state(db);
+        // Counts requests rejected by the layers above too, labelled `unmatched`
+        .layer(from_fn(telemetry::track_http_metrics))
+        // Outermost, so every log line and error body from the layers above carries the request ID
+        .layer(from_fn(request_id_middleware))
+        .with_state(db)
+}
 
-    // Start server
-    let listener = TcpListener::bind(&config.server_addr).await?;
-    tracing::info!("Server listening on {}", config.server_addr);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::rate_limit::RateLimiter;
+    use serde::Deserialize;
+    use uuid::Uuid;
 
-    axum::serve(listener, app).await?;
+    /// The response shape the CLI's `ApiClient::login` deserializes
+    #[derive(Debug, Deserialize)]
+    struct CliLoginResponse {
+        #[serde(rename = "access_token")]
+        token: String,
+        user: CliUserInfo,
+    }
 
-    Ok(())
-}
+    #[derive(Debug, Deserialize)]
+    struct CliUserInfo {
+        id: String,
+        email: String,
+    }
+
+    /// The response shape the CLI's `ApiClient::get_user_info` deserializes
+    #[derive(Debug, Deserialize)]
+    struct CliCurrentUser {
+        id: String,
+        email: String,
+        created_at: String,
+    }
+
+    /// The response shape the CLI's `ApiClient::list_agents` deserializes
+    #[derive(Debug, Deserialize)]
+    struct CliAgentInfo {
+        name: String,
+        description: String,
+    }
+
+    /// Serve the full application on an ephemeral port, returning its base URL
+    async fn serve_test_app(db: Arc<Database>) -> String {
+        let engine = Arc::new(InheritanceEngine::new(Arc::new(db.pool().clone()), None));
+        let limits = Arc::new(RateLimits {
+            default: RateLimiter::new(0),
+            auth: RateLimiter::new(0),
+            analysis: RateLimiter::new(0),
+            trusted_proxies: Default::default(),
+        });
+        let idempotency_store = Arc::new(IdempotencyStore::new(db.clone(), Duration::from_secs(60)));
+        let services = AppServices {
+            inheritance_engine: engine,
+            agent_queue: Arc::new(AgentQueue::new(1)),
+            collaboration_manager: CollaborationManager::new(),
+            event_bus: Arc::new(EventBus::new(16)),
+            invitation_settings: Arc::new(InvitationSettings::default()),
+            readiness_probes: Arc::new(ReadinessProbes::default()),
+            github_oauth: None,
+        };
+        let app = build_router(db, services, limits, idempotency_store, CorsLayer::new());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        base_url
+    }
+
+    const TEST_PASSWORD: &str = "RoutePass123";
+
+    /// A verified user whose email is `{id}@example.com` and password `TEST_PASSWORD`
+    async fn insert_verified_user(db: &Database) -> Uuid {
+        let user_id = crate::db::insert_test_user(db.pool()).await;
+        sqlx::query("UPDATE users SET password_hash = $2, email_verified = TRUE WHERE id = $1")
+            .bind(user_id)
+            .bind(bcrypt::hash(TEST_PASSWORD, 4).unwrap())
+            .execute(db.pool())
+            .await
+            .unwrap();
+        user_id
+    }
+
+    async fn log_in(client: &reqwest::Client, base_url: &str, user_id: Uuid) -> CliLoginResponse {
+        client
+            .post(format!("{}/api/auth/login", base_url))
+            .json(&serde_json::json!({ "email": format!("{}@example.com", user_id), "password": TEST_PASSWORD }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_cli_login_round_trip() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        let db = crate::db::test_database().await;
+
+        let user_id = insert_verified_user(&db).await;
+        let email = format!("{}@example.com", user_id);
+
+        let base_url = serve_test_app(db).await;
+
+        // Same request the CLI's `cx7 auth login` sends
+        let client = reqwest::Client::new();
+        let response = client
+            .post(format!("{}/api/auth/login", base_url))
+            .json(&serde_json::json!({ "email": email, "password": TEST_PASSWORD }))
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+
+        let login: CliLoginResponse = response.json().await.unwrap();
+        assert!(!login.token.is_empty());
+        assert_eq!(login.user.id, user_id.to_string());
+        assert_eq!(login.user.email, email);
+
+        // Probes stay at the root, outside the /api prefix
+        let health = client.get(format!("{}/health", base_url)).send().await.unwrap();
+        assert!(health.status().is_success());
+        assert!(health.headers().contains_key("x-request-id"));
+        let unprefixed = client
+            .post(format!("{}/auth/login", base_url))
+            .json(&serde_json::json!({ "email": email, "password": TEST_PASSWORD }))
+            .send()
+            .await
+            .unwrap();
+        assert!(!unprefixed.status().is_success());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_cli_whoami_and_agent_list_are_served() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        let db = crate::db::test_database().await;
+        let user_id = insert_verified_user(&db).await;
+        let base_url = serve_test_app(db).await;
+        let client = reqwest::Client::new();
+        let login = log_in(&client, &base_url, user_id).await;
+
+        // Same request as `cx7 auth whoami`
+        let response = client
+            .get(format!("{}/api/auth/me", base_url))
+            .bearer_auth(&login.token)
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        let me: CliCurrentUser = response.json().await.unwrap();
+        assert_eq!(me.id, user_id.to_string());
+        assert_eq!(me.email, format!("{}@example.com", user_id));
+        assert!(!me.created_at.is_empty());
+
+        // Same request as `cx7 agent list`; every agent listed can be started by name
+        let response = client
+            .get(format!("{}/api/agents", base_url))
+            .bearer_auth(&login.token)
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        let agents: Vec<CliAgentInfo> = response.json().await.unwrap();
+        let names: Vec<&str> = agents.iter().map(|agent| agent.name.as_str()).collect();
+        assert_eq!(names, vec!["frontend", "backend", "qa"]);
+        assert!(agents.iter().all(|agent| !agent.description.is_empty()));
+        for name in names {
+            let response = client
+                .post(format!("{}/api/agents/{}", base_url, name))
+                .bearer_auth(&login.token)
+                .json(&serde_json::json!({}))
+                .send()
+                .await
+                .unwrap();
+            assert_ne!(response.status(), reqwest::StatusCode::NOT_FOUND);
+        }
+
+        // Both need a signed-in user
+        for path in ["/api/auth/me", "/api/agents"] {
+            let response = client.get(format!("{}{}", base_url, path)).send().await.unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_team_routes_are_served() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        let db = crate::db::test_database().await;
+
+        let owner_id = insert_verified_user(&db).await;
+        let member_id = insert_verified_user(&db).await;
+        let base_url = serve_test_app(db).await;
+
+        let client = reqwest::Client::new();
+        let login = log_in(&client, &base_url, owner_id).await;
+
+        let created = client
+            .post(format!("{}/api/teams", base_url))
+            .bearer_auth(&login.token)
+            .json(&serde_json::json!({ "name": format!("Routed Team {}", owner_id) }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(created.status(), reqwest::StatusCode::CREATED);
+        let team: serde_json::Value = created.json().await.unwrap();
+        let team_id = team["id"].as_str().unwrap().to_string();
+
+        let bulk = client
+            .post(format!("{}/api/teams/{}/members/bulk", base_url, team_id))
+            .bearer_auth(&login.token)
+            .json(&serde_json::json!([{ "user_id": member_id, "role": "member" }]))
+            .send()
+            .await
+            .unwrap();
+        assert!(bulk.status().is_success());
+        let results: serde_json::Value = bulk.json().await.unwrap();
+        assert_eq!(results[0]["status"], "added");
+
+        let invitation = client
+            .post(format!("{}/api/teams/{}/invitations", base_url, team_id))
+            .bearer_auth(&login.token)
+            .json(&serde_json::json!({ "email": "invitee@example.com", "role": "viewer" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(invitation.status(), reqwest::StatusCode::CREATED);
+
+        // The accept route is matched rather than taken for a team ID
+        let unknown = client
+            .post(format!("{}/api/teams/invitations/not-a-token/accept", base_url))
+            .bearer_auth(&login.token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(unknown.status(), reqwest::StatusCode::NOT_FOUND);
+        let error: crate::error::ErrorResponse = unknown.json().await.unwrap();
+        assert_eq!(error.code, "NOT_FOUND_ERROR");
+
+        let members: serde_json::Value = client
+            .get(format!("{}/api/teams/{}/members", base_url, team_id))
+            .bearer_auth(&login.token)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(members.as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_review_and_inheritance_routes_are_served() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        let db = crate::db::test_database().await;
+        let owner_id = insert_verified_user(&db).await;
+        let base_url = serve_test_app(db).await;
+
+        let client = reqwest::Client::new();
+        let token = log_in(&client, &base_url, owner_id).await.token;
+        let send = |request: reqwest::RequestBuilder| {
+            let request = request.bearer_auth(&token);
+            async move {
+                let response = request.send().await.unwrap();
+                let status = response.status();
+                (status, response.json::<serde_json::Value>().await.unwrap_or_default())
+            }
+        };
+
+        let (status, project) = send(
+            client.post(format!("{}/api/projects", base_url)).json(&serde_json::json!({ "name": "Reviewed" })),
+        )
+        .await;
+        assert!(status.is_success());
+        let project_url = format!("{}/api/projects/{}", base_url, project["id"].as_str().unwrap());
+
+        let (status, review) = send(
+            client.post(format!("{}/reviews", project_url)).json(&serde_json::json!({ "title": "Routed review" })),
+        )
+        .await;
+        assert_eq!(status, reqwest::StatusCode::CREATED);
+        let review_url = format!("{}/reviews/{}", project_url, review["id"].as_str().unwrap());
+
+        let (status, comment) = send(
+            client.post(format!("{}/comments", review_url)).json(&serde_json::json!({ "content": "Why?" })),
+        )
+        .await;
+        assert_eq!(status, reqwest::StatusCode::CREATED);
+        let (status, _) = send(client.post(format!("{}/comments", review_url)).json(
+            &serde_json::json!({ "content": "Because", "parent_comment_id": comment["id"] }),
+        ))
+        .await;
+        assert_eq!(status, reqwest::StatusCode::CREATED);
+
+        // Both the single-thread and the resolve-everything routes are matched
+        let (status, resolved) = send(client.post(format!(
+            "{}/comments/{}/resolve",
+            review_url,
+            comment["id"].as_str().unwrap()
+        )))
+        .await;
+        assert!(status.is_success());
+        assert_eq!(resolved["resolved"], 2);
+        let (status, resolved) = send(client.post(format!("{}/comments/resolve", review_url))).await;
+        assert!(status.is_success());
+        assert_eq!(resolved["resolved"], 0);
+
+        let (status, details) = send(client.get(&review_url)).await;
+        assert!(status.is_success());
+        assert_eq!(details["comments"]["items"][0]["replies"].as_array().unwrap().len(), 1);
+
+        // Status changes go through the transition table
+        let (status, _) = send(client.put(&review_url).json(&serde_json::json!({ "status": "closed" }))).await;
+        assert!(status.is_success());
+        let (status, _) = send(client.put(&review_url).json(&serde_json::json!({ "status": "approved" }))).await;
+        assert_eq!(status, reqwest::StatusCode::CONFLICT);
+
+        let (status, settings) = send(client.get(format!("{}/review-settings", project_url))).await;
+        assert!(status.is_success());
+        assert!(settings["required_approvals"].is_number());
+
+        let project_id = project["id"].as_str().unwrap();
+        let (status, _) = send(client.get(format!("{}/api/permissions/{}/project", base_url, project_id))).await;
+        assert!(status.is_success());
+        let (status, tree) = send(client.get(format!("{}/api/hierarchies/{}/project", base_url, project_id))).await;
+        assert!(status.is_success());
+        assert_eq!(tree["children"].as_array().unwrap().len(), 0);
+        let (status, logs) = send(client.get(format!("{}/api/audit-logs", base_url))).await;
+        assert!(status.is_success());
+        assert!(logs["items"].is_array());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_signal_drains_in_flight_requests() {
+        let started = Arc::new(tokio::sync::Notify::new());
+        let handler_started = started.clone();
+        let app = Router::new().route(
+            "/slow",
+            get(move || {
+                let started = handler_started.clone();
+                async move {
+                    started.notify_one();
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    "done"
+                }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let signal = async move {
+            let _ = shutdown_rx.await;
+        };
+        let server = tokio::spawn(serve_until_shutdown(listener, app, signal, Duration::from_secs(5)));
+
+        let request = tokio::spawn(reqwest::get(format!("{}/slow", base_url)));
+        started.notified().await;
+        shutdown_tx.send(()).unwrap();
+
+        // The request that was already running still gets its response
+        let response = request.await.unwrap().unwrap();
+        assert_eq!(response.text().await.unwrap(), "done");
+
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("server did not stop after the shutdown signal")
+            .unwrap()
+            .unwrap();
+        assert!(reqwest::get(format!("{}/slow", base_url)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_drain_timeout_bounds_shutdown() {
+        let app = Router::new().route("/stuck", get(std::future::pending::<&'static str>));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let signal = async move {
+            let _ = shutdown_rx.await;
+        };
+        let server = tokio::spawn(serve_until_shutdown(listener, app, signal, Duration::from_millis(200)));
+
+        let _request = tokio::spawn(reqwest::get(format!("{}/stuck", base_url)));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        shutdown_tx.send(()).unwrap();
 
-async fn health_check() -> &'static str {
-    "OK"
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("a stuck request held up shutdown past the drain timeout")
+            .unwrap()
+            .unwrap();
+    }
 }
@@ -0,0 +1,149 @@
+use axum::http::{
+    header::{AUTHORIZATION, CONTENT_TYPE, ETAG, IF_MATCH, RETRY_AFTER},
+    HeaderName, HeaderValue, Method,
+};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+use crate::config::Config;
+use crate::middleware::idempotency::{IDEMPOTENCY_KEY_HEADER, IDEMPOTENT_REPLAYED_HEADER};
+use crate::middleware::request_id::REQUEST_ID_HEADER;
+
+/// Which cross-origin callers the API answers, taken from `Config`
+#[derive(Debug, Clone)]
+pub struct CorsSettings {
+    pub allowed_origins: Vec<String>,
+    pub allow_credentials: bool,
+    /// With no origins configured, accept any origin (development only)
+    pub allow_any_when_empty: bool,
+}
+
+impl CorsSettings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            allowed_origins: config.cors_allowed_origins.clone(),
+            allow_credentials: config.cors_allow_credentials,
+            allow_any_when_empty: config.environment == "development",
+        }
+    }
+
+    /// CORS for the configured origins only, the methods the routes use, and the
+    /// headers clients send. An empty list allows no origins outside development.
+    pub fn layer(&self) -> anyhow::Result<CorsLayer> {
+        let layer = CorsLayer::new()
+            .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+            .allow_headers([
+                AUTHORIZATION,
+                CONTENT_TYPE,
+                IF_MATCH,
+                HeaderName::from_static(IDEMPOTENCY_KEY_HEADER),
+            ])
+            .expose_headers([
+                HeaderName::from_static(REQUEST_ID_HEADER),
+                RETRY_AFTER,
+                ETAG,
+                HeaderName::from_static(IDEMPOTENT_REPLAYED_HEADER),
+            ]);
+
+        if self.allowed_origins.is_empty() {
+            if !self.allow_any_when_empty {
+                return Ok(layer.allow_origin(AllowOrigin::list([])));
+            }
+            // Browsers refuse credentials with a wildcard origin, and so does tower-http
+            if self.allow_credentials {
+                tracing::warn!("CORS_ALLOW_CREDENTIALS is ignored while any origin is allowed");
+            }
+            return Ok(layer.allow_origin(Any));
+        }
+
+        let origins = self
+            .allowed_origins
+            .iter()
+            .map(|origin| {
+                HeaderValue::from_str(origin)
+                    .map_err(|_| anyhow::anyhow!("Invalid CORS origin: {}", origin))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(layer
+            .allow_origin(AllowOrigin::list(origins))
+            .allow_credentials(self.allow_credentials))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        extract::Request,
+        http::header::{
+            ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_ORIGIN,
+            ACCESS_CONTROL_REQUEST_METHOD, ORIGIN,
+        },
+        response::Response,
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    fn settings(origins: &[&str], allow_credentials: bool, allow_any_when_empty: bool) -> CorsSettings {
+        CorsSettings {
+            allowed_origins: origins.iter().map(|origin| origin.to_string()).collect(),
+            allow_credentials,
+            allow_any_when_empty,
+        }
+    }
+
+    async fn preflight(settings: &CorsSettings, origin: &str) -> Response {
+        let app = Router::new()
+            .route("/api/projects", get(|| async { "ok" }))
+            .layer(settings.layer().unwrap());
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/api/projects")
+            .header(ORIGIN, origin)
+            .header(ACCESS_CONTROL_REQUEST_METHOD, "GET")
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_only_allowlisted_origins_get_cors_headers() {
+        let settings = settings(&["https://app.example.com"], true, false);
+
+        let allowed = preflight(&settings, "https://app.example.com").await;
+        assert_eq!(
+            allowed.headers()[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        assert_eq!(allowed.headers()[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+
+        let denied = preflight(&settings, "https://evil.example.com").await;
+        assert!(denied.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_preflight_allows_the_headers_clients_send() {
+        let allowed = preflight(&settings(&["https://app.example.com"], false, false), "https://app.example.com").await;
+        let headers = allowed.headers()[ACCESS_CONTROL_ALLOW_HEADERS].to_str().unwrap();
+        for header in ["authorization", "content-type", "if-match", "idempotency-key"] {
+            assert!(headers.contains(header), "{} missing from {}", header, headers);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_empty_allowlist_depends_on_environment() {
+        let development = preflight(&settings(&[], true, true), "http://localhost:3000").await;
+        assert_eq!(development.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(development.headers().get(ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+
+        let production = preflight(&settings(&[], false, false), "http://localhost:3000").await;
+        assert!(production.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+
+    #[test]
+    fn test_invalid_origin_is_rejected() {
+        assert!(settings(&["https://bad\norigin"], false, false).layer().is_err());
+    }
+}
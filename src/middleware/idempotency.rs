@@ -0,0 +1,480 @@
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::{header::CONTENT_TYPE, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+use sqlx::Row;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::db::Database;
+use crate::error::{AppError, AppResult};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+/// Set on responses served from the store rather than by running the handler again
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+
+const MAX_KEY_LENGTH: usize = 255;
+/// How long a claimed key blocks retries while its request runs; longer than any
+/// idempotent route takes to answer
+const CLAIM_LEASE: Duration = Duration::from_secs(5 * 60);
+// Matches the router's DefaultBodyLimit
+const MAX_BODY_BYTES: usize = 10 * 1024 * 1024;
+
+/// Remembers the response to each (user, Idempotency-Key) pair for `ttl`
+pub struct IdempotencyStore {
+    db: Arc<Database>,
+    ttl: Duration,
+}
+
+enum Claim {
+    /// First time this key has been seen; the caller must `complete` or `release` it
+    New,
+    Replay(StoredResponse),
+    InProgress,
+    /// The key was first used for a different method, path or body
+    Mismatch,
+}
+
+struct StoredResponse {
+    status: StatusCode,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+impl IntoResponse for StoredResponse {
+    fn into_response(self) -> Response {
+        let mut response = (self.status, self.body).into_response();
+        let headers = response.headers_mut();
+        headers.remove(CONTENT_TYPE);
+        if let Some(value) = self.content_type.and_then(|ct| HeaderValue::from_str(&ct).ok()) {
+            headers.insert(CONTENT_TYPE, value);
+        }
+        headers.insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+        response
+    }
+}
+
+impl IdempotencyStore {
+    pub fn new(db: Arc<Database>, ttl: Duration) -> Self {
+        Self { db, ttl }
+    }
+
+    /// Reserve the key for this request, or report what an earlier request with it did
+    async fn claim(&self, user_id: Uuid, key: &str, request_hash: &str) -> AppResult<Claim> {
+        // Expired keys, and claims whose request died without completing or releasing them
+        sqlx::query(
+            r#"
+            DELETE FROM idempotency_keys
+            WHERE user_id = $1 AND idempotency_key = $2
+              AND (expires_at <= NOW() OR (status_code IS NULL AND locked_until <= NOW()))
+            "#,
+        )
+        .bind(user_id)
+        .bind(key)
+        .execute(self.db.pool())
+        .await?;
+
+        let inserted = sqlx::query(
+            r#"
+            INSERT INTO idempotency_keys (user_id, idempotency_key, request_hash, expires_at, locked_until)
+            VALUES ($1, $2, $3, NOW() + make_interval(secs => $4), NOW() + make_interval(secs => $5))
+            ON CONFLICT (user_id, idempotency_key) DO NOTHING
+            "#,
+        )
+        .bind(user_id)
+        .bind(key)
+        .bind(request_hash)
+        .bind(self.ttl.as_secs_f64())
+        .bind(CLAIM_LEASE.as_secs_f64())
+        .execute(self.db.pool())
+        .await?;
+        if inserted.rows_affected() == 1 {
+            return Ok(Claim::New);
+        }
+
+        let Some(row) = sqlx::query(
+            "SELECT request_hash, status_code, content_type, response_body FROM idempotency_keys WHERE user_id = $1 AND idempotency_key = $2",
+        )
+        .bind(user_id)
+        .bind(key)
+        .fetch_optional(self.db.pool())
+        .await?
+        else {
+            // Released by a failed first attempt between our insert and select
+            return Ok(Claim::InProgress);
+        };
+
+        if row.get::<String, _>("request_hash") != request_hash {
+            return Ok(Claim::Mismatch);
+        }
+        let Some(status_code) = row.get::<Option<i16>, _>("status_code") else {
+            return Ok(Claim::InProgress);
+        };
+
+        Ok(Claim::Replay(StoredResponse {
+            status: StatusCode::from_u16(status_code as u16).unwrap_or(StatusCode::OK),
+            content_type: row.get("content_type"),
+            body: row.get::<Option<Vec<u8>>, _>("response_body").unwrap_or_default(),
+        }))
+    }
+
+    async fn complete(
+        &self,
+        user_id: Uuid,
+        key: &str,
+        status: StatusCode,
+        content_type: Option<&str>,
+        body: &[u8],
+    ) -> AppResult<()> {
+        sqlx::query(
+            "UPDATE idempotency_keys SET status_code = $3, content_type = $4, response_body = $5 WHERE user_id = $1 AND idempotency_key = $2",
+        )
+        .bind(user_id)
+        .bind(key)
+        .bind(status.as_u16() as i16)
+        .bind(content_type)
+        .bind(body)
+        .execute(self.db.pool())
+        .await?;
+        Ok(())
+    }
+
+    /// Forget a key whose request failed, so a retry runs it again
+    async fn release(&self, user_id: Uuid, key: &str) -> AppResult<()> {
+        sqlx::query("DELETE FROM idempotency_keys WHERE user_id = $1 AND idempotency_key = $2")
+            .bind(user_id)
+            .bind(key)
+            .execute(self.db.pool())
+            .await?;
+        Ok(())
+    }
+
+    /// Delete keys past their TTL, and claims abandoned past their lease
+    pub async fn purge_expired(&self) -> Result<u64, sqlx::Error> {
+        let purged = sqlx::query(
+            "DELETE FROM idempotency_keys WHERE expires_at <= NOW() OR (status_code IS NULL AND locked_until <= NOW())",
+        )
+        .execute(self.db.pool())
+        .await?;
+        Ok(purged.rows_affected())
+    }
+}
+
+/// A `Claim::New` key held for the request running under it. If the request is dropped
+/// before finishing, e.g. because the client disconnected, the key is released so a
+/// retry runs it again.
+struct ClaimGuard {
+    store: Arc<IdempotencyStore>,
+    user_id: Uuid,
+    key: String,
+    held: bool,
+}
+
+impl ClaimGuard {
+    fn new(store: Arc<IdempotencyStore>, user_id: Uuid, key: String) -> Self {
+        Self { store, user_id, key, held: true }
+    }
+
+    /// The response has been stored; the key now replays it
+    fn completed(mut self) {
+        self.held = false;
+    }
+
+    async fn release(mut self) {
+        self.held = false;
+        if let Err(e) = self.store.release(self.user_id, &self.key).await {
+            tracing::error!("Failed to release idempotency key: {:?}", e);
+        }
+    }
+}
+
+impl Drop for ClaimGuard {
+    fn drop(&mut self) {
+        if !self.held {
+            return;
+        }
+        let (store, user_id, key) = (self.store.clone(), self.user_id, std::mem::take(&mut self.key));
+        tokio::spawn(async move {
+            if let Err(e) = store.release(user_id, &key).await {
+                tracing::error!("Failed to release abandoned idempotency key: {:?}", e);
+            }
+        });
+    }
+}
+
+/// Replay the stored response when an authenticated user repeats a POST to an agent,
+/// analysis or deploy endpoint with the same `Idempotency-Key`. Must run inside
+/// `auth_middleware`, which supplies the user the key is scoped to.
+pub async fn idempotency_middleware(
+    State(store): State<Arc<IdempotencyStore>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() != Method::POST || !is_idempotent_route(request.uri().path()) {
+        return next.run(request).await;
+    }
+    let Some(key) = request.headers().get(IDEMPOTENCY_KEY_HEADER) else {
+        return next.run(request).await;
+    };
+    let key = match key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => key.to_string(),
+        _ => {
+            return AppError::ValidationError(format!(
+                "Idempotency-Key must be 1 to {} visible ASCII characters",
+                MAX_KEY_LENGTH
+            ))
+            .into_response()
+        }
+    };
+    let Some(user_id) = request.extensions().get::<Uuid>().copied() else {
+        return next.run(request).await;
+    };
+
+    let (parts, body) = request.into_parts();
+    let Ok(body) = to_bytes(body, MAX_BODY_BYTES).await else {
+        return AppError::ValidationError("Request body is too large".to_string()).into_response();
+    };
+    let request_hash = request_hash(&parts.method, parts.uri.path(), &body);
+
+    let claim = match store.claim(user_id, &key, &request_hash).await {
+        Ok(Claim::New) => ClaimGuard::new(store.clone(), user_id, key),
+        Ok(Claim::Replay(stored)) => return stored.into_response(),
+        Ok(Claim::InProgress) => {
+            return AppError::ConflictError(
+                "A request with this Idempotency-Key is still in progress".to_string(),
+            )
+            .into_response()
+        }
+        Ok(Claim::Mismatch) => {
+            return AppError::ValidationError(
+                "Idempotency-Key was already used for a different request".to_string(),
+            )
+            .into_response()
+        }
+        Err(e) => return e.into_response(),
+    };
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+
+    // Server errors aren't stored, so the client's retry gets a real second attempt
+    if response.status().is_server_error() {
+        claim.release().await;
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!("Failed to buffer response for idempotency key: {:?}", e);
+            claim.release().await;
+            return AppError::InternalServerError("Failed to read response".to_string()).into_response();
+        }
+    };
+    let content_type = parts.headers.get(CONTENT_TYPE).and_then(|value| value.to_str().ok());
+    match store.complete(user_id, &claim.key, parts.status, content_type, &body).await {
+        Ok(()) => claim.completed(),
+        Err(e) => {
+            tracing::error!("Failed to store response for idempotency key: {:?}", e);
+            claim.release().await;
+        }
+    }
+
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// Agent runs, analyses and deploys; streamed analysis can't be buffered and replayed
+fn is_idempotent_route(path: &str) -> bool {
+    if path.ends_with("/stream") {
+        return false;
+    }
+    path.starts_with("/api/agents/")
+        || path.starts_with("/api/analysis/")
+        || (path.starts_with("/api/projects/") && path.ends_with("/deploy"))
+}
+
+fn request_hash(method: &Method, path: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_str().as_bytes());
+    hasher.update(b" ");
+    hasher.update(path.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(body);
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::agents;
+    use crate::services::agent::AgentQueue;
+    use crate::services::events::EventBus;
+    use axum::{extract::Extension, middleware, routing::post, Router};
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_idempotent_routes() {
+        assert!(is_idempotent_route("/api/agents/backend"));
+        assert!(is_idempotent_route("/api/analysis/review"));
+        assert!(is_idempotent_route(&format!("/api/projects/{}/deploy", Uuid::new_v4())));
+        assert!(!is_idempotent_route("/api/analysis/optimize/stream"));
+        assert!(!is_idempotent_route("/api/projects"));
+        assert!(!is_idempotent_route("/api/auth/login"));
+    }
+
+    #[test]
+    fn test_request_hash_covers_path_and_body() {
+        let hash = request_hash(&Method::POST, "/api/agents/qa", b"{}");
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, request_hash(&Method::POST, "/api/agents/qa", b"{}"));
+        assert_ne!(hash, request_hash(&Method::POST, "/api/agents/backend", b"{}"));
+        assert_ne!(hash, request_hash(&Method::POST, "/api/agents/qa", b"{\"a\":1}"));
+    }
+
+    async fn insert_user_and_project(db: &Database) -> (Uuid, Uuid) {
+        let user_id = crate::db::insert_test_user(db.pool()).await;
+        let project_id = crate::db::insert_test_project(db.pool(), user_id, "idempotency").await;
+        (user_id, project_id)
+    }
+
+    /// The agent routes behind the idempotency layer, with the caller already authenticated
+    fn app(db: Arc<Database>, user_id: Uuid) -> Router {
+        let store = Arc::new(IdempotencyStore::new(db.clone(), Duration::from_secs(60)));
+        Router::new()
+            .route("/api/agents/backend", post(agents::backend_agent))
+            .layer(middleware::from_fn_with_state(store, idempotency_middleware))
+            .layer(middleware::from_fn(move |mut request: Request, next: Next| async move {
+                request.extensions_mut().insert(user_id);
+                next.run(request).await
+            }))
+            .layer(Extension(Arc::new(AgentQueue::new(1))))
+            .layer(Extension(Arc::new(EventBus::new(16))))
+            .with_state(db)
+    }
+
+    /// Post a backend agent request; returns whether it was replayed and the task it refers to
+    async fn run_agent(app: &Router, project_id: Uuid, key: &str) -> (bool, Uuid) {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/api/agents/backend")
+            .header(CONTENT_TYPE, "application/json")
+            .header(IDEMPOTENCY_KEY_HEADER, key)
+            .body(Body::from(
+                serde_json::json!({ "project_id": project_id, "task_description": "Add a health endpoint" })
+                    .to_string(),
+            ))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let replayed = response.headers().contains_key(IDEMPOTENT_REPLAYED_HEADER);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let task: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        (replayed, task["task_id"].as_str().unwrap().parse().unwrap())
+    }
+
+    async fn count_tasks(db: &Database, project_id: Uuid) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM agent_tasks WHERE project_id = $1")
+            .bind(project_id)
+            .fetch_one(db.pool())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_repeated_key_creates_one_agent_task() {
+        let db = crate::db::test_database().await;
+        let (user_id, project_id) = insert_user_and_project(&db).await;
+        let app = app(db.clone(), user_id);
+
+        let (replayed, first) = run_agent(&app, project_id, "retry-me").await;
+        assert!(!replayed);
+        let (replayed, second) = run_agent(&app, project_id, "retry-me").await;
+        assert!(replayed);
+        assert_eq!(first, second);
+        assert_eq!(count_tasks(&db, project_id).await, 1);
+
+        // A new key is a new request
+        let (_, third) = run_agent(&app, project_id, "another-key").await;
+        assert_ne!(third, first);
+        assert_eq!(count_tasks(&db, project_id).await, 2);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_keys_are_scoped_per_user_and_request() {
+        let db = crate::db::test_database().await;
+        let (user_id, project_id) = insert_user_and_project(&db).await;
+        let (other_user_id, other_project_id) = insert_user_and_project(&db).await;
+
+        let (_, first) = run_agent(&app(db.clone(), user_id), project_id, "shared-key").await;
+        let (replayed, other) = run_agent(&app(db.clone(), other_user_id), other_project_id, "shared-key").await;
+        assert!(!replayed);
+        assert_ne!(first, other);
+
+        // Reusing a key for a different body is refused rather than replayed
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/api/agents/backend")
+            .header(CONTENT_TYPE, "application/json")
+            .header(IDEMPOTENCY_KEY_HEADER, "shared-key")
+            .body(Body::from(
+                serde_json::json!({ "project_id": project_id, "task_description": "Something else" })
+                    .to_string(),
+            ))
+            .unwrap();
+        let response = app(db.clone(), user_id).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(count_tasks(&db, project_id).await, 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_abandoned_claims_stop_blocking_retries() {
+        let db = crate::db::test_database().await;
+        let (user_id, _) = insert_user_and_project(&db).await;
+        let store = Arc::new(IdempotencyStore::new(db.clone(), Duration::from_secs(60)));
+        let hash = request_hash(&Method::POST, "/api/agents/qa", b"{}");
+
+        // A request dropped mid-flight gives its key back
+        assert!(matches!(store.claim(user_id, "dropped", &hash).await.unwrap(), Claim::New));
+        assert!(matches!(store.claim(user_id, "dropped", &hash).await.unwrap(), Claim::InProgress));
+        drop(ClaimGuard::new(store.clone(), user_id, "dropped".to_string()));
+        let mut reclaimed = false;
+        for _ in 0..50 {
+            if matches!(store.claim(user_id, "dropped", &hash).await.unwrap(), Claim::New) {
+                reclaimed = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(reclaimed);
+
+        // One whose server died is reclaimable once its lease runs out
+        assert!(matches!(store.claim(user_id, "crashed", &hash).await.unwrap(), Claim::New));
+        sqlx::query("UPDATE idempotency_keys SET locked_until = NOW() - INTERVAL '1 second' WHERE user_id = $1 AND idempotency_key = 'crashed'")
+            .bind(user_id)
+            .execute(db.pool())
+            .await
+            .unwrap();
+        assert!(matches!(store.claim(user_id, "crashed", &hash).await.unwrap(), Claim::New));
+
+        // Completed keys are replayed whatever their lease says
+        store.complete(user_id, "crashed", StatusCode::OK, None, b"done").await.unwrap();
+        sqlx::query("UPDATE idempotency_keys SET locked_until = NOW() - INTERVAL '1 second' WHERE user_id = $1 AND idempotency_key = 'crashed'")
+            .bind(user_id)
+            .execute(db.pool())
+            .await
+            .unwrap();
+        assert!(matches!(store.claim(user_id, "crashed", &hash).await.unwrap(), Claim::Replay(_)));
+    }
+}
@@ -1,7 +1,10 @@
+pub mod cors;
+pub mod idempotency;
+pub mod rate_limit;
 pub mod rbac;
+pub mod request_id;
 
-pub use rbac::{
-    rbac_middleware, check_project_permission, check_team_role,
-    check_project_admin, get_user_project_role, enforce_permission,
-    enforce_role, can_modify_review, can_comment_on_review,
-};
+pub use cors::CorsSettings;
+pub use idempotency::{idempotency_middleware, IdempotencyStore};
+pub use rate_limit::{rate_limit_middleware, RateLimits};
+pub use request_id::request_id_middleware;
@@ -0,0 +1,383 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header::RETRY_AFTER, Extensions, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use dashmap::DashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::error::AppError;
+
+/// Token bucket per client IP: holds up to `requests_per_minute` tokens and refills
+/// continuously, so short bursts are allowed but the sustained rate is capped
+pub struct RateLimiter {
+    requests_per_minute: u32,
+    buckets: DashMap<IpAddr, Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    /// A limit of 0 disables limiting for the group
+    pub fn new(requests_per_minute: u32) -> Self {
+        Self {
+            requests_per_minute,
+            buckets: DashMap::new(),
+        }
+    }
+
+    /// Take a token for `ip`, or say how long until one is available
+    pub fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        self.check_at(ip, Instant::now())
+    }
+
+    fn check_at(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        if self.requests_per_minute == 0 {
+            return Ok(());
+        }
+
+        let capacity = f64::from(self.requests_per_minute);
+        let per_second = capacity / 60.0;
+        let mut bucket = self.buckets.entry(ip).or_insert(Bucket {
+            tokens: capacity,
+            last_refill: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+        }
+    }
+
+    /// Drop buckets that have refilled completely; they behave exactly like a new client
+    pub fn purge_idle(&self) -> usize {
+        let before = self.buckets.len();
+        self.buckets
+            .retain(|_, bucket| bucket.last_refill.elapsed() < Duration::from_secs(60));
+        before - self.buckets.len()
+    }
+}
+
+/// The reverse proxies allowed to say who the client is, as addresses or CIDR ranges.
+/// `X-Forwarded-For` is ignored on connections from anywhere else, since any client can
+/// send one.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Vec<(IpAddr, u8)>);
+
+impl TrustedProxies {
+    pub fn parse(entries: &[String]) -> anyhow::Result<Self> {
+        entries
+            .iter()
+            .map(|entry| {
+                let (addr, prefix) = match entry.split_once('/') {
+                    Some((addr, prefix)) => (addr, Some(prefix)),
+                    None => (entry.as_str(), None),
+                };
+                let addr: IpAddr = addr
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid trusted proxy address: {}", entry))?;
+                let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+                let prefix = match prefix {
+                    Some(prefix) => prefix
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|prefix| *prefix <= max_prefix)
+                        .ok_or_else(|| anyhow::anyhow!("Invalid trusted proxy range: {}", entry))?,
+                    None => max_prefix,
+                };
+                Ok((addr, prefix))
+            })
+            .collect::<anyhow::Result<_>>()
+            .map(TrustedProxies)
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.0.iter().any(|&(network, prefix)| match (network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        })
+    }
+
+    /// The address of the client behind any trusted proxies. Starting from the peer,
+    /// `X-Forwarded-For` is read right to left for as long as the hop that added each
+    /// entry is trusted; the first address that isn't a trusted proxy is the client.
+    /// Entries further left were written by the client itself and are never believed.
+    pub fn client_ip(&self, headers: &HeaderMap, peer: IpAddr) -> IpAddr {
+        let forwarded: Vec<&str> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+
+        let mut client = peer;
+        for hop in forwarded.into_iter().rev() {
+            if !self.contains(client) {
+                break;
+            }
+            // Past a malformed entry, nothing further left can be attributed to a proxy
+            match hop.trim().parse() {
+                Ok(ip) => client = ip,
+                Err(_) => break,
+            }
+        }
+        client
+    }
+}
+
+/// The client address worked out by `rate_limit_middleware`, for handlers that record it
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+/// Separate limits for the public auth endpoints, the AI-backed analysis endpoints,
+/// and everything else; each group counts requests independently
+pub struct RateLimits {
+    pub default: RateLimiter,
+    pub auth: RateLimiter,
+    pub analysis: RateLimiter,
+    pub trusted_proxies: TrustedProxies,
+}
+
+impl RateLimits {
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        Ok(Self {
+            default: RateLimiter::new(config.rate_limit_rpm),
+            auth: RateLimiter::new(config.rate_limit_auth_rpm),
+            analysis: RateLimiter::new(config.rate_limit_analysis_rpm),
+            trusted_proxies: TrustedProxies::parse(&config.trusted_proxies)?,
+        })
+    }
+
+    fn for_path(&self, path: &str) -> &RateLimiter {
+        if path.starts_with("/api/auth/") {
+            &self.auth
+        } else if path.starts_with("/api/analysis/") {
+            &self.analysis
+        } else {
+            &self.default
+        }
+    }
+
+    pub fn purge_idle(&self) -> usize {
+        self.default.purge_idle() + self.auth.purge_idle() + self.analysis.purge_idle()
+    }
+}
+
+pub async fn rate_limit_middleware(
+    State(limits): State<Arc<RateLimits>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let ip = peer
+        .map(|ConnectInfo(peer)| limits.trusted_proxies.client_ip(request.headers(), peer.ip()))
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    request.extensions_mut().insert(ClientIp(ip));
+
+    let path = request.uri().path();
+    if is_exempt_route(path) {
+        return next.run(request).await;
+    }
+
+    if let Err(retry_after) = limits.for_path(path).check(ip) {
+        tracing::warn!("Rate limit exceeded for {} on {}", ip, path);
+        let mut response =
+            AppError::RateLimitError("Too many requests, slow down".to_string()).into_response();
+        let seconds = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(seconds));
+        return response;
+    }
+
+    next.run(request).await
+}
+
+/// Probes must keep answering however busy a client is
+fn is_exempt_route(path: &str) -> bool {
+    matches!(path, "/health" | "/livez" | "/readyz")
+}
+
+fn peer_ip(extensions: &Extensions) -> Option<IpAddr> {
+    extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+}
+
+/// The client address `rate_limit_middleware` resolved, otherwise the peer address
+pub(crate) fn request_ip(extensions: &Extensions) -> Option<IpAddr> {
+    extensions
+        .get::<ClientIp>()
+        .map(|ClientIp(ip)| *ip)
+        .or_else(|| peer_ip(extensions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorResponse;
+    use axum::{
+        body::{to_bytes, Body},
+        extract::connect_info::MockConnectInfo,
+        http::StatusCode,
+        middleware,
+        routing::{get, post},
+        Router,
+    };
+    use tower::ServiceExt;
+
+    /// Served as if behind a load balancer at 10.0.0.1
+    fn app(limits: RateLimits) -> Router {
+        Router::new()
+            .route("/health", get(|| async { "ok" }))
+            .route("/api/projects", get(|| async { "ok" }))
+            .route("/api/auth/login", post(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(Arc::new(limits), rate_limit_middleware))
+            .layer(MockConnectInfo(SocketAddr::from(([10, 0, 0, 1], 4000))))
+    }
+
+    fn proxies(entries: &[&str]) -> TrustedProxies {
+        TrustedProxies::parse(&entries.iter().map(|entry| entry.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    async fn send(app: &Router, method: &str, uri: &str, ip: &str) -> Response {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("x-forwarded-for", ip)
+            .body(Body::empty())
+            .unwrap();
+        app.clone().oneshot(request).await.unwrap()
+    }
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let limiter = RateLimiter::new(60);
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let start = Instant::now();
+
+        for _ in 0..60 {
+            assert!(limiter.check_at(ip, start).is_ok());
+        }
+        let retry_after = limiter.check_at(ip, start).unwrap_err();
+        assert!(retry_after <= Duration::from_secs(1));
+
+        // One token a second at 60 per minute
+        assert!(limiter.check_at(ip, start + Duration::from_secs(1)).is_ok());
+        assert!(limiter.check_at(ip, start + Duration::from_secs(1)).is_err());
+
+        assert!(RateLimiter::new(0).check_at(ip, start).is_ok());
+    }
+
+    #[test]
+    fn test_forwarded_for_is_only_believed_from_trusted_proxies() {
+        let trusted = proxies(&["10.0.0.0/8", "2001:db8::1"]);
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let client_ip = |trusted: &TrustedProxies, forwarded: &str, peer: IpAddr| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-forwarded-for", HeaderValue::from_str(forwarded).unwrap());
+            trusted.client_ip(&headers, peer).to_string()
+        };
+
+        // The right-most hop that isn't one of our proxies; what the client wrote is ignored
+        assert_eq!(client_ip(&trusted, "198.51.100.66, 203.0.113.7", proxy), "203.0.113.7");
+        assert_eq!(client_ip(&trusted, "203.0.113.7, 10.1.2.3", proxy), "203.0.113.7");
+        assert_eq!(client_ip(&trusted, "203.0.113.7", "2001:db8::1".parse().unwrap()), "203.0.113.7");
+        assert_eq!(client_ip(&trusted, "10.0.0.9, 10.1.2.3", proxy), "10.0.0.9");
+        assert_eq!(client_ip(&trusted, "203.0.113.7, not-an-ip, 10.1.2.3", proxy), "10.1.2.3");
+        assert_eq!(trusted.client_ip(&HeaderMap::new(), proxy), proxy);
+
+        // Anyone else can put anything in the header
+        assert_eq!(client_ip(&trusted, "203.0.113.7", "192.0.2.9".parse().unwrap()), "192.0.2.9");
+        assert_eq!(client_ip(&TrustedProxies::default(), "203.0.113.7", proxy), "10.0.0.1");
+    }
+
+    #[test]
+    fn test_trusted_proxies_parse() {
+        let trusted = proxies(&["192.0.2.0/24", "::ffff:198.51.100.1"]);
+        assert!(trusted.contains("192.0.2.200".parse().unwrap()));
+        assert!(!trusted.contains("192.0.3.1".parse().unwrap()));
+        assert!(trusted.contains("::ffff:192.0.2.1".parse().unwrap()));
+        assert!(proxies(&["0.0.0.0/0"]).contains("203.0.113.7".parse().unwrap()));
+
+        for invalid in ["10.0.0.0/33", "fd00::/129", "proxy.internal", "10.0.0.0/"] {
+            assert!(TrustedProxies::parse(&[invalid.to_string()]).is_err(), "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_past_the_limit_gets_429() {
+        let app = app(RateLimits {
+            default: RateLimiter::new(3),
+            auth: RateLimiter::new(1),
+            analysis: RateLimiter::new(0),
+            trusted_proxies: proxies(&["10.0.0.1"]),
+        });
+
+        for _ in 0..3 {
+            let response = send(&app, "GET", "/api/projects", "198.51.100.1").await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = send(&app, "GET", "/api/projects", "198.51.100.1").await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = response.headers()[RETRY_AFTER].to_str().unwrap().parse().unwrap();
+        assert!((1..=20).contains(&retry_after));
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let error: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error.code, "RATE_LIMIT_ERROR");
+
+        // Other clients, other route groups and the health check are unaffected
+        let response = send(&app, "GET", "/api/projects", "198.51.100.2").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send(&app, "POST", "/api/auth/login", "198.51.100.1").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send(&app, "POST", "/api/auth/login", "198.51.100.1").await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        for _ in 0..5 {
+            let response = send(&app, "GET", "/health", "198.51.100.1").await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_socket_address_is_used_without_a_trusted_proxy() {
+        let app = app(RateLimits {
+            default: RateLimiter::new(1),
+            auth: RateLimiter::new(1),
+            analysis: RateLimiter::new(1),
+            trusted_proxies: TrustedProxies::default(),
+        });
+        let get_projects = || Request::builder().uri("/api/projects").body(Body::empty()).unwrap();
+
+        let response = app.clone().oneshot(get_projects()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.clone().oneshot(get_projects()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // Making up a new X-Forwarded-For doesn't buy a fresh bucket
+        let response = send(&app, "GET", "/api/projects", "198.51.100.99").await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+}
@@ -1,63 +1,48 @@
-use axum::{
-    middleware::Next,
-    http::{Request, StatusCode},
-    response::IntoResponse,
-    Json,
-};
-use serde_json::json;
 use uuid::Uuid;
 use sqlx::Pool;
 use sqlx::Postgres;
 
-use crate::error::ApiError;
+use crate::error::{AppError, AppResult};
+use crate::models::collaboration::TeamRole;
+use crate::models::inheritance::ResolvedPermissions;
+use crate::services::InheritanceEngine;
 
-/// RBAC middleware for enforcing role-based access control
-pub async fn rbac_middleware<B>(
-    request: Request<B>,
-    next: Next,
-) -> Result<impl IntoResponse, ApiError> {
-    // Extract user from JWT claims
-    let user_id = request
-        .extensions()
-        .get::<Uuid>()
-        .copied()
-        .ok_or(ApiError::Unauthorized)?;
-
-    // User ID added to extensions, continue
-    Ok(next.run(request).await)
-}
-
-/// Check if user has specific permission on project
+/// Check if user has specific permission on project. Owners hold every permission on
+/// their projects without being listed as members. Nobody has any on a trashed project.
 pub async fn check_project_permission(
     pool: &Pool<Postgres>,
     user_id: Uuid,
     project_id: Uuid,
     required_permission: &str,
-) -> Result<bool, ApiError> {
-    let result = sqlx::query_scalar::<_, Vec<String>>(
+) -> AppResult<bool> {
+    let allowed = sqlx::query_scalar::<_, bool>(
         r#"
-        SELECT permissions FROM project_members
-        WHERE user_id = $1 AND project_id = $2
+        SELECT EXISTS(
+            SELECT 1 FROM projects p
+            WHERE p.id = $2 AND p.deleted_at IS NULL
+              AND (p.user_id = $1 OR EXISTS(
+                  SELECT 1 FROM project_members
+                  WHERE user_id = $1 AND project_id = p.id AND $3 = ANY(permissions)
+              ))
+        )
         "#,
     )
     .bind(user_id)
     .bind(project_id)
-    .fetch_optional(pool)
+    .bind(required_permission)
+    .fetch_one(pool)
     .await?;
 
-    match result {
-        Some(permissions) => Ok(permissions.contains(&required_permission.to_string())),
-        None => Ok(false),
-    }
+    Ok(allowed)
 }
 
-/// Check if user has specific role in team
-pub async fn check_team_role(
+/// The user's role level in a team (see `TeamRole::hierarchy_level`), or 0 if they aren't
+/// a member
+pub async fn team_role_level(
     pool: &Pool<Postgres>,
     user_id: Uuid,
     team_id: Uuid,
-    min_role_level: i32,
-) -> Result<bool, ApiError> {
+) -> AppResult<i32> {
     let role = sqlx::query_scalar::<_, String>(
         r#"
         SELECT role FROM team_members
@@ -69,28 +54,30 @@ pub async fn check_team_role(
     .fetch_optional(pool)
     .await?;
 
-    if let Some(role_str) = role {
-        let role_level = match role_str.as_str() {
-            "owner" => 4,
-            "admin" => 3,
-            "member" => 2,
-            "viewer" => 1,
-            _ => 0,
-        };
-        Ok(role_level >= min_role_level)
-    } else {
-        Ok(false)
-    }
+    Ok(role
+        .as_deref()
+        .and_then(TeamRole::parse)
+        .map_or(0, |role| role.hierarchy_level()))
+}
+
+/// Check if user has specific role in team
+pub async fn check_team_role(
+    pool: &Pool<Postgres>,
+    user_id: Uuid,
+    team_id: Uuid,
+    min_role_level: i32,
+) -> AppResult<bool> {
+    Ok(team_role_level(pool, user_id, team_id).await? >= min_role_level)
 }
 
-/// Verify user is project owner or admin
+/// Verify user owns the project, which must not be in the trash
 pub async fn check_project_admin(
     pool: &Pool<Postgres>,
     user_id: Uuid,
     project_id: Uuid,
-) -> Result<bool, ApiError> {
+) -> AppResult<bool> {
     let project_owner = sqlx::query_scalar::<_, Uuid>(
-        "SELECT user_id FROM projects WHERE id = $1"
+        "SELECT user_id FROM projects WHERE id = $1 AND deleted_at IS NULL"
     )
     .bind(project_id)
     .fetch_optional(pool)
@@ -107,7 +94,7 @@ pub async fn get_user_project_role(
     pool: &Pool<Postgres>,
     user_id: Uuid,
     project_id: Uuid,
-) -> Result<Option<String>, ApiError> {
+) -> AppResult<Option<String>> {
     let role = sqlx::query_scalar::<_, String>(
         r#"
         SELECT role FROM project_members
@@ -122,44 +109,242 @@ pub async fn get_user_project_role(
     Ok(role)
 }
 
-/// Enforce permission check - returns 403 if unauthorized
+/// Error for a failed access check. Users who can't see a resource at all get 404, so
+/// probing IDs doesn't reveal which ones exist; 403 is only for users who can see the
+/// resource but lack the right for this particular operation.
+pub fn access_denied(visible: bool) -> AppError {
+    if visible {
+        AppError::AuthorizationError("You don't have permission to do this".to_string())
+    } else {
+        AppError::NotFoundError("Resource not found".to_string())
+    }
+}
+
+/// Check if user can see the project at all: its owner or a member with any permissions,
+/// as long as it isn't in the trash
+pub async fn can_view_project(
+    pool: &Pool<Postgres>,
+    user_id: Uuid,
+    project_id: Uuid,
+) -> AppResult<bool> {
+    let visible = sqlx::query_scalar::<_, bool>(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM projects p
+            WHERE p.id = $2 AND p.deleted_at IS NULL
+              AND (p.user_id = $1 OR EXISTS(SELECT 1 FROM project_members WHERE project_id = p.id AND user_id = $1))
+        )
+        "#,
+    )
+    .bind(user_id)
+    .bind(project_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(visible)
+}
+
+/// Check if user belongs to the team in any role
+pub async fn is_team_member(
+    pool: &Pool<Postgres>,
+    user_id: Uuid,
+    team_id: Uuid,
+) -> AppResult<bool> {
+    let is_member = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM team_members WHERE team_id = $1 AND user_id = $2)"
+    )
+    .bind(team_id)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(is_member)
+}
+
+/// Enforce project visibility - returns 404 unless the user owns or belongs to the project
+pub async fn enforce_project_visible(
+    pool: &Pool<Postgres>,
+    user_id: Uuid,
+    project_id: Uuid,
+) -> AppResult<()> {
+    if !can_view_project(pool, user_id, project_id).await? {
+        return Err(access_denied(false));
+    }
+
+    Ok(())
+}
+
+/// Enforce team membership - returns 404 for anyone outside the team
+pub async fn enforce_team_member(
+    pool: &Pool<Postgres>,
+    user_id: Uuid,
+    team_id: Uuid,
+) -> AppResult<()> {
+    if !is_team_member(pool, user_id, team_id).await? {
+        return Err(access_denied(false));
+    }
+
+    Ok(())
+}
+
+/// Enforce permission check - returns 404 if the user can't see the project, 403 if they
+/// can but lack the permission
 pub async fn enforce_permission(
     pool: &Pool<Postgres>,
     user_id: Uuid,
     project_id: Uuid,
     required_permission: &str,
-) -> Result<(), ApiError> {
+) -> AppResult<()> {
     let has_permission = check_project_permission(pool, user_id, project_id, required_permission).await?;
 
     if !has_permission {
-        return Err(ApiError::Forbidden);
+        return Err(access_denied(can_view_project(pool, user_id, project_id).await?));
     }
 
     Ok(())
 }
 
-/// Enforce role check - returns 403 if user doesn't meet minimum role level
+/// Enforce role check - returns 404 for non-members, 403 if a member doesn't meet the
+/// minimum role level
 pub async fn enforce_role(
     pool: &Pool<Postgres>,
     user_id: Uuid,
     team_id: Uuid,
     min_role_level: i32,
-) -> Result<(), ApiError> {
+) -> AppResult<()> {
     let has_role = check_team_role(pool, user_id, team_id, min_role_level).await?;
 
     if !has_role {
-        return Err(ApiError::Forbidden);
+        return Err(access_denied(is_team_member(pool, user_id, team_id).await?));
     }
 
     Ok(())
 }
 
+/// Permissions a team role carries before any permission rules are applied
+fn default_team_permissions(role: &str) -> &'static [&'static str] {
+    match role {
+        "owner" => &["read", "write", "admin", "delete", "invite", "manage_roles", "view_audit"],
+        "admin" => &["read", "write", "admin", "invite", "manage_roles", "view_audit"],
+        "member" => &["read", "write"],
+        "viewer" => &["read"],
+        _ => &[],
+    }
+}
+
+/// Check if user's team role grants a permission, either by default or through a
+/// permission rule defined for that role on the team
+pub async fn check_team_permission(
+    pool: &Pool<Postgres>,
+    user_id: Uuid,
+    team_id: Uuid,
+    required_permission: &str,
+) -> AppResult<bool> {
+    let role = sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT role FROM team_members
+        WHERE user_id = $1 AND team_id = $2
+        "#,
+    )
+    .bind(user_id)
+    .bind(team_id)
+    .fetch_optional(pool)
+    .await?;
+
+    let Some(role) = role else {
+        return Ok(false);
+    };
+    if default_team_permissions(&role).contains(&required_permission) {
+        return Ok(true);
+    }
+
+    let granted = sqlx::query_scalar::<_, bool>(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM permission_rules
+            WHERE team_id = $1 AND role = $2 AND permissions @> jsonb_build_array($3::text)
+        )
+        "#,
+    )
+    .bind(team_id)
+    .bind(&role)
+    .bind(required_permission)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(granted)
+}
+
+/// Enforce team permission check - returns 404 for non-members, 403 if the user's role
+/// doesn't grant it
+pub async fn enforce_team_permission(
+    pool: &Pool<Postgres>,
+    user_id: Uuid,
+    team_id: Uuid,
+    required_permission: &str,
+) -> AppResult<()> {
+    if !check_team_permission(pool, user_id, team_id, required_permission).await? {
+        return Err(access_denied(is_team_member(pool, user_id, team_id).await?));
+    }
+
+    Ok(())
+}
+
+/// Resolve a user's effective permissions on a project or team: direct, inherited from
+/// parent resources, and granted by permission rules
+pub async fn get_resolved_permissions(
+    engine: &InheritanceEngine,
+    user_id: Uuid,
+    resource_id: Uuid,
+    resource_type: &str,
+) -> AppResult<ResolvedPermissions> {
+    if !matches!(resource_type, "project" | "team") {
+        return Err(AppError::ValidationError(format!("Invalid resource type: {}", resource_type)));
+    }
+
+    engine
+        .resolve_permissions(user_id, resource_id, resource_type)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to resolve permissions: {}", e);
+            AppError::InternalServerError("Failed to resolve permissions".to_string())
+        })
+}
+
+/// Enforce a permission the user holds directly or through inheritance - returns 404 if
+/// they can't see the resource, 403 if they can but lack the permission. Project owners
+/// hold every permission on their projects.
+pub async fn enforce_permission_with_inheritance(
+    pool: &Pool<Postgres>,
+    engine: &InheritanceEngine,
+    user_id: Uuid,
+    resource_id: Uuid,
+    resource_type: &str,
+    required_permission: &str,
+) -> AppResult<()> {
+    if resource_type == "project" && check_project_admin(pool, user_id, resource_id).await? {
+        return Ok(());
+    }
+
+    let resolved = get_resolved_permissions(engine, user_id, resource_id, resource_type).await?;
+    if resolved.effective_permissions.iter().any(|p| p == required_permission) {
+        return Ok(());
+    }
+
+    let visible = !resolved.effective_permissions.is_empty()
+        || match resource_type {
+            "project" => can_view_project(pool, user_id, resource_id).await?,
+            _ => is_team_member(pool, user_id, resource_id).await?,
+        };
+    Err(access_denied(visible))
+}
+
 /// Check if user can modify code review
 pub async fn can_modify_review(
     pool: &Pool<Postgres>,
     user_id: Uuid,
     review_id: Uuid,
-) -> Result<bool, ApiError> {
+) -> AppResult<bool> {
     let author_id = sqlx::query_scalar::<_, Uuid>(
         "SELECT author_id FROM code_reviews WHERE id = $1"
     )
@@ -175,7 +360,7 @@ pub async fn can_comment_on_review(
     pool: &Pool<Postgres>,
     user_id: Uuid,
     review_id: Uuid,
-) -> Result<bool, ApiError> {
+) -> AppResult<bool> {
     let project_id = sqlx::query_scalar::<_, Uuid>(
         "SELECT project_id FROM code_reviews WHERE id = $1"
     )
@@ -201,4 +386,58 @@ mod tests {
         assert!(2 >= 1); // member >= viewer
         assert!(1 < 2);  // viewer < member
     }
+
+    #[test]
+    fn test_default_team_permissions() {
+        assert!(default_team_permissions("owner").contains(&"manage_roles"));
+        assert!(default_team_permissions("admin").contains(&"manage_roles"));
+        assert!(default_team_permissions("admin").contains(&"view_audit"));
+        assert!(!default_team_permissions("member").contains(&"manage_roles"));
+        assert!(!default_team_permissions("viewer").contains(&"view_audit"));
+        assert!(default_team_permissions("unknown").is_empty());
+    }
+
+    #[test]
+    fn test_access_denied_hides_invisible_resources() {
+        assert!(matches!(access_denied(false), AppError::NotFoundError(_)));
+        assert!(matches!(access_denied(true), AppError::AuthorizationError(_)));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_trashed_projects_grant_nothing() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool();
+        let owner_id = crate::db::insert_test_user(pool).await;
+        let member_id = crate::db::insert_test_user(pool).await;
+        let project_id = crate::db::insert_test_project(pool, owner_id, "trashed").await;
+        sqlx::query("INSERT INTO project_members (id, project_id, user_id, role, permissions) VALUES ($1, $2, $3, 'member', $4)")
+            .bind(Uuid::new_v4())
+            .bind(project_id)
+            .bind(member_id)
+            .bind(vec!["read", "write"])
+            .execute(pool)
+            .await
+            .unwrap();
+
+        assert!(check_project_admin(pool, owner_id, project_id).await.unwrap());
+        assert!(can_view_project(pool, member_id, project_id).await.unwrap());
+        assert!(check_project_permission(pool, member_id, project_id, "write").await.unwrap());
+
+        sqlx::query("UPDATE projects SET deleted_at = NOW() WHERE id = $1")
+            .bind(project_id)
+            .execute(pool)
+            .await
+            .unwrap();
+
+        assert!(!check_project_admin(pool, owner_id, project_id).await.unwrap());
+        for user_id in [owner_id, member_id] {
+            assert!(!can_view_project(pool, user_id, project_id).await.unwrap());
+            assert!(!check_project_permission(pool, user_id, project_id, "read").await.unwrap());
+        }
+        assert!(matches!(
+            enforce_project_visible(pool, owner_id, project_id).await,
+            Err(AppError::NotFoundError(_))
+        ));
+    }
 }
@@ -0,0 +1,110 @@
+use axum::{
+    extract::Request,
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+use std::time::Instant;
+use tracing::Instrument;
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static REQUEST_ID: Uuid;
+}
+
+/// The ID of the request being handled, if called from inside `request_id_middleware`
+pub fn current_request_id() -> Option<Uuid> {
+    REQUEST_ID.try_with(|id| *id).ok()
+}
+
+/// Tag each request with a fresh ID: every log line it produces is inside a span
+/// carrying the ID, method and path, error bodies include it, and the client gets
+/// it back in `x-request-id` to quote in bug reports
+pub async fn request_id_middleware(request: Request, next: Next) -> Response {
+    let request_id = Uuid::new_v4();
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %request.method(),
+        path = %request.uri().path(),
+        status = tracing::field::Empty,
+    );
+
+    let started = Instant::now();
+    let mut response = REQUEST_ID
+        .scope(request_id, next.run(request))
+        .instrument(span.clone())
+        .await;
+
+    span.record("status", response.status().as_u16());
+    span.in_scope(|| {
+        tracing::info!(latency_ms = started.elapsed().as_millis() as u64, "request completed");
+    });
+
+    if let Ok(value) = HeaderValue::from_str(&request_id.to_string()) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{AppError, ErrorResponse};
+    use axum::{
+        body::{to_bytes, Body},
+        http::StatusCode,
+        middleware,
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        Router::new()
+            .route("/ok", get(|| async { "ok" }))
+            .route(
+                "/missing",
+                get(|| async { Err::<&str, _>(AppError::NotFoundError("Nothing here".to_string())) }),
+            )
+            .layer(middleware::from_fn(request_id_middleware))
+    }
+
+    async fn get_response(uri: &str) -> Response {
+        let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        app().oneshot(request).await.unwrap()
+    }
+
+    fn request_id(response: &Response) -> Uuid {
+        response.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_responses_carry_a_request_id() {
+        let first = get_response("/ok").await;
+        let second = get_response("/ok").await;
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_ne!(request_id(&first), request_id(&second));
+    }
+
+    #[tokio::test]
+    async fn test_error_body_includes_the_request_id() {
+        let response = get_response("/missing").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let header_id = request_id(&response);
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let error: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error.code, "NOT_FOUND_ERROR");
+        assert_eq!(error.request_id, Some(header_id));
+
+        // Outside a request there's nothing to attach
+        assert_eq!(current_request_id(), None);
+    }
+}
@@ -1,19 +1,44 @@
 use axum::{
-    body::Body,
-    extract::Request,
+    async_trait,
+    extract::{FromRequestParts, Request, State},
+    http::{header::AUTHORIZATION, request::Parts},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
 };
-use jsonwebtoken::{decode, DecodingKey, Validation};
-use serde::{Deserialize, Serialize};
+use jsonwebtoken::errors::ErrorKind;
+use sqlx::{PgPool, Row};
+use std::sync::Arc;
+use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Claims {
-    pub sub: String,
-    pub exp: usize,
+use crate::{
+    db::Database,
+    error::AppError,
+    utils::jwt::{self, Claims, TokenType},
+};
+
+/// Authenticated user ID, read from the request extensions populated by `auth_middleware`
+#[derive(Debug, Clone, Copy)]
+pub struct AuthUser(pub Uuid);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Uuid>()
+            .copied()
+            .map(AuthUser)
+            .ok_or_else(|| AppError::AuthenticationError("Not authenticated".to_string()))
+    }
 }
 
 pub async fn auth_middleware(
+    State(db): State<Arc<Database>>,
     mut request: Request,
     next: Next,
 ) -> Response {
@@ -24,40 +49,208 @@ pub async fn auth_middleware(
     }
 
     // Extract authorization header
-    let auth_header = request
+    let Some(auth_header) = request
         .headers()
-        .get("Authorization")
-        .and_then(|h| h.to_str().ok());
-
-    if let Some(auth_header) = auth_header {
-        if let Some(token) = auth_header.strip_prefix("Bearer ") {
-            // Validate token (simplified - should use actual JWT secret)
-            if validate_token(token).is_ok() {
-                return next.run(request).await;
-            }
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+    else {
+        return unauthorized("Missing Authorization header");
+    };
+
+    let Some(token) = auth_header.strip_prefix("Bearer ") else {
+        return unauthorized("Malformed Bearer token");
+    };
+
+    // Validate with the same secret and algorithm the tokens were signed with
+    let jwt_config = match jwt::config() {
+        Ok(config) => config,
+        Err(e) => return e.into_response(),
+    };
+    let claims = match jwt_config.decode(token) {
+        Ok(claims) => claims,
+        Err(e) if matches!(e.kind(), ErrorKind::ExpiredSignature) => {
+            return unauthorized("Token has expired");
         }
+        Err(_) => return unauthorized("Invalid token"),
+    };
+    if claims.typ != TokenType::Access {
+        return unauthorized("Invalid token");
+    }
+
+    // Reject tokens invalidated by logout
+    match is_token_revoked(db.pool(), &claims.jti).await {
+        Ok(false) => {}
+        Ok(true) => return unauthorized("Token has been revoked"),
+        Err(e) => return AppError::from(e).into_response(),
     }
 
-    Response::builder()
-        .status(401)
-        .body(Body::from("Unauthorized"))
-        .unwrap()
+    // Expose the caller to handlers and the RBAC layer
+    let Some(user_id) = user_id_from_claims(&claims) else {
+        return unauthorized("Invalid token subject");
+    };
+    request.extensions_mut().insert(user_id);
+
+    next.run(request).await
+}
+
+/// 401 response carrying the same `ErrorResponse` body as `AppError`
+fn unauthorized(message: &str) -> Response {
+    AppError::AuthenticationError(message.to_string()).into_response()
 }
 
 fn is_public_route(path: &str) -> bool {
     matches!(
         path,
-        "/health" | "/auth/register" | "/auth/login" | "/auth/refresh"
+        "/health"
+            | "/livez"
+            | "/readyz"
+            | "/api/auth/register"
+            | "/api/auth/login"
+            | "/api/auth/refresh"
+            | "/api/auth/forgot-password"
+            | "/api/auth/reset-password"
+            | "/api/auth/verify"
+            | "/api/auth/oauth/github"
+            | "/api/auth/oauth/github/callback"
+            | "/api/auth/2fa/challenge"
     )
 }
 
-fn validate_token(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
-    // This is a simplified version - in production, use your actual JWT secret
-    let secret = std::env::var("JWT_SECRET").unwrap_or_default();
-    decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &Validation::default(),
-    )
-    .map(|data| data.claims)
+fn user_id_from_claims(claims: &Claims) -> Option<Uuid> {
+    Uuid::parse_str(&claims.sub).ok()
+}
+
+async fn is_token_revoked(pool: &PgPool, jti: &str) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query("SELECT EXISTS(SELECT 1 FROM revoked_tokens WHERE jti = $1) AS revoked")
+        .bind(jti)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(row.get("revoked"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorResponse;
+    use axum::{
+        body::{to_bytes, Body},
+        http::{header::CONTENT_TYPE, Request as HttpRequest, StatusCode},
+        middleware,
+        routing::{get, post},
+        Router,
+    };
+    use sqlx::postgres::PgPoolOptions;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_user_id_from_claims() {
+        let user_id = Uuid::new_v4();
+        let claims = Claims {
+            sub: user_id.to_string(),
+            exp: 0,
+            iat: 0,
+            jti: Uuid::new_v4().to_string(),
+            typ: TokenType::Access,
+        };
+        assert_eq!(user_id_from_claims(&claims), Some(user_id));
+
+        let claims = Claims {
+            sub: "not-a-uuid".to_string(),
+            exp: 0,
+            iat: 0,
+            jti: Uuid::new_v4().to_string(),
+            typ: TokenType::Access,
+        };
+        assert_eq!(user_id_from_claims(&claims), None);
+    }
+
+    async fn call_protected(auth_header: Option<&str>) -> (StatusCode, ErrorResponse) {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        // These requests are rejected before the revocation lookup, so the pool never connects
+        let pool = PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/unused")
+            .unwrap();
+        let db = Arc::new(Database::from_pool(pool));
+        let app = Router::new()
+            .route("/projects", get(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(db, auth_middleware));
+
+        let mut request = HttpRequest::builder().uri("/projects");
+        if let Some(value) = auth_header {
+            request = request.header(AUTHORIZATION, value);
+        }
+
+        let response = app
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_unauthorized_responses_are_error_responses() {
+        let (status, error) = call_protected(None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(error.code, "AUTHENTICATION_ERROR");
+        assert_eq!(error.message, "Missing Authorization header");
+
+        let (status, error) = call_protected(Some("Token abc")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(error.message, "Malformed Bearer token");
+
+        let (status, error) = call_protected(Some("Bearer not.a.jwt")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(error.message, "Invalid token");
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token_is_not_an_access_token() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        let refresh_token = jwt::generate_refresh_token(&Uuid::new_v4().to_string(), 3600).unwrap();
+
+        let (status, error) = call_protected(Some(&format!("Bearer {}", refresh_token))).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(error.message, "Invalid token");
+    }
+
+    async fn send(app: &Router, method: &str, uri: &str, token: &str) -> StatusCode {
+        let request = HttpRequest::builder()
+            .method(method)
+            .uri(uri)
+            .header(AUTHORIZATION, format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap();
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_logged_out_token_is_rejected() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        let db = crate::db::test_database().await;
+
+        let user_id = crate::db::insert_test_user(db.pool()).await;
+
+        let (token, _) = crate::utils::jwt::generate_access_token(&user_id.to_string()).unwrap();
+        let app = Router::new()
+            .route("/projects", get(|| async { "ok" }))
+            .route("/auth/logout", post(crate::handlers::auth::logout))
+            .layer(middleware::from_fn_with_state(db.clone(), auth_middleware))
+            .with_state(db);
+
+        assert_eq!(send(&app, "GET", "/projects", &token).await, StatusCode::OK);
+        assert_eq!(send(&app, "POST", "/auth/logout", &token).await, StatusCode::OK);
+        assert_eq!(
+            send(&app, "GET", "/projects", &token).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
 }
@@ -2,6 +2,8 @@ use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
+use crate::utils::pagination::Page;
+
 // ============ Team & RBAC Models ============
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -23,6 +25,16 @@ impl TeamRole {
         }
     }
 
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "owner" => Some(TeamRole::Owner),
+            "admin" => Some(TeamRole::Admin),
+            "member" => Some(TeamRole::Member),
+            "viewer" => Some(TeamRole::Viewer),
+            _ => None,
+        }
+    }
+
     pub fn hierarchy_level(&self) -> i32 {
         match self {
             TeamRole::Owner => 4,
@@ -33,7 +45,7 @@ impl TeamRole {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Team {
     pub id: Uuid,
     pub owner_id: Uuid,
@@ -44,7 +56,7 @@ pub struct Team {
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct TeamMember {
     pub id: Uuid,
     pub team_id: Uuid,
@@ -76,6 +88,55 @@ pub struct UpdateTeamMemberRequest {
     pub role: String,
 }
 
+/// An invitation to join a team, addressed by email. The token itself is only ever emailed.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct TeamInvitation {
+    pub id: Uuid,
+    pub team_id: Uuid,
+    pub email: String,
+    pub role: String,
+    pub invited_by: Uuid,
+    pub expires_at: DateTime<Utc>,
+    pub accepted_by: Option<Uuid>,
+    pub accepted_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTeamInvitationRequest {
+    pub email: String,
+    pub role: String,
+}
+
+/// A JSON array of `{user_id, role}`, applied in one transaction
+#[derive(Debug, Deserialize)]
+#[serde(transparent)]
+pub struct BulkAddTeamMembersRequest {
+    pub members: Vec<AddTeamMemberRequest>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BulkMemberStatus {
+    Added,
+    /// Already a member, now with the requested role
+    Updated,
+    /// Already a member with the requested role
+    Skipped,
+    /// Left untouched; `error` says why
+    Error,
+}
+
+/// What happened to one entry of a bulk import, in request order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkMemberResult {
+    pub user_id: Uuid,
+    pub role: String,
+    pub status: BulkMemberStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
 // ============ Project RBAC Models ============
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -151,9 +212,46 @@ impl ReviewStatus {
             ReviewStatus::Closed => "closed",
         }
     }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "open" => Some(ReviewStatus::Open),
+            "approved" => Some(ReviewStatus::Approved),
+            "changes_requested" => Some(ReviewStatus::ChangesRequested),
+            "merged" => Some(ReviewStatus::Merged),
+            "closed" => Some(ReviewStatus::Closed),
+            _ => None,
+        }
+    }
+
+    /// The statuses a review may move to from this one. Merged is final; a closed review
+    /// can only be reopened.
+    pub fn next_statuses(&self) -> &'static [ReviewStatus] {
+        match self {
+            ReviewStatus::Open => &[
+                ReviewStatus::Approved,
+                ReviewStatus::ChangesRequested,
+                ReviewStatus::Merged,
+                ReviewStatus::Closed,
+            ],
+            ReviewStatus::Approved => &[ReviewStatus::Merged, ReviewStatus::ChangesRequested, ReviewStatus::Closed],
+            ReviewStatus::ChangesRequested => &[ReviewStatus::Open, ReviewStatus::Approved, ReviewStatus::Closed],
+            ReviewStatus::Merged => &[],
+            ReviewStatus::Closed => &[ReviewStatus::Open],
+        }
+    }
+
+    pub fn can_transition_to(&self, next: ReviewStatus) -> bool {
+        self.next_statuses().contains(&next)
+    }
+
+    /// Whether a review in this status is finished with, and so has a `closed_at`
+    pub fn is_closed(&self) -> bool {
+        matches!(self, ReviewStatus::Merged | ReviewStatus::Closed)
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct CodeReview {
     pub id: Uuid,
     pub project_id: Uuid,
@@ -168,19 +266,34 @@ pub struct CodeReview {
     pub closed_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct ReviewComment {
     pub id: Uuid,
     pub review_id: Uuid,
     pub author_id: Uuid,
+    /// The comment this one replies to; `None` for a comment that starts a thread
+    pub parent_comment_id: Option<Uuid>,
     pub file_path: Option<String>,
     pub line_number: Option<i32>,
+    /// The version of the file `line_number` refers to, if the file had one
+    pub file_version: Option<i32>,
+    /// The commented line has since been changed or removed; `line_number` still points
+    /// at it in `file_version`
+    pub outdated: bool,
     pub content: String,
     pub resolved: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// A comment and its replies, oldest reply first
+#[derive(Debug, Clone, Serialize)]
+pub struct ReviewCommentThread {
+    #[serde(flatten)]
+    pub comment: ReviewComment,
+    pub replies: Vec<ReviewCommentThread>,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum ApprovalStatus {
@@ -199,7 +312,7 @@ impl ApprovalStatus {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct ReviewApproval {
     pub id: Uuid,
     pub review_id: Uuid,
@@ -229,6 +342,8 @@ pub struct AddReviewCommentRequest {
     pub file_path: Option<String>,
     pub line_number: Option<i32>,
     pub content: String,
+    /// Reply to this comment, which must be on the same review
+    pub parent_comment_id: Option<Uuid>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -243,6 +358,30 @@ pub struct SubmitApprovalRequest {
     pub comments: Option<String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReviewSettings {
+    pub project_id: Uuid,
+    pub required_approvals: i32,
+    pub require_no_changes_requested: bool,
+}
+
+impl ReviewSettings {
+    /// Settings used when a project has not configured its own
+    pub fn default_for(project_id: Uuid) -> Self {
+        Self {
+            project_id,
+            required_approvals: 1,
+            require_no_changes_requested: true,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateReviewSettingsRequest {
+    pub required_approvals: Option<i32>,
+    pub require_no_changes_requested: Option<bool>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct DiffStat {
     pub file_path: String,
@@ -253,7 +392,9 @@ pub struct DiffStat {
 #[derive(Debug, Serialize)]
 pub struct CodeReviewDetails {
     pub review: CodeReview,
-    pub comments: Vec<ReviewComment>,
+    /// Threads, newest first, each with every reply beneath it; pass `next_cursor` back as
+    /// `?cursor=` for older threads
+    pub comments: Page<ReviewCommentThread>,
     pub approvals: Vec<ReviewApproval>,
     pub diff_stats: Vec<DiffStat>,
 }
@@ -310,6 +451,12 @@ pub struct CursorUpdate {
     pub selection_end: Option<i32>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ConflictQuery {
+    /// Document version the client last synced to
+    pub version: u32,
+}
+
 // ============ Operational Transformation Models ============
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -321,6 +468,7 @@ pub struct DocumentOperation {
     pub operation: OperationType,
 }
 
+/// Positions and lengths are measured in chars, not bytes
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum OperationType {
@@ -336,6 +484,14 @@ pub enum OperationType {
     },
 }
 
+/// Sent instead of applying an operation built against a version too far behind the
+/// session; the client rebases its pending edits over `missed_operations`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResyncRequired {
+    pub server_version: u32,
+    pub missed_operations: Vec<DocumentOperation>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConflictDetection {
     pub session_id: Uuid,
@@ -362,6 +518,81 @@ pub struct WebSocketMessage {
     pub timestamp: DateTime<Utc>,
 }
 
+impl WebSocketMessage {
+    pub const OPERATION: &'static str = "operation";
+    pub const CURSOR: &'static str = "cursor";
+    pub const RESYNC: &'static str = "resync";
+    pub const USER_JOINED: &'static str = "user_joined";
+    pub const USER_LEFT: &'static str = "user_left";
+    pub const ERROR: &'static str = "error";
+
+    pub fn new(event_type: &str, session_id: Uuid, user_id: Uuid, data: serde_json::Value) -> Self {
+        WebSocketMessage {
+            event_type: event_type.to_string(),
+            session_id,
+            user_id,
+            data,
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+/// A participant arriving in or leaving a session
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum PresenceEvent {
+    Joined(Uuid),
+    Left(Uuid),
+}
+
+/// Everything broadcast to a session's participants
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SessionMessage {
+    Operation(DocumentOperation),
+    Cursor(CursorUpdate),
+    Presence(PresenceEvent),
+}
+
+impl SessionMessage {
+    /// Wrap in the envelope sent to clients
+    pub fn to_websocket_message(&self, session_id: Uuid) -> serde_json::Result<WebSocketMessage> {
+        let message = match self {
+            SessionMessage::Operation(operation) => WebSocketMessage::new(
+                WebSocketMessage::OPERATION,
+                session_id,
+                operation.user_id,
+                serde_json::to_value(operation)?,
+            ),
+            SessionMessage::Cursor(cursor) => WebSocketMessage::new(
+                WebSocketMessage::CURSOR,
+                session_id,
+                cursor.user_id,
+                serde_json::to_value(cursor)?,
+            ),
+            SessionMessage::Presence(PresenceEvent::Joined(user_id)) => WebSocketMessage::new(
+                WebSocketMessage::USER_JOINED,
+                session_id,
+                *user_id,
+                serde_json::Value::Null,
+            ),
+            SessionMessage::Presence(PresenceEvent::Left(user_id)) => WebSocketMessage::new(
+                WebSocketMessage::USER_LEFT,
+                session_id,
+                *user_id,
+                serde_json::Value::Null,
+            ),
+        };
+        Ok(message)
+    }
+}
+
+/// What a client sends over a session WebSocket: an edit, or a cursor/selection move
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ClientMessage {
+    Operation(DocumentOperation),
+    Cursor(CursorUpdate),
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CollaborationEvent {
     pub session_id: Uuid,
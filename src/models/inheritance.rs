@@ -53,7 +53,7 @@ pub struct InheritedPermission {
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct PermissionRule {
     pub id: Uuid,
     pub team_id: Option<Uuid>,
@@ -92,6 +92,8 @@ pub struct ResolvedPermissions {
     pub resource_type: String,
     pub direct_permissions: Vec<String>,
     pub inherited_permissions: Vec<InheritedPermissionInfo>,
+    /// Granted by permission rules for the user's role on this resource or its ancestors
+    pub rule_permissions: Vec<String>,
     pub effective_permissions: Vec<String>,
     pub role: String,
 }
@@ -116,7 +118,7 @@ pub struct HierarchyTree {
 
 // ============ Audit Log Models ============
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct AuditLog {
     pub id: Uuid,
     pub actor_id: Uuid,
@@ -138,7 +140,8 @@ pub struct AuditLogQuery {
     pub start_date: Option<DateTime<Utc>>,
     pub end_date: Option<DateTime<Utc>>,
     pub limit: Option<i64>,
-    pub offset: Option<i64>,
+    /// `next_cursor` from the previous page
+    pub cursor: Option<String>,
 }
 
 // ============ Permission Models ============
@@ -218,6 +221,11 @@ impl Permission {
             Self::view_audit(),
         ]
     }
+
+    /// Whether `name` is one of the permissions defined in `all()`
+    pub fn is_known(name: &str) -> bool {
+        Self::all().iter().any(|permission| permission.name == name)
+    }
 }
 
 // ============ Inheritance Configuration ============
@@ -228,6 +236,15 @@ pub struct InheritanceConfig {
     pub max_depth: i32,
     pub cascading_updates: bool,
     pub override_allowed: bool,
+    /// How long resolved permissions stay cached
+    pub cache_ttl_secs: u64,
+    /// Most resolved `(user, resource)` pairs kept cached at once
+    #[serde(default = "default_cache_capacity")]
+    pub cache_capacity: usize,
+}
+
+fn default_cache_capacity() -> usize {
+    10_000
 }
 
 impl Default for InheritanceConfig {
@@ -237,6 +254,8 @@ impl Default for InheritanceConfig {
             max_depth: 5,
             cascading_updates: true,
             override_allowed: true,
+            cache_ttl_secs: 60,
+            cache_capacity: default_cache_capacity(),
         }
     }
 }
@@ -2,6 +2,9 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
 
+use crate::services::circuit_breaker::CircuitState;
+use crate::services::code_analysis::Finding;
+
 pub mod collaboration;
 pub mod inheritance;
 
@@ -12,6 +15,7 @@ pub struct User {
     pub email: String,
     pub first_name: Option<String>,
     pub last_name: Option<String>,
+    pub email_verified: bool,
     pub created_at: DateTime<Utc>,
 }
 
@@ -32,10 +36,71 @@ pub struct LoginRequest {
 #[derive(Debug, Serialize)]
 pub struct AuthResponse {
     pub access_token: String,
+    /// Seconds until `access_token` expires
+    pub expires_in: i64,
     pub refresh_token: String,
     pub user: User,
 }
 
+/// What a login returns: tokens, or a challenge when the account has two-factor enabled
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Authenticated(AuthResponse),
+    TwoFactorRequired(TwoFactorChallenge),
+}
+
+/// What registering returns: tokens in development, otherwise just the new account, which
+/// has to verify its email before it can sign in
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum RegisterResponse {
+    Authenticated(AuthResponse),
+    VerificationRequired(PendingVerification),
+}
+
+/// Stands in for tokens until the emailed verification link is followed
+#[derive(Debug, Serialize)]
+pub struct PendingVerification {
+    /// Always `verification_required`
+    pub status: String,
+    pub user: User,
+}
+
+/// Stands in for tokens until `POST /auth/2fa/challenge` gets a valid code
+#[derive(Debug, Serialize)]
+pub struct TwoFactorChallenge {
+    /// Always `2fa_required`
+    pub status: String,
+    pub challenge_token: String,
+    pub expires_in: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorChallengeRequest {
+    pub challenge_token: String,
+    /// A 6-digit authenticator code or an unused recovery code
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TwoFactorEnrollment {
+    /// Base32, for typing into an authenticator app
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorVerifyRequest {
+    pub code: String,
+}
+
+/// Shown once, when enrollment is confirmed
+#[derive(Debug, Serialize)]
+pub struct TwoFactorEnabled {
+    pub recovery_codes: Vec<String>,
+}
+
 // Project Models
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Project {
@@ -63,6 +128,27 @@ pub struct UpdateProjectRequest {
     pub language: Option<String>,
 }
 
+/// A project in the owner's trash, restorable until it's purged
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashedProject {
+    #[serde(flatten)]
+    pub project: Project,
+    pub deleted_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DeleteProjectQuery {
+    /// Skip the trash and delete the project and everything in it right away
+    #[serde(default)]
+    pub permanent: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransferProjectRequest {
+    /// The new owner; must be an existing user other than the current owner
+    pub user_id: Uuid,
+}
+
 // Code File Models
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CodeFile {
@@ -71,6 +157,7 @@ pub struct CodeFile {
     pub file_path: String,
     pub content: String,
     pub language: Option<String>,
+    pub updated_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -80,6 +167,58 @@ pub struct CreateFileRequest {
     pub language: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct UpdateFileRequest {
+    pub content: String,
+    pub language: Option<String>,
+    pub change_description: Option<String>,
+}
+
+// Deployment Models
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileContent {
+    pub path: String,
+    pub content: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeployRequest {
+    pub files: Vec<FileContent>,
+    #[serde(default)]
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Deployment {
+    pub id: Uuid,
+    pub project_id: Uuid,
+    pub user_id: Uuid,
+    pub status: String,
+    pub message: String,
+    pub file_count: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeploymentHistoryQuery {
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DuplicatesQuery {
+    /// Shortest span, in tokens, to report
+    pub min_tokens: Option<usize>,
+    /// Lowest similarity, from 0 to 1, for spans to count as duplicates
+    pub threshold: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProjectAnalysis {
+    pub lines_of_code: usize,
+    pub complexity: f64,
+    pub issues: usize,
+}
+
 // Analysis Models
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AnalysisTask {
@@ -90,33 +229,54 @@ pub struct AnalysisTask {
     pub created_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct OptimizeCodeRequest {
+    pub project_id: Option<Uuid>,
     pub code: String,
-    pub language: String,
+    /// Detected from `file_path`, then from the code itself, when omitted
+    pub language: Option<String>,
     pub file_path: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ReviewCodeRequest {
+    pub project_id: Option<Uuid>,
     pub code: String,
-    pub language: String,
+    /// Detected from `file_path`, then from the code itself, when omitted
+    pub language: Option<String>,
     pub file_path: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct RefactorCodeRequest {
+    pub project_id: Option<Uuid>,
     pub code: String,
-    pub language: String,
+    /// Detected from `file_path`, then from the code itself, when omitted
+    pub language: Option<String>,
+    pub file_path: Option<String>,
     pub target_pattern: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct CodeAnalysisResponse {
     pub task_id: Uuid,
+    /// The language the code was analyzed as, whether given or detected
+    pub language: String,
     pub suggestions: Vec<String>,
+    /// The static analysis's findings with their rule, severity and position. Their
+    /// messages are also included in `suggestions`.
+    pub findings: Vec<Finding>,
     pub optimized_code: Option<String>,
     pub metrics: AnalysisMetrics,
+    /// Whether the AI's suggestions were reused from an earlier identical request
+    pub cached: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AnalysisQuery {
+    /// Ask the AI again even if an answer for the same code is cached
+    #[serde(default)]
+    pub no_cache: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -127,13 +287,20 @@ pub struct AnalysisMetrics {
 }
 
 // Agent Models
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct AgentRequest {
     pub project_id: Uuid,
     pub task_description: String,
     pub context: Option<String>,
 }
 
+/// An agent tasks can be started with, by the name `POST /agents/:name` takes
+#[derive(Debug, Serialize)]
+pub struct AgentInfo {
+    pub name: String,
+    pub description: String,
+}
+
 #[derive(Debug, Serialize)]
 pub struct AgentTaskResponse {
     pub task_id: Uuid,
@@ -147,6 +314,7 @@ pub struct AgentTaskStatus {
     pub status: String,
     pub progress: f64,
     pub result: Option<serde_json::Value>,
+    pub queue_depth: usize,
 }
 
 // Analytics Models
@@ -165,11 +333,218 @@ pub struct Metric {
     pub timestamp: DateTime<Utc>,
 }
 
+/// `GET /analytics/metrics` filters; `from` is inclusive and `to` exclusive
+#[derive(Debug, Default, Deserialize)]
+pub struct MetricsQuery {
+    pub metric_type: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// `hour` or `day`, defaulting to `day`
+    pub interval: Option<String>,
+}
+
+/// `POST /analytics/reports`; defaults to the 7 days up to now
+#[derive(Debug, Default, Deserialize)]
+pub struct GenerateReportRequest {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl GenerateReportRequest {
+    /// Longest window one report may cover
+    pub const MAX_DAYS: i64 = 366;
+
+    /// The `(from, to)` window with the defaults filled in
+    pub fn window(&self) -> (DateTime<Utc>, DateTime<Utc>) {
+        let to = self.to.unwrap_or_else(Utc::now);
+        let from = self.from.unwrap_or(to - chrono::Duration::days(7));
+        (from, to)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetricInterval {
+    Hour,
+    Day,
+}
+
+impl MetricInterval {
+    pub fn parse(interval: Option<&str>) -> Option<Self> {
+        match interval {
+            None | Some("day") => Some(MetricInterval::Day),
+            Some("hour") => Some(MetricInterval::Hour),
+            Some(_) => None,
+        }
+    }
+
+    /// The `date_trunc` field name, which is also a valid interval unit
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MetricInterval::Hour => "hour",
+            MetricInterval::Day => "day",
+        }
+    }
+
+    pub fn duration(&self) -> chrono::Duration {
+        match self {
+            MetricInterval::Hour => chrono::Duration::hours(1),
+            MetricInterval::Day => chrono::Duration::days(1),
+        }
+    }
+
+    /// Most buckets one request may span, which bounds the range
+    pub fn max_buckets(&self) -> i64 {
+        match self {
+            MetricInterval::Hour => 24 * 7,
+            MetricInterval::Day => 366,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenUsageSummary {
+    pub day: chrono::NaiveDate,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub total_tokens: i64,
+    pub daily_budget: i64,
+    pub remaining: i64,
+}
+
+// Webhook Models
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WebhookEvent {
+    #[serde(rename = "review.status_changed")]
+    ReviewStatusChanged,
+    #[serde(rename = "deployment.completed")]
+    DeploymentCompleted,
+    #[serde(rename = "agent_task.completed")]
+    AgentTaskCompleted,
+}
+
+impl WebhookEvent {
+    pub fn all() -> [WebhookEvent; 3] {
+        [
+            WebhookEvent::ReviewStatusChanged,
+            WebhookEvent::DeploymentCompleted,
+            WebhookEvent::AgentTaskCompleted,
+        ]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::ReviewStatusChanged => "review.status_changed",
+            WebhookEvent::DeploymentCompleted => "deployment.completed",
+            WebhookEvent::AgentTaskCompleted => "agent_task.completed",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::all().into_iter().find(|event| event.as_str() == value)
+    }
+}
+
+/// A project's webhook. The signing secret is only returned when the webhook is created.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub id: Uuid,
+    pub project_id: Uuid,
+    pub url: String,
+    pub event_types: Vec<String>,
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateWebhookRequest {
+    pub url: String,
+    pub event_types: Vec<String>,
+    /// Generated when absent
+    pub secret: Option<String>,
+    #[serde(default = "default_true")]
+    pub active: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateWebhookRequest {
+    pub url: Option<String>,
+    pub event_types: Option<Vec<String>>,
+    pub secret: Option<String>,
+    pub active: Option<bool>,
+}
+
+/// One attempt at delivering an event; retries share `delivery_id`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub webhook_id: Uuid,
+    pub delivery_id: Uuid,
+    pub event_type: String,
+    pub payload: serde_json::Value,
+    pub attempt: i32,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub succeeded: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+// Health Models
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HealthStatus {
+    pub ok: bool,
+    pub database_ok: bool,
+    pub migrations_ok: bool,
+    pub cache_ok: bool,
+    /// Absent when the AI check is turned off
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ai_ok: Option<bool>,
+    /// Whether AI calls are going through (`closed`), being refused after repeated
+    /// failures (`open`) or probing for recovery (`half_open`)
+    pub ai_circuit: CircuitState,
+    /// Names of the dependencies that failed their check
+    pub failed: Vec<String>,
+    pub agents_running: usize,
+    /// Agent tasks that can run at once; more than this wait in the queue
+    pub agent_workers: usize,
+    pub queue_depth: usize,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct TokenRefreshRequest {
     pub refresh_token: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmailQuery {
+    pub token: String,
+}
+
+/// Query GitHub sends the browser back with; `error` replaces `code` when the user declines
+#[derive(Debug, Deserialize)]
+pub struct OAuthCallbackQuery {
+    pub code: Option<String>,
+    pub state: String,
+    pub error: Option<String>,
+}
+
 // Re-export collaboration models
 pub use collaboration::{
     Team, TeamMember, TeamRole, ProjectMember, ProjectPermission,
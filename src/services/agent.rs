@@ -1,6 +1,13 @@
 use async_trait::async_trait;
 use crate::error::AppResult;
+use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use uuid::Uuid;
 
 #[async_trait]
 pub trait Agent: Send + Sync {
@@ -21,8 +28,11 @@ pub struct AgentMetrics {
     pub issues_found: usize,
 }
 
+#[derive(Default)]
 pub struct FrontendAgent;
+#[derive(Default)]
 pub struct BackendAgent;
+#[derive(Default)]
 pub struct QAAgent;
 
 impl FrontendAgent {
@@ -100,3 +110,243 @@ impl Agent for QAAgent {
         })
     }
 }
+
+/// Work submitted to the agent queue; it does not start until a worker polls it
+pub type AgentJob = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Runs agent jobs in submission order with at most `max_concurrent` in flight
+pub struct AgentQueue {
+    sender: mpsc::UnboundedSender<AgentJob>,
+    queued: Arc<AtomicUsize>,
+    running: Arc<AtomicUsize>,
+    max_concurrent: usize,
+    cancellations: Arc<DashMap<Uuid, oneshot::Sender<()>>>,
+}
+
+impl AgentQueue {
+    /// Start the dispatcher; must be called from within a Tokio runtime
+    pub fn new(max_concurrent: usize) -> Self {
+        let max_concurrent = max_concurrent.max(1);
+        let (sender, mut receiver) = mpsc::unbounded_channel::<AgentJob>();
+        let queued = Arc::new(AtomicUsize::new(0));
+        let running = Arc::new(AtomicUsize::new(0));
+        let semaphore = Arc::new(Semaphore::new(max_concurrent));
+
+        let dispatcher_queued = queued.clone();
+        let dispatcher_running = running.clone();
+        tokio::spawn(async move {
+            while let Some(job) = receiver.recv().await {
+                let Ok(permit) = semaphore.clone().acquire_owned().await else {
+                    break;
+                };
+                dispatcher_queued.fetch_sub(1, Ordering::SeqCst);
+                dispatcher_running.fetch_add(1, Ordering::SeqCst);
+
+                let running = dispatcher_running.clone();
+                tokio::spawn(async move {
+                    job.await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    drop(permit);
+                });
+            }
+        });
+
+        Self {
+            sender,
+            queued,
+            running,
+            max_concurrent,
+            cancellations: Arc::new(DashMap::new()),
+        }
+    }
+
+    pub fn enqueue<F>(&self, job: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        if self.sender.send(Box::pin(job)).is_err() {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            tracing::error!("Agent queue dispatcher has stopped; job dropped");
+        }
+    }
+
+    /// Enqueue a job that can be stopped with `cancel` while queued or running
+    pub fn enqueue_task<F>(&self, task_id: Uuid, job: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let (cancel_tx, mut cancel_rx) = oneshot::channel();
+        self.cancellations.insert(task_id, cancel_tx);
+        let cancellations = self.cancellations.clone();
+
+        self.enqueue(async move {
+            tokio::select! {
+                _ = job => {}
+                Ok(()) = &mut cancel_rx => {
+                    tracing::info!("Agent task {} cancelled", task_id);
+                }
+            }
+            cancellations.remove(&task_id);
+        });
+    }
+
+    /// Signal a queued or running task to stop; false if it is not tracked
+    pub fn cancel(&self, task_id: Uuid) -> bool {
+        self.cancellations
+            .remove(&task_id)
+            .is_some_and(|(_, cancel_tx)| cancel_tx.send(()).is_ok())
+    }
+
+    /// Cancel every tracked task, queued or running, and return their ids so the
+    /// caller can record why they stopped
+    pub fn cancel_all(&self) -> Vec<Uuid> {
+        let task_ids: Vec<Uuid> = self.cancellations.iter().map(|entry| *entry.key()).collect();
+        task_ids.into_iter().filter(|task_id| self.cancel(*task_id)).collect()
+    }
+
+    /// Number of jobs waiting for a free worker
+    pub fn depth(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    /// Number of jobs currently holding a worker
+    pub fn running(&self) -> usize {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// Number of jobs that can hold a worker at once
+    pub fn max_concurrent(&self) -> usize {
+        self.max_concurrent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_queue_limits_concurrency_and_runs_everything() {
+        let queue = AgentQueue::new(2);
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+
+        for i in 0..6 {
+            let running = running.clone();
+            let peak = peak.clone();
+            let done_tx = done_tx.clone();
+            queue.enqueue(async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                done_tx.send(i).unwrap();
+            });
+        }
+        drop(done_tx);
+
+        let mut finished = Vec::new();
+        while let Some(i) = tokio::time::timeout(Duration::from_secs(5), done_rx.recv())
+            .await
+            .expect("queued jobs did not finish")
+        {
+            finished.push(i);
+        }
+        finished.sort();
+
+        assert_eq!(finished, vec![0, 1, 2, 3, 4, 5]);
+        assert!(peak.load(Ordering::SeqCst) <= 2);
+        assert_eq!(queue.depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_queue_depth_counts_waiting_jobs() {
+        let queue = AgentQueue::new(1);
+        let (release_tx, release_rx) = tokio::sync::oneshot::channel::<()>();
+
+        queue.enqueue(async move {
+            let _ = release_rx.await;
+        });
+        queue.enqueue(async {});
+        queue.enqueue(async {});
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(queue.depth(), 2);
+        assert_eq!(queue.running(), 1);
+
+        release_tx.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(queue.depth(), 0);
+        assert_eq!(queue.running(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stops_running_job() {
+        let queue = AgentQueue::new(1);
+        let task_id = Uuid::new_v4();
+        let (started_tx, started_rx) = oneshot::channel();
+        let finished = Arc::new(AtomicUsize::new(0));
+
+        let job_finished = finished.clone();
+        queue.enqueue_task(task_id, async move {
+            started_tx.send(()).unwrap();
+            tokio::time::sleep(Duration::from_secs(30)).await;
+            job_finished.fetch_add(1, Ordering::SeqCst);
+        });
+
+        started_rx.await.unwrap();
+        assert!(queue.cancel(task_id));
+        assert!(!queue.cancel(task_id));
+
+        // The worker slot is released, so the next job runs promptly
+        let (next_tx, next_rx) = oneshot::channel();
+        queue.enqueue(async move {
+            next_tx.send(()).unwrap();
+        });
+        tokio::time::timeout(Duration::from_secs(5), next_rx)
+            .await
+            .expect("cancelled job kept its worker")
+            .unwrap();
+        assert_eq!(finished.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_all_stops_running_and_queued_jobs() {
+        let queue = AgentQueue::new(1);
+        let running_id = Uuid::new_v4();
+        let queued_id = Uuid::new_v4();
+        let (started_tx, started_rx) = oneshot::channel();
+        let finished = Arc::new(AtomicUsize::new(0));
+
+        let job_finished = finished.clone();
+        queue.enqueue_task(running_id, async move {
+            started_tx.send(()).unwrap();
+            tokio::time::sleep(Duration::from_secs(30)).await;
+            job_finished.fetch_add(1, Ordering::SeqCst);
+        });
+        let job_finished = finished.clone();
+        queue.enqueue_task(queued_id, async move {
+            tokio::time::sleep(Duration::from_secs(30)).await;
+            job_finished.fetch_add(1, Ordering::SeqCst);
+        });
+        started_rx.await.unwrap();
+
+        let mut cancelled = queue.cancel_all();
+        cancelled.sort();
+        let mut expected = vec![running_id, queued_id];
+        expected.sort();
+        assert_eq!(cancelled, expected);
+        assert!(queue.cancel_all().is_empty());
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while queue.running() + queue.depth() > 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("cancelled jobs kept their workers");
+        assert_eq!(finished.load(Ordering::SeqCst), 0);
+    }
+}
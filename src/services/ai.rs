@@ -1,12 +1,31 @@
 use async_trait::async_trait;
+use futures::{Stream, StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
+use rand::Rng;
+use sqlx::PgPool;
+use std::collections::VecDeque;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use uuid::Uuid;
 use crate::error::{AppError, AppResult};
+use crate::services::circuit_breaker::{BreakerSettings, CircuitBreaker};
+use crate::services::prompts::{prompt_templates, PromptTask, PromptTemplates};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AIRequest {
     pub messages: Vec<Message>,
     pub model: String,
     pub temperature: f32,
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub stream_options: Option<StreamOptions>,
+}
+
+/// Asks for a final chunk carrying the token counts of a streamed completion
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamOptions {
+    pub include_usage: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,6 +50,200 @@ pub struct AIService {
     client: reqwest::Client,
     api_key: String,
     api_url: String,
+    model: String,
+    retry: RetryPolicy,
+    breaker: Arc<CircuitBreaker>,
+    prompts: Arc<PromptTemplates>,
+    usage: Option<UsageContext>,
+    daily_token_budget: i64,
+}
+
+/// The breaker every `AIService::new()` shares, so one outage is noticed across requests
+pub fn ai_circuit_breaker() -> Arc<CircuitBreaker> {
+    static BREAKER: OnceLock<Arc<CircuitBreaker>> = OnceLock::new();
+    BREAKER
+        .get_or_init(|| Arc::new(CircuitBreaker::new(BreakerSettings::from_env())))
+        .clone()
+}
+
+/// The user whose daily token budget an AI call is charged against
+#[derive(Clone)]
+struct UsageContext {
+    pool: PgPool,
+    user_id: Uuid,
+}
+
+const DEFAULT_DAILY_TOKEN_BUDGET: i64 = 100_000;
+
+const DEFAULT_MODEL: &str = "gpt-3.5-turbo";
+
+/// Daily per-user token allowance, from `AI_DAILY_TOKEN_BUDGET`
+pub fn daily_token_budget() -> i64 {
+    std::env::var("AI_DAILY_TOKEN_BUDGET")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_DAILY_TOKEN_BUDGET)
+}
+
+/// Prompt and completion tokens the user has consumed today (UTC)
+pub async fn tokens_used_today(pool: &PgPool, user_id: Uuid) -> AppResult<(i64, i64)> {
+    let used = sqlx::query_as::<_, (i64, i64)>(
+        "SELECT prompt_tokens, completion_tokens FROM token_usage WHERE user_id = $1 AND day = $2"
+    )
+    .bind(user_id)
+    .bind(chrono::Utc::now().date_naive())
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(used.unwrap_or((0, 0)))
+}
+
+/// Reject the call when the user has already spent their daily budget
+async fn check_token_budget(usage: Option<&UsageContext>, budget: i64) -> AppResult<()> {
+    let Some(usage) = usage else {
+        return Ok(());
+    };
+
+    let (prompt_tokens, completion_tokens) = tokens_used_today(&usage.pool, usage.user_id).await?;
+    if prompt_tokens + completion_tokens >= budget {
+        return Err(AppError::ValidationError("token budget exceeded".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Add a call's token counts to the user's total for today
+async fn record_usage(context: Option<&UsageContext>, usage: &TokenUsage) -> AppResult<()> {
+    let Some(context) = context else {
+        return Ok(());
+    };
+
+    sqlx::query(
+        r#"
+        INSERT INTO token_usage (user_id, day, prompt_tokens, completion_tokens)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (user_id, day) DO UPDATE SET
+        prompt_tokens = token_usage.prompt_tokens + EXCLUDED.prompt_tokens,
+        completion_tokens = token_usage.completion_tokens + EXCLUDED.completion_tokens
+        "#,
+    )
+    .bind(context.user_id)
+    .bind(chrono::Utc::now().date_naive())
+    .bind(i64::from(usage.prompt_tokens))
+    .bind(i64::from(usage.completion_tokens))
+    .execute(&context.pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Rough token count for text the API didn't count for us, at about four characters a token
+fn estimated_tokens(text: &str) -> u32 {
+    (text.chars().count() as u32).div_ceil(4)
+}
+
+/// How transient upstream failures (429, 5xx, network errors) are retried
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+impl RetryPolicy {
+    /// Send the request, retrying transient failures with exponential backoff. Refused
+    /// outright while `breaker` is open; a call that ends in a transient failure counts
+    /// against it, while any answer short of that shows the API is up.
+    async fn send(&self, request: reqwest::RequestBuilder, breaker: &CircuitBreaker) -> AppResult<reqwest::Response> {
+        if !breaker.try_acquire() {
+            return Err(AppError::ExternalApiError("AI unavailable".to_string()));
+        }
+
+        let mut attempt = 0;
+
+        loop {
+            let this_try = request.try_clone().ok_or_else(|| {
+                AppError::InternalServerError("AI request cannot be retried".to_string())
+            })?;
+            let retries_left = attempt < self.max_retries;
+
+            let retry_after = match this_try.send().await {
+                Ok(response) if response.status().is_success() => {
+                    breaker.record_success();
+                    return Ok(response);
+                }
+                Ok(response) if retries_left && is_retryable_status(response.status()) => {
+                    tracing::warn!("AI API returned {}, retrying", response.status());
+                    retry_after(&response)
+                }
+                Ok(response) => {
+                    if is_retryable_status(response.status()) {
+                        breaker.record_failure();
+                    } else {
+                        breaker.record_success();
+                    }
+                    return Err(AppError::ExternalApiError(format!(
+                        "AI API call failed with status {}",
+                        response.status()
+                    )));
+                }
+                Err(e) if retries_left && (e.is_timeout() || e.is_connect()) => {
+                    tracing::warn!("AI API request failed, retrying: {:?}", e);
+                    None
+                }
+                Err(e) => {
+                    breaker.record_failure();
+                    return Err(e.into());
+                }
+            };
+
+            tokio::time::sleep(retry_after.unwrap_or_else(|| self.backoff(attempt))).await;
+            attempt += 1;
+        }
+    }
+
+    /// Exponential delay for the given attempt with up to 50% random jitter
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(MAX_RETRY_DELAY);
+        let jitter = rand::thread_rng().gen_range(0.0..0.5);
+        delay.mul_f64(1.0 + jitter).min(MAX_RETRY_DELAY)
+    }
+}
+
+fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+    status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Delay requested by a `Retry-After: <seconds>` header, capped at the backoff maximum
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(|secs| Duration::from_secs(secs).min(MAX_RETRY_DELAY))
+}
+
+impl Default for AIService {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AIService {
@@ -39,26 +252,85 @@ impl AIService {
         let api_url = std::env::var("AI_API_URL")
             .unwrap_or_else(|_| "https://api.openai.com/v1".to_string());
 
+        let mut service = Self::with_endpoint(api_url, api_key)
+            .with_circuit_breaker(ai_circuit_breaker())
+            .with_prompt_templates(prompt_templates());
+        if let Some(model) = std::env::var("AI_MODEL").ok().filter(|model| !model.is_empty()) {
+            service.model = model;
+        }
+        if let Some(max_retries) = std::env::var("AI_MAX_RETRIES")
+            .ok()
+            .and_then(|value| value.parse().ok())
+        {
+            service.retry.max_retries = max_retries;
+        }
+        service
+    }
+
+    pub fn with_endpoint(api_url: impl Into<String>, api_key: impl Into<String>) -> Self {
         AIService {
             client: reqwest::Client::new(),
-            api_key,
-            api_url,
+            api_key: api_key.into(),
+            api_url: api_url.into(),
+            model: DEFAULT_MODEL.to_string(),
+            retry: RetryPolicy::default(),
+            breaker: Arc::new(CircuitBreaker::new(BreakerSettings::from_env())),
+            prompts: Arc::new(PromptTemplates::default()),
+            usage: None,
+            daily_token_budget: daily_token_budget(),
         }
     }
 
+    /// Charge calls made through this service to the user's daily token budget
+    pub fn for_user(mut self, pool: PgPool, user_id: Uuid) -> Self {
+        self.usage = Some(UsageContext { pool, user_id });
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_daily_token_budget(mut self, budget: i64) -> Self {
+        self.daily_token_budget = budget;
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_circuit_breaker(mut self, breaker: Arc<CircuitBreaker>) -> Self {
+        self.breaker = breaker;
+        self
+    }
+
+    pub fn with_prompt_templates(mut self, prompts: Arc<PromptTemplates>) -> Self {
+        self.prompts = prompts;
+        self
+    }
+
+    /// The model completions are requested from
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
     pub async fn optimize(&self, code: &str, language: &str) -> AppResult<Vec<String>> {
-        let prompt = format!(
-            "Optimize the following {} code:\n\n{}\n\nProvide optimization suggestions.",
-            language, code
-        );
+        let prompt = self.prompts.render(PromptTask::Optimize, language, code);
         self.call_ai(&prompt).await
     }
 
+    /// Like `optimize`, but yields the completion text as it is generated
+    pub fn optimize_streaming(
+        &self,
+        code: &str,
+        language: &str,
+    ) -> impl Stream<Item = AppResult<String>> + Send + 'static {
+        let prompt = self.prompts.render(PromptTask::Optimize, language, code);
+        self.call_ai_streaming(&prompt)
+    }
+
     pub async fn review(&self, code: &str, language: &str) -> AppResult<Vec<String>> {
-        let prompt = format!(
-            "Review the following {} code and provide feedback on:\n- Code quality\n- Best practices\n- Potential issues\n\n{}",
-            language, code
-        );
+        let prompt = self.prompts.render(PromptTask::Review, language, code);
         self.call_ai(&prompt).await
     }
 
@@ -67,47 +339,34 @@ impl AIService {
         code: &str,
         language: &str,
     ) -> AppResult<(Vec<String>, String)> {
-        let prompt = format!(
-            "Refactor the following {} code to be more maintainable and efficient:\n\n{}",
-            language, code
-        );
-        let suggestions = self.call_ai(&prompt).await?;
-        
-        // For now, return the original code as refactored
-        // In production, parse the AI response to extract the refactored code
-        Ok((suggestions, code.to_string()))
+        let prompt = self.prompts.render(PromptTask::Refactor, language, code);
+        let content = self.complete(&prompt).await?;
+
+        Ok(parse_refactor_response(&content))
     }
 
-    async fn call_ai(&self, prompt: &str) -> AppResult<Vec<String>> {
-        let request = AIRequest {
-            messages: vec![Message {
-                role: "user".to_string(),
-                content: prompt.to_string(),
-            }],
-            model: "gpt-3.5-turbo".to_string(),
+    /// The shared system prompt followed by `prompt` as the user's message
+    fn completion_request(&self, prompt: &str, stream: bool) -> AIRequest {
+        AIRequest {
+            messages: vec![
+                Message {
+                    role: "system".to_string(),
+                    content: self.prompts.system().to_string(),
+                },
+                Message {
+                    role: "user".to_string(),
+                    content: prompt.to_string(),
+                },
+            ],
+            model: self.model.clone(),
             temperature: 0.7,
-        };
-
-        let response = self
-            .client
-            .post(format!("{}/chat/completions", self.api_url))
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&request)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            return Err(AppError::ExternalApiError(
-                "AI API call failed".to_string(),
-            ));
+            stream,
+            stream_options: stream.then_some(StreamOptions { include_usage: true }),
         }
+    }
 
-        // Parse response and extract suggestions
-        let result: serde_json::Value = response.json().await?;
-        let content = result["choices"][0]["message"]["content"]
-            .as_str()
-            .unwrap_or("No response")
-            .to_string();
+    async fn call_ai(&self, prompt: &str) -> AppResult<Vec<String>> {
+        let content = self.complete(prompt).await?;
 
         // Simple parsing - split by newlines
         let suggestions = content
@@ -119,6 +378,257 @@ impl AIService {
 
         Ok(suggestions)
     }
+
+    /// Request a completion and return the full message content
+    async fn complete(&self, prompt: &str) -> AppResult<String> {
+        check_token_budget(self.usage.as_ref(), self.daily_token_budget).await?;
+
+        let request = self.completion_request(prompt, false);
+
+        let response = self
+            .retry
+            .send(
+                self.client
+                    .post(format!("{}/chat/completions", self.api_url))
+                    .header("Authorization", format!("Bearer {}", self.api_key))
+                    .json(&request),
+                &self.breaker,
+            )
+            .await?;
+
+        // Parse response and extract suggestions
+        let result: serde_json::Value = response.json().await?;
+        let content = result["choices"][0]["message"]["content"]
+            .as_str()
+            .unwrap_or("No response")
+            .to_string();
+
+        if let Ok(usage) = serde_json::from_value::<TokenUsage>(result["usage"].clone()) {
+            record_usage(self.usage.as_ref(), &usage).await?;
+        }
+
+        Ok(content)
+    }
+
+    /// Request a streamed completion and yield each content delta in order. The tokens
+    /// are recorded once the stream ends, as reported by the API or else estimated.
+    pub fn call_ai_streaming(
+        &self,
+        prompt: &str,
+    ) -> impl Stream<Item = AppResult<String>> + Send + 'static {
+        let request = self
+            .client
+            .post(format!("{}/chat/completions", self.api_url))
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .json(&self.completion_request(prompt, true));
+        let retry = self.retry;
+        let breaker = self.breaker.clone();
+        let usage = self.usage.clone();
+        let budget = self.daily_token_budget;
+        let prompt_tokens = estimated_tokens(self.prompts.system()) + estimated_tokens(prompt);
+
+        futures::stream::once(async move {
+            // Only establishing the stream is retried; a stream that breaks midway ends with an error
+            check_token_budget(usage.as_ref(), budget).await?;
+            let response = retry.send(request, &breaker).await?;
+            Ok::<_, AppError>(completion_deltas(response.bytes_stream(), usage, prompt_tokens))
+        })
+        .try_flatten()
+    }
+}
+
+/// Split a refactor response into prose suggestions and the refactored code.
+/// The code is the first fenced block, or the whole message when there is none.
+fn parse_refactor_response(content: &str) -> (Vec<String>, String) {
+    let mut suggestions = Vec::new();
+    let mut code: Option<Vec<&str>> = None;
+    let mut in_fence = false;
+    let mut capturing = false;
+
+    for line in content.lines() {
+        if line.trim_start().starts_with("```") {
+            if in_fence {
+                in_fence = false;
+                capturing = false;
+            } else {
+                in_fence = true;
+                // Only the first block is the refactored code
+                if code.is_none() {
+                    code = Some(Vec::new());
+                    capturing = true;
+                }
+            }
+            continue;
+        }
+
+        if in_fence {
+            if capturing {
+                if let Some(lines) = code.as_mut() {
+                    lines.push(line);
+                }
+            }
+        } else if !line.trim().is_empty() {
+            suggestions.push(line.trim().to_string());
+        }
+    }
+
+    let code = match code {
+        Some(lines) => lines.join("\n"),
+        None => content.trim().to_string(),
+    };
+
+    (suggestions, code)
+}
+
+/// Decode an OpenAI-style SSE byte stream into content deltas, charging the tokens to
+/// `usage` when it ends or is dropped. Without a usage chunk, each delta is counted as one token.
+fn completion_deltas<S, B>(
+    bytes: S,
+    usage: Option<UsageContext>,
+    prompt_tokens: u32,
+) -> impl Stream<Item = AppResult<String>> + Send
+where
+    S: Stream<Item = Result<B, reqwest::Error>> + Send,
+    B: AsRef<[u8]>,
+{
+    let metered = MeteredDecoder {
+        decoder: SseDecoder::default(),
+        usage,
+        prompt_tokens,
+    };
+    let state = (Box::pin(bytes), metered, VecDeque::new());
+
+    futures::stream::unfold(state, move |(mut bytes, mut metered, mut pending)| async move {
+        loop {
+            if let Some(item) = pending.pop_front() {
+                return Some((item, (bytes, metered, pending)));
+            }
+            if !metered.decoder.done {
+                match bytes.next().await {
+                    Some(Ok(chunk)) => {
+                        pending.extend(metered.decoder.feed(chunk.as_ref()));
+                        continue;
+                    }
+                    Some(Err(e)) => {
+                        metered.decoder.done = true;
+                        pending.push_back(Err(e.into()));
+                        continue;
+                    }
+                    None => {}
+                }
+            }
+
+            metered.finish().await;
+            return None;
+        }
+    })
+}
+
+/// A stream decoder that charges its tokens exactly once: when the stream ends, or with what
+/// was decoded so far if it's dropped first, e.g. because the client disconnected
+struct MeteredDecoder {
+    decoder: SseDecoder,
+    usage: Option<UsageContext>,
+    prompt_tokens: u32,
+}
+
+impl MeteredDecoder {
+    /// The API's own count when it sent one, otherwise an estimate of one token per delta
+    fn tokens(&mut self) -> TokenUsage {
+        self.decoder.usage.take().unwrap_or(TokenUsage {
+            prompt_tokens: self.prompt_tokens,
+            completion_tokens: self.decoder.deltas,
+        })
+    }
+
+    async fn finish(&mut self) {
+        if let Some(context) = self.usage.take() {
+            let tokens = self.tokens();
+            if let Err(e) = record_usage(Some(&context), &tokens).await {
+                tracing::warn!("Failed to record streamed token usage: {:?}", e);
+            }
+        }
+    }
+}
+
+impl Drop for MeteredDecoder {
+    fn drop(&mut self) {
+        let Some(context) = self.usage.take() else {
+            return;
+        };
+        let tokens = self.tokens();
+
+        // Dropping can't wait on the database, so the charge is recorded in the background
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    if let Err(e) = record_usage(Some(&context), &tokens).await {
+                        tracing::warn!("Failed to record token usage of an abandoned stream: {:?}", e);
+                    }
+                });
+            }
+            Err(_) => tracing::warn!(
+                "Abandoned stream outside a runtime; {} completion tokens not recorded",
+                tokens.completion_tokens
+            ),
+        }
+    }
+}
+
+/// Incremental parser for `data:` lines of a chat completion event stream
+#[derive(Default)]
+struct SseDecoder {
+    buffer: Vec<u8>,
+    done: bool,
+    /// Deltas decoded so far
+    deltas: u32,
+    /// Token counts from the final chunk, when the API sends one
+    usage: Option<TokenUsage>,
+}
+
+impl SseDecoder {
+    /// Consume raw bytes and return the deltas from every complete line
+    fn feed(&mut self, bytes: &[u8]) -> Vec<AppResult<String>> {
+        self.buffer.extend_from_slice(bytes);
+        let mut deltas = Vec::new();
+
+        while let Some(newline) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            if self.done {
+                continue;
+            }
+
+            let line = String::from_utf8_lossy(&line);
+            let Some(data) = line.trim_end().strip_prefix("data:") else {
+                continue;
+            };
+            let data = data.trim_start();
+
+            if data == "[DONE]" {
+                self.done = true;
+                continue;
+            }
+
+            match serde_json::from_str::<serde_json::Value>(data) {
+                Ok(event) => {
+                    if let Some(content) = event["choices"][0]["delta"]["content"].as_str() {
+                        if !content.is_empty() {
+                            self.deltas += 1;
+                            deltas.push(Ok(content.to_string()));
+                        }
+                    }
+                    if let Ok(usage) = serde_json::from_value::<TokenUsage>(event["usage"].clone()) {
+                        self.usage = Some(usage);
+                    }
+                }
+                Err(_) => deltas.push(Err(AppError::ExternalApiError(
+                    "Malformed chunk in AI response stream".to_string(),
+                ))),
+            }
+        }
+
+        deltas
+    }
 }
 
 #[cfg(test)]
@@ -130,4 +640,351 @@ mod tests {
         let service = AIService::new();
         assert!(!service.api_key.is_empty() || service.api_key.is_empty()); // Just check it exists
     }
+
+    #[test]
+    fn test_requests_pair_system_and_user_messages() {
+        let prompts = PromptTemplates::default().with_system_prompt("Answer in haiku.");
+        let service = AIService::with_endpoint("http://localhost", "test-key").with_prompt_templates(Arc::new(prompts));
+
+        let request = service.completion_request("Review this", false);
+        let messages: Vec<(&str, &str)> = request
+            .messages
+            .iter()
+            .map(|message| (message.role.as_str(), message.content.as_str()))
+            .collect();
+        assert_eq!(messages, vec![("system", "Answer in haiku."), ("user", "Review this")]);
+    }
+
+    fn delta_event(content: &str) -> String {
+        format!(
+            "data: {}\n\n",
+            serde_json::json!({ "choices": [{ "delta": { "content": content } }] })
+        )
+    }
+
+    #[test]
+    fn test_sse_decoder_handles_split_lines() {
+        let stream = format!(
+            "{}{}data: [DONE]\n\n",
+            delta_event("Hello"),
+            delta_event(", wörld")
+        );
+        let bytes = stream.as_bytes();
+
+        let mut decoder = SseDecoder::default();
+        let mut deltas = Vec::new();
+        // Feed a few bytes at a time so lines and UTF-8 sequences are split
+        for chunk in bytes.chunks(7) {
+            deltas.extend(decoder.feed(chunk).into_iter().map(Result::unwrap));
+        }
+
+        assert_eq!(deltas, vec!["Hello", ", wörld"]);
+        assert!(decoder.done);
+    }
+
+    #[tokio::test]
+    async fn test_streaming_chunks_arrive_in_order() {
+        use axum::{http::header, routing::post, Router};
+
+        let body = format!(
+            ": keep-alive\n\n{}{}{}data: [DONE]\n\n",
+            delta_event("fn "),
+            delta_event("main"),
+            delta_event("() {}")
+        );
+        let app = Router::new().route(
+            "/chat/completions",
+            post(move || async move { ([(header::CONTENT_TYPE, "text/event-stream")], body) }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        let service = AIService::with_endpoint(format!("http://{}", addr), "test-key");
+        let chunks: Vec<String> = service
+            .call_ai_streaming("write main")
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert_eq!(chunks, vec!["fn ", "main", "() {}"]);
+    }
+
+    /// Serve `/chat/completions` with the given status codes in order, then 200
+    async fn flaky_server(
+        failures: Vec<u16>,
+    ) -> (String, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+        use axum::{http::StatusCode, routing::post, Json, Router};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let server_calls = calls.clone();
+        let app = Router::new().route(
+            "/chat/completions",
+            post(move || {
+                let calls = server_calls.clone();
+                let failures = failures.clone();
+                async move {
+                    let call = calls.fetch_add(1, Ordering::SeqCst);
+                    match failures.get(call) {
+                        Some(&code) => (
+                            StatusCode::from_u16(code).unwrap(),
+                            [("retry-after", "0")],
+                            Json(serde_json::json!({ "error": "unavailable" })),
+                        ),
+                        None => (
+                            StatusCode::OK,
+                            [("retry-after", "0")],
+                            Json(serde_json::json!({
+                                "choices": [{ "message": { "content": "Use an iterator" } }]
+                            })),
+                        ),
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        (format!("http://{}", addr), calls)
+    }
+
+    fn fast_retries() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_rate_limited_requests() {
+        let (url, calls) = flaky_server(vec![429, 429]).await;
+        let service = AIService::with_endpoint(url, "test-key").with_retry_policy(fast_retries());
+
+        let suggestions = service.call_ai("optimize").await.unwrap();
+
+        assert_eq!(suggestions, vec!["Use an iterator"]);
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_fail_fast() {
+        let (url, calls) = flaky_server(vec![401]).await;
+        let service = AIService::with_endpoint(url, "test-key").with_retry_policy(fast_retries());
+
+        let result = service.call_ai("optimize").await;
+
+        assert!(matches!(result, Err(AppError::ExternalApiError(msg)) if msg.contains("401")));
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_retries() {
+        let (url, calls) = flaky_server(vec![503, 503, 503, 503, 503]).await;
+        let service = AIService::with_endpoint(url, "test-key").with_retry_policy(fast_retries());
+
+        let result = service.call_ai("optimize").await;
+
+        assert!(matches!(result, Err(AppError::ExternalApiError(msg)) if msg.contains("503")));
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_open_breaker_fails_fast() {
+        use crate::services::circuit_breaker::CircuitState;
+        use std::sync::atomic::Ordering;
+
+        let (url, calls) = flaky_server(vec![503, 503, 503]).await;
+        let breaker = Arc::new(CircuitBreaker::new(BreakerSettings {
+            failure_threshold: 2,
+            cooldown: Duration::from_millis(50),
+        }));
+        let service = AIService::with_endpoint(url, "test-key")
+            .with_retry_policy(RetryPolicy { max_retries: 0, base_delay: Duration::from_millis(1) })
+            .with_circuit_breaker(breaker.clone());
+
+        for _ in 0..2 {
+            assert!(matches!(service.call_ai("optimize").await, Err(AppError::ExternalApiError(msg)) if msg.contains("503")));
+        }
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        // Refused without reaching the API
+        for _ in 0..3 {
+            let result = service.call_ai("optimize").await;
+            assert!(matches!(result, Err(AppError::ExternalApiError(msg)) if msg == "AI unavailable"));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // After the cooldown a failed probe reopens it, and a successful one closes it
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(service.call_ai("optimize").await.is_err());
+        assert_eq!(breaker.state(), CircuitState::Open);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(service.call_ai("optimize").await.unwrap(), vec!["Use an iterator"]);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_backoff_grows_and_is_capped() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            base_delay: Duration::from_millis(100),
+        };
+
+        let first = policy.backoff(0);
+        assert!(first >= Duration::from_millis(100) && first < Duration::from_millis(150));
+        let third = policy.backoff(2);
+        assert!(third >= Duration::from_millis(400) && third < Duration::from_millis(600));
+        assert!(policy.backoff(20) <= MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn test_parse_refactor_response() {
+        let response = "Here is a cleaner version:\n\n```rust\nfn total(xs: &[i32]) -> i32 {\n    xs.iter().sum()\n}\n```\n\n- Replaced the manual loop with `sum`\n- Took a slice instead of a Vec\n\n```text\nextra block\n```\n";
+
+        let (suggestions, code) = parse_refactor_response(response);
+
+        assert_eq!(code, "fn total(xs: &[i32]) -> i32 {\n    xs.iter().sum()\n}");
+        assert_eq!(
+            suggestions,
+            vec![
+                "Here is a cleaner version:",
+                "- Replaced the manual loop with `sum`",
+                "- Took a slice instead of a Vec",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_refactor_response_without_fence() {
+        let (suggestions, code) = parse_refactor_response("  fn main() {}\n");
+
+        assert_eq!(code, "fn main() {}");
+        assert_eq!(suggestions, vec!["fn main() {}"]);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_calls_are_rejected_once_budget_is_spent() {
+        use axum::{routing::post, Json, Router};
+
+        let db = crate::db::test_database().await;
+        let user_id = crate::db::insert_test_user(db.pool()).await;
+
+        let app = Router::new().route(
+            "/chat/completions",
+            post(|| async {
+                Json(serde_json::json!({
+                    "choices": [{ "message": { "content": "Looks good" } }],
+                    "usage": { "prompt_tokens": 60, "completion_tokens": 50, "total_tokens": 110 }
+                }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        let service = AIService::with_endpoint(format!("http://{}", addr), "test-key")
+            .for_user(db.pool().clone(), user_id)
+            .with_daily_token_budget(100);
+
+        assert!(service.call_ai("review").await.is_ok());
+        assert_eq!(tokens_used_today(db.pool(), user_id).await.unwrap(), (60, 50));
+
+        assert!(matches!(
+            service.call_ai("review").await,
+            Err(AppError::ValidationError(msg)) if msg == "token budget exceeded"
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_streamed_calls_are_charged_to_the_budget() {
+        use axum::{http::header, routing::post, Json, Router};
+
+        let db = crate::db::test_database().await;
+        let user_id = crate::db::insert_test_user(db.pool()).await;
+
+        // Only the first stream reports its usage; the second is estimated
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let app = Router::new().route(
+            "/chat/completions",
+            post(move |Json(request): Json<AIRequest>| {
+                let call = calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                async move {
+                    assert!(request.stream_options.is_some_and(|options| options.include_usage));
+                    let usage = serde_json::json!({
+                        "choices": [],
+                        "usage": { "prompt_tokens": 30, "completion_tokens": 20, "total_tokens": 50 }
+                    });
+                    let body = format!(
+                        "{}{}{}data: [DONE]\n\n",
+                        delta_event("Looks "),
+                        delta_event("good"),
+                        if call == 0 { format!("data: {}\n\n", usage) } else { String::new() }
+                    );
+                    ([(header::CONTENT_TYPE, "text/event-stream")], body)
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        let prompts = PromptTemplates::default().with_system_prompt("Be brief.");
+        let service = AIService::with_endpoint(format!("http://{}", addr), "test-key")
+            .with_prompt_templates(Arc::new(prompts))
+            .for_user(db.pool().clone(), user_id)
+            .with_daily_token_budget(55);
+
+        let chunks: Vec<String> = service.call_ai_streaming("review").try_collect().await.unwrap();
+        assert_eq!(chunks, vec!["Looks ", "good"]);
+        assert_eq!(tokens_used_today(db.pool(), user_id).await.unwrap(), (30, 20));
+
+        let chunks: Vec<String> = service.call_ai_streaming("review").try_collect().await.unwrap();
+        assert_eq!(chunks, vec!["Looks ", "good"]);
+        assert_eq!(tokens_used_today(db.pool(), user_id).await.unwrap(), (30 + 3 + 2, 20 + 2));
+
+        let result: AppResult<Vec<String>> = service.call_ai_streaming("review").try_collect().await;
+        assert!(matches!(result, Err(AppError::ValidationError(msg)) if msg == "token budget exceeded"));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_abandoned_streams_are_charged_for_what_was_sent() {
+        let db = crate::db::test_database().await;
+        let user_id = crate::db::insert_test_user(db.pool()).await;
+        let context = UsageContext { pool: db.pool().clone(), user_id };
+
+        // The client goes away after the first delta, before the API's usage chunk arrives
+        let chunks: Vec<Result<String, reqwest::Error>> = vec![
+            Ok(delta_event("Looks ")),
+            Ok(delta_event("good")),
+            Ok("data: [DONE]\n\n".to_string()),
+        ];
+        let mut stream = Box::pin(completion_deltas(futures::stream::iter(chunks), Some(context), 12));
+        assert_eq!(stream.next().await.unwrap().unwrap(), "Looks ");
+        drop(stream);
+
+        let mut used = (0, 0);
+        for _ in 0..50 {
+            used = tokens_used_today(db.pool(), user_id).await.unwrap();
+            if used != (0, 0) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(used, (12, 1));
+    }
 }
@@ -0,0 +1,122 @@
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::time::Duration;
+
+use crate::error::AppResult;
+use crate::services::prompts::PromptTask;
+use crate::utils::crypto;
+
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long a cached AI answer is reused, from `ANALYSIS_CACHE_TTL_SECS`. Zero turns the
+/// cache off.
+pub fn analysis_cache_ttl() -> Duration {
+    std::env::var("ANALYSIS_CACHE_TTL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_CACHE_TTL)
+}
+
+/// Identifies an AI answer by what was asked: the code's content hash, the language it was
+/// analyzed as, the task and the model that answered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnalysisCacheKey {
+    pub code_hash: String,
+    pub language: String,
+    pub task: PromptTask,
+    pub model: String,
+}
+
+impl AnalysisCacheKey {
+    pub fn new(code: &str, language: &str, task: PromptTask, model: &str) -> Self {
+        AnalysisCacheKey {
+            code_hash: crypto::hash_token(code),
+            language: language.to_string(),
+            task,
+            model: model.to_string(),
+        }
+    }
+}
+
+/// The AI's part of an analysis. The static analysis is cheap and always rerun.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedAnalysis {
+    pub suggestions: Vec<String>,
+    #[serde(default)]
+    pub refactored_code: Option<String>,
+}
+
+/// Content-addressed store of AI answers, so resubmitting identical code doesn't spend
+/// tokens on the same question again
+#[derive(Clone)]
+pub struct AnalysisCache {
+    pool: PgPool,
+    ttl: Duration,
+}
+
+impl AnalysisCache {
+    pub fn new(pool: PgPool, ttl: Duration) -> Self {
+        AnalysisCache { pool, ttl }
+    }
+
+    pub fn from_env(pool: PgPool) -> Self {
+        Self::new(pool, analysis_cache_ttl())
+    }
+
+    fn enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    /// The stored answer for `key`, unless it has expired
+    pub async fn get(&self, key: &AnalysisCacheKey) -> AppResult<Option<CachedAnalysis>> {
+        if !self.enabled() {
+            return Ok(None);
+        }
+
+        let result = sqlx::query_scalar::<_, serde_json::Value>(
+            r#"
+            SELECT result FROM analysis_cache
+            WHERE code_hash = $1 AND language = $2 AND task_type = $3 AND model = $4
+            AND expires_at > NOW()
+            "#,
+        )
+        .bind(&key.code_hash)
+        .bind(&key.language)
+        .bind(key.task.as_str())
+        .bind(&key.model)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        // An entry written by an older release that no longer parses is treated as a miss
+        Ok(result.and_then(|result| serde_json::from_value(result).ok()))
+    }
+
+    /// Store a fresh answer, replacing any earlier one for the same key
+    pub async fn put(&self, key: &AnalysisCacheKey, analysis: &CachedAnalysis) -> AppResult<()> {
+        if !self.enabled() {
+            return Ok(());
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO analysis_cache (code_hash, language, task_type, model, result, expires_at)
+            VALUES ($1, $2, $3, $4, $5, NOW() + make_interval(secs => $6))
+            ON CONFLICT (code_hash, language, task_type, model) DO UPDATE SET
+            result = EXCLUDED.result,
+            created_at = NOW(),
+            expires_at = EXCLUDED.expires_at
+            "#,
+        )
+        .bind(&key.code_hash)
+        .bind(&key.language)
+        .bind(key.task.as_str())
+        .bind(&key.model)
+        .bind(serde_json::json!(analysis))
+        .bind(self.ttl.as_secs_f64())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use crate::error::AppResult;
+use crate::services::events::{DomainEvent, EventBus};
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
 
@@ -15,6 +16,9 @@ pub struct AnalyticsEvent {
 pub struct AnalyticsReport {
     pub report_id: String,
     pub generated_at: DateTime<Utc>,
+    /// The window the metrics cover; `period_end` is exclusive
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
     pub metrics: ReportMetrics,
 }
 
@@ -45,37 +49,72 @@ impl AnalyticsService {
         user_id: &str,
         metadata: serde_json::Value,
     ) -> AppResult<()> {
-        let event = AnalyticsEvent {
+        self.record(AnalyticsEvent {
             event_type: event_type.to_string(),
             project_id: project_id.to_string(),
             user_id: user_id.to_string(),
             timestamp: Utc::now(),
             metadata,
-        };
+        });
+        Ok(())
+    }
 
+    /// Record an event that happened earlier, keeping its timestamp
+    pub fn record(&self, event: AnalyticsEvent) {
         self.events.lock().push(event);
-        Ok(())
     }
 
-    pub fn generate_report(&self) -> AppResult<AnalyticsReport> {
+    /// Summarize the events in `[from, to)`. An event failed when its metadata `status`
+    /// is `failed`; response times come from `duration_ms` where events carry one.
+    pub fn generate_report(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> AppResult<AnalyticsReport> {
         let events = self.events.lock();
+        let in_window: Vec<&AnalyticsEvent> = events
+            .iter()
+            .filter(|event| event.timestamp >= from && event.timestamp < to)
+            .collect();
 
-        let total_requests = events.len() as u64;
+        let total_requests = in_window.len() as u64;
+        let failed = in_window
+            .iter()
+            .filter(|event| event.metadata.get("status").and_then(|s| s.as_str()) == Some("failed"))
+            .count() as u64;
         let success_rate = if total_requests > 0 {
-            (total_requests - 1) as f64 / total_requests as f64 * 100.0
+            (total_requests - failed) as f64 / total_requests as f64 * 100.0
         } else {
             100.0
         };
 
+        let durations: Vec<f64> = in_window
+            .iter()
+            .filter_map(|event| event.metadata.get("duration_ms").and_then(|d| d.as_f64()))
+            .collect();
+        let avg_response_time_ms = if durations.is_empty() {
+            0.0
+        } else {
+            durations.iter().sum::<f64>() / durations.len() as f64
+        };
+
+        let total_code_analyzed = in_window
+            .iter()
+            .filter(|event| event.event_type == "code_analysis")
+            .count() as u64;
+        let agent_types: std::collections::HashSet<&str> = in_window
+            .iter()
+            .filter(|event| event.event_type == "agent_task")
+            .filter_map(|event| event.metadata.get("agent_type").and_then(|a| a.as_str()))
+            .collect();
+
         Ok(AnalyticsReport {
             report_id: uuid::Uuid::new_v4().to_string(),
             generated_at: Utc::now(),
+            period_start: from,
+            period_end: to,
             metrics: ReportMetrics {
                 total_requests,
                 success_rate,
-                avg_response_time_ms: 125.5,
-                total_code_analyzed: (total_requests * 100) as u64,
-                active_agents: 3,
+                avg_response_time_ms,
+                total_code_analyzed,
+                active_agents: agent_types.len() as u32,
             },
         })
     }
@@ -85,6 +124,27 @@ impl AnalyticsService {
     }
 }
 
+/// Count every domain event in `analytics_metrics`, under its name with a value of 1,
+/// so `GET /analytics/metrics` can chart them
+pub fn subscribe(bus: &EventBus, pool: sqlx::PgPool) {
+    bus.spawn_subscriber("analytics", move |event: DomainEvent| {
+        let pool = pool.clone();
+        async move {
+            let recorded = sqlx::query(
+                "INSERT INTO analytics_metrics (id, metric_type, value, metadata) VALUES ($1, $2, 1, $3)"
+            )
+            .bind(uuid::Uuid::new_v4())
+            .bind(event.name())
+            .bind(serde_json::json!(event))
+            .execute(&pool)
+            .await;
+            if let Err(e) = recorded {
+                tracing::error!("Failed to record {} metric: {:?}", event.name(), e);
+            }
+        }
+    });
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -106,7 +166,36 @@ mod tests {
     fn test_report_generation() {
         let service = AnalyticsService::new();
         let _ = service.record_event("test", "p1", "u1", serde_json::json!({}));
-        let report = service.generate_report().unwrap();
+        let now = Utc::now();
+        let report = service
+            .generate_report(now - chrono::Duration::hours(1), now + chrono::Duration::hours(1))
+            .unwrap();
         assert!(report.metrics.success_rate > 0.0);
     }
+
+    #[test]
+    fn test_report_covers_only_its_window() {
+        let service = AnalyticsService::new();
+        let start: DateTime<Utc> = "2024-03-01T00:00:00Z".parse().unwrap();
+        let event = |event_type: &str, minutes: i64, metadata: serde_json::Value| AnalyticsEvent {
+            event_type: event_type.to_string(),
+            project_id: "p1".to_string(),
+            user_id: "u1".to_string(),
+            timestamp: start + chrono::Duration::minutes(minutes),
+            metadata,
+        };
+        service.record(event("code_analysis", 0, serde_json::json!({"status": "completed", "duration_ms": 100})));
+        service.record(event("code_analysis", 10, serde_json::json!({"status": "failed", "duration_ms": 300})));
+        service.record(event("agent_task", 20, serde_json::json!({"status": "completed", "agent_type": "qa"})));
+        service.record(event("agent_task", 30, serde_json::json!({"status": "queued", "agent_type": "qa"})));
+        service.record(event("agent_task", 60, serde_json::json!({"status": "failed", "agent_type": "backend"})));
+
+        let report = service.generate_report(start, start + chrono::Duration::minutes(60)).unwrap();
+        assert_eq!(report.period_start, start);
+        assert_eq!(report.metrics.total_requests, 4);
+        assert_eq!(report.metrics.success_rate, 75.0);
+        assert_eq!(report.metrics.avg_response_time_ms, 200.0);
+        assert_eq!(report.metrics.total_code_analyzed, 2);
+        assert_eq!(report.metrics.active_agents, 1);
+    }
 }
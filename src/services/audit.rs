@@ -0,0 +1,158 @@
+use async_trait::async_trait;
+use axum::{
+    extract::FromRequestParts,
+    http::{header::USER_AGENT, request::Parts},
+};
+use chrono::Utc;
+use sqlx::PgExecutor;
+use std::convert::Infallible;
+use uuid::Uuid;
+
+use crate::middleware::rate_limit::request_ip;
+
+/// Where a request came from, recorded with every change it makes. The address is the
+/// one rate limiting resolved, so `X-Forwarded-For` only counts from trusted proxies.
+/// Missing pieces stay `None` rather than rejecting the request.
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(ClientInfo {
+            ip_address: request_ip(&parts.extensions).map(|ip| ip.to_string()),
+            user_agent: parts
+                .headers
+                .get(USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+        })
+    }
+}
+
+/// One change to one resource, with its state before and after where that applies
+#[derive(Debug, Clone)]
+pub struct AuditEntry<'a> {
+    pub actor_id: Uuid,
+    pub action: &'a str,
+    pub resource_type: &'a str,
+    pub resource_id: Uuid,
+    pub old_value: Option<serde_json::Value>,
+    pub new_value: Option<serde_json::Value>,
+}
+
+impl<'a> AuditEntry<'a> {
+    pub fn new(actor_id: Uuid, action: &'a str, resource_type: &'a str, resource_id: Uuid) -> Self {
+        Self {
+            actor_id,
+            action,
+            resource_type,
+            resource_id,
+            old_value: None,
+            new_value: None,
+        }
+    }
+
+    pub fn old_value(mut self, value: serde_json::Value) -> Self {
+        self.old_value = Some(value);
+        self
+    }
+
+    pub fn new_value(mut self, value: serde_json::Value) -> Self {
+        self.new_value = Some(value);
+        self
+    }
+}
+
+/// Write `entry` to `audit_logs`. Pass the transaction making the change, so the change
+/// and its audit row commit or roll back together.
+pub async fn record(executor: impl PgExecutor<'_>, client: &ClientInfo, entry: AuditEntry<'_>) -> sqlx::Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO audit_logs (id, actor_id, action, resource_type, resource_id, old_value, new_value, ip_address, user_agent, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(entry.actor_id)
+    .bind(entry.action)
+    .bind(entry.resource_type)
+    .bind(entry.resource_id)
+    .bind(entry.old_value)
+    .bind(entry.new_value)
+    .bind(&client.ip_address)
+    .bind(&client.user_agent)
+    .bind(Utc::now())
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::connect_info::ConnectInfo, http::Request};
+    use std::net::SocketAddr;
+
+    async fn client_info(request: Request<()>) -> ClientInfo {
+        let (mut parts, _) = request.into_parts();
+        ClientInfo::from_request_parts(&mut parts, &()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_client_info_ignores_forwarded_for_from_untrusted_peers() {
+        let mut request = Request::builder()
+            .header("x-forwarded-for", "203.0.113.7")
+            .header(USER_AGENT, "cx7/1.0")
+            .body(())
+            .unwrap();
+        request.extensions_mut().insert(ConnectInfo(SocketAddr::from(([192, 0, 2, 9], 4000))));
+        let info = client_info(request).await;
+        assert_eq!(info.ip_address.as_deref(), Some("192.0.2.9"));
+        assert_eq!(info.user_agent.as_deref(), Some("cx7/1.0"));
+
+        let info = client_info(Request::builder().body(()).unwrap()).await;
+        assert_eq!(info.ip_address, None);
+        assert_eq!(info.user_agent, None);
+    }
+
+    #[tokio::test]
+    async fn test_client_info_uses_the_address_behind_trusted_proxies() {
+        use crate::middleware::rate_limit::{rate_limit_middleware, RateLimiter, RateLimits, TrustedProxies};
+        use axum::{body::Body, extract::connect_info::MockConnectInfo, middleware, routing::get, Router};
+        use std::sync::Arc;
+        use tower::ServiceExt;
+
+        let limits = RateLimits {
+            default: RateLimiter::new(0),
+            auth: RateLimiter::new(0),
+            analysis: RateLimiter::new(0),
+            trusted_proxies: TrustedProxies::parse(&["10.0.0.0/8".to_string()]).unwrap(),
+        };
+        let app = Router::new()
+            .route("/", get(|info: ClientInfo| async move { info.ip_address.unwrap_or_default() }))
+            .layer(middleware::from_fn_with_state(Arc::new(limits), rate_limit_middleware))
+            .layer(MockConnectInfo(SocketAddr::from(([10, 0, 0, 1], 443))));
+        let recorded_ip = |forwarded: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = Request::builder().uri("/").header("x-forwarded-for", forwarded).body(Body::empty()).unwrap();
+                let body = axum::body::to_bytes(app.oneshot(request).await.unwrap().into_body(), usize::MAX).await.unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+
+        assert_eq!(recorded_ip("203.0.113.7, 10.0.0.2").await, "203.0.113.7");
+        // An address the client put in front of its own is not believed
+        assert_eq!(recorded_ip("198.51.100.66, 203.0.113.7").await, "203.0.113.7");
+    }
+}
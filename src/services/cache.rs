@@ -0,0 +1,267 @@
+use async_trait::async_trait;
+use metrics::counter;
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Where a `Cache` keeps its entries. Implemented in memory here; a shared store can take
+/// its place so every instance sees the same entries.
+#[async_trait]
+pub trait CacheBackend<K, V>: Send + Sync {
+    /// The value stored under `key`, unless it has expired
+    async fn get(&self, key: &K) -> Option<V>;
+
+    /// Store `value` under `key` until `ttl` has passed, replacing any earlier value
+    async fn insert(&self, key: K, value: V, ttl: Duration);
+
+    async fn remove(&self, key: &K);
+
+    async fn clear(&self);
+}
+
+struct MemoryEntry<V> {
+    value: V,
+    expires_at: Instant,
+    /// Position in the recency order; higher is more recent
+    used: u64,
+}
+
+struct MemoryState<K, V> {
+    entries: HashMap<K, MemoryEntry<V>>,
+    /// Keys by when they were last used, least recent first
+    recency: BTreeMap<u64, K>,
+    clock: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> MemoryState<K, V> {
+    fn touch(&mut self, key: &K) {
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.recency.remove(&entry.used);
+            entry.used = clock;
+            self.recency.insert(clock, key.clone());
+        }
+    }
+
+    fn remove(&mut self, key: &K) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.used);
+        }
+    }
+}
+
+/// In-process backend that holds at most `capacity` entries, evicting the least recently
+/// used to make room
+pub struct MemoryBackend<K, V> {
+    capacity: usize,
+    state: Mutex<MemoryState<K, V>>,
+}
+
+impl<K: Hash + Eq + Clone, V> MemoryBackend<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        MemoryBackend {
+            capacity: capacity.max(1),
+            state: Mutex::new(MemoryState {
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                clock: 0,
+            }),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.state.lock().entries.len()
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[async_trait]
+impl<K, V> CacheBackend<K, V> for MemoryBackend<K, V>
+where
+    K: Hash + Eq + Clone + Send + Sync,
+    V: Clone + Send + Sync,
+{
+    async fn get(&self, key: &K) -> Option<V> {
+        let mut state = self.state.lock();
+        match state.entries.get(key) {
+            Some(entry) if entry.expires_at > Instant::now() => {
+                let value = entry.value.clone();
+                state.touch(key);
+                Some(value)
+            }
+            Some(_) => {
+                state.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    async fn insert(&self, key: K, value: V, ttl: Duration) {
+        let mut state = self.state.lock();
+        state.remove(&key);
+
+        // Expired entries go first, then the least recently used
+        if state.entries.len() >= self.capacity {
+            let now = Instant::now();
+            state.entries.retain(|_, entry| entry.expires_at > now);
+            let live: std::collections::HashSet<u64> = state.entries.values().map(|entry| entry.used).collect();
+            state.recency.retain(|used, _| live.contains(used));
+        }
+        while state.entries.len() >= self.capacity {
+            let Some((_, oldest)) = state.recency.pop_first() else {
+                break;
+            };
+            state.entries.remove(&oldest);
+        }
+
+        state.clock += 1;
+        let used = state.clock;
+        state.recency.insert(used, key.clone());
+        state.entries.insert(
+            key,
+            MemoryEntry {
+                value,
+                expires_at: Instant::now() + ttl,
+                used,
+            },
+        );
+    }
+
+    async fn remove(&self, key: &K) {
+        self.state.lock().remove(key);
+    }
+
+    async fn clear(&self) {
+        let mut state = self.state.lock();
+        state.entries.clear();
+        state.recency.clear();
+    }
+}
+
+/// A named cache with one TTL for every entry. Hits and misses are counted in the
+/// `cache_requests_total` metric, labelled with the cache's name.
+pub struct Cache<K, V> {
+    name: &'static str,
+    ttl: Duration,
+    backend: Arc<dyn CacheBackend<K, V>>,
+}
+
+impl<K, V> Cache<K, V>
+where
+    K: Hash + Eq + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    /// An in-process cache of at most `capacity` entries
+    pub fn in_memory(name: &'static str, capacity: usize, ttl: Duration) -> Self {
+        Self::with_backend(name, ttl, Arc::new(MemoryBackend::new(capacity)))
+    }
+}
+
+impl<K, V> Cache<K, V> {
+    pub fn with_backend(name: &'static str, ttl: Duration, backend: Arc<dyn CacheBackend<K, V>>) -> Self {
+        Cache {
+            name,
+            ttl,
+            backend,
+        }
+    }
+
+    pub async fn get(&self, key: &K) -> Option<V> {
+        let value = self.backend.get(key).await;
+        let result = if value.is_some() { "hit" } else { "miss" };
+        counter!("cache_requests_total", "cache" => self.name, "result" => result).increment(1);
+
+        value
+    }
+
+    pub async fn insert(&self, key: K, value: V) {
+        self.backend.insert(key, value, self.ttl).await;
+    }
+
+    pub async fn remove(&self, key: &K) {
+        self.backend.remove(key).await;
+    }
+
+    pub async fn clear(&self) {
+        self.backend.clear().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::install_recorder;
+
+    /// `cache_requests_total` for one cache and result, as `/metrics` reports it
+    fn requests(cache: &str, result: &str) -> f64 {
+        let prefix = format!(r#"cache_requests_total{{cache="{}",result="{}"}} "#, cache, result);
+        install_recorder()
+            .render()
+            .lines()
+            .find_map(|line| line.strip_prefix(&prefix))
+            .map(|value| value.parse().unwrap())
+            .unwrap_or(0.0)
+    }
+
+    #[tokio::test]
+    async fn test_least_recently_used_entry_is_evicted_at_capacity() {
+        install_recorder();
+        let backend = Arc::new(MemoryBackend::new(2));
+        let cache: Cache<&str, u32> = Cache::with_backend("lru_test", Duration::from_secs(60), backend.clone());
+
+        cache.insert("a", 1).await;
+        cache.insert("b", 2).await;
+        // Reading "a" makes "b" the least recently used
+        assert_eq!(cache.get(&"a").await, Some(1));
+        cache.insert("c", 3).await;
+
+        assert_eq!(backend.len(), 2);
+        assert_eq!(cache.get(&"b").await, None);
+        assert_eq!(cache.get(&"a").await, Some(1));
+        assert_eq!(cache.get(&"c").await, Some(3));
+
+        // Replacing a key doesn't evict anything else
+        cache.insert("c", 4).await;
+        assert_eq!(backend.len(), 2);
+        assert_eq!(cache.get(&"a").await, Some(1));
+        assert_eq!(cache.get(&"c").await, Some(4));
+
+        assert_eq!(requests("lru_test", "hit"), 5.0);
+        assert_eq!(requests("lru_test", "miss"), 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_entries_expire_after_ttl() {
+        install_recorder();
+        let backend = Arc::new(MemoryBackend::new(2));
+        let cache: Cache<u32, &str> = Cache::with_backend("ttl_test", Duration::from_millis(20), backend.clone());
+
+        cache.insert(1, "one").await;
+        assert_eq!(cache.get(&1).await, Some("one"));
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(cache.get(&1).await, None);
+        assert_eq!(backend.len(), 0);
+
+        // An expired entry makes room before a live one is evicted, even a less recent one
+        let fresh: Cache<u32, &str> = Cache::with_backend("ttl_fresh_test", Duration::from_secs(60), backend.clone());
+        fresh.insert(3, "three").await;
+        cache.insert(2, "two").await;
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        fresh.insert(4, "four").await;
+        assert_eq!(fresh.get(&3).await, Some("three"));
+        assert_eq!(fresh.get(&4).await, Some("four"));
+
+        assert_eq!(requests("ttl_test", "hit"), 1.0);
+        assert_eq!(requests("ttl_test", "miss"), 1.0);
+    }
+}
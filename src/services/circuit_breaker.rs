@@ -0,0 +1,190 @@
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// When a breaker opens and how long it stays open
+#[derive(Debug, Clone, Copy)]
+pub struct BreakerSettings {
+    /// Consecutive failures that open the breaker
+    pub failure_threshold: u32,
+    /// How long calls are refused before one is let through to probe for recovery
+    pub cooldown: Duration,
+}
+
+impl Default for BreakerSettings {
+    fn default() -> Self {
+        BreakerSettings {
+            failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+            cooldown: DEFAULT_COOLDOWN,
+        }
+    }
+}
+
+impl BreakerSettings {
+    /// `AI_BREAKER_FAILURE_THRESHOLD` and `AI_BREAKER_COOLDOWN_SECS`, falling back to the defaults
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        BreakerSettings {
+            failure_threshold: std::env::var("AI_BREAKER_FAILURE_THRESHOLD")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|threshold| *threshold > 0)
+                .unwrap_or(defaults.failure_threshold),
+            cooldown: std::env::var("AI_BREAKER_COOLDOWN_SECS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.cooldown),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// Calls go through
+    Closed,
+    /// Calls are refused until the cooldown ends
+    Open,
+    /// The cooldown is over and a single probe call is finding out whether to close again
+    HalfOpen,
+}
+
+#[derive(Debug)]
+enum Inner {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { probe_started: Instant },
+}
+
+/// Stops calling a dependency that keeps failing, so callers fail fast instead of queueing
+/// up behind timeouts and retries
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    settings: BreakerSettings,
+    inner: Mutex<Inner>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(BreakerSettings::default())
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(settings: BreakerSettings) -> Self {
+        CircuitBreaker {
+            settings,
+            inner: Mutex::new(Inner::Closed { failures: 0 }),
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        match *self.inner.lock() {
+            Inner::Closed { .. } => CircuitState::Closed,
+            Inner::Open { until } if Instant::now() >= until => CircuitState::HalfOpen,
+            Inner::Open { .. } => CircuitState::Open,
+            Inner::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Whether a call may go ahead now. Once the cooldown is over, one caller at a time is
+    /// let through as the probe.
+    pub fn try_acquire(&self) -> bool {
+        let mut inner = self.inner.lock();
+        let now = Instant::now();
+        let probe_due = match *inner {
+            Inner::Closed { .. } => return true,
+            Inner::Open { until } => now >= until,
+            // A probe that never reported back, because its caller went away, is replaced
+            // after another cooldown
+            Inner::HalfOpen { probe_started } => now >= probe_started + self.settings.cooldown,
+        };
+
+        if probe_due {
+            *inner = Inner::HalfOpen { probe_started: now };
+        }
+        probe_due
+    }
+
+    pub fn record_success(&self) {
+        *self.inner.lock() = Inner::Closed { failures: 0 };
+    }
+
+    pub fn record_failure(&self) {
+        let mut inner = self.inner.lock();
+        let failures = match *inner {
+            Inner::Closed { failures } => failures + 1,
+            // A failed probe, or a late failure from before the breaker opened
+            Inner::Open { .. } | Inner::HalfOpen { .. } => self.settings.failure_threshold,
+        };
+
+        *inner = if failures >= self.settings.failure_threshold {
+            if matches!(*inner, Inner::Closed { .. }) {
+                tracing::warn!("Circuit breaker opened after {} consecutive failures", failures);
+            }
+            Inner::Open { until: Instant::now() + self.settings.cooldown }
+        } else {
+            Inner::Closed { failures }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(cooldown: Duration) -> CircuitBreaker {
+        CircuitBreaker::new(BreakerSettings {
+            failure_threshold: 3,
+            cooldown,
+        })
+    }
+
+    #[test]
+    fn test_opens_after_consecutive_failures() {
+        let breaker = breaker(Duration::from_secs(60));
+
+        breaker.record_failure();
+        breaker.record_failure();
+        // A success in between starts the count again
+        breaker.record_success();
+        breaker.record_failure();
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.try_acquire());
+
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.try_acquire());
+    }
+
+    #[test]
+    fn test_half_opens_for_a_single_probe() {
+        let breaker = breaker(Duration::from_millis(20));
+        for _ in 0..3 {
+            breaker.record_failure();
+        }
+        assert!(!breaker.try_acquire());
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(breaker.try_acquire());
+        // Everyone else waits on the probe
+        assert!(!breaker.try_acquire());
+
+        // A failed probe opens it again for a full cooldown
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.try_acquire());
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.try_acquire());
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.try_acquire());
+    }
+}
@@ -1,15 +1,640 @@
 use crate::error::AppResult;
+use crate::models::AnalysisMetrics;
 use serde::{Deserialize, Serialize};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+/// A 1-based line and column, counted in characters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// One problem the analyzer found, and where
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Finding {
+    pub rule_id: String,
+    pub severity: Severity,
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+    pub suggestion: Option<String>,
+}
+
+/// What a rule reports each time it matches
+struct Rule {
+    id: &'static str,
+    severity: Severity,
+    message: &'static str,
+    suggestion: &'static str,
+}
+
+impl Rule {
+    fn at(&self, location: Location) -> Finding {
+        Finding {
+            rule_id: self.id.to_string(),
+            severity: self.severity,
+            message: self.message.to_string(),
+            line: location.line,
+            column: location.column,
+            suggestion: Some(self.suggestion.to_string()),
+        }
+    }
+}
+
+const DYNAMIC_EXECUTION: Rule = Rule {
+    id: "dynamic-execution",
+    severity: Severity::Error,
+    message: "Dynamic code execution detected",
+    suggestion: "Parse or dispatch on the input instead of evaluating it as code",
+};
+
+const PLAINTEXT_PASSWORD: Rule = Rule {
+    id: "plaintext-password",
+    severity: Severity::Warning,
+    message: "Potential plaintext password handling",
+    suggestion: "Hash passwords with a slow password hash such as bcrypt or argon2 before storing or comparing them",
+};
+
+const SQL_INJECTION: Rule = Rule {
+    id: "sql-injection",
+    severity: Severity::Error,
+    message: "Potential SQL injection vulnerability",
+    suggestion: "Pass runtime values as bound query parameters instead of building the SQL string",
+};
+
+const NESTED_LOOPS: Rule = Rule {
+    id: "nested-loops",
+    severity: Severity::Warning,
+    message: "Nested loops detected - O(n²) complexity",
+    suggestion: "Index the inner collection in a map or set so the inner loop becomes a lookup",
+};
+
+const EXCESSIVE_CLONING: Rule = Rule {
+    id: "excessive-cloning",
+    severity: Severity::Info,
+    message: "Excessive cloning detected",
+    suggestion: "Borrow where ownership isn't needed, or share with Rc/Arc",
+};
+
+/// Clones in one snippet beyond which cloning is reported
+const MAX_CLONES: usize = 5;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CodeAnalysisResult {
     pub language: String,
     pub complexity: f64,
     pub maintainability: f64,
-    pub security_issues: Vec<String>,
-    pub performance_issues: Vec<String>,
+    pub security_issues: Vec<Finding>,
+    pub performance_issues: Vec<Finding>,
 }
 
+impl CodeAnalysisResult {
+    /// Security and performance findings, security first
+    pub fn findings(&self) -> Vec<Finding> {
+        self.security_issues
+            .iter()
+            .chain(self.performance_issues.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// The findings' messages, once per rule, for clients that only show a list of strings
+    pub fn issues(&self) -> Vec<String> {
+        let mut issues: Vec<String> = Vec::new();
+        for finding in self.security_issues.iter().chain(self.performance_issues.iter()) {
+            if !issues.contains(&finding.message) {
+                issues.push(finding.message.clone());
+            }
+        }
+        issues
+    }
+}
+
+/// Derive response metrics from the analysis of the submitted code and, when
+/// the AI produced a rewrite, the analysis of that rewrite
+pub fn compare_metrics(
+    before: &CodeAnalysisResult,
+    after: Option<&CodeAnalysisResult>,
+) -> AnalysisMetrics {
+    let Some(after) = after else {
+        return AnalysisMetrics {
+            complexity_reduction: 0.0,
+            performance_gain: 0.0,
+            maintainability_score: before.maintainability,
+        };
+    };
+
+    let complexity_reduction = (before.complexity - after.complexity) / before.complexity * 100.0;
+
+    let issues_before = distinct_rules(&before.performance_issues) as f64;
+    let issues_after = distinct_rules(&after.performance_issues) as f64;
+    let performance_gain = if issues_before > 0.0 {
+        (issues_before - issues_after) / issues_before * 100.0
+    } else {
+        0.0
+    };
+
+    AnalysisMetrics {
+        complexity_reduction,
+        performance_gain,
+        maintainability_score: after.maintainability,
+    }
+}
+
+fn distinct_rules(findings: &[Finding]) -> usize {
+    findings
+        .iter()
+        .map(|finding| finding.rule_id.as_str())
+        .collect::<std::collections::HashSet<_>>()
+        .len()
+}
+
+/// Syntax and heuristics for a single language
+pub trait LanguageAnalyzer: Send + Sync {
+    /// Keywords that each add a decision point to the complexity estimate
+    fn decision_keywords(&self) -> &'static [&'static str];
+
+    /// Keywords that open a loop
+    fn loop_keywords(&self) -> &'static [&'static str];
+
+    /// Marker that starts a comment running to the end of the line
+    fn line_comment(&self) -> &'static str;
+
+    /// Delimiters of a block comment, if the language has one
+    fn block_comment(&self) -> Option<(&'static str, &'static str)>;
+
+    /// Characters that open and close string literals
+    fn string_quotes(&self) -> &'static [char];
+
+    /// Functions that evaluate code at runtime
+    fn dynamic_execution_calls(&self) -> &'static [&'static str];
+
+    /// Fragments that indicate a string is being built from runtime values
+    fn interpolation_markers(&self) -> &'static [&'static str];
+
+    /// Blank out comments and string literals with spaces, returning the remaining code
+    /// and the number of comments removed. Every character keeps its line and column, so
+    /// positions found in the result point into the source.
+    fn strip(&self, code: &str) -> (String, usize) {
+        let mut out = String::with_capacity(code.len());
+        let mut comments = 0;
+        let mut rest = code;
+
+        while let Some(c) = rest.chars().next() {
+            if rest.starts_with(self.line_comment()) {
+                comments += 1;
+                let end = rest.find('\n').unwrap_or(rest.len());
+                out.extend(blank(&rest[..end]));
+                rest = &rest[end..];
+                continue;
+            }
+
+            if let Some((open, close)) = self.block_comment() {
+                if rest.starts_with(open) {
+                    comments += 1;
+                    let end = rest[open.len()..]
+                        .find(close)
+                        .map(|i| open.len() + i + close.len())
+                        .unwrap_or(rest.len());
+                    out.extend(blank(&rest[..end]));
+                    rest = &rest[end..];
+                    continue;
+                }
+            }
+
+            if self.string_quotes().contains(&c) {
+                let mut end = rest.len();
+                let mut chars = rest.char_indices().skip(1);
+                while let Some((i, ch)) = chars.next() {
+                    if ch == '\\' {
+                        chars.next();
+                    } else if ch == c {
+                        end = i + ch.len_utf8();
+                        break;
+                    }
+                }
+                out.extend(blank(&rest[..end]));
+                rest = &rest[end..];
+                continue;
+            }
+
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+
+        (out, comments)
+    }
+
+    /// Count decision keywords outside comments and strings, matching whole words only
+    fn count_decision_points(&self, code: &str) -> usize {
+        let (stripped, _) = self.strip(code);
+        words(&stripped)
+            .filter(|word| self.decision_keywords().contains(word))
+            .count()
+    }
+
+    fn count_comments(&self, code: &str) -> usize {
+        self.strip(code).1
+    }
+
+    /// Where a loop opens inside another loop's braces
+    fn nested_loops(&self, code: &str) -> Vec<Location> {
+        let (stripped, _) = self.strip(code);
+        let mut found = Vec::new();
+        // One entry per open brace, true when the block is a loop body
+        let mut blocks: Vec<bool> = Vec::new();
+        let mut pending_loop = false;
+
+        for (index, line) in stripped.lines().enumerate() {
+            let mut word_start = None;
+            for (i, c) in line.char_indices().chain(std::iter::once((line.len(), ' '))) {
+                if is_word_char(c) {
+                    word_start.get_or_insert(i);
+                    continue;
+                }
+
+                if let Some(start) = word_start.take() {
+                    if self.loop_keywords().contains(&&line[start..i]) {
+                        if blocks.contains(&true) {
+                            found.push(location(index, line, start));
+                        }
+                        pending_loop = true;
+                    }
+                }
+
+                match c {
+                    '{' => {
+                        blocks.push(pending_loop);
+                        pending_loop = false;
+                    }
+                    '}' => {
+                        blocks.pop();
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        found
+    }
+
+    /// Where the code calls one of the dynamic execution functions
+    fn dynamic_executions(&self, code: &str) -> Vec<Location> {
+        let (stripped, _) = self.strip(code);
+        let mut found = Vec::new();
+
+        for (index, line) in stripped.lines().enumerate() {
+            for name in self.dynamic_execution_calls() {
+                for (i, _) in line.match_indices(name) {
+                    let before = line[..i].chars().next_back();
+                    let after = line[i + name.len()..].trim_start();
+                    if !before.is_some_and(is_word_char) && after.starts_with('(') {
+                        found.push(location(index, line, i));
+                    }
+                }
+            }
+        }
+
+        found.sort();
+        found
+    }
+
+    /// Lines that build a SQL statement from runtime values, pointing at the statement
+    fn dynamic_sql(&self, code: &str) -> Vec<Location> {
+        code.lines()
+            .enumerate()
+            .filter_map(|(index, line)| {
+                let lower = line.to_ascii_lowercase();
+                let statement = [("select ", " from "), ("insert into ", ""), ("delete from ", ""), ("update ", " set ")]
+                    .iter()
+                    .filter_map(|(keyword, clause)| {
+                        let start = lower.find(keyword)?;
+                        lower[start..].contains(clause).then_some(start)
+                    })
+                    .min()?;
+
+                self.interpolation_markers()
+                    .iter()
+                    .any(|marker| line.contains(marker))
+                    .then(|| location(index, line, statement))
+            })
+            .collect()
+    }
+}
+
+/// The position of byte `offset` in the line at 0-based `index`
+fn location(index: usize, line: &str, offset: usize) -> Location {
+    Location {
+        line: index + 1,
+        column: line[..offset].chars().count() + 1,
+    }
+}
+
+/// Spaces in place of everything but newlines
+fn blank(text: &str) -> impl Iterator<Item = char> + '_ {
+    text.chars().map(|c| if c == '\n' { '\n' } else { ' ' })
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn words(code: &str) -> impl Iterator<Item = &str> {
+    code.split(|c: char| !is_word_char(c))
+        .filter(|word| !word.is_empty())
+}
+
+/// Every word, and every other non-space character, with the 1-based line it's on
+fn tokens(stripped: &str) -> Vec<(usize, &str)> {
+    let mut tokens = Vec::new();
+
+    for (index, line) in stripped.lines().enumerate() {
+        let mut rest = line;
+        while let Some(c) = rest.chars().next() {
+            let len = if is_word_char(c) {
+                rest.find(|ch: char| !is_word_char(ch)).unwrap_or(rest.len())
+            } else {
+                c.len_utf8()
+            };
+            if !c.is_whitespace() {
+                tokens.push((index + 1, &rest[..len]));
+            }
+            rest = &rest[len..];
+        }
+    }
+
+    tokens
+}
+
+/// Halstead volume `N log2(n)`, approximated by taking every word as an operand and every
+/// other non-space character as an operator, with N the total and n the distinct count
+fn halstead_volume(stripped: &str) -> f64 {
+    let tokens = tokens(stripped);
+    let distinct = tokens
+        .iter()
+        .map(|(_, token)| token)
+        .collect::<std::collections::HashSet<_>>()
+        .len();
+    if distinct < 2 {
+        return tokens.len() as f64;
+    }
+
+    tokens.len() as f64 * (distinct as f64).log2()
+}
+
+pub struct RustAnalyzer;
+
+impl LanguageAnalyzer for RustAnalyzer {
+    fn decision_keywords(&self) -> &'static [&'static str] {
+        &["if", "for", "while", "loop", "match"]
+    }
+
+    fn loop_keywords(&self) -> &'static [&'static str] {
+        &["for", "while", "loop"]
+    }
+
+    fn line_comment(&self) -> &'static str {
+        "//"
+    }
+
+    fn block_comment(&self) -> Option<(&'static str, &'static str)> {
+        Some(("/*", "*/"))
+    }
+
+    fn string_quotes(&self) -> &'static [char] {
+        // Single quotes are char literals and lifetimes, so they are left alone
+        &['"']
+    }
+
+    fn dynamic_execution_calls(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    fn interpolation_markers(&self) -> &'static [&'static str] {
+        &["format!(", "push_str("]
+    }
+}
+
+pub struct PythonAnalyzer;
+
+impl LanguageAnalyzer for PythonAnalyzer {
+    fn decision_keywords(&self) -> &'static [&'static str] {
+        &["if", "elif", "for", "while", "except", "and", "or"]
+    }
+
+    fn loop_keywords(&self) -> &'static [&'static str] {
+        &["for", "while"]
+    }
+
+    fn line_comment(&self) -> &'static str {
+        "#"
+    }
+
+    fn block_comment(&self) -> Option<(&'static str, &'static str)> {
+        None
+    }
+
+    fn string_quotes(&self) -> &'static [char] {
+        &['"', '\'']
+    }
+
+    fn dynamic_execution_calls(&self) -> &'static [&'static str] {
+        &["eval", "exec"]
+    }
+
+    fn interpolation_markers(&self) -> &'static [&'static str] {
+        &["f\"", "f'", ".format(", "\" %", "' %", "\" +", "' +", "+ \"", "+ '"]
+    }
+
+    /// Python blocks are delimited by indentation rather than braces
+    fn nested_loops(&self, code: &str) -> Vec<Location> {
+        let (stripped, _) = self.strip(code);
+        let mut found = Vec::new();
+        let mut loop_indents: Vec<usize> = Vec::new();
+
+        for (index, line) in stripped.lines().enumerate() {
+            let trimmed = line.trim_start();
+            if trimmed.is_empty() {
+                continue;
+            }
+
+            let indent = line.len() - trimmed.len();
+            while loop_indents.last().is_some_and(|&open| open >= indent) {
+                loop_indents.pop();
+            }
+
+            let starts_loop = words(trimmed)
+                .next()
+                .is_some_and(|word| self.loop_keywords().contains(&word));
+            if starts_loop {
+                if !loop_indents.is_empty() {
+                    found.push(location(index, line, indent));
+                }
+                loop_indents.push(indent);
+            }
+        }
+
+        found
+    }
+}
+
+pub struct JavaScriptAnalyzer;
+
+impl LanguageAnalyzer for JavaScriptAnalyzer {
+    fn decision_keywords(&self) -> &'static [&'static str] {
+        &["if", "for", "while", "case", "catch"]
+    }
+
+    fn loop_keywords(&self) -> &'static [&'static str] {
+        &["for", "while"]
+    }
+
+    fn line_comment(&self) -> &'static str {
+        "//"
+    }
+
+    fn block_comment(&self) -> Option<(&'static str, &'static str)> {
+        Some(("/*", "*/"))
+    }
+
+    fn string_quotes(&self) -> &'static [char] {
+        &['"', '\'', '`']
+    }
+
+    fn dynamic_execution_calls(&self) -> &'static [&'static str] {
+        &["eval", "Function"]
+    }
+
+    fn interpolation_markers(&self) -> &'static [&'static str] {
+        &["${", "\" +", "' +", "+ \"", "+ '"]
+    }
+}
+
+/// C-style fallback for languages without a dedicated analyzer
+pub struct DefaultAnalyzer;
+
+impl LanguageAnalyzer for DefaultAnalyzer {
+    fn decision_keywords(&self) -> &'static [&'static str] {
+        &["if", "for", "while", "case", "catch", "match"]
+    }
+
+    fn loop_keywords(&self) -> &'static [&'static str] {
+        &["for", "while"]
+    }
+
+    fn line_comment(&self) -> &'static str {
+        "//"
+    }
+
+    fn block_comment(&self) -> Option<(&'static str, &'static str)> {
+        Some(("/*", "*/"))
+    }
+
+    fn string_quotes(&self) -> &'static [char] {
+        &['"', '\'']
+    }
+
+    fn dynamic_execution_calls(&self) -> &'static [&'static str] {
+        &["eval", "exec"]
+    }
+
+    fn interpolation_markers(&self) -> &'static [&'static str] {
+        &["\" +", "' +", "+ \"", "+ '"]
+    }
+}
+
+/// Pick the analyzer for a language name, falling back to the C-style default
+pub fn analyzer_for(language: &str) -> &'static dyn LanguageAnalyzer {
+    match language.to_ascii_lowercase().as_str() {
+        "rust" | "rs" => &RustAnalyzer,
+        "python" | "py" => &PythonAnalyzer,
+        "javascript" | "js" | "jsx" | "typescript" | "ts" | "tsx" => &JavaScriptAnalyzer,
+        _ => &DefaultAnalyzer,
+    }
+}
+
+/// What a snippet was given as, or failing that its file extension, or failing that the
+/// language its content looks most like
+pub fn detect_language(language: Option<&str>, file_path: Option<&str>, code: &str) -> String {
+    if let Some(language) = language.map(str::trim).filter(|language| !language.is_empty()) {
+        return language.to_string();
+    }
+
+    file_path
+        .and_then(language_from_extension)
+        .unwrap_or_else(|| language_from_content(code))
+        .to_string()
+}
+
+/// Language named by a path's extension, if it's one we recognise
+pub fn language_from_extension(path: &str) -> Option<&'static str> {
+    let (_, extension) = path.rsplit_once('.')?;
+    // A dot in a directory name isn't an extension
+    if extension.contains('/') || extension.contains('\\') {
+        return None;
+    }
+
+    Some(match extension.to_ascii_lowercase().as_str() {
+        "rs" => "rust",
+        "py" | "pyi" => "python",
+        "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" => "javascript",
+        "go" => "go",
+        "java" => "java",
+        "kt" | "kts" => "kotlin",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "cxx" | "hpp" | "hh" => "cpp",
+        "cs" => "csharp",
+        "rb" => "ruby",
+        "php" => "php",
+        "swift" => "swift",
+        "sh" | "bash" => "shell",
+        "sql" => "sql",
+        _ => return None,
+    })
+}
+
+/// Markers that, found in a snippet, point to a language. Earlier languages win ties.
+const CONTENT_MARKERS: &[(&str, &[&str])] = &[
+    ("rust", &["fn ", "let mut ", "impl ", "pub fn ", "use std::", "println!", "-> Result<", "&self", "match "]),
+    ("python", &["def ", "import ", "elif ", "self.", "print(", "__init__", "None", "    pass"]),
+    ("javascript", &["function ", "const ", "=> ", "console.log", "require(", "export ", "let ", "===", "undefined"]),
+    ("go", &["package ", "func ", ":= ", "fmt.", "go func", "err != nil"]),
+    ("java", &["public class ", "public static void", "System.out", "import java.", "private final "]),
+    ("c", &["#include", "printf(", "malloc(", "int main(", "->"]),
+    ("ruby", &["def ", "end\n", "puts ", "require '", ".each do", "attr_accessor"]),
+    ("shell", &["#!/bin/sh", "#!/bin/bash", "echo ", "fi\n", "then\n", "$1"]),
+];
+
+/// The language a snippet looks most like, or "text" when nothing points anywhere
+pub fn language_from_content(code: &str) -> &'static str {
+    // An interpreter line settles it
+    if let Some(shebang) = code.lines().next().filter(|line| line.starts_with("#!")) {
+        for (needle, language) in [("python", "python"), ("node", "javascript"), ("ruby", "ruby"), ("sh", "shell")] {
+            if shebang.contains(needle) {
+                return language;
+            }
+        }
+    }
+
+    CONTENT_MARKERS
+        .iter()
+        .map(|(language, markers)| (*language, markers.iter().filter(|marker| code.contains(*marker)).count()))
+        .filter(|(_, hits)| *hits > 0)
+        // max_by_key keeps the last of equal maxima, so walk backwards to keep the first
+        .rev()
+        .max_by_key(|(_, hits)| *hits)
+        .map_or("text", |(language, _)| language)
+}
+
+#[derive(Default)]
 pub struct CodeAnalyzer;
 
 impl CodeAnalyzer {
@@ -18,8 +643,8 @@ impl CodeAnalyzer {
     }
 
     pub fn analyze(&self, code: &str, language: &str) -> AppResult<CodeAnalysisResult> {
-        let complexity = self.calculate_complexity(code);
-        let maintainability = self.calculate_maintainability(code);
+        let complexity = self.calculate_complexity(code, language);
+        let maintainability = self.calculate_maintainability(code, language);
         let security_issues = self.detect_security_issues(code, language);
         let performance_issues = self.detect_performance_issues(code, language);
 
@@ -32,60 +657,378 @@ impl CodeAnalyzer {
         })
     }
 
-    fn calculate_complexity(&self, code: &str) -> f64 {
+    fn calculate_complexity(&self, code: &str, language: &str) -> f64 {
         // Simple cyclomatic complexity estimation
-        let conditions = code.matches("if").count()
-            + code.matches("for").count()
-            + code.matches("while").count()
-            + code.matches("match").count();
+        let conditions = analyzer_for(language).count_decision_points(code);
 
         1.0 + (conditions as f64 * 0.5)
     }
 
-    fn calculate_maintainability(&self, code: &str) -> f64 {
-        let lines = code.lines().count();
-        let comments = code.matches("//").count() + code.matches("/*").count();
-        let comment_ratio = if lines > 0 {
-            (comments as f64) / (lines as f64) * 100.0
-        } else {
-            0.0
-        };
+    /// The Maintainability Index: `171 - 5.2 ln(V) - 0.23 G - 16.2 ln(LOC)`, with the SEI
+    /// bonus of `50 sin(sqrt(2.4 C))` for comments, where V is the Halstead volume, G the
+    /// cyclomatic complexity and C the share of lines carrying a comment. Rescaled to 0-100
+    /// as Visual Studio does, then to the 0-10 the metrics report.
+    fn calculate_maintainability(&self, code: &str, language: &str) -> f64 {
+        let analyzer = analyzer_for(language);
+        let (stripped, _) = analyzer.strip(code);
+        let comments = analyzer.count_comments(code);
+        let loc = code.lines().filter(|line| !line.trim().is_empty()).count();
+        if loc == 0 {
+            return 10.0;
+        }
+
+        let volume = halstead_volume(&stripped).max(1.0);
+        let cyclomatic = 1.0 + analyzer.count_decision_points(code) as f64;
+        let comment_ratio = (comments as f64 / loc as f64).min(1.0);
+
+        let index = 171.0 - 5.2 * volume.ln() - 0.23 * cyclomatic - 16.2 * (loc as f64).ln()
+            + 50.0 * (2.4 * comment_ratio).sqrt().sin();
 
-        // Maintainability score (0-10)
-        10.0 - (lines as f64 / 100.0).min(10.0) + (comment_ratio / 10.0).min(2.0)
+        (index * 100.0 / 171.0).clamp(0.0, 100.0) / 10.0
     }
 
-    fn detect_security_issues(&self, code: &str, _language: &str) -> Vec<String> {
-        let mut issues = Vec::new();
+    fn detect_security_issues(&self, code: &str, language: &str) -> Vec<Finding> {
+        let analyzer = analyzer_for(language);
+        let mut issues: Vec<Finding> = analyzer
+            .dynamic_executions(code)
+            .into_iter()
+            .map(|location| DYNAMIC_EXECUTION.at(location))
+            .collect();
 
-        if code.contains("eval(") || code.contains("exec(") {
-            issues.push("Dynamic code execution detected".to_string());
+        if !code.contains("hash") {
+            issues.extend(
+                occurrences(code, "password").map(|location| PLAINTEXT_PASSWORD.at(location)),
+            );
         }
 
-        if code.contains("password") && !code.contains("hash") {
-            issues.push("Potential plaintext password handling".to_string());
-        }
+        issues.extend(
+            analyzer
+                .dynamic_sql(code)
+                .into_iter()
+                .map(|location| SQL_INJECTION.at(location)),
+        );
+
+        issues
+    }
+
+    fn detect_performance_issues(&self, code: &str, language: &str) -> Vec<Finding> {
+        let analyzer = analyzer_for(language);
+        let mut issues: Vec<Finding> = analyzer
+            .nested_loops(code)
+            .into_iter()
+            .map(|location| NESTED_LOOPS.at(location))
+            .collect();
 
-        if code.contains("SQL") && !code.contains("prepared") {
-            issues.push("Potential SQL injection vulnerability".to_string());
+        // Reported once, at the clone that crosses the limit
+        if let Some(location) = occurrences(code, ".clone()").nth(MAX_CLONES) {
+            issues.push(EXCESSIVE_CLONING.at(location));
         }
 
         issues
     }
+}
+
+/// Where `needle` appears in `code`, line by line
+fn occurrences<'a>(code: &'a str, needle: &'a str) -> impl Iterator<Item = Location> + 'a {
+    code.lines().enumerate().flat_map(move |(index, line)| {
+        line.match_indices(needle)
+            .map(move |(offset, _)| location(index, line, offset))
+    })
+}
 
-    fn detect_performance_issues(&self, code: &str, _language: &str) -> Vec<String> {
-        let mut issues = Vec::new();
+/// Tokens hashed together into one k-gram
+const KGRAM_TOKENS: usize = 5;
+/// Consecutive k-grams each fingerprint is picked from
+const WINNOWING_WINDOW: usize = 4;
+/// Shortest run of identical tokens winnowing is guaranteed to notice
+pub const MIN_DUPLICATE_TOKENS: usize = KGRAM_TOKENS + WINNOWING_WINDOW - 1;
+/// K-grams found in more places than this are boilerplate, such as runs of closing braces,
+/// rather than evidence of copying
+const MAX_FINGERPRINT_OCCURRENCES: usize = 50;
+/// Matching fingerprints further apart than this, in tokens, belong to separate duplicates.
+/// Anything closer is one duplicate with a small edit in between.
+const MAX_MATCH_GAP: usize = 3 * (KGRAM_TOKENS + WINNOWING_WINDOW);
+
+/// What counts as a duplicate
+#[derive(Debug, Clone, Copy)]
+pub struct DuplicateSettings {
+    /// Shortest span, in tokens, worth reporting
+    pub min_tokens: usize,
+    /// Lowest similarity, from 0 to 1, for two spans to count as copies of each other
+    pub threshold: f64,
+}
 
-        if code.contains("nested for") || code.contains("for (") && code.contains("for (") {
-            issues.push("Nested loops detected - O(n²) complexity".to_string());
+impl Default for DuplicateSettings {
+    fn default() -> Self {
+        DuplicateSettings {
+            min_tokens: 50,
+            threshold: 0.8,
         }
+    }
+}
+
+/// A file to compare against the others
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub path: String,
+    pub language: String,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DuplicateSpan {
+    pub file_path: String,
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
+/// Spans of code that are copies, or near copies, of one another
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DuplicateCluster {
+    pub spans: Vec<DuplicateSpan>,
+    /// Length of the longest span, in tokens
+    pub tokens: usize,
+    /// Share of fingerprints the least similar pair of spans have in common, from 0 to 1
+    pub similarity: f64,
+}
+
+/// A file's tokens, reduced to the line each is on, and its winnowed fingerprints
+struct Fingerprints {
+    lines: Vec<usize>,
+    /// K-gram hash and the index of the k-gram's first token, in token order
+    selected: Vec<(u64, usize)>,
+}
 
-        if code.contains(".clone()") && code.matches(".clone()").count() > 5 {
-            issues.push("Excessive cloning detected".to_string());
+impl Fingerprints {
+    fn new(file: &SourceFile) -> Self {
+        let (stripped, _) = analyzer_for(&file.language).strip(&file.content);
+        let tokens = tokens(&stripped);
+        let words: Vec<&str> = tokens.iter().map(|(_, token)| *token).collect();
+
+        Fingerprints {
+            lines: tokens.iter().map(|(line, _)| *line).collect(),
+            selected: winnow(&words),
         }
+    }
 
-        issues
+    /// Fingerprints whose k-gram lies entirely within tokens `start..end`
+    fn count_within(&self, start: usize, end: usize) -> usize {
+        self.selected
+            .iter()
+            .filter(|(_, position)| *position >= start && position + KGRAM_TOKENS <= end)
+            .count()
+    }
+}
+
+/// Hash every k-gram of tokens and keep the smallest hash in each window of consecutive
+/// k-grams (the rightmost on ties), which picks the same fingerprints from the same code
+/// wherever it appears
+fn winnow(tokens: &[&str]) -> Vec<(u64, usize)> {
+    use std::hash::{Hash, Hasher};
+
+    let hashes: Vec<u64> = tokens
+        .windows(KGRAM_TOKENS)
+        .map(|kgram| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            kgram.hash(&mut hasher);
+            hasher.finish()
+        })
+        .collect();
+
+    let mut selected: Vec<(u64, usize)> = Vec::new();
+    for start in 0..=hashes.len().saturating_sub(WINNOWING_WINDOW) {
+        let window = &hashes[start..(start + WINNOWING_WINDOW).min(hashes.len())];
+        let Some((offset, &hash)) = window
+            .iter()
+            .enumerate()
+            .min_by_key(|(offset, hash)| (**hash, std::cmp::Reverse(*offset)))
+        else {
+            break;
+        };
+        if selected.last().map(|(_, position)| *position) != Some(start + offset) {
+            selected.push((hash, start + offset));
+        }
+    }
+
+    selected
+}
+
+/// A run of tokens in one file
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SpanRef {
+    file: usize,
+    start: usize,
+    end: usize,
+}
+
+impl SpanRef {
+    fn overlaps(&self, other: &SpanRef) -> bool {
+        self.file == other.file && self.start < other.end && other.start < self.end
+    }
+}
+
+/// Group matching fingerprint positions into runs that both advance through their files
+/// together, allowing gaps where the copy was edited
+fn chain_matches(mut matches: Vec<(usize, usize)>) -> Vec<Vec<(usize, usize)>> {
+    matches.sort_unstable();
+    let mut chains: Vec<Vec<(usize, usize)>> = Vec::new();
+
+    for (a, b) in matches {
+        let extends = chains.iter_mut().find(|chain| {
+            let (last_a, last_b) = chain[chain.len() - 1];
+            a > last_a && a <= last_a + MAX_MATCH_GAP && b > last_b && b <= last_b + MAX_MATCH_GAP
+        });
+        match extends {
+            Some(chain) => chain.push((a, b)),
+            None => chains.push(vec![(a, b)]),
+        }
+    }
+
+    chains
+}
+
+fn find_root(parents: &mut [usize], mut node: usize) -> usize {
+    while parents[node] != node {
+        parents[node] = parents[parents[node]];
+        node = parents[node];
+    }
+    node
+}
+
+/// Find code copied between, or within, files by winnowing their token k-grams and
+/// following runs of shared fingerprints. Largest clusters come first.
+pub fn find_duplicates(files: &[SourceFile], settings: &DuplicateSettings) -> Vec<DuplicateCluster> {
+    let fingerprints: Vec<Fingerprints> = files.iter().map(Fingerprints::new).collect();
+
+    let mut occurrences: std::collections::HashMap<u64, Vec<(usize, usize)>> = std::collections::HashMap::new();
+    for (file, prints) in fingerprints.iter().enumerate() {
+        for &(hash, position) in &prints.selected {
+            occurrences.entry(hash).or_default().push((file, position));
+        }
     }
+
+    // Matching positions for every pair of places sharing a fingerprint, earlier place first
+    let mut pairs: std::collections::BTreeMap<(usize, usize), Vec<(usize, usize)>> = std::collections::BTreeMap::new();
+    for places in occurrences.values() {
+        if places.len() < 2 || places.len() > MAX_FINGERPRINT_OCCURRENCES {
+            continue;
+        }
+        for (i, &(file_a, a)) in places.iter().enumerate() {
+            for &(file_b, b) in &places[i + 1..] {
+                let ((file_a, a), (file_b, b)) = if (file_a, a) < (file_b, b) {
+                    ((file_a, a), (file_b, b))
+                } else {
+                    ((file_b, b), (file_a, a))
+                };
+                pairs.entry((file_a, file_b)).or_default().push((a, b));
+            }
+        }
+    }
+
+    let mut spans: Vec<SpanRef> = Vec::new();
+    let mut links: Vec<(usize, usize, f64)> = Vec::new();
+    for ((file_a, file_b), matches) in pairs {
+        for chain in chain_matches(matches) {
+            let span_a = SpanRef {
+                file: file_a,
+                start: chain[0].0,
+                end: chain[chain.len() - 1].0 + KGRAM_TOKENS,
+            };
+            let span_b = SpanRef {
+                file: file_b,
+                start: chain[0].1,
+                end: chain[chain.len() - 1].1 + KGRAM_TOKENS,
+            };
+            let shortest = (span_a.end - span_a.start).min(span_b.end - span_b.start);
+            if shortest < settings.min_tokens || span_a.overlaps(&span_b) {
+                continue;
+            }
+
+            let mut matched_a: Vec<usize> = chain.iter().map(|(a, _)| *a).collect();
+            let mut matched_b: Vec<usize> = chain.iter().map(|(_, b)| *b).collect();
+            matched_a.dedup();
+            matched_b.sort_unstable();
+            matched_b.dedup();
+            let total = fingerprints[file_a].count_within(span_a.start, span_a.end)
+                + fingerprints[file_b].count_within(span_b.start, span_b.end);
+            let similarity = ((matched_a.len() + matched_b.len()) as f64 / total.max(1) as f64).min(1.0);
+            if similarity < settings.threshold {
+                continue;
+            }
+
+            spans.push(span_a);
+            spans.push(span_b);
+            links.push((spans.len() - 2, spans.len() - 1, similarity));
+        }
+    }
+
+    // Spans linked by a match, or overlapping in the same file, are the same duplicated code
+    let mut parents: Vec<usize> = (0..spans.len()).collect();
+    for &(a, b, _) in &links {
+        let (root_a, root_b) = (find_root(&mut parents, a), find_root(&mut parents, b));
+        parents[root_a] = root_b;
+    }
+    for a in 0..spans.len() {
+        for b in a + 1..spans.len() {
+            if spans[a].overlaps(&spans[b]) {
+                let (root_a, root_b) = (find_root(&mut parents, a), find_root(&mut parents, b));
+                parents[root_a] = root_b;
+            }
+        }
+    }
+
+    let mut clusters: std::collections::BTreeMap<usize, (Vec<SpanRef>, f64)> = std::collections::BTreeMap::new();
+    for (node, span) in spans.iter().enumerate() {
+        let root = find_root(&mut parents, node);
+        clusters.entry(root).or_insert_with(|| (Vec::new(), 1.0)).0.push(*span);
+    }
+    for &(a, _, similarity) in &links {
+        let root = find_root(&mut parents, a);
+        if let Some(cluster) = clusters.get_mut(&root) {
+            cluster.1 = cluster.1.min(similarity);
+        }
+    }
+
+    let mut report: Vec<DuplicateCluster> = clusters
+        .into_values()
+        .map(|(mut members, similarity)| {
+            // Merge overlapping spans in the same file into one
+            members.sort_by_key(|span| (span.file, span.start));
+            let mut merged: Vec<SpanRef> = Vec::new();
+            for span in members {
+                match merged.last_mut() {
+                    Some(last) if last.file == span.file && span.start < last.end => {
+                        last.end = last.end.max(span.end);
+                    }
+                    _ => merged.push(span),
+                }
+            }
+
+            DuplicateCluster {
+                tokens: merged.iter().map(|span| span.end - span.start).max().unwrap_or(0),
+                spans: merged
+                    .iter()
+                    .map(|span| {
+                        let lines = &fingerprints[span.file].lines;
+                        DuplicateSpan {
+                            file_path: files[span.file].path.clone(),
+                            start_line: lines[span.start],
+                            end_line: lines[span.end - 1],
+                        }
+                    })
+                    .collect(),
+                similarity,
+            }
+        })
+        .collect();
+
+    report.sort_by(|a, b| {
+        b.tokens
+            .cmp(&a.tokens)
+            .then_with(|| a.spans[0].file_path.cmp(&b.spans[0].file_path))
+            .then_with(|| a.spans[0].start_line.cmp(&b.spans[0].start_line))
+    });
+    report
 }
 
 #[cfg(test)]
@@ -96,7 +1039,315 @@ mod tests {
     fn test_complexity_calculation() {
         let analyzer = CodeAnalyzer::new();
         let code = "if x { if y { if z { } } }";
-        let complexity = analyzer.calculate_complexity(code);
+        let complexity = analyzer.calculate_complexity(code, "rust");
         assert!(complexity > 1.0);
     }
+
+    #[test]
+    fn test_maintainability_drops_with_complexity() {
+        let analyzer = CodeAnalyzer::new();
+        let straight = "fn f(x: i32) -> i32 {\n    let y = x + 1;\n    let z = y * 2;\n    z - x\n}";
+        let branchy = "fn f(x: i32) -> i32 {\n    if x > 0 { return 1; }\n    if x < 0 { return -1; }\n    match x { _ => 0 }\n}";
+        assert!(
+            analyzer.calculate_maintainability(branchy, "rust")
+                < analyzer.calculate_maintainability(straight, "rust")
+        );
+
+        // Each extra branch costs something
+        let mut code = String::from("fn f(x: i32) {\n");
+        let mut previous = analyzer.calculate_maintainability(&code, "rust");
+        for i in 0..20 {
+            code.push_str(&format!("    if x == {} {{ g(x); }}\n", i));
+            let score = analyzer.calculate_maintainability(&code, "rust");
+            assert!(score < previous, "branch {} did not lower the score", i);
+            previous = score;
+        }
+
+        // Comments help, and a block comment counts once however it is written
+        let commented = format!("// Doubles x /* not a block */\n{}", straight);
+        assert!(
+            analyzer.calculate_maintainability(&commented, "rust")
+                > analyzer.calculate_maintainability(straight, "rust")
+        );
+        assert_eq!(RustAnalyzer.count_comments("// see /* here\n/* a\n/* b */ x"), 2);
+    }
+
+    #[test]
+    fn test_maintainability_stays_in_range() {
+        let analyzer = CodeAnalyzer::new();
+        let huge: String = (0..5000)
+            .map(|i| format!("if a{} && b{} {{ while c{} {{ d{}(); }} }}\n", i, i, i, i))
+            .collect();
+        let comments_only = "// one\n// two\n// three";
+
+        for (code, language) in [
+            ("", "rust"),
+            ("x", "rust"),
+            (comments_only, "rust"),
+            ("# note\nx = 1", "python"),
+            (huge.as_str(), "javascript"),
+        ] {
+            let score = analyzer.calculate_maintainability(code, language);
+            assert!((0.0..=10.0).contains(&score), "{} out of range for {:?}", score, code);
+        }
+        assert_eq!(analyzer.calculate_maintainability(&huge, "javascript"), 0.0);
+        assert_eq!(analyzer.calculate_maintainability("", "rust"), 10.0);
+    }
+
+    #[test]
+    fn test_metrics_vary_with_input() {
+        let analyzer = CodeAnalyzer::new();
+        let simple = analyzer.analyze("fn add(a: i32, b: i32) -> i32 { a + b }", "rust").unwrap();
+        let branchy = analyzer
+            .analyze(
+                "fn f(x: i32) {\n    if x > 0 {\n        for i in 0..x {\n            while i > 0 {}\n        }\n    }\n}",
+                "rust",
+            )
+            .unwrap();
+
+        let unchanged = compare_metrics(&simple, None);
+        assert_eq!(unchanged.complexity_reduction, 0.0);
+        assert_eq!(unchanged.maintainability_score, simple.maintainability);
+
+        let simplified = compare_metrics(&branchy, Some(&simple));
+        assert!(simplified.complexity_reduction > 0.0);
+
+        let complicated = compare_metrics(&simple, Some(&branchy));
+        assert!(complicated.complexity_reduction < 0.0);
+        assert_ne!(simplified.maintainability_score, complicated.maintainability_score);
+    }
+
+    #[test]
+    fn test_issues_are_reported() {
+        let analyzer = CodeAnalyzer::new();
+        let result = analyzer.analyze("eval(input); let password = input;", "javascript").unwrap();
+        let issues = result.issues();
+        assert!(issues.contains(&"Dynamic code execution detected".to_string()));
+        assert!(issues.contains(&"Potential plaintext password handling".to_string()));
+    }
+
+    #[test]
+    fn test_rust_ignores_keywords_in_strings_and_identifiers() {
+        let rust = RustAnalyzer;
+        let code = "let gift = \"if only\"; // if\n/* while */\nif gift.is_empty() { diff(); }";
+        assert_eq!(rust.count_decision_points(code), 1);
+        assert_eq!(rust.count_comments(code), 2);
+        assert!(rust.nested_loops("for x in xs { foo(x); }\nfor y in ys { bar(y); }").is_empty());
+        assert!(!rust.nested_loops("for x in xs {\n    while ok { loop { break; } }\n}").is_empty());
+    }
+
+    #[test]
+    fn test_python_ignores_keywords_in_strings_and_identifiers() {
+        let python = PythonAnalyzer;
+        let code = "gift = 'if only'  # if\nif gift:\n    verify(\"for\")\n";
+        assert_eq!(python.count_decision_points(code), 1);
+        assert_eq!(python.count_comments(code), 1);
+        assert!(python.nested_loops("for a in b:\n    pass\nfor c in d:\n    pass\n").is_empty());
+        assert!(!python.nested_loops("for a in b:\n    for c in d:\n        pass\n").is_empty());
+    }
+
+    #[test]
+    fn test_javascript_ignores_keywords_in_strings_and_identifiers() {
+        let js = JavaScriptAnalyzer;
+        let code = "const gift = `if ${x}`; /* if */ if (gift) { items.forEach(f); }";
+        assert_eq!(js.count_decision_points(code), 1);
+        assert!(js.nested_loops("items.forEach(item => { for (const x of item) {} });").is_empty());
+        assert!(!js.nested_loops("for (let i = 0; i < n; i++) { for (let j = 0; j < n; j++) {} }").is_empty());
+        assert!(!js.dynamic_executions("eval(input)").is_empty());
+        assert!(js.dynamic_executions("retrieval(input); 'eval(x)'").is_empty());
+    }
+
+    #[test]
+    fn test_findings_point_at_their_lines() {
+        let analyzer = CodeAnalyzer::new();
+        let code = "function load(id, input) {\n  // eval(input) is not called here\n  const q = \"SELECT * FROM users WHERE id = \" + id;\n  for (const a of xs) {\n    for (const b of ys) { eval(input); }\n  }\n  return password;\n}";
+        let result = analyzer.analyze(code, "javascript").unwrap();
+
+        let at = |rule: &str| -> Vec<(usize, usize)> {
+            result
+                .findings()
+                .iter()
+                .filter(|finding| finding.rule_id == rule)
+                .map(|finding| (finding.line, finding.column))
+                .collect()
+        };
+        assert_eq!(at("sql-injection"), vec![(3, 14)]);
+        assert_eq!(at("nested-loops"), vec![(5, 5)]);
+        assert_eq!(at("dynamic-execution"), vec![(5, 27)]);
+        assert_eq!(at("plaintext-password"), vec![(7, 10)]);
+
+        let sql = result.findings().into_iter().find(|f| f.rule_id == "sql-injection").unwrap();
+        assert_eq!(sql.severity, Severity::Error);
+        assert_eq!(sql.message, "Potential SQL injection vulnerability");
+        assert!(sql.suggestion.is_some());
+
+        // Strings and comments before a finding don't shift its column
+        let python = analyzer
+            .analyze("x = 'a' # note\nfor a in b:\n    s = \"é\"; exec(s)\n    for c in d:\n        pass\n", "python")
+            .unwrap();
+        let positions: Vec<(String, usize, usize)> = python
+            .findings()
+            .into_iter()
+            .map(|f| (f.rule_id, f.line, f.column))
+            .collect();
+        assert_eq!(positions, vec![
+                ("dynamic-execution".to_string(), 3, 14),
+                ("nested-loops".to_string(), 4, 5)
+            ]);
+    }
+
+    #[test]
+    fn test_issues_list_each_rule_once() {
+        let analyzer = CodeAnalyzer::new();
+        let code = "let a = x.clone();\n".repeat(7) + "eval(a);\neval(b);";
+        let result = analyzer.analyze(&code, "javascript").unwrap();
+
+        assert_eq!(result.security_issues.len(), 2);
+        assert_eq!(
+            result.issues(),
+            vec!["Dynamic code execution detected", "Excessive cloning detected"]
+        );
+        // Cloning is reported where it crosses the limit
+        let cloning = &result.performance_issues[0];
+        assert_eq!((cloning.line, cloning.column), (MAX_CLONES + 1, 10));
+    }
+
+    const COPIED_FUNCTION: &str = "fn parse_settings(input: &str) -> Vec<(String, u32)> {
+    let mut settings = Vec::new();
+    for line in input.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        if let Ok(value) = value.trim().parse::<u32>() {
+            settings.push((key.trim().to_lowercase(), value));
+        }
+    }
+    settings.sort_by(|a, b| a.0.cmp(&b.0));
+    settings
+}
+";
+
+    fn source(path: &str, content: String) -> SourceFile {
+        SourceFile {
+            path: path.to_string(),
+            language: "rust".to_string(),
+            content,
+        }
+    }
+
+    #[test]
+    fn test_copied_function_is_reported_as_duplicate() {
+        let original = source(
+            "src/config.rs",
+            format!("use std::fs;\n\npub fn load(path: &str) -> String {{\n    fs::read_to_string(path).unwrap()\n}}\n\n{}", COPIED_FUNCTION),
+        );
+        // Reindented, with a comment added, after unrelated code
+        let copy = source(
+            "src/legacy/loader.rs",
+            format!(
+                "struct Loader {{ retries: u8 }}\n\nimpl Loader {{\n    fn retries(&self) -> u8 {{ self.retries * 2 + 1 }}\n}}\n\n// Copied from config.rs\n{}",
+                COPIED_FUNCTION.replace("    ", "  ")
+            ),
+        );
+        let unrelated = source(
+            "src/main.rs",
+            "fn main() {\n    let total: u64 = (1..=10).map(|n| n * n).sum();\n    println!(\"{}\", total);\n}\n".to_string(),
+        );
+
+        let clusters = find_duplicates(&[original, copy, unrelated], &DuplicateSettings::default());
+
+        assert_eq!(clusters.len(), 1, "{:?}", clusters);
+        let cluster = &clusters[0];
+        assert!(cluster.similarity >= 0.8);
+        assert!(cluster.tokens >= DuplicateSettings::default().min_tokens);
+
+        let spans: Vec<&str> = cluster.spans.iter().map(|span| span.file_path.as_str()).collect();
+        assert_eq!(spans, vec!["src/config.rs", "src/legacy/loader.rs"]);
+        // The function spans lines 7-19 of config.rs and 8-20 of loader.rs; winnowing may
+        // miss a few tokens at either end
+        let (config, loader) = (&cluster.spans[0], &cluster.spans[1]);
+        assert!((7..=8).contains(&config.start_line) && (18..=19).contains(&config.end_line), "{:?}", config);
+        assert!((8..=9).contains(&loader.start_line) && (19..=20).contains(&loader.end_line), "{:?}", loader);
+    }
+
+    #[test]
+    fn test_duplicate_settings_limit_what_is_reported() {
+        let exact = [source("a.rs", COPIED_FUNCTION.to_string()), source("b.rs", COPIED_FUNCTION.to_string())];
+        let renamed = [
+            source("a.rs", COPIED_FUNCTION.to_string()),
+            source("b.rs", COPIED_FUNCTION.replace("settings", "entries")),
+        ];
+
+        let exact_clusters = find_duplicates(&exact, &DuplicateSettings::default());
+        let renamed_clusters = find_duplicates(&renamed, &DuplicateSettings::default());
+        assert_eq!(exact_clusters.len(), 1);
+        assert_eq!(renamed_clusters.len(), 1);
+        // Renaming a variable throughout is still a near copy, but a less similar one
+        assert!(renamed_clusters[0].similarity < exact_clusters[0].similarity);
+
+        let strict = DuplicateSettings {
+            threshold: 0.95,
+            ..DuplicateSettings::default()
+        };
+        assert_eq!(find_duplicates(&exact, &strict).len(), 1);
+        assert!(find_duplicates(&renamed, &strict).is_empty());
+
+        // Nothing is long enough when the minimum is longer than the files
+        let too_long = DuplicateSettings {
+            min_tokens: 10_000,
+            ..DuplicateSettings::default()
+        };
+        assert!(find_duplicates(&exact, &too_long).is_empty());
+    }
+
+    #[test]
+    fn test_sql_detection_requires_dynamic_query() {
+        let analyzer = CodeAnalyzer::new();
+        let mention = analyzer.analyze("// SQL helpers live elsewhere", "rust").unwrap();
+        assert!(mention.security_issues.is_empty());
+
+        let built = analyzer
+            .analyze("let q = format!(\"SELECT * FROM users WHERE id = {}\", id);", "rust")
+            .unwrap();
+        assert!(built
+            .issues()
+            .contains(&"Potential SQL injection vulnerability".to_string()));
+    }
+
+    #[test]
+    fn test_language_from_extension() {
+        assert_eq!(language_from_extension("src/main.rs"), Some("rust"));
+        assert_eq!(language_from_extension("scripts/build.PY"), Some("python"));
+        assert_eq!(language_from_extension("web/app.ts"), Some("javascript"));
+        assert_eq!(language_from_extension("index.js"), Some("javascript"));
+        assert_eq!(language_from_extension("notes.txt"), None);
+        assert_eq!(language_from_extension("Makefile"), None);
+        assert_eq!(language_from_extension("v1.2/Dockerfile"), None);
+
+        // An explicit language beats the path, and the path beats the content
+        assert_eq!(detect_language(Some("Kotlin"), Some("main.rs"), "def f(): pass"), "Kotlin");
+        assert_eq!(detect_language(None, Some("main.rs"), "def f(): pass"), "rust");
+        assert_eq!(detect_language(Some("  "), Some("main.go"), ""), "go");
+    }
+
+    #[test]
+    fn test_language_from_content() {
+        assert_eq!(language_from_content("fn main() {\n    let mut total = 0;\n    println!(\"{}\", total);\n}"), "rust");
+        assert_eq!(language_from_content("import os\n\ndef main():\n    print(os.getcwd())\n"), "python");
+        assert_eq!(language_from_content("const add = (a, b) => a + b;\nconsole.log(add(1, 2));"), "javascript");
+        assert_eq!(language_from_content("package main\n\nfunc main() {\n    x := 1\n    fmt.Println(x)\n}"), "go");
+        assert_eq!(language_from_content("#!/usr/bin/env python3\nx = 1\n"), "python");
+        assert_eq!(language_from_content("Just some prose."), "text");
+
+        // Without a path the content decides
+        assert_eq!(detect_language(None, Some("README"), "def greet(name):\n    print(name)\n"), "python");
+        assert_eq!(detect_language(None, None, "#include <stdio.h>\nint main() { printf(\"hi\"); }"), "c");
+    }
+
+    #[test]
+    fn test_unknown_language_uses_default_analyzer() {
+        let analyzer = analyzer_for("kotlin");
+        assert_eq!(analyzer.count_decision_points("if (a) { while (b) {} }"), 2);
+        assert_eq!(analyzer_for("Python").line_comment(), "#");
+    }
 }
@@ -1,27 +1,150 @@
+use async_trait::async_trait;
 use dashmap::DashMap;
-use tokio::sync::broadcast;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Row};
+use tokio::sync::{broadcast, oneshot, watch, Mutex};
 use uuid::Uuid;
 use crate::models::collaboration::{
-    DocumentOperation, OperationType, CursorUpdate, ConflictDetection,
+    DocumentOperation, CursorUpdate, ResyncRequired, SessionMessage,
 };
-use std::sync::Arc;
+use crate::services::diff;
+use crate::services::ot_engine::OTEngine;
+use std::sync::{Arc, OnceLock};
 use std::collections::HashMap;
-use chrono::Utc;
+use std::fmt;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+
+/// Number of operations between automatic `document_versions` snapshots
+pub const SNAPSHOT_INTERVAL: u32 = 50;
+
+/// How many versions behind the session an operation may be and still be transformed
+pub const DEFAULT_MAX_LAG: u32 = 100;
+
+/// Broadcasts buffered per session before the slowest subscriber starts missing them
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 1000;
+
+/// Why an operation was not applied
+#[derive(Debug)]
+pub enum OperationError {
+    /// The operation is more than `max_lag` versions behind; the client must rebase
+    /// over the operations it missed before editing again
+    ResyncRequired(ResyncRequired),
+    /// The session's `expires_at` has passed
+    SessionExpired,
+    Rejected(String),
+}
+
+impl fmt::Display for OperationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OperationError::ResyncRequired(resync) => write!(
+                f,
+                "Operation is {} versions behind session version {}; resync before editing",
+                resync.missed_operations.len(),
+                resync.server_version
+            ),
+            OperationError::SessionExpired => f.write_str("Collaborative session has expired"),
+            OperationError::Rejected(message) => f.write_str(message),
+        }
+    }
+}
+
+impl From<String> for OperationError {
+    fn from(message: String) -> Self {
+        OperationError::Rejected(message)
+    }
+}
+
+/// Keep-alive and cleanup timings for collaboration sessions
+#[derive(Debug, Clone, Copy)]
+pub struct HeartbeatConfig {
+    /// How often each WebSocket is pinged
+    pub ping_interval: Duration,
+    /// Connections that send nothing (not even a pong) for this long are dropped
+    pub idle_timeout: Duration,
+    /// How long a session may sit with no participants before it is closed
+    pub empty_session_grace: Duration,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval: Duration::from_secs(30),
+            idle_timeout: Duration::from_secs(60),
+            empty_session_grace: Duration::from_secs(300),
+        }
+    }
+}
+
+/// An operation as a client submitted it, queued in a session's shared log. Every
+/// instance applies the log in the same order, so they all transform it the same way.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SequencedOperation {
+    /// Lets the instance that queued it find out how it was applied
+    pub ticket: Uuid,
+    pub operation: DocumentOperation,
+}
+
+/// Shares sessions with the other server instances, so participants connected to
+/// different instances edit the same document
+#[async_trait]
+pub trait SessionRelay: Send + Sync {
+    /// Hand a cursor move or presence change to the other instances
+    fn publish(&self, session_id: Uuid, message: &SessionMessage);
+
+    /// Append to the session's operation log and tell the other instances to catch up
+    async fn append(&self, session_id: Uuid, entry: &SequencedOperation) -> Result<(), String>;
+
+    /// The session's operation log from entry `from` on
+    async fn entries(&self, session_id: Uuid, from: usize) -> Result<Vec<SequencedOperation>, String>;
+}
 
 pub struct CollaborationManager {
     // Session ID -> Participants and operations
     active_sessions: DashMap<Uuid, SessionState>,
     // Broadcast channel for each session
-    channels: DashMap<Uuid, broadcast::Sender<DocumentOperation>>,
+    channels: DashMap<Uuid, broadcast::Sender<SessionMessage>>,
+    heartbeat: HeartbeatConfig,
+    max_lag: u32,
+    channel_capacity: usize,
+    // Flipped once on server shutdown so open WebSockets close themselves
+    shutdown: watch::Sender<bool>,
+    // Set when other instances share the sessions; everything stays local otherwise
+    relay: OnceLock<Arc<dyn SessionRelay>>,
+    // Held while a session's shared log is being applied, so entries apply once and in order
+    sequencers: DashMap<Uuid, Arc<Mutex<()>>>,
+    // Operations this instance queued in a shared log, by ticket, until they are applied
+    pending: DashMap<Uuid, oneshot::Sender<Result<DocumentOperation, OperationError>>>,
 }
 
 #[derive(Clone)]
 struct SessionState {
     session_id: Uuid,
     file_id: Uuid,
+    // By connection, so a user with the session open twice is here twice
     participants: HashMap<Uuid, ParticipantState>,
     operations: Vec<DocumentOperation>,
     version: u32,
+    // The file content the operations apply to, as the session loaded it
+    base_content: String,
+    // Length in chars of the document at each version, starting with the base content's
+    lengths: Vec<usize>,
+    // The file's latest `document_versions` entry when the session loaded it; newer entries
+    // other than the session's own snapshots mean it was edited outside the session.
+    // None for sessions not loaded from the file.
+    base_version: Option<i32>,
+    // When the last participant left, or the session was created with none
+    empty_since: Option<Instant>,
+    expires_at: Option<DateTime<Utc>>,
+    // Entries of the shared operation log applied so far
+    sequenced: usize,
+}
+
+impl SessionState {
+    fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= Utc::now())
+    }
 }
 
 #[derive(Clone)]
@@ -34,14 +157,78 @@ struct ParticipantState {
 
 impl CollaborationManager {
     pub fn new() -> Arc<Self> {
+        Self::with_heartbeat(HeartbeatConfig::default())
+    }
+
+    pub fn with_heartbeat(heartbeat: HeartbeatConfig) -> Arc<Self> {
+        Self::with_settings(heartbeat, DEFAULT_MAX_LAG, DEFAULT_CHANNEL_CAPACITY)
+    }
+
+    pub fn with_settings(heartbeat: HeartbeatConfig, max_lag: u32, channel_capacity: usize) -> Arc<Self> {
         Arc::new(Self {
             active_sessions: DashMap::new(),
             channels: DashMap::new(),
+            heartbeat,
+            max_lag,
+            channel_capacity: channel_capacity.max(1),
+            shutdown: watch::channel(false).0,
+            relay: OnceLock::new(),
+            sequencers: DashMap::new(),
+            pending: DashMap::new(),
         })
     }
 
+    pub fn heartbeat(&self) -> HeartbeatConfig {
+        self.heartbeat
+    }
+
     /// Create new collaboration session
     pub fn create_session(&self, session_id: Uuid, file_id: Uuid) -> Result<(), String> {
+        self.create_session_until(session_id, file_id, None)
+    }
+
+    /// Create a session that refuses joins and edits once `expires_at` has passed, over an
+    /// empty document
+    pub fn create_session_until(
+        &self,
+        session_id: Uuid,
+        file_id: Uuid,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(), String> {
+        self.insert_session(session_id, file_id, String::new(), None, expires_at)
+    }
+
+    /// Create a session over the file's current content, which snapshots write back to
+    pub async fn load_session(
+        &self,
+        pool: &PgPool,
+        session_id: Uuid,
+        file_id: Uuid,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(), String> {
+        let (content, version) = sqlx::query_as::<_, (String, i32)>(
+            r#"
+            SELECT content, (SELECT COALESCE(MAX(version_number), 0) FROM document_versions WHERE file_id = $1)
+            FROM code_files WHERE id = $1
+            "#,
+        )
+        .bind(file_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("Failed to load file: {}", e))?
+        .ok_or_else(|| "File not found".to_string())?;
+
+        self.insert_session(session_id, file_id, content, Some(version), expires_at)
+    }
+
+    fn insert_session(
+        &self,
+        session_id: Uuid,
+        file_id: Uuid,
+        base_content: String,
+        base_version: Option<i32>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(), String> {
         if self.active_sessions.contains_key(&session_id) {
             return Err("Session already exists".to_string());
         }
@@ -52,22 +239,32 @@ impl CollaborationManager {
             participants: HashMap::new(),
             operations: Vec::new(),
             version: 0,
+            lengths: vec![base_content.chars().count()],
+            base_content,
+            base_version,
+            empty_since: Some(Instant::now()),
+            expires_at,
+            sequenced: 0,
         };
 
         self.active_sessions.insert(session_id, state);
 
         // Create broadcast channel for this session
-        let (tx, _) = broadcast::channel(1000);
+        let (tx, _) = broadcast::channel(self.channel_capacity);
         self.channels.insert(session_id, tx);
 
         Ok(())
     }
 
-    /// Join user to session
-    pub fn join_session(&self, session_id: Uuid, user_id: Uuid) -> Result<(), String> {
+    /// Join user to session through a new connection, returning the connection's ID
+    pub fn join_session(&self, session_id: Uuid, user_id: Uuid) -> Result<Uuid, String> {
         if let Some(mut session) = self.active_sessions.get_mut(&session_id) {
+            if session.is_expired() {
+                return Err("Session has expired".to_string());
+            }
+            let connection_id = Uuid::new_v4();
             session.participants.insert(
-                user_id,
+                connection_id,
                 ParticipantState {
                     user_id,
                     cursor_position: None,
@@ -75,30 +272,38 @@ impl CollaborationManager {
                     selection_end: None,
                 },
             );
-            Ok(())
+            session.empty_since = None;
+            Ok(connection_id)
         } else {
             Err("Session not found".to_string())
         }
     }
 
-    /// Remove user from session
-    pub fn leave_session(&self, session_id: Uuid, user_id: Uuid) -> Result<(), String> {
+    /// Remove one connection from the session. Returns whether its user is still
+    /// there through another connection.
+    pub fn leave_session(&self, session_id: Uuid, connection_id: Uuid) -> Result<bool, String> {
         if let Some(mut session) = self.active_sessions.get_mut(&session_id) {
-            session.participants.remove(&user_id);
-            Ok(())
+            let Some(left) = session.participants.remove(&connection_id) else {
+                return Ok(false);
+            };
+            if session.participants.is_empty() {
+                session.empty_since = Some(Instant::now());
+            }
+            Ok(session.participants.values().any(|participant| participant.user_id == left.user_id))
         } else {
             Err("Session not found".to_string())
         }
     }
 
-    /// Update cursor position for user
+    /// Update the cursor position of one connection
     pub fn update_cursor(
         &self,
         session_id: Uuid,
+        connection_id: Uuid,
         cursor_update: CursorUpdate,
     ) -> Result<(), String> {
         if let Some(mut session) = self.active_sessions.get_mut(&session_id) {
-            if let Some(participant) = session.participants.get_mut(&cursor_update.user_id) {
+            if let Some(participant) = session.participants.get_mut(&connection_id) {
                 participant.cursor_position = Some(cursor_update.cursor_position);
                 participant.selection_start = cursor_update.selection_start;
                 participant.selection_end = cursor_update.selection_end;
@@ -111,31 +316,76 @@ impl CollaborationManager {
         }
     }
 
-    /// Apply operation to document
+    /// Apply an operation made against document version `operation.version`.
+    ///
+    /// The operation is transformed against everything applied since that version and
+    /// returned stamped with the version it was applied at, so clients can rebase their
+    /// pending edits on it. The session is then at `version + 1`. Operations more than
+    /// `max_lag` versions behind are refused with the operations the client missed.
     pub fn apply_operation(
         &self,
         session_id: Uuid,
-        mut operation: DocumentOperation,
-    ) -> Result<u32, String> {
-        if let Some(mut session) = self.active_sessions.get_mut(&session_id) {
-            // Transform against concurrent operations
-            let concurrent_ops: Vec<_> = session
-                .operations
-                .iter()
-                .filter(|op| op.version >= operation.version && op.user_id != operation.user_id)
-                .cloned()
-                .collect();
+        operation: DocumentOperation,
+    ) -> Result<DocumentOperation, OperationError> {
+        let mut session = self
+            .active_sessions
+            .get_mut(&session_id)
+            .ok_or_else(|| "Session not found".to_string())?;
 
-            if !concurrent_ops.is_empty() {
-                operation = Self::transform_operation(&operation, &concurrent_ops);
-            }
+        if session.is_expired() {
+            return Err(OperationError::SessionExpired);
+        }
 
-            session.operations.push(operation);
-            session.version += 1;
-            Ok(session.version)
-        } else {
-            Err("Session not found".to_string())
+        Self::transform_into(&mut session, operation, self.max_lag)
+    }
+
+    fn transform_into(
+        session: &mut SessionState,
+        operation: DocumentOperation,
+        max_lag: u32,
+    ) -> Result<DocumentOperation, OperationError> {
+        if operation.version > session.version {
+            return Err(format!(
+                "Operation version {} is ahead of session version {}",
+                operation.version, session.version
+            )
+            .into());
+        }
+
+        if session.version - operation.version > max_lag {
+            return Err(OperationError::ResyncRequired(ResyncRequired {
+                server_version: session.version,
+                missed_operations: session.operations[operation.version as usize..].to_vec(),
+            }));
         }
+
+        // Checked against the document the client edited, before any transform does arithmetic on it
+        OTEngine::validate_operation(&operation, session.lengths[operation.version as usize])?;
+
+        let mut transformed =
+            OTEngine::transform(&operation, &session.operations[operation.version as usize..]);
+        transformed.version = session.version;
+
+        let length = OTEngine::applied_length(&transformed, session.lengths[session.version as usize]);
+        session.lengths.push(length);
+        session.operations.push(transformed.clone());
+        session.version += 1;
+        Ok(transformed)
+    }
+
+    /// What a client whose view of the session stops at `version` needs to catch up:
+    /// the current version and every operation applied since
+    pub fn resync_from(&self, session_id: Uuid, version: u32) -> Result<ResyncRequired, String> {
+        let session = self
+            .active_sessions
+            .get(&session_id)
+            .ok_or_else(|| "Session not found".to_string())?;
+
+        let from = (version as usize).min(session.operations.len());
+        Ok(ResyncRequired {
+            server_version: session.version,
+            missed_operations: session.operations[from..].to_vec(),
+        })
     }
 
     /// Detect conflicts in operations
@@ -158,17 +408,17 @@ impl CollaborationManager {
         }
     }
 
-    /// Get all participants in session
+    /// Get all participants in session, with the connection each is joined through
     pub fn get_participants(&self, session_id: Uuid) -> Result<Vec<(Uuid, CursorUpdate)>, String> {
         if let Some(session) = self.active_sessions.get(&session_id) {
             let participants = session
                 .participants
                 .iter()
-                .map(|(user_id, state)| {
+                .map(|(connection_id, state)| {
                     (
-                        *user_id,
+                        *connection_id,
                         CursorUpdate {
-                            user_id: *user_id,
+                            user_id: state.user_id,
                             session_id,
                             cursor_position: state.cursor_position.unwrap_or(0),
                             selection_start: state.selection_start,
@@ -188,131 +438,138 @@ impl CollaborationManager {
     pub fn get_channel(
         &self,
         session_id: Uuid,
-    ) -> Result<broadcast::Sender<DocumentOperation>, String> {
+    ) -> Result<broadcast::Sender<SessionMessage>, String> {
         self.channels
             .get(&session_id)
             .map(|ch| ch.clone())
             .ok_or_else(|| "Session channel not found".to_string())
     }
 
-    /// Transform operation against concurrent operations (OT)
-    pub fn transform_operation(
-        base_op: &DocumentOperation,
-        concurrent_ops: &[DocumentOperation],
-    ) -> DocumentOperation {
-        let mut transformed_op = base_op.clone();
+    /// Send broadcasts through `relay` as well as to local participants. Only the first
+    /// relay set is used.
+    pub fn set_relay(&self, relay: Arc<dyn SessionRelay>) {
+        if self.relay.set(relay).is_err() {
+            tracing::warn!("Collaboration relay is already set; ignoring another");
+        }
+    }
 
-        for concurrent_op in concurrent_ops {
-            transformed_op = Self::transform_against_single(&transformed_op, concurrent_op);
+    /// Send `message` to the session's participants on this instance and, through the
+    /// relay, on every other
+    pub fn broadcast(&self, session_id: Uuid, message: SessionMessage) -> Result<(), String> {
+        let channel = self.get_channel(session_id)?;
+        if let Some(relay) = self.relay.get() {
+            relay.publish(session_id, &message);
         }
+        // No receivers just means nobody is connected here right now
+        let _ = channel.send(message);
+        Ok(())
+    }
 
-        transformed_op
+    /// Hand a broadcast relayed from another instance to the participants connected here
+    pub fn deliver(&self, session_id: Uuid, message: SessionMessage) {
+        if let Some(channel) = self.channels.get(&session_id) {
+            let _ = channel.send(message);
+        }
     }
 
-    /// Transform single operation against concurrent operation
-    fn transform_against_single(
-        base_op: &DocumentOperation,
-        concurrent_op: &DocumentOperation,
-    ) -> DocumentOperation {
-        match (&base_op.operation, &concurrent_op.operation) {
-            // Insert vs Insert
-            (
-                OperationType::Insert {
-                    position: base_pos,
-                    content: base_content,
-                },
-                OperationType::Insert {
-                    position: conc_pos, ..
-                },
-            ) => {
-                let new_position = if conc_pos < base_pos {
-                    base_pos + base_content.len()
-                } else {
-                    *base_pos
-                };
+    /// Apply a client's operation and broadcast the result to the session. With a relay,
+    /// the operation is queued in the session's shared log and applied from there, in the
+    /// same order on every instance, so the instances' documents stay identical.
+    pub async fn submit_operation(
+        &self,
+        pool: &PgPool,
+        session_id: Uuid,
+        operation: DocumentOperation,
+    ) -> Result<DocumentOperation, OperationError> {
+        let Some(relay) = self.relay.get() else {
+            let applied = self.record_operation(pool, session_id, operation).await?;
+            self.deliver(session_id, SessionMessage::Operation(applied.clone()));
+            return Ok(applied);
+        };
 
-                let mut new_op = base_op.clone();
-                if let OperationType::Insert { position, .. } = &mut new_op.operation {
-                    *position = new_position;
-                }
-                new_op
-            }
+        // Checked here rather than as the log is applied, where instances' clocks could disagree
+        let expired = self
+            .active_sessions
+            .get(&session_id)
+            .map(|session| session.is_expired())
+            .ok_or_else(|| "Session not found".to_string())?;
+        if expired {
+            return Err(OperationError::SessionExpired);
+        }
 
-            // Insert vs Delete
-            (
-                OperationType::Insert {
-                    position: base_pos, ..
-                },
-                OperationType::Delete {
-                    position: del_pos,
-                    length: del_len,
-                },
-            ) => {
-                let new_position = if del_pos <= base_pos {
-                    base_pos.saturating_sub(del_len.min(base_pos - del_pos))
-                } else {
-                    *base_pos
-                };
+        let ticket = Uuid::new_v4();
+        let (sender, outcome) = oneshot::channel();
+        self.pending.insert(ticket, sender);
+        if let Err(e) = relay.append(session_id, &SequencedOperation { ticket, operation }).await {
+            self.pending.remove(&ticket);
+            return Err(e.into());
+        }
 
-                let mut new_op = base_op.clone();
-                if let OperationType::Insert { position, .. } = &mut new_op.operation {
-                    *position = new_position;
-                }
-                new_op
-            }
+        // Applies the entry, unless a catch-up already under way gets to it first
+        let caught_up = self.catch_up(session_id).await;
+        if self.pending.remove(&ticket).is_some() {
+            // The log couldn't be read, or the session was closed meanwhile
+            return Err(caught_up.err().unwrap_or_else(|| "Session not found".to_string()).into());
+        }
+        let applied = outcome
+            .await
+            .map_err(|_| "Operation was not applied".to_string())??;
 
-            // Delete vs Insert
-            (
-                OperationType::Delete {
-                    position: base_pos,
-                    length: base_len,
-                },
-                OperationType::Insert {
-                    position: ins_pos,
-                    content: ins_content,
-                },
-            ) => {
-                let new_position = if ins_pos < base_pos {
-                    base_pos + ins_content.len()
-                } else {
-                    *base_pos
-                };
+        // Only the instance that queued the operation snapshots, so each snapshot is taken once
+        if (applied.version + 1) % SNAPSHOT_INTERVAL == 0 {
+            self.snapshot_session(pool, session_id).await?;
+        }
 
-                let mut new_op = base_op.clone();
-                if let OperationType::Delete { position, .. } = &mut new_op.operation {
-                    *position = new_position;
-                }
-                new_op
-            }
+        Ok(applied)
+    }
 
-            // Delete vs Delete
-            (
-                OperationType::Delete {
-                    position: base_pos,
-                    length: base_len,
-                },
-                OperationType::Delete {
-                    position: del_pos,
-                    length: del_len,
-                },
-            ) => {
-                let new_position = if del_pos < base_pos {
-                    base_pos.saturating_sub(del_len.min(base_pos - del_pos))
-                } else {
-                    *base_pos
-                };
+    /// Apply the entries of the session's shared log this instance hasn't yet, in log
+    /// order, broadcasting each to the participants connected here. Does nothing without
+    /// a relay or for sessions this instance doesn't hold.
+    pub async fn catch_up(&self, session_id: Uuid) -> Result<(), String> {
+        let Some(relay) = self.relay.get() else {
+            return Ok(());
+        };
+        let sequencer = self.sequencers.entry(session_id).or_default().clone();
+        let _applying = sequencer.lock().await;
 
-                let mut new_op = base_op.clone();
-                if let OperationType::Delete { position, .. } = &mut new_op.operation {
-                    *position = new_position;
-                }
-                new_op
+        let Some(from) = self.active_sessions.get(&session_id).map(|session| session.sequenced) else {
+            return Ok(());
+        };
+        for entry in relay.entries(session_id, from).await? {
+            let result = {
+                let Some(mut session) = self.active_sessions.get_mut(&session_id) else {
+                    break;
+                };
+                session.sequenced += 1;
+                Self::transform_into(&mut session, entry.operation, self.max_lag)
+            };
+            if let Ok(applied) = &result {
+                self.deliver(session_id, SessionMessage::Operation(applied.clone()));
+            }
+            if let Some((_, waiting)) = self.pending.remove(&entry.ticket) {
+                let _ = waiting.send(result);
             }
+        }
+        Ok(())
+    }
 
-            _ => base_op.clone(),
+    /// Catch up on every session held here, e.g. after missing notifications from the relay
+    #[cfg(feature = "redis")]
+    pub async fn catch_up_all(&self) {
+        let session_ids: Vec<Uuid> = self.active_sessions.iter().map(|session| *session.key()).collect();
+        for session_id in session_ids {
+            if let Err(e) = self.catch_up(session_id).await {
+                tracing::warn!("Failed to catch up on collaboration session {}: {}", session_id, e);
+            }
         }
     }
 
+    /// Number of sessions currently held in memory
+    pub fn active_session_count(&self) -> usize {
+        self.active_sessions.len()
+    }
+
     /// Get current session version
     pub fn get_version(&self, session_id: Uuid) -> Result<u32, String> {
         self.active_sessions
@@ -321,23 +578,235 @@ impl CollaborationManager {
             .ok_or_else(|| "Session not found".to_string())
     }
 
-    /// Close session and clean up
-    pub fn close_session(&self, session_id: Uuid) -> Result<(), String> {
+    /// Apply operation and snapshot the document every `SNAPSHOT_INTERVAL` operations,
+    /// returning the transformed operation
+    pub async fn record_operation(
+        &self,
+        pool: &PgPool,
+        session_id: Uuid,
+        operation: DocumentOperation,
+    ) -> Result<DocumentOperation, OperationError> {
+        let applied = self.apply_operation(session_id, operation)?;
+
+        if (applied.version + 1) % SNAPSHOT_INTERVAL == 0 {
+            self.snapshot_session(pool, session_id).await?;
+        }
+
+        Ok(applied)
+    }
+
+    /// The session's document: the file content it started from, with every operation
+    /// applied so far
+    pub fn session_content(&self, session_id: Uuid) -> Result<String, String> {
+        self.active_sessions
+            .get(&session_id)
+            .map(|session| Self::materialize(&session.base_content, &session.operations))
+            .ok_or_else(|| "Session not found".to_string())
+    }
+
+    /// Persist the current document content as a new row in `document_versions`, and as the
+    /// file's content unless the file was edited outside the session since it was loaded
+    pub async fn snapshot_session(&self, pool: &PgPool, session_id: Uuid) -> Result<i32, String> {
+        // Copy what we need so the session lock is not held across queries
+        let (file_id, author_id, base_version) = self
+            .active_sessions
+            .get(&session_id)
+            .map(|session| (session.file_id, session.operations.last().map(|op| op.user_id), session.base_version))
+            .ok_or_else(|| "Session not found".to_string())?;
+        let author_id = author_id.ok_or_else(|| "No operations to snapshot".to_string())?;
+
+        let content = self.session_content(session_id)?;
+        let description = format!("Snapshot of collaborative session {}", session_id);
+
+        let mut tx = pool
+            .begin()
+            .await
+            .map_err(|e| format!("Failed to save snapshot: {}", e))?;
+
+        // Locked so a write from outside the session can't land between the check and the update
+        sqlx::query("SELECT id FROM code_files WHERE id = $1 FOR UPDATE")
+            .bind(file_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| format!("Failed to load file: {}", e))?
+            .ok_or_else(|| "File not found".to_string())?;
+
+        let edited_elsewhere = match base_version {
+            Some(base_version) => sqlx::query_scalar::<_, bool>(
+                r#"
+                SELECT EXISTS (
+                    SELECT 1 FROM document_versions
+                    WHERE file_id = $1 AND version_number > $2 AND change_description IS DISTINCT FROM $3
+                )
+                "#,
+            )
+            .bind(file_id)
+            .bind(base_version)
+            .bind(&description)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(|e| format!("Failed to load file history: {}", e))?,
+            None => true,
+        };
+
+        if edited_elsewhere {
+            // The session's edits still go into the history, but don't overwrite the newer content
+            tracing::warn!(
+                "File {} changed outside collaborative session {}; saving the session's edits as a version only",
+                file_id,
+                session_id
+            );
+        } else {
+            sqlx::query("UPDATE code_files SET content = $1, updated_at = NOW() WHERE id = $2")
+                .bind(&content)
+                .bind(file_id)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| format!("Failed to save file: {}", e))?;
+        }
+
+        let row = sqlx::query(
+            r#"
+            INSERT INTO document_versions (id, file_id, version_number, content, author_id, change_description)
+            SELECT $1, $2, COALESCE(MAX(version_number), 0) + 1, $3, $4, $5
+            FROM document_versions
+            WHERE file_id = $2
+            RETURNING version_number
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(file_id)
+        .bind(&content)
+        .bind(author_id)
+        .bind(&description)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| format!("Failed to save snapshot: {}", e))?;
+
+        tx.commit()
+            .await
+            .map_err(|e| format!("Failed to save snapshot: {}", e))?;
+
+        diff::reanchor_comments(pool, file_id)
+            .await
+            .map_err(|e| format!("Failed to re-anchor review comments: {}", e))?;
+
+        Ok(row.get("version_number"))
+    }
+
+    /// Fold session operations over the file content the session started from
+    fn materialize(base_content: &str, operations: &[DocumentOperation]) -> String {
+        operations
+            .iter()
+            .fold(base_content.to_string(), |content, op| {
+                OTEngine::apply_operation(&content, op)
+            })
+    }
+
+    /// Snapshot any unsaved edits, then close session and clean up
+    pub async fn close_session(&self, pool: &PgPool, session_id: Uuid) -> Result<(), String> {
+        let has_operations = self
+            .active_sessions
+            .get(&session_id)
+            .map(|session| !session.operations.is_empty())
+            .unwrap_or(false);
+
+        if has_operations {
+            self.snapshot_session(pool, session_id).await?;
+        }
+
         self.active_sessions.remove(&session_id);
         self.channels.remove(&session_id);
+        self.sequencers.remove(&session_id);
         Ok(())
     }
+
+    /// Sessions that have had no participants for longer than the grace period
+    pub fn idle_sessions(&self) -> Vec<Uuid> {
+        let grace = self.heartbeat.empty_session_grace;
+        self.active_sessions
+            .iter()
+            .filter(|session| session.empty_since.is_some_and(|since| since.elapsed() >= grace))
+            .map(|session| *session.key())
+            .collect()
+    }
+
+    /// Mark sessions past their `expires_at` as expired and close any that are live,
+    /// snapshotting unsaved edits. Returns how many were expired.
+    pub async fn expire_sessions(&self, pool: &PgPool) -> Result<usize, String> {
+        let expired: Vec<Uuid> = sqlx::query_scalar(
+            r#"
+            UPDATE collaborative_sessions SET status = 'expired', updated_at = NOW()
+            WHERE status = 'active' AND expires_at <= NOW()
+            RETURNING id
+            "#,
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Failed to expire sessions: {}", e))?;
+
+        for session_id in &expired {
+            if self.active_sessions.contains_key(session_id) {
+                self.close_session(pool, *session_id).await?;
+            }
+        }
+        Ok(expired.len())
+    }
+
+    /// Close every idle session, snapshotting unsaved edits. Returns how many were closed.
+    pub async fn close_idle_sessions(&self, pool: &PgPool) -> Result<usize, String> {
+        let idle = self.idle_sessions();
+        for session_id in &idle {
+            self.close_session(pool, *session_id).await?;
+        }
+        Ok(idle.len())
+    }
+
+    /// Ask every open WebSocket to close; called when the server starts shutting down
+    pub fn begin_shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Resolves for WebSocket handlers once `begin_shutdown` has been called
+    pub fn shutdown_receiver(&self) -> watch::Receiver<bool> {
+        self.shutdown.subscribe()
+    }
+
+    /// Close every live session, snapshotting unsaved edits. A session that fails to
+    /// snapshot is logged and skipped so the rest still get flushed. Returns how many were closed.
+    pub async fn close_all_sessions(&self, pool: &PgPool) -> usize {
+        let session_ids: Vec<Uuid> = self.active_sessions.iter().map(|session| *session.key()).collect();
+        let mut closed = 0;
+        for session_id in session_ids {
+            match self.close_session(pool, session_id).await {
+                Ok(()) => closed += 1,
+                Err(e) => tracing::error!("Failed to flush collaboration session {}: {}", session_id, e),
+            }
+        }
+        closed
+    }
 }
 
 impl Default for CollaborationManager {
     fn default() -> Self {
-        *Self::new()
+        Self {
+            active_sessions: DashMap::new(),
+            channels: DashMap::new(),
+            heartbeat: HeartbeatConfig::default(),
+            max_lag: DEFAULT_MAX_LAG,
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            shutdown: watch::channel(false).0,
+            relay: OnceLock::new(),
+            sequencers: DashMap::new(),
+            pending: DashMap::new(),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::collaboration::OperationType;
 
     #[test]
     fn test_session_creation() {
@@ -357,16 +826,75 @@ mod tests {
         let user_id = Uuid::new_v4();
 
         manager.create_session(session_id, file_id).unwrap();
-        assert!(manager.join_session(session_id, user_id).is_ok());
+        let connection_id = manager.join_session(session_id, user_id).unwrap();
 
         let participants = manager.get_participants(session_id).unwrap();
         assert_eq!(participants.len(), 1);
 
-        assert!(manager.leave_session(session_id, user_id).is_ok());
+        assert_eq!(manager.leave_session(session_id, connection_id), Ok(false));
         let participants = manager.get_participants(session_id).unwrap();
         assert_eq!(participants.len(), 0);
     }
 
+    #[test]
+    fn test_same_user_in_two_tabs() {
+        let manager = CollaborationManager::new();
+        let session_id = Uuid::new_v4();
+        let user_id = Uuid::new_v4();
+        manager.create_session(session_id, Uuid::new_v4()).unwrap();
+
+        let first = manager.join_session(session_id, user_id).unwrap();
+        let second = manager.join_session(session_id, user_id).unwrap();
+        assert_ne!(first, second);
+
+        let cursor = |position| CursorUpdate {
+            user_id,
+            session_id,
+            cursor_position: position,
+            selection_start: None,
+            selection_end: None,
+        };
+        manager.update_cursor(session_id, first, cursor(3)).unwrap();
+        manager.update_cursor(session_id, second, cursor(9)).unwrap();
+        let mut positions: Vec<i32> = manager
+            .get_participants(session_id)
+            .unwrap()
+            .into_iter()
+            .map(|(_, cursor)| cursor.cursor_position)
+            .collect();
+        positions.sort();
+        assert_eq!(positions, vec![3, 9]);
+
+        // Closing one tab leaves the user in the session through the other
+        assert_eq!(manager.leave_session(session_id, first), Ok(true));
+        let participants = manager.get_participants(session_id).unwrap();
+        assert_eq!(participants.len(), 1);
+        assert_eq!((participants[0].0, participants[0].1.cursor_position), (second, 9));
+
+        assert_eq!(manager.leave_session(session_id, second), Ok(false));
+        assert!(manager.get_participants(session_id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_idle_sessions_respect_grace_period() {
+        let manager = CollaborationManager::with_heartbeat(HeartbeatConfig {
+            empty_session_grace: Duration::from_millis(20),
+            ..HeartbeatConfig::default()
+        });
+        let (occupied, abandoned) = (Uuid::new_v4(), Uuid::new_v4());
+        let user_id = Uuid::new_v4();
+
+        manager.create_session(occupied, Uuid::new_v4()).unwrap();
+        manager.create_session(abandoned, Uuid::new_v4()).unwrap();
+        manager.join_session(occupied, user_id).unwrap();
+        let connection_id = manager.join_session(abandoned, user_id).unwrap();
+        manager.leave_session(abandoned, connection_id).unwrap();
+
+        assert!(manager.idle_sessions().is_empty());
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(manager.idle_sessions(), vec![abandoned]);
+    }
+
     #[test]
     fn test_cursor_update() {
         let manager = CollaborationManager::new();
@@ -375,7 +903,7 @@ mod tests {
         let user_id = Uuid::new_v4();
 
         manager.create_session(session_id, file_id).unwrap();
-        manager.join_session(session_id, user_id).unwrap();
+        let connection_id = manager.join_session(session_id, user_id).unwrap();
 
         let cursor_update = CursorUpdate {
             user_id,
@@ -385,6 +913,461 @@ mod tests {
             selection_end: Some(50),
         };
 
-        assert!(manager.update_cursor(session_id, cursor_update).is_ok());
+        assert!(manager.update_cursor(session_id, connection_id, cursor_update.clone()).is_ok());
+        assert!(manager.update_cursor(session_id, Uuid::new_v4(), cursor_update).is_err());
+    }
+
+    #[test]
+    fn test_broadcast_goes_to_local_participants_and_relay() {
+        use crate::models::collaboration::PresenceEvent;
+
+        struct RecordingRelay(parking_lot::Mutex<Vec<Uuid>>);
+        #[async_trait]
+        impl SessionRelay for RecordingRelay {
+            fn publish(&self, session_id: Uuid, _message: &SessionMessage) {
+                self.0.lock().push(session_id);
+            }
+
+            async fn append(&self, _session_id: Uuid, _entry: &SequencedOperation) -> Result<(), String> {
+                Ok(())
+            }
+
+            async fn entries(&self, _session_id: Uuid, _from: usize) -> Result<Vec<SequencedOperation>, String> {
+                Ok(Vec::new())
+            }
+        }
+
+        let manager = CollaborationManager::new();
+        let relay = Arc::new(RecordingRelay(parking_lot::Mutex::new(Vec::new())));
+        manager.set_relay(relay.clone());
+        let (session_id, user_id) = (Uuid::new_v4(), Uuid::new_v4());
+        manager.create_session(session_id, Uuid::new_v4()).unwrap();
+        let mut receiver = manager.get_channel(session_id).unwrap().subscribe();
+
+        manager
+            .broadcast(session_id, SessionMessage::Presence(PresenceEvent::Joined(user_id)))
+            .unwrap();
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(SessionMessage::Presence(PresenceEvent::Joined(id))) if id == user_id
+        ));
+        assert_eq!(*relay.0.lock(), vec![session_id]);
+
+        // Relayed broadcasts are delivered here without going back out
+        manager.deliver(session_id, SessionMessage::Presence(PresenceEvent::Left(user_id)));
+        assert!(matches!(receiver.try_recv(), Ok(SessionMessage::Presence(PresenceEvent::Left(_)))));
+        assert_eq!(relay.0.lock().len(), 1);
+
+        assert!(manager.broadcast(Uuid::new_v4(), SessionMessage::Presence(PresenceEvent::Left(user_id))).is_err());
+    }
+
+    /// One operation log shared by every manager it is attached to, like the Redis one
+    #[derive(Default)]
+    struct SharedLog(parking_lot::Mutex<Vec<SequencedOperation>>);
+
+    #[async_trait]
+    impl SessionRelay for SharedLog {
+        fn publish(&self, _session_id: Uuid, _message: &SessionMessage) {}
+
+        async fn append(&self, _session_id: Uuid, entry: &SequencedOperation) -> Result<(), String> {
+            // Let the other manager interleave its own submissions
+            tokio::task::yield_now().await;
+            self.0.lock().push(entry.clone());
+            Ok(())
+        }
+
+        async fn entries(&self, _session_id: Uuid, from: usize) -> Result<Vec<SequencedOperation>, String> {
+            Ok(self.0.lock().get(from..).unwrap_or_default().to_vec())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_instances_sharing_a_session_converge_on_the_same_document() {
+        // Never queried: no snapshot is due this early in the session
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/unused")
+            .unwrap();
+        let log = Arc::new(SharedLog::default());
+        let (first, second) = (CollaborationManager::new(), CollaborationManager::new());
+        first.set_relay(log.clone());
+        second.set_relay(log.clone());
+
+        let (session_id, file_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        first.create_session(session_id, file_id).unwrap();
+        second.create_session(session_id, file_id).unwrap();
+        let mut watching_second = second.get_channel(session_id).unwrap().subscribe();
+
+        // Both users edit version 0 at once, each through a different instance
+        let (from_first, from_second) = tokio::join!(
+            first.submit_operation(&pool, session_id, make_insert_op(alice, 0, 0, "Hello")),
+            second.submit_operation(&pool, session_id, make_insert_op(bob, 0, 0, "World")),
+        );
+        let (from_first, from_second) = (from_first.unwrap(), from_second.unwrap());
+        assert_ne!(from_first.version, from_second.version);
+
+        // A later edit made through the first instance, on top of both
+        first
+            .submit_operation(&pool, session_id, make_insert_op(alice, 2, 10, "!"))
+            .await
+            .unwrap();
+        second.catch_up(session_id).await.unwrap();
+
+        assert_eq!(first.get_version(session_id).unwrap(), 3);
+        assert_eq!(second.get_version(session_id).unwrap(), 3);
+        let content = first.session_content(session_id).unwrap();
+        assert_eq!(content.len(), 11);
+        assert!(content.ends_with('!'));
+        assert_eq!(second.session_content(session_id).unwrap(), content);
+
+        // Participants on the second instance saw every edit, whichever instance made it
+        let mut seen = Vec::new();
+        while let Ok(SessionMessage::Operation(op)) = watching_second.try_recv() {
+            seen.push(op.version);
+        }
+        assert_eq!(seen, vec![0, 1, 2]);
+    }
+
+    fn make_insert_op(user_id: Uuid, version: u32, pos: usize, content: &str) -> DocumentOperation {
+        DocumentOperation {
+            id: Uuid::new_v4().to_string(),
+            version,
+            timestamp: Utc::now(),
+            user_id,
+            operation: OperationType::Insert {
+                position: pos,
+                content: content.to_string(),
+            },
+        }
+    }
+
+    fn make_delete_op(user_id: Uuid, version: u32, pos: usize, length: usize) -> DocumentOperation {
+        DocumentOperation {
+            id: Uuid::new_v4().to_string(),
+            version,
+            timestamp: Utc::now(),
+            user_id,
+            operation: OperationType::Delete {
+                position: pos,
+                length,
+            },
+        }
+    }
+
+    /// Two clients edit the same version concurrently. The server applies `first` then
+    /// `second`; each client applies its own edit locally, then rebases the other one
+    /// as it arrives from the server.
+    fn converge(base: &str, first: DocumentOperation, second: DocumentOperation) -> [String; 3] {
+        let manager = CollaborationManager::new();
+        let session_id = Uuid::new_v4();
+        manager.insert_session(session_id, Uuid::new_v4(), base.to_string(), None, None).unwrap();
+
+        let first_applied = manager.apply_operation(session_id, first.clone()).unwrap();
+        let second_applied = manager.apply_operation(session_id, second.clone()).unwrap();
+        assert_eq!((first_applied.version, second_applied.version), (0, 1));
+        assert_eq!(manager.get_version(session_id).unwrap(), 2);
+
+        // The first client's edit was acknowledged as-is; the server's rebased second edit follows
+        let first_client = OTEngine::apply_operation(
+            &OTEngine::apply_operation(base, &first),
+            &second_applied,
+        );
+
+        // The second client still has its edit pending, so it rebases the first edit over it
+        let second_client = OTEngine::apply_operation(
+            &OTEngine::apply_operation(base, &second),
+            &OTEngine::transform(&first_applied, &[second]),
+        );
+
+        let operations = manager.active_sessions.get(&session_id).unwrap().operations.clone();
+        let server = CollaborationManager::materialize(base, &operations);
+
+        [server, first_client, second_client]
+    }
+
+    #[test]
+    fn test_concurrent_clients_converge() {
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let cases = vec![
+            // Inserts at the same position are ordered by operation ID on every replica
+            (make_insert_op(alice, 0, 5, " there"), make_insert_op(bob, 0, 5, ",")),
+            (make_insert_op(bob, 0, 5, ","), make_insert_op(alice, 0, 5, " there")),
+            // Insert inside a concurrently deleted range
+            (make_delete_op(alice, 0, 2, 6), make_insert_op(bob, 0, 4, "XY")),
+            (make_insert_op(bob, 0, 4, "XY"), make_delete_op(alice, 0, 2, 6)),
+            // Insert at either edge of a deleted range
+            (make_delete_op(alice, 0, 2, 3), make_insert_op(bob, 0, 2, "<")),
+            (make_delete_op(alice, 0, 2, 3), make_insert_op(bob, 0, 5, ">")),
+            // Overlapping and nested deletes
+            (make_delete_op(alice, 0, 1, 5), make_delete_op(bob, 0, 3, 6)),
+            (make_delete_op(alice, 0, 0, 11), make_delete_op(bob, 0, 4, 2)),
+        ];
+
+        for (first, second) in cases {
+            let description = format!("{:?} then {:?}", first.operation, second.operation);
+            let [server, first_client, second_client] = converge("hello world", first, second);
+            assert_eq!(first_client, server, "{}", description);
+            assert_eq!(second_client, server, "{}", description);
+        }
+    }
+
+    #[test]
+    fn test_apply_operation_rejects_future_version() {
+        let manager = CollaborationManager::new();
+        let session_id = Uuid::new_v4();
+        manager.create_session(session_id, Uuid::new_v4()).unwrap();
+
+        assert!(manager
+            .apply_operation(session_id, make_insert_op(Uuid::new_v4(), 1, 0, "x"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_apply_operation_rejects_out_of_range_operations() {
+        let manager = CollaborationManager::new();
+        let session_id = Uuid::new_v4();
+        manager.insert_session(session_id, Uuid::new_v4(), "hello".to_string(), None, None).unwrap();
+        let user_id = Uuid::new_v4();
+
+        for op in [
+            make_insert_op(user_id, 0, 6, "x"),
+            make_delete_op(user_id, 0, 2, usize::MAX),
+            make_delete_op(user_id, 0, usize::MAX, 1),
+        ] {
+            assert!(matches!(manager.apply_operation(session_id, op), Err(OperationError::Rejected(_))));
+        }
+        assert_eq!(manager.get_version(session_id).unwrap(), 0);
+
+        // Stale operations are checked against the version they were made on
+        manager.apply_operation(session_id, make_delete_op(user_id, 0, 0, 5)).unwrap();
+        manager.apply_operation(session_id, make_delete_op(user_id, 0, 3, 2)).unwrap();
+        assert!(manager.apply_operation(session_id, make_insert_op(user_id, 1, 1, "x")).is_err());
+        assert_eq!(manager.session_content(session_id).unwrap(), "");
+    }
+
+    #[test]
+    fn test_expired_session_refuses_joins_and_operations() {
+        let manager = CollaborationManager::new();
+        let session_id = Uuid::new_v4();
+        manager
+            .create_session_until(session_id, Uuid::new_v4(), Some(Utc::now() - chrono::Duration::seconds(1)))
+            .unwrap();
+
+        let user_id = Uuid::new_v4();
+        assert!(manager.join_session(session_id, user_id).is_err());
+        assert!(matches!(
+            manager.apply_operation(session_id, make_insert_op(user_id, 0, 0, "x")),
+            Err(OperationError::SessionExpired)
+        ));
+    }
+
+    #[test]
+    fn test_apply_operation_requires_resync_when_too_stale() {
+        let manager = CollaborationManager::with_settings(HeartbeatConfig::default(), 2, DEFAULT_CHANNEL_CAPACITY);
+        let session_id = Uuid::new_v4();
+        manager.create_session(session_id, Uuid::new_v4()).unwrap();
+
+        let user_id = Uuid::new_v4();
+        for version in 0..3 {
+            manager
+                .apply_operation(session_id, make_insert_op(user_id, version, 0, "x"))
+                .unwrap();
+        }
+
+        // Two versions behind is within the limit
+        assert!(manager
+            .apply_operation(session_id, make_insert_op(user_id, 1, 0, "y"))
+            .is_ok());
+
+        // Four versions behind is not, and nothing is applied
+        let stale = make_insert_op(Uuid::new_v4(), 0, 0, "z");
+        let resync = match manager.apply_operation(session_id, stale) {
+            Err(OperationError::ResyncRequired(resync)) => resync,
+            other => panic!("expected a resync error, got {:?}", other),
+        };
+        assert_eq!(resync.server_version, 4);
+        assert_eq!(
+            resync
+                .missed_operations
+                .iter()
+                .map(|op| op.version)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+        assert_eq!(manager.get_version(session_id).unwrap(), 4);
+    }
+
+    #[test]
+    fn test_materialize() {
+        let user_id = Uuid::new_v4();
+        let operations = vec![
+            make_insert_op(user_id, 0, 5, ","),
+            make_insert_op(user_id, 1, 12, "!"),
+        ];
+        assert_eq!(
+            CollaborationManager::materialize("hello world", &operations),
+            "hello, world!"
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_snapshot_session() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool();
+
+        let user_id = crate::db::insert_test_user(pool).await;
+        let project_id = crate::db::insert_test_project(pool, user_id, "snapshot test").await;
+        let file_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO code_files (id, project_id, file_path, content) VALUES ($1, $2, $3, $4)")
+            .bind(file_id)
+            .bind(project_id)
+            .bind("main.rs")
+            .bind("hello world")
+            .execute(pool)
+            .await
+            .unwrap();
+
+        let manager = CollaborationManager::new();
+        let session_id = Uuid::new_v4();
+        manager.load_session(pool, session_id, file_id, None).await.unwrap();
+        manager
+            .apply_operation(session_id, make_insert_op(user_id, 0, 5, ","))
+            .unwrap();
+        manager
+            .apply_operation(session_id, make_insert_op(user_id, 1, 12, "!"))
+            .unwrap();
+
+        assert_eq!(manager.snapshot_session(pool, session_id).await.unwrap(), 1);
+        manager
+            .apply_operation(session_id, make_insert_op(user_id, 2, 0, "> "))
+            .unwrap();
+        manager.close_session(pool, session_id).await.unwrap();
+
+        let rows = sqlx::query(
+            "SELECT version_number, content FROM document_versions WHERE file_id = $1 ORDER BY version_number",
+        )
+        .bind(file_id)
+        .fetch_all(pool)
+        .await
+        .unwrap();
+        let versions: Vec<(i32, String)> = rows
+            .iter()
+            .map(|row| (row.get("version_number"), row.get("content")))
+            .collect();
+        assert_eq!(
+            versions,
+            vec![
+                (1, "hello, world!".to_string()),
+                (2, "> hello, world!".to_string()),
+            ]
+        );
+
+        let content: String = sqlx::query_scalar("SELECT content FROM code_files WHERE id = $1")
+            .bind(file_id)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        assert_eq!(content, "> hello, world!");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_snapshot_keeps_outside_edits() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool();
+
+        let user_id = crate::db::insert_test_user(pool).await;
+        let project_id = crate::db::insert_test_project(pool, user_id, "outside edit test").await;
+        let file_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO code_files (id, project_id, file_path, content) VALUES ($1, $2, $3, $4)")
+            .bind(file_id)
+            .bind(project_id)
+            .bind("main.rs")
+            .bind("hello world")
+            .execute(pool)
+            .await
+            .unwrap();
+
+        let manager = CollaborationManager::new();
+        let session_id = Uuid::new_v4();
+        manager.load_session(pool, session_id, file_id, None).await.unwrap();
+        manager
+            .apply_operation(session_id, make_insert_op(user_id, 0, 11, "!"))
+            .unwrap();
+        // As update_file does it
+        sqlx::query("UPDATE code_files SET content = 'goodbye', updated_at = NOW() WHERE id = $1")
+            .bind(file_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO document_versions (id, file_id, version_number, content, author_id) VALUES ($1, $2, 1, 'goodbye', $3)",
+        )
+        .bind(Uuid::new_v4())
+        .bind(file_id)
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .unwrap();
+
+        manager.close_session(pool, session_id).await.unwrap();
+
+        let content: String = sqlx::query_scalar("SELECT content FROM code_files WHERE id = $1")
+            .bind(file_id)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        assert_eq!(content, "goodbye");
+        let saved: String = sqlx::query_scalar("SELECT content FROM document_versions WHERE file_id = $1 AND version_number = 2")
+            .bind(file_id)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        assert_eq!(saved, "hello world!");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_shutdown_flushes_every_session() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool();
+
+        let user_id = crate::db::insert_test_user(pool).await;
+        let project_id = crate::db::insert_test_project(pool, user_id, "shutdown test").await;
+        let file_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO code_files (id, project_id, file_path, content) VALUES ($1, $2, $3, $4)")
+            .bind(file_id)
+            .bind(project_id)
+            .bind("main.rs")
+            .bind("hello world")
+            .execute(pool)
+            .await
+            .unwrap();
+
+        let manager = CollaborationManager::new();
+        let mut shutdown = manager.shutdown_receiver();
+        let edited = Uuid::new_v4();
+        let untouched = Uuid::new_v4();
+        manager.load_session(pool, edited, file_id, None).await.unwrap();
+        manager.load_session(pool, untouched, file_id, None).await.unwrap();
+        manager
+            .apply_operation(edited, make_insert_op(user_id, 0, 11, "!"))
+            .unwrap();
+
+        manager.begin_shutdown();
+        assert!(shutdown.changed().await.is_ok());
+        assert!(*shutdown.borrow());
+
+        assert_eq!(manager.close_all_sessions(pool).await, 2);
+        assert!(manager.get_version(edited).is_err());
+        assert!(manager.get_version(untouched).is_err());
+
+        let content: String = sqlx::query_scalar("SELECT content FROM document_versions WHERE file_id = $1")
+            .bind(file_id)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        assert_eq!(content, "hello world!");
     }
 }
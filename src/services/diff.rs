@@ -0,0 +1,317 @@
+use std::collections::{BTreeSet, HashMap};
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::models::collaboration::DiffStat;
+
+/// Rounds of the Myers search `line_map` runs before giving up on the lines between the
+/// unchanged start and end; the trace it keeps grows with the square of this
+const MAX_LINE_MAP_EDITS: usize = 1_000;
+
+/// Count added and deleted lines between two versions of a file
+pub fn diff_lines(old: &str, new: &str) -> (u32, u32) {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+
+    let distance = edit_distance(&old_lines, &new_lines);
+    // Every edit is a single-line insert or delete, so the common lines follow from the distance
+    let common = (old_lines.len() + new_lines.len() - distance) / 2;
+
+    (
+        (new_lines.len() - common) as u32,
+        (old_lines.len() - common) as u32,
+    )
+}
+
+/// Per-file stats for changes from `target` to `source`, keyed by file path
+pub fn diff_file_sets(
+    source: &HashMap<String, String>,
+    target: &HashMap<String, String>,
+) -> Vec<DiffStat> {
+    let paths: BTreeSet<&String> = source.keys().chain(target.keys()).collect();
+
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let old = target.get(path).map(String::as_str).unwrap_or("");
+            let new = source.get(path).map(String::as_str).unwrap_or("");
+            let (additions, deletions) = diff_lines(old, new);
+
+            if additions == 0 && deletions == 0 {
+                return None;
+            }
+
+            Some(DiffStat {
+                file_path: path.clone(),
+                additions,
+                deletions,
+            })
+        })
+        .collect()
+}
+
+/// Where each line of `old` ended up in `new`: `Some(index)` for a line kept unchanged,
+/// `None` for one that was deleted or rewritten. Indices are 0-based. When the middle of
+/// the file changed too much to diff cheaply, lines there all come back `None`.
+pub fn line_map(old: &str, new: &str) -> Vec<Option<usize>> {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let mut map = vec![None; old_lines.len()];
+
+    // Lines before the first change and after the last one map straight across
+    let prefix = old_lines
+        .iter()
+        .zip(&new_lines)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old_lines[prefix..]
+        .iter()
+        .rev()
+        .zip(new_lines[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    for (index, line) in map.iter_mut().enumerate().take(prefix) {
+        *line = Some(index);
+    }
+    for offset in 1..=suffix {
+        map[old_lines.len() - offset] = Some(new_lines.len() - offset);
+    }
+
+    let a = &old_lines[prefix..old_lines.len() - suffix];
+    let b = &new_lines[prefix..new_lines.len() - suffix];
+    let Some(trace) = edit_trace(a, b, MAX_LINE_MAP_EDITS) else {
+        return map;
+    };
+    let (mut x, mut y) = (a.len() as isize, b.len() as isize);
+
+    // Walk the edit script back from the end; every diagonal step is a kept line
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        // Each round's snapshot covers diagonals -d - 1 ..= d + 1
+        let at = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            map[prefix + x as usize] = Some(prefix + y as usize);
+        }
+
+        x = prev_x;
+        y = prev_y;
+    }
+
+    map
+}
+
+/// Move line comments on a file onto its latest version. Called whenever a new version is
+/// saved: a comment whose line survived unchanged follows it, one whose line was edited or
+/// removed stays where it was and is marked outdated.
+pub async fn reanchor_comments(pool: &PgPool, file_id: Uuid) -> Result<(), sqlx::Error> {
+    let stale = sqlx::query_as::<_, (Uuid, i32, i32, String, i32, String)>(
+        r#"
+        SELECT c.id, c.line_number, c.file_version, old.content, latest.version_number, latest.content
+        FROM code_files f
+        JOIN code_reviews r ON r.project_id = f.project_id
+        JOIN review_comments c ON c.review_id = r.id AND c.file_path = f.file_path
+        JOIN document_versions old ON old.file_id = f.id AND old.version_number = c.file_version
+        JOIN LATERAL (
+            SELECT version_number, content FROM document_versions
+            WHERE file_id = f.id ORDER BY version_number DESC LIMIT 1
+        ) latest ON latest.version_number > c.file_version
+        WHERE f.id = $1 AND c.line_number IS NOT NULL AND NOT c.outdated
+        "#
+    )
+    .bind(file_id)
+    .fetch_all(pool)
+    .await?;
+
+    // Comments on the same version share one diff
+    let mut line_maps: HashMap<i32, Vec<Option<usize>>> = HashMap::new();
+    for (comment_id, line_number, file_version, old, latest_version, latest) in stale {
+        let map = line_maps
+            .entry(file_version)
+            .or_insert_with(|| line_map(&old, &latest));
+        let moved_to = usize::try_from(line_number - 1)
+            .ok()
+            .and_then(|index| map.get(index).copied().flatten());
+
+        // Only move comments nobody else has re-anchored in the meantime
+        let query = match moved_to {
+            Some(index) => sqlx::query(
+                "UPDATE review_comments SET line_number = $2, file_version = $3 WHERE id = $1 AND file_version = $4"
+            )
+            .bind(comment_id)
+            .bind(index as i32 + 1)
+            .bind(latest_version)
+            .bind(file_version),
+            None => sqlx::query(
+                "UPDATE review_comments SET outdated = TRUE WHERE id = $1 AND file_version = $2"
+            )
+            .bind(comment_id)
+            .bind(file_version),
+        };
+        query.execute(pool).await?;
+    }
+
+    Ok(())
+}
+
+/// The furthest-reaching x per diagonal before each round `d` of the Myers search, up to
+/// the round that reaches the end of both inputs. Round `d` only reads diagonals within
+/// `d + 1` of the middle, so only those are kept. `None` if it takes more than `max_edits`.
+fn edit_trace(a: &[&str], b: &[&str], max_edits: usize) -> Option<Vec<Vec<isize>>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = a.len() + b.len();
+    let offset = max as isize + 1;
+    let mut v = vec![0isize; 2 * max + 3];
+    let mut trace = Vec::new();
+
+    for d in 0..=max.min(max_edits) as isize {
+        trace.push(v[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let index = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+                v[index + 1]
+            } else {
+                v[index - 1] + 1
+            };
+            let mut y = x - k;
+
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+
+            v[index] = x;
+            if x >= n && y >= m {
+                return Some(trace);
+            }
+        }
+    }
+
+    None
+}
+
+/// Length of the shortest insert/delete edit script (Myers, O(ND))
+fn edit_distance(a: &[&str], b: &[&str]) -> usize {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = a.len() + b.len();
+    if max == 0 {
+        return 0;
+    }
+
+    let offset = max as isize;
+    // v[k + offset] holds the furthest x reached on diagonal k
+    let mut v = vec![0isize; 2 * max + 2];
+
+    for d in 0..=max as isize {
+        for k in (-d..=d).step_by(2) {
+            let index = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+                v[index + 1]
+            } else {
+                v[index - 1] + 1
+            };
+            let mut y = x - k;
+
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+
+            v[index] = x;
+            if x >= n && y >= m {
+                return d as usize;
+            }
+        }
+    }
+
+    max
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_lines() {
+        assert_eq!(diff_lines("a\nb\nc", "a\nb\nc"), (0, 0));
+        assert_eq!(diff_lines("a\nb\nc", "a\nx\nc"), (1, 1));
+        assert_eq!(diff_lines("a\nb\nc", "a\nb\nc\nd\ne"), (2, 0));
+        assert_eq!(diff_lines("a\nb\nc\nd", "b\nd"), (0, 2));
+        assert_eq!(diff_lines("", "a\nb"), (2, 0));
+        assert_eq!(diff_lines("a\nb", ""), (0, 2));
+    }
+
+    #[test]
+    fn test_line_map() {
+        assert_eq!(line_map("a\nb\nc", "a\nb\nc"), vec![Some(0), Some(1), Some(2)]);
+        assert_eq!(line_map("a\nb\nc", "x\ny\na\nb\nc"), vec![Some(2), Some(3), Some(4)]);
+        assert_eq!(line_map("a\nb\nc", "a\nx\nc"), vec![Some(0), None, Some(2)]);
+        assert_eq!(line_map("a\nb\nc\nd", "b\nd"), vec![None, Some(0), None, Some(1)]);
+        assert_eq!(line_map("a\nb", ""), vec![None, None]);
+        assert_eq!(line_map("", "a"), Vec::<Option<usize>>::new());
+        assert_eq!(line_map("a\nb\na", "a\nx\nb\na"), vec![Some(0), Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn test_line_map_gives_up_on_large_rewrites() {
+        let old: String = (0..3_000).map(|i| format!("old {}\n", i)).collect();
+        let new: String = (0..3_000).map(|i| format!("new {}\n", i)).collect();
+        let old = format!("head\n{}tail\n", old);
+        let new = format!("head\n{}tail\n", new);
+
+        let map = line_map(&old, &new);
+        assert_eq!(map.len(), 3_002);
+        assert_eq!(map[0], Some(0));
+        assert_eq!(map[3_001], Some(3_001));
+        assert!(map[1..3_001].iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn test_diff_file_sets() {
+        let target: HashMap<String, String> = [
+            ("src/main.rs", "fn main() {\n    run();\n}\n"),
+            ("src/lib.rs", "pub mod a;\npub mod b;\n"),
+            ("README.md", "# Project\n"),
+            ("old.rs", "one\ntwo\nthree\n"),
+        ]
+        .into_iter()
+        .map(|(path, content)| (path.to_string(), content.to_string()))
+        .collect();
+
+        let source: HashMap<String, String> = [
+            ("src/main.rs", "fn main() {\n    setup();\n    run();\n}\n"),
+            ("src/lib.rs", "pub mod a;\npub mod c;\n"),
+            ("README.md", "# Project\n"),
+            ("new.rs", "fn new() {}\n"),
+        ]
+        .into_iter()
+        .map(|(path, content)| (path.to_string(), content.to_string()))
+        .collect();
+
+        let stats: Vec<(String, u32, u32)> = diff_file_sets(&source, &target)
+            .into_iter()
+            .map(|stat| (stat.file_path, stat.additions, stat.deletions))
+            .collect();
+
+        assert_eq!(
+            stats,
+            vec![
+                ("new.rs".to_string(), 1, 0),
+                ("old.rs".to_string(), 0, 3),
+                ("src/lib.rs".to_string(), 1, 1),
+                ("src/main.rs".to_string(), 1, 0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use parking_lot::RwLock;
+use std::future::Future;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::models::Deployment;
+
+/// Events published after the change they describe has been committed
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DomainEvent {
+    ProjectCreated {
+        project_id: Uuid,
+        owner_id: Uuid,
+        name: String,
+    },
+    /// Any status change other than a merge
+    ReviewStatusChanged {
+        project_id: Uuid,
+        review_id: Uuid,
+        previous_status: String,
+        status: String,
+        changed_by: Uuid,
+    },
+    ReviewMerged {
+        project_id: Uuid,
+        review_id: Uuid,
+        previous_status: String,
+        merged_by: Uuid,
+    },
+    DeploymentCompleted {
+        deployment: Deployment,
+    },
+    /// An agent task finished, successfully or not; cancelled tasks don't count
+    AgentCompleted {
+        project_id: Uuid,
+        task_id: Uuid,
+        agent_type: String,
+        status: String,
+        result: serde_json::Value,
+    },
+    MemberAdded {
+        project_id: Uuid,
+        user_id: Uuid,
+        added_by: Uuid,
+        role: String,
+    },
+    /// Carries the token for the email; it's never serialized, so it stays out of logs
+    TeamInvitationCreated {
+        team_id: Uuid,
+        invitation_id: Uuid,
+        email: String,
+        role: String,
+        invited_by: Uuid,
+        expires_at: DateTime<Utc>,
+        #[serde(skip_serializing)]
+        token: String,
+    },
+    /// A new account needs its email confirmed; carries the token the same way
+    EmailVerificationRequested {
+        user_id: Uuid,
+        email: String,
+        expires_at: DateTime<Utc>,
+        #[serde(skip_serializing)]
+        token: String,
+    },
+    PasswordResetRequested {
+        user_id: Uuid,
+        email: String,
+        expires_at: DateTime<Utc>,
+        #[serde(skip_serializing)]
+        token: String,
+    },
+}
+
+impl DomainEvent {
+    /// snake_case name, as in the serialized `type`
+    pub fn name(&self) -> &'static str {
+        match self {
+            DomainEvent::ProjectCreated { .. } => "project_created",
+            DomainEvent::ReviewStatusChanged { .. } => "review_status_changed",
+            DomainEvent::ReviewMerged { .. } => "review_merged",
+            DomainEvent::DeploymentCompleted { .. } => "deployment_completed",
+            DomainEvent::AgentCompleted { .. } => "agent_completed",
+            DomainEvent::MemberAdded { .. } => "member_added",
+            DomainEvent::TeamInvitationCreated { .. } => "team_invitation_created",
+            DomainEvent::EmailVerificationRequested { .. } => "email_verification_requested",
+            DomainEvent::PasswordResetRequested { .. } => "password_reset_requested",
+        }
+    }
+
+    /// `None` for events about teams or accounts rather than projects
+    pub fn project_id(&self) -> Option<Uuid> {
+        match self {
+            DomainEvent::ProjectCreated { project_id, .. }
+            | DomainEvent::ReviewStatusChanged { project_id, .. }
+            | DomainEvent::ReviewMerged { project_id, .. }
+            | DomainEvent::AgentCompleted { project_id, .. }
+            | DomainEvent::MemberAdded { project_id, .. } => Some(*project_id),
+            DomainEvent::DeploymentCompleted { deployment } => Some(deployment.project_id),
+            DomainEvent::TeamInvitationCreated { .. }
+            | DomainEvent::EmailVerificationRequested { .. }
+            | DomainEvent::PasswordResetRequested { .. } => None,
+        }
+    }
+}
+
+/// Fans domain events out to every subscriber, so handlers don't need to know about
+/// webhooks, analytics or auditing. Subscribers never miss an event: each has its own
+/// queue of `capacity` events, and publishing waits while a subscriber's queue is full.
+pub struct EventBus {
+    queues: RwLock<Vec<mpsc::Sender<DomainEvent>>>,
+    capacity: usize,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            queues: RwLock::new(Vec::new()),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Never fails; with nobody subscribed the event is dropped
+    pub async fn publish(&self, event: DomainEvent) {
+        tracing::debug!("Publishing {}", event.name());
+        let queues = self.queues.read().clone();
+        for queue in queues {
+            // Only fails once the subscriber has dropped its receiver
+            let _ = queue.send(event.clone()).await;
+        }
+    }
+
+    /// Events published from now on. The receiver must keep up, since publishing waits
+    /// for room in its queue.
+    pub fn subscribe(&self) -> mpsc::Receiver<DomainEvent> {
+        let (queue, receiver) = mpsc::channel(self.capacity);
+        let mut queues = self.queues.write();
+        queues.retain(|queue| !queue.is_closed());
+        queues.push(queue);
+        receiver
+    }
+
+    /// Run `handle` for each event on a task of its own, in publish order, until the bus is
+    /// dropped. Subscribes before returning, so nothing published afterwards is missed.
+    pub fn spawn_subscriber<F, Fut>(&self, name: &'static str, handle: F)
+    where
+        F: Fn(DomainEvent) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send,
+    {
+        let mut receiver = self.subscribe();
+        tokio::spawn(async move {
+            while let Some(event) = receiver.recv().await {
+                handle(event).await;
+            }
+            tracing::debug!("{} subscriber stopped", name);
+        });
+    }
+}
+
+/// Structured log line per event under the `audit` target
+pub fn spawn_audit_log(bus: &EventBus) {
+    bus.spawn_subscriber("audit", |event| async move {
+        match serde_json::to_string(&event) {
+            Ok(details) => tracing::info!(target: "audit", event = event.name(), project_id = ?event.project_id(), "{}", details),
+            Err(e) => tracing::error!("Failed to serialize {} for the audit log: {:?}", event.name(), e),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    fn merged(project_id: Uuid) -> DomainEvent {
+        DomainEvent::ReviewMerged {
+            project_id,
+            review_id: Uuid::new_v4(),
+            previous_status: "approved".to_string(),
+            merged_by: Uuid::new_v4(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_published_review_merged_reaches_subscribers() {
+        let bus = EventBus::new(16);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        bus.spawn_subscriber("test", move |event| {
+            let tx = tx.clone();
+            async move {
+                tx.send(event).ok();
+            }
+        });
+        let mut direct = bus.subscribe();
+
+        let project_id = Uuid::new_v4();
+        bus.publish(merged(project_id)).await;
+
+        let received = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .expect("subscriber never saw the event")
+            .unwrap();
+        assert!(matches!(received, DomainEvent::ReviewMerged { project_id: p, .. } if p == project_id));
+        assert_eq!(direct.recv().await.unwrap().name(), "review_merged");
+
+        let serialized = serde_json::to_value(merged(project_id)).unwrap();
+        assert_eq!(serialized["type"], "review_merged");
+        assert_eq!(serialized["project_id"], project_id.to_string());
+    }
+
+    #[tokio::test]
+    async fn test_publishing_without_subscribers_is_fine() {
+        let bus = EventBus::new(1);
+        bus.publish(merged(Uuid::new_v4())).await;
+
+        // Nor does a receiver that has gone away hold publishing up
+        let receiver = bus.subscribe();
+        drop(receiver);
+        let last = Uuid::new_v4();
+        bus.publish(merged(last)).await;
+        bus.publish(merged(last)).await;
+        let mut receiver = bus.subscribe();
+        bus.publish(merged(last)).await;
+        assert_eq!(receiver.recv().await.unwrap().project_id(), Some(last));
+    }
+
+    #[tokio::test]
+    async fn test_slow_subscribers_miss_nothing() {
+        let bus = EventBus::new(1);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        bus.spawn_subscriber("slow", move |event| {
+            let tx = tx.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                tx.send(event.project_id()).ok();
+            }
+        });
+
+        let published: Vec<Uuid> = (0..10).map(|_| Uuid::new_v4()).collect();
+        for project_id in &published {
+            bus.publish(merged(*project_id)).await;
+        }
+        drop(bus);
+
+        let mut received = Vec::new();
+        while let Some(project_id) = rx.recv().await {
+            received.push(project_id.unwrap());
+        }
+        assert_eq!(received, published);
+    }
+}
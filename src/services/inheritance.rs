@@ -1,24 +1,53 @@
-use sqlx::{Pool, Postgres, Row};
+use sqlx::{types::Json, PgConnection, Pool, Postgres};
 use uuid::Uuid;
 use std::collections::HashMap;
 use crate::models::inheritance::{
     ResolvedPermissions, InheritedPermissionInfo, HierarchyTree, InheritanceConfig,
 };
+use crate::services::cache::Cache;
 use std::sync::Arc;
+use std::time::Duration;
 
 pub struct InheritanceEngine {
     pool: Arc<Pool<Postgres>>,
     config: InheritanceConfig,
-    cache: std::sync::Mutex<HashMap<(Uuid, Uuid), ResolvedPermissions>>,
+    /// Resolved permissions by `(user, resource)`
+    cache: Cache<(Uuid, Uuid), ResolvedPermissions>,
+}
+
+/// `(permissions, priority)` of the rules for each `(resource, role)` pair
+type RulesByScope = HashMap<(Uuid, String), Vec<(Vec<String>, i32)>>;
+
+/// A user's role and direct permissions on one resource
+struct Membership {
+    role: String,
+    permissions: Vec<String>,
 }
 
 impl InheritanceEngine {
     pub fn new(pool: Arc<Pool<Postgres>>, config: Option<InheritanceConfig>) -> Self {
-        Self {
-            pool,
-            config: config.unwrap_or_default(),
-            cache: std::sync::Mutex::new(HashMap::new()),
-        }
+        let config = config.unwrap_or_default();
+        let cache = Cache::in_memory(
+            "inheritance",
+            config.cache_capacity,
+            Duration::from_secs(config.cache_ttl_secs),
+        );
+        Self { pool, config, cache }
+    }
+
+    /// Keep resolved permissions in `backend` instead of this process's memory, e.g. so
+    /// every instance sees the same entries and invalidations
+    #[cfg(any(test, feature = "redis"))]
+    pub fn with_cache_backend(
+        mut self,
+        backend: Arc<dyn crate::services::cache::CacheBackend<(Uuid, Uuid), ResolvedPermissions>>,
+    ) -> Self {
+        self.cache = Cache::with_backend(
+            "inheritance",
+            Duration::from_secs(self.config.cache_ttl_secs),
+            backend,
+        );
+        self
     }
 
     /// Resolve effective permissions for a user on a resource
@@ -28,102 +57,202 @@ impl InheritanceEngine {
         resource_id: Uuid,
         resource_type: &str,
     ) -> Result<ResolvedPermissions, String> {
-        // Check cache first
-        let cache_key = (user_id, resource_id);
-        if let Ok(cache) = self.cache.lock() {
-            if let Some(cached) = cache.get(&cache_key) {
-                return Ok(cached.clone());
+        self.resolve_permissions_batch(user_id, &[resource_id], resource_type)
+            .await?
+            .remove(&resource_id)
+            .ok_or_else(|| "Permissions could not be resolved".to_string())
+    }
+
+    /// Resolve effective permissions for a user on many resources of one type at once,
+    /// for list endpoints. The ancestor hierarchy, the user's memberships and the matching
+    /// rules are each loaded in a single query and resolved in memory, instead of a few
+    /// round-trips per resource and per ancestor.
+    pub async fn resolve_permissions_batch(
+        &self,
+        user_id: Uuid,
+        resource_ids: &[Uuid],
+        resource_type: &str,
+    ) -> Result<HashMap<Uuid, ResolvedPermissions>, String> {
+        let mut resolved = HashMap::new();
+
+        // Serve what we can from cache
+        let mut misses = Vec::new();
+        for &resource_id in resource_ids {
+            match self.cache.get(&(user_id, resource_id)).await {
+                Some(cached) => {
+                    resolved.insert(resource_id, cached);
+                }
+                None => misses.push(resource_id),
             }
         }
+        misses.sort();
+        misses.dedup();
+        if misses.is_empty() {
+            return Ok(resolved);
+        }
 
-        // Get direct permissions
-        let direct_perms = self
-            .get_direct_permissions(user_id, resource_id, resource_type)
-            .await?;
+        let parents = if self.config.enabled {
+            self.load_ancestor_edges(&misses, resource_type).await?
+        } else {
+            HashMap::new()
+        };
 
-        // Get inherited permissions
-        let inherited_perms = self
-            .get_inherited_permissions(user_id, resource_id, resource_type)
-            .await?;
+        let mut nodes: Vec<Uuid> = misses.clone();
+        nodes.extend(parents.values().flatten().copied());
+        nodes.sort();
+        nodes.dedup();
+        let memberships = self.load_memberships(user_id, &nodes, resource_type).await?;
 
-        // Merge and resolve effective permissions
-        let effective_perms = Self::merge_permissions(&direct_perms, &inherited_perms);
-        let role = self
-            .get_user_role(user_id, resource_id, resource_type)
-            .await?;
+        let mut resolutions = Vec::with_capacity(misses.len());
+        let mut all_scopes = Vec::new();
+        for &resource_id in &misses {
+            let membership = memberships.get(&resource_id);
+            let direct_perms = membership.map(|m| m.permissions.clone()).unwrap_or_default();
+            let member_role = membership.map(|m| m.role.clone());
+            let inherited_perms =
+                self.inherited_from(&parents, &memberships, resource_id, resource_type);
 
-        let resolved = ResolvedPermissions {
-            user_id,
-            resource_id,
-            resource_type: resource_type.to_string(),
-            direct_permissions: direct_perms,
-            inherited_permissions: inherited_perms,
-            effective_permissions: effective_perms,
-            role,
-        };
+            // Role-based rules for this resource and every resource inherited from
+            let mut role_scopes: Vec<(Uuid, String)> = inherited_perms
+                .iter()
+                .map(|info| (info.source_id, info.from_role.clone()))
+                .collect();
+            if let Some(role) = &member_role {
+                role_scopes.push((resource_id, role.clone()));
+            }
+            all_scopes.extend(role_scopes.iter().cloned());
+            resolutions.push((resource_id, direct_perms, inherited_perms, member_role, role_scopes));
+        }
+
+        all_scopes.sort();
+        all_scopes.dedup();
+        let rules = self.load_rules(&all_scopes, resource_type).await?;
+
+        let mut fresh = Vec::with_capacity(resolutions.len());
+        for (resource_id, direct_perms, inherited_perms, member_role, role_scopes) in resolutions {
+            let applicable: Vec<(Vec<String>, i32)> = role_scopes
+                .iter()
+                .filter_map(|scope| rules.get(scope))
+                .flatten()
+                .cloned()
+                .collect();
+            let rule_perms = Self::fold_rule_permissions(&applicable, self.config.override_allowed);
+
+            // Merge and resolve effective permissions
+            let mut effective_perms = Self::merge_permissions(&direct_perms, &inherited_perms);
+            effective_perms.extend(rule_perms.iter().cloned());
+            effective_perms.sort();
+            effective_perms.dedup();
 
-        // Cache result
-        if let Ok(mut cache) = self.cache.lock() {
-            cache.insert(cache_key, resolved.clone());
+            fresh.push(ResolvedPermissions {
+                user_id,
+                resource_id,
+                resource_type: resource_type.to_string(),
+                direct_permissions: direct_perms,
+                inherited_permissions: inherited_perms,
+                rule_permissions: rule_perms,
+                effective_permissions: effective_perms,
+                role: member_role.unwrap_or_else(|| "viewer".to_string()),
+            });
         }
 
+        // Cache results
+        for permissions in &fresh {
+            self.cache.insert((user_id, permissions.resource_id), permissions.clone()).await;
+        }
+        resolved.extend(fresh.into_iter().map(|permissions| (permissions.resource_id, permissions)));
+
         Ok(resolved)
     }
 
-    /// Get direct permissions assigned to user on resource
-    async fn get_direct_permissions(
+    /// Enabled child -> parents edges reachable upward from `resource_ids`, as deep as
+    /// inheritance is followed
+    async fn load_ancestor_edges(
         &self,
-        user_id: Uuid,
-        resource_id: Uuid,
+        resource_ids: &[Uuid],
         resource_type: &str,
-    ) -> Result<Vec<String>, String> {
-        let table = if resource_type == "team" {
-            "team_members"
-        } else if resource_type == "project" {
-            "project_members"
-        } else {
-            return Err("Invalid resource type".to_string());
-        };
-
-        let id_col = if resource_type == "team" {
-            "team_id"
-        } else {
-            "project_id"
-        };
+    ) -> Result<HashMap<Uuid, Vec<Uuid>>, String> {
+        let (table, parent_col, child_col) = Self::hierarchy_table(resource_type)?;
 
         let query = format!(
             r#"
-            SELECT permissions FROM {} 
-            WHERE {} = $1 AND user_id = $2
+            WITH RECURSIVE ancestors (child_id, parent_id, depth) AS (
+                SELECT {child}, {parent}, 1 FROM {table}
+                WHERE {child} = ANY($1) AND {parent} IS NOT NULL AND inheritance_enabled = TRUE
+                UNION
+                SELECT h.{child}, h.{parent}, a.depth + 1 FROM {table} h
+                JOIN ancestors a ON h.{child} = a.parent_id
+                WHERE h.{parent} IS NOT NULL AND h.inheritance_enabled = TRUE AND a.depth <= $2
+            )
+            SELECT DISTINCT child_id, parent_id FROM ancestors
             "#,
-            table, id_col
+            child = child_col,
+            parent = parent_col,
+            table = table,
         );
 
-        let result = sqlx::query_scalar::<_, Option<serde_json::Value>>(&query)
-            .bind(resource_id)
-            .bind(user_id)
-            .fetch_optional(&*self.pool)
+        let edges = sqlx::query_as::<_, (Uuid, Uuid)>(&query)
+            .bind(resource_ids)
+            .bind(self.config.max_depth)
+            .fetch_all(&*self.pool)
             .await
             .map_err(|e| e.to_string())?;
 
-        match result {
-            Some(Some(perms)) => {
-                let perms: Vec<String> = serde_json::from_value(perms).unwrap_or_default();
-                Ok(perms)
-            }
-            _ => Ok(vec![]),
+        let mut parents: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+        for (child, parent) in edges {
+            parents.entry(child).or_default().push(parent);
         }
+        Ok(parents)
     }
 
-    /// Get inherited permissions from parent resources
-    async fn get_inherited_permissions(
+    /// The user's membership on each of `resource_ids` they belong to
+    async fn load_memberships(
         &self,
         user_id: Uuid,
+        resource_ids: &[Uuid],
+        resource_type: &str,
+    ) -> Result<HashMap<Uuid, Membership>, String> {
+        let (table, id_col) = Self::membership_table(resource_type)?;
+
+        // Team permissions are JSONB and project permissions TEXT[]; read both as JSON
+        let query = format!(
+            r#"
+            SELECT {id}, role, to_jsonb(permissions) FROM {table}
+            WHERE user_id = $1 AND {id} = ANY($2)
+            "#,
+            id = id_col,
+            table = table,
+        );
+
+        let rows = sqlx::query_as::<_, (Uuid, String, Option<serde_json::Value>)>(&query)
+            .bind(user_id)
+            .bind(resource_ids)
+            .fetch_all(&*self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(resource_id, role, permissions)| {
+                let permissions = permissions
+                    .and_then(|perms| serde_json::from_value(perms).ok())
+                    .unwrap_or_default();
+                (resource_id, Membership { role, permissions })
+            })
+            .collect())
+    }
+
+    /// Walk up from `resource_id` through ancestors the user holds permissions on,
+    /// recording what each one passes down
+    fn inherited_from(
+        &self,
+        parents: &HashMap<Uuid, Vec<Uuid>>,
+        memberships: &HashMap<Uuid, Membership>,
         resource_id: Uuid,
         resource_type: &str,
-    ) -> Result<Vec<InheritedPermissionInfo>, String> {
+    ) -> Vec<InheritedPermissionInfo> {
         if !self.config.enabled {
-            return Ok(vec![]);
+            return vec![];
         }
 
         let mut inherited = Vec::new();
@@ -131,125 +260,144 @@ impl InheritanceEngine {
         let mut processed = std::collections::HashSet::new();
 
         while let Some((current_id, depth)) = to_process.pop() {
-            if depth > self.config.max_depth || processed.contains(&current_id) {
+            if depth > self.config.max_depth || !processed.insert(current_id) {
                 continue;
             }
-            processed.insert(current_id);
 
-            // Get parents
-            let parents = self.get_parents(current_id, resource_type).await?;
-
-            for parent_id in parents {
-                // Get parent permissions for user
-                let parent_perms = self
-                    .get_direct_permissions(user_id, parent_id, resource_type)
-                    .await?;
+            for &parent_id in parents.get(&current_id).into_iter().flatten() {
+                let Some(membership) = memberships.get(&parent_id) else { continue };
+                if membership.permissions.is_empty() {
+                    continue;
+                }
 
-                if !parent_perms.is_empty() {
-                    let role = self
-                        .get_user_role(user_id, parent_id, resource_type)
-                        .await?;
+                inherited.push(InheritedPermissionInfo {
+                    source_id: parent_id,
+                    source_type: resource_type.to_string(),
+                    permissions: membership.permissions.clone(),
+                    depth: depth + 1,
+                    from_role: membership.role.clone(),
+                });
 
-                    inherited.push(InheritedPermissionInfo {
-                        source_id: parent_id,
-                        source_type: resource_type.to_string(),
-                        permissions: parent_perms,
-                        depth: depth + 1,
-                        from_role: role,
-                    });
-
-                    // Continue traversal
-                    if depth < self.config.max_depth {
-                        to_process.push((parent_id, depth + 1));
-                    }
+                // Continue traversal
+                if depth < self.config.max_depth {
+                    to_process.push((parent_id, depth + 1));
                 }
             }
         }
 
-        Ok(inherited)
+        inherited
     }
 
-    /// Get parent resources
-    async fn get_parents(&self, resource_id: Uuid, resource_type: &str) -> Result<Vec<Uuid>, String> {
-        let table = if resource_type == "team" {
-            "team_hierarchy"
-        } else if resource_type == "project" {
-            "project_hierarchy"
-        } else {
-            return Err("Invalid resource type".to_string());
-        };
-
-        let child_col = if resource_type == "team" {
-            "child_team_id"
-        } else {
-            "child_project_id"
-        };
-
-        let parent_col = if resource_type == "team" {
-            "parent_team_id"
-        } else {
-            "parent_project_id"
-        };
+    /// The `(permissions, priority)` of every rule for each `(resource, role)` pair
+    async fn load_rules(
+        &self,
+        role_scopes: &[(Uuid, String)],
+        resource_type: &str,
+    ) -> Result<RulesByScope, String> {
+        if role_scopes.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let (_, id_col) = Self::membership_table(resource_type)?;
 
         let query = format!(
             r#"
-            SELECT {} FROM {} 
-            WHERE {} = $1 AND inheritance_enabled = TRUE
+            SELECT r.{id}, r.role, r.permissions, r.priority
+            FROM permission_rules r
+            JOIN UNNEST($1::uuid[], $2::text[]) AS scope (resource_id, role)
+                ON r.{id} = scope.resource_id AND r.role = scope.role
             "#,
-            parent_col, table, child_col
+            id = id_col,
         );
 
-        let parents = sqlx::query_scalar::<_, Option<Uuid>>(&query)
-            .bind(resource_id)
+        let (scope_ids, roles): (Vec<Uuid>, Vec<String>) = role_scopes.iter().cloned().unzip();
+        let rows = sqlx::query_as::<_, (Uuid, String, Json<Vec<String>>, i32)>(&query)
+            .bind(scope_ids)
+            .bind(roles)
             .fetch_all(&*self.pool)
             .await
-            .map_err(|e| e.to_string())?
-            .into_iter()
-            .filter_map(|p| p)
-            .collect();
+            .map_err(|e| e.to_string())?;
 
-        Ok(parents)
+        let mut rules = RulesByScope::new();
+        for (scope_id, role, Json(perms), priority) in rows {
+            rules.entry((scope_id, role)).or_default().push((perms, priority));
+        }
+        Ok(rules)
     }
 
-    /// Get user's role on resource
-    async fn get_user_role(
-        &self,
-        user_id: Uuid,
-        resource_id: Uuid,
+    /// `(table, parent column, child column)` of the hierarchy for a resource type
+    fn hierarchy_table(resource_type: &str) -> Result<(&'static str, &'static str, &'static str), String> {
+        match resource_type {
+            "team" => Ok(("team_hierarchy", "parent_team_id", "child_team_id")),
+            "project" => Ok(("project_hierarchy", "parent_project_id", "child_project_id")),
+            _ => Err("Invalid resource type".to_string()),
+        }
+    }
+
+    /// `(table, resource column)` of the memberships for a resource type
+    fn membership_table(resource_type: &str) -> Result<(&'static str, &'static str), String> {
+        match resource_type {
+            "team" => Ok(("team_members", "team_id")),
+            "project" => Ok(("project_members", "project_id")),
+            _ => Err("Invalid resource type".to_string()),
+        }
+    }
+
+    /// Check whether making `parent_id` a parent of `child_id` would close a loop.
+    /// Takes the hierarchy's advisory lock first, so the answer holds until `conn`'s
+    /// transaction ends and concurrent edges can't form a cycle between them.
+    pub async fn would_create_cycle(
+        conn: &mut PgConnection,
+        child_id: Uuid,
+        parent_id: Uuid,
         resource_type: &str,
-    ) -> Result<String, String> {
-        let table = if resource_type == "team" {
-            "team_members"
-        } else if resource_type == "project" {
-            "project_members"
-        } else {
-            return Err("Invalid resource type".to_string());
-        };
+    ) -> Result<bool, String> {
+        let (table, parent_col, child_col) = Self::hierarchy_table(resource_type)?;
 
-        let id_col = if resource_type == "team" {
-            "team_id"
-        } else {
-            "project_id"
-        };
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+            .bind(table)
+            .execute(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?;
 
+        // Disabled edges still count: re-enabling one must not be able to form a cycle
         let query = format!(
             r#"
-            SELECT role FROM {} 
-            WHERE {} = $1 AND user_id = $2
+            WITH RECURSIVE ancestors (id) AS (
+                SELECT $2::uuid
+                UNION
+                SELECT h.{parent} FROM {table} h
+                JOIN ancestors a ON h.{child} = a.id
+                WHERE h.{parent} IS NOT NULL
+            )
+            SELECT EXISTS(SELECT 1 FROM ancestors WHERE id = $1)
             "#,
-            table, id_col
+            child = child_col,
+            parent = parent_col,
+            table = table,
         );
 
-        let role = sqlx::query_scalar::<_, Option<String>>(&query)
-            .bind(resource_id)
-            .bind(user_id)
-            .fetch_optional(&*self.pool)
+        sqlx::query_scalar::<_, bool>(&query)
+            .bind(child_id)
+            .bind(parent_id)
+            .fetch_one(&mut *conn)
             .await
-            .map_err(|e| e.to_string())?
-            .flatten()
-            .unwrap_or_else(|| "viewer".to_string());
+            .map_err(|e| e.to_string())
+    }
+
+    /// Combine rule grants. With `override_allowed` only the highest-priority rules
+    /// apply (ties are merged); otherwise every applicable rule contributes.
+    fn fold_rule_permissions(rules: &[(Vec<String>, i32)], override_allowed: bool) -> Vec<String> {
+        let top_priority = rules.iter().map(|(_, priority)| *priority).max();
 
-        Ok(role)
+        let mut folded: Vec<String> = rules
+            .iter()
+            .filter(|(_, priority)| !override_allowed || Some(*priority) == top_priority)
+            .flat_map(|(perms, _)| perms.iter().cloned())
+            .collect();
+
+        folded.sort();
+        folded.dedup();
+        folded
     }
 
     /// Merge direct and inherited permissions
@@ -285,9 +433,8 @@ impl InheritanceEngine {
 
         let mut tree_children = Vec::new();
         for child_id in children {
-            let child_tree = self
-                .build_hierarchy_tree(child_id, resource_type, "child")
-                .await?;
+            // Boxed, since the future can't contain itself
+            let child_tree = Box::pin(self.build_hierarchy_tree(child_id, resource_type, "child")).await?;
             tree_children.push(child_tree);
         }
 
@@ -295,8 +442,8 @@ impl InheritanceEngine {
             id: resource_id,
             name: name.to_string(),
             resource_type: resource_type.to_string(),
-            children: tree_children,
             permissions_inherited: !tree_children.is_empty(),
+            children: tree_children,
         })
     }
 
@@ -340,17 +487,13 @@ impl InheritanceEngine {
     }
 
     /// Clear permission cache
-    pub fn clear_cache(&self) {
-        if let Ok(mut cache) = self.cache.lock() {
-            cache.clear();
-        }
+    pub async fn clear_cache(&self) {
+        self.cache.clear().await;
     }
 
     /// Clear cache for specific resource
-    pub fn clear_cache_for_resource(&self, user_id: Uuid, resource_id: Uuid) {
-        if let Ok(mut cache) = self.cache.lock() {
-            cache.remove(&(user_id, resource_id));
-        }
+    pub async fn clear_cache_for_resource(&self, user_id: Uuid, resource_id: Uuid) {
+        self.cache.remove(&(user_id, resource_id)).await;
     }
 
     /// Check if user has permission
@@ -373,6 +516,290 @@ impl InheritanceEngine {
 mod tests {
     use super::*;
 
+    impl InheritanceEngine {
+        /// The per-node resolution `resolve_permissions_batch` replaced: a few queries per
+        /// ancestor, no cache. Kept as the reference the batch path is checked against.
+        async fn resolve_permissions_naive(
+            &self,
+            user_id: Uuid,
+            resource_id: Uuid,
+            resource_type: &str,
+        ) -> Result<ResolvedPermissions, String> {
+            // Get direct permissions
+            let direct_perms = self
+                .get_direct_permissions(user_id, resource_id, resource_type)
+                .await?;
+
+            // Get inherited permissions
+            let inherited_perms = self
+                .get_inherited_permissions(user_id, resource_id, resource_type)
+                .await?;
+
+            // Fold in role-based rules for this resource and every resource inherited from
+            let member_role = self
+                .find_user_role(user_id, resource_id, resource_type)
+                .await?;
+            let mut role_scopes: Vec<(Uuid, String)> = inherited_perms
+                .iter()
+                .map(|info| (info.source_id, info.from_role.clone()))
+                .collect();
+            if let Some(role) = &member_role {
+                role_scopes.push((resource_id, role.clone()));
+            }
+            let rules = self.get_applicable_rules(&role_scopes, resource_type).await?;
+            let rule_perms = Self::fold_rule_permissions(&rules, self.config.override_allowed);
+
+            // Merge and resolve effective permissions
+            let mut effective_perms = Self::merge_permissions(&direct_perms, &inherited_perms);
+            effective_perms.extend(rule_perms.iter().cloned());
+            effective_perms.sort();
+            effective_perms.dedup();
+            let role = member_role.unwrap_or_else(|| "viewer".to_string());
+
+            Ok(ResolvedPermissions {
+                user_id,
+                resource_id,
+                resource_type: resource_type.to_string(),
+                direct_permissions: direct_perms,
+                inherited_permissions: inherited_perms,
+                rule_permissions: rule_perms,
+                effective_permissions: effective_perms,
+                role,
+            })
+        }
+
+        /// Get direct permissions assigned to user on resource
+        async fn get_direct_permissions(
+            &self,
+            user_id: Uuid,
+            resource_id: Uuid,
+            resource_type: &str,
+        ) -> Result<Vec<String>, String> {
+            let table = if resource_type == "team" {
+                "team_members"
+            } else if resource_type == "project" {
+                "project_members"
+            } else {
+                return Err("Invalid resource type".to_string());
+            };
+
+            let id_col = if resource_type == "team" {
+                "team_id"
+            } else {
+                "project_id"
+            };
+
+            let query = format!(
+                r#"
+                SELECT permissions FROM {} 
+                WHERE {} = $1 AND user_id = $2
+                "#,
+                table, id_col
+            );
+
+            let result = sqlx::query_scalar::<_, Option<serde_json::Value>>(&query)
+                .bind(resource_id)
+                .bind(user_id)
+                .fetch_optional(&*self.pool)
+                .await
+                .map_err(|e| e.to_string())?;
+
+            match result {
+                Some(Some(perms)) => {
+                    let perms: Vec<String> = serde_json::from_value(perms).unwrap_or_default();
+                    Ok(perms)
+                }
+                _ => Ok(vec![]),
+            }
+        }
+
+        /// Get inherited permissions from parent resources
+        async fn get_inherited_permissions(
+            &self,
+            user_id: Uuid,
+            resource_id: Uuid,
+            resource_type: &str,
+        ) -> Result<Vec<InheritedPermissionInfo>, String> {
+            if !self.config.enabled {
+                return Ok(vec![]);
+            }
+
+            let mut inherited = Vec::new();
+            let mut to_process = vec![(resource_id, 0)];
+            let mut processed = std::collections::HashSet::new();
+
+            while let Some((current_id, depth)) = to_process.pop() {
+                if depth > self.config.max_depth || processed.contains(&current_id) {
+                    continue;
+                }
+                processed.insert(current_id);
+
+                // Get parents
+                let parents = self.get_parents(current_id, resource_type).await?;
+
+                for parent_id in parents {
+                    // Get parent permissions for user
+                    let parent_perms = self
+                        .get_direct_permissions(user_id, parent_id, resource_type)
+                        .await?;
+
+                    if !parent_perms.is_empty() {
+                        let role = self
+                            .get_user_role(user_id, parent_id, resource_type)
+                            .await?;
+
+                        inherited.push(InheritedPermissionInfo {
+                            source_id: parent_id,
+                            source_type: resource_type.to_string(),
+                            permissions: parent_perms,
+                            depth: depth + 1,
+                            from_role: role,
+                        });
+
+                        // Continue traversal
+                        if depth < self.config.max_depth {
+                            to_process.push((parent_id, depth + 1));
+                        }
+                    }
+                }
+            }
+
+            Ok(inherited)
+        }
+
+        /// Get parent resources
+        async fn get_parents(&self, resource_id: Uuid, resource_type: &str) -> Result<Vec<Uuid>, String> {
+            let table = if resource_type == "team" {
+                "team_hierarchy"
+            } else if resource_type == "project" {
+                "project_hierarchy"
+            } else {
+                return Err("Invalid resource type".to_string());
+            };
+
+            let child_col = if resource_type == "team" {
+                "child_team_id"
+            } else {
+                "child_project_id"
+            };
+
+            let parent_col = if resource_type == "team" {
+                "parent_team_id"
+            } else {
+                "parent_project_id"
+            };
+
+            let query = format!(
+                r#"
+                SELECT {} FROM {} 
+                WHERE {} = $1 AND inheritance_enabled = TRUE
+                "#,
+                parent_col, table, child_col
+            );
+
+            let parents = sqlx::query_scalar::<_, Option<Uuid>>(&query)
+                .bind(resource_id)
+                .fetch_all(&*self.pool)
+                .await
+                .map_err(|e| e.to_string())?
+                .into_iter()
+                .flatten()
+                .collect();
+
+            Ok(parents)
+        }
+
+        /// Load the `(permissions, priority)` of every rule matching one of the given
+        /// `(resource, role)` pairs
+        async fn get_applicable_rules(
+            &self,
+            role_scopes: &[(Uuid, String)],
+            resource_type: &str,
+        ) -> Result<Vec<(Vec<String>, i32)>, String> {
+            let id_col = if resource_type == "team" {
+                "team_id"
+            } else if resource_type == "project" {
+                "project_id"
+            } else {
+                return Err("Invalid resource type".to_string());
+            };
+
+            let query = format!(
+                r#"
+                SELECT permissions, priority FROM permission_rules
+                WHERE {} = $1 AND role = $2
+                "#,
+                id_col
+            );
+
+            let mut rules = Vec::new();
+            for (scope_id, role) in role_scopes {
+                let rows = sqlx::query_as::<_, (Json<Vec<String>>, i32)>(&query)
+                    .bind(scope_id)
+                    .bind(role)
+                    .fetch_all(&*self.pool)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                rules.extend(rows.into_iter().map(|(Json(perms), priority)| (perms, priority)));
+            }
+
+            Ok(rules)
+        }
+
+        /// Get user's role on resource, defaulting to viewer for non-members
+        async fn get_user_role(
+            &self,
+            user_id: Uuid,
+            resource_id: Uuid,
+            resource_type: &str,
+        ) -> Result<String, String> {
+            Ok(self
+                .find_user_role(user_id, resource_id, resource_type)
+                .await?
+                .unwrap_or_else(|| "viewer".to_string()))
+        }
+
+        /// Get user's membership role on resource, if any
+        async fn find_user_role(
+            &self,
+            user_id: Uuid,
+            resource_id: Uuid,
+            resource_type: &str,
+        ) -> Result<Option<String>, String> {
+            let table = if resource_type == "team" {
+                "team_members"
+            } else if resource_type == "project" {
+                "project_members"
+            } else {
+                return Err("Invalid resource type".to_string());
+            };
+
+            let id_col = if resource_type == "team" {
+                "team_id"
+            } else {
+                "project_id"
+            };
+
+            let query = format!(
+                r#"
+                SELECT role FROM {} 
+                WHERE {} = $1 AND user_id = $2
+                "#,
+                table, id_col
+            );
+
+            let role = sqlx::query_scalar::<_, Option<String>>(&query)
+                .bind(resource_id)
+                .bind(user_id)
+                .fetch_optional(&*self.pool)
+                .await
+                .map_err(|e| e.to_string())?
+                .flatten();
+
+            Ok(role)
+        }
+    }
+
     #[test]
     fn test_merge_permissions() {
         let direct = vec!["read".to_string(), "write".to_string()];
@@ -390,4 +817,313 @@ mod tests {
         assert!(merged.contains(&"write".to_string()));
         assert!(merged.contains(&"admin".to_string()));
     }
+
+    #[test]
+    fn test_fold_rule_permissions_respects_priority() {
+        let perms = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        let rules = vec![
+            (perms(&["read", "write"]), 0),
+            (perms(&["read", "view_audit"]), 10),
+            (perms(&["invite"]), 10),
+        ];
+
+        assert_eq!(
+            InheritanceEngine::fold_rule_permissions(&rules, true),
+            vec!["invite", "read", "view_audit"]
+        );
+        assert_eq!(
+            InheritanceEngine::fold_rule_permissions(&rules, false),
+            vec!["invite", "read", "view_audit", "write"]
+        );
+        assert!(InheritanceEngine::fold_rule_permissions(&[], true).is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_cycle_detection_on_chain() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool();
+
+        // a -> b -> c
+        let owner = crate::db::insert_test_user(pool).await;
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        for team_id in [a, b, c] {
+            sqlx::query("INSERT INTO teams (id, owner_id, name, slug) VALUES ($1, $2, $3, $4)")
+                .bind(team_id)
+                .bind(owner)
+                .bind("cycle test")
+                .bind(team_id.to_string())
+                .execute(pool)
+                .await
+                .unwrap();
+        }
+        for (parent, child) in [(a, b), (b, c)] {
+            sqlx::query("INSERT INTO team_hierarchy (id, parent_team_id, child_team_id, inheritance_enabled) VALUES ($1, $2, $3, FALSE)")
+                .bind(Uuid::new_v4())
+                .bind(parent)
+                .bind(child)
+                .execute(pool)
+                .await
+                .unwrap();
+        }
+
+        let mut tx = pool.begin().await.unwrap();
+        // Linking c as parent of a would close the loop, even through disabled edges
+        for (child, parent) in [(a, c), (b, c)] {
+            assert!(InheritanceEngine::would_create_cycle(&mut tx, child, parent, "team").await.unwrap());
+        }
+        // Self-parenting is a cycle too
+        assert!(InheritanceEngine::would_create_cycle(&mut tx, b, b, "team").await.unwrap());
+
+        // Shortcut edges and new leaves are fine
+        for (child, parent) in [(c, a), (Uuid::new_v4(), c)] {
+            assert!(!InheritanceEngine::would_create_cycle(&mut tx, child, parent, "team").await.unwrap());
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_cache_entries_expire() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool();
+
+        let user_id = crate::db::insert_test_user(pool).await;
+        let team_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO teams (id, owner_id, name, slug) VALUES ($1, $2, $3, $4)")
+            .bind(team_id)
+            .bind(user_id)
+            .bind("cache test")
+            .bind(team_id.to_string())
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            r#"
+            INSERT INTO team_members (id, team_id, user_id, role, permissions)
+            VALUES ($1, $2, $3, 'member', '["read"]')
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(team_id)
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .unwrap();
+
+        let config = InheritanceConfig {
+            cache_ttl_secs: 1,
+            ..InheritanceConfig::default()
+        };
+        let engine = InheritanceEngine::new(Arc::new(pool.clone()), Some(config));
+        let resolve = || engine.resolve_permissions(user_id, team_id, "team");
+        assert_eq!(resolve().await.unwrap().effective_permissions, vec!["read"]);
+
+        sqlx::query(r#"UPDATE team_members SET permissions = '["read", "write"]' WHERE team_id = $1"#)
+            .bind(team_id)
+            .execute(pool)
+            .await
+            .unwrap();
+
+        // Still served from cache
+        assert_eq!(resolve().await.unwrap().effective_permissions, vec!["read"]);
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert_eq!(
+            resolve().await.unwrap().effective_permissions,
+            vec!["read", "write"]
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_role_rule_grants_missing_permission() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool();
+
+        let user_id = crate::db::insert_test_user(pool).await;
+        let team_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO teams (id, owner_id, name, slug) VALUES ($1, $2, $3, $4)")
+            .bind(team_id)
+            .bind(user_id)
+            .bind("rule test")
+            .bind(team_id.to_string())
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            r#"
+            INSERT INTO team_members (id, team_id, user_id, role, permissions)
+            VALUES ($1, $2, $3, 'member', '["read"]')
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(team_id)
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .unwrap();
+
+        // Resolutions land in whichever backend the engine was given
+        let backend = Arc::new(crate::services::cache::MemoryBackend::new(16));
+        let engine = InheritanceEngine::new(Arc::new(pool.clone()), None).with_cache_backend(backend.clone());
+        assert!(!engine.has_permission(user_id, team_id, "team", "view_audit").await.unwrap());
+        assert!(!backend.is_empty());
+
+        sqlx::query(
+            r#"
+            INSERT INTO permission_rules (id, team_id, role, permissions, priority)
+            VALUES ($1, $2, 'member', '["view_audit"]', 10)
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(team_id)
+        .execute(pool)
+        .await
+        .unwrap();
+        engine.clear_cache().await;
+        assert!(backend.is_empty());
+
+        let resolved = engine.resolve_permissions(user_id, team_id, "team").await.unwrap();
+        assert_eq!(resolved.direct_permissions, vec!["read"]);
+        assert_eq!(resolved.rule_permissions, vec!["view_audit"]);
+        assert_eq!(resolved.effective_permissions, vec!["read", "view_audit"]);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_batch_resolution_matches_per_resource_resolution() {
+        let db = crate::db::test_database().await;
+        let pool = db.pool();
+
+        let user_id = crate::db::insert_test_user(pool).await;
+
+        //        org
+        //       /   \
+        //   eng       ops    (ops -> org is disabled)
+        //   /  \     /
+        // web  api--+        (api has two parents)
+        //  |
+        // web-ui
+        let [org, eng, ops, web, api, web_ui] = [(); 6].map(|_| Uuid::new_v4());
+        for team_id in [org, eng, ops, web, api, web_ui] {
+            sqlx::query("INSERT INTO teams (id, owner_id, name, slug) VALUES ($1, $2, $3, $4)")
+                .bind(team_id)
+                .bind(user_id)
+                .bind("batch test")
+                .bind(team_id.to_string())
+                .execute(pool)
+                .await
+                .unwrap();
+        }
+        for (parent, child, enabled) in [
+            (org, eng, true),
+            (org, ops, false),
+            (eng, web, true),
+            (eng, api, true),
+            (ops, api, true),
+            (web, web_ui, true),
+        ] {
+            sqlx::query(
+                r#"
+                INSERT INTO team_hierarchy (id, parent_team_id, child_team_id, inheritance_enabled)
+                VALUES ($1, $2, $3, $4)
+                "#,
+            )
+            .bind(Uuid::new_v4())
+            .bind(parent)
+            .bind(child)
+            .bind(enabled)
+            .execute(pool)
+            .await
+            .unwrap();
+        }
+        for (team_id, role, permissions) in [
+            (org, "admin", r#"["read", "admin"]"#),
+            (eng, "member", r#"["read"]"#),
+            (ops, "member", r#"["deploy"]"#),
+            (web, "member", r#"["read", "write"]"#),
+            (api, "viewer", "[]"),
+        ] {
+            sqlx::query(
+                r#"
+                INSERT INTO team_members (id, team_id, user_id, role, permissions)
+                VALUES ($1, $2, $3, $4, $5::jsonb)
+                "#,
+            )
+            .bind(Uuid::new_v4())
+            .bind(team_id)
+            .bind(user_id)
+            .bind(role)
+            .bind(permissions)
+            .execute(pool)
+            .await
+            .unwrap();
+        }
+        for (team_id, role, permissions, priority) in [
+            (org, "admin", r#"["view_audit"]"#, 10),
+            (web, "member", r#"["invite"]"#, 0),
+            (api, "viewer", r#"["comment"]"#, 0),
+        ] {
+            sqlx::query(
+                r#"
+                INSERT INTO permission_rules (id, team_id, role, permissions, priority)
+                VALUES ($1, $2, $3, $4::jsonb, $5)
+                "#,
+            )
+            .bind(Uuid::new_v4())
+            .bind(team_id)
+            .bind(role)
+            .bind(permissions)
+            .bind(priority)
+            .execute(pool)
+            .await
+            .unwrap();
+        }
+
+        let engine = InheritanceEngine::new(Arc::new(pool.clone()), None);
+        let teams = [org, eng, ops, web, api, web_ui];
+        let batch = engine
+            .resolve_permissions_batch(user_id, &teams, "team")
+            .await
+            .unwrap();
+        assert_eq!(batch.len(), teams.len());
+
+        let sources = |resolved: &ResolvedPermissions| {
+            let mut sources: Vec<_> = resolved
+                .inherited_permissions
+                .iter()
+                .map(|info| (info.source_id, info.depth, info.from_role.clone(), info.permissions.clone()))
+                .collect();
+            sources.sort();
+            sources
+        };
+        for team_id in teams {
+            let naive = engine
+                .resolve_permissions_naive(user_id, team_id, "team")
+                .await
+                .unwrap();
+            let batched = &batch[&team_id];
+            assert_eq!(batched.direct_permissions, naive.direct_permissions);
+            assert_eq!(sources(batched), sources(&naive));
+            assert_eq!(batched.rule_permissions, naive.rule_permissions);
+            assert_eq!(batched.effective_permissions, naive.effective_permissions);
+            assert_eq!(batched.role, naive.role);
+        }
+
+        // Three levels up to org, whose higher-priority rule overrides web's
+        assert_eq!(batch[&web_ui].inherited_permissions.len(), 3);
+        assert_eq!(batch[&web_ui].rule_permissions, vec!["view_audit"]);
+        assert_eq!(
+            batch[&web_ui].effective_permissions,
+            vec!["admin", "read", "view_audit", "write"]
+        );
+        // ops' grant stops at the disabled edge to org
+        assert_eq!(batch[&ops].effective_permissions, vec!["deploy"]);
+
+        // The single-resource API goes through the same path and agrees
+        engine.clear_cache().await;
+        let single = engine.resolve_permissions(user_id, api, "team").await.unwrap();
+        assert_eq!(sources(&single), sources(&batch[&api]));
+        assert_eq!(single.effective_permissions, batch[&api].effective_permissions);
+    }
 }
@@ -0,0 +1,36 @@
+use crate::services::events::{DomainEvent, EventBus};
+
+/// Send the emails domain events call for. There's no outbound mail transport yet, so each
+/// message is logged, without its secret, where it would be sent.
+pub fn subscribe(bus: &EventBus) {
+    bus.spawn_subscriber("mailer", |event| async move {
+        match event {
+            DomainEvent::TeamInvitationCreated { team_id, invitation_id, email, expires_at, .. } => {
+                tracing::info!(
+                    "Team invitation {} to team {} for {} issued, valid until {}",
+                    invitation_id,
+                    team_id,
+                    email,
+                    expires_at
+                );
+            }
+            DomainEvent::EmailVerificationRequested { user_id, email, expires_at, .. } => {
+                tracing::info!(
+                    "Email verification link for user {} sent to {}, valid until {}",
+                    user_id,
+                    email,
+                    expires_at
+                );
+            }
+            DomainEvent::PasswordResetRequested { user_id, email, expires_at, .. } => {
+                tracing::info!(
+                    "Password reset link for user {} sent to {}, valid until {}",
+                    user_id,
+                    email,
+                    expires_at
+                );
+            }
+            _ => {}
+        }
+    });
+}
@@ -1,10 +1,22 @@
 pub mod ai;
 pub mod agent;
+pub mod analysis_cache;
+pub mod cache;
+pub mod audit;
+pub mod circuit_breaker;
 pub mod code_analysis;
 pub mod analytics;
 pub mod collaboration;
 pub mod ot_engine;
 pub mod inheritance;
+pub mod mailer;
+pub mod diff;
+pub mod events;
+pub mod oauth;
+pub mod prompts;
+#[cfg(feature = "redis")]
+pub mod redis_store;
+pub mod webhooks;
 
 pub use ot_engine::OTEngine;
 pub use inheritance::InheritanceEngine;
@@ -0,0 +1,164 @@
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::error::{AppError, AppResult};
+
+const GITHUB_SCOPES: &str = "read:user user:email";
+const GITHUB_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// GitHub OAuth app settings, taken from `Config`. The URLs only change in tests,
+/// which point them at a local stand-in for GitHub.
+#[derive(Debug, Clone)]
+pub struct GithubOAuth {
+    pub client_id: String,
+    pub client_secret: String,
+    /// Where GitHub sends the browser back to: our `/api/auth/oauth/github/callback`
+    pub redirect_url: String,
+    /// Serves `/authorize` and `/access_token`
+    pub oauth_url: String,
+    pub api_url: String,
+    client: reqwest::Client,
+}
+
+/// The GitHub account behind an access token
+#[derive(Debug, Clone)]
+pub struct GithubProfile {
+    pub id: i64,
+    pub login: String,
+    pub name: Option<String>,
+    /// The primary address if GitHub has verified it, otherwise any verified one
+    pub verified_email: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AccessTokenResponse {
+    access_token: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GithubUser {
+    id: i64,
+    login: String,
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GithubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+impl GithubOAuth {
+    /// `None` unless both `GITHUB_CLIENT_ID` and `GITHUB_CLIENT_SECRET` are set
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let client_id = config.github_client_id.clone()?;
+        let client_secret = config.github_client_secret.clone()?;
+        Some(Self::new(
+            client_id,
+            client_secret,
+            config.github_redirect_url.clone(),
+            config.github_oauth_url.clone(),
+            config.github_api_url.clone(),
+        ))
+    }
+
+    pub fn new(
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+        redirect_url: impl Into<String>,
+        oauth_url: impl Into<String>,
+        api_url: impl Into<String>,
+    ) -> Self {
+        Self {
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            redirect_url: redirect_url.into(),
+            oauth_url: oauth_url.into().trim_end_matches('/').to_string(),
+            api_url: api_url.into().trim_end_matches('/').to_string(),
+            client: reqwest::Client::builder()
+                .timeout(GITHUB_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// The GitHub consent page to send the browser to
+    pub fn authorize_url(&self, state: &str) -> AppResult<String> {
+        reqwest::Url::parse_with_params(
+            &format!("{}/authorize", self.oauth_url),
+            &[
+                ("client_id", self.client_id.as_str()),
+                ("redirect_uri", self.redirect_url.as_str()),
+                ("scope", GITHUB_SCOPES),
+                ("state", state),
+            ],
+        )
+        .map(String::from)
+        .map_err(|_| AppError::InternalServerError("Invalid GitHub OAuth URL".to_string()))
+    }
+
+    /// Trade the callback's `code` for a GitHub access token
+    pub async fn exchange_code(&self, code: &str) -> AppResult<String> {
+        let response: AccessTokenResponse = self
+            .client
+            .post(format!("{}/access_token", self.oauth_url))
+            .header(reqwest::header::ACCEPT, "application/json")
+            .form(&[
+                ("client_id", self.client_id.as_str()),
+                ("client_secret", self.client_secret.as_str()),
+                ("code", code),
+                ("redirect_uri", self.redirect_url.as_str()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        // GitHub reports a bad or expired code as 200 with an `error` field
+        match (response.access_token, response.error) {
+            (Some(token), _) => Ok(token),
+            (None, error) => {
+                tracing::warn!("GitHub rejected an OAuth code: {}", error.unwrap_or_default());
+                Err(AppError::AuthenticationError("GitHub login failed".to_string()))
+            }
+        }
+    }
+
+    /// Look up the account and its verified email
+    pub async fn fetch_profile(&self, access_token: &str) -> AppResult<GithubProfile> {
+        let user: GithubUser = self.get(access_token, "/user").await?;
+        let emails: Vec<GithubEmail> = self.get(access_token, "/user/emails").await?;
+
+        let verified_email = emails
+            .iter()
+            .filter(|email| email.verified)
+            .max_by_key(|email| email.primary)
+            .map(|email| email.email.clone());
+
+        Ok(GithubProfile {
+            id: user.id,
+            login: user.login,
+            name: user.name,
+            verified_email,
+        })
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&self, access_token: &str, path: &str) -> AppResult<T> {
+        Ok(self
+            .client
+            .get(format!("{}{}", self.api_url, path))
+            .bearer_auth(access_token)
+            .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+            // GitHub refuses API requests without one
+            .header(reqwest::header::USER_AGENT, "compilex7")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+}
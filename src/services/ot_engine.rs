@@ -6,8 +6,23 @@ use crate::models::collaboration::{
 };
 
 /// Operational Transformation engine for conflict resolution
+///
+/// Positions and lengths count chars, not bytes, so edits never split a multi-byte character.
 pub struct OTEngine;
 
+/// Number of chars in `text`
+fn char_len(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// Byte offset of the char at `char_pos`, clamped to the end of `text`
+fn byte_index(text: &str, char_pos: usize) -> usize {
+    text.char_indices()
+        .nth(char_pos)
+        .map(|(index, _)| index)
+        .unwrap_or(text.len())
+}
+
 impl OTEngine {
     /// Transform operation against concurrent operations (Client-side OT)
     pub fn transform(
@@ -32,21 +47,21 @@ impl OTEngine {
             // Insert vs Insert
             (
                 OperationType::Insert {
-                    position: base_pos,
-                    content: base_content,
+                    position: base_pos, ..
                 },
                 OperationType::Insert {
-                    position: other_pos, ..
+                    position: other_pos,
+                    content: other_content,
                 },
             ) => {
                 let new_pos = if other_pos < base_pos {
-                    base_pos + base_content.len()
+                    base_pos + char_len(other_content)
                 } else if other_pos == base_pos {
                     // Tie-break by operation ID
                     if base_op.id < other_op.id {
                         *base_pos
                     } else {
-                        base_pos + base_content.len()
+                        base_pos + char_len(other_content)
                     }
                 } else {
                     *base_pos
@@ -61,28 +76,22 @@ impl OTEngine {
 
             // Insert vs Delete
             (
-                OperationType::Insert {
-                    position: base_pos, ..
-                },
+                OperationType::Insert { .. },
                 OperationType::Delete {
                     position: del_pos,
                     length: del_len,
                 },
             ) => {
-                let new_pos = if del_pos < base_pos && del_pos + del_len > base_pos {
-                    // Delete range overlaps with insert position
-                    *del_pos
-                } else if del_pos < base_pos {
-                    // Delete before insert
-                    base_pos.saturating_sub(del_len)
-                } else {
-                    // Delete after insert
-                    *base_pos
-                };
-
                 let mut result = base_op.clone();
-                if let OperationType::Insert { position, .. } = &mut result.operation {
-                    *position = new_pos;
+                if let OperationType::Insert { position, content } = &mut result.operation {
+                    if *del_pos < *position && *position < del_pos + del_len {
+                        // Inserted inside the deleted range - the delete wins, so the insert is dropped
+                        *position = *del_pos;
+                        content.clear();
+                    } else if del_pos + del_len <= *position {
+                        // Delete before insert
+                        *position -= del_len;
+                    }
                 }
                 result
             }
@@ -98,25 +107,21 @@ impl OTEngine {
                     content: ins_content,
                 },
             ) => {
-                let new_pos = if ins_pos < base_pos {
+                let (new_pos, new_len) = if ins_pos <= base_pos {
                     // Insert before delete
-                    base_pos + ins_content.len()
-                } else if ins_pos >= base_pos && ins_pos < base_pos + base_len {
-                    // Insert within delete range - trim delete
-                    let new_len = base_len.saturating_sub(1);
-                    let mut result = base_op.clone();
-                    if let OperationType::Delete { length, .. } = &mut result.operation {
-                        *length = new_len;
-                    }
-                    return result;
+                    (base_pos + char_len(ins_content), *base_len)
+                } else if *ins_pos < base_pos + base_len {
+                    // Insert within delete range - the delete also removes the inserted content
+                    (*base_pos, base_len + char_len(ins_content))
                 } else {
                     // Insert after delete
-                    *base_pos
+                    (*base_pos, *base_len)
                 };
 
                 let mut result = base_op.clone();
-                if let OperationType::Delete { position, .. } = &mut result.operation {
+                if let OperationType::Delete { position, length } = &mut result.operation {
                     *position = new_pos;
+                    *length = new_len;
                 }
                 result
             }
@@ -132,27 +137,17 @@ impl OTEngine {
                     length: other_len,
                 },
             ) => {
-                let (new_pos, new_len) = if other_pos < base_pos {
-                    if other_pos + other_len > base_pos {
-                        // Other delete overlaps with base delete
-                        let overlap = (other_pos + other_len) - base_pos;
-                        (
-                            *other_pos,
-                            base_len.saturating_sub(overlap.min(base_len as usize) as usize),
-                        )
-                    } else {
-                        // Other delete fully before base delete
-                        (base_pos.saturating_sub(other_len), *base_len)
-                    }
-                } else if other_pos >= base_pos && other_pos < base_pos + base_len {
-                    // Other delete overlaps with base delete
-                    let overlap_end = (base_pos + base_len).min(other_pos + other_len);
-                    let new_delete_len = (overlap_end - base_pos).max(other_pos - base_pos);
-                    (*base_pos, new_delete_len)
+                // Only what the other delete hasn't already removed is left to delete,
+                // shifted left by whatever it removed before the base range
+                let overlap = (base_pos + base_len)
+                    .min(other_pos + other_len)
+                    .saturating_sub(*base_pos.max(other_pos));
+                let removed_before = if other_pos < base_pos {
+                    (*other_len).min(base_pos - other_pos)
                 } else {
-                    // Other delete fully after base delete
-                    (*base_pos, *base_len)
+                    0
                 };
+                let (new_pos, new_len) = (base_pos - removed_before, base_len - overlap);
 
                 let mut result = base_op.clone();
                 if let OperationType::Delete {
@@ -166,14 +161,276 @@ impl OTEngine {
                 result
             }
 
-            // Replace against Insert/Delete
+            // Replace against Insert/Delete/Replace
             (OperationType::Replace { .. }, _) | (_, OperationType::Replace { .. }) => {
-                // Replace is treated as delete + insert
-                base_op.clone()
+                let mut result = base_op.clone();
+                result.operation = Self::transform_replace(base_op, other_op);
+                result
             }
         }
     }
 
+    /// Transform when either side is a Replace, treating a Replace as a delete of
+    /// `old_content` followed by an insert of `new_content` at the same position
+    fn transform_replace(
+        base_op: &DocumentOperation,
+        other_op: &DocumentOperation,
+    ) -> OperationType {
+        match (&base_op.operation, &other_op.operation) {
+            (
+                OperationType::Replace {
+                    position,
+                    old_content,
+                    new_content,
+                },
+                OperationType::Delete {
+                    position: del_pos,
+                    length: del_len,
+                },
+            ) => Self::transform_replace_against_delete(
+                *position,
+                old_content,
+                new_content,
+                *del_pos,
+                *del_len,
+            ),
+
+            (
+                OperationType::Delete { position, length },
+                OperationType::Replace {
+                    position: rep_pos,
+                    old_content,
+                    new_content,
+                },
+            ) => Self::transform_delete_against_replace(
+                *position,
+                *length,
+                *rep_pos,
+                char_len(old_content),
+                char_len(new_content),
+            ),
+
+            // An Insert is a Replace of empty content
+            (base, other) => match (Self::replace_parts(base), Self::replace_parts(other)) {
+                (Some(base_parts), Some(other_parts)) => Self::transform_replace_against_replace(
+                    base_parts,
+                    other_parts,
+                    base_op.id < other_op.id,
+                ),
+                _ => base.clone(),
+            },
+        }
+    }
+
+    fn replace_parts(op: &OperationType) -> Option<(usize, &str, &str)> {
+        match op {
+            OperationType::Insert { position, content } => Some((*position, "", content)),
+            OperationType::Replace {
+                position,
+                old_content,
+                new_content,
+            } => Some((*position, old_content, new_content)),
+            OperationType::Delete { .. } => None,
+        }
+    }
+
+    /// Overlapping replaces converge on replacing the union of both regions with
+    /// both new contents, ordered by operation ID
+    fn transform_replace_against_replace(
+        (base_pos, base_old, base_new): (usize, &str, &str),
+        (other_pos, other_old, other_new): (usize, &str, &str),
+        base_first: bool,
+    ) -> OperationType {
+        let base_end = base_pos + char_len(base_old);
+        let other_end = other_pos + char_len(other_old);
+        let base_before_other = base_end <= other_pos;
+        let other_before_base = other_end <= base_pos;
+
+        let shifted = |position: usize| OperationType::Replace {
+            position,
+            old_content: base_old.to_string(),
+            new_content: base_new.to_string(),
+        };
+
+        if base_before_other && other_before_base {
+            // Both are pure insertions at the same position - tie-break by operation ID
+            let position = if base_first {
+                base_pos
+            } else {
+                base_pos + char_len(other_new)
+            };
+            return Self::simplify_replace(position, base_old.to_string(), base_new.to_string());
+        }
+        if base_before_other {
+            return shifted(base_pos);
+        }
+        if other_before_base {
+            return shifted(base_pos - char_len(other_old) + char_len(other_new));
+        }
+
+        // Remaining base content either side of the other replacement, which is now removed too
+        let mut old_content = String::new();
+        old_content.push_str(&base_old[..byte_index(base_old, other_pos.saturating_sub(base_pos))]);
+        old_content.push_str(other_new);
+        old_content.push_str(&base_old[byte_index(base_old, other_end.saturating_sub(base_pos))..]);
+
+        let new_content = if base_first {
+            format!("{}{}", base_new, other_new)
+        } else {
+            format!("{}{}", other_new, base_new)
+        };
+
+        Self::simplify_replace(base_pos.min(other_pos), old_content, new_content)
+    }
+
+    /// A delete overlapping the replaced region wins: the replacement content is dropped
+    fn transform_replace_against_delete(
+        position: usize,
+        old_content: &str,
+        new_content: &str,
+        del_pos: usize,
+        del_len: usize,
+    ) -> OperationType {
+        let end = position + char_len(old_content);
+
+        if del_len == 0 || end <= del_pos {
+            // Delete after replace
+            return OperationType::Replace {
+                position,
+                old_content: old_content.to_string(),
+                new_content: new_content.to_string(),
+            };
+        }
+        if del_pos + del_len <= position {
+            // Delete before replace
+            return OperationType::Replace {
+                position: position - del_len,
+                old_content: old_content.to_string(),
+                new_content: new_content.to_string(),
+            };
+        }
+
+        // Only the replaced content the delete did not already remove is left to delete
+        let mut remaining = String::new();
+        remaining.push_str(&old_content[..byte_index(old_content, del_pos.saturating_sub(position))]);
+        remaining.push_str(
+            &old_content[byte_index(old_content, (del_pos + del_len).saturating_sub(position))..],
+        );
+
+        Self::simplify_replace(position.min(del_pos), remaining, String::new())
+    }
+
+    /// Mirror of `transform_replace_against_delete`: the delete also removes the replacement content
+    fn transform_delete_against_replace(
+        position: usize,
+        length: usize,
+        rep_pos: usize,
+        old_len: usize,
+        new_len: usize,
+    ) -> OperationType {
+        let rep_end = rep_pos + old_len;
+
+        let (position, length) = if length == 0 || position + length <= rep_pos {
+            // Replace after delete
+            (position, length)
+        } else if rep_end <= position {
+            // Replace before delete
+            (position - old_len + new_len, length)
+        } else {
+            (
+                position.min(rep_pos),
+                rep_pos.saturating_sub(position)
+                    + new_len
+                    + (position + length).saturating_sub(rep_end),
+            )
+        };
+
+        OperationType::Delete { position, length }
+    }
+
+    /// Reduce a replace to its minimal form by trimming content common to old and new
+    fn simplify_replace(position: usize, old_content: String, new_content: String) -> OperationType {
+        let prefix: usize = old_content
+            .chars()
+            .zip(new_content.chars())
+            .take_while(|(a, b)| a == b)
+            .map(|(c, _)| c.len_utf8())
+            .sum();
+        let suffix: usize = old_content[prefix..]
+            .chars()
+            .rev()
+            .zip(new_content[prefix..].chars().rev())
+            .take_while(|(a, b)| a == b)
+            .map(|(c, _)| c.len_utf8())
+            .sum();
+
+        let position = position + char_len(&old_content[..prefix]);
+        let old_content = &old_content[prefix..old_content.len() - suffix];
+        let new_content = &new_content[prefix..new_content.len() - suffix];
+
+        if old_content.is_empty() && !new_content.is_empty() {
+            OperationType::Insert {
+                position,
+                content: new_content.to_string(),
+            }
+        } else if new_content.is_empty() && !old_content.is_empty() {
+            OperationType::Delete {
+                position,
+                length: char_len(old_content),
+            }
+        } else {
+            OperationType::Replace {
+                position,
+                old_content: old_content.to_string(),
+                new_content: new_content.to_string(),
+            }
+        }
+    }
+
+    /// Build the operation that undoes `op`, given the content it was applied to
+    // Nothing calls it until sessions keep a per-user undo stack
+    #[allow(dead_code)]
+    pub fn invert(op: &DocumentOperation, content_before: &str) -> DocumentOperation {
+        let content_len = char_len(content_before);
+        let removed = |position: usize, length: usize| {
+            content_before
+                .chars()
+                .skip(position)
+                .take(length)
+                .collect::<String>()
+        };
+
+        let operation = match &op.operation {
+            OperationType::Insert { position, content } => OperationType::Delete {
+                position: (*position).min(content_len),
+                length: char_len(content),
+            },
+
+            OperationType::Delete { position, length } => OperationType::Insert {
+                position: (*position).min(content_len),
+                content: removed(*position, *length),
+            },
+
+            OperationType::Replace {
+                position,
+                old_content,
+                new_content,
+            } => OperationType::Replace {
+                position: (*position).min(content_len),
+                old_content: new_content.clone(),
+                new_content: removed(*position, char_len(old_content)),
+            },
+        };
+
+        DocumentOperation {
+            id: Uuid::new_v4().to_string(),
+            version: op.version + 1,
+            timestamp: Utc::now(),
+            user_id: op.user_id,
+            operation,
+        }
+    }
+
     /// Detect conflicts between operations
     pub fn detect_conflicts(
         client_version: u32,
@@ -227,10 +484,10 @@ impl OTEngine {
     }
 
     /// Apply single operation to content
-    fn apply_operation(content: &str, op: &DocumentOperation) -> String {
+    pub fn apply_operation(content: &str, op: &DocumentOperation) -> String {
         match &op.operation {
             OperationType::Insert { position, content: text } => {
-                let pos = (*position).min(content.len());
+                let pos = byte_index(content, *position);
                 let mut result = String::new();
                 result.push_str(&content[..pos]);
                 result.push_str(text);
@@ -239,8 +496,8 @@ impl OTEngine {
             }
 
             OperationType::Delete { position, length } => {
-                let start = (*position).min(content.len());
-                let end = (start + length).min(content.len());
+                let start = byte_index(content, *position);
+                let end = byte_index(content, position.saturating_add(*length));
                 let mut result = String::new();
                 result.push_str(&content[..start]);
                 result.push_str(&content[end..]);
@@ -249,20 +506,37 @@ impl OTEngine {
 
             OperationType::Replace {
                 position,
-                old_content: _,
+                old_content,
                 new_content: text,
             } => {
-                let pos = (*position).min(content.len());
+                let start = byte_index(content, *position);
+                let end = byte_index(content, position.saturating_add(char_len(old_content)));
                 let mut result = String::new();
-                result.push_str(&content[..pos]);
+                result.push_str(&content[..start]);
                 result.push_str(text);
-                result.push_str(&content[pos..]);
+                result.push_str(&content[end..]);
                 result
             }
         }
     }
 
-    /// Validate operation feasibility
+    /// Length in chars of a `content_length`-char document once `op` is applied to it
+    pub fn applied_length(op: &DocumentOperation, content_length: usize) -> usize {
+        let removed = |position: usize, length: usize| {
+            position.saturating_add(length).min(content_length) - position.min(content_length)
+        };
+        match &op.operation {
+            OperationType::Insert { content, .. } => content_length + char_len(content),
+            OperationType::Delete { position, length } => content_length - removed(*position, *length),
+            OperationType::Replace {
+                position,
+                old_content,
+                new_content,
+            } => content_length - removed(*position, char_len(old_content)) + char_len(new_content),
+        }
+    }
+
+    /// Validate operation feasibility against a document of `content_length` chars
     pub fn validate_operation(
         op: &DocumentOperation,
         content_length: usize,
@@ -291,7 +565,7 @@ impl OTEngine {
                 if *length == 0 {
                     return Err("Delete length must be greater than 0".to_string());
                 }
-                if position + length > content_length {
+                if position.checked_add(*length).is_none_or(|end| end > content_length) {
                     return Err("Delete range exceeds content length".to_string());
                 }
                 Ok(())
@@ -311,6 +585,12 @@ impl OTEngine {
                 if old_content.is_empty() && new_content.is_empty() {
                     return Err("Replace must have non-empty old or new content".to_string());
                 }
+                if position
+                    .checked_add(char_len(old_content))
+                    .is_none_or(|end| end > content_length)
+                {
+                    return Err("Replace range exceeds content length".to_string());
+                }
                 Ok(())
             }
         }
@@ -347,6 +627,31 @@ mod tests {
         }
     }
 
+    fn make_replace_op(pos: usize, old: &str, new: &str) -> DocumentOperation {
+        DocumentOperation {
+            id: Uuid::new_v4().to_string(),
+            version: 1,
+            timestamp: Utc::now(),
+            user_id: Uuid::new_v4(),
+            operation: OperationType::Replace {
+                position: pos,
+                old_content: old.to_string(),
+                new_content: new.to_string(),
+            },
+        }
+    }
+
+    /// Apply each op followed by the other's transformed op and assert both sides agree
+    fn converge(content: &str, a: &DocumentOperation, b: &DocumentOperation) -> String {
+        let a_prime = OTEngine::transform_against(a, b);
+        let b_prime = OTEngine::transform_against(b, a);
+
+        let left = OTEngine::apply_operation(&OTEngine::apply_operation(content, a), &b_prime);
+        let right = OTEngine::apply_operation(&OTEngine::apply_operation(content, b), &a_prime);
+        assert_eq!(left, right);
+        left
+    }
+
     #[test]
     fn test_insert_insert_transform() {
         let op1 = make_insert_op(5, "hello");
@@ -388,6 +693,11 @@ mod tests {
 
         let invalid_delete = make_delete_op(15, 10);
         assert!(OTEngine::validate_operation(&invalid_delete, 20).is_err());
+
+        // Ranges whose end overflows are out of range rather than a panic
+        assert!(OTEngine::validate_operation(&make_delete_op(5, usize::MAX), 20).is_err());
+        assert!(OTEngine::validate_operation(&make_replace_op(usize::MAX, "", "x"), usize::MAX).is_ok());
+        assert!(OTEngine::validate_operation(&make_replace_op(usize::MAX, "x", ""), usize::MAX).is_err());
     }
 
     #[test]
@@ -401,5 +711,125 @@ mod tests {
         let delete = make_delete_op(5, 6);
         let result = OTEngine::apply_operation(content, &delete);
         assert_eq!(result, "hello");
+
+        // Lengths are predicted without applying, out-of-range ends included
+        for op in [insert, delete, make_delete_op(8, usize::MAX), make_replace_op(6, "world!!", "rust")] {
+            let applied = OTEngine::apply_operation(content, &op).chars().count();
+            assert_eq!(OTEngine::applied_length(&op, content.chars().count()), applied);
+        }
+    }
+
+    #[test]
+    fn test_apply_replace() {
+        let replace = make_replace_op(6, "world", "rust");
+        assert_eq!(OTEngine::apply_operation("hello world", &replace), "hello rust");
+    }
+
+    #[test]
+    fn test_replace_insert_convergence() {
+        let content = "hello world";
+        let replace = make_replace_op(6, "world", "rust");
+
+        let before = make_insert_op(0, ">> ");
+        assert_eq!(converge(content, &replace, &before), ">> hello rust");
+
+        let after = make_insert_op(11, "!");
+        assert_eq!(converge(content, &replace, &after), "hello rust!");
+
+        let inside = make_insert_op(8, "XX");
+        let result = converge(content, &replace, &inside);
+        assert!(result.starts_with("hello "));
+        assert!(result.contains("XX") && result.contains("rust"));
+    }
+
+    #[test]
+    fn test_replace_delete_convergence() {
+        let content = "hello world";
+        let replace = make_replace_op(6, "world", "rust");
+
+        let before = make_delete_op(0, 6);
+        assert_eq!(converge(content, &replace, &before), "rust");
+
+        // Overlapping delete removes the replaced region along with the replacement
+        let overlapping = make_delete_op(4, 4);
+        assert_eq!(converge(content, &replace, &overlapping), "hell");
+
+        let inside = make_delete_op(7, 2);
+        assert_eq!(converge(content, &replace, &inside), "hello ");
+    }
+
+    #[test]
+    fn test_replace_replace_convergence() {
+        let content = "hello world";
+
+        let a = make_replace_op(0, "hello", "goodbye");
+        let b = make_replace_op(6, "world", "rust");
+        assert_eq!(converge(content, &a, &b), "goodbye rust");
+
+        let a = make_replace_op(6, "world", "rust");
+        let b = make_replace_op(6, "world", "there");
+        let result = converge(content, &a, &b);
+        assert!(result == "hello rustthere" || result == "hello thererust");
+
+        let a = make_replace_op(2, "llo w", "_");
+        let b = make_replace_op(4, "o wor", "-");
+        let result = converge(content, &a, &b);
+        assert!(result == "he_-ld" || result == "he-_ld");
+    }
+
+    #[test]
+    fn test_multibyte_positions() {
+        let content = "héllo 🎉 world";
+        let char_count = content.chars().count();
+
+        for pos in 0..=char_count {
+            let insert = make_insert_op(pos, "X");
+            assert!(OTEngine::validate_operation(&insert, char_count).is_ok());
+            let result = OTEngine::apply_operation(content, &insert);
+            assert_eq!(result.chars().count(), char_count + 1);
+            assert_eq!(result.chars().nth(pos), Some('X'));
+        }
+
+        let insert = make_insert_op(2, "X");
+        assert_eq!(OTEngine::apply_operation(content, &insert), "héXllo 🎉 world");
+
+        let insert = make_insert_op(7, "!");
+        assert_eq!(OTEngine::apply_operation(content, &insert), "héllo 🎉! world");
+
+        let delete = make_delete_op(6, 2);
+        assert_eq!(OTEngine::apply_operation(content, &delete), "héllo world");
+
+        let replace = make_replace_op(0, "héllo", "hi");
+        assert_eq!(OTEngine::apply_operation(content, &replace), "hi 🎉 world");
+
+        // Concurrent inserts of multi-byte text shift by chars
+        let op1 = make_insert_op(7, "é");
+        let op2 = make_insert_op(6, "🎉🎉");
+        let result = OTEngine::transform_against(&op1, &op2);
+        if let OperationType::Insert { position, .. } = result.operation {
+            assert_eq!(position, 9);
+        } else {
+            panic!("Expected Insert operation");
+        }
+        converge(content, &op1, &op2);
+    }
+
+    #[test]
+    fn test_invert_round_trips() {
+        let content = "héllo 🎉 world";
+        let ops = [
+            make_insert_op(6, "big "),
+            make_insert_op(14, "!"),
+            make_delete_op(0, 6),
+            make_delete_op(6, 2),
+            make_replace_op(8, "world", "wörld"),
+            make_replace_op(0, "héllo 🎉", ""),
+        ];
+
+        for op in &ops {
+            let applied = OTEngine::apply_operation(content, op);
+            let inverse = OTEngine::invert(op, content);
+            assert_eq!(OTEngine::apply_operation(&applied, &inverse), content);
+        }
     }
 }
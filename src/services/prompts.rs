@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+/// Sent as the system message with every AI call, so all tasks answer in a shape the
+/// response parsers understand
+const DEFAULT_SYSTEM_PROMPT: &str = "You are a senior software engineer helping a developer improve their code. \
+Answer with one suggestion per line, most important first, with no preamble or closing remarks. \
+When you return code, return all of it in a single fenced code block.";
+
+const DEFAULT_OPTIMIZE_PROMPT: &str = "Optimize the following {language} code. \
+List your optimization suggestions.\n\n```{language}\n{code}\n```";
+
+const DEFAULT_REVIEW_PROMPT: &str = "Review the following {language} code and give feedback on code quality, \
+best practices and potential issues.\n\n```{language}\n{code}\n```";
+
+const DEFAULT_REFACTOR_PROMPT: &str = "Refactor the following {language} code to be more maintainable and efficient. \
+List what you changed, then give the complete refactored code.\n\n```{language}\n{code}\n```";
+
+/// The kinds of request `AIService` makes, each with its own user prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PromptTask {
+    Optimize,
+    Review,
+    Refactor,
+}
+
+impl PromptTask {
+    pub const ALL: [PromptTask; 3] = [PromptTask::Optimize, PromptTask::Review, PromptTask::Refactor];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PromptTask::Optimize => "optimize",
+            PromptTask::Review => "review",
+            PromptTask::Refactor => "refactor",
+        }
+    }
+
+    fn default_template(&self) -> &'static str {
+        match self {
+            PromptTask::Optimize => DEFAULT_OPTIMIZE_PROMPT,
+            PromptTask::Review => DEFAULT_REVIEW_PROMPT,
+            PromptTask::Refactor => DEFAULT_REFACTOR_PROMPT,
+        }
+    }
+}
+
+/// A user prompt with `{language}` and `{code}` placeholders
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptTemplate(String);
+
+impl PromptTemplate {
+    pub fn new(template: impl Into<String>) -> Self {
+        PromptTemplate(template.into())
+    }
+
+    /// Fill in the placeholders in one pass, so braces in the code itself are left alone.
+    /// Anything in braces that isn't a known placeholder is kept as written.
+    pub fn render(&self, language: &str, code: &str) -> String {
+        let mut rendered = String::with_capacity(self.0.len() + code.len());
+        let mut rest = self.0.as_str();
+
+        while let Some(start) = rest.find('{') {
+            rendered.push_str(&rest[..start]);
+            let candidate = &rest[start..];
+            let (value, len) = if candidate.starts_with("{language}") {
+                (language, "{language}".len())
+            } else if candidate.starts_with("{code}") {
+                (code, "{code}".len())
+            } else {
+                ("{", 1)
+            };
+            rendered.push_str(value);
+            rest = &candidate[len..];
+        }
+        rendered.push_str(rest);
+
+        rendered
+    }
+}
+
+/// The system prompt and one user template per task
+#[derive(Debug, Clone)]
+pub struct PromptTemplates {
+    system: String,
+    templates: HashMap<PromptTask, PromptTemplate>,
+}
+
+impl Default for PromptTemplates {
+    fn default() -> Self {
+        PromptTemplates {
+            system: DEFAULT_SYSTEM_PROMPT.to_string(),
+            templates: PromptTask::ALL
+                .into_iter()
+                .map(|task| (task, PromptTemplate::new(task.default_template())))
+                .collect(),
+        }
+    }
+}
+
+impl PromptTemplates {
+    /// The defaults, with any of `system.txt`, `optimize.txt`, `review.txt` and
+    /// `refactor.txt` found in `dir` taking their place
+    pub fn load(dir: &Path) -> std::io::Result<Self> {
+        let mut templates = Self::default();
+
+        if let Some(system) = read_override(dir, "system")? {
+            templates = templates.with_system_prompt(system);
+        }
+        for task in PromptTask::ALL {
+            if let Some(template) = read_override(dir, task.as_str())? {
+                templates = templates.with_template(task, PromptTemplate::new(template));
+            }
+        }
+
+        Ok(templates)
+    }
+
+    /// Templates from `AI_PROMPTS_DIR`, or the defaults when it's unset or empty. A
+    /// directory that can't be read is logged and ignored rather than stopping AI calls.
+    pub fn from_env() -> Self {
+        let Some(dir) = std::env::var("AI_PROMPTS_DIR").ok().filter(|dir| !dir.is_empty()) else {
+            return Self::default();
+        };
+
+        Self::load(Path::new(&dir)).unwrap_or_else(|e| {
+            tracing::error!("Failed to load prompt templates from {}: {:?}", dir, e);
+            Self::default()
+        })
+    }
+
+    pub fn with_system_prompt(mut self, system: impl Into<String>) -> Self {
+        self.system = system.into();
+        self
+    }
+
+    pub fn with_template(mut self, task: PromptTask, template: PromptTemplate) -> Self {
+        self.templates.insert(task, template);
+        self
+    }
+
+    pub fn system(&self) -> &str {
+        &self.system
+    }
+
+    pub fn render(&self, task: PromptTask, language: &str, code: &str) -> String {
+        match self.templates.get(&task) {
+            Some(template) => template.render(language, code),
+            None => PromptTemplate::new(task.default_template()).render(language, code),
+        }
+    }
+}
+
+/// The trimmed contents of `<dir>/<name>.txt`, if there is one
+fn read_override(dir: &Path, name: &str) -> std::io::Result<Option<String>> {
+    match std::fs::read_to_string(dir.join(format!("{}.txt", name))) {
+        Ok(contents) => Ok(Some(contents.trim().to_string())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// The templates every `AIService::new()` shares, loaded once
+pub fn prompt_templates() -> Arc<PromptTemplates> {
+    static TEMPLATES: OnceLock<Arc<PromptTemplates>> = OnceLock::new();
+    TEMPLATES
+        .get_or_init(|| Arc::new(PromptTemplates::from_env()))
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_substitutes_fields() {
+        let template = PromptTemplate::new("Review this {language}:\n{code}\n({language}, {unknown})");
+        assert_eq!(
+            template.render("rust", "fn f() { let s = \"{language}\"; }"),
+            "Review this rust:\nfn f() { let s = \"{language}\"; }\n(rust, {unknown})"
+        );
+
+        let rendered = PromptTemplates::default().render(PromptTask::Refactor, "python", "print(1)");
+        assert!(rendered.contains("python code"));
+        assert!(rendered.contains("```python\nprint(1)\n```"));
+        assert!(!rendered.contains("{code}"));
+    }
+
+    #[test]
+    fn test_overrides_replace_defaults() {
+        let defaults = PromptTemplates::default();
+        let templates = defaults
+            .clone()
+            .with_template(PromptTask::Review, PromptTemplate::new("Nitpick {code}"));
+        assert_eq!(templates.render(PromptTask::Review, "go", "x := 1"), "Nitpick x := 1");
+        assert_eq!(
+            templates.render(PromptTask::Optimize, "go", "x := 1"),
+            defaults.render(PromptTask::Optimize, "go", "x := 1")
+        );
+
+        let dir = std::env::temp_dir().join(format!("prompts-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("system.txt"), "Be brief.\n").unwrap();
+        std::fs::write(dir.join("optimize.txt"), "Speed up this {language}: {code}\n").unwrap();
+        let loaded = PromptTemplates::load(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded.system(), "Be brief.");
+        assert_eq!(loaded.render(PromptTask::Optimize, "c", "i++"), "Speed up this c: i++");
+        assert_eq!(
+            loaded.render(PromptTask::Review, "c", "i++"),
+            defaults.render(PromptTask::Review, "c", "i++")
+        );
+    }
+}
@@ -0,0 +1,470 @@
+use ::redis::aio::ConnectionManager;
+use ::redis::{AsyncCommands, Client, RedisResult};
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::models::collaboration::SessionMessage;
+use crate::services::cache::CacheBackend;
+use crate::services::collaboration::{CollaborationManager, SequencedOperation, SessionRelay};
+
+/// Pub/sub channel every instance publishes its session broadcasts on
+pub const SESSION_CHANNEL: &str = "collaboration:sessions";
+
+/// How long a session's operation log is kept after its last operation
+const SESSION_LOG_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long the subscriber waits before reconnecting after losing Redis
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
+/// Keys scanned per `SCAN` round trip when clearing a cache
+const SCAN_BATCH: usize = 500;
+
+/// Cache entries kept in Redis under `{prefix}:{key as JSON}`, shared by every instance
+/// pointed at the same server. Redis failures are logged and treated as misses so a
+/// cache outage only costs lookups.
+pub struct RedisBackend<K, V> {
+    connection: ConnectionManager,
+    prefix: String,
+    _entries: PhantomData<fn(K) -> V>,
+}
+
+impl<K, V> RedisBackend<K, V> {
+    /// `prefix` namespaces this cache's keys; it must not contain glob characters
+    pub async fn connect(client: &Client, prefix: &str) -> RedisResult<Self> {
+        Ok(RedisBackend {
+            connection: client.get_connection_manager().await?,
+            prefix: prefix.to_string(),
+            _entries: PhantomData,
+        })
+    }
+
+    fn redis_key(&self, key: &K) -> Option<String>
+    where
+        K: Serialize,
+    {
+        match serde_json::to_string(key) {
+            Ok(key) => Some(format!("{}:{}", self.prefix, key)),
+            Err(e) => {
+                tracing::warn!("Failed to encode {} cache key: {:?}", self.prefix, e);
+                None
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<K, V> CacheBackend<K, V> for RedisBackend<K, V>
+where
+    K: Serialize + Send + Sync,
+    V: Serialize + DeserializeOwned + Send + Sync,
+{
+    async fn get(&self, key: &K) -> Option<V> {
+        let key = self.redis_key(key)?;
+        let mut connection = self.connection.clone();
+        match connection.get::<_, Option<String>>(&key).await {
+            // An entry another release wrote that no longer parses is treated as a miss
+            Ok(value) => value.and_then(|value| serde_json::from_str(&value).ok()),
+            Err(e) => {
+                tracing::warn!("Failed to read {} from Redis: {:?}", key, e);
+                None
+            }
+        }
+    }
+
+    async fn insert(&self, key: K, value: V, ttl: Duration) {
+        let Some(key) = self.redis_key(&key) else {
+            return;
+        };
+        let value = match serde_json::to_string(&value) {
+            Ok(value) => value,
+            Err(e) => {
+                tracing::warn!("Failed to encode {} for Redis: {:?}", key, e);
+                return;
+            }
+        };
+
+        // Redis expiries are whole seconds and zero is rejected
+        let ttl = ttl.as_secs().max(1);
+        let mut connection = self.connection.clone();
+        if let Err(e) = connection.set_ex::<_, _, ()>(&key, value, ttl).await {
+            tracing::warn!("Failed to write {} to Redis: {:?}", key, e);
+        }
+    }
+
+    async fn remove(&self, key: &K) {
+        let Some(key) = self.redis_key(key) else {
+            return;
+        };
+        let mut connection = self.connection.clone();
+        if let Err(e) = connection.del::<_, ()>(&key).await {
+            tracing::warn!("Failed to delete {} from Redis: {:?}", key, e);
+        }
+    }
+
+    async fn clear(&self) {
+        let pattern = format!("{}:*", self.prefix);
+        let mut connection = self.connection.clone();
+        let mut cursor: u64 = 0;
+        loop {
+            let scanned: RedisResult<(u64, Vec<String>)> = ::redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(SCAN_BATCH)
+                .query_async(&mut connection)
+                .await;
+            let (next, keys) = match scanned {
+                Ok(scanned) => scanned,
+                Err(e) => {
+                    tracing::warn!("Failed to clear {} in Redis: {:?}", self.prefix, e);
+                    return;
+                }
+            };
+
+            if !keys.is_empty() {
+                if let Err(e) = connection.del::<_, ()>(keys).await {
+                    tracing::warn!("Failed to clear {} in Redis: {:?}", self.prefix, e);
+                    return;
+                }
+            }
+            if next == 0 {
+                return;
+            }
+            cursor = next;
+        }
+    }
+}
+
+/// What travels over `SESSION_CHANNEL`
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind")]
+enum RelayedMessage {
+    /// A cursor move or presence change
+    Message {
+        /// The instance that published it, which has already delivered it locally
+        origin: Uuid,
+        session_id: Uuid,
+        message: SessionMessage,
+    },
+    /// The session's operation log has grown
+    Operations { origin: Uuid, session_id: Uuid },
+}
+
+fn session_log_key(session_id: Uuid) -> String {
+    format!("collaboration:session:{}:operations", session_id)
+}
+
+/// Shares collaboration sessions between instances through Redis. Each session's
+/// operations go through one list, which every instance applies in order so their copies
+/// of the document stay identical; cursor and presence broadcasts go out over pub/sub.
+pub struct RedisSessionRelay {
+    origin: Uuid,
+    connection: ConnectionManager,
+    outgoing: mpsc::UnboundedSender<String>,
+}
+
+impl RedisSessionRelay {
+    /// Connect to Redis, start relaying `manager`'s broadcasts and deliver the other
+    /// instances' broadcasts to it
+    pub async fn start(client: Client, manager: &Arc<CollaborationManager>) -> RedisResult<Arc<Self>> {
+        let publisher = client.get_connection_manager().await?;
+        let subscriber = Self::subscribe(&client).await?;
+
+        let (outgoing, receiver) = mpsc::unbounded_channel();
+        let relay = Arc::new(RedisSessionRelay {
+            origin: Uuid::new_v4(),
+            connection: publisher.clone(),
+            outgoing,
+        });
+
+        // One publisher keeps each session's broadcasts in the order they were sent
+        tokio::spawn(Self::publish_loop(publisher, receiver));
+        tokio::spawn(Self::subscribe_loop(
+            client,
+            subscriber,
+            relay.origin,
+            Arc::downgrade(manager),
+        ));
+
+        manager.set_relay(relay.clone());
+        Ok(relay)
+    }
+
+    async fn subscribe(client: &Client) -> RedisResult<::redis::aio::PubSub> {
+        let mut pubsub = client.get_async_pubsub().await?;
+        pubsub.subscribe(SESSION_CHANNEL).await?;
+        Ok(pubsub)
+    }
+
+    async fn publish_loop(mut connection: ConnectionManager, mut receiver: mpsc::UnboundedReceiver<String>) {
+        while let Some(payload) = receiver.recv().await {
+            if let Err(e) = connection.publish::<_, _, ()>(SESSION_CHANNEL, payload).await {
+                tracing::warn!("Failed to relay collaboration broadcast: {:?}", e);
+            }
+        }
+    }
+
+    /// Runs until the manager is dropped, resubscribing whenever the connection is lost
+    async fn subscribe_loop(
+        client: Client,
+        mut pubsub: ::redis::aio::PubSub,
+        origin: Uuid,
+        manager: Weak<CollaborationManager>,
+    ) {
+        loop {
+            let mut messages = pubsub.into_on_message();
+            while let Some(message) = messages.next().await {
+                let Some(manager) = manager.upgrade() else {
+                    return;
+                };
+                let relayed = message
+                    .get_payload::<String>()
+                    .map_err(|e| e.to_string())
+                    .and_then(|payload| {
+                        serde_json::from_str::<RelayedMessage>(&payload).map_err(|e| e.to_string())
+                    });
+                match relayed {
+                    Ok(RelayedMessage::Message { origin: from, session_id, message }) if from != origin => {
+                        manager.deliver(session_id, message)
+                    }
+                    Ok(RelayedMessage::Operations { origin: from, session_id }) if from != origin => {
+                        tokio::spawn(async move {
+                            if let Err(e) = manager.catch_up(session_id).await {
+                                tracing::warn!("Failed to catch up on collaboration session {}: {}", session_id, e);
+                            }
+                        });
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!("Ignoring malformed collaboration broadcast: {}", e),
+                }
+            }
+            drop(messages);
+
+            tracing::warn!("Lost the collaboration broadcast subscription; reconnecting");
+            pubsub = loop {
+                if manager.strong_count() == 0 {
+                    return;
+                }
+                tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+                match Self::subscribe(&client).await {
+                    Ok(pubsub) => {
+                        // Apply any operations announced while we weren't listening
+                        if let Some(manager) = manager.upgrade() {
+                            manager.catch_up_all().await;
+                        }
+                        break pubsub;
+                    }
+                    Err(e) => tracing::warn!("Failed to resubscribe to collaboration broadcasts: {:?}", e),
+                }
+            };
+        }
+    }
+}
+
+#[async_trait]
+impl SessionRelay for RedisSessionRelay {
+    fn publish(&self, session_id: Uuid, message: &SessionMessage) {
+        let relayed = RelayedMessage::Message {
+            origin: self.origin,
+            session_id,
+            message: message.clone(),
+        };
+        match serde_json::to_string(&relayed) {
+            // Only fails once the publisher task has stopped, i.e. at shutdown
+            Ok(payload) => {
+                let _ = self.outgoing.send(payload);
+            }
+            Err(e) => tracing::warn!("Failed to encode collaboration broadcast: {:?}", e),
+        }
+    }
+
+    async fn append(&self, session_id: Uuid, entry: &SequencedOperation) -> Result<(), String> {
+        let entry = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+        let announcement = serde_json::to_string(&RelayedMessage::Operations {
+            origin: self.origin,
+            session_id,
+        })
+        .map_err(|e| e.to_string())?;
+
+        let key = session_log_key(session_id);
+        let mut connection = self.connection.clone();
+        ::redis::pipe()
+            .atomic()
+            .rpush(&key, entry)
+            .ignore()
+            .expire(&key, SESSION_LOG_TTL.as_secs() as i64)
+            .ignore()
+            .publish(SESSION_CHANNEL, announcement)
+            .ignore()
+            .query_async::<_, ()>(&mut connection)
+            .await
+            .map_err(|e| {
+                tracing::warn!("Failed to append to collaboration session {}: {:?}", session_id, e);
+                "Failed to share the operation with other instances".to_string()
+            })
+    }
+
+    async fn entries(&self, session_id: Uuid, from: usize) -> Result<Vec<SequencedOperation>, String> {
+        let mut connection = self.connection.clone();
+        let entries: Vec<String> = connection
+            .lrange(session_log_key(session_id), from as isize, -1)
+            .await
+            .map_err(|e| {
+                tracing::warn!("Failed to read collaboration session {}: {:?}", session_id, e);
+                "Failed to read the session's operations".to_string()
+            })?;
+
+        entries
+            .iter()
+            .map(|entry| serde_json::from_str(entry).map_err(|e| e.to_string()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::collaboration::PresenceEvent;
+    use crate::services::cache::Cache;
+
+    fn test_client() -> Client {
+        let url = std::env::var("REDIS_URL").expect("REDIS_URL must be set for Redis tests");
+        Client::open(url).unwrap()
+    }
+
+    async fn next_message(
+        receiver: &mut tokio::sync::broadcast::Receiver<SessionMessage>,
+    ) -> Option<SessionMessage> {
+        tokio::time::timeout(Duration::from_secs(5), receiver.recv()).await.ok()?.ok()
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a Redis server (REDIS_URL)"]
+    async fn test_broadcasts_reach_participants_on_other_instances() {
+        let client = test_client();
+        // Two managers stand in for two server instances behind a load balancer
+        let first = CollaborationManager::new();
+        let second = CollaborationManager::new();
+        RedisSessionRelay::start(client.clone(), &first).await.unwrap();
+        RedisSessionRelay::start(client, &second).await.unwrap();
+
+        let (session_id, file_id, user_id) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        first.create_session(session_id, file_id).unwrap();
+        second.create_session(session_id, file_id).unwrap();
+        let mut local = first.get_channel(session_id).unwrap().subscribe();
+        let mut remote = second.get_channel(session_id).unwrap().subscribe();
+
+        first
+            .broadcast(session_id, SessionMessage::Presence(PresenceEvent::Joined(user_id)))
+            .unwrap();
+
+        assert!(matches!(
+            next_message(&mut remote).await,
+            Some(SessionMessage::Presence(PresenceEvent::Joined(id))) if id == user_id
+        ));
+        assert!(matches!(
+            next_message(&mut local).await,
+            Some(SessionMessage::Presence(PresenceEvent::Joined(id))) if id == user_id
+        ));
+
+        // The publishing instance doesn't get its own broadcast back from Redis
+        second
+            .broadcast(session_id, SessionMessage::Presence(PresenceEvent::Left(user_id)))
+            .unwrap();
+        assert!(matches!(
+            next_message(&mut local).await,
+            Some(SessionMessage::Presence(PresenceEvent::Left(_)))
+        ));
+        assert!(local.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a Redis server (REDIS_URL)"]
+    async fn test_edits_through_either_instance_reach_both_documents() {
+        use crate::models::collaboration::{DocumentOperation, OperationType};
+
+        let client = test_client();
+        // Never queried: no snapshot is due this early in the session
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/unused")
+            .unwrap();
+        let first = CollaborationManager::new();
+        let second = CollaborationManager::new();
+        RedisSessionRelay::start(client.clone(), &first).await.unwrap();
+        RedisSessionRelay::start(client, &second).await.unwrap();
+
+        let (session_id, file_id, user_id) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        first.create_session(session_id, file_id).unwrap();
+        second.create_session(session_id, file_id).unwrap();
+        let mut remote = second.get_channel(session_id).unwrap().subscribe();
+        let insert = |version, content: &str| DocumentOperation {
+            id: Uuid::new_v4().to_string(),
+            version,
+            timestamp: chrono::Utc::now(),
+            user_id,
+            operation: OperationType::Insert { position: 0, content: content.to_string() },
+        };
+
+        // Concurrent edits of version 0 through different instances
+        let (a, b) = tokio::join!(
+            first.submit_operation(&pool, session_id, insert(0, "abc")),
+            second.submit_operation(&pool, session_id, insert(0, "xyz")),
+        );
+        a.unwrap();
+        b.unwrap();
+        first.submit_operation(&pool, session_id, insert(2, ">")).await.unwrap();
+
+        // The second instance applies the first's edits when Redis announces them
+        for _ in 0..3 {
+            assert!(matches!(next_message(&mut remote).await, Some(SessionMessage::Operation(_))));
+        }
+        first.catch_up(session_id).await.unwrap();
+
+        assert_eq!(first.get_version(session_id).unwrap(), 3);
+        assert_eq!(second.get_version(session_id).unwrap(), 3);
+        let content = first.session_content(session_id).unwrap();
+        assert_eq!(content.len(), 7);
+        assert!(content.starts_with('>'));
+        assert_eq!(second.session_content(session_id).unwrap(), content);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a Redis server (REDIS_URL)"]
+    async fn test_redis_backend_shares_entries_until_they_expire() {
+        let client = test_client();
+        let prefix = format!("test:{}", Uuid::new_v4());
+        let writer: Cache<(Uuid, Uuid), Vec<String>> = Cache::with_backend(
+            "test",
+            Duration::from_secs(1),
+            Arc::new(RedisBackend::connect(&client, &prefix).await.unwrap()),
+        );
+        let reader: Cache<(Uuid, Uuid), Vec<String>> = Cache::with_backend(
+            "test",
+            Duration::from_secs(1),
+            Arc::new(RedisBackend::connect(&client, &prefix).await.unwrap()),
+        );
+
+        let (kept, removed) = ((Uuid::new_v4(), Uuid::new_v4()), (Uuid::new_v4(), Uuid::new_v4()));
+        writer.insert(kept, vec!["read".to_string()]).await;
+        writer.insert(removed, vec!["write".to_string()]).await;
+        assert_eq!(reader.get(&kept).await, Some(vec!["read".to_string()]));
+
+        reader.remove(&removed).await;
+        assert_eq!(writer.get(&removed).await, None);
+
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert_eq!(reader.get(&kept).await, None);
+
+        writer.insert(kept, vec!["read".to_string()]).await;
+        reader.clear().await;
+        assert_eq!(writer.get(&kept).await, None);
+    }
+}
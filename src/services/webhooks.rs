@@ -0,0 +1,415 @@
+use chrono::Utc;
+use data_encoding::HEXLOWER;
+use ring::hmac;
+use sqlx::{PgPool, Row};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::models::WebhookEvent;
+use crate::services::events::{DomainEvent, EventBus};
+use crate::utils::validation::is_public_address;
+
+/// `sha256=<hex HMAC-SHA256 of the body, keyed by the webhook's secret>`
+pub const SIGNATURE_HEADER: &str = "X-CX7-Signature";
+pub const EVENT_HEADER: &str = "X-CX7-Event";
+/// The same for every attempt at one event, so receivers can drop duplicates
+pub const DELIVERY_HEADER: &str = "X-CX7-Delivery";
+
+/// Waits between attempts; a delivery is tried once more than there are delays
+pub const RETRY_DELAYS: [Duration; 3] = [
+    Duration::from_secs(1),
+    Duration::from_secs(10),
+    Duration::from_secs(60),
+];
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Why a delivery couldn't be sent
+enum DestinationError {
+    /// The receiver is on an internal address; retrying won't change that
+    Refused(String),
+    Unresolved(String),
+}
+
+/// Tests run their receivers on 127.0.0.1
+fn is_allowed_destination(ip: IpAddr) -> bool {
+    is_public_address(ip) || (cfg!(test) && ip.is_loopback())
+}
+
+fn allowed_addresses(resolved: impl IntoIterator<Item = SocketAddr>) -> Option<Vec<SocketAddr>> {
+    let allowed: Vec<SocketAddr> = resolved
+        .into_iter()
+        .filter(|addr| is_allowed_destination(addr.ip()))
+        .collect();
+    (!allowed.is_empty()).then_some(allowed)
+}
+
+/// A client for one request to `url`. Redirects, which could lead anywhere, are never
+/// followed. The receiver's name is resolved here and the client pinned to the public
+/// addresses it resolves to, so it can't be re-pointed inside our network between the
+/// check and the request.
+async fn client_for(url: &str) -> Result<reqwest::Client, DestinationError> {
+    let builder = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none());
+
+    let parsed = reqwest::Url::parse(url).map_err(|e| DestinationError::Refused(e.to_string()))?;
+    let host = parsed.host_str().unwrap_or_default();
+    let builder = match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(ip) if is_allowed_destination(ip) => builder,
+        Ok(ip) => return Err(DestinationError::Refused(format!("{} is an internal address", ip))),
+        Err(_) => {
+            let port = parsed.port_or_known_default().unwrap_or(80);
+            let resolved = tokio::net::lookup_host((host, port))
+                .await
+                .map_err(|e| DestinationError::Unresolved(format!("Failed to resolve {}: {}", host, e)))?;
+            let allowed = allowed_addresses(resolved).ok_or_else(|| {
+                DestinationError::Refused(format!("{} only resolves to internal addresses", host))
+            })?;
+            builder.resolve_to_addrs(host, &allowed)
+        }
+    };
+    builder.build().map_err(|e| DestinationError::Unresolved(e.to_string()))
+}
+
+/// Value of the signature header for `body`
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    format!("sha256={}", HEXLOWER.encode(hmac::sign(&key, body).as_ref()))
+}
+
+/// A webhook an event is going out to
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub webhook_id: Uuid,
+    pub url: String,
+    pub secret: String,
+}
+
+/// Send `event` to every active webhook on the project subscribed to it. Deliveries run in
+/// the background with retries, so a slow or failing receiver never holds up the caller;
+/// a failure to look the webhooks up is only logged for the same reason.
+pub async fn dispatch(pool: &PgPool, project_id: Uuid, event: WebhookEvent, data: serde_json::Value) {
+    let targets = match subscribed_targets(pool, project_id, event).await {
+        Ok(targets) => targets,
+        Err(e) => {
+            tracing::error!("Failed to look up webhooks for {} on project {}: {:?}", event.as_str(), project_id, e);
+            return;
+        }
+    };
+
+    for target in targets {
+        let delivery_id = Uuid::new_v4();
+        let payload = serde_json::json!({
+            "id": delivery_id,
+            "event": event.as_str(),
+            "project_id": project_id,
+            "occurred_at": Utc::now(),
+            "data": data,
+        });
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            deliver(&pool, &target, delivery_id, event, &payload, &RETRY_DELAYS).await;
+        });
+    }
+}
+
+/// Deliver the domain events webhooks can subscribe to
+pub fn subscribe(bus: &EventBus, pool: PgPool) {
+    bus.spawn_subscriber("webhooks", move |event| {
+        let pool = pool.clone();
+        async move {
+            if let (Some(project_id), Some((webhook_event, data))) = (event.project_id(), webhook_payload(&event)) {
+                dispatch(&pool, project_id, webhook_event, data).await;
+            }
+        }
+    });
+}
+
+/// The webhook event a domain event goes out as, and its `data`
+pub fn webhook_payload(event: &DomainEvent) -> Option<(WebhookEvent, serde_json::Value)> {
+    match event {
+        DomainEvent::ReviewStatusChanged { review_id, previous_status, status, changed_by, .. } => Some((
+            WebhookEvent::ReviewStatusChanged,
+            serde_json::json!({
+                "review_id": review_id,
+                "previous_status": previous_status,
+                "status": status,
+                "changed_by": changed_by,
+            }),
+        )),
+        DomainEvent::ReviewMerged { review_id, previous_status, merged_by, .. } => Some((
+            WebhookEvent::ReviewStatusChanged,
+            serde_json::json!({
+                "review_id": review_id,
+                "previous_status": previous_status,
+                "status": "merged",
+                "changed_by": merged_by,
+            }),
+        )),
+        DomainEvent::DeploymentCompleted { deployment } => {
+            Some((WebhookEvent::DeploymentCompleted, serde_json::json!(deployment)))
+        }
+        DomainEvent::AgentCompleted { task_id, agent_type, status, result, .. } => Some((
+            WebhookEvent::AgentTaskCompleted,
+            serde_json::json!({
+                "task_id": task_id,
+                "agent_type": agent_type,
+                "status": status,
+                "result": result,
+            }),
+        )),
+        DomainEvent::ProjectCreated { .. }
+        | DomainEvent::MemberAdded { .. }
+        | DomainEvent::TeamInvitationCreated { .. }
+        | DomainEvent::EmailVerificationRequested { .. }
+        | DomainEvent::PasswordResetRequested { .. } => None,
+    }
+}
+
+async fn subscribed_targets(pool: &PgPool, project_id: Uuid, event: WebhookEvent) -> sqlx::Result<Vec<Target>> {
+    let rows = sqlx::query(
+        "SELECT id, url, secret FROM webhooks WHERE project_id = $1 AND active AND $2 = ANY(event_types)"
+    )
+    .bind(project_id)
+    .bind(event.as_str())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| Target {
+            webhook_id: row.get("id"),
+            url: row.get("url"),
+            secret: row.get("secret"),
+        })
+        .collect())
+}
+
+/// POST the payload until the receiver answers 2xx or the retries run out, recording every
+/// attempt. Returns whether it was delivered.
+pub async fn deliver(
+    pool: &PgPool,
+    target: &Target,
+    delivery_id: Uuid,
+    event: WebhookEvent,
+    payload: &serde_json::Value,
+    retry_delays: &[Duration],
+) -> bool {
+    let body = payload.to_string();
+    let signature = sign(&target.secret, body.as_bytes());
+
+    for attempt in 1..=retry_delays.len() + 1 {
+        let response = match client_for(&target.url).await {
+            Ok(client) => client
+                .post(&target.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(SIGNATURE_HEADER, &signature)
+                .header(EVENT_HEADER, event.as_str())
+                .header(DELIVERY_HEADER, delivery_id.to_string())
+                .body(body.clone())
+                .send()
+                .await
+                .map_err(|e| e.to_string()),
+            Err(DestinationError::Unresolved(e)) => Err(e),
+            Err(DestinationError::Refused(reason)) => {
+                tracing::warn!("Webhook delivery {} to {} refused: {}", delivery_id, target.url, reason);
+                record_attempt(pool, target, delivery_id, event, payload, attempt as i32, None, Some(&reason), false).await;
+                return false;
+            }
+        };
+
+        let (status_code, error) = match &response {
+            Ok(response) => (Some(response.status().as_u16() as i32), None),
+            Err(e) => (None, Some(e.clone())),
+        };
+        let succeeded = matches!(&response, Ok(response) if response.status().is_success());
+
+        record_attempt(pool, target, delivery_id, event, payload, attempt as i32, status_code, error.as_deref(), succeeded).await;
+
+        if succeeded {
+            return true;
+        }
+        tracing::warn!(
+            "Webhook delivery {} to {} failed (attempt {}): {}",
+            delivery_id,
+            target.url,
+            attempt,
+            error.unwrap_or_else(|| format!("HTTP {}", status_code.unwrap_or_default()))
+        );
+
+        match retry_delays.get(attempt - 1) {
+            Some(delay) => tokio::time::sleep(*delay).await,
+            None => break,
+        }
+    }
+
+    false
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn record_attempt(
+    pool: &PgPool,
+    target: &Target,
+    delivery_id: Uuid,
+    event: WebhookEvent,
+    payload: &serde_json::Value,
+    attempt: i32,
+    status_code: Option<i32>,
+    error: Option<&str>,
+    succeeded: bool,
+) {
+    let recorded = sqlx::query(
+        "INSERT INTO webhook_deliveries (id, webhook_id, delivery_id, event_type, payload, attempt, status_code, error, succeeded) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"
+    )
+    .bind(Uuid::new_v4())
+    .bind(target.webhook_id)
+    .bind(delivery_id)
+    .bind(event.as_str())
+    .bind(payload)
+    .bind(attempt)
+    .bind(status_code)
+    .bind(error)
+    .bind(succeeded)
+    .execute(pool)
+    .await;
+    if let Err(e) = recorded {
+        tracing::error!("Failed to record webhook delivery {}: {:?}", delivery_id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature_is_hmac_sha256_of_the_body() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_ne!(sign("other", b"what do ya want for nothing?"), sign("Jefe", b"what do ya want for nothing?"));
+    }
+
+    #[test]
+    fn test_only_public_addresses_are_kept() {
+        let addr = |ip: &str| SocketAddr::new(ip.parse().unwrap(), 443);
+        assert_eq!(
+            allowed_addresses([addr("10.0.0.5"), addr("203.0.113.7"), addr("fe80::1")]),
+            Some(vec![addr("203.0.113.7")])
+        );
+        assert_eq!(allowed_addresses([addr("169.254.169.254"), addr("fd00::1")]), None);
+    }
+
+    #[tokio::test]
+    async fn test_internal_receivers_are_refused() {
+        for url in [
+            "http://192.168.1.20/hook",
+            "http://[::ffff:10.0.0.1]:8080/hook",
+            "http://100.64.0.1/hook",
+            "http://[fe80::1]/hook",
+        ] {
+            assert!(matches!(client_for(url).await, Err(DestinationError::Refused(_))), "{}", url);
+        }
+        assert!(client_for("https://203.0.113.7/hook").await.is_ok());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database (DATABASE_URL)"]
+    async fn test_failed_deliveries_are_retried_and_recorded() {
+        use axum::{http::StatusCode, routing::post, Router};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let db = crate::db::test_database().await;
+        let pool = db.pool().clone();
+
+        // Fails twice, then accepts
+        let calls = Arc::new(AtomicUsize::new(0));
+        let server_calls = calls.clone();
+        let app = Router::new()
+            .route(
+                "/hook",
+                post(move || {
+                    let calls = server_calls.clone();
+                    async move {
+                        match calls.fetch_add(1, Ordering::SeqCst) {
+                            0 | 1 => StatusCode::SERVICE_UNAVAILABLE,
+                            _ => StatusCode::OK,
+                        }
+                    }
+                }),
+            )
+            .route(
+                "/moved",
+                post(|| async { (StatusCode::TEMPORARY_REDIRECT, [("location", "http://169.254.169.254/")]) }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        let owner = crate::db::insert_test_user(&pool).await;
+        let project_id = crate::db::insert_test_project(&pool, owner, "flaky-receiver").await;
+        let target = Target {
+            webhook_id: Uuid::new_v4(),
+            url: format!("http://{}/hook", addr),
+            secret: "s3cret".to_string(),
+        };
+        sqlx::query("INSERT INTO webhooks (id, project_id, created_by, url, secret, event_types) VALUES ($1, $2, $3, $4, $5, $6)")
+            .bind(target.webhook_id)
+            .bind(project_id)
+            .bind(owner)
+            .bind(&target.url)
+            .bind(&target.secret)
+            .bind(vec!["deployment.completed"])
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let delivery_id = Uuid::new_v4();
+        let payload = serde_json::json!({ "event": "deployment.completed" });
+        let no_wait = [Duration::ZERO; 3];
+        assert!(deliver(&pool, &target, delivery_id, WebhookEvent::DeploymentCompleted, &payload, &no_wait).await);
+
+        let attempts: Vec<(i32, Option<i32>, bool)> = sqlx::query_as(
+            "SELECT attempt, status_code, succeeded FROM webhook_deliveries WHERE delivery_id = $1 ORDER BY attempt"
+        )
+        .bind(delivery_id)
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(attempts, vec![(1, Some(503), false), (2, Some(503), false), (3, Some(200), true)]);
+
+        // A receiver that never answers is given up on after the last retry
+        let unreachable = Target { url: "http://127.0.0.1:1/hook".to_string(), ..target.clone() };
+        assert!(!deliver(&pool, &unreachable, Uuid::new_v4(), WebhookEvent::DeploymentCompleted, &payload, &no_wait[..1]).await);
+
+        // Redirects are not followed
+        let redirecting = Target { url: format!("http://{}/moved", addr), ..target.clone() };
+        let delivery_id = Uuid::new_v4();
+        assert!(!deliver(&pool, &redirecting, delivery_id, WebhookEvent::DeploymentCompleted, &payload, &[]).await);
+        let status_code: Option<i32> = sqlx::query_scalar("SELECT status_code FROM webhook_deliveries WHERE delivery_id = $1")
+            .bind(delivery_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(status_code, Some(307));
+
+        // Internal addresses are refused outright, without retries
+        let internal = Target { url: "http://10.255.255.1/hook".to_string(), ..target };
+        let delivery_id = Uuid::new_v4();
+        assert!(!deliver(&pool, &internal, delivery_id, WebhookEvent::DeploymentCompleted, &payload, &no_wait).await);
+        let attempts: Vec<(Option<i32>, Option<String>)> = sqlx::query_as(
+            "SELECT status_code, error FROM webhook_deliveries WHERE delivery_id = $1"
+        )
+        .bind(delivery_id)
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(attempts, vec![(None, Some("10.255.255.1 is an internal address".to_string()))]);
+    }
+}
@@ -0,0 +1,196 @@
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::header::CONTENT_TYPE,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use metrics::{counter, gauge, histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+use crate::db::Database;
+use crate::services::{agent::AgentQueue, collaboration::CollaborationManager};
+
+/// Latency buckets in seconds; AI-backed routes routinely take several seconds
+const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+static RECORDER: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Install the global Prometheus recorder on first call; later calls return the same handle
+pub fn install_recorder() -> PrometheusHandle {
+    RECORDER
+        .get_or_init(|| {
+            PrometheusBuilder::new()
+                .set_buckets_for_metric(
+                    Matcher::Full("http_request_duration_seconds".to_string()),
+                    LATENCY_BUCKETS,
+                )
+                .expect("latency buckets are not empty")
+                .install_recorder()
+                .expect("no other metrics recorder is installed")
+        })
+        .clone()
+}
+
+/// Everything `/metrics` reports: the recorded counters and histograms, plus gauges
+/// sampled from the live queue, sessions and pool at scrape time
+pub struct Telemetry {
+    handle: PrometheusHandle,
+    db: Arc<Database>,
+    agent_queue: Arc<AgentQueue>,
+    collaboration_manager: Arc<CollaborationManager>,
+}
+
+impl Telemetry {
+    pub fn new(
+        handle: PrometheusHandle,
+        db: Arc<Database>,
+        agent_queue: Arc<AgentQueue>,
+        collaboration_manager: Arc<CollaborationManager>,
+    ) -> Self {
+        Self {
+            handle,
+            db,
+            agent_queue,
+            collaboration_manager,
+        }
+    }
+
+    pub fn render(&self) -> String {
+        gauge!("agent_queue_depth").set(self.agent_queue.depth() as f64);
+        gauge!("agent_tasks_running").set(self.agent_queue.running() as f64);
+        gauge!("collaboration_sessions_active").set(self.collaboration_manager.active_session_count() as f64);
+
+        let pool = self.db.pool();
+        let idle = pool.num_idle();
+        gauge!("db_pool_connections", "state" => "idle").set(idle as f64);
+        gauge!("db_pool_connections", "state" => "in_use").set((pool.size() as usize).saturating_sub(idle) as f64);
+        gauge!("db_pool_max_connections").set(f64::from(pool.options().get_max_connections()));
+
+        self.handle.run_upkeep();
+        self.handle.render()
+    }
+}
+
+/// `GET /metrics`, either merged into the API router or served on its own port
+pub fn metrics_router(telemetry: Arc<Telemetry>) -> Router {
+    Router::new()
+        .route("/metrics", get(render_metrics))
+        .with_state(telemetry)
+}
+
+async fn render_metrics(State(telemetry): State<Arc<Telemetry>>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        telemetry.render(),
+    )
+}
+
+/// Carry the matched route template out to `track_http_metrics`, which runs before
+/// routing and so never sees it on the request. Added with `route_layer`.
+pub async fn expose_matched_path(request: Request, next: Next) -> Response {
+    let matched_path = request.extensions().get::<MatchedPath>().cloned();
+    let mut response = next.run(request).await;
+    if let Some(matched_path) = matched_path {
+        response.extensions_mut().insert(matched_path);
+    }
+    response
+}
+
+/// Count every request and time it, labelled by method, route template and status.
+/// Requests rejected before routing (auth, rate limiting, unknown paths) are `unmatched`.
+pub async fn track_http_metrics(request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let started = Instant::now();
+    let response = next.run(request).await;
+
+    let route = response
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let labels = [
+        ("method", method),
+        ("route", route),
+        ("status", response.status().as_u16().to_string()),
+    ];
+    counter!("http_requests_total", &labels).increment(1);
+    histogram!("http_request_duration_seconds", &labels).record(started.elapsed().as_secs_f64());
+
+    response
+}
+
+/// Count agent tasks that ended as `completed`, `failed`, `cancelled` or `interrupted`
+pub fn count_agent_outcome(outcome: &'static str, tasks: u64) {
+    counter!("agent_tasks_total", "outcome" => outcome).increment(tasks);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::{to_bytes, Body}, middleware, http::StatusCode};
+    use sqlx::postgres::PgPoolOptions;
+    use tower::ServiceExt;
+
+    fn telemetry() -> Arc<Telemetry> {
+        // The pool is never used for queries, only sampled for its size
+        let pool = PgPoolOptions::new()
+            .max_connections(7)
+            .connect_lazy("postgres://localhost/unused")
+            .unwrap();
+        Arc::new(Telemetry::new(
+            install_recorder(),
+            Arc::new(Database::from_pool(pool)),
+            Arc::new(AgentQueue::new(1)),
+            CollaborationManager::new(),
+        ))
+    }
+
+    async fn scrape(app: &Router) -> String {
+        let request = Request::builder().uri("/metrics").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    /// Value of the sample whose name and labels start with `prefix`, or 0 if absent
+    fn sample(metrics: &str, prefix: &str) -> f64 {
+        metrics
+            .lines()
+            .find(|line| line.starts_with(prefix))
+            .and_then(|line| line.rsplit(' ').next())
+            .map(|value| value.parse().unwrap())
+            .unwrap_or(0.0)
+    }
+
+    #[tokio::test]
+    async fn test_scrape_shows_request_counter_incrementing() {
+        let api = Router::new()
+            .route("/telemetry-test/:id", axum::routing::get(|| async { "ok" }))
+            .route_layer(middleware::from_fn(expose_matched_path));
+        let app = Router::new()
+            .nest("/api", api)
+            .layer(middleware::from_fn(track_http_metrics))
+            .merge(metrics_router(telemetry()));
+        let counter = r#"http_requests_total{method="GET",route="/api/telemetry-test/:id",status="200"}"#;
+
+        let before = sample(&scrape(&app).await, counter);
+        for id in 0..3 {
+            let request = Request::builder()
+                .uri(format!("/api/telemetry-test/{}", id))
+                .body(Body::empty())
+                .unwrap();
+            assert_eq!(app.clone().oneshot(request).await.unwrap().status(), StatusCode::OK);
+        }
+
+        let metrics = scrape(&app).await;
+        assert_eq!(sample(&metrics, counter), before + 3.0);
+        assert!(metrics.contains("http_request_duration_seconds_bucket{"));
+        assert!(metrics.contains("agent_queue_depth 0"));
+        assert!(metrics.contains("db_pool_max_connections 7"));
+    }
+}
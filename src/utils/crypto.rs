@@ -1,4 +1,6 @@
 use bcrypt::{hash, verify};
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+use sha2::{Digest, Sha256};
 use crate::error::{AppError, AppResult};
 
 pub fn hash_password(password: &str) -> AppResult<String> {
@@ -15,6 +17,23 @@ pub fn verify_password(password: &str, hash: &str) -> AppResult<bool> {
     })
 }
 
+/// Random alphanumeric token for single-use links such as password resets
+pub fn generate_secure_token() -> String {
+    OsRng
+        .sample_iter(&Alphanumeric)
+        .take(48)
+        .map(char::from)
+        .collect()
+}
+
+/// Hex SHA-256 of a bearer token, so stored tokens can't be replayed from a database dump
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -26,4 +45,21 @@ mod tests {
         assert!(verify_password(password, &hash).unwrap());
         assert!(!verify_password("WrongPassword", &hash).unwrap());
     }
+
+    #[test]
+    fn test_secure_tokens_are_random() {
+        let token = generate_secure_token();
+        assert_eq!(token.len(), 48);
+        assert!(token.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_ne!(token, generate_secure_token());
+    }
+
+    #[test]
+    fn test_hash_token_is_stable_hex() {
+        let hash = hash_token("refresh-token");
+        assert_eq!(hash.len(), 64);
+        assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(hash, hash_token("refresh-token"));
+        assert_ne!(hash, hash_token("other-token"));
+    }
 }
@@ -0,0 +1,99 @@
+use async_trait::async_trait;
+use axum::{
+    extract::FromRequestParts,
+    http::{
+        header::{ETAG, IF_MATCH},
+        request::Parts,
+        HeaderValue,
+    },
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+
+use crate::error::AppError;
+
+/// Response carrying the resource's `updated_at` as its ETag, so the client can send it
+/// back in `If-Match` when it next writes
+#[derive(Debug)]
+pub struct ETagged<T>(pub DateTime<Utc>, pub T);
+
+impl<T: IntoResponse> IntoResponse for ETagged<T> {
+    fn into_response(self) -> Response {
+        let ETagged(updated_at, body) = self;
+        let mut response = body.into_response();
+        if let Ok(value) = HeaderValue::from_str(&etag(updated_at)) {
+            response.headers_mut().insert(ETAG, value);
+        }
+        response
+    }
+}
+
+/// Microseconds match Postgres' timestamp precision, so the tag compares exactly in SQL
+pub fn etag(updated_at: DateTime<Utc>) -> String {
+    format!("\"{}\"", updated_at.timestamp_micros())
+}
+
+/// The `updated_at` the client last saw, from `If-Match`. Without the header (or with
+/// `*`) the write is unconditional; a tag we never issued is a `ValidationError`.
+#[derive(Debug, Clone, Copy)]
+pub struct IfMatch(pub Option<DateTime<Utc>>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for IfMatch
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(value) = parts.headers.get(IF_MATCH) else {
+            return Ok(IfMatch(None));
+        };
+
+        let invalid = || AppError::ValidationError("If-Match must be an ETag from this API".to_string());
+        let value = value.to_str().map_err(|_| invalid())?.trim();
+        if value == "*" {
+            return Ok(IfMatch(None));
+        }
+
+        let tag = value.strip_prefix("W/").unwrap_or(value);
+        let micros = tag
+            .strip_prefix('"')
+            .and_then(|tag| tag.strip_suffix('"'))
+            .and_then(|micros| micros.parse().ok())
+            .ok_or_else(invalid)?;
+        let updated_at = DateTime::from_timestamp_micros(micros).ok_or_else(invalid)?;
+
+        Ok(IfMatch(Some(updated_at)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    async fn if_match(value: Option<&str>) -> Result<IfMatch, AppError> {
+        let mut request = Request::builder();
+        if let Some(value) = value {
+            request = request.header(IF_MATCH, value);
+        }
+        let (mut parts, _) = request.body(()).unwrap().into_parts();
+        IfMatch::from_request_parts(&mut parts, &()).await
+    }
+
+    #[tokio::test]
+    async fn test_if_match_round_trips_the_etag() {
+        let updated_at = DateTime::from_timestamp_micros(1_700_000_000_654_321).unwrap();
+        let tag = etag(updated_at);
+
+        assert_eq!(if_match(Some(&tag)).await.unwrap().0, Some(updated_at));
+        assert_eq!(if_match(Some(&format!("W/{}", tag))).await.unwrap().0, Some(updated_at));
+        assert_eq!(if_match(Some("*")).await.unwrap().0, None);
+        assert_eq!(if_match(None).await.unwrap().0, None);
+        assert!(matches!(if_match(Some("\"yesterday\"")).await, Err(AppError::ValidationError(_))));
+
+        let response = ETagged(updated_at, "ok").into_response();
+        assert_eq!(response.headers()[ETAG], tag.as_str());
+    }
+}
@@ -1,51 +1,178 @@
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
 use chrono::{Duration, Utc};
+use std::sync::OnceLock;
+use uuid::Uuid;
 
+use crate::config::Config;
 use crate::error::{AppError, AppResult};
 
+static JWT_CONFIG: OnceLock<JwtConfig> = OnceLock::new();
+
+/// Clock skew tolerated when checking `exp`
+const DEFAULT_LEEWAY_SECS: u64 = 30;
+
+/// How long access tokens last unless configured otherwise
+const DEFAULT_ACCESS_TOKEN_TTL_SECS: i64 = 3600;
+
+/// What a token may be used for. Refresh tokens are only accepted by the refresh endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenType {
+    Access,
+    Refresh,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
     pub exp: i64,
     pub iat: i64,
+    /// Unique token ID, recorded in `revoked_tokens` on logout
+    pub jti: String,
+    pub typ: TokenType,
 }
 
-pub fn generate_token(user_id: &str, expires_in: i64) -> AppResult<String> {
-    let secret = std::env::var("JWT_SECRET")
-        .map_err(|_| AppError::InternalServerError("JWT_SECRET not configured".to_string()))?;
-
-    let now = Utc::now();
-    let exp = (now + Duration::seconds(expires_in)).timestamp();
-
-    let claims = Claims {
-        sub: user_id.to_string(),
-        exp,
-        iat: now.timestamp(),
-    };
-
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_bytes()),
-    )
-    .map_err(|e| {
-        tracing::error!("Token encoding error: {:?}", e);
-        AppError::InternalServerError("Failed to generate token".to_string())
-    })
+/// Secret and validation rules shared by the token signer and every verifier
+#[derive(Clone)]
+pub struct JwtConfig {
+    secret: String,
+    algorithm: Algorithm,
+    leeway: u64,
+    access_token_ttl: i64,
+}
+
+impl JwtConfig {
+    /// Only HMAC algorithms are supported since tokens are signed with a shared secret
+    pub fn new(secret: &str, algorithm: &str, leeway: u64) -> AppResult<Self> {
+        if secret.trim().is_empty() {
+            return Err(AppError::InternalServerError("JWT_SECRET must not be empty".to_string()));
+        }
+
+        let algorithm = match algorithm {
+            "HS256" => Algorithm::HS256,
+            "HS384" => Algorithm::HS384,
+            "HS512" => Algorithm::HS512,
+            other => {
+                return Err(AppError::InternalServerError(format!(
+                    "Unsupported JWT algorithm: {}",
+                    other
+                )))
+            }
+        };
+
+        Ok(Self {
+            secret: secret.to_string(),
+            algorithm,
+            leeway,
+            access_token_ttl: DEFAULT_ACCESS_TOKEN_TTL_SECS,
+        })
+    }
+
+    /// Issue access tokens lasting `seconds` instead of the default hour
+    pub fn with_access_token_ttl(mut self, seconds: u64) -> AppResult<Self> {
+        self.access_token_ttl = i64::try_from(seconds)
+            .map_err(|_| AppError::InternalServerError("JWT_EXPIRY is out of range".to_string()))?;
+        Ok(self)
+    }
+
+    pub fn from_config(config: &Config) -> AppResult<Self> {
+        Self::new(&config.jwt_secret, &config.jwt_algorithm, config.jwt_leeway)?
+            .with_access_token_ttl(config.jwt_expiry)
+    }
+
+    fn from_env() -> AppResult<Self> {
+        let secret = std::env::var("JWT_SECRET")
+            .map_err(|_| AppError::InternalServerError("JWT_SECRET not configured".to_string()))?;
+        let algorithm = std::env::var("JWT_ALGORITHM").unwrap_or_else(|_| "HS256".to_string());
+        let leeway = std::env::var("JWT_LEEWAY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_LEEWAY_SECS);
+        let config = Self::new(&secret, &algorithm, leeway)?;
+
+        match std::env::var("JWT_EXPIRY").ok().and_then(|v| v.parse().ok()) {
+            Some(seconds) => config.with_access_token_ttl(seconds),
+            None => Ok(config),
+        }
+    }
+
+    /// Seconds an access token lasts, as written into its `exp` claim
+    pub fn access_token_ttl(&self) -> i64 {
+        self.access_token_ttl
+    }
+
+    fn validation(&self) -> Validation {
+        let mut validation = Validation::new(self.algorithm);
+        validation.leeway = self.leeway;
+        validation.validate_exp = true;
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        validation
+    }
+
+    pub fn encode(&self, user_id: &str, expires_in: i64, typ: TokenType) -> AppResult<String> {
+        let now = Utc::now();
+        let exp = (now + Duration::seconds(expires_in)).timestamp();
+
+        let claims = Claims {
+            sub: user_id.to_string(),
+            exp,
+            iat: now.timestamp(),
+            jti: Uuid::new_v4().to_string(),
+            typ,
+        };
+
+        encode(
+            &Header::new(self.algorithm),
+            &claims,
+            &EncodingKey::from_secret(self.secret.as_bytes()),
+        )
+        .map_err(|e| {
+            tracing::error!("Token encoding error: {:?}", e);
+            AppError::InternalServerError("Failed to generate token".to_string())
+        })
+    }
+
+    pub fn decode(&self, token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+        decode::<Claims>(
+            token,
+            &DecodingKey::from_secret(self.secret.as_bytes()),
+            &self.validation(),
+        )
+        .map(|data| data.claims)
+    }
+}
+
+/// Install the process-wide JWT settings; call once at startup before serving requests
+pub fn init(config: JwtConfig) {
+    if JWT_CONFIG.set(config).is_err() {
+        tracing::warn!("JWT configuration was already initialized");
+    }
+}
+
+/// The installed settings, falling back to the environment when `init` was never called (tests)
+pub fn config() -> AppResult<&'static JwtConfig> {
+    if let Some(config) = JWT_CONFIG.get() {
+        return Ok(config);
+    }
+
+    let _ = JWT_CONFIG.set(JwtConfig::from_env()?);
+    Ok(JWT_CONFIG.get().expect("JWT configuration was just set"))
+}
+
+/// An access token with the configured lifetime, and that lifetime in seconds
+pub fn generate_access_token(user_id: &str) -> AppResult<(String, i64)> {
+    let config = config()?;
+    let expires_in = config.access_token_ttl();
+    Ok((config.encode(user_id, expires_in, TokenType::Access)?, expires_in))
+}
+
+pub fn generate_refresh_token(user_id: &str, expires_in: i64) -> AppResult<String> {
+    config()?.encode(user_id, expires_in, TokenType::Refresh)
 }
 
 pub fn verify_token(token: &str) -> AppResult<Claims> {
-    let secret = std::env::var("JWT_SECRET")
-        .map_err(|_| AppError::InternalServerError("JWT_SECRET not configured".to_string()))?;
-
-    decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &Validation::default(),
-    )
-    .map(|data| data.claims)
-    .map_err(|e| {
+    config()?.decode(token).map_err(|e| {
         tracing::error!("Token verification error: {:?}", e);
         AppError::AuthenticationError("Invalid token".to_string())
     })
@@ -59,8 +186,62 @@ mod tests {
     fn test_token_generation_and_verification() {
         std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
         let user_id = "test_user";
-        let token = generate_token(user_id, 3600).unwrap();
+        let (token, expires_in) = generate_access_token(user_id).unwrap();
         let claims = verify_token(&token).unwrap();
         assert_eq!(claims.sub, user_id);
+        assert_eq!(claims.typ, TokenType::Access);
+        // The lifetime reported alongside the token is the one it carries
+        assert_eq!(claims.exp - claims.iat, expires_in);
+
+        let refresh = verify_token(&generate_refresh_token(user_id, 3600).unwrap()).unwrap();
+        assert_eq!(refresh.typ, TokenType::Refresh);
+    }
+
+    #[test]
+    fn test_tokens_have_unique_ids() {
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        let first = verify_token(&generate_access_token("test_user").unwrap().0).unwrap();
+        let second = verify_token(&generate_access_token("test_user").unwrap().0).unwrap();
+        assert_ne!(first.jti, second.jti);
+    }
+
+    #[test]
+    fn test_token_from_other_secret_is_rejected() {
+        let signer = JwtConfig::new("secret-a", "HS256", 0).unwrap();
+        let verifier = JwtConfig::new("secret-b", "HS256", 0).unwrap();
+
+        let token = signer.encode("test_user", 3600, TokenType::Access).unwrap();
+        assert_eq!(signer.decode(&token).unwrap().sub, "test_user");
+        assert!(matches!(
+            verifier.decode(&token).unwrap_err().kind(),
+            jsonwebtoken::errors::ErrorKind::InvalidSignature
+        ));
+    }
+
+    #[test]
+    fn test_algorithm_mismatch_is_rejected() {
+        let signer = JwtConfig::new("shared-secret", "HS512", 0).unwrap();
+        let verifier = JwtConfig::new("shared-secret", "HS256", 0).unwrap();
+
+        let token = signer.encode("test_user", 3600, TokenType::Access).unwrap();
+        assert!(verifier.decode(&token).is_err());
+    }
+
+    #[test]
+    fn test_expired_token_is_rejected() {
+        let config = JwtConfig::new("secret-a", "HS256", 0).unwrap();
+        let token = config.encode("test_user", -60, TokenType::Access).unwrap();
+        assert!(matches!(
+            config.decode(&token).unwrap_err().kind(),
+            jsonwebtoken::errors::ErrorKind::ExpiredSignature
+        ));
+    }
+
+    #[test]
+    fn test_empty_secret_and_unknown_algorithm_fail() {
+        assert!(JwtConfig::new("", "HS256", 0).is_err());
+        assert!(JwtConfig::new("   ", "HS256", 0).is_err());
+        assert!(JwtConfig::new("secret", "RS256", 0).is_err());
+        assert!(JwtConfig::new("secret", "HS256", 0).unwrap().with_access_token_ttl(u64::MAX).is_err());
     }
 }
@@ -1,3 +1,6 @@
 pub mod jwt;
 pub mod validation;
 pub mod crypto;
+pub mod pagination;
+pub mod etag;
+pub mod totp;
@@ -0,0 +1,110 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub const DEFAULT_PAGE_SIZE: i64 = 100;
+pub const MAX_PAGE_SIZE: i64 = 1000;
+
+/// Keyset position in a listing ordered by `(created_at, id)` descending: the next
+/// page starts strictly after this row. Clients only ever see it as an opaque string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    pub created_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    pub fn new(created_at: DateTime<Utc>, id: Uuid) -> Self {
+        Self { created_at, id }
+    }
+
+    /// Microseconds match Postgres' timestamp precision, so the position round-trips exactly
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}|{}", self.created_at.timestamp_micros(), self.id))
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let bytes = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+        let raw = String::from_utf8(bytes).ok()?;
+        let (micros, id) = raw.split_once('|')?;
+        Some(Self {
+            created_at: DateTime::from_timestamp_micros(micros.parse().ok()?)?,
+            id: id.parse().ok()?,
+        })
+    }
+}
+
+/// `?limit=&cursor=` for listings that don't take other filters
+#[derive(Debug, Default, Deserialize)]
+pub struct PageQuery {
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+}
+
+/// Requested page size, defaulting to 100 and capped at 1000
+pub fn page_size(limit: Option<i64>) -> i64 {
+    limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+}
+
+/// One page of a listing; `next_cursor` is absent on the last page
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Build a page from a query that fetched `page_size + 1` rows: the extra row
+    /// is dropped and only tells us there's another page after this one
+    pub fn from_rows(mut rows: Vec<T>, page_size: i64, cursor_of: impl Fn(&T) -> Cursor) -> Self {
+        let page_size = page_size.max(0) as usize;
+        let next_cursor = if rows.len() > page_size {
+            rows.truncate(page_size);
+            rows.last().map(|row| cursor_of(row).encode())
+        } else {
+            None
+        };
+        Self {
+            items: rows,
+            next_cursor,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_round_trips() {
+        let cursor = Cursor::new(
+            DateTime::from_timestamp_micros(1_700_000_000_123_456).unwrap(),
+            Uuid::new_v4(),
+        );
+        let encoded = cursor.encode();
+        assert!(encoded.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_eq!(Cursor::decode(&encoded), Some(cursor));
+
+        assert_eq!(Cursor::decode("not a cursor"), None);
+        assert_eq!(Cursor::decode(&URL_SAFE_NO_PAD.encode("12|not-a-uuid")), None);
+    }
+
+    #[test]
+    fn test_page_only_has_a_cursor_when_rows_remain() {
+        let now = Utc::now();
+        let ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+
+        let page = Page::from_rows(ids.clone(), 2, |id| Cursor::new(now, *id));
+        assert_eq!(page.items, ids[..2]);
+        assert_eq!(page.next_cursor, Some(Cursor::new(now, ids[1]).encode()));
+
+        let last = Page::from_rows(ids[2..].to_vec(), 2, |id| Cursor::new(now, *id));
+        assert_eq!(last.items.len(), 1);
+        assert_eq!(last.next_cursor, None);
+
+        assert_eq!(page_size(None), DEFAULT_PAGE_SIZE);
+        assert_eq!(page_size(Some(0)), 1);
+        assert_eq!(page_size(Some(5000)), MAX_PAGE_SIZE);
+    }
+}
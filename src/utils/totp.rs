@@ -0,0 +1,192 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use data_encoding::BASE32_NOPAD;
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng, RngCore};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    hmac,
+};
+use sha2::{Digest, Sha256};
+
+use crate::error::{AppError, AppResult};
+
+const ISSUER: &str = "CompileX7";
+const SECRET_LEN: usize = 20;
+const STEP_SECS: i64 = 30;
+const DIGITS: u32 = 6;
+/// Codes from one step either side of now are accepted, for clock skew
+const SKEW_STEPS: i64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// A new random shared secret (160 bits, as RFC 4226 recommends)
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; SECRET_LEN];
+    OsRng.fill_bytes(&mut secret);
+    secret
+}
+
+/// The secret as authenticator apps expect it to be typed in
+pub fn encode_secret(secret: &[u8]) -> String {
+    BASE32_NOPAD.encode(secret)
+}
+
+/// `otpauth://` URI for authenticator apps to scan as a QR code
+pub fn otpauth_uri(secret: &[u8], account: &str) -> AppResult<String> {
+    let secret = encode_secret(secret);
+    reqwest::Url::parse_with_params(
+        &format!("otpauth://totp/{}:{}", ISSUER, account),
+        &[
+            ("secret", secret.as_str()),
+            ("issuer", ISSUER),
+            ("algorithm", "SHA1"),
+            ("digits", "6"),
+            ("period", "30"),
+        ],
+    )
+    .map(String::from)
+    .map_err(|_| AppError::InternalServerError("Failed to build otpauth URI".to_string()))
+}
+
+/// The 30-second time step `now` falls in
+pub fn time_step(now: DateTime<Utc>) -> i64 {
+    now.timestamp().div_euclid(STEP_SECS)
+}
+
+/// The 6-digit code for a time step (RFC 6238 over HMAC-SHA1)
+pub fn code_at(secret: &[u8], step: i64) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let digest = hmac::sign(&key, &(step as u64).to_be_bytes());
+    let digest = digest.as_ref();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([digest[offset], digest[offset + 1], digest[offset + 2], digest[offset + 3]])
+        & 0x7fff_ffff;
+    format!("{:0width$}", binary % 10u32.pow(DIGITS), width = DIGITS as usize)
+}
+
+/// The step whose code matches, if it's within the skew window around `now` and later
+/// than `last_used_step`. Callers record the step so a code can't be replayed.
+pub fn verify_code(secret: &[u8], code: &str, now: DateTime<Utc>, last_used_step: Option<i64>) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let current = time_step(now);
+    (current - SKEW_STEPS..=current + SKEW_STEPS)
+        .filter(|step| last_used_step.is_none_or(|last| *step > last))
+        .find(|step| code_at(secret, *step) == code)
+}
+
+/// Fresh single-use recovery codes, formatted `xxxxx-xxxxx`
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let raw: String = OsRng
+                .sample_iter(&Alphanumeric)
+                .take(10)
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .collect();
+            format!("{}-{}", &raw[..5], &raw[5..])
+        })
+        .collect()
+}
+
+/// Recovery codes are compared ignoring case, spaces and dashes
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// AES-256-GCM key from `TOTP_ENCRYPTION_KEY`; secrets are never stored in the clear
+fn cipher_key() -> AppResult<LessSafeKey> {
+    let key = std::env::var("TOTP_ENCRYPTION_KEY")
+        .ok()
+        .filter(|key| !key.trim().is_empty())
+        .ok_or_else(|| {
+            tracing::error!("TOTP_ENCRYPTION_KEY is not set");
+            AppError::InternalServerError("Two-factor authentication is not configured".to_string())
+        })?;
+    let key = UnboundKey::new(&AES_256_GCM, &Sha256::digest(key.as_bytes()))
+        .map_err(|_| AppError::InternalServerError("Invalid TOTP encryption key".to_string()))?;
+    Ok(LessSafeKey::new(key))
+}
+
+/// Encrypt a secret for storage as base64 of nonce followed by ciphertext
+pub fn encrypt_secret(secret: &[u8]) -> AppResult<String> {
+    let key = cipher_key()?;
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+
+    let mut sealed = secret.to_vec();
+    key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut sealed)
+        .map_err(|_| AppError::InternalServerError("Failed to encrypt TOTP secret".to_string()))?;
+
+    let mut stored = nonce.to_vec();
+    stored.extend(sealed);
+    Ok(STANDARD.encode(stored))
+}
+
+pub fn decrypt_secret(stored: &str) -> AppResult<Vec<u8>> {
+    let invalid = || AppError::InternalServerError("Failed to decrypt TOTP secret".to_string());
+    let key = cipher_key()?;
+    let bytes = STANDARD.decode(stored).map_err(|_| invalid())?;
+    if bytes.len() < NONCE_LEN {
+        return Err(invalid());
+    }
+
+    let (nonce, sealed) = bytes.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| invalid())?;
+    let mut sealed = sealed.to_vec();
+    let secret = key.open_in_place(nonce, Aad::empty(), &mut sealed).map_err(|_| invalid())?;
+    Ok(secret.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc_6238_vectors() {
+        // SHA1 test vectors from RFC 6238 Appendix B, truncated to six digits
+        let secret = b"12345678901234567890";
+        for (unix_time, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+        ] {
+            assert_eq!(code_at(secret, unix_time / STEP_SECS), code);
+        }
+    }
+
+    #[test]
+    fn test_codes_within_skew_are_accepted_once() {
+        let secret = generate_secret();
+        let now = Utc::now();
+        let step = time_step(now);
+
+        for offset in [-1, 0, 1] {
+            assert_eq!(verify_code(&secret, &code_at(&secret, step + offset), now, None), Some(step + offset));
+        }
+        assert_eq!(verify_code(&secret, &code_at(&secret, step - 2), now, None), None);
+        assert_eq!(verify_code(&secret, &code_at(&secret, step), now, Some(step)), None);
+        assert_eq!(verify_code(&secret, "12345", now, None), None);
+    }
+
+    #[test]
+    fn test_secret_encryption_round_trips() {
+        std::env::set_var("TOTP_ENCRYPTION_KEY", "test_totp_key");
+        let secret = generate_secret();
+
+        let stored = encrypt_secret(&secret).unwrap();
+        assert_ne!(encrypt_secret(&secret).unwrap(), stored);
+        assert_eq!(decrypt_secret(&stored).unwrap(), secret);
+
+        let uri = otpauth_uri(&secret, "dev@example.com").unwrap();
+        assert!(uri.starts_with("otpauth://totp/CompileX7:dev@example.com?"));
+        assert!(uri.contains(&format!("secret={}", encode_secret(&secret))));
+    }
+}
@@ -1,7 +1,123 @@
+use axum::{
+    async_trait,
+    extract::{FromRequest, Request},
+    Json,
+};
+use serde::de::DeserializeOwned;
+use std::net::IpAddr;
+
 use crate::error::{AppError, AppResult};
+use crate::models::collaboration::{
+    AddProjectMemberRequest, AddReviewCommentRequest, AddTeamMemberRequest, ApprovalStatus,
+    BulkAddTeamMembersRequest, CreateCodeReviewRequest, CreateTeamInvitationRequest, CreateCollaborativeSessionRequest, CreateTeamRequest, ReviewStatus,
+    SubmitApprovalRequest, TeamRole, UpdateCodeReviewRequest, UpdateProjectMemberRequest,
+    UpdateReviewCommentRequest, UpdateReviewSettingsRequest, UpdateTeamMemberRequest,
+    UpdateTeamRequest,
+};
+use crate::models::inheritance::{
+    CreatePermissionRuleRequest, CreateProjectHierarchyRequest, CreateTeamHierarchyRequest,
+    Permission, UpdatePermissionRuleRequest,
+};
+use crate::models::{
+    AgentRequest, CreateFileRequest, CreateProjectRequest, DeployRequest, ForgotPasswordRequest,
+    GenerateReportRequest,
+    LoginRequest, OptimizeCodeRequest, RefactorCodeRequest, RegisterRequest, ResetPasswordRequest,
+    ReviewCodeRequest, TokenRefreshRequest, TransferProjectRequest, TwoFactorChallengeRequest,
+    TwoFactorVerifyRequest, UpdateFileRequest, UpdateProjectRequest, CreateWebhookRequest, UpdateWebhookRequest, WebhookEvent,
+};
+
+/// Largest file accepted by the file and deploy endpoints
+pub const MAX_FILE_SIZE: usize = 1024 * 1024;
+
+/// Largest snippet accepted by the code analysis and agent endpoints
+pub const MAX_CODE_LENGTH: usize = 100 * 1024;
+
+const MAX_NAME_LENGTH: usize = 255;
+const MAX_LANGUAGE_LENGTH: usize = 50;
+/// Analysis requests only use the path to guess a language, so any path short enough will do
+const MAX_FILE_PATH_LENGTH: usize = 1024;
+const MAX_TASK_DESCRIPTION_LENGTH: usize = 10_000;
+
+/// Most members one bulk import may add or update
+pub const MAX_BULK_MEMBERS: usize = 500;
+
+/// Field rules for a request body, checked by `ValidatedJson` before the handler runs
+pub trait Validate {
+    fn validate(&self) -> AppResult<()>;
+}
+
+/// Failures collected across every field, so the client hears about all of them at once
+#[derive(Debug, Default)]
+pub struct ValidationErrors(Vec<String>);
+
+impl ValidationErrors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the outcome of a field check
+    pub fn check(&mut self, field: &str, result: AppResult<()>) {
+        if let Err(err) = result {
+            let message = match err {
+                AppError::ValidationError(message) => message,
+                other => format!("{:?}", other),
+            };
+            self.0.push(format!("{}: {}", field, message));
+        }
+    }
+
+    /// Record `message` against `field` unless `ok` holds
+    pub fn require(&mut self, field: &str, ok: bool, message: &str) {
+        if !ok {
+            self.0.push(format!("{}: {}", field, message));
+        }
+    }
+
+    pub fn into_result(self) -> AppResult<()> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::ValidationError(self.0.join("; ")))
+        }
+    }
+}
+
+/// `Json` extractor that also runs the body's `Validate` rules. Malformed JSON and failed
+/// rules both come back as a `ValidationError`.
+#[derive(Debug)]
+pub struct ValidatedJson<T>(pub T);
+
+#[async_trait]
+impl<S, T> FromRequest<S> for ValidatedJson<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state)
+            .await
+            .map_err(|rejection| AppError::ValidationError(rejection.body_text()))?;
+        value.validate()?;
+        Ok(ValidatedJson(value))
+    }
+}
 
 pub fn validate_email(email: &str) -> AppResult<()> {
-    if email.is_empty() || !email.contains('@') {
+    let valid = match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && email.len() <= MAX_NAME_LENGTH
+                && !email.chars().any(char::is_whitespace)
+        }
+        None => false,
+    };
+    if !valid {
         return Err(AppError::ValidationError("Invalid email format".to_string()));
     }
     Ok(())
@@ -27,7 +143,7 @@ pub fn validate_password(password: &str) -> AppResult<()> {
 }
 
 pub fn validate_project_name(name: &str) -> AppResult<()> {
-    if name.is_empty() || name.len() > 255 {
+    if name.trim().is_empty() || name.len() > MAX_NAME_LENGTH {
         return Err(AppError::ValidationError(
             "Project name must be between 1 and 255 characters".to_string(),
         ));
@@ -35,15 +151,606 @@ pub fn validate_project_name(name: &str) -> AppResult<()> {
     Ok(())
 }
 
+/// Non-blank text of at most `max` bytes
+pub fn validate_text(value: &str, max: usize) -> AppResult<()> {
+    if value.trim().is_empty() {
+        return Err(AppError::ValidationError("Must not be empty".to_string()));
+    }
+    validate_max_length(value, max)
+}
+
+pub fn validate_max_length(value: &str, max: usize) -> AppResult<()> {
+    if value.len() > max {
+        return Err(AppError::ValidationError(format!(
+            "Must be at most {} bytes",
+            max
+        )));
+    }
+    Ok(())
+}
+
+/// Relative path inside a project; rejects anything that could escape it
+pub fn validate_file_path(path: &str) -> AppResult<()> {
+    let valid = !path.is_empty()
+        && path.len() <= 1024
+        && !path.starts_with('/')
+        && !path.contains('\\')
+        && path.split('/').all(|part| !part.is_empty() && part != "." && part != "..");
+    if !valid {
+        return Err(AppError::ValidationError(format!("Invalid file path: {}", path)));
+    }
+    Ok(())
+}
+
+/// Text file content within `MAX_FILE_SIZE`
+pub fn validate_file_content(content: &str) -> AppResult<()> {
+    if content.len() > MAX_FILE_SIZE {
+        return Err(AppError::ValidationError(format!(
+            "File is larger than the {} byte limit",
+            MAX_FILE_SIZE
+        )));
+    }
+    if content.contains('\0') {
+        return Err(AppError::ValidationError("Binary files are not supported".to_string()));
+    }
+    Ok(())
+}
+
+pub fn validate_code(code: &str) -> AppResult<()> {
+    if code.trim().is_empty() {
+        return Err(AppError::ValidationError("Code must not be empty".to_string()));
+    }
+    if code.len() > MAX_CODE_LENGTH {
+        return Err(AppError::ValidationError(format!(
+            "Code is larger than the {} byte limit",
+            MAX_CODE_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+pub fn validate_team_role(role: &str) -> AppResult<()> {
+    let known = [TeamRole::Owner, TeamRole::Admin, TeamRole::Member, TeamRole::Viewer]
+        .iter()
+        .any(|known| known.as_str() == role);
+    if !known {
+        return Err(AppError::ValidationError(format!("Unknown team role: {}", role)));
+    }
+    Ok(())
+}
+
+/// Every name must be defined by `Permission::all()`
+pub fn validate_permissions(permissions: &[String]) -> AppResult<()> {
+    let unknown: Vec<&str> = permissions
+        .iter()
+        .filter(|name| !Permission::is_known(name))
+        .map(String::as_str)
+        .collect();
+    if !unknown.is_empty() {
+        return Err(AppError::ValidationError(format!(
+            "Unknown permissions: {}",
+            unknown.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+fn check_optional(
+    errors: &mut ValidationErrors,
+    field: &str,
+    value: Option<&String>,
+    rule: impl FnOnce(&str) -> AppResult<()>,
+) {
+    if let Some(value) = value {
+        errors.check(field, rule(value));
+    }
+}
+
+// Auth
+
+impl Validate for RegisterRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.check("email", validate_email(&self.email));
+        errors.check("password", validate_password(&self.password));
+        check_optional(&mut errors, "first_name", self.first_name.as_ref(), |v| validate_max_length(v, 100));
+        check_optional(&mut errors, "last_name", self.last_name.as_ref(), |v| validate_max_length(v, 100));
+        errors.into_result()
+    }
+}
+
+impl Validate for LoginRequest {
+    fn validate(&self) -> AppResult<()> {
+        // Strength rules only apply when a password is chosen
+        let mut errors = ValidationErrors::new();
+        errors.require("email", !self.email.is_empty(), "Must not be empty");
+        errors.require("password", !self.password.is_empty(), "Must not be empty");
+        errors.into_result()
+    }
+}
+
+impl Validate for TokenRefreshRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.require("refresh_token", !self.refresh_token.is_empty(), "Must not be empty");
+        errors.into_result()
+    }
+}
+
+impl Validate for ForgotPasswordRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.check("email", validate_email(&self.email));
+        errors.into_result()
+    }
+}
+
+impl Validate for ResetPasswordRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.require("token", !self.token.is_empty(), "Must not be empty");
+        errors.check("new_password", validate_password(&self.new_password));
+        errors.into_result()
+    }
+}
+
+impl Validate for TwoFactorVerifyRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.require("code", !self.code.trim().is_empty(), "Must not be empty");
+        errors.into_result()
+    }
+}
+
+impl Validate for TwoFactorChallengeRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.require("challenge_token", !self.challenge_token.is_empty(), "Must not be empty");
+        errors.require("code", !self.code.trim().is_empty(), "Must not be empty");
+        errors.into_result()
+    }
+}
+
+// Projects and files
+
+impl Validate for CreateProjectRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.check("name", validate_project_name(&self.name));
+        check_optional(&mut errors, "language", self.language.as_ref(), |v| validate_max_length(v, MAX_LANGUAGE_LENGTH));
+        check_optional(&mut errors, "repository_url", self.repository_url.as_ref(), |v| validate_max_length(v, MAX_NAME_LENGTH));
+        errors.into_result()
+    }
+}
+
+impl Validate for UpdateProjectRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        check_optional(&mut errors, "name", self.name.as_ref(), validate_project_name);
+        check_optional(&mut errors, "language", self.language.as_ref(), |v| validate_max_length(v, MAX_LANGUAGE_LENGTH));
+        errors.into_result()
+    }
+}
+
+impl Validate for CreateFileRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.check("file_path", validate_file_path(&self.file_path));
+        errors.check("content", validate_file_content(&self.content));
+        check_optional(&mut errors, "language", self.language.as_ref(), |v| validate_max_length(v, MAX_LANGUAGE_LENGTH));
+        errors.into_result()
+    }
+}
+
+impl Validate for UpdateFileRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.check("content", validate_file_content(&self.content));
+        check_optional(&mut errors, "language", self.language.as_ref(), |v| validate_max_length(v, MAX_LANGUAGE_LENGTH));
+        errors.into_result()
+    }
+}
+
+impl Validate for TransferProjectRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.require("user_id", !self.user_id.is_nil(), "must be a user ID");
+        errors.into_result()
+    }
+}
+
+impl Validate for DeployRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.require("files", !self.files.is_empty(), "No files to deploy");
+        for (index, file) in self.files.iter().enumerate() {
+            errors.check(&format!("files[{}].path", index), validate_file_path(&file.path));
+            errors.check(&format!("files[{}].content", index), validate_file_content(&file.content));
+        }
+        errors.into_result()
+    }
+}
+
+// Code analysis and agents
+
+impl Validate for OptimizeCodeRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.check("code", validate_code(&self.code));
+        check_optional(&mut errors, "language", self.language.as_ref(), |v| validate_text(v, MAX_LANGUAGE_LENGTH));
+        check_optional(&mut errors, "file_path", self.file_path.as_ref(), |v| validate_max_length(v, MAX_FILE_PATH_LENGTH));
+        errors.into_result()
+    }
+}
+
+impl Validate for ReviewCodeRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.check("code", validate_code(&self.code));
+        check_optional(&mut errors, "language", self.language.as_ref(), |v| validate_text(v, MAX_LANGUAGE_LENGTH));
+        check_optional(&mut errors, "file_path", self.file_path.as_ref(), |v| validate_max_length(v, MAX_FILE_PATH_LENGTH));
+        errors.into_result()
+    }
+}
+
+impl Validate for RefactorCodeRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.check("code", validate_code(&self.code));
+        check_optional(&mut errors, "language", self.language.as_ref(), |v| validate_text(v, MAX_LANGUAGE_LENGTH));
+        check_optional(&mut errors, "file_path", self.file_path.as_ref(), |v| validate_max_length(v, MAX_FILE_PATH_LENGTH));
+        check_optional(&mut errors, "target_pattern", self.target_pattern.as_ref(), |v| validate_max_length(v, MAX_NAME_LENGTH));
+        errors.into_result()
+    }
+}
+
+impl Validate for AgentRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.check("task_description", validate_text(&self.task_description, MAX_TASK_DESCRIPTION_LENGTH));
+        check_optional(&mut errors, "context", self.context.as_ref(), |v| validate_max_length(v, MAX_CODE_LENGTH));
+        errors.into_result()
+    }
+}
+
+// Teams and project membership
+
+impl Validate for CreateTeamRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.check("name", validate_text(&self.name, MAX_NAME_LENGTH));
+        errors.into_result()
+    }
+}
+
+impl Validate for UpdateTeamRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        check_optional(&mut errors, "name", self.name.as_ref(), |v| validate_text(v, MAX_NAME_LENGTH));
+        errors.into_result()
+    }
+}
+
+impl Validate for AddTeamMemberRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.check("role", validate_team_role(&self.role));
+        errors.into_result()
+    }
+}
+
+impl Validate for CreateTeamInvitationRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.check("email", validate_email(&self.email));
+        errors.check("role", validate_team_role(&self.role));
+        errors.into_result()
+    }
+}
+
+/// Only the size is checked up front; each entry's role is checked on its own so one bad
+/// entry doesn't reject the rest
+impl Validate for BulkAddTeamMembersRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.require("members", !self.members.is_empty(), "must list at least one member");
+        errors.require(
+            "members",
+            self.members.len() <= MAX_BULK_MEMBERS,
+            &format!("at most {} members per import", MAX_BULK_MEMBERS),
+        );
+        errors.into_result()
+    }
+}
+
+impl Validate for UpdateTeamMemberRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.check("role", validate_team_role(&self.role));
+        errors.into_result()
+    }
+}
+
+impl Validate for AddProjectMemberRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.check("role", validate_text(&self.role, MAX_LANGUAGE_LENGTH));
+        if let Some(permissions) = &self.permissions {
+            errors.check("permissions", validate_permissions(permissions));
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for UpdateProjectMemberRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        check_optional(&mut errors, "role", self.role.as_ref(), |v| validate_text(v, MAX_LANGUAGE_LENGTH));
+        if let Some(permissions) = &self.permissions {
+            errors.check("permissions", validate_permissions(permissions));
+        }
+        errors.into_result()
+    }
+}
+
+// Permission inheritance
+
+impl Validate for CreateTeamHierarchyRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.require(
+            "child_team_id",
+            self.child_team_id != self.parent_team_id,
+            "A team can't be its own parent",
+        );
+        errors.into_result()
+    }
+}
+
+impl Validate for CreateProjectHierarchyRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.require(
+            "child_project_id",
+            self.child_project_id != self.parent_project_id,
+            "A project can't be its own parent",
+        );
+        errors.into_result()
+    }
+}
+
+impl Validate for CreatePermissionRuleRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.require(
+            "team_id",
+            self.team_id.is_some() || self.project_id.is_some(),
+            "team_id or project_id is required",
+        );
+        errors.check("role", validate_text(&self.role, MAX_LANGUAGE_LENGTH));
+        errors.check("permissions", validate_permissions(&self.permissions));
+        errors.into_result()
+    }
+}
+
+impl Validate for UpdatePermissionRuleRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        if let Some(permissions) = &self.permissions {
+            errors.check("permissions", validate_permissions(permissions));
+        }
+        errors.into_result()
+    }
+}
+
+// Code review
+
+impl Validate for CreateCodeReviewRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.check("title", validate_text(&self.title, MAX_NAME_LENGTH));
+        check_optional(&mut errors, "source_branch", self.source_branch.as_ref(), |v| validate_text(v, MAX_NAME_LENGTH));
+        check_optional(&mut errors, "target_branch", self.target_branch.as_ref(), |v| validate_text(v, MAX_NAME_LENGTH));
+        errors.into_result()
+    }
+}
+
+impl Validate for UpdateCodeReviewRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        check_optional(&mut errors, "title", self.title.as_ref(), |v| validate_text(v, MAX_NAME_LENGTH));
+        if let Some(status) = &self.status {
+            errors.require(
+                "status",
+                ReviewStatus::parse(status).is_some(),
+                &format!("Invalid review status: {}", status),
+            );
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for AddReviewCommentRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.check("content", validate_text(&self.content, MAX_TASK_DESCRIPTION_LENGTH));
+        check_optional(&mut errors, "file_path", self.file_path.as_ref(), validate_file_path);
+        if let Some(line_number) = self.line_number {
+            errors.require("line_number", line_number >= 1, "Line numbers start at 1");
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for UpdateReviewCommentRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        check_optional(&mut errors, "content", self.content.as_ref(), |v| validate_text(v, MAX_TASK_DESCRIPTION_LENGTH));
+        errors.into_result()
+    }
+}
+
+impl Validate for SubmitApprovalRequest {
+    fn validate(&self) -> AppResult<()> {
+        let known = [
+            ApprovalStatus::Approved,
+            ApprovalStatus::ChangesRequested,
+            ApprovalStatus::Commented,
+        ]
+        .iter()
+        .any(|known| known.as_str() == self.status);
+
+        let mut errors = ValidationErrors::new();
+        errors.require("status", known, &format!("Invalid approval status: {}", self.status));
+        errors.into_result()
+    }
+}
+
+impl Validate for UpdateReviewSettingsRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        if let Some(count) = self.required_approvals {
+            errors.require("required_approvals", count >= 0, "Must not be negative");
+        }
+        errors.into_result()
+    }
+}
+
+// Collaboration
+
+impl Validate for CreateCollaborativeSessionRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        if let Some(seconds) = self.expires_in_seconds {
+            errors.require("expires_in_seconds", seconds > 0, "Must be positive");
+        }
+        errors.into_result()
+    }
+}
+
+// Analytics
+
+impl Validate for GenerateReportRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        let (from, to) = self.window();
+        errors.require("from", from < to, "must be before to");
+        errors.require(
+            "to",
+            to - from <= chrono::Duration::days(Self::MAX_DAYS),
+            &format!("reports cover at most {} days", Self::MAX_DAYS),
+        );
+        errors.into_result()
+    }
+}
+
+/// Whether `ip` is on the public internet, rather than loopback, a private or shared
+/// network, link-local (including cloud metadata endpoints) or otherwise not routable
+pub fn is_public_address(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && (64..128).contains(&b))
+                || a == 0)
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // Unique local, fc00::/7, and link-local, fe80::/10
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Absolute http(s) URL a webhook can be delivered to. Hosts given as internal
+/// addresses are refused here; names are checked again when each delivery resolves them.
+pub fn validate_webhook_url(url: &str) -> AppResult<()> {
+    let parsed = reqwest::Url::parse(url)
+        .map_err(|_| AppError::ValidationError(format!("Invalid URL: {}", url)))?;
+    if !matches!(parsed.scheme(), "http" | "https") || parsed.host_str().is_none() {
+        return Err(AppError::ValidationError("Webhook URLs must be http or https".to_string()));
+    }
+    let host = parsed.host_str().unwrap_or_default();
+    let internal = match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) => !is_public_address(ip),
+        Err(_) => {
+            let domain = host.trim_end_matches('.');
+            domain == "localhost" || domain.ends_with(".localhost")
+        }
+    };
+    if internal {
+        return Err(AppError::ValidationError(
+            "Webhook URLs must point to a public address".to_string(),
+        ));
+    }
+    validate_max_length(url, 2048)
+}
+
+/// At least one event, each defined by `WebhookEvent`
+pub fn validate_webhook_events(event_types: &[String]) -> AppResult<()> {
+    if event_types.is_empty() {
+        return Err(AppError::ValidationError("At least one event type is required".to_string()));
+    }
+    let unknown: Vec<&str> = event_types
+        .iter()
+        .filter(|name| WebhookEvent::parse(name).is_none())
+        .map(String::as_str)
+        .collect();
+    if !unknown.is_empty() {
+        return Err(AppError::ValidationError(format!(
+            "Unknown event types: {}",
+            unknown.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+impl Validate for CreateWebhookRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        errors.check("url", validate_webhook_url(&self.url));
+        errors.check("event_types", validate_webhook_events(&self.event_types));
+        check_optional(&mut errors, "secret", self.secret.as_ref(), |v| validate_text(v, MAX_NAME_LENGTH));
+        errors.into_result()
+    }
+}
+
+impl Validate for UpdateWebhookRequest {
+    fn validate(&self) -> AppResult<()> {
+        let mut errors = ValidationErrors::new();
+        check_optional(&mut errors, "url", self.url.as_ref(), validate_webhook_url);
+        if let Some(event_types) = &self.event_types {
+            errors.check("event_types", validate_webhook_events(event_types));
+        }
+        check_optional(&mut errors, "secret", self.secret.as_ref(), |v| validate_text(v, MAX_NAME_LENGTH));
+        errors.into_result()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::FileContent;
 
     #[test]
     fn test_email_validation() {
         assert!(validate_email("test@example.com").is_ok());
         assert!(validate_email("invalid").is_err());
         assert!(validate_email("").is_err());
+        assert!(validate_email("@example.com").is_err());
+        assert!(validate_email("test@localhost").is_err());
+        assert!(validate_email("a@b@example.com").is_err());
+        assert!(validate_email("test @example.com").is_err());
     }
 
     #[test]
@@ -52,4 +759,146 @@ mod tests {
         assert!(validate_password("short").is_err());
         assert!(validate_password("nouppercase123").is_err());
     }
+
+    #[test]
+    fn test_file_validation() {
+        assert!(validate_file_path("src/main.rs").is_ok());
+        assert!(validate_file_path(".env.example").is_ok());
+        assert!(validate_file_path("").is_err());
+        assert!(validate_file_path("/etc/passwd").is_err());
+        assert!(validate_file_path("src/../../secrets").is_err());
+        assert!(validate_file_path("src//main.rs").is_err());
+        assert!(validate_file_path("src\\main.rs").is_err());
+        assert!(validate_file_content("fn main() {}").is_ok());
+        assert!(validate_file_content("\u{89}PNG\0\0").is_err());
+        assert!(validate_file_content(&"x".repeat(MAX_FILE_SIZE + 1)).is_err());
+    }
+
+    fn message(result: AppResult<()>) -> String {
+        match result {
+            Err(AppError::ValidationError(message)) => message,
+            other => panic!("expected a validation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_register_request_reports_every_failing_field() {
+        let request = RegisterRequest {
+            email: "not-an-email".to_string(),
+            password: "short".to_string(),
+            first_name: Some("x".repeat(101)),
+            last_name: None,
+        };
+
+        let message = message(request.validate());
+        assert_eq!(
+            message,
+            "email: Invalid email format; \
+             password: Password must be at least 8 characters; \
+             first_name: Must be at most 100 bytes"
+        );
+
+        let request = RegisterRequest {
+            email: "dev@example.com".to_string(),
+            password: "Secure123".to_string(),
+            first_name: Some("Ada".to_string()),
+            last_name: Some("Lovelace".to_string()),
+        };
+        assert!(request.validate().is_ok());
+    }
+
+    #[test]
+    fn test_deploy_request_names_the_failing_file() {
+        let request = DeployRequest {
+            files: vec![
+                FileContent { path: "src/lib.rs".to_string(), content: String::new() },
+                FileContent { path: "../escape".to_string(), content: "\0".to_string() },
+            ],
+            message: String::new(),
+        };
+
+        let errors = message(request.validate());
+        assert!(errors.starts_with("files[1].path: Invalid file path"));
+        assert!(errors.contains("files[1].content: Binary files are not supported"));
+        assert!(!errors.contains("files[0]"));
+
+        let empty = DeployRequest { files: Vec::new(), message: String::new() };
+        assert_eq!(message(empty.validate()), "files: No files to deploy");
+    }
+
+    #[test]
+    fn test_role_and_permission_enums() {
+        assert!(validate_team_role("admin").is_ok());
+        assert!(validate_team_role("superuser").is_err());
+
+        let perms = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        assert!(validate_permissions(&perms(&["read", "write", "admin", "delete"])).is_ok());
+        assert!(validate_permissions(&perms(&["invite", "manage_roles", "view_audit"])).is_ok());
+        assert_eq!(
+            message(validate_permissions(&perms(&["read", "superuser", "root"]))),
+            "Unknown permissions: superuser, root"
+        );
+    }
+
+    #[test]
+    fn test_code_length_is_bounded() {
+        let request = |code: String| OptimizeCodeRequest {
+            project_id: None,
+            code,
+            language: Some("rust".to_string()),
+            file_path: None,
+        };
+
+        assert!(request("fn main() {}".to_string()).validate().is_ok());
+        assert!(request("   ".to_string()).validate().is_err());
+        assert!(request("x".repeat(MAX_CODE_LENGTH + 1)).validate().is_err());
+    }
+
+    #[test]
+    fn test_webhook_validation() {
+        assert!(validate_webhook_url("https://hooks.example.com/cx7").is_ok());
+        assert!(validate_webhook_url("ftp://hooks.example.com").is_err());
+        assert!(validate_webhook_url("not a url").is_err());
+        for internal in [
+            "http://127.0.0.1:8080/hook",
+            "http://localhost/hook",
+            "http://10.1.2.3/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/hook",
+            "http://[fd12::1]/hook",
+            "http://[::ffff:192.168.0.1]/hook",
+        ] {
+            assert!(validate_webhook_url(internal).is_err(), "{}", internal);
+        }
+        assert!(validate_webhook_url("https://203.0.113.7/hook").is_ok());
+        assert!(validate_webhook_events(&["deployment.completed".to_string()]).is_ok());
+        assert!(validate_webhook_events(&[]).is_err());
+        assert_eq!(
+            message(validate_webhook_events(&["deployment.completed".to_string(), "push".to_string()])),
+            "Unknown event types: push"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_validated_json_rejects_invalid_bodies() {
+        use axum::body::Body;
+
+        let extract = |body: &'static str| async move {
+            let request = axum::http::Request::builder()
+                .method("POST")
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            ValidatedJson::<RegisterRequest>::from_request(request, &()).await
+        };
+
+        let ok = extract(r#"{"email": "dev@example.com", "password": "Secure123"}"#).await;
+        assert!(ok.is_ok());
+
+        let invalid = extract(r#"{"email": "dev", "password": "Secure123"}"#).await;
+        assert!(matches!(invalid, Err(AppError::ValidationError(m)) if m == "email: Invalid email format"));
+
+        let malformed = extract(r#"{"email": "#).await;
+        assert!(matches!(malformed, Err(AppError::ValidationError(_))));
+    }
 }